@@ -0,0 +1,102 @@
+//! Benchmarks of the request path on a long Claude Code conversation.
+//!
+//! The history is about 200k tokens: rounds of a tool call reading a file and
+//! its result, with Claude Code's tools offered. Run with
+//! `cargo bench --bench translation`.
+
+use claude_proxy::translate::anthropic_types::MessagesRequest;
+use claude_proxy::translate::raw::RawObject;
+use claude_proxy::translate::request::{anthropic_to_openai_for_model, translate_messages};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const TURNS: usize = 400;
+
+/// The JSON body of a request `turns` rounds of tool calls long.
+fn request_body(turns: usize) -> Vec<u8> {
+    let file = "fn main() {\n    println!(\"hello\");\n}\n".repeat(50);
+    let mut messages = vec![serde_json::json!({"role": "user", "content": "Read the project."})];
+    for turn in 0..turns {
+        messages.push(serde_json::json!({"role": "assistant", "content": [
+            {"type": "text", "text": "Let me read the next file."},
+            {"type": "tool_use", "id": format!("toolu_{turn}"), "name": "Read",
+             "input": {"file_path": format!("src/file_{turn}.rs")}}
+        ]}));
+        messages.push(serde_json::json!({"role": "user", "content": [
+            {"type": "tool_result", "tool_use_id": format!("toolu_{turn}"), "content": file}
+        ]}));
+    }
+    let tools: Vec<serde_json::Value> = (0..20)
+        .map(|i| {
+            serde_json::json!({
+                "name": format!("Tool{i}"),
+                "description": "Does one thing. ".repeat(40),
+                "input_schema": {
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string", "description": "The file to act on"},
+                        "limit": {"type": "integer", "minimum": 1},
+                        "mode": {"type": "string", "enum": ["read", "write", "append"]},
+                    },
+                    "required": ["path"],
+                },
+            })
+        })
+        .collect();
+    serde_json::to_vec(&serde_json::json!({
+        "model": "claude-sonnet-4-20250514",
+        "max_tokens": 32000,
+        "system": "You are Claude Code. ".repeat(500),
+        "stream": true,
+        "messages": messages,
+        "tools": tools,
+    }))
+    .expect("serializable request")
+}
+
+fn translation(c: &mut Criterion) {
+    let body = request_body(TURNS);
+    let req: MessagesRequest = serde_json::from_slice(&body).expect("valid request");
+
+    c.bench_function("parse_request", |b| {
+        b.iter(|| serde_json::from_slice::<MessagesRequest>(black_box(&body)).unwrap());
+    });
+    c.bench_function("translate_request", |b| {
+        b.iter(|| anthropic_to_openai_for_model(black_box(&req), "kimi"));
+    });
+    c.bench_function("translate_and_serialize", |b| {
+        b.iter(|| serde_json::to_vec(&anthropic_to_openai_for_model(black_box(&req), "kimi")));
+    });
+
+    let mut group = c.benchmark_group("translate_messages");
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &n| {
+            b.iter(|| translate_messages(black_box(&req.messages), n));
+        });
+    }
+    group.finish();
+}
+
+/// Renaming a passthrough request's model: top-level raw fields against a
+/// full `Value` round trip.
+fn passthrough(c: &mut Criterion) {
+    let body = request_body(TURNS);
+    let mut group = c.benchmark_group("rename_model");
+    group.bench_function("raw", |b| {
+        b.iter(|| {
+            let mut fields = RawObject::parse(black_box(&body)).unwrap();
+            fields.set("model", &"kimi").unwrap();
+            fields.to_vec().unwrap()
+        });
+    });
+    group.bench_function("value", |b| {
+        b.iter(|| {
+            let mut value: serde_json::Value = serde_json::from_slice(black_box(&body)).unwrap();
+            value["model"] = "kimi".into();
+            serde_json::to_vec(&value).unwrap()
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, translation, passthrough);
+criterion_main!(benches);
@@ -23,11 +23,7 @@ async fn main() -> anyhow::Result<()> {
         .build()?;
 
     let port = config.port;
-    let state = Arc::new(AppState {
-        config,
-        client,
-        logger,
-    });
+    let state = Arc::new(AppState::new(config, client, logger));
 
     let app = build_router(state);
     let addr = format!("0.0.0.0:{port}");
@@ -75,12 +75,16 @@ fn main() {
                 tool_calls: None,
             },
             finish_reason: Some("stop".to_string()),
+            logprobs: None,
         }],
         usage: Some(ChatUsage {
             prompt_tokens: 42,
             completion_tokens: 8,
             total_tokens: 50,
+            ..ChatUsage::default()
         }),
+        system_fingerprint: None,
+        citations: None,
     };
 
     let anthropic_resp = openai_to_anthropic(&openai_resp, "claude-sonnet-4-20250514").unwrap();
@@ -126,8 +130,11 @@ fn main() {
                 index: 0,
                 delta,
                 finish_reason: None,
+                logprobs: None,
             }],
             usage: None,
+            system_fingerprint: None,
+            citations: None,
         };
 
         let events = translator.process_chunk(&chunk);
@@ -145,8 +152,11 @@ fn main() {
             index: 0,
             delta: ChunkDelta::default(),
             finish_reason: Some("stop".to_string()),
+            logprobs: None,
         }],
         usage: None,
+        system_fingerprint: None,
+        citations: None,
     };
 
     let events = translator.process_chunk(&finish_chunk);
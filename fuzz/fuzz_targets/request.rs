@@ -0,0 +1,20 @@
+//! An Anthropic request body, as a client might send it: if it parses, it
+//! must translate for every backend format without panicking.
+
+#![no_main]
+
+use claude_proxy::translate::anthropic_types::MessagesRequest;
+use claude_proxy::translate::request::anthropic_to_openai_for_model;
+use claude_proxy::translate::{bedrock, cohere, gemini_backend};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(req) = serde_json::from_slice::<MessagesRequest>(data) else {
+        return;
+    };
+    let translated = anthropic_to_openai_for_model(&req, "model");
+    serde_json::to_vec(&translated).expect("translated requests serialize");
+    let _ = bedrock::openai_to_converse(&translated);
+    let _ = cohere::openai_to_cohere(&translated);
+    let _ = gemini_backend::openai_to_gemini(&translated);
+});
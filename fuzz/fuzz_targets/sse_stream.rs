@@ -0,0 +1,46 @@
+//! A provider's SSE response body, as the stream path reads it: framed into
+//! events, parsed into chunks up to `[DONE]`, and translated. Whatever the
+//! bytes, and however they are split across reads, the client must get a
+//! stream that keeps the Anthropic contract.
+
+#![no_main]
+
+use bytes::Bytes;
+use claude_proxy::translate::openai_types::ChatCompletionChunk;
+use claude_proxy::translate::stream_check;
+use claude_proxy::translate::streaming::StreamTranslator;
+use eventsource_stream::Eventsource;
+use futures::StreamExt;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the size of each read
+    let Some((&size, body)) = data.split_first() else {
+        return;
+    };
+    let size = usize::from(size).max(1);
+    let reads: Vec<Result<Bytes, std::io::Error>> = body
+        .chunks(size)
+        .map(|read| Ok(Bytes::copy_from_slice(read)))
+        .collect();
+
+    let mut translator = StreamTranslator::new("claude")
+        .with_thinking_blocks(size % 2 == 0)
+        .with_stop_token_stripping(size % 3 == 0);
+    let mut events = Vec::new();
+    futures::executor::block_on(async {
+        let mut stream = futures::stream::iter(reads).eventsource();
+        while let Some(Ok(event)) = stream.next().await {
+            if event.data == "[DONE]" {
+                break;
+            }
+            if let Ok(chunk) = serde_json::from_str::<ChatCompletionChunk>(&event.data) {
+                events.extend(translator.process_chunk(&chunk));
+            }
+        }
+    });
+    events.extend(translator.finish());
+
+    let problems = stream_check::check(&events);
+    assert!(problems.is_empty(), "{problems:?}");
+});
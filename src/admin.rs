@@ -0,0 +1,273 @@
+//! Admin API, mounted under `/admin`: JSON views of the proxy's runtime state,
+//! and a dashboard over them.
+//!
+//! - `GET /admin` — the dashboard, a single HTML page
+//! - `GET /admin/logs?limit=N` — recent log entries, newest first
+//! - `POST /admin/logs/compact` — compact the stored log now (see
+//!   [`log_compaction`])
+//! - `GET /admin/config` — the active config, secrets redacted
+//! - `GET /admin/summary` — model mappings and per-model request, error and
+//!   token totals since startup
+//! - `GET /admin/events` — SSE: each new log entry (`log`), and the summary
+//!   every few seconds (`summary`)
+//! - `GET /admin/audit?limit=N` — recent translation audit entries, newest first
+//! - `GET /admin/audit/{id}` — a single audit entry
+//! - `GET /admin/registry` — the model pricing and capability registry
+//! - `POST /admin/registry/reload` — re-read the registry file now
+//! - `GET /admin/keys` — request counts per rotated API key, by variable name
+//!   (see [`key_pool`](crate::key_pool))
+//!
+//! Everything but the dashboard page sits behind `[auth]`; the page holds no
+//! data and asks for a key to send with its requests. Without `[auth]`, the
+//! API answers only clients on the same machine, as the proxy listens on
+//! every interface. It isn't open to cross-origin requests either way.
+
+use crate::auth;
+use crate::log_compaction;
+use crate::registry;
+use crate::state::AppState;
+use crate::translate::anthropic_types::ErrorResponse;
+
+use axum::extract::{ConnectInfo, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::Stream;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+const DEFAULT_AUDIT_LIMIT: usize = 50;
+const DEFAULT_LOG_LIMIT: usize = 200;
+
+/// How often `/admin/events` sends a fresh summary.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+
+const DASHBOARD: &str = include_str!("admin/dashboard.html");
+
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/logs", get(list_logs))
+        .route("/logs/compact", post(compact_logs))
+        .route("/config", get(get_config))
+        .route("/summary", get(get_summary))
+        .route("/events", get(events))
+        .route("/audit", get(list_audit))
+        .route("/audit/:id", get(get_audit))
+        .route("/registry", get(get_registry))
+        .route("/registry/reload", post(reload_registry))
+        .route("/keys", get(get_keys))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ))
+        .route_layer(middleware::from_fn_with_state(state, require_local_or_auth))
+        .route("/", get(dashboard))
+}
+
+/// Middleware refusing the API to remote clients while `[auth]` is off. The
+/// peer address comes from the server's connect info; a request without it
+/// (an embedder serving the router without
+/// `into_make_service_with_connect_info`) counts as remote.
+async fn require_local_or_auth(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let local = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .is_some_and(|ConnectInfo(peer)| peer.ip().is_loopback());
+    if local || !state.config.load().auth.accepted_keys().is_empty() {
+        return next.run(request).await;
+    }
+    state.logger.warn(
+        "admin",
+        format!(
+            "Refused {} {} from a remote client: [auth] is off",
+            request.method(),
+            request.uri().path()
+        ),
+    );
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse::new(
+            "permission_error",
+            "The admin API is only served to localhost unless [auth] is configured",
+        )),
+    )
+        .into_response()
+}
+
+async fn dashboard() -> Response {
+    ([(header::CACHE_CONTROL, "no-store")], Html(DASHBOARD)).into_response()
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+}
+
+async fn list_logs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Json<serde_json::Value> {
+    let entries = state
+        .logger
+        .recent(query.limit.unwrap_or(DEFAULT_LOG_LIMIT));
+    Json(serde_json::json!({ "entries": entries }))
+}
+
+async fn compact_logs(State(state): State<Arc<AppState>>) -> Response {
+    match log_compaction::compact(&state.logger).await {
+        Ok(result) => {
+            state.logger.info("logging", "Compacted the log on request");
+            state.journal.record(
+                "logs_compacted",
+                serde_json::json!({
+                    "source": "admin",
+                    "bytes_before": result.bytes_before,
+                    "bytes_after": result.bytes_after,
+                }),
+            );
+            Json(result).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn get_config(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let config = state.config.load().redacted();
+    Json(serde_json::to_value(config).unwrap_or_default())
+}
+
+async fn get_summary(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(summary(&state))
+}
+
+fn summary(state: &AppState) -> serde_json::Value {
+    let config = state.config.load();
+    let mut models: Vec<serde_json::Value> = config
+        .models
+        .iter()
+        .map(|(name, mapping)| {
+            serde_json::json!({
+                "name": name,
+                "model": mapping.model(),
+                "provider": mapping.provider().unwrap_or(&config.provider.name),
+            })
+        })
+        .collect();
+    models.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    let mut providers: Vec<&str> = std::iter::once(config.provider.name.as_str())
+        .chain(config.providers.keys().map(String::as_str))
+        .collect();
+    providers[1..].sort_unstable();
+
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "provider": config.provider.name,
+        "providers": providers,
+        "fallback": config.fallback,
+        "models": models,
+        "totals": state.metrics.totals(),
+    })
+}
+
+/// Live log entries and periodic summaries. A client that falls too far behind
+/// the log skips ahead rather than being disconnected.
+async fn events(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let mut logs = state.logger.subscribe();
+    let stream = async_stream::stream! {
+        let mut ticks = tokio::time::interval(SUMMARY_INTERVAL);
+        loop {
+            tokio::select! {
+                entry = logs.recv() => match entry {
+                    Ok(entry) => {
+                        if let Ok(data) = serde_json::to_string(&entry) {
+                            yield Ok(Event::default().event("log").data(data));
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ticks.tick() => {
+                    yield Ok(Event::default().event("summary").data(summary(&state).to_string()));
+                }
+            }
+        }
+    };
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn list_audit(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Json<serde_json::Value> {
+    let entries = state
+        .audit
+        .recent(query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT));
+    Json(serde_json::json!({
+        "enabled": state.audit.is_enabled(),
+        "entries": entries,
+    }))
+}
+
+async fn get_audit(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    match state.audit.get(&id) {
+        Some(entry) => Json(entry).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "not_found_error",
+                format!("No audit entry '{id}'"),
+            )),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_registry(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let models: serde_json::Map<String, serde_json::Value> = state
+        .registry
+        .entries()
+        .into_iter()
+        .map(|(key, info)| (key, serde_json::to_value(info).unwrap_or_default()))
+        .collect();
+    Json(serde_json::json!({
+        "file": state.config.load().registry.file,
+        "models": models,
+    }))
+}
+
+async fn reload_registry(State(state): State<Arc<AppState>>) -> Response {
+    match registry::reload(&state) {
+        Ok(count) => {
+            state
+                .logger
+                .info("registry", format!("Reloaded {count} models on request"));
+            state.journal.record(
+                "registry_reloaded",
+                serde_json::json!({ "source": "admin", "models": count }),
+            );
+            Json(serde_json::json!({ "models": count })).into_response()
+        }
+        Err(e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse::new("invalid_request_error", e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_keys(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "providers": state.keys.stats() }))
+}
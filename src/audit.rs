@@ -0,0 +1,321 @@
+//! Translation audit trail: what the proxy changed in each request.
+//!
+//! When `[audit] enabled = true`, every forwarded request records an
+//! [`AuditEntry`] listing the fields that were dropped, clamped, injected or
+//! renamed on the way to the provider. Entries are kept in a bounded in-memory
+//! ring buffer and served by the admin API (`/admin/audit`).
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::translate::anthropic_types::{ContentBlock, MessagesRequest};
+use crate::translate::openai_types::ChatCompletionRequest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// Sent by the client, not forwarded.
+    Dropped,
+    /// Forwarded with its value reduced to what the provider accepts.
+    Clamped,
+    /// Added by the proxy (config defaults, provider requirements).
+    Injected,
+    /// Forwarded under another name or shape.
+    Renamed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub field: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Change {
+    fn new(kind: ChangeKind, field: impl Into<String>, detail: Option<String>) -> Self {
+        Self {
+            kind,
+            field: field.into(),
+            detail,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub requested_model: String,
+    pub provider: String,
+    pub upstream_model: String,
+    pub streaming: bool,
+    pub changes: Vec<Change>,
+}
+
+impl AuditEntry {
+    #[must_use]
+    pub fn new(
+        requested_model: &str,
+        provider: &str,
+        upstream_model: &str,
+        streaming: bool,
+        changes: Vec<Change>,
+    ) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            requested_model: requested_model.to_string(),
+            provider: provider.to_string(),
+            upstream_model: upstream_model.to_string(),
+            streaming,
+            changes,
+        }
+    }
+}
+
+/// Bounded, shared store of recent audit entries. A capacity of 0 disables
+/// recording.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    entries: Arc<Mutex<VecDeque<AuditEntry>>>,
+    capacity: usize,
+}
+
+impl AuditLog {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+
+    /// The most recent `limit` entries, newest first.
+    #[must_use]
+    pub fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        self.entries.lock().map_or_else(
+            |_| Vec::new(),
+            |entries| entries.iter().rev().take(limit).cloned().collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<AuditEntry> {
+        self.entries
+            .lock()
+            .ok()?
+            .iter()
+            .find(|e| e.id == id)
+            .cloned()
+    }
+}
+
+/// Compare what the client sent with what goes upstream.
+#[must_use]
+pub fn translation_changes(req: &MessagesRequest, out: &ChatCompletionRequest) -> Vec<Change> {
+    use ChangeKind::{Clamped, Dropped, Injected, Renamed};
+
+    let mut changes = Vec::new();
+    let mut push = |kind, field: &str, detail: Option<String>| {
+        changes.push(Change::new(kind, field, detail));
+    };
+
+    if out.model != req.model {
+        push(
+            Renamed,
+            "model",
+            Some(format!("{} → {}", req.model, out.model)),
+        );
+    }
+    if req.system.is_some() {
+        push(
+            Renamed,
+            "system",
+            Some("→ messages[0] (role=system)".into()),
+        );
+    }
+    if req.stop_sequences.is_some() {
+        push(Renamed, "stop_sequences", Some("→ stop".into()));
+    }
+    let requested = req.stop_sequences.as_deref().unwrap_or_default();
+    let added: Vec<&str> = out
+        .stop
+        .iter()
+        .flatten()
+        .filter(|s| !requested.contains(s))
+        .map(String::as_str)
+        .collect();
+    if !added.is_empty() {
+        push(Injected, "stop", Some(added.join(", ")));
+    }
+    if out.max_tokens != Some(req.max_tokens) {
+        push(
+            Clamped,
+            "max_tokens",
+            Some(format!("{} → {:?}", req.max_tokens, out.max_tokens)),
+        );
+    }
+    if req.temperature.is_some() && out.temperature != req.temperature {
+        push(
+            Clamped,
+            "temperature",
+            Some(format!("{:?} → {:?}", req.temperature, out.temperature)),
+        );
+    }
+
+    if req
+        .metadata
+        .as_ref()
+        .and_then(|m| m.user_id.as_ref())
+        .is_some()
+    {
+        if out.user.is_some() {
+            push(Renamed, "metadata.user_id", Some("→ user".into()));
+        } else {
+            push(Dropped, "metadata.user_id", None);
+        }
+    }
+    for (field, present) in [
+        ("top_k", req.top_k.is_some()),
+        ("thinking", req.thinking.is_some()),
+        ("betas", req.betas.is_some()),
+        ("context_management", req.context_management.is_some()),
+    ] {
+        if present {
+            push(Dropped, field, None);
+        }
+    }
+    match (&req.reasoning_effort, &out.reasoning_effort) {
+        (_, Some(effort)) => push(Injected, "reasoning_effort", Some(effort.clone())),
+        (Some(_), None) => push(Dropped, "reasoning_effort", None),
+        (None, None) => {}
+    }
+
+    let mut extra: Vec<&String> = req.extra.keys().collect();
+    extra.sort();
+    for key in extra {
+        if key == "output_format" && out.response_format.is_some() {
+            push(Renamed, "output_format", Some("→ response_format".into()));
+        } else {
+            push(Dropped, key, None);
+        }
+    }
+    if out.response_format.is_some() && !req.extra.contains_key("output_format") {
+        push(Injected, "response_format", None);
+    }
+
+    let mut web_search = false;
+    for tool in req.tools.iter().flatten().filter(|t| t.is_server_tool()) {
+        let field = format!("tools[{}]", tool.name);
+        if tool.is_web_search() && out.search_parameters.is_some() {
+            web_search = true;
+            push(Renamed, &field, Some("→ search_parameters".into()));
+        } else {
+            push(Dropped, &field, tool.tool_type.clone());
+        }
+    }
+    if out.search_parameters.is_some() && !web_search {
+        push(Injected, "search_parameters", None);
+    }
+    if out.stream_options.is_some() {
+        push(Injected, "stream_options.include_usage", None);
+    }
+
+    let sent_ids: HashSet<&str> = out
+        .messages
+        .iter()
+        .flat_map(|m| m.tool_calls.iter().flatten())
+        .map(|c| c.id.as_str())
+        .collect();
+    let rewritten = req
+        .messages
+        .iter()
+        .flat_map(|m| m.content.blocks())
+        .filter(
+            |b| matches!(b, ContentBlock::ToolUse { id, .. } if !sent_ids.contains(id.as_str())),
+        )
+        .count();
+    if rewritten > 0 {
+        push(
+            Renamed,
+            "tool_use.id",
+            Some(format!("{rewritten} id(s) rewritten")),
+        );
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::request::anthropic_to_openai_for_model;
+
+    #[test]
+    fn test_translation_changes() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "stream": true,
+            "system": "Be brief.",
+            "top_k": 5,
+            "thinking": {"type": "enabled", "budget_tokens": 1024},
+            "container": "abc",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "tools": [{"type": "web_search_20250305", "name": "web_search"}]
+        }))
+        .unwrap();
+        let mut out = anthropic_to_openai_for_model(&req, "kimi-k2");
+        out.stop = Some(vec!["<|im_end|>".to_string()]);
+
+        let changes = translation_changes(&req, &out);
+        let has = |kind, field: &str| changes.iter().any(|c| c.kind == kind && c.field == field);
+
+        assert!(has(ChangeKind::Renamed, "model"));
+        assert!(has(ChangeKind::Renamed, "system"));
+        assert!(has(ChangeKind::Dropped, "top_k"));
+        assert!(has(ChangeKind::Dropped, "thinking"));
+        assert!(has(ChangeKind::Dropped, "container"));
+        assert!(has(ChangeKind::Dropped, "tools[web_search]"));
+        assert!(has(ChangeKind::Injected, "stream_options.include_usage"));
+        assert!(has(ChangeKind::Injected, "stop"));
+        assert!(!has(ChangeKind::Clamped, "max_tokens"));
+    }
+
+    #[test]
+    fn test_audit_log_is_bounded() {
+        let log = AuditLog::new(2);
+        for model in ["a", "b", "c"] {
+            log.record(AuditEntry::new(model, "p", model, false, Vec::new()));
+        }
+
+        let recent = log.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].requested_model, "c");
+        assert!(log.get(&recent[1].id).is_some());
+
+        let disabled = AuditLog::new(0);
+        disabled.record(AuditEntry::new("a", "p", "a", false, Vec::new()));
+        assert!(disabled.recent(10).is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+//! Inbound authentication: the keys clients must send to use the proxy.
+//!
+//! With `[auth] keys` (or `key_env`) set, every route except `/health` and the
+//! `/admin` dashboard page requires one of the keys in `x-api-key`,
+//! `Authorization: Bearer`, or (for Gemini clients) `x-goog-api-key`. Anything
+//! else gets an Anthropic-format 401. The accepted key is kept in the request
+//! context as the client's tenant.
+
+use crate::log_context;
+use crate::state::AppState;
+use crate::translate::anthropic_types::ErrorResponse;
+
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::sync::Arc;
+
+/// Middleware rejecting requests without an accepted key.
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let accepted = state.config.load().auth.accepted_keys();
+    if accepted.is_empty() {
+        return next.run(request).await;
+    }
+
+    match presented_key(request.headers()) {
+        Some(key) if accepted.iter().any(|k| constant_time_eq(k, key)) => {
+            let key = key.to_string();
+            log_context::update(|ctx| ctx.tenant_key = Some(key));
+            next.run(request).await
+        }
+        presented => {
+            let message = if presented.is_some() {
+                "Invalid API key"
+            } else {
+                "Missing API key: send it in the x-api-key or Authorization header"
+            };
+            state.logger.warn(
+                "auth",
+                format!(
+                    "Rejected {} {}: {message}",
+                    request.method(),
+                    request.uri().path()
+                ),
+            );
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::authentication(message)),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// The key a client sent, from whichever header carries it.
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    header("x-api-key")
+        .or_else(|| {
+            header("authorization").and_then(|v| {
+                v.strip_prefix("Bearer ")
+                    .or_else(|| v.strip_prefix("bearer "))
+            })
+        })
+        .or_else(|| header("x-goog-api-key"))
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+}
+
+/// Compare without an early exit, so response timing doesn't leak how much of
+/// a key matched.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presented_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(presented_key(&headers), None);
+
+        headers.insert("authorization", "Bearer sk-proxy".parse().unwrap());
+        assert_eq!(presented_key(&headers), Some("sk-proxy"));
+
+        headers.insert("x-api-key", "sk-other".parse().unwrap());
+        assert_eq!(presented_key(&headers), Some("sk-other"));
+
+        assert!(constant_time_eq("sk-proxy", "sk-proxy"));
+        assert!(!constant_time_eq("sk-proxy", "sk-proxz"));
+        assert!(!constant_time_eq("sk", "sk-proxy"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Claude Code's auxiliary endpoints.
+//!
+//! Besides `/v1/messages`, Claude Code posts telemetry and event logs to its
+//! base URL (`/api/event_logging/batch`, `/api/claude_code/...`). The proxy
+//! has nothing to do with them, and answering 404 makes for log noise and the
+//! odd client warning. Requests to the `[auxiliary] paths` are instead
+//! discarded with an empty `200 {}`, or forwarded unchanged to `forward_url`.
+//! Any other unknown path gets an Anthropic-format 404.
+
+use crate::config::{AuxiliaryAction, AuxiliaryConfig};
+use crate::state::AppState;
+use crate::translate::anthropic_types::ErrorResponse;
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::sync::Arc;
+
+/// Largest auxiliary request body forwarded upstream.
+const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Headers never copied to the forwarded request.
+const HOP_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "transfer-encoding",
+    "accept-encoding",
+];
+
+/// Fallback handler for every path without a route of its own.
+pub async fn handle(State(state): State<Arc<AppState>>, request: Request) -> Response {
+    let config = state.config.load();
+    let path = request.uri().path().to_string();
+    let aux = &config.auxiliary;
+    if aux.action == AuxiliaryAction::NotFound || !aux.matches(&path) {
+        state.logger.debug(
+            "auxiliary",
+            format!("No route for {} {path}", request.method()),
+        );
+        return not_found(&path);
+    }
+
+    if aux.action == AuxiliaryAction::Forward {
+        let strip_auth = !config.auth.accepted_keys().is_empty();
+        return forward(&state, aux, request, strip_auth).await;
+    }
+    state.logger.debug(
+        "auxiliary",
+        format!("Discarded {} {path}", request.method()),
+    );
+    Json(serde_json::json!({})).into_response()
+}
+
+/// Send the request on to `forward_url` and relay the answer. With `[auth]` on,
+/// the client's credentials are the proxy's key, so they are not passed on.
+async fn forward(
+    state: &AppState,
+    aux: &AuxiliaryConfig,
+    request: Request,
+    strip_auth: bool,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map_or(parts.uri.path(), |pq| pq.as_str());
+    let url = format!("{}{path_and_query}", aux.forward_url.trim_end_matches('/'));
+    let body = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => {
+            return bad_gateway(&format!("Failed to read request body: {e}"));
+        }
+    };
+
+    let result = state
+        .client
+        .request(parts.method.clone(), &url)
+        .headers(forwarded_headers(&parts.headers, strip_auth))
+        .body(body)
+        .send()
+        .await;
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            state
+                .logger
+                .warn("auxiliary", format!("Forwarding to {url} failed: {e}"));
+            return bad_gateway(&format!("Forwarding failed: {e}"));
+        }
+    };
+
+    let status = response.status();
+    state.logger.debug(
+        "auxiliary",
+        format!("Forwarded {} {url}: {status}", parts.method),
+    );
+    let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+    let body = response.bytes().await.unwrap_or_default();
+    let mut builder = Response::builder().status(status);
+    if let Some(content_type) = content_type {
+        builder = builder.header(header::CONTENT_TYPE, content_type);
+    }
+    builder
+        .body(Body::from(body))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+fn forwarded_headers(headers: &HeaderMap, strip_auth: bool) -> HeaderMap {
+    headers
+        .iter()
+        .filter(|(name, _)| !HOP_HEADERS.contains(&name.as_str()))
+        .filter(|(name, _)| !strip_auth || !matches!(name.as_str(), "x-api-key" | "authorization"))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+fn not_found(path: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new(
+            "not_found_error",
+            format!("No such endpoint: {path}"),
+        )),
+    )
+        .into_response()
+}
+
+fn bad_gateway(message: &str) -> Response {
+    (
+        StatusCode::BAD_GATEWAY,
+        Json(ErrorResponse::new("api_error", message)),
+    )
+        .into_response()
+}
@@ -0,0 +1,635 @@
+//! The Message Batches API (`/v1/messages/batches`).
+//!
+//! Clients built for Anthropic's batch endpoints submit many requests at
+//! once and poll for the results. The proxy has no batch backend: a batch's
+//! requests are sent as ordinary requests, [`CONCURRENCY`] at a time, with
+//! the same routing, fallbacks and limits as `/v1/messages` (streaming is
+//! turned off). Provider batch APIs aren't used.
+//!
+//! A batch body may be up to [`MAX_BATCH_BYTES`], Anthropic's own limit,
+//! rather than the `max_request_mb` single requests are held to, so that a
+//! batch of [`MAX_REQUESTS`] fits.
+//!
+//! A batch belongs to the `[auth]` key that created it: other keys are told
+//! there is no such batch.
+//!
+//! Batches are kept in memory, so they are lost on restart. Requests not
+//! started within [`EXPIRY`] of the batch's creation expire, and a batch is
+//! forgotten [`RETENTION`] after it was created, or when deleted.
+
+use crate::error::ProxyError;
+use crate::log_context::{self, RequestContext};
+use crate::logging::LogLevel;
+use crate::proxy;
+use crate::server::MB;
+use crate::state::AppState;
+use crate::translate::anthropic_types::{ErrorResponse, MessagesRequest, MessagesResponse};
+
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Requests of one batch in flight at once.
+pub const CONCURRENCY: usize = 8;
+
+/// Most requests in one batch, as Anthropic allows.
+pub const MAX_REQUESTS: usize = 100_000;
+
+/// Largest batch body, as Anthropic allows.
+pub const MAX_BATCH_BYTES: usize = 256 * MB;
+
+/// Longest `custom_id`.
+const MAX_CUSTOM_ID_LEN: usize = 64;
+
+/// How long a batch's requests may wait to be sent before they expire.
+pub const EXPIRY: Duration = Duration::hours(24);
+
+/// How long a batch is kept after it was created.
+pub const RETENTION: Duration = Duration::days(29);
+
+/// Batches listed per page unless `limit` says otherwise.
+const DEFAULT_LIST_LIMIT: usize = 20;
+
+/// Most batches listed per page.
+const MAX_LIST_LIMIT: usize = 1000;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/v1/messages/batches", post(create).get(list))
+        .route("/v1/messages/batches/:id", get(retrieve).delete(delete))
+        .route("/v1/messages/batches/:id/cancel", post(cancel))
+        .route("/v1/messages/batches/:id/results", get(results))
+        .layer(DefaultBodyLimit::max(MAX_BATCH_BYTES))
+}
+
+/// A `POST /v1/messages/batches` body.
+#[derive(Debug, Deserialize)]
+pub struct CreateBatch {
+    pub requests: Vec<BatchRequest>,
+}
+
+/// One request of a batch, named by the client's `custom_id`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchRequest {
+    pub custom_id: String,
+    pub params: MessagesRequest,
+}
+
+/// A batch as the API describes it.
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageBatch {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub batch_type: &'static str,
+    /// `in_progress`, `canceling` or `ended`.
+    pub processing_status: &'static str,
+    pub request_counts: RequestCounts,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub archived_at: Option<DateTime<Utc>>,
+    pub cancel_initiated_at: Option<DateTime<Utc>>,
+    /// Where the results are, once the batch has ended.
+    pub results_url: Option<String>,
+}
+
+/// How many of a batch's requests are in each state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RequestCounts {
+    pub processing: usize,
+    pub succeeded: usize,
+    pub errored: usize,
+    pub canceled: usize,
+    pub expired: usize,
+}
+
+/// The outcome of one request of a batch.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchResult {
+    Succeeded { message: MessagesResponse },
+    Errored { error: ErrorResponse },
+    Canceled,
+    Expired,
+}
+
+/// A line of a batch's results.
+#[derive(Serialize)]
+struct ResultLine<'a> {
+    custom_id: &'a str,
+    result: &'a BatchResult,
+}
+
+#[derive(Debug)]
+struct Batch {
+    /// The `[auth]` key of the client that created it.
+    tenant_key: Option<String>,
+    created_at: DateTime<Utc>,
+    cancel_initiated_at: Option<DateTime<Utc>>,
+    ended_at: Option<DateTime<Utc>>,
+    custom_ids: Vec<String>,
+    /// Each request's result, `None` while it is processing.
+    results: Vec<Option<BatchResult>>,
+}
+
+impl Batch {
+    fn belongs_to(&self, tenant_key: Option<&str>) -> bool {
+        self.tenant_key.as_deref() == tenant_key
+    }
+
+    fn view(&self, id: &str) -> MessageBatch {
+        let mut counts = RequestCounts::default();
+        for result in &self.results {
+            match result {
+                None => counts.processing += 1,
+                Some(BatchResult::Succeeded { .. }) => counts.succeeded += 1,
+                Some(BatchResult::Errored { .. }) => counts.errored += 1,
+                Some(BatchResult::Canceled) => counts.canceled += 1,
+                Some(BatchResult::Expired) => counts.expired += 1,
+            }
+        }
+        let processing_status = if self.ended_at.is_some() {
+            "ended"
+        } else if self.cancel_initiated_at.is_some() {
+            "canceling"
+        } else {
+            "in_progress"
+        };
+        MessageBatch {
+            id: id.to_string(),
+            batch_type: "message_batch",
+            processing_status,
+            request_counts: counts,
+            ended_at: self.ended_at,
+            created_at: self.created_at,
+            expires_at: self.created_at + EXPIRY,
+            archived_at: None,
+            cancel_initiated_at: self.cancel_initiated_at,
+            results_url: self
+                .ended_at
+                .map(|_| format!("/v1/messages/batches/{id}/results")),
+        }
+    }
+}
+
+/// The batches submitted to this proxy, by id.
+#[derive(Debug, Clone, Default)]
+pub struct Batches {
+    inner: Arc<Mutex<HashMap<String, Batch>>>,
+}
+
+impl Batches {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Batch>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Store a new batch of `tenant_key`'s, returning its id. Batches past
+    /// their retention are dropped.
+    fn insert(&self, tenant_key: Option<String>, custom_ids: Vec<String>) -> String {
+        let id = format!("msgbatch_{}", uuid::Uuid::new_v4().simple());
+        let now = Utc::now();
+        let mut batches = self.lock();
+        batches.retain(|_, batch| batch.created_at + RETENTION > now);
+        batches.insert(
+            id.clone(),
+            Batch {
+                tenant_key,
+                created_at: now,
+                cancel_initiated_at: None,
+                ended_at: None,
+                results: vec![None; custom_ids.len()],
+                custom_ids,
+            },
+        );
+        id
+    }
+
+    /// The batch of `tenant_key`'s with the given id.
+    #[must_use]
+    pub fn get(&self, id: &str, tenant_key: Option<&str>) -> Option<MessageBatch> {
+        self.lock()
+            .get(id)
+            .filter(|batch| batch.belongs_to(tenant_key))
+            .map(|batch| batch.view(id))
+    }
+
+    /// All of `tenant_key`'s batches, newest first.
+    #[must_use]
+    pub fn list(&self, tenant_key: Option<&str>) -> Vec<MessageBatch> {
+        let mut batches: Vec<MessageBatch> = self
+            .lock()
+            .iter()
+            .filter(|(_, batch)| batch.belongs_to(tenant_key))
+            .map(|(id, batch)| batch.view(id))
+            .collect();
+        batches.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        batches
+    }
+
+    /// Cancel a batch: requests not yet sent are canceled, and it ends once
+    /// those in flight are done.
+    #[must_use]
+    pub fn cancel(&self, id: &str, tenant_key: Option<&str>) -> Option<MessageBatch> {
+        let mut batches = self.lock();
+        let batch = batches
+            .get_mut(id)
+            .filter(|batch| batch.belongs_to(tenant_key))?;
+        if batch.ended_at.is_none() && batch.cancel_initiated_at.is_none() {
+            batch.cancel_initiated_at = Some(Utc::now());
+        }
+        Some(batch.view(id))
+    }
+
+    /// Forget an ended batch.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::InvalidRequest` if the batch is still processing,
+    /// and `None` if `tenant_key` has no such batch.
+    #[must_use]
+    pub fn delete(&self, id: &str, tenant_key: Option<&str>) -> Option<Result<(), ProxyError>> {
+        let mut batches = self.lock();
+        let batch = batches
+            .get(id)
+            .filter(|batch| batch.belongs_to(tenant_key))?;
+        if batch.ended_at.is_none() {
+            return Some(Err(ProxyError::invalid_request(format!(
+                "Batch {id} is still processing; cancel it before deleting it"
+            ))));
+        }
+        batches.remove(id);
+        Some(Ok(()))
+    }
+
+    /// An ended batch's results, one JSON line per request in the order they
+    /// were submitted.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::InvalidRequest` if the batch hasn't ended, and
+    /// `None` if `tenant_key` has no such batch.
+    #[must_use]
+    pub fn results(
+        &self,
+        id: &str,
+        tenant_key: Option<&str>,
+    ) -> Option<Result<String, ProxyError>> {
+        let batches = self.lock();
+        let batch = batches
+            .get(id)
+            .filter(|batch| batch.belongs_to(tenant_key))?;
+        if batch.ended_at.is_none() {
+            return Some(Err(ProxyError::invalid_request(format!(
+                "Batch {id} is still processing; its results aren't ready"
+            ))));
+        }
+        let lines = batch
+            .custom_ids
+            .iter()
+            .zip(&batch.results)
+            .filter_map(|(custom_id, result)| {
+                let line = ResultLine {
+                    custom_id,
+                    result: result.as_ref()?,
+                };
+                serde_json::to_string(&line).ok()
+            })
+            .map(|line| line + "\n")
+            .collect();
+        Some(Ok(lines))
+    }
+
+    /// The result of a request of a batch that is about to be sent, if it
+    /// is not to be: canceled or expired.
+    fn skip(&self, id: &str) -> Option<BatchResult> {
+        let batches = self.lock();
+        match batches.get(id) {
+            Some(batch) if batch.cancel_initiated_at.is_some() => Some(BatchResult::Canceled),
+            Some(batch) if Utc::now() > batch.created_at + EXPIRY => Some(BatchResult::Expired),
+            Some(_) => None,
+            None => Some(BatchResult::Canceled),
+        }
+    }
+
+    /// Record a request's result; the batch ends with its last one.
+    fn finish(&self, id: &str, index: usize, result: BatchResult) {
+        let mut batches = self.lock();
+        let Some(batch) = batches.get_mut(id) else {
+            return;
+        };
+        batch.results[index] = Some(result);
+        if batch.results.iter().all(Option::is_some) {
+            batch.ended_at = Some(Utc::now());
+        }
+    }
+}
+
+/// Check a batch's requests: there must be some, and their `custom_id`s must
+/// be distinct, 1 to 64 letters, digits, `-` and `_`.
+fn validate(requests: &[BatchRequest]) -> Result<(), ProxyError> {
+    if requests.is_empty() {
+        return Err(ProxyError::invalid_request("requests: must not be empty"));
+    }
+    if requests.len() > MAX_REQUESTS {
+        return Err(ProxyError::invalid_request(format!(
+            "requests: at most {MAX_REQUESTS} requests are allowed in a batch"
+        )));
+    }
+    let mut seen = HashSet::new();
+    for request in requests {
+        let id = &request.custom_id;
+        let valid = !id.is_empty()
+            && id.len() <= MAX_CUSTOM_ID_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(ProxyError::invalid_request(format!(
+                "custom_id '{id}' must be 1 to {MAX_CUSTOM_ID_LEN} letters, digits, '-' or '_'"
+            )));
+        }
+        if !seen.insert(id.as_str()) {
+            return Err(ProxyError::invalid_request(format!(
+                "custom_id '{id}' is used more than once"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Send a batch's requests, a few at a time, recording each result. Each
+/// runs in a request context of its own, copied from the one that created
+/// the batch, with a request id made of the batch id and its `custom_id`.
+async fn run(state: Arc<AppState>, id: String, requests: Vec<BatchRequest>, ctx: RequestContext) {
+    let (state, id, ctx) = (&state, &id, &ctx);
+    stream::iter(requests.into_iter().enumerate())
+        .map(|(index, request)| async move {
+            let result = if let Some(result) = state.batches.skip(id) {
+                result
+            } else {
+                let mut ctx = ctx.clone();
+                ctx.request_id = format!("{id}/{}", request.custom_id);
+                log_context::scope(ctx, send(state, request.params)).await
+            };
+            state.batches.finish(id, index, result);
+        })
+        .buffer_unordered(CONCURRENCY)
+        .for_each(|()| async {})
+        .await;
+    state.logger.info("batches", format!("Batch {id} ended"));
+}
+
+async fn send(state: &AppState, mut params: MessagesRequest) -> BatchResult {
+    params.stream = None;
+    log_context::set_request(
+        &params.model,
+        params.metadata.as_ref().and_then(|m| m.user_id.as_deref()),
+    );
+    state.logger.log_with_context(
+        LogLevel::Info,
+        "batches",
+        "Batch request",
+        serde_json::json!({ "messages": params.messages.len() }),
+    );
+    match proxy::proxy_parsed_non_streaming(&params, state).await {
+        Ok(message) => BatchResult::Succeeded { message },
+        Err(e) => {
+            state
+                .logger
+                .warn("batches", format!("Batch request failed: {e}"));
+            BatchResult::Errored {
+                error: e.to_error_response(),
+            }
+        }
+    }
+}
+
+/// `POST /v1/messages/batches`
+async fn create(State(state): State<Arc<AppState>>, body: axum::body::Bytes) -> Response {
+    let body: CreateBatch = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            return ProxyError::invalid_request(format!("Invalid request body: {e}"))
+                .into_response();
+        }
+    };
+    if let Err(e) = validate(&body.requests) {
+        return e.into_response();
+    }
+
+    let ctx = log_context::current().unwrap_or_default();
+    let custom_ids = body.requests.iter().map(|r| r.custom_id.clone()).collect();
+    let id = state.batches.insert(ctx.tenant_key.clone(), custom_ids);
+    state.logger.log_with_context(
+        LogLevel::Info,
+        "batches",
+        format!("Batch {id} created"),
+        serde_json::json!({ "requests": body.requests.len() }),
+    );
+    let tenant_key = ctx.tenant_key.clone();
+    tokio::spawn(run(state.clone(), id.clone(), body.requests, ctx));
+
+    match state.batches.get(&id, tenant_key.as_deref()) {
+        Some(batch) => Json(batch).into_response(),
+        None => not_found(&id),
+    }
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+    /// Batches older than this one.
+    after_id: Option<String>,
+    /// Batches newer than this one.
+    before_id: Option<String>,
+}
+
+/// `GET /v1/messages/batches`, newest first.
+async fn list(State(state): State<Arc<AppState>>, Query(query): Query<ListQuery>) -> Response {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let batches = state.batches.list(tenant_key().as_deref());
+    let position = |id: &str| batches.iter().position(|b| b.id == id);
+    let (page, has_more) = if let Some(before) = query.before_id.as_deref() {
+        let end = position(before).unwrap_or(0);
+        let start = end.saturating_sub(limit);
+        (&batches[start..end], start > 0)
+    } else {
+        let start = query
+            .after_id
+            .as_deref()
+            .map_or(0, |after| position(after).map_or(batches.len(), |i| i + 1));
+        let end = (start + limit).min(batches.len());
+        (&batches[start..end], end < batches.len())
+    };
+    Json(serde_json::json!({
+        "data": page,
+        "has_more": has_more,
+        "first_id": page.first().map(|b| &b.id),
+        "last_id": page.last().map(|b| &b.id),
+    }))
+    .into_response()
+}
+
+/// `GET /v1/messages/batches/{id}`
+async fn retrieve(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    match state.batches.get(&id, tenant_key().as_deref()) {
+        Some(batch) => Json(batch).into_response(),
+        None => not_found(&id),
+    }
+}
+
+/// `POST /v1/messages/batches/{id}/cancel`
+async fn cancel(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    match state.batches.cancel(&id, tenant_key().as_deref()) {
+        Some(batch) => {
+            state.logger.info("batches", format!("Batch {id} canceled"));
+            Json(batch).into_response()
+        }
+        None => not_found(&id),
+    }
+}
+
+/// `DELETE /v1/messages/batches/{id}`
+async fn delete(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    match state.batches.delete(&id, tenant_key().as_deref()) {
+        Some(Ok(())) => Json(serde_json::json!({
+            "id": id,
+            "type": "message_batch_deleted",
+        }))
+        .into_response(),
+        Some(Err(e)) => e.into_response(),
+        None => not_found(&id),
+    }
+}
+
+/// `GET /v1/messages/batches/{id}/results`, as JSON Lines.
+async fn results(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    match state.batches.results(&id, tenant_key().as_deref()) {
+        Some(Ok(lines)) => ([(header::CONTENT_TYPE, "application/x-jsonl")], lines).into_response(),
+        Some(Err(e)) => e.into_response(),
+        None => not_found(&id),
+    }
+}
+
+/// The `[auth]` key of the client making the request.
+fn tenant_key() -> Option<String> {
+    log_context::current().and_then(|ctx| ctx.tenant_key)
+}
+
+fn not_found(id: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new(
+            "not_found_error",
+            format!("No batch '{id}'"),
+        )),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(custom_id: &str) -> BatchRequest {
+        serde_json::from_value(serde_json::json!({
+            "custom_id": custom_id,
+            "params": {"model": "m", "max_tokens": 10, "messages": [{"role": "user", "content": "Hi"}]},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[request("a"), request("b-2_c")]).is_ok());
+        assert!(validate(&[]).is_err());
+        assert!(validate(&[request("")]).is_err());
+        assert!(validate(&[request("a b")]).is_err());
+        assert!(validate(&[request(&"x".repeat(65))]).is_err());
+        assert!(validate(&[request("a"), request("a")]).is_err());
+    }
+
+    #[test]
+    fn test_batch_lifecycle() {
+        let batches = Batches::new();
+        let id = batches.insert(None, vec!["a".to_string(), "b".to_string()]);
+        let batch = batches.get(&id, None).unwrap();
+        assert_eq!(batch.processing_status, "in_progress");
+        assert_eq!(batch.request_counts.processing, 2);
+        assert!(batch.results_url.is_none());
+        assert!(batches.results(&id, None).unwrap().is_err());
+        assert!(batches.delete(&id, None).unwrap().is_err());
+
+        // Once canceled, requests not yet sent are canceled
+        batches.finish(
+            &id,
+            0,
+            BatchResult::Errored {
+                error: ErrorResponse::api_error("boom"),
+            },
+        );
+        assert_eq!(
+            batches.cancel(&id, None).unwrap().processing_status,
+            "canceling"
+        );
+        let result = batches.skip(&id).unwrap();
+        batches.finish(&id, 1, result);
+
+        let batch = batches.get(&id, None).unwrap();
+        assert_eq!(batch.processing_status, "ended");
+        assert_eq!(
+            batch.request_counts,
+            RequestCounts {
+                errored: 1,
+                canceled: 1,
+                ..RequestCounts::default()
+            }
+        );
+        assert_eq!(
+            batch.results_url.unwrap(),
+            format!("/v1/messages/batches/{id}/results")
+        );
+        assert_eq!(
+            batches.results(&id, None).unwrap().unwrap(),
+            "{\"custom_id\":\"a\",\"result\":{\"type\":\"errored\",\"error\":{\"type\":\"error\",\"error\":{\"type\":\"api_error\",\"message\":\"boom\"}}}}\n\
+             {\"custom_id\":\"b\",\"result\":{\"type\":\"canceled\"}}\n"
+        );
+
+        assert!(batches.delete(&id, None).unwrap().is_ok());
+        assert!(batches.get(&id, None).is_none());
+    }
+
+    #[test]
+    fn test_batches_belong_to_their_tenant() {
+        let batches = Batches::new();
+        let id = batches.insert(Some("key-a".to_string()), vec!["a".to_string()]);
+        assert!(batches.get(&id, Some("key-a")).is_some());
+        assert_eq!(batches.list(Some("key-a")).len(), 1);
+
+        // Another key, or none, is told there is no such batch
+        for other in [Some("key-b"), None] {
+            assert!(batches.get(&id, other).is_none());
+            assert!(batches.list(other).is_empty());
+            assert!(batches.cancel(&id, other).is_none());
+            assert!(batches.results(&id, other).is_none());
+            assert!(batches.delete(&id, other).is_none());
+        }
+        assert_eq!(
+            batches.get(&id, Some("key-a")).unwrap().processing_status,
+            "in_progress"
+        );
+    }
+}
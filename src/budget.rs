@@ -0,0 +1,93 @@
+//! Retry budgets shared across a conversation.
+//!
+//! Every retry of a transient upstream error and every fallback to the next
+//! provider spends one unit of the session's budget. A session that has spent
+//! its `[retry_budget]` allowance within the window gets an `overloaded_error`
+//! instead of more retries, so a provider outage isn't amplified by every
+//! client retrying every request several times over.
+//!
+//! Sessions are identified by the request context's session id (see
+//! [`log_context`](crate::log_context)); requests without one aren't limited.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Retries spent per session, as timestamps within the current window.
+#[derive(Debug, Clone, Default)]
+pub struct RetryBudget(Arc<Mutex<HashMap<String, VecDeque<Instant>>>>);
+
+impl RetryBudget {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spend one retry for `session` if fewer than `limit` were spent in the
+    /// last `window`. Returns `false`, spending nothing, once the budget is used up.
+    #[must_use]
+    pub fn try_spend(&self, session: &str, limit: u32, window: Duration) -> bool {
+        let Ok(mut sessions) = self.0.lock() else {
+            return true;
+        };
+        let now = Instant::now();
+        let expired = |at: &Instant| now.duration_since(*at) >= window;
+
+        if !sessions.contains_key(session) {
+            // Forget sessions that have gone quiet before tracking a new one
+            sessions.retain(|_, spent| !spent.back().map_or(true, expired));
+        }
+        let spent = sessions.entry(session.to_string()).or_default();
+        while spent.front().is_some_and(expired) {
+            spent.pop_front();
+        }
+        if spent.len() >= limit as usize {
+            return false;
+        }
+        spent.push_back(now);
+        true
+    }
+
+    /// Retries `session` has spent in the last `window`.
+    #[must_use]
+    pub fn spent(&self, session: &str, window: Duration) -> usize {
+        let Ok(sessions) = self.0.lock() else {
+            return 0;
+        };
+        let now = Instant::now();
+        sessions.get(session).map_or(0, |spent| {
+            spent
+                .iter()
+                .filter(|at| now.duration_since(**at) < window)
+                .count()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_is_per_session() {
+        let budget = RetryBudget::new();
+        let window = Duration::from_secs(60);
+        assert!(budget.try_spend("s1", 2, window));
+        assert!(budget.try_spend("s1", 2, window));
+        assert!(!budget.try_spend("s1", 2, window));
+        assert_eq!(budget.spent("s1", window), 2);
+
+        assert!(budget.try_spend("s2", 2, window));
+        assert!(!budget.try_spend("s3", 0, window));
+    }
+
+    #[test]
+    fn test_budget_refills_after_window() {
+        let budget = RetryBudget::new();
+        let window = Duration::from_millis(20);
+        assert!(budget.try_spend("s1", 1, window));
+        assert!(!budget.try_spend("s1", 1, window));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(budget.try_spend("s1", 1, window));
+    }
+}
@@ -0,0 +1,195 @@
+//! Per-request capture of exactly what was forwarded, for debugging.
+//!
+//! With `[capture] dir` set, every translated request writes one pretty JSON
+//! file to `<dir>/<YYYY-MM-DD>/<request id>.json`: the Anthropic request as
+//! received, the `OpenAI` request it was translated to, the upstream's
+//! response and the Anthropic response sent back. Streams capture their chunks
+//! and events in order. A request tried more than once (fallback, `expect`
+//! re-prompts) writes one file per attempt, `<request id>.2.json` and so on.
+//!
+//! Unlike `[record]`, captures are meant for reading, not replay. With
+//! `[encryption]` on they are written encrypted, as `<request id>.json.enc`;
+//! `claude-proxy decrypt` prints one.
+
+use crate::encryption::Cipher;
+use crate::error::Result;
+use crate::log_context;
+use crate::translate::anthropic_types::MessagesRequest;
+use crate::translate::openai_types::ChatCompletionRequest;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// One captured attempt at a request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capture {
+    pub request_id: String,
+    pub captured_at: DateTime<Utc>,
+    pub provider: String,
+    pub streaming: bool,
+    pub anthropic_request: Value,
+    pub openai_request: Value,
+    /// Upstream status; 0 if no response arrived.
+    pub status: u16,
+    /// The upstream response: the parsed response body, the chunks of a
+    /// stream, or the raw body of an error.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub openai_response: Value,
+    /// What the client got back: the response, or the events of a stream.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub anthropic_response: Value,
+}
+
+impl Capture {
+    /// A capture of a request about to be sent to `provider`, under the
+    /// current request id.
+    #[must_use]
+    pub fn new(req: &MessagesRequest, provider: &str, openai_req: &ChatCompletionRequest) -> Self {
+        Self {
+            request_id: log_context::current()
+                .map_or_else(|| uuid::Uuid::new_v4().to_string(), |ctx| ctx.request_id),
+            captured_at: Utc::now(),
+            provider: provider.to_string(),
+            streaming: req.stream.unwrap_or(false),
+            anthropic_request: serde_json::to_value(req).unwrap_or_default(),
+            openai_request: serde_json::to_value(openai_req).unwrap_or_default(),
+            status: 0,
+            openai_response: Value::Null,
+            anthropic_response: Value::Null,
+        }
+    }
+}
+
+/// Writes captures when `[capture] dir` is set; a no-op otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Capturer {
+    dir: Option<PathBuf>,
+    cipher: Option<Cipher>,
+}
+
+impl Capturer {
+    #[must_use]
+    pub fn new(dir: Option<&str>) -> Self {
+        Self {
+            dir: dir.map(PathBuf::from),
+            cipher: None,
+        }
+    }
+
+    /// Encrypt captures with `cipher`.
+    #[must_use]
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Write a capture into its day's directory, returning the file written
+    /// (`None` when capture is off). Synchronous, like the recorder, so a
+    /// stream can be saved as it's dropped.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Io` if the directory or file can't be written.
+    pub fn save(&self, capture: &Capture) -> Result<Option<PathBuf>> {
+        let Some(ref dir) = self.dir else {
+            return Ok(None);
+        };
+        let day = dir.join(capture.captured_at.format("%Y-%m-%d").to_string());
+        std::fs::create_dir_all(&day)?;
+        let mut json = serde_json::to_vec_pretty(capture)?;
+        let mut extension = "json";
+        if let Some(ref cipher) = self.cipher {
+            json = (cipher.seal(&json)? + "\n").into_bytes();
+            extension = "json.enc";
+        }
+        let stem = file_stem(&capture.request_id);
+        for attempt in 1.. {
+            let path = capture_path(&day, &stem, attempt, extension);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(&json)?;
+                    return Ok(Some(path));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        unreachable!("some attempt number is free")
+    }
+}
+
+/// The request id made safe as a file name: it may come from the client's
+/// `x-request-id` header.
+fn file_stem(request_id: &str) -> String {
+    let stem: String = request_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .take(128)
+        .collect();
+    if stem.is_empty() {
+        "request".to_string()
+    } else {
+        stem
+    }
+}
+
+fn capture_path(day: &Path, stem: &str, attempt: u32, extension: &str) -> PathBuf {
+    if attempt == 1 {
+        day.join(format!("{stem}.{extension}"))
+    } else {
+        day.join(format!("{stem}.{attempt}.{extension}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_per_day_and_attempt() {
+        let dir = tempfile::tempdir().unwrap();
+        let capturer = Capturer::new(dir.path().to_str());
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hi"}]
+        }))
+        .unwrap();
+        let openai_req =
+            crate::translate::request::anthropic_to_openai_for_model(&req, "kimi-k2p5");
+        let mut capture = Capture::new(&req, "fireworks", &openai_req);
+        capture.request_id = "../req_1".to_string();
+
+        let first = capturer.save(&capture).unwrap().unwrap();
+        let second = capturer.save(&capture).unwrap().unwrap();
+
+        let day = dir
+            .path()
+            .join(capture.captured_at.format("%Y-%m-%d").to_string());
+        assert_eq!(first, day.join("req_1.json"));
+        assert_eq!(second, day.join("req_1.2.json"));
+        let saved: Capture = serde_json::from_slice(&std::fs::read(&first).unwrap()).unwrap();
+        assert_eq!(saved.openai_request["model"], "kimi-k2p5");
+        assert!(Capturer::default().save(&capture).unwrap().is_none());
+
+        let cipher = Cipher::from_hex(&"ab".repeat(32)).unwrap();
+        let encrypted = Capturer::new(dir.path().to_str())
+            .with_cipher(Some(cipher.clone()))
+            .save(&capture)
+            .unwrap()
+            .unwrap();
+        assert_eq!(encrypted, day.join("req_1.json.enc"));
+        let sealed = std::fs::read_to_string(&encrypted).unwrap();
+        assert!(!sealed.contains("kimi-k2p5"));
+        let saved: Capture = serde_json::from_slice(&cipher.open(&sealed).unwrap()).unwrap();
+        assert_eq!(saved.request_id, "../req_1");
+    }
+}
@@ -0,0 +1,136 @@
+//! Per-provider limits on requests in flight.
+//!
+//! A provider with `max_concurrent_upstream` set gets that many slots. A
+//! request that finds them all taken waits in line, so a burst of parallel
+//! Claude Code subagents is spread out instead of tripping the provider's
+//! rate limits, and gives up with `overloaded_error` after the provider's
+//! `queue_timeout_secs`. A streamed response holds its slot until the stream
+//! ends.
+
+use crate::config::ProviderConfig;
+use crate::error::{ProxyError, Result};
+use crate::logging::SharedLogger;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A provider's slots, sized for the limit they were created with.
+#[derive(Debug)]
+struct Slots {
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+/// Shared slot counts, keyed by provider name.
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyLimiter(Arc<Mutex<HashMap<String, Slots>>>);
+
+/// A slot held for one upstream request; released when dropped. Unlimited
+/// providers hand out empty permits.
+#[derive(Debug)]
+pub struct UpstreamPermit(#[allow(dead_code)] Option<OwnedSemaphorePermit>);
+
+impl ConcurrencyLimiter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take one of the provider's slots, waiting up to its queue timeout.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Overloaded` if no slot frees up in time.
+    pub async fn acquire(
+        &self,
+        provider: &ProviderConfig,
+        logger: &SharedLogger,
+    ) -> Result<UpstreamPermit> {
+        let Some(limit) = provider.max_concurrent_upstream else {
+            return Ok(UpstreamPermit(None));
+        };
+        let semaphore = self.semaphore(&provider.name, limit);
+        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            return Ok(UpstreamPermit(Some(permit)));
+        }
+
+        let timeout = provider.queue_timeout();
+        logger.debug(
+            "queue",
+            format!(
+                "{} has {limit} requests in flight; waiting for a free slot",
+                provider.name
+            ),
+        );
+        if let Ok(Ok(permit)) = tokio::time::timeout(timeout, semaphore.acquire_owned()).await {
+            return Ok(UpstreamPermit(Some(permit)));
+        }
+        logger.warn(
+            "queue",
+            format!(
+                "No free slot on {} within {}s; shedding the request",
+                provider.name,
+                timeout.as_secs()
+            ),
+        );
+        Err(ProxyError::overloaded(format!(
+            "{} is at its limit of {limit} concurrent requests; \
+             gave up after queueing for {}s",
+            provider.name,
+            timeout.as_secs()
+        )))
+    }
+
+    /// The provider's semaphore. A changed limit (after a config reload) gets
+    /// a fresh one; requests holding slots of the old one finish normally.
+    fn semaphore(&self, provider: &str, limit: usize) -> Arc<Semaphore> {
+        let mut slots = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = slots.entry(provider.to_string()).or_insert_with(|| Slots {
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit)),
+        });
+        if entry.limit != limit {
+            *entry = Slots {
+                limit,
+                semaphore: Arc::new(Semaphore::new(limit)),
+            };
+        }
+        entry.semaphore.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limit_queues_then_sheds() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+        let mut provider: ProviderConfig = serde_json::from_value(serde_json::json!({
+            "name": "groq",
+            "max_concurrent_upstream": 1,
+            "queue_timeout_secs": 1,
+        }))
+        .unwrap();
+        let limiter = ConcurrencyLimiter::new();
+
+        let held = limiter.acquire(&provider, &logger).await.unwrap();
+        let queued = {
+            let (limiter, provider, logger) = (limiter.clone(), provider.clone(), logger.clone());
+            tokio::spawn(async move { limiter.acquire(&provider, &logger).await })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        drop(held);
+        let held = queued.await.unwrap().unwrap();
+
+        provider.queue_timeout_secs = Some(0);
+        let err = limiter.acquire(&provider, &logger).await.unwrap_err();
+        assert!(matches!(err, ProxyError::Overloaded { .. }));
+        drop(held);
+
+        provider.max_concurrent_upstream = None;
+        let _a = limiter.acquire(&provider, &logger).await.unwrap();
+        let _b = limiter.acquire(&provider, &logger).await.unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+//! Keeping prompts within a model's context window.
+//!
+//! A model's context window (a mapped model's `max_context`, else the
+//! registry's `context_window`) is checked against the translated prompt's
+//! estimated size before it is sent. A prompt that fills the window would only
+//! be rejected by the backend, in its own words; instead, by `context_strategy`,
+//! the proxy either answers as Anthropic does (`prompt is too long: N tokens >
+//! M maximum`, which Claude Code meets by compacting the conversation), or
+//! drops the oldest turns until it fits, so a long session keeps going on a
+//! small-context model. A note in the system prompt tells the model how many
+//! messages it no longer sees.
+
+use crate::config::ContextStrategy;
+use crate::error::{ProxyError, Result};
+use crate::tokens::estimate_json_tokens;
+use crate::translate::openai_types::{ChatCompletionRequest, ChatContent, ChatMessage};
+
+/// Estimated prompt tokens of a translated request: its messages and tools.
+#[must_use]
+pub fn prompt_tokens(openai_req: &ChatCompletionRequest) -> u64 {
+    estimate_json_tokens(&openai_req.messages) + estimate_json_tokens(&openai_req.tools)
+}
+
+/// Tokens set aside for the note on dropped messages.
+const NOTE_TOKENS: u64 = 32;
+
+/// Make a request's prompt fit in `window` tokens, returning how many
+/// messages were dropped to do so.
+///
+/// # Errors
+/// Returns `ProxyError::InvalidRequest` ("prompt is too long") if the prompt
+/// doesn't fit and `strategy` is `Reject`, or if even its last turn alone
+/// doesn't fit.
+pub fn fit(
+    openai_req: &mut ChatCompletionRequest,
+    window: u64,
+    strategy: ContextStrategy,
+) -> Result<usize> {
+    let tokens = prompt_tokens(openai_req);
+    if tokens < window {
+        return Ok(0);
+    }
+    let too_long = || {
+        ProxyError::invalid_request(format!(
+            "prompt is too long: {tokens} tokens > {window} maximum"
+        ))
+    };
+    if strategy == ContextStrategy::Reject {
+        return Err(too_long());
+    }
+
+    // System messages stay; the conversation after them is cut at the start
+    // of a user turn, so it never opens on a tool result or a reply
+    let start = openai_req
+        .messages
+        .iter()
+        .take_while(|m| m.role == "system")
+        .count();
+    let sizes: Vec<u64> = openai_req.messages[start..]
+        .iter()
+        .map(message_tokens)
+        .collect();
+    // The tools and system prompt, which stay whatever is cut
+    let mut remaining: u64 = sizes.iter().sum();
+    let fixed = tokens.saturating_sub(remaining) + NOTE_TOKENS;
+    let mut cut = None;
+    for (i, (message, size)) in openai_req.messages[start..].iter().zip(&sizes).enumerate() {
+        if i > 0 && message.role == "user" && fixed + remaining < window {
+            cut = Some(start + i);
+            break;
+        }
+        remaining -= size;
+    }
+    let cut = cut.ok_or_else(too_long)?;
+    openai_req.messages.drain(start..cut);
+    note_dropped(&mut openai_req.messages, start, cut - start);
+    Ok(cut - start)
+}
+
+/// Tell the model, in the system prompt, that the start of the conversation
+/// is gone. `start` is the number of system messages; the note goes at the end
+/// of the last, or in one of its own if there are none.
+fn note_dropped(messages: &mut Vec<ChatMessage>, start: usize, dropped: usize) {
+    let note = format!(
+        "The {dropped} oldest messages of this conversation were removed to fit the \
+         context window; earlier context may be missing."
+    );
+    if let Some(ChatMessage {
+        content: Some(ChatContent::Text(text)),
+        ..
+    }) = start.checked_sub(1).and_then(|i| messages.get_mut(i))
+    {
+        let text = text.to_mut();
+        text.push_str("\n\n");
+        text.push_str(&note);
+    } else {
+        messages.insert(
+            start,
+            ChatMessage {
+                role: "system".to_string(),
+                content: Some(ChatContent::Text(note.into())),
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            },
+        );
+    }
+}
+
+fn message_tokens(message: &ChatMessage) -> u64 {
+    estimate_json_tokens(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(messages: &[(&str, usize)]) -> ChatCompletionRequest<'static> {
+        let messages: Vec<serde_json::Value> = messages
+            .iter()
+            .map(|(role, chars)| {
+                let mut message = serde_json::json!({"role": role, "content": "x".repeat(*chars)});
+                if *role == "tool" {
+                    message["tool_call_id"] = "call_1".into();
+                }
+                message
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({"model": "m", "messages": messages})).unwrap()
+    }
+
+    fn roles<'a>(req: &'a ChatCompletionRequest) -> Vec<&'a str> {
+        req.messages.iter().map(|m| m.role.as_str()).collect()
+    }
+
+    #[test]
+    fn test_fit() {
+        let turns = [
+            ("system", 400),
+            ("user", 400),
+            ("assistant", 400),
+            ("tool", 400),
+            ("user", 400),
+            ("assistant", 400),
+            ("user", 40),
+        ];
+        let mut req = request(&turns);
+        assert_eq!(fit(&mut req, 10_000, ContextStrategy::Reject).unwrap(), 0);
+        let err = fit(&mut req, 500, ContextStrategy::Reject).unwrap_err();
+        assert!(err.to_string().contains("prompt is too long"), "{err}");
+        assert_eq!(req.messages.len(), turns.len());
+
+        // Cut at a user turn, never at the tool result, keeping the system prompt
+        assert_eq!(
+            fit(&mut req, 500, ContextStrategy::TruncateOldest).unwrap(),
+            3
+        );
+        assert_eq!(roles(&req), ["system", "user", "assistant", "user"]);
+        let Some(ChatContent::Text(system)) = &req.messages[0].content else {
+            panic!("system prompt lost");
+        };
+        assert!(system.contains("The 3 oldest messages of this conversation were removed"));
+        let mut req = request(&turns);
+        assert_eq!(
+            fit(&mut req, 250, ContextStrategy::TruncateOldest).unwrap(),
+            5
+        );
+        assert_eq!(roles(&req), ["system", "user"]);
+
+        // Without a system prompt the note gets a message of its own
+        let mut req = request(&turns[1..]);
+        assert_eq!(
+            fit(&mut req, 250, ContextStrategy::TruncateOldest).unwrap(),
+            5
+        );
+        assert_eq!(roles(&req), ["system", "user"]);
+
+        // Not even the last turn fits
+        let mut req = request(&turns);
+        assert!(fit(&mut req, 100, ContextStrategy::TruncateOldest).is_err());
+    }
+}
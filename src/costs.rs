@@ -0,0 +1,231 @@
+//! Spend reports over recorded usage.
+//!
+//! Each completed request is costed when it's recorded, at the `[costs]` price
+//! of its upstream model (see [`CostsConfig`](crate::config::CostsConfig)), so
+//! later price changes don't rewrite history. [`report`] totals those records
+//! overall, per upstream model, per client session and per
+//! `x-claude-proxy-tags` tag; it backs both `GET /stats` and
+//! `claude-proxy stats`.
+
+use crate::error::{ProxyError, Result};
+use crate::storage::UsageRecord;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Requests, tokens and spend for some group of requests.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// USD spent on requests with a known price.
+    pub cost: f64,
+    /// Requests to models without a `[costs]` price, not included in `cost`.
+    pub unpriced_requests: u64,
+}
+
+impl Totals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.input_tokens += record.input_tokens;
+        self.output_tokens += record.output_tokens;
+        match record.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced_requests += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SpendReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    pub total: Totals,
+    /// Keyed by `"<provider>/<upstream model>"`.
+    pub by_model: BTreeMap<String, Totals>,
+    /// Keyed by session id; requests without one are left out.
+    pub by_session: BTreeMap<String, Totals>,
+    /// Keyed by tag; a request with several tags counts under each.
+    pub by_tag: BTreeMap<String, Totals>,
+}
+
+/// Total up usage records, optionally only those of one session and/or
+/// carrying one tag.
+#[must_use]
+pub fn report(
+    records: &[UsageRecord],
+    since: Option<DateTime<Utc>>,
+    session: Option<&str>,
+    tag: Option<&str>,
+) -> SpendReport {
+    let mut report = SpendReport {
+        since,
+        ..SpendReport::default()
+    };
+    for record in records {
+        if session.is_some() && record.session_id.as_deref() != session {
+            continue;
+        }
+        if tag.is_some_and(|tag| !record.tags.iter().any(|t| t == tag)) {
+            continue;
+        }
+        report.total.add(record);
+        report
+            .by_model
+            .entry(format!("{}/{}", record.provider, record.upstream_model))
+            .or_default()
+            .add(record);
+        if let Some(ref id) = record.session_id {
+            report.by_session.entry(id.clone()).or_default().add(record);
+        }
+        for tag in &record.tags {
+            report.by_tag.entry(tag.clone()).or_default().add(record);
+        }
+    }
+    report
+}
+
+/// Parse a report start: an RFC 3339 timestamp, or a span back from `now`
+/// such as `30m`, `24h` or `7d`.
+///
+/// # Errors
+/// Returns `ProxyError::InvalidRequest` if `value` is neither.
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let invalid = || {
+        ProxyError::invalid_request(format!(
+            "Invalid 'since' value '{value}': use an RFC 3339 time or a span like 24h or 7d"
+        ))
+    };
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let count: i64 = value[..value.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let span = match unit {
+        'm' => Duration::minutes(count),
+        'h' => Duration::hours(count),
+        'd' => Duration::days(count),
+        _ => return Err(invalid()),
+    };
+    Ok(now - span)
+}
+
+/// A plain-text rendering of a report, for the terminal.
+#[must_use]
+pub fn render_table(report: &SpendReport) -> String {
+    let mut out = String::new();
+    match report.since {
+        Some(since) => {
+            let _ = writeln!(out, "Usage since {}", since.format("%Y-%m-%d %H:%M UTC"));
+        }
+        None => out.push_str("All recorded usage\n"),
+    }
+    out.push('\n');
+    write_section(&mut out, "Model", &report.by_model);
+    if !report.by_session.is_empty() {
+        out.push('\n');
+        write_section(&mut out, "Session", &report.by_session);
+    }
+    if !report.by_tag.is_empty() {
+        out.push('\n');
+        write_section(&mut out, "Tag", &report.by_tag);
+    }
+    out.push('\n');
+    write_row(&mut out, "Total", &report.total);
+    if report.total.unpriced_requests > 0 {
+        let _ = writeln!(
+            out,
+            "\n{} request(s) to models without a [costs] price are not included in the cost.",
+            report.total.unpriced_requests
+        );
+    }
+    out
+}
+
+fn write_section(out: &mut String, title: &str, rows: &BTreeMap<String, Totals>) {
+    let _ = writeln!(
+        out,
+        "{title:<48} {:>8} {:>12} {:>12} {:>10}",
+        "Requests", "Input", "Output", "Cost (USD)"
+    );
+    for (name, totals) in rows {
+        write_row(out, name, totals);
+    }
+}
+
+fn write_row(out: &mut String, name: &str, totals: &Totals) {
+    let _ = writeln!(
+        out,
+        "{name:<48} {:>8} {:>12} {:>12} {:>10.4}",
+        totals.requests, totals.input_tokens, totals.output_tokens, totals.cost
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(model: &str, session: Option<&str>, cost: Option<f64>) -> UsageRecord {
+        UsageRecord {
+            timestamp: Utc::now(),
+            provider: "groq".to_string(),
+            requested_model: "claude-sonnet-4-20250514".to_string(),
+            upstream_model: model.to_string(),
+            input_tokens: 1000,
+            output_tokens: 200,
+            streaming: true,
+            session_id: session.map(str::to_string),
+            tags: session
+                .map(|s| vec![format!("task={s}")])
+                .unwrap_or_default(),
+            cost,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let records = vec![
+            record("kimi-k2", Some("s1"), Some(0.5)),
+            record("kimi-k2", Some("s2"), Some(0.25)),
+            record("llama", Some("s1"), None),
+        ];
+
+        let all = report(&records, None, None, None);
+        assert_eq!(all.total.requests, 3);
+        assert_eq!(all.total.input_tokens, 3000);
+        assert!((all.total.cost - 0.75).abs() < 1e-9);
+        assert_eq!(all.total.unpriced_requests, 1);
+        assert_eq!(all.by_model["groq/kimi-k2"].requests, 2);
+        assert_eq!(all.by_session["s1"].requests, 2);
+        assert_eq!(all.by_tag["task=s2"].requests, 1);
+
+        let s1 = report(&records, None, Some("s1"), None);
+        assert_eq!(s1.total.requests, 2);
+        assert!((s1.total.cost - 0.5).abs() < 1e-9);
+        assert!(render_table(&s1).contains("groq/llama"));
+
+        let tagged = report(&records, None, None, Some("task=s2"));
+        assert_eq!(tagged.total.requests, 1);
+        assert!((tagged.total.cost - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = Utc::now();
+        assert_eq!(parse_since("24h", now).unwrap(), now - Duration::hours(24));
+        assert_eq!(parse_since("7d", now).unwrap(), now - Duration::days(7));
+        assert_eq!(
+            parse_since("2025-01-01T00:00:00Z", now)
+                .unwrap()
+                .to_rfc3339(),
+            "2025-01-01T00:00:00+00:00"
+        );
+        assert!(parse_since("soon", now).is_err());
+        assert!(parse_since("", now).is_err());
+    }
+}
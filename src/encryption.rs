@@ -0,0 +1,191 @@
+//! At-rest encryption of logs, usage records, cached values and captures.
+//!
+//! Logs and captures hold whole prompts, which for Claude Code means source
+//! code. With `[encryption]` configured, the file storage backend and
+//! `[capture]` write everything through a [`Cipher`]: AES-256-GCM with a
+//! 32-byte key given as 64 hex characters, from an environment variable
+//! (`key_env`) or a file (`key_file`, e.g. a mounted secret). Generate one with
+//! `openssl rand -hex 32`.
+//!
+//! Each record is sealed separately with a fresh random nonce and written as
+//! one text line, `enc:v1:` followed by the hex of the nonce, ciphertext and
+//! tag. JSONL files stay line-oriented, so appends and compaction work as
+//! before, and lines written before encryption was turned on are still read.
+//! `claude-proxy decrypt <FILE>` prints a file's plaintext.
+
+use crate::config::EncryptionConfig;
+use crate::error::{ProxyError, Result};
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+/// Marks a sealed line, and the format version.
+pub const SEALED_PREFIX: &str = "enc:v1:";
+
+/// Encrypts and decrypts records with the configured key.
+#[derive(Clone)]
+pub struct Cipher {
+    key: Arc<LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cipher(AES-256-GCM)")
+    }
+}
+
+impl Cipher {
+    /// The cipher for `[encryption]`, or `None` when no key is configured.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Config` if the key's variable or file is missing
+    /// or doesn't hold 64 hex characters.
+    pub fn from_config(config: &EncryptionConfig) -> Result<Option<Self>> {
+        let hex = match (&config.key_env, &config.key_file) {
+            (Some(var), _) => std::env::var(var).map_err(|_| {
+                ProxyError::config(format!("Encryption key variable '{var}' is not set"))
+            })?,
+            (None, Some(path)) => std::fs::read_to_string(path).map_err(|e| {
+                ProxyError::config(format!("Failed to read encryption key file {path}: {e}"))
+            })?,
+            (None, None) => return Ok(None),
+        };
+        Self::from_hex(hex.trim()).map(Some)
+    }
+
+    /// A cipher for a key given as 64 hex characters.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Config` if `hex` isn't a 32-byte hex key.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let key = decode_hex(hex)
+            .filter(|key| key.len() == AES_256_GCM.key_len())
+            .ok_or_else(|| {
+                ProxyError::config("Encryption key must be 64 hex characters (32 bytes)")
+            })?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| ProxyError::config("Invalid encryption key"))?;
+        Ok(Self {
+            key: Arc::new(LessSafeKey::new(key)),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Encrypt `plaintext` into a sealed line (without a newline).
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Storage` if no random nonce could be generated.
+    pub fn seal(&self, plaintext: &[u8]) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| ProxyError::storage("Failed to generate an encryption nonce"))?;
+        let mut sealed = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| ProxyError::storage("Encryption failed"))?;
+        let mut line = String::with_capacity(SEALED_PREFIX.len() + 2 * (NONCE_LEN + sealed.len()));
+        line.push_str(SEALED_PREFIX);
+        for b in nonce.iter().chain(&sealed) {
+            let _ = write!(line, "{b:02x}");
+        }
+        Ok(line)
+    }
+
+    /// Decrypt a sealed line.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Storage` if `line` isn't sealed, or was sealed
+    /// with another key or altered since.
+    pub fn open(&self, line: &str) -> Result<Vec<u8>> {
+        let data = line
+            .trim_end()
+            .strip_prefix(SEALED_PREFIX)
+            .and_then(decode_hex)
+            .filter(|data| data.len() >= NONCE_LEN)
+            .ok_or_else(|| ProxyError::storage("Not an encrypted record"))?;
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| ProxyError::storage("Not an encrypted record"))?;
+        let mut sealed = sealed.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .map_err(|_| {
+                ProxyError::storage("Failed to decrypt a record: wrong key or altered data")
+            })?;
+        Ok(plaintext.to_vec())
+    }
+
+    /// A stored line as plaintext: opened if sealed, else as it is.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Storage` if the line is sealed but can't be opened.
+    pub fn open_line(&self, line: &str) -> Result<String> {
+        if !is_sealed(line) {
+            return Ok(line.to_string());
+        }
+        String::from_utf8(self.open(line)?)
+            .map_err(|_| ProxyError::storage("Decrypted record is not UTF-8"))
+    }
+}
+
+/// Whether a stored line was written by [`Cipher::seal`].
+#[must_use]
+pub fn is_sealed(line: &str) -> bool {
+    line.starts_with(SEALED_PREFIX)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_seal_and_open() {
+        let cipher = Cipher::from_hex(KEY).unwrap();
+        let sealed = cipher.seal(b"{\"message\":\"fn main() {}\"}").unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("main"));
+        // Fresh nonce per record
+        assert_ne!(
+            sealed,
+            cipher.seal(b"{\"message\":\"fn main() {}\"}").unwrap()
+        );
+        assert_eq!(
+            cipher.open_line(&sealed).unwrap(),
+            "{\"message\":\"fn main() {}\"}"
+        );
+        assert_eq!(cipher.open_line("{\"plain\":1}").unwrap(), "{\"plain\":1}");
+
+        let other = Cipher::from_hex(&KEY.replace("1f", "ff")).unwrap();
+        assert!(other.open(&sealed).is_err());
+        let mut altered = sealed.clone();
+        let last = if sealed.ends_with('0') { "1" } else { "0" };
+        altered.replace_range(sealed.len() - 1.., last);
+        assert!(cipher.open(&altered).is_err());
+
+        assert!(Cipher::from_hex("abcd").is_err());
+        assert!(Cipher::from_config(&EncryptionConfig::default())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,5 +1,14 @@
 //! Error types for the proxy.
+//!
+//! Every [`ProxyError`] knows the HTTP status and Anthropic error type it is
+//! reported to clients with, and whether retrying (or falling back to another
+//! provider) could help. Handlers return it as a response directly.
 
+use crate::translate::anthropic_types::ErrorResponse;
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,12 +17,30 @@ pub enum ProxyError {
     #[error("Configuration error: {message}")]
     Config { message: String },
 
+    /// The client's request can't be served as sent.
+    #[error("Invalid request: {message}")]
+    InvalidRequest { message: String },
+
+    /// The provider couldn't be reached or its response couldn't be read.
     #[error("Provider error: {message}")]
     Provider { message: String },
 
+    /// The provider answered with an error status; `error` is its body
+    /// translated to Anthropic format.
+    #[error("Provider returned status {status}: {}", error.error.message)]
+    Upstream { status: u16, error: ErrorResponse },
+
+    /// The request was refused to protect a struggling provider, e.g. because
+    /// its session's retry budget is spent.
+    #[error("Overloaded: {message}")]
+    Overloaded { message: String },
+
     #[error("Translation error: {message}")]
     Translation { message: String },
 
+    #[error("Storage error: {message}")]
+    Storage { message: String },
+
     #[error("HTTP error: {0}")]
     Http(#[from] reqwest::Error),
 
@@ -37,21 +64,157 @@ impl ProxyError {
         }
     }
 
+    pub fn invalid_request(msg: impl Into<String>) -> Self {
+        Self::InvalidRequest {
+            message: msg.into(),
+        }
+    }
+
     pub fn provider(msg: impl Into<String>) -> Self {
         Self::Provider {
             message: msg.into(),
         }
     }
 
+    #[must_use]
+    pub fn upstream(status: u16, error: ErrorResponse) -> Self {
+        Self::Upstream { status, error }
+    }
+
+    pub fn overloaded(msg: impl Into<String>) -> Self {
+        Self::Overloaded {
+            message: msg.into(),
+        }
+    }
+
     pub fn translation(msg: impl Into<String>) -> Self {
         Self::Translation {
             message: msg.into(),
         }
     }
 
+    pub fn storage(msg: impl Into<String>) -> Self {
+        Self::Storage {
+            message: msg.into(),
+        }
+    }
+
     pub fn other(msg: impl Into<String>) -> Self {
         Self::Other(msg.into())
     }
+
+    /// The status this error is reported to clients with: the provider's own
+    /// for upstream errors, 502/504 when the provider misbehaves or is
+    /// unreachable, 400 for bad requests, 529 when the proxy sheds load, and 500
+    /// for the proxy's own failures.
+    #[must_use]
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest { .. } => StatusCode::BAD_REQUEST,
+            // Anthropic's non-standard "overloaded" status
+            Self::Overloaded { .. } => {
+                StatusCode::from_u16(529).unwrap_or(StatusCode::SERVICE_UNAVAILABLE)
+            }
+            Self::Upstream { status, .. } => {
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_GATEWAY)
+            }
+            Self::Http(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            Self::Provider { .. } | Self::Translation { .. } | Self::Http(_) | Self::Json(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::Config { .. }
+            | Self::Storage { .. }
+            | Self::Io(_)
+            | Self::Toml(_)
+            | Self::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether the same request might succeed on a retry or another provider:
+    /// transport failures, and upstream 429/5xx responses.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Provider { .. } => true,
+            Self::Upstream { status, .. } => crate::routing::should_fall_back(*status),
+            Self::Http(e) => e.is_timeout() || e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// The Anthropic-format body reported to clients.
+    #[must_use]
+    pub fn to_error_response(&self) -> ErrorResponse {
+        match self {
+            Self::Upstream { error, .. } => error.clone(),
+            Self::InvalidRequest { message } => ErrorResponse::invalid_request(message.clone()),
+            Self::Overloaded { message } => ErrorResponse::new("overloaded_error", message.clone()),
+            _ => ErrorResponse::new(
+                error_type_for_status(self.status().as_u16()),
+                self.to_string(),
+            ),
+        }
+    }
+}
+
+impl IntoResponse for ProxyError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.to_error_response())).into_response()
+    }
+}
+
+/// The Anthropic error type for an HTTP status.
+#[must_use]
+pub fn error_type_for_status(status: u16) -> &'static str {
+    match status {
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        529 => "overloaded_error",
+        _ => "api_error",
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ProxyError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_mapping() {
+        let bad = ProxyError::invalid_request("missing model");
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            bad.to_error_response().error.error_type,
+            "invalid_request_error"
+        );
+        assert!(!bad.is_retryable());
+
+        let unreachable = ProxyError::provider("connection refused");
+        assert_eq!(unreachable.status(), StatusCode::BAD_GATEWAY);
+        assert!(unreachable.is_retryable());
+
+        let limited =
+            ProxyError::upstream(429, ErrorResponse::new("rate_limit_error", "slow down"));
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.to_error_response().error.message, "slow down");
+        assert!(limited.is_retryable());
+        assert!(!ProxyError::upstream(401, ErrorResponse::api_error("bad key")).is_retryable());
+
+        let shed = ProxyError::overloaded("retry budget spent");
+        assert_eq!(shed.status().as_u16(), 529);
+        assert_eq!(
+            shed.to_error_response().error.error_type,
+            "overloaded_error"
+        );
+        assert!(!shed.is_retryable());
+
+        let config = ProxyError::config("no provider");
+        assert_eq!(config.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(config.to_error_response().error.error_type, "api_error");
+    }
+}
@@ -0,0 +1,550 @@
+//! Comparing two providers on a captured session (`claude-proxy eval`).
+//!
+//! With `[capture] dir` set, every request Claude Code sends is saved (see
+//! [`crate::capture`]). [`run`] replays those requests, in the order they were
+//! captured, against two providers and reports how their answers differ: the
+//! text, the tool calls chosen, tokens, cost and latency. Each request goes to
+//! both providers at once, non-streaming, with every Claude model routed to
+//! the provider under test (using its own `models` remaps) and no fallback.
+//! Nothing is recorded, captured, cached or written to the usage log.
+//!
+//! The replay is turn by turn: each request carries the conversation as the
+//! original provider shaped it, so the two answers are compared against the
+//! same history rather than as two diverging sessions.
+
+use crate::capture::Capture;
+use crate::config::{ModelMapping, ModelRoute, ProxyConfig};
+use crate::encryption::Cipher;
+use crate::error::{ProxyError, Result};
+use crate::log_context;
+use crate::logging::SharedLogger;
+use crate::provider_test;
+use crate::proxy;
+use crate::self_test::NoStorage;
+use crate::state::AppState;
+use crate::translate::anthropic_types::{MessagesRequest, MessagesResponse, ResponseContentBlock};
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long each request may take.
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Most characters of an answer's text to show.
+const MAX_TEXT_CHARS: usize = 80;
+
+/// Read the captures in `paths` (files, or directories searched recursively),
+/// oldest first, leaving out retried attempts and, with `session`, requests
+/// from other client sessions. Encrypted captures need `cipher`.
+///
+/// # Errors
+/// Returns `ProxyError::Io` if a path can't be read, and `ProxyError::Config`
+/// if a capture can't be parsed or decrypted.
+pub fn load(
+    paths: &[PathBuf],
+    cipher: Option<&Cipher>,
+    session: Option<&str>,
+) -> Result<Vec<Capture>> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(path, &mut files)?;
+    }
+    let mut captures = Vec::new();
+    for file in files {
+        let content = std::fs::read(&file)?;
+        let json = if is_encrypted(&file) {
+            let Some(cipher) = cipher else {
+                return Err(ProxyError::config(format!(
+                    "{} is encrypted, and [encryption] is not configured",
+                    file.display()
+                )));
+            };
+            cipher.open(String::from_utf8_lossy(&content).trim())?
+        } else {
+            content
+        };
+        let capture: Capture = serde_json::from_slice(&json)
+            .map_err(|e| ProxyError::config(format!("{} is not a capture: {e}", file.display())))?;
+        if session.map_or(true, |session| session_of(&capture) == Some(session)) {
+            captures.push(capture);
+        }
+    }
+    captures.sort_by_key(|capture| capture.captured_at);
+    Ok(captures)
+}
+
+/// Capture files under `path`: first attempts only, as `<id>.2.json` and on
+/// are the same request retried.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<_> = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        entries.sort();
+        for entry in entries {
+            collect_files(&entry, files)?;
+        }
+        return Ok(());
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let stem = name
+        .strip_suffix(".json.enc")
+        .or_else(|| name.strip_suffix(".json"));
+    if stem.is_some_and(|stem| !stem.contains('.')) {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn is_encrypted(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".json.enc")
+}
+
+fn session_of(capture: &Capture) -> Option<&str> {
+    capture.anthropic_request["metadata"]["user_id"]
+        .as_str()
+        .and_then(log_context::session_from_user_id)
+}
+
+/// A tool call an answer made.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ToolCall {
+    pub name: String,
+    pub input: serde_json::Value,
+}
+
+/// One provider's answer to one request.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Answer {
+    /// The backend model asked.
+    pub model: String,
+    pub latency_ms: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// USD, when the model has a `[costs]` or registry price.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+    pub text: String,
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// How the two answers to one captured request compare.
+#[derive(Debug, Clone, Serialize)]
+pub struct TurnDiff {
+    pub request_id: String,
+    /// The Claude model requested.
+    pub model: String,
+    pub answers: [Answer; 2],
+    /// Both answered, calling the same tools in the same order.
+    pub same_tools: bool,
+    /// As `same_tools`, with the same inputs too.
+    pub same_tool_inputs: bool,
+    /// Overlap of the two texts' words, from 0 (none) to 1 (the same words).
+    pub text_similarity: f64,
+}
+
+/// One provider's totals over the session.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub answered: u64,
+    pub errors: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// USD spent on answers with a known price.
+    pub cost: f64,
+    /// Answers from models without a price, not included in `cost`.
+    pub unpriced: u64,
+    pub latency_ms: u64,
+    pub tool_calls: u64,
+}
+
+impl Totals {
+    fn add(&mut self, answer: &Answer) {
+        if answer.error.is_some() {
+            self.errors += 1;
+            return;
+        }
+        self.answered += 1;
+        self.input_tokens += answer.input_tokens;
+        self.output_tokens += answer.output_tokens;
+        match answer.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+        self.latency_ms += answer.latency_ms;
+        self.tool_calls += answer.tool_calls.len() as u64;
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub providers: [String; 2],
+    pub turns: Vec<TurnDiff>,
+    pub totals: [Totals; 2],
+    /// Turns on which both chose the same tools.
+    pub same_tools: usize,
+}
+
+/// Replay `captures` against the `providers`.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if a provider isn't configured; failed
+/// requests are reported as errored answers.
+pub async fn run(
+    config: &ProxyConfig,
+    captures: &[Capture],
+    providers: [&str; 2],
+) -> Result<EvalReport> {
+    let client = config
+        .outbound
+        .apply(reqwest::Client::builder().timeout(TIMEOUT))?
+        .build()
+        .map_err(ProxyError::Http)?;
+    let states = [pinned(config, providers[0])?, pinned(config, providers[1])?].map(|config| {
+        AppState::new(
+            config,
+            client.clone(),
+            SharedLogger::with_storage(Arc::new(NoStorage)),
+        )
+    });
+
+    let mut report = EvalReport {
+        providers: providers.map(str::to_string),
+        turns: Vec::new(),
+        totals: [Totals::default(), Totals::default()],
+        same_tools: 0,
+    };
+    for capture in captures {
+        let mut req: MessagesRequest = serde_json::from_value(capture.anthropic_request.clone())
+            .map_err(|e| {
+                ProxyError::config(format!(
+                    "Capture {} has no valid request: {e}",
+                    capture.request_id
+                ))
+            })?;
+        req.stream = Some(false);
+        let (a, b) = tokio::join!(answer(&req, &states[0]), answer(&req, &states[1]));
+        let turn = compare(&capture.request_id, &req.model, [a, b]);
+        for (totals, answer) in report.totals.iter_mut().zip(&turn.answers) {
+            totals.add(answer);
+        }
+        report.same_tools += usize::from(turn.same_tools);
+        report.turns.push(turn);
+    }
+    Ok(report)
+}
+
+/// `config` with every Claude model routed to `provider`, without fallback,
+/// and nothing recorded or reused.
+fn pinned(config: &ProxyConfig, provider: &str) -> Result<ProxyConfig> {
+    let provider = config
+        .named_provider(provider)
+        .cloned()
+        .ok_or_else(|| ProxyError::config(format!("Unknown provider '{provider}'")))?;
+    let mut pinned = provider_test::test_config(config);
+    for (claude_model, mapping) in &mut pinned.models {
+        let model = provider
+            .models
+            .get(claude_model)
+            .cloned()
+            .unwrap_or_else(|| mapping.model().to_string());
+        *mapping = match mapping {
+            ModelMapping::Name(_) => ModelMapping::Name(model),
+            ModelMapping::Route(route) => ModelMapping::Route(Box::new(ModelRoute {
+                model,
+                provider: None,
+                ..(**route).clone()
+            })),
+        };
+    }
+    for (claude_model, model) in &provider.models {
+        pinned
+            .models
+            .entry(claude_model.clone())
+            .or_insert_with(|| ModelMapping::Name(model.clone()));
+    }
+    pinned.provider = provider;
+    pinned.fallback.clear();
+    Ok(pinned)
+}
+
+async fn answer(req: &MessagesRequest, state: &AppState) -> Answer {
+    let config = state.config.load();
+    let model = config
+        .route(&req.model)
+        .map(|route| route.model)
+        .unwrap_or_default();
+    let started = Instant::now();
+    let result = proxy::proxy_non_streaming(req, state).await;
+    let latency_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            return Answer {
+                model,
+                latency_ms,
+                error: Some(e.to_string()),
+                ..Answer::default()
+            }
+        }
+    };
+    let price = config
+        .costs
+        .price(&config.provider.name, &model)
+        .copied()
+        .or_else(|| {
+            state
+                .registry
+                .get(&config.provider.name, &model)
+                .and_then(|info| info.price())
+        });
+    Answer {
+        cost: price.map(|price| price.cost(resp.usage.input_tokens, resp.usage.output_tokens)),
+        input_tokens: resp.usage.input_tokens,
+        output_tokens: resp.usage.output_tokens,
+        stop_reason: resp.stop_reason.clone(),
+        text: text_of(&resp),
+        tool_calls: tool_calls_of(&resp),
+        model,
+        latency_ms,
+        error: None,
+    }
+}
+
+fn text_of(resp: &MessagesResponse) -> String {
+    resp.content
+        .iter()
+        .filter_map(|block| match block {
+            ResponseContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn tool_calls_of(resp: &MessagesResponse) -> Vec<ToolCall> {
+    resp.content
+        .iter()
+        .filter_map(|block| match block {
+            ResponseContentBlock::ToolUse { name, input, .. } => Some(ToolCall {
+                name: name.clone(),
+                input: input.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn compare(request_id: &str, model: &str, answers: [Answer; 2]) -> TurnDiff {
+    let [a, b] = &answers;
+    let answered = a.error.is_none() && b.error.is_none();
+    let names = |answer: &Answer| {
+        answer
+            .tool_calls
+            .iter()
+            .map(|call| call.name.clone())
+            .collect::<Vec<_>>()
+    };
+    TurnDiff {
+        request_id: request_id.to_string(),
+        model: model.to_string(),
+        same_tools: answered && names(a) == names(b),
+        same_tool_inputs: answered && a.tool_calls == b.tool_calls,
+        text_similarity: if answered {
+            similarity(&a.text, &b.text)
+        } else {
+            0.0
+        },
+        answers,
+    }
+}
+
+/// The Jaccard index of the two texts' sets of lowercased words.
+#[allow(clippy::cast_precision_loss)]
+fn similarity(a: &str, b: &str) -> f64 {
+    let words = |text: &str| -> HashSet<String> {
+        text.split_whitespace().map(str::to_lowercase).collect()
+    };
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+/// A plain-text rendering of a report, for the terminal.
+#[must_use]
+pub fn render(report: &EvalReport) -> String {
+    let [a, b] = &report.providers;
+    let width = a.len().max(b.len());
+    let mut out = String::new();
+    let _ = writeln!(out, "{a} vs {b}, {} turns\n", report.turns.len());
+    for (i, turn) in report.turns.iter().enumerate() {
+        let _ = writeln!(out, "{}. {} ({})", i + 1, turn.request_id, turn.model);
+        for (provider, answer) in report.providers.iter().zip(&turn.answers) {
+            let _ = write!(out, "   {provider:<width$}  ");
+            if let Some(ref error) = answer.error {
+                let _ = writeln!(out, "error: {error}");
+                continue;
+            }
+            let tools: Vec<&str> = answer.tool_calls.iter().map(|c| c.name.as_str()).collect();
+            let _ = writeln!(
+                out,
+                "{:>6}ms  {:>7} in  {:>6} out  {:<10}  {}  {}",
+                answer.latency_ms,
+                answer.input_tokens,
+                answer.output_tokens,
+                answer.stop_reason.as_deref().unwrap_or("-"),
+                if tools.is_empty() {
+                    "no tools".to_string()
+                } else {
+                    tools.join(", ")
+                },
+                excerpt(&answer.text),
+            );
+        }
+        let tools = match (turn.same_tools, turn.same_tool_inputs) {
+            (true, true) => "same tool calls",
+            (true, false) => "same tools, different inputs",
+            (false, _) => "different tools",
+        };
+        let _ = writeln!(
+            out,
+            "   {tools}, text similarity {:.2}\n",
+            turn.text_similarity
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "{:<width$}  {:>8} {:>6} {:>12} {:>12} {:>10} {:>12} {:>10}",
+        "", "Answered", "Errors", "Input", "Output", "Cost (USD)", "Mean latency", "Tool calls"
+    );
+    for (provider, totals) in report.providers.iter().zip(&report.totals) {
+        let mean = totals.latency_ms.checked_div(totals.answered).unwrap_or(0);
+        let _ = writeln!(
+            out,
+            "{provider:<width$}  {:>8} {:>6} {:>12} {:>12} {:>10.4} {:>10}ms {:>10}",
+            totals.answered,
+            totals.errors,
+            totals.input_tokens,
+            totals.output_tokens,
+            totals.cost,
+            mean,
+            totals.tool_calls
+        );
+    }
+    let _ = writeln!(
+        out,
+        "\nSame tools chosen on {} of {} turns.",
+        report.same_tools,
+        report.turns.len()
+    );
+    out
+}
+
+fn excerpt(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= MAX_TEXT_CHARS {
+        return format!("{line:?}");
+    }
+    let cut: String = line.chars().take(MAX_TEXT_CHARS).collect();
+    format!("{:?}", cut + "…")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(id: &str, user_id: &str, minute: u32) -> Capture {
+        Capture {
+            request_id: id.to_string(),
+            captured_at: chrono::DateTime::parse_from_rfc3339(&format!(
+                "2026-01-01T00:{minute:02}:00Z"
+            ))
+            .unwrap()
+            .into(),
+            provider: "groq".to_string(),
+            streaming: true,
+            anthropic_request: serde_json::json!({
+                "model": "claude-sonnet-4-20250514", "max_tokens": 100,
+                "messages": [{"role": "user", "content": "Hi"}],
+                "metadata": {"user_id": user_id},
+            }),
+            openai_request: serde_json::Value::Null,
+            status: 200,
+            openai_response: serde_json::Value::Null,
+            anthropic_response: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_load_and_compare() {
+        let dir = tempfile::tempdir().unwrap();
+        let day = dir.path().join("2026-01-01");
+        std::fs::create_dir_all(&day).unwrap();
+        let write = |name: &str, capture: &Capture| {
+            std::fs::write(day.join(name), serde_json::to_vec(capture).unwrap()).unwrap();
+        };
+        write("b.json", &capture("b", "user_x_session_s1", 2));
+        write("a.json", &capture("a", "user_x_session_s1", 1));
+        write("a.2.json", &capture("a", "user_x_session_s1", 3));
+        write("c.json", &capture("c", "user_x_session_s2", 4));
+        std::fs::write(day.join("notes.txt"), "not a capture").unwrap();
+
+        let ids = |captures: Vec<Capture>| {
+            captures
+                .into_iter()
+                .map(|c| c.request_id)
+                .collect::<Vec<_>>()
+        };
+        let all = load(&[dir.path().to_path_buf()], None, None).unwrap();
+        assert_eq!(ids(all), ["a", "b", "c"]);
+        let session = load(&[dir.path().to_path_buf()], None, Some("s1")).unwrap();
+        assert_eq!(ids(session), ["a", "b"]);
+
+        let call = |name: &str, command: &str| ToolCall {
+            name: name.to_string(),
+            input: serde_json::json!({ "command": command }),
+        };
+        let answer = |text: &str, calls: Vec<ToolCall>| Answer {
+            text: text.to_string(),
+            tool_calls: calls,
+            ..Answer::default()
+        };
+        let turn = compare(
+            "a",
+            "m",
+            [
+                answer("Running the tests now", vec![call("Bash", "cargo test")]),
+                answer("running the tests", vec![call("Bash", "cargo test -q")]),
+            ],
+        );
+        assert!(turn.same_tools);
+        assert!(!turn.same_tool_inputs);
+        assert!((turn.text_similarity - 0.75).abs() < 1e-9);
+
+        let failed = Answer {
+            error: Some("status 500".to_string()),
+            ..Answer::default()
+        };
+        let turn = compare("a", "m", [answer("", vec![]), failed]);
+        assert!(!turn.same_tools);
+        assert!(render(&EvalReport {
+            providers: ["groq".to_string(), "fireworks".to_string()],
+            turns: vec![turn],
+            totals: [Totals::default(), Totals::default()],
+            same_tools: 0,
+        })
+        .contains("error: status 500"));
+    }
+}
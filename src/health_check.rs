@@ -0,0 +1,75 @@
+//! Background health checks of providers taken out of the fallback chain.
+//!
+//! With `[health_check] enabled`, the task started by [`spawn`] probes every
+//! provider whose circuit breaker is open each `interval_secs`, with a GET of
+//! `path` under its base URL. A probe passes on any answer that wouldn't make
+//! the proxy fall back, i.e. anything but a 429 or 5xx. The provider is then
+//! returned to the chain with a warm-up ramp (see [`crate::routing`]), and the
+//! change is logged at `warn`, so it reaches the same log sinks as the breaker
+//! opening did.
+
+use crate::config::ProxyConfig;
+use crate::proxy;
+use crate::routing::should_fall_back;
+use crate::state::AppState;
+
+use std::sync::Arc;
+
+/// Start the health-check task. The config is read afresh on every round, so
+/// turning checks on or off, or changing the interval, needs no restart.
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        loop {
+            let config = state.config.load();
+            if config.health_check.enabled {
+                check(&state, &config).await;
+            }
+            tokio::time::sleep(config.health_check.interval()).await;
+        }
+    });
+}
+
+/// One round of checks: end finished warm-ups, then probe each open provider.
+pub async fn check(state: &AppState, config: &ProxyConfig) {
+    let settings = &config.health_check;
+    for name in state.health.finish_warmups() {
+        state.logger.info(
+            "health",
+            format!("Provider {name} warmed up; back to its full share of requests"),
+        );
+    }
+
+    for name in state.health.open_providers() {
+        let Some(provider) = config.named_provider(&name) else {
+            continue;
+        };
+        match proxy::probe(state, provider, &settings.path).await {
+            Ok(status) if !should_fall_back(status) => {
+                if state
+                    .health
+                    .reenable(&name, settings.warmup(), settings.warmup_start_percent)
+                {
+                    state.logger.warn(
+                        "health",
+                        format!(
+                            "Provider {name} passed a health check (status {status}); \
+                             returning it to rotation at {}% of its requests, ramping up over {}s",
+                            settings.warmup_start_percent, settings.warmup_secs
+                        ),
+                    );
+                    state.journal.record(
+                        "provider_reenabled",
+                        serde_json::json!({ "provider": name, "status": status }),
+                    );
+                }
+            }
+            Ok(status) => state.logger.debug(
+                "health",
+                format!("Health check of {name} failed: status {status}"),
+            ),
+            Err(e) => state
+                .logger
+                .debug("health", format!("Health check of {name} failed: {e}")),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Hooks for embedders to inspect and change traffic.
+//!
+//! An application embedding the proxy can register closures on [`AppState`]
+//! to redact secrets, rewrite prompts, add upstream headers or collect
+//! metrics without patching the forwarding code:
+//!
+//! ```rust,no_run
+//! use claude_proxy::{AppState, ProxyConfig, SharedLogger};
+//! use claude_proxy::translate::anthropic_types::{Delta, StreamEvent};
+//!
+//! # fn run(config: ProxyConfig, logger: SharedLogger) {
+//! let state = AppState::new(config, reqwest::Client::new(), logger)
+//!     .on_request(|req| req.metadata = None)
+//!     .on_translated_request(|upstream| {
+//!         upstream
+//!             .headers
+//!             .insert("x-team".to_string(), "billing".to_string());
+//!     })
+//!     .on_stream_event(|event| {
+//!         if let StreamEvent::ContentBlockDelta {
+//!             delta: Delta::TextDelta { text },
+//!             ..
+//!         } = event
+//!         {
+//!             *text = text.replace("hunter2", "[redacted]");
+//!         }
+//!     })
+//!     .on_tool_use(|call| {
+//!         if call.name == "Bash" {
+//!             if let Some(serde_json::Value::String(command)) = call.input.get_mut("command") {
+//!                 if command.starts_with("terraform apply") {
+//!                     command.push_str(" -refresh-only");
+//!                 }
+//!             }
+//!         }
+//!     });
+//! # }
+//! ```
+//!
+//! Each kind of hook runs in the order registered:
+//!
+//! - `on_request` on the Anthropic request, before it is routed;
+//! - `on_translated_request` on the request translated for the provider about
+//!   to be tried (again on fallback), with headers to add upstream;
+//! - `on_tool_use` on each tool call in a response, before the client sees it,
+//!   streamed or not;
+//! - `on_response` on a non-streaming response, translated back;
+//! - `on_stream_event` on each event of a streamed response.
+//!
+//! A streamed tool call's input arrives in fragments, so while there are
+//! `on_tool_use` hooks each `tool_use` block is held back until it is
+//! complete, then sent on as one `input_json_delta` with the hooks' input.
+//! Text and thinking still stream as they arrive.
+//!
+//! They apply to every inbound API, since the Gemini, completions and chat
+//! completions endpoints forward through the same path, but not to requests
+//! passed through unchanged to an Anthropic-format provider. A streamed
+//! request for a model with `expect` checks is answered upstream
+//! non-streaming and replayed as a stream, so its response passes through the
+//! stream hooks only.
+//!
+//! [`AppState`]: crate::AppState
+
+use crate::proxy::{self, SseStream};
+use crate::translate::anthropic_types::{
+    Delta, MessagesRequest, MessagesResponse, ResponseContentBlock, StreamEvent,
+};
+use crate::translate::openai_types::ChatCompletionRequest;
+
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A translated request on its way to a provider.
+pub struct UpstreamRequest<'r, 'a> {
+    /// The name of the provider it is sent to.
+    pub provider: &'r str,
+    /// The request in `OpenAI` format; providers with another API get it
+    /// converted from this.
+    pub body: &'r mut ChatCompletionRequest<'a>,
+    /// Headers to send with it, beside the provider's own.
+    pub headers: &'r mut HashMap<String, String>,
+}
+
+/// A tool call the model made, about to be returned to the client.
+pub struct ToolCall<'r> {
+    /// The `tool_use` id the client answers with its result.
+    pub id: &'r str,
+    pub name: &'r str,
+    /// The arguments; hooks may rewrite them.
+    pub input: &'r mut serde_json::Value,
+}
+
+type RequestHook = Arc<dyn Fn(&mut MessagesRequest) + Send + Sync>;
+type TranslatedRequestHook = Arc<dyn Fn(&mut UpstreamRequest<'_, '_>) + Send + Sync>;
+type ResponseHook = Arc<dyn Fn(&mut MessagesResponse) + Send + Sync>;
+type StreamEventHook = Arc<dyn Fn(&mut StreamEvent) + Send + Sync>;
+type ToolUseHook = Arc<dyn Fn(&mut ToolCall<'_>) + Send + Sync>;
+
+/// The hooks registered on an [`AppState`](crate::AppState).
+#[derive(Clone, Default)]
+pub struct Hooks {
+    request: Vec<RequestHook>,
+    translated_request: Vec<TranslatedRequestHook>,
+    response: Vec<ResponseHook>,
+    stream_event: Vec<StreamEventHook>,
+    tool_use: Vec<ToolUseHook>,
+}
+
+impl Hooks {
+    pub fn add_request(&mut self, hook: impl Fn(&mut MessagesRequest) + Send + Sync + 'static) {
+        self.request.push(Arc::new(hook));
+    }
+
+    pub fn add_translated_request(
+        &mut self,
+        hook: impl Fn(&mut UpstreamRequest<'_, '_>) + Send + Sync + 'static,
+    ) {
+        self.translated_request.push(Arc::new(hook));
+    }
+
+    pub fn add_response(&mut self, hook: impl Fn(&mut MessagesResponse) + Send + Sync + 'static) {
+        self.response.push(Arc::new(hook));
+    }
+
+    pub fn add_stream_event(&mut self, hook: impl Fn(&mut StreamEvent) + Send + Sync + 'static) {
+        self.stream_event.push(Arc::new(hook));
+    }
+
+    pub fn add_tool_use(&mut self, hook: impl Fn(&mut ToolCall<'_>) + Send + Sync + 'static) {
+        self.tool_use.push(Arc::new(hook));
+    }
+
+    /// `req` as the `on_request` hooks leave it, or `None` when there are none.
+    #[must_use]
+    pub fn request(&self, req: &MessagesRequest) -> Option<MessagesRequest> {
+        if self.request.is_empty() {
+            return None;
+        }
+        let mut req = req.clone();
+        for hook in &self.request {
+            hook(&mut req);
+        }
+        Some(req)
+    }
+
+    /// Run the `on_translated_request` hooks on `body`, returning the headers
+    /// they add.
+    #[must_use]
+    pub fn translated_request(
+        &self,
+        provider: &str,
+        body: &mut ChatCompletionRequest<'_>,
+    ) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        for hook in &self.translated_request {
+            hook(&mut UpstreamRequest {
+                provider,
+                body,
+                headers: &mut headers,
+            });
+        }
+        headers
+    }
+
+    /// Run the `on_tool_use` hooks on the tool calls in `resp`, then the
+    /// `on_response` hooks on it.
+    pub fn response(&self, resp: &mut MessagesResponse) {
+        for block in &mut resp.content {
+            if let ResponseContentBlock::ToolUse { id, name, input } = block {
+                run_tool_use(&self.tool_use, id, name, input);
+            }
+        }
+        for hook in &self.response {
+            hook(resp);
+        }
+    }
+
+    /// `stream` with the `on_tool_use` hooks run on each tool call and then
+    /// the `on_stream_event` hooks on each event. Events that aren't Anthropic
+    /// stream events, such as errors, pass unchanged.
+    #[must_use]
+    pub fn stream(&self, stream: SseStream) -> SseStream {
+        if self.stream_event.is_empty() && self.tool_use.is_empty() {
+            return stream;
+        }
+        let event_hooks = self.stream_event.clone();
+        let mut calls = ToolCalls {
+            hooks: self.tool_use.clone(),
+            pending: HashMap::new(),
+        };
+        Box::pin(stream.flat_map(move |item| {
+            let events = match item {
+                Ok(event) => match serde_json::from_str::<StreamEvent>(&event.data) {
+                    Ok(parsed) => calls
+                        .push(parsed)
+                        .into_iter()
+                        .filter_map(|mut parsed| {
+                            for hook in &event_hooks {
+                                hook(&mut parsed);
+                            }
+                            proxy::to_sse_event(&parsed)
+                        })
+                        .map(Ok)
+                        .collect(),
+                    Err(_) => vec![Ok(event)],
+                },
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(events)
+        }))
+    }
+}
+
+fn run_tool_use(hooks: &[ToolUseHook], id: &str, name: &str, input: &mut serde_json::Value) {
+    for hook in hooks {
+        hook(&mut ToolCall { id, name, input });
+    }
+}
+
+/// A streamed `tool_use` block held back until its input is complete.
+struct PendingCall {
+    id: String,
+    name: String,
+    json: String,
+}
+
+/// The `tool_use` blocks of one stream, for running the `on_tool_use` hooks.
+struct ToolCalls {
+    hooks: Vec<ToolUseHook>,
+    pending: HashMap<usize, PendingCall>,
+}
+
+impl ToolCalls {
+    /// The events to send on for `event`: none while a tool call is held back,
+    /// and the whole call once it ends.
+    fn push(&mut self, event: StreamEvent) -> Vec<StreamEvent> {
+        if self.hooks.is_empty() {
+            return vec![event];
+        }
+        match event {
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: ResponseContentBlock::ToolUse { id, name, input },
+            } => {
+                // Providers that send the input whole put it in the start block
+                let json = match input {
+                    serde_json::Value::Object(fields) if fields.is_empty() => String::new(),
+                    input => input.to_string(),
+                };
+                self.pending.insert(index, PendingCall { id, name, json });
+                Vec::new()
+            }
+            StreamEvent::ContentBlockDelta {
+                index,
+                delta: Delta::InputJsonDelta { partial_json },
+            } => match self.pending.get_mut(&index) {
+                Some(call) => {
+                    call.json.push_str(&partial_json);
+                    Vec::new()
+                }
+                None => vec![StreamEvent::ContentBlockDelta {
+                    index,
+                    delta: Delta::InputJsonDelta { partial_json },
+                }],
+            },
+            StreamEvent::ContentBlockStop { index } => match self.pending.remove(&index) {
+                Some(call) => self.complete(index, call),
+                None => vec![StreamEvent::ContentBlockStop { index }],
+            },
+            event => vec![event],
+        }
+    }
+
+    /// The events of a held-back call, with the hooks' input. Input that isn't
+    /// JSON is sent on as it came, without running the hooks.
+    fn complete(&self, index: usize, call: PendingCall) -> Vec<StreamEvent> {
+        let json = if call.json.trim().is_empty() {
+            "{}"
+        } else {
+            &call.json
+        };
+        let partial_json = match serde_json::from_str::<serde_json::Value>(json) {
+            Ok(mut input) => {
+                run_tool_use(&self.hooks, &call.id, &call.name, &mut input);
+                input.to_string()
+            }
+            Err(_) => call.json,
+        };
+        vec![
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: ResponseContentBlock::ToolUse {
+                    id: call.id,
+                    name: call.name,
+                    input: serde_json::json!({}),
+                },
+            },
+            StreamEvent::ContentBlockDelta {
+                index,
+                delta: Delta::InputJsonDelta { partial_json },
+            },
+            StreamEvent::ContentBlockStop { index },
+        ]
+    }
+}
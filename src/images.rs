@@ -0,0 +1,524 @@
+//! Inlining images given by URL, and shrinking inline images.
+//!
+//! An Anthropic image block may point at a URL instead of carrying its data.
+//! The URL is passed on as an `image_url` part, which `OpenAI` fetches itself;
+//! many other backends (and the Bedrock and Gemini formats) only take inline
+//! data. For a provider with `inline_image_urls` set, the proxy downloads each
+//! image and sends it as a base64 `data:` URL instead.
+//!
+//! Claude Code's screenshots are often several megabytes, and a conversation
+//! resends them every turn, which can exceed a provider's payload limit. With
+//! `[translation.images]` set, [`shrink`] scales inline images down and
+//! re-encodes them as JPEG. The results are kept in an [`ImageCache`], so an
+//! image is processed once, not on every turn that repeats it.
+//!
+//! For a model with `supports_images = false`, [`strip`] replaces images with
+//! a note of their size and format instead, as text-only backends reject
+//! `image_url` parts.
+
+use crate::config::ImageConfig;
+use crate::error::{ProxyError, Result};
+use crate::translate::openai_types::{ChatCompletionRequest, ChatContent, ContentPart};
+
+use base64::Engine as _;
+use futures::future::try_join_all;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// Largest image downloaded, in bytes.
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// How long one download may take.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Processed images kept by an [`ImageCache`].
+const CACHED_IMAGES: usize = 64;
+
+/// Largest width or height of an image decoded for shrinking.
+const MAX_DECODED_SIDE: u32 = 16_384;
+
+/// JPEG quality of a resized JPEG when `jpeg_quality` is unset.
+const RESIZED_JPEG_QUALITY: u8 = 90;
+
+/// Base64 characters decoded to read an image's size: 48 KiB, past the
+/// metadata that can come before a JPEG's frame header.
+const HEADER_BASE64_CHARS: usize = 65_536;
+
+/// Replace the `http(s)` image URLs in a request with the images' data,
+/// returning how many were downloaded. Each URL is fetched once.
+///
+/// # Errors
+/// Returns `ProxyError::InvalidRequest` if an image can't be downloaded, is
+/// too large, or isn't an image.
+pub async fn inline_urls(
+    openai_req: &mut ChatCompletionRequest<'_>,
+    client: &reqwest::Client,
+) -> Result<usize> {
+    let mut urls: Vec<String> = image_urls(openai_req)
+        .filter(|url| is_remote(url))
+        .map(str::to_string)
+        .collect();
+    urls.sort_unstable();
+    urls.dedup();
+    if urls.is_empty() {
+        return Ok(0);
+    }
+
+    let data = try_join_all(urls.iter().map(|url| download(client, url))).await?;
+    let inlined: HashMap<String, String> = urls.into_iter().zip(data).collect();
+    for part in image_parts(openai_req) {
+        if let ContentPart::ImageUrl { image_url } = part {
+            if let Some(data) = inlined.get(&image_url.url) {
+                image_url.url.clone_from(data);
+            }
+        }
+    }
+    Ok(inlined.len())
+}
+
+fn is_remote(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+fn image_parts<'r, 'a>(
+    openai_req: &'r mut ChatCompletionRequest<'a>,
+) -> impl Iterator<Item = &'r mut ContentPart<'a>> {
+    openai_req
+        .messages
+        .iter_mut()
+        .filter_map(|m| match &mut m.content {
+            Some(ChatContent::Parts(parts)) => Some(parts.iter_mut()),
+            _ => None,
+        })
+        .flatten()
+}
+
+fn image_urls<'r>(openai_req: &'r ChatCompletionRequest<'_>) -> impl Iterator<Item = &'r str> {
+    openai_req
+        .messages
+        .iter()
+        .filter_map(|m| match &m.content {
+            Some(ChatContent::Parts(parts)) => Some(parts.iter()),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|part| match part {
+            ContentPart::ImageUrl { image_url } => Some(image_url.url.as_str()),
+            ContentPart::Text { .. } | ContentPart::File { .. } => None,
+        })
+}
+
+/// Replace the request's images with notes saying what was left out, for a
+/// model that can't read them (`supports_images = false`), returning how many
+/// were removed. A message left with only text is sent as a plain string.
+pub fn strip(openai_req: &mut ChatCompletionRequest<'_>) -> usize {
+    let mut stripped = 0;
+    for message in &mut openai_req.messages {
+        let Some(ChatContent::Parts(parts)) = &mut message.content else {
+            continue;
+        };
+        for part in parts.iter_mut() {
+            if let ContentPart::ImageUrl { image_url } = part {
+                let text = format!("[image omitted: {}]", describe(&image_url.url));
+                *part = ContentPart::Text { text: text.into() };
+                stripped += 1;
+            }
+        }
+        if parts
+            .iter()
+            .all(|part| matches!(part, ContentPart::Text { .. }))
+        {
+            let text: Vec<&str> = parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_ref()),
+                    _ => None,
+                })
+                .collect();
+            message.content = Some(ChatContent::Text(text.join("\n").into()));
+        }
+    }
+    stripped
+}
+
+/// An image's size and format (`1024x768 png`), read from the header of a
+/// `data:` URL image, or the URL of one given by URL.
+fn describe(url: &str) -> String {
+    let Some((media_type, data)) = url
+        .strip_prefix("data:")
+        .and_then(|url| url.split_once(";base64,"))
+    else {
+        return url.to_string();
+    };
+    let name = media_type.strip_prefix("image/").unwrap_or(media_type);
+    let size = ImageFormat::from_mime_type(media_type).and_then(|format| {
+        let header = &data[..data.len().min(HEADER_BASE64_CHARS)];
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(header)
+            .ok()?;
+        ImageReader::with_format(Cursor::new(bytes), format)
+            .into_dimensions()
+            .ok()
+    });
+    match size {
+        Some((width, height)) => format!("{width}x{height} {name}"),
+        None => name.to_string(),
+    }
+}
+
+/// Shrink the request's inline images as `config` asks, returning how many
+/// were changed. Images that can't be decoded are sent as they are.
+pub async fn shrink(
+    openai_req: &mut ChatCompletionRequest<'_>,
+    config: ImageConfig,
+    cache: &ImageCache,
+) -> usize {
+    let mut urls: Vec<String> = image_urls(openai_req)
+        .filter(|url| url.starts_with("data:image/"))
+        .map(str::to_string)
+        .collect();
+    urls.sort_unstable();
+    urls.dedup();
+    let mut shrunk: HashMap<String, Option<Arc<str>>> = HashMap::new();
+    let mut misses = Vec::new();
+    for url in urls {
+        match cache.get(&url, config) {
+            Some(result) => {
+                shrunk.insert(url, result);
+            }
+            None => misses.push(url),
+        }
+    }
+    if !misses.is_empty() {
+        let processed = tokio::task::spawn_blocking(move || {
+            misses
+                .into_iter()
+                .map(|url| {
+                    let result = shrink_one(&url, config).map(Arc::from);
+                    (url, result)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        for (url, result) in processed {
+            cache.insert(&url, config, result.clone());
+            shrunk.insert(url, result);
+        }
+    }
+
+    let mut changed = 0;
+    for part in image_parts(openai_req) {
+        if let ContentPart::ImageUrl { image_url } = part {
+            if let Some(Some(data)) = shrunk.get(&image_url.url) {
+                image_url.url = data.to_string();
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+/// One `data:` URL image scaled down and re-encoded, or `None` if it is best
+/// left as it is.
+fn shrink_one(url: &str, config: ImageConfig) -> Option<String> {
+    let (media_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .ok()?;
+    let format = ImageFormat::from_mime_type(media_type)?;
+    let mut reader = ImageReader::with_format(Cursor::new(&bytes), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DECODED_SIDE);
+    limits.max_image_height = Some(MAX_DECODED_SIDE);
+    reader.limits(limits);
+    let mut image = reader.decode().ok()?;
+
+    let max = config.max_dimension;
+    let resized = max > 0 && image.width().max(image.height()) > max;
+    if resized {
+        image = image.resize(max, max, FilterType::Triangle);
+    }
+    let (encoded, media_type) = match (config.jpeg_quality, format) {
+        (Some(quality), _) => (jpeg(&image, quality)?, "image/jpeg"),
+        (None, _) if !resized => return None,
+        (None, ImageFormat::Jpeg) => (jpeg(&image, RESIZED_JPEG_QUALITY)?, "image/jpeg"),
+        // PNG, and GIF and WebP (whose encoders are limited) as PNG
+        (None, _) => {
+            let mut out = Cursor::new(Vec::new());
+            image.write_to(&mut out, ImageFormat::Png).ok()?;
+            (out.into_inner(), "image/png")
+        }
+    };
+    if !resized && encoded.len() >= bytes.len() {
+        return None;
+    }
+    Some(format!(
+        "data:{media_type};base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(&encoded)
+    ))
+}
+
+/// `image` as a JPEG, transparent areas on white.
+fn jpeg(image: &DynamicImage, quality: u8) -> Option<Vec<u8>> {
+    let rgb = if image.color().has_alpha() {
+        let mut rgba = image.to_rgba8();
+        for pixel in rgba.pixels_mut() {
+            let alpha = u16::from(pixel.0[3]);
+            for channel in &mut pixel.0[..3] {
+                let blended = (u16::from(*channel) * alpha + 255 * (255 - alpha)) / 255;
+                *channel = u8::try_from(blended).unwrap_or(u8::MAX);
+            }
+            pixel.0[3] = 255;
+        }
+        DynamicImage::ImageRgba8(rgba).to_rgb8()
+    } else {
+        image.to_rgb8()
+    };
+    let mut out = Vec::new();
+    JpegEncoder::new_with_quality(&mut out, quality)
+        .encode_image(&rgb)
+        .ok()?;
+    Some(out)
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    /// Each image's result (`None` if left as it was) and when last used.
+    entries: HashMap<u64, (Option<Arc<str>>, u64)>,
+    tick: u64,
+}
+
+/// Recently shrunk images, by a hash of their data and the settings used.
+#[derive(Debug, Clone, Default)]
+pub struct ImageCache {
+    inner: Arc<Mutex<CacheEntries>>,
+}
+
+impl ImageCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(url: &str, config: ImageConfig) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+        config.max_dimension.hash(&mut hasher);
+        config.jpeg_quality.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[allow(clippy::option_option)]
+    fn get(&self, url: &str, config: ImageConfig) -> Option<Option<Arc<str>>> {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tick += 1;
+        let tick = inner.tick;
+        let (result, used) = inner.entries.get_mut(&Self::key(url, config))?;
+        *used = tick;
+        Some(result.clone())
+    }
+
+    fn insert(&self, url: &str, config: ImageConfig, result: Option<Arc<str>>) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tick += 1;
+        let tick = inner.tick;
+        if inner.entries.len() >= CACHED_IMAGES {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(Self::key(url, config), (result, tick));
+    }
+}
+
+/// The image at `url` as a `data:` URL.
+async fn download(client: &reqwest::Client, url: &str) -> Result<String> {
+    let failed = |reason: String| {
+        ProxyError::invalid_request(format!("Unable to download the image at {url}: {reason}"))
+    };
+    let response = client
+        .get(url)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(failed(format!("status {}", response.status())));
+    }
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_IMAGE_BYTES as u64)
+    {
+        return Err(failed(format!("larger than {MAX_IMAGE_BYTES} bytes")));
+    }
+    let media_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(str::trim)
+        .unwrap_or_default()
+        .to_string();
+    if !media_type.starts_with("image/") {
+        return Err(failed(format!(
+            "not an image (content type '{media_type}')"
+        )));
+    }
+    let bytes = response.bytes().await.map_err(|e| failed(e.to_string()))?;
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(failed(format!("larger than {MAX_IMAGE_BYTES} bytes")));
+    }
+    Ok(format!(
+        "data:{media_type};base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(&bytes)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_url(image: &DynamicImage, format: ImageFormat, media_type: &str) -> String {
+        let mut out = Cursor::new(Vec::new());
+        image.write_to(&mut out, format).unwrap();
+        format!(
+            "data:{media_type};base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(out.into_inner())
+        )
+    }
+
+    fn decode(url: &str) -> (String, DynamicImage) {
+        let (media_type, data) = url
+            .strip_prefix("data:")
+            .unwrap()
+            .split_once(";base64,")
+            .unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .unwrap();
+        (
+            media_type.to_string(),
+            image::load_from_memory(&bytes).unwrap(),
+        )
+    }
+
+    fn request(urls: &[&str]) -> ChatCompletionRequest<'static> {
+        let parts: Vec<_> = urls
+            .iter()
+            .map(|url| serde_json::json!({"type": "image_url", "image_url": {"url": url}}))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "messages": [{"role": "user", "content": parts}]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shrink() {
+        // A noisy half-transparent screenshot-sized image, and a small icon
+        let screenshot = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(1200, 600, |x, y| {
+            let v = u8::try_from((x * 7 + y * 13) % 251).unwrap();
+            image::Rgba([v, v / 2, 255 - v, if x < 600 { 255 } else { 0 }])
+        }));
+        let icon =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(16, 16, image::Rgb([9, 9, 9])));
+        let screenshot_url = data_url(&screenshot, ImageFormat::Png, "image/png");
+        let icon_url = data_url(&icon, ImageFormat::Png, "image/png");
+        let urls = [
+            screenshot_url.as_str(),
+            icon_url.as_str(),
+            "https://example.com/a.png",
+        ];
+
+        // Resized only, a PNG stays a PNG
+        let cache = ImageCache::new();
+        let config = ImageConfig {
+            max_dimension: 500,
+            jpeg_quality: None,
+        };
+        let mut req = request(&urls);
+        assert_eq!(shrink(&mut req, config, &cache).await, 1);
+        let json = serde_json::to_value(&req).unwrap();
+        let content = &json["messages"][0]["content"];
+        let (media_type, image) = decode(content[0]["image_url"]["url"].as_str().unwrap());
+        assert_eq!(media_type, "image/png");
+        assert_eq!((image.width(), image.height()), (500, 250));
+        assert_eq!(content[1]["image_url"]["url"], icon_url.as_str());
+        assert_eq!(content[2]["image_url"]["url"], "https://example.com/a.png");
+
+        // As JPEG, transparency is flattened onto white; the icon is smaller
+        // as the PNG it was
+        let config = ImageConfig {
+            max_dimension: 500,
+            jpeg_quality: Some(80),
+        };
+        let mut req = request(&urls);
+        assert_eq!(shrink(&mut req, config, &cache).await, 1);
+        let json = serde_json::to_value(&req).unwrap();
+        let content = &json["messages"][0]["content"];
+        let url = content[0]["image_url"]["url"].as_str().unwrap();
+        assert!(url.len() < screenshot_url.len() / 4);
+        let (media_type, image) = decode(url);
+        assert_eq!(media_type, "image/jpeg");
+        let white = image.to_rgb8().get_pixel(450, 125).0;
+        assert!(white.iter().all(|c| *c > 240), "{white:?}");
+        assert_eq!(content[1]["image_url"]["url"], icon_url.as_str());
+
+        // Processed images are cached
+        assert_eq!(cache.inner.lock().unwrap().entries.len(), 4);
+        let mut req = request(&urls);
+        assert_eq!(shrink(&mut req, config, &cache).await, 1);
+        assert_eq!(cache.inner.lock().unwrap().entries.len(), 4);
+
+        // What isn't an image it can decode is left alone
+        let broken = "data:image/png;base64,iVBORw0KGgo=";
+        let mut req = request(&[broken]);
+        assert_eq!(shrink(&mut req, config, &cache).await, 0);
+    }
+
+    #[test]
+    fn test_strip() {
+        let icon = DynamicImage::ImageRgb8(image::RgbImage::new(40, 30));
+        let icon_url = data_url(&icon, ImageFormat::Png, "image/png");
+        let mut req: ChatCompletionRequest<'static> = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What's this?"},
+                    {"type": "image_url", "image_url": {"url": icon_url}},
+                ]},
+                {"role": "user", "content": [
+                    {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+                    {"type": "file", "file": {"file_data": "data:application/pdf;base64,"}},
+                ]},
+            ]
+        }))
+        .unwrap();
+        assert_eq!(strip(&mut req), 2);
+        let json = serde_json::to_value(&req).unwrap();
+        assert_eq!(
+            json["messages"][0]["content"],
+            "What's this?\n[image omitted: 40x30 png]"
+        );
+        let content = &json["messages"][1]["content"];
+        assert_eq!(
+            content[0]["text"],
+            "[image omitted: https://example.com/a.png]"
+        );
+        assert_eq!(content[1]["type"], "file");
+    }
+}
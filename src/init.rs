@@ -0,0 +1,260 @@
+//! `claude-proxy init`: write a starter config interactively.
+//!
+//! Asks for a provider preset, checks that its API key variable is set, lists
+//! the provider's models (see [`crate::models::fetch_provider_models`]) and
+//! asks which one serves each of the [`known_claude_models`]. The answers are
+//! checked by loading them as a [`ProxyConfig`] before the file is written.
+//!
+//! Prompts go to `output` and answers come from `input`, one per line, so the
+//! flow can be scripted.
+
+use crate::config::ProxyConfig;
+use crate::error::{ProxyError, Result};
+use crate::migrate;
+use crate::models::{fetch_provider_models, known_claude_models};
+use crate::providers::{ApiFormat, ProviderPreset};
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Most fetched models to list; the rest can still be typed by name.
+const MAX_LISTED_MODELS: usize = 50;
+
+/// Run the interactive setup and write the config to `path`.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the input ends early or the answers don't
+/// make a valid config, and `ProxyError::Io` if the prompts or file can't be
+/// written. An existing file is only replaced after confirmation.
+pub async fn run(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    client: &reqwest::Client,
+    path: &Path,
+) -> Result<()> {
+    if path.exists() {
+        let answer = ask(
+            input,
+            output,
+            &format!("{} already exists. Overwrite it? [y/N]", path.display()),
+        )?;
+        if !answer.eq_ignore_ascii_case("y") && !answer.eq_ignore_ascii_case("yes") {
+            writeln!(output, "Left {} unchanged.", path.display())?;
+            return Ok(());
+        }
+    }
+
+    let presets: Vec<&ProviderPreset> = ProviderPreset::all()
+        .iter()
+        .filter(|p| p.format != ApiFormat::Replay.as_str())
+        .collect();
+    writeln!(output, "Providers:")?;
+    for (i, preset) in presets.iter().enumerate() {
+        writeln!(output, "  {:>2}. {}", i + 1, preset.name)?;
+    }
+    let preset = loop {
+        let answer = ask(input, output, "Provider (name or number)")?;
+        let chosen = answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| presets.get(n.wrapping_sub(1)))
+            .or_else(|| {
+                presets
+                    .iter()
+                    .find(|p| p.name.eq_ignore_ascii_case(&answer))
+            });
+        match chosen {
+            Some(preset) => break *preset,
+            None => writeln!(output, "Unknown provider '{answer}'.")?,
+        }
+    };
+
+    let mut config = parse(&render(preset, &BTreeMap::new())?)?;
+    let env = preset.default_api_key_env;
+    match config.resolve_api_key() {
+        Ok(_) if env.is_empty() => {}
+        Ok(key) if key.is_empty() => {
+            writeln!(output, "{} runs without an API key.", preset.name)?;
+        }
+        Ok(_) => writeln!(output, "Found {env}.")?,
+        Err(_) if !env.is_empty() => writeln!(
+            output,
+            "Warning: {env} is not set. Set it before starting the proxy."
+        )?,
+        Err(e) => writeln!(output, "Warning: {e}")?,
+    }
+
+    let available = match fetch_provider_models(&config, client).await {
+        Ok(mut models) => {
+            models.sort();
+            models
+        }
+        Err(e) => {
+            writeln!(output, "Couldn't list {}'s models: {e}", preset.name)?;
+            Vec::new()
+        }
+    };
+    if available.is_empty() {
+        writeln!(output, "Enter backend model names by hand.")?;
+    } else {
+        writeln!(output, "Models on {}:", preset.name)?;
+        for (i, model) in available.iter().take(MAX_LISTED_MODELS).enumerate() {
+            writeln!(output, "  {:>2}. {model}", i + 1)?;
+        }
+        if available.len() > MAX_LISTED_MODELS {
+            writeln!(
+                output,
+                "  ... and {} more (type a name to use one)",
+                available.len() - MAX_LISTED_MODELS
+            )?;
+        }
+    }
+
+    let mut mappings = BTreeMap::new();
+    let mut previous: Option<String> = None;
+    for claude_model in known_claude_models() {
+        let prompt = match previous {
+            Some(ref model) => format!("Model for {claude_model} (name or number) [{model}]"),
+            None => format!("Model for {claude_model} (name or number)"),
+        };
+        let model = loop {
+            let answer = ask(input, output, &prompt)?;
+            if answer.is_empty() {
+                if let Some(ref model) = previous {
+                    break model.clone();
+                }
+                continue;
+            }
+            match answer.parse::<usize>() {
+                Ok(n) => match available.get(n.wrapping_sub(1)) {
+                    Some(model) => break model.clone(),
+                    None => writeln!(output, "No model numbered {n}.")?,
+                },
+                Err(_) => break answer,
+            }
+        };
+        mappings.insert(claude_model.to_string(), model.clone());
+        previous = Some(model);
+    }
+
+    let content = render(preset, &mappings)?;
+    config = parse(&content)?;
+    std::fs::write(path, content)?;
+    writeln!(
+        output,
+        "Wrote {} ({} models on {}). Start the proxy with: claude-proxy -c {}",
+        path.display(),
+        config.models.len(),
+        config.provider.name,
+        path.display()
+    )?;
+    Ok(())
+}
+
+/// Prompt for one line of input, trimmed.
+fn ask(input: &mut impl BufRead, output: &mut impl Write, prompt: &str) -> Result<String> {
+    write!(output, "{prompt}: ")?;
+    output.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(ProxyError::config("Input ended before setup was finished"));
+    }
+    Ok(line.trim().to_string())
+}
+
+/// The config file for `preset` with the given Claude → backend mappings.
+fn render(preset: &ProviderPreset, mappings: &BTreeMap<String, String>) -> Result<String> {
+    let api_key_env = if preset.default_api_key_env.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "api_key_env = {}\n",
+            toml::Value::from(preset.default_api_key_env)
+        )
+    };
+    let models: toml::Table = mappings
+        .iter()
+        .map(|(claude, backend)| (claude.clone(), backend.as_str().into()))
+        .collect();
+    let models = toml::to_string(&models)
+        .map_err(|e| ProxyError::config(format!("Failed to write config: {e}")))?;
+    Ok(format!(
+        "# Written by `claude-proxy init`. See config.example.toml for every option.\n\n\
+         version = {}\n\n[provider]\nname = {}\n{api_key_env}\n[models]\n{models}",
+        migrate::CURRENT_VERSION,
+        toml::Value::from(preset.name)
+    ))
+}
+
+fn parse(content: &str) -> Result<ProxyConfig> {
+    let config: ProxyConfig = toml::from_str(content)?;
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_is_a_valid_config() {
+        let mappings = BTreeMap::from([
+            (
+                "claude-3-5-haiku-20241022".to_string(),
+                "llama-3.1-8b-instant".to_string(),
+            ),
+            (
+                "claude-sonnet-4-20250514".to_string(),
+                "moonshotai/kimi-k2-instruct".to_string(),
+            ),
+        ]);
+        let groq = ProviderPreset::from_name("groq").unwrap();
+        let config = parse(&render(groq, &mappings).unwrap()).unwrap();
+        assert_eq!(config.provider.name, "groq");
+        assert_eq!(config.provider.api_key_env, "GROQ_API_KEY");
+        assert_eq!(
+            config.route("claude-sonnet-4-20250514").unwrap().model,
+            "moonshotai/kimi-k2-instruct"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_writes_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claude-proxy.toml");
+        // Nothing listens on this port, so models are entered by hand; an
+        // empty answer reuses the previous model.
+        let mut answers = String::from("nosuch\nollama\n3\nqwen2.5-coder\n");
+        answers.push_str(&"\n".repeat(known_claude_models().len() - 2));
+        answers.push_str("llama3.2\n");
+        let client = reqwest::Client::builder()
+            .connect_timeout(std::time::Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let mut output = Vec::new();
+        run(&mut answers.as_bytes(), &mut output, &client, &path)
+            .await
+            .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Unknown provider 'nosuch'"), "{output}");
+        assert!(output.contains("No model numbered 3"), "{output}");
+        let config = ProxyConfig::load(&path).unwrap();
+        assert_eq!(config.provider.name, "ollama");
+        let models = known_claude_models();
+        assert_eq!(config.route(models[0]).unwrap().model, "qwen2.5-coder");
+        assert_eq!(config.route(models[1]).unwrap().model, "qwen2.5-coder");
+        assert_eq!(
+            config.route(models.last().unwrap()).unwrap().model,
+            "llama3.2"
+        );
+
+        // An existing file is kept unless the user agrees
+        let mut output = Vec::new();
+        run(&mut "n\n".as_bytes(), &mut output, &client, &path)
+            .await
+            .unwrap();
+        assert_eq!(ProxyConfig::load(&path).unwrap().provider.name, "ollama");
+    }
+}
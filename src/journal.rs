@@ -0,0 +1,258 @@
+//! Tamper-evident journal of configuration and operational events.
+//!
+//! With `[journal] file` set, the proxy appends one JSON line per event that
+//! changes how traffic is handled — config reloads (and rejected edits),
+//! retry budget changes, admin actions, circuit breakers opening, providers
+//! re-enabled by a health check, failovers to another provider, and
+//! `[[schedule]]` windows opening and closing. It is kept
+//! apart from the request log so it stays small enough to retain indefinitely.
+//!
+//! Each entry carries the SHA-256 `hash` of its own contents and of the
+//! previous entry's hash, so editing, removing or reordering any entry breaks
+//! the chain from that point on. [`verify`] (and `claude-proxy verify-journal`)
+//! walks the file and reports the first entry that doesn't check out. The file
+//! is only ever appended to; on startup the chain continues from its last entry.
+
+use crate::error::{ProxyError, Result};
+use crate::log_context;
+use crate::logging::SharedLogger;
+
+use chrono::{DateTime, Utc};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// `prev_hash` of the first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One journaled event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Position in the journal, from 1.
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    /// What happened, e.g. `config_reloaded` or `provider_failover`.
+    pub event: String,
+    /// Event-specific fields.
+    pub detail: serde_json::Value,
+    /// The request the event happened during, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl JournalEntry {
+    /// The hash of everything in the entry but `hash` itself.
+    fn compute_hash(&self) -> String {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Some(obj) = value.as_object_mut() {
+            obj.remove("hash");
+        }
+        // `Value` objects are sorted maps, so the serialization is canonical.
+        digest::digest(&digest::SHA256, value.to_string().as_bytes())
+            .as_ref()
+            .iter()
+            .fold(String::new(), |mut hex, b| {
+                let _ = write!(hex, "{b:02x}");
+                hex
+            })
+    }
+}
+
+/// The open journal file and the tail of its chain.
+struct Writer {
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+/// Appends events to the journal; a no-op when no file is configured.
+#[derive(Clone, Default)]
+pub struct Journal {
+    writer: Option<Arc<Mutex<Writer>>>,
+    logger: Option<SharedLogger>,
+}
+
+impl Journal {
+    /// A journal that records nothing.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Open (or create) the journal at `path`, continuing its chain. Write
+    /// failures later on are reported to `logger`.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Io` if the file can't be opened, and
+    /// `ProxyError::Storage` if its last entry can't be parsed.
+    pub fn open(path: &Path, logger: SharedLogger) -> Result<Self> {
+        let (seq, last_hash) = match last_entry(path)? {
+            Some(entry) => (entry.seq, entry.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Some(Arc::new(Mutex::new(Writer {
+                file,
+                seq,
+                last_hash,
+            }))),
+            logger: Some(logger),
+        })
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Append an event. A failed write is logged, never returned: journaling
+    /// must not fail the action being journaled.
+    pub fn record(&self, event: &str, detail: serde_json::Value) {
+        let Some(ref writer) = self.writer else {
+            return;
+        };
+        let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut entry = JournalEntry {
+            seq: writer.seq + 1,
+            timestamp: Utc::now(),
+            event: event.to_string(),
+            detail,
+            request_id: log_context::current().map(|ctx| ctx.request_id),
+            prev_hash: writer.last_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        let written = serde_json::to_string(&entry)
+            .map_err(ProxyError::from)
+            .and_then(|line| Ok(writeln!(writer.file, "{line}")?));
+        match written {
+            Ok(()) => {
+                writer.seq = entry.seq;
+                writer.last_hash = entry.hash;
+            }
+            Err(e) => {
+                if let Some(ref logger) = self.logger {
+                    logger.error("journal", format!("Failed to journal {event}: {e}"));
+                }
+            }
+        }
+    }
+}
+
+/// The last entry of the journal at `path`, if it has any.
+fn last_entry(path: &Path) -> Result<Option<JournalEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    last.map(|line| {
+        serde_json::from_str(&line).map_err(|e| {
+            ProxyError::storage(format!(
+                "Last journal entry in {} is malformed: {e}",
+                path.display()
+            ))
+        })
+    })
+    .transpose()
+}
+
+/// Check the hash chain of the journal at `path`, returning its entry count.
+///
+/// # Errors
+/// Returns `ProxyError::Storage` naming the first line that is malformed, out
+/// of sequence, or whose hashes don't match, and `ProxyError::Io` if the file
+/// can't be read.
+pub fn verify(path: &Path) -> Result<u64> {
+    let file = File::open(path)?;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut seq = 0;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |why: &str| {
+            ProxyError::storage(format!(
+                "Journal {} fails verification at line {}: {why}",
+                path.display(),
+                index + 1
+            ))
+        };
+        let entry: JournalEntry =
+            serde_json::from_str(&line).map_err(|e| invalid(&format!("malformed entry ({e})")))?;
+        if entry.seq != seq + 1 {
+            return Err(invalid(&format!(
+                "expected entry {}, found {}",
+                seq + 1,
+                entry.seq
+            )));
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(invalid(
+                "chain broken (prev_hash doesn't match the previous entry)",
+            ));
+        }
+        if entry.hash != entry.compute_hash() {
+            return Err(invalid(
+                "entry was modified (hash doesn't match its contents)",
+            ));
+        }
+        seq = entry.seq;
+        prev_hash = entry.hash;
+    }
+    Ok(seq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let journal = Journal::open(&path, logger.clone()).unwrap();
+        journal.record(
+            "config_reloaded",
+            serde_json::json!({"changed": ["models"]}),
+        );
+        journal.record(
+            "provider_failover",
+            serde_json::json!({"from": "a", "to": "b"}),
+        );
+        // Reopening continues the chain
+        let journal = Journal::open(&path, logger).unwrap();
+        journal.record(
+            "admin_action",
+            serde_json::json!({"action": "registry_reload"}),
+        );
+        assert_eq!(verify(&path).unwrap(), 3);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let tampered = content.replacen("\"to\":\"b\"", "\"to\":\"c\"", 1);
+        std::fs::write(&path, tampered).unwrap();
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2"), "{err}");
+
+        let lines: Vec<&str> = content.lines().collect();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify(&path).unwrap_err().to_string().contains("line 2"));
+    }
+}
@@ -0,0 +1,139 @@
+//! Parsing a JSON body as it downloads.
+//!
+//! A non-streaming response read whole with `.text()` is held twice before it
+//! is parsed: once as the received bytes and once as the checked `String`,
+//! both alive while `serde_json` builds the parsed value. [`parse`] instead
+//! hands each chunk to a parser on a blocking thread as it arrives, so only
+//! the parsed value and the chunks in flight are held at once.
+
+use crate::error::{ProxyError, Result};
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use std::io::{self, Read};
+use tokio::sync::mpsc;
+
+/// Chunks buffered between the download and the parser.
+const CHUNKS_IN_FLIGHT: usize = 8;
+
+/// The start of the body, kept to show in a parse error.
+const HEAD_BYTES: usize = 300;
+
+/// Parse the JSON body arriving as `chunks`.
+///
+/// # Errors
+/// Returns `ProxyError::Provider` if reading a chunk fails, and
+/// `ProxyError::Translation` (with the start of the body) if it isn't a `T`.
+pub async fn parse<T, S, E>(chunks: S) -> Result<T>
+where
+    T: DeserializeOwned + Send + 'static,
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send,
+    E: std::fmt::Display,
+{
+    let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let parser = tokio::task::spawn_blocking(move || {
+        let mut reader = ChunkReader {
+            chunks: rx,
+            current: Bytes::new(),
+            head: Vec::new(),
+        };
+        let parsed = serde_json::from_reader::<_, T>(&mut reader);
+        // The parser stops at the error; the rest of the head is still to come
+        if parsed.as_ref().is_err_and(|e| !e.is_io()) {
+            let mut buf = [0; HEAD_BYTES];
+            while reader.head.len() < HEAD_BYTES && reader.read(&mut buf).is_ok_and(|n| n > 0) {}
+        }
+        (parsed, reader.head)
+    });
+
+    let mut chunks = std::pin::pin!(chunks);
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| io::Error::other(e.to_string()));
+        let failed = chunk.is_err();
+        // The parser stops reading at the end of the value or at an error
+        if tx.send(chunk).await.is_err() || failed {
+            break;
+        }
+    }
+    drop(tx);
+
+    let (parsed, head) = parser
+        .await
+        .map_err(|e| ProxyError::provider(format!("Response parser failed: {e}")))?;
+    parsed.map_err(|e| {
+        if e.is_io() {
+            ProxyError::provider(format!("Failed to read response body: {e}"))
+        } else {
+            ProxyError::translation(format!(
+                "Failed to parse provider response: {e}. Body: {}",
+                String::from_utf8_lossy(&head)
+            ))
+        }
+    })
+}
+
+/// The chunks of a body, read on a blocking thread.
+struct ChunkReader {
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+    current: Bytes,
+    head: Vec<u8>,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current[..n]);
+        self.current.advance(n);
+        let keep = n.min(HEAD_BYTES - self.head.len());
+        self.head.extend_from_slice(&buf[..keep]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(body: &str, size: usize) -> Vec<std::result::Result<Bytes, String>> {
+        body.as_bytes()
+            .chunks(size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_parse() {
+        let body = r#"{"text": "héllo wörld", "n": [1, 2, 3]}"#;
+        for size in [1, 2, 7, body.len()] {
+            let value: serde_json::Value = parse(futures::stream::iter(chunked(body, size)))
+                .await
+                .unwrap();
+            assert_eq!(
+                value,
+                serde_json::json!({"text": "héllo wörld", "n": [1, 2, 3]})
+            );
+        }
+
+        // A body that isn't the type shows its start
+        let err = parse::<Vec<u32>, _, _>(futures::stream::iter(chunked("{\"a\": 1}", 3)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProxyError::Translation { .. }), "{err}");
+        assert!(err.to_string().contains("Body: {\"a\": 1}"), "{err}");
+
+        // A failed download is a provider error, not a parse error
+        let mut chunks = chunked("{\"a\": ", 3);
+        chunks.push(Err("connection reset".to_string()));
+        let err = parse::<serde_json::Value, _, _>(futures::stream::iter(chunks))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProxyError::Provider { .. }), "{err}");
+        assert!(err.to_string().contains("connection reset"), "{err}");
+    }
+}
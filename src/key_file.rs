@@ -0,0 +1,93 @@
+//! Reading API keys from files.
+//!
+//! A provider's `api_key_file = "<path>"` reads its key from that file, e.g.
+//! a mounted secret. Keys are resolved for every request, so, as with the
+//! [`crate::keychain`], [`load`] reads a config's key files when the config
+//! is loaded, and requests only look them up in memory. A rotated key is
+//! picked up when the config is next reloaded.
+
+use crate::config::ProxyConfig;
+use crate::error::{ProxyError, Result};
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// What was read from each file: the key, or why there isn't one.
+static KEYS: OnceLock<Mutex<HashMap<String, std::result::Result<String, String>>>> =
+    OnceLock::new();
+
+fn keys() -> &'static Mutex<HashMap<String, std::result::Result<String, String>>> {
+    KEYS.get_or_init(Mutex::default)
+}
+
+/// Read the files `config`'s providers take their keys from, replacing what
+/// was read from them before. A file that can't be read is remembered as
+/// such, and the error returned by [`read`].
+pub fn load(config: &ProxyConfig) {
+    let paths = std::iter::once(&config.provider)
+        .chain(config.providers.values())
+        .filter_map(|provider| provider.api_key_file.as_deref());
+    for path in paths {
+        remember(path, lookup(path));
+    }
+}
+
+/// The key in the file at `path`, as read by [`load`]. A file it hasn't
+/// seen, such as one in a config built in code, is read now.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the file can't be read.
+pub fn read(path: &str) -> Result<String> {
+    let known = keys()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(path)
+        .cloned();
+    let key = known.unwrap_or_else(|| {
+        let key = lookup(path);
+        remember(path, key.clone());
+        key
+    });
+    key.map_err(ProxyError::config)
+}
+
+fn remember(path: &str, key: std::result::Result<String, String>) {
+    keys()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(path.to_string(), key);
+}
+
+/// The key in the file at `path`, trimmed.
+fn lookup(path: &str) -> std::result::Result<String, String> {
+    std::fs::read_to_string(path)
+        .map(|key| key.trim().to_string())
+        .map_err(|e| format!("Can't read api_key_file {path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_read() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, " gsk-old ").unwrap();
+        let path = file.path().to_string_lossy().into_owned();
+        let config = ProxyConfig::builder().api_key_file(&path).build().unwrap();
+        load(&config);
+        assert_eq!(read(&path).unwrap(), "gsk-old");
+
+        // A rotated key is read on the next load, not on every request
+        std::fs::write(file.path(), "gsk-new\n").unwrap();
+        assert_eq!(read(&path).unwrap(), "gsk-old");
+        load(&config);
+        assert_eq!(read(&path).unwrap(), "gsk-new");
+
+        let error = read("/nonexistent/claude-proxy-key")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Can't read api_key_file"), "{error}");
+    }
+}
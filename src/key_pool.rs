@@ -0,0 +1,278 @@
+//! Rotating between several API keys for one provider.
+//!
+//! A provider with `api_key_envs = ["GROQ_KEY_1", "GROQ_KEY_2", ...]` spreads
+//! its requests over those keys round-robin, e.g. to combine several free-tier
+//! quotas. A key answered with 429 rests for the `Retry-After` the provider
+//! asks (else [`RATE_LIMIT_COOLDOWN`]), and one answered with 401 or 403 for
+//! [`AUTH_COOLDOWN`]; resting keys are skipped while others are available,
+//! and a request refused that way is sent again at once with the next key.
+//! Variables that aren't set are skipped. Keys are known by their variable's
+//! name, never their value, in logs and in the per-key counts served at
+//! `GET /admin/keys`.
+//!
+//! Requests passed through unchanged to an Anthropic-format provider rotate
+//! keys and rest them too, but aren't sent again with another key.
+
+use crate::config::ProviderConfig;
+use crate::error::{ProxyError, Result};
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a rate-limited key rests when the provider doesn't say.
+pub const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// How long a rejected key rests; it may have been revoked, or be out of
+/// credit.
+pub const AUTH_COOLDOWN: Duration = Duration::from_secs(600);
+
+/// A key picked for a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PooledKey {
+    /// The variable it was read from.
+    pub env: String,
+    pub key: String,
+}
+
+/// Per-key request counts since startup.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct KeyStats {
+    pub requests: u64,
+    pub successes: u64,
+    pub rate_limited: u64,
+    pub rejected: u64,
+    /// Seconds left before a resting key is used again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resting_secs: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct KeyEntry {
+    stats: KeyStats,
+    rest_until: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct ProviderKeys {
+    next: usize,
+    keys: HashMap<String, KeyEntry>,
+}
+
+/// Rotation state and counts for every provider's keys, keyed by provider
+/// name.
+#[derive(Debug, Clone, Default)]
+pub struct KeyPool(Arc<Mutex<HashMap<String, ProviderKeys>>>);
+
+impl KeyPool {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next key for `provider`, or `None` if it doesn't rotate keys. When
+    /// every key is resting, the one back soonest.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Config` if none of its variables is set.
+    pub fn pick(&self, provider: &ProviderConfig) -> Result<Option<PooledKey>> {
+        let Some(envs) = provider.pooled_key_envs() else {
+            return Ok(None);
+        };
+        if let Some(key) = self.pick_at(provider, envs, Instant::now(), true) {
+            return Ok(Some(key));
+        }
+        Err(ProxyError::config(format!(
+            "None of api_key_envs ({}) is set for provider '{}'",
+            envs.join(", "),
+            provider.name
+        )))
+    }
+
+    /// The next key for `provider` that isn't resting, if any.
+    #[must_use]
+    pub fn next_available(&self, provider: &ProviderConfig) -> Option<PooledKey> {
+        let envs = provider.pooled_key_envs()?;
+        self.pick_at(provider, envs, Instant::now(), false)
+    }
+
+    fn pick_at(
+        &self,
+        provider: &ProviderConfig,
+        envs: &[String],
+        now: Instant,
+        or_resting: bool,
+    ) -> Option<PooledKey> {
+        let set: Vec<(usize, String)> = envs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, env)| std::env::var(env).ok().map(|key| (i, key)))
+            .collect();
+        let Ok(mut providers) = self.0.lock() else {
+            return set.into_iter().next().map(|(i, key)| PooledKey {
+                env: envs[i].clone(),
+                key,
+            });
+        };
+        let keys = providers.entry(provider.name.clone()).or_default();
+        let rest_until = |i: usize| {
+            keys.keys
+                .get(&envs[i])
+                .and_then(|entry| entry.rest_until)
+                .filter(|until| *until > now)
+        };
+        // Round-robin from the cursor, over the keys that are set
+        let start = set.iter().position(|(i, _)| *i >= keys.next).unwrap_or(0);
+        let rotation = set[start..].iter().chain(&set[..start]);
+        let chosen = match rotation.clone().find(|(i, _)| rest_until(*i).is_none()) {
+            Some(chosen) => chosen,
+            None if or_resting => rotation.min_by_key(|(i, _)| rest_until(*i))?,
+            None => return None,
+        };
+        let (i, key) = chosen.clone();
+        keys.next = i + 1;
+        Some(PooledKey {
+            env: envs[i].clone(),
+            key,
+        })
+    }
+
+    /// Count a response to a request sent with `provider`'s key from `env`,
+    /// resting the key if it was refused. Returns whether it was.
+    #[must_use]
+    pub fn record(
+        &self,
+        provider: &str,
+        env: &str,
+        status: u16,
+        retry_after: Option<Duration>,
+    ) -> bool {
+        self.record_at(provider, env, status, retry_after, Instant::now())
+    }
+
+    fn record_at(
+        &self,
+        provider: &str,
+        env: &str,
+        status: u16,
+        retry_after: Option<Duration>,
+        now: Instant,
+    ) -> bool {
+        let Ok(mut providers) = self.0.lock() else {
+            return false;
+        };
+        let entry = providers
+            .entry(provider.to_string())
+            .or_default()
+            .keys
+            .entry(env.to_string())
+            .or_default();
+        entry.stats.requests += 1;
+        let rest = match status {
+            429 => {
+                entry.stats.rate_limited += 1;
+                retry_after.unwrap_or(RATE_LIMIT_COOLDOWN)
+            }
+            401 | 403 => {
+                entry.stats.rejected += 1;
+                AUTH_COOLDOWN
+            }
+            status => {
+                if status < 400 {
+                    entry.stats.successes += 1;
+                }
+                return false;
+            }
+        };
+        entry.rest_until = Some(now + rest);
+        true
+    }
+
+    /// Each provider's keys with their counts, by variable name.
+    #[must_use]
+    pub fn stats(&self) -> HashMap<String, HashMap<String, KeyStats>> {
+        let Ok(providers) = self.0.lock() else {
+            return HashMap::new();
+        };
+        let now = Instant::now();
+        providers
+            .iter()
+            .map(|(provider, keys)| {
+                let keys = keys
+                    .keys
+                    .iter()
+                    .map(|(env, entry)| {
+                        let mut stats = entry.stats.clone();
+                        stats.resting_secs = entry
+                            .rest_until
+                            .filter(|until| *until > now)
+                            .map(|until| (until - now).as_secs().max(1));
+                        (env.clone(), stats)
+                    })
+                    .collect();
+                (provider.clone(), keys)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        std::env::set_var("CLAUDE_PROXY_TEST_POOL_A", "key-a");
+        std::env::set_var("CLAUDE_PROXY_TEST_POOL_C", "key-c");
+        let provider = ProviderConfig {
+            name: "groq".to_string(),
+            api_key_envs: ["A", "B", "C"]
+                .map(|k| format!("CLAUDE_PROXY_TEST_POOL_{k}"))
+                .to_vec(),
+            ..ProviderConfig::default()
+        };
+        let pool = KeyPool::new();
+        let keys = |n| {
+            (0..n)
+                .map(|_| pool.pick(&provider).unwrap().unwrap().key)
+                .collect::<Vec<_>>()
+        };
+        // B isn't set, so A and C take turns
+        assert_eq!(keys(3), ["key-a", "key-c", "key-a"]);
+
+        // A rate-limited key is skipped while it rests
+        assert!(pool.record("groq", "CLAUDE_PROXY_TEST_POOL_A", 429, None));
+        assert_eq!(keys(2), ["key-c", "key-c"]);
+        assert!(!pool.record("groq", "CLAUDE_PROXY_TEST_POOL_C", 200, None));
+        assert!(pool.record("groq", "CLAUDE_PROXY_TEST_POOL_C", 401, None));
+        assert!(pool.next_available(&provider).is_none());
+        // With both resting, the one back first is used
+        assert_eq!(keys(1), ["key-a"]);
+
+        let stats = &pool.stats()["groq"];
+        let a = &stats["CLAUDE_PROXY_TEST_POOL_A"];
+        assert_eq!((a.requests, a.rate_limited), (1, 1));
+        assert!(a.resting_secs.is_some());
+        let c = &stats["CLAUDE_PROXY_TEST_POOL_C"];
+        assert_eq!((c.requests, c.successes, c.rejected), (2, 1, 1));
+
+        // Resting ends
+        let later = Instant::now() + AUTH_COOLDOWN + Duration::from_secs(1);
+        let envs = provider.pooled_key_envs().unwrap();
+        assert!(pool.pick_at(&provider, envs, later, false).is_some());
+
+        // Rotation takes precedence over an inline key, unless none of the
+        // variables is set
+        let inline = ProviderConfig {
+            api_key: Some("key".to_string()),
+            ..provider
+        };
+        assert!(pool.pick(&inline).unwrap().is_some());
+        let inline = ProviderConfig {
+            api_key_envs: vec!["CLAUDE_PROXY_TEST_POOL_B".to_string()],
+            ..inline
+        };
+        assert!(pool.pick(&inline).unwrap().is_none());
+    }
+}
@@ -0,0 +1,137 @@
+//! Reading API keys from the OS keychain.
+//!
+//! A provider's `api_key_keychain = "<account>"` reads its key from the
+//! generic password stored under the service [`SERVICE`] and that account,
+//! through [`keyring`]: the login keychain on macOS, the Secret Service on
+//! Linux (e.g. GNOME Keyring or `KWallet`) and the Credential Manager on
+//! Windows. Store a key with
+//!
+//! ```text
+//! security add-generic-password -s claude-proxy -a groq -w          # macOS
+//! secret-tool store --label "claude-proxy groq" service claude-proxy account groq  # Linux
+//! ```
+//!
+//! The keychain is slow and blocking to ask, and keys are resolved for every
+//! request, so [`load`] reads a config's keys when the config is loaded, and
+//! requests only look them up in memory.
+
+use crate::config::ProxyConfig;
+use crate::error::{ProxyError, Result};
+
+use keyring::Entry;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// The service keys are stored under.
+pub const SERVICE: &str = "claude-proxy";
+
+/// What was read for each account: the key, or why there isn't one.
+static KEYS: OnceLock<Mutex<HashMap<String, std::result::Result<String, String>>>> =
+    OnceLock::new();
+
+fn keys() -> &'static Mutex<HashMap<String, std::result::Result<String, String>>> {
+    KEYS.get_or_init(Mutex::default)
+}
+
+/// Read the keys `config`'s providers take from the keychain, replacing what
+/// was read for them before. A key that can't be read is remembered as such,
+/// and the error returned by [`read`].
+pub fn load(config: &ProxyConfig) {
+    let accounts = std::iter::once(&config.provider)
+        .chain(config.providers.values())
+        .filter_map(|provider| provider.api_key_keychain.as_deref());
+    for account in accounts {
+        remember(account, &lookup(account));
+    }
+}
+
+/// The key stored for `account`, as read by [`load`]. An account it hasn't
+/// seen, such as one in a config built in code, is read now.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the keychain can't be read or holds no such
+/// key.
+pub fn read(account: &str) -> Result<String> {
+    let known = keys()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(account)
+        .cloned();
+    if let Some(key) = known {
+        return key.map_err(ProxyError::config);
+    }
+    let key = lookup(account);
+    remember(account, &key);
+    key
+}
+
+fn remember(account: &str, key: &Result<String>) {
+    let key = match key {
+        Ok(key) => Ok(key.clone()),
+        Err(e) => Err(e.to_string()),
+    };
+    keys()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(account.to_string(), key);
+}
+
+fn lookup(account: &str) -> Result<String> {
+    let entry = Entry::new(SERVICE, account).map_err(|e| unreadable(account, &e))?;
+    password(&entry, account)
+}
+
+/// The key held by `entry`, trimmed.
+fn password(entry: &Entry, account: &str) -> Result<String> {
+    match entry.get_password() {
+        Ok(key) if !key.trim().is_empty() => Ok(key.trim().to_string()),
+        Ok(_) | Err(keyring::Error::NoEntry) => Err(ProxyError::config(format!(
+            "No key for account '{account}' of service '{SERVICE}' in the keychain"
+        ))),
+        Err(e) => Err(unreadable(account, &e)),
+    }
+}
+
+fn unreadable(account: &str, error: &keyring::Error) -> ProxyError {
+    ProxyError::config(format!(
+        "Can't read key '{account}' from the keychain: {error}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+
+        let entry = Entry::new(SERVICE, "groq").unwrap();
+        entry.set_password(" gsk-test\n").unwrap();
+        assert_eq!(password(&entry, "groq").unwrap(), "gsk-test");
+
+        let missing = Entry::new(SERVICE, "mistral").unwrap();
+        let error = password(&missing, "mistral").unwrap_err().to_string();
+        assert!(error.contains("No key for account 'mistral'"), "{error}");
+
+        let locked = Entry::new(SERVICE, "cohere").unwrap();
+        let mock: &keyring::mock::MockCredential = locked.get_credential().downcast_ref().unwrap();
+        mock.set_error(keyring::Error::NoStorageAccess("locked".into()));
+        let error = password(&locked, "cohere").unwrap_err().to_string();
+        assert!(error.contains("Can't read key 'cohere'"), "{error}");
+
+        // Mock entries hold nothing, so a loaded account reads as missing,
+        // and the failure is kept rather than asked again on every request
+        let config = ProxyConfig::builder()
+            .api_key_keychain("together")
+            .build()
+            .unwrap();
+        load(&config);
+        assert!(read("together").is_err());
+        keys()
+            .lock()
+            .unwrap()
+            .insert("together".into(), Ok("tg-key".into()));
+        assert_eq!(read("together").unwrap(), "tg-key");
+    }
+}
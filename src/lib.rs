@@ -33,25 +33,78 @@
 //! let logger = SharedLogger::new("proxy.log")?;
 //! let client = reqwest::Client::new();
 //!
-//! let state = Arc::new(AppState { config, client, logger });
+//! let state = Arc::new(AppState::new(config, client, logger));
 //! let app = build_router(state);
 //!
 //! let listener = tokio::net::TcpListener::bind("0.0.0.0:4222").await?;
+//! // The admin API needs the client's address to serve it without [auth]
+//! let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
 //! axum::serve(listener, app).await?;
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! Without a config file, build the config in code with [`ProxyConfig::builder`].
+//! To see or change requests and responses as they pass, register [`hooks`] on
+//! the [`AppState`].
 
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod auxiliary;
+pub mod aws;
+pub mod batches;
+pub mod budget;
+pub mod capture;
+pub mod concurrency;
 pub mod config;
+pub mod context_window;
+pub mod costs;
+pub mod encryption;
 pub mod error;
+pub mod eval;
+pub mod health_check;
+pub mod hooks;
+pub mod images;
+pub mod init;
+pub mod journal;
+pub mod json_stream;
+pub mod key_file;
+pub mod key_pool;
+pub mod keychain;
+pub mod listen;
+pub mod log_compaction;
+pub mod log_context;
+pub mod log_view;
 pub mod logging;
+pub mod loop_guard;
+pub mod metrics;
+pub mod migrate;
 pub mod models;
+pub mod oauth;
+pub mod provenance;
+pub mod provider_test;
 pub mod providers;
 pub mod proxy;
+pub mod rate_limit;
+pub mod recording;
+pub mod registry;
+pub mod reload;
+pub mod response_cache;
+pub mod routing;
+pub mod schedule;
+pub mod self_test;
 pub mod server;
+pub mod sinks;
+pub mod state;
+pub mod storage;
+pub mod tls;
+pub mod tokens;
+pub mod tool_policy;
 pub mod translate;
+pub mod validation;
 
-pub use config::ProxyConfig;
+pub use config::{ProxyConfig, ProxyConfigBuilder, SharedConfig};
 pub use error::{ProxyError, Result};
 pub use logging::SharedLogger;
 pub use server::{build_router, AppState};
@@ -0,0 +1,123 @@
+//! Binding the proxy's port, and what to do when it's taken.
+//!
+//! A second `claude-proxy` started on the same port would otherwise fail
+//! with a bare "address in use". [`bind`] asks the occupant's `/health`
+//! whether it is a claude-proxy (its answer names the [`SERVICE`]) and,
+//! as `port_conflict` says, fails saying what holds the port, leaves the
+//! running proxy to serve (`reuse`), or moves on to the next free port
+//! (`next`).
+
+use crate::config::PortConflict;
+use crate::error::{ProxyError, Result};
+
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// The `service` a claude-proxy's `/health` names.
+pub const SERVICE: &str = "claude-proxy";
+
+/// How many ports after the configured one `next` tries.
+const NEXT_PORTS: u16 = 20;
+
+/// How long the occupant of a port has to answer `/health`.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The outcome of [`bind`].
+#[derive(Debug)]
+pub enum Bound {
+    /// Listening on `port`.
+    Listening(TcpListener, u16),
+    /// Another claude-proxy, of the given version, already serves `port`.
+    Running { port: u16, version: String },
+}
+
+/// Listen on `port` on all interfaces, handling a port that's taken as
+/// `policy` says.
+///
+/// # Errors
+/// Returns `ProxyError::Other` if the port can't be bound and `policy` has no
+/// way around it, naming what holds it.
+pub async fn bind(port: u16, policy: PortConflict) -> Result<Bound> {
+    let error = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => return Ok(Bound::Listening(listener, port)),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => e,
+        Err(e) => {
+            return Err(ProxyError::other(format!(
+                "Can't listen on port {port}: {e}"
+            )))
+        }
+    };
+
+    if policy == PortConflict::Next {
+        for next in (1..=NEXT_PORTS).filter_map(|i| port.checked_add(i)) {
+            if let Ok(listener) = TcpListener::bind(("0.0.0.0", next)).await {
+                return Ok(Bound::Listening(listener, next));
+            }
+        }
+        return Err(ProxyError::other(format!(
+            "Ports {port} to {} are all in use",
+            port.saturating_add(NEXT_PORTS)
+        )));
+    }
+
+    let occupant = probe(port).await;
+    match (policy, occupant) {
+        (PortConflict::Reuse, Some(version)) => Ok(Bound::Running { port, version }),
+        (_, Some(version)) => Err(ProxyError::other(format!(
+            "Port {port} is in use by claude-proxy v{version} (http://localhost:{port}); \
+             set port_conflict = \"reuse\" to use it, or \"next\" to pick another port"
+        ))),
+        (_, None) => Err(ProxyError::other(format!(
+            "Port {port} is in use by another program ({error}); \
+             set port_conflict = \"next\" to pick another port"
+        ))),
+    }
+}
+
+/// The version of the claude-proxy listening on `port`, if that's what is.
+/// One serving HTTPS (`[tls]`) is asked over HTTPS, trusting whatever
+/// certificate it has.
+async fn probe(port: u16) -> Option<String> {
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .danger_accept_invalid_certs(true)
+        .build()
+        .ok()?;
+    let mut health = None;
+    for scheme in ["http", "https"] {
+        let response = client
+            .get(format!("{scheme}://127.0.0.1:{port}/health"))
+            .send()
+            .await;
+        if let Ok(response) = response {
+            health = response.json::<serde_json::Value>().await.ok();
+            break;
+        }
+    }
+    let health = health?;
+    if health["service"] != SERVICE {
+        return None;
+    }
+    Some(health["version"].as_str().unwrap_or("unknown").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_taken_port() {
+        // Something that isn't a claude-proxy holds the port
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let error = bind(port, PortConflict::Fail).await.unwrap_err();
+        assert!(error.to_string().contains("another program"), "{error}");
+        assert!(bind(port, PortConflict::Reuse).await.is_err());
+        match bind(port, PortConflict::Next).await.unwrap() {
+            Bound::Listening(_, next) => assert!(next > port && next <= port + NEXT_PORTS),
+            Bound::Running { .. } => panic!("not a claude-proxy"),
+        }
+    }
+}
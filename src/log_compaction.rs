@@ -0,0 +1,166 @@
+//! Scheduled compaction of the stored log.
+//!
+//! The logger keeps only its last 10,000 entries in memory, but the storage
+//! backend is only ever appended to. The task started by [`spawn`] looks at the
+//! log every minute and compacts it down to those entries once it has grown
+//! past `[logging] compact_max_bytes`, or when it was last compacted more than
+//! `compact_interval_secs` ago. `POST /admin/logs/compact` compacts it on
+//! demand.
+//!
+//! If 10,000 entries alone take more than `compact_max_bytes`, compacting
+//! can't bring the log under the limit; that's logged once, and the log is
+//! then compacted on the age check only, rather than rewritten every minute.
+
+use crate::config::LoggingConfig;
+use crate::error::{ProxyError, Result};
+use crate::logging::SharedLogger;
+use crate::state::AppState;
+
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often the thresholds are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The stored log's size around one compaction, where the backend reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Compaction {
+    pub bytes_before: Option<u64>,
+    pub bytes_after: Option<u64>,
+}
+
+/// Why the log is due for compaction, if it is. `size` is the log's size in
+/// bytes where known; `over_limit` is set while the last compaction couldn't
+/// bring it under `compact_max_bytes`.
+#[must_use]
+pub fn due(
+    config: &LoggingConfig,
+    size: Option<u64>,
+    over_limit: bool,
+    since_compacted: Duration,
+) -> Option<String> {
+    if let Some(size) = size {
+        if config.compact_max_bytes > 0 && size > config.compact_max_bytes && !over_limit {
+            return Some(format!(
+                "{size} bytes, over the {} byte limit",
+                config.compact_max_bytes
+            ));
+        }
+    }
+    config
+        .compact_interval()
+        .filter(|interval| since_compacted >= *interval)
+        .map(|interval| format!("last compacted over {}s ago", interval.as_secs()))
+}
+
+/// Compact the log now, away from the async runtime's worker threads.
+///
+/// # Errors
+/// Returns `ProxyError::Io` if the log can't be rewritten.
+pub async fn compact(logger: &SharedLogger) -> Result<Compaction> {
+    let logger = logger.clone();
+    tokio::task::spawn_blocking(move || {
+        let storage = logger.storage();
+        let bytes_before = storage.log_size().ok().flatten();
+        logger.compact()?;
+        Ok(Compaction {
+            bytes_before,
+            bytes_after: storage.log_size().ok().flatten(),
+        })
+    })
+    .await
+    .map_err(|e| ProxyError::other(format!("Log compaction did not finish: {e}")))?
+}
+
+/// Start the compaction task. The thresholds are read afresh on every check,
+/// so changing them needs no restart.
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut over_limit = false;
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            let config = state.config.load();
+            let settings = &config.logging;
+            let size = state.logger.storage().log_size().ok().flatten();
+            let Some(reason) = due(settings, size, over_limit, state.logger.since_compacted())
+            else {
+                continue;
+            };
+            match compact(&state.logger).await {
+                Ok(result) => {
+                    state
+                        .logger
+                        .info("logging", format!("Compacted the log ({reason})"));
+                    let still_over = settings.compact_max_bytes > 0
+                        && result
+                            .bytes_after
+                            .is_some_and(|after| after > settings.compact_max_bytes);
+                    if still_over && !over_limit {
+                        state.logger.warn(
+                            "logging",
+                            format!(
+                                "The log's last 10000 entries take more than compact_max_bytes \
+                                 ({}); compacting on the age check only until it's raised",
+                                settings.compact_max_bytes
+                            ),
+                        );
+                    }
+                    over_limit = still_over;
+                }
+                Err(e) => state
+                    .logger
+                    .error("logging", format!("Failed to compact the log: {e}")),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::{LogEntry, LogLevel};
+
+    #[test]
+    fn test_due() {
+        let config = LoggingConfig {
+            compact_max_bytes: 1000,
+            compact_interval_secs: 3600,
+            ..LoggingConfig::default()
+        };
+        let minute = Duration::from_secs(60);
+        let day = Duration::from_secs(86_400);
+        assert!(due(&config, Some(500), false, minute).is_none());
+        assert!(due(&config, None, false, minute).is_none());
+        assert!(due(&config, Some(2000), false, minute)
+            .unwrap()
+            .contains("over the 1000 byte limit"));
+        // Stuck over the limit: only the age check applies
+        assert!(due(&config, Some(2000), true, minute).is_none());
+        assert!(due(&config, Some(2000), true, day)
+            .unwrap()
+            .contains("3600s ago"));
+
+        let off = LoggingConfig {
+            compact_max_bytes: 0,
+            compact_interval_secs: 0,
+            ..LoggingConfig::default()
+        };
+        assert!(due(&off, Some(u64::MAX), false, day).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compact_keeps_ring_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.log");
+        let line = serde_json::to_string(&LogEntry::new(LogLevel::Info, "test", "old")).unwrap();
+        std::fs::write(&path, format!("{line}\n").repeat(10_005)).unwrap();
+
+        let logger = SharedLogger::new(&path).unwrap();
+        let result = compact(&logger).await.unwrap();
+        assert!(result.bytes_before.unwrap() > result.bytes_after.unwrap());
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 10_000);
+        assert!(logger.since_compacted() < Duration::from_secs(5));
+    }
+}
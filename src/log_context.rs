@@ -0,0 +1,335 @@
+//! Request-scoped log context.
+//!
+//! Every request runs with a task-local [`RequestContext`] holding its request
+//! id, the requested model, the provider and model currently serving it, and the client's
+//! session id. [`SharedLogger`](crate::logging::SharedLogger) merges those fields
+//! into the `context` of each entry logged while the request is handled,
+//! including from its response stream, so logs can be filtered with e.g.
+//! `jq 'select(.context.request_id == "...")'`.
+//!
+//! The request id comes from an incoming `x-request-id` header when present and
+//! is echoed back in the response. Every response also carries a `request-id`
+//! in Anthropic's style (`req_...`), which client tooling logs and reports to
+//! support: Anthropic's own in passthrough mode, else made from the request id. Clients sharing one proxy can label their
+//! traffic with an `x-claude-proxy-tags` header of comma-separated tags (e.g.
+//! `project=billing,task=refactor`); the tags are logged with the request and
+//! kept on its usage record and token metrics, so spend can be attributed.
+//!
+//! The context also carries the upstream response headers a provider's
+//! `response_headers` lets through, which are copied onto the response.
+
+use axum::body::{Body, HttpBody};
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::Stream;
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Sent by Claude Code on every request of a session.
+pub const SESSION_ID_HEADER: &str = "x-claude-code-session-id";
+/// Comma-separated tags attributing a request to a project or task.
+pub const TAGS_HEADER: &str = "x-claude-proxy-tags";
+/// Anthropic's request id header.
+pub const ANTHROPIC_REQUEST_ID_HEADER: &str = "request-id";
+/// The name an upstream's own `x-request-id` is passed on under.
+pub const UPSTREAM_REQUEST_ID_HEADER: &str = "x-upstream-request-id";
+
+/// Most tags kept per request; further ones are ignored.
+const MAX_TAGS: usize = 10;
+/// Longest tag kept, in bytes; longer ones are ignored.
+const MAX_TAG_LEN: usize = 64;
+
+/// Fields attached to every log entry made on behalf of one request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RequestContext {
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// The model `provider` was asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Backend model forced for this request by the client, replacing the
+    /// routed provider's mapped model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_override: Option<String>,
+    /// Whether the client asked for provider-specific data under `extra`.
+    #[serde(skip)]
+    pub extra: bool,
+    /// Tags from the `x-claude-proxy-tags` header.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The `[auth]` key the client authenticated with, identifying its tenant.
+    /// Never logged.
+    #[serde(skip)]
+    pub tenant_key: Option<String>,
+    /// Headers of the last upstream response to copy onto the response.
+    #[serde(skip)]
+    pub response_headers: HeaderMap,
+    /// The `request-id` Anthropic answered a passthrough request with.
+    #[serde(skip)]
+    pub anthropic_request_id: Option<String>,
+}
+
+impl RequestContext {
+    /// A context with a fresh request id.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            request_id: uuid::Uuid::new_v4().to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// The `request-id` to answer with: Anthropic's, or `req_` and the
+    /// letters and digits of the request id.
+    #[must_use]
+    pub fn anthropic_request_id(&self) -> String {
+        self.anthropic_request_id.clone().unwrap_or_else(|| {
+            let id: String = self
+                .request_id
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect();
+            format!("req_{id}")
+        })
+    }
+
+    /// A context for an incoming request, reusing its `x-request-id` and
+    /// session headers when present.
+    #[must_use]
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        let mut ctx = Self::new();
+        if let Some(id) = header(REQUEST_ID_HEADER) {
+            ctx.request_id = id;
+        }
+        ctx.session_id = header(SESSION_ID_HEADER);
+        ctx.tags = parse_tags(
+            headers
+                .get_all(TAGS_HEADER)
+                .iter()
+                .filter_map(|v| v.to_str().ok()),
+        );
+        ctx
+    }
+}
+
+/// The distinct, non-empty tags of one or more `x-claude-proxy-tags` values,
+/// in order of appearance.
+fn parse_tags<'a>(values: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in values.flat_map(|v| v.split(',')).map(str::trim) {
+        if tags.len() == MAX_TAGS {
+            break;
+        }
+        if !tag.is_empty() && tag.len() <= MAX_TAG_LEN && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+type Handle = Arc<Mutex<RequestContext>>;
+
+tokio::task_local! {
+    static CURRENT: Handle;
+}
+
+/// Run `future` with `ctx` as the current request context.
+pub async fn scope<F: Future>(ctx: RequestContext, future: F) -> F::Output {
+    CURRENT.scope(Arc::new(Mutex::new(ctx)), future).await
+}
+
+/// The current request context, if running inside one.
+#[must_use]
+pub fn current() -> Option<RequestContext> {
+    CURRENT
+        .try_with(|handle| handle.lock().ok().map(|ctx| ctx.clone()))
+        .ok()
+        .flatten()
+}
+
+/// Modify the current request context; a no-op outside a request.
+pub fn update(f: impl FnOnce(&mut RequestContext)) {
+    let _ = CURRENT.try_with(|handle| {
+        if let Ok(mut ctx) = handle.lock() {
+            f(&mut ctx);
+        }
+    });
+}
+
+/// Record the requested model and the client's session, as far as known.
+pub fn set_request(model: &str, user_id: Option<&str>) {
+    update(|ctx| {
+        ctx.model = Some(model.to_string());
+        if ctx.session_id.is_none() {
+            ctx.session_id = user_id.and_then(session_from_user_id).map(str::to_string);
+        }
+    });
+}
+
+/// Record the provider now serving the request, and the model it is asked
+/// for (they change on fallback).
+pub fn set_provider(provider: &str, backend_model: &str) {
+    update(|ctx| {
+        ctx.provider = Some(provider.to_string());
+        ctx.backend_model = Some(backend_model.to_string());
+    });
+}
+
+/// Record a backend model the client asked for in place of the mapped one.
+pub fn set_model_override(model: &str) {
+    update(|ctx| ctx.model_override = Some(model.to_string()));
+}
+
+/// Record that the client asked for provider-specific data in the response.
+pub fn set_extra() {
+    update(|ctx| ctx.extra = true);
+}
+
+/// Record the upstream response headers to pass on, replacing those of an
+/// earlier attempt or provider.
+pub fn set_response_headers(headers: HeaderMap) {
+    update(|ctx| ctx.response_headers = headers);
+}
+
+/// Record the `request-id` of Anthropic's response to a passthrough request.
+pub fn set_anthropic_request_id(id: &str) {
+    update(|ctx| ctx.anthropic_request_id = Some(id.to_string()));
+}
+
+/// Claude Code's `metadata.user_id` ends in `_session_<uuid>`.
+pub(crate) fn session_from_user_id(user_id: &str) -> Option<&str> {
+    user_id
+        .rsplit_once("_session_")
+        .map(|(_, session)| session)
+        .filter(|s| !s.is_empty())
+}
+
+/// Middleware running each request, and its response body, in a fresh context.
+pub async fn scope_request(request: Request, next: Next) -> Response {
+    let handle: Handle = Arc::new(Mutex::new(RequestContext::from_headers(request.headers())));
+    let mut response = CURRENT.scope(handle.clone(), next.run(request)).await;
+
+    let (request_id, anthropic_request_id, upstream_headers) = handle
+        .lock()
+        .map(|mut ctx| {
+            let headers = std::mem::take(&mut ctx.response_headers);
+            (ctx.request_id.clone(), ctx.anthropic_request_id(), headers)
+        })
+        .unwrap_or_default();
+    for (name, value) in &upstream_headers {
+        let name = if name == REQUEST_ID_HEADER {
+            HeaderName::from_static(UPSTREAM_REQUEST_ID_HEADER)
+        } else {
+            name.clone()
+        };
+        response.headers_mut().append(name, value.clone());
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&anthropic_request_id) {
+        response
+            .headers_mut()
+            .insert(ANTHROPIC_REQUEST_ID_HEADER, value);
+    }
+    // Streaming bodies are polled after the handler returns; keep them in scope.
+    // Buffered ones are left alone so they keep their Content-Length.
+    if response.body().size_hint().exact().is_some() {
+        return response;
+    }
+    response.map(|body| {
+        Body::from_stream(Scoped {
+            inner: Box::pin(body.into_data_stream()),
+            handle,
+        })
+    })
+}
+
+/// A stream polled with a request context set.
+struct Scoped<S> {
+    inner: Pin<Box<S>>,
+    handle: Handle,
+}
+
+impl<S: Stream> Stream for Scoped<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        CURRENT.sync_scope(this.handle.clone(), || this.inner.as_mut().poll_next(cx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_from_user_id() {
+        assert_eq!(
+            session_from_user_id("user_abc_account_123_session_9f8e-7d6c"),
+            Some("9f8e-7d6c")
+        );
+        assert_eq!(session_from_user_id("user_abc"), None);
+    }
+
+    #[test]
+    fn test_tags_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            TAGS_HEADER,
+            HeaderValue::from_static("project=billing, ,task=x"),
+        );
+        headers.append(TAGS_HEADER, HeaderValue::from_static("task=x,urgent"));
+        assert_eq!(
+            RequestContext::from_headers(&headers).tags,
+            ["project=billing", "task=x", "urgent"]
+        );
+        assert!(RequestContext::from_headers(&HeaderMap::new())
+            .tags
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scope_and_update() {
+        assert!(current().is_none());
+        let ctx = scope(RequestContext::new(), async {
+            set_request("claude-sonnet-4-20250514", Some("user_x_session_s1"));
+            set_provider("groq", "llama-3.3-70b-versatile");
+            current().unwrap()
+        })
+        .await;
+        assert_eq!(ctx.model.as_deref(), Some("claude-sonnet-4-20250514"));
+        assert_eq!(ctx.provider.as_deref(), Some("groq"));
+        assert_eq!(
+            ctx.backend_model.as_deref(),
+            Some("llama-3.3-70b-versatile")
+        );
+        assert_eq!(ctx.session_id.as_deref(), Some("s1"));
+        assert!(current().is_none());
+
+        let mut ctx = RequestContext::new();
+        ctx.request_id = "3f2a-9c".to_string();
+        assert_eq!(ctx.anthropic_request_id(), "req_3f2a9c");
+        ctx.anthropic_request_id = Some("req_011CAbc".to_string());
+        assert_eq!(ctx.anthropic_request_id(), "req_011CAbc");
+    }
+}
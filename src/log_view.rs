@@ -0,0 +1,177 @@
+//! Reading the log back for `claude-proxy logs`.
+//!
+//! A [`LogFilter`] picks entries by minimum level, component and time range,
+//! and [`render`] prints one per line for the terminal. [`Follower`] tails the
+//! JSONL log file as the server appends to it, decrypting sealed lines with the
+//! `[encryption]` key. A compaction that rewrites the file restarts the tail at
+//! its new end.
+
+use crate::encryption::{is_sealed, Cipher};
+use crate::error::Result;
+use crate::logging::{LogEntry, LogLevel};
+
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Which entries to show.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// Entries at this level and above.
+    pub min_level: Option<LogLevel>,
+    /// Entries from this component only, e.g. `proxy` or `health`.
+    pub component: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl LogFilter {
+    #[must_use]
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.min_level
+            .as_ref()
+            .map_or(true, |min| entry.level >= *min)
+            && self
+                .component
+                .as_ref()
+                .map_or(true, |component| entry.component == *component)
+            && self.since.map_or(true, |since| entry.timestamp >= since)
+            && self.until.map_or(true, |until| entry.timestamp < until)
+    }
+}
+
+/// The last `lines` entries that pass `filter`, oldest first.
+#[must_use]
+pub fn select(entries: Vec<LogEntry>, filter: &LogFilter, lines: usize) -> Vec<LogEntry> {
+    let mut selected: Vec<LogEntry> = entries.into_iter().filter(|e| filter.matches(e)).collect();
+    let skip = selected.len().saturating_sub(lines);
+    selected.split_off(skip)
+}
+
+/// An entry on one line: time, level, component, message, then its context.
+#[must_use]
+pub fn render(entry: &LogEntry) -> String {
+    let mut line = format!(
+        "{} {:<5} {}: {}",
+        entry.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+        entry.level.as_str().to_uppercase(),
+        entry.component,
+        entry.message
+    );
+    if let Some(ref context) = entry.context {
+        line.push(' ');
+        line.push_str(&context.to_string());
+    }
+    line
+}
+
+/// Tails a JSONL log file.
+pub struct Follower {
+    path: PathBuf,
+    cipher: Option<Cipher>,
+    offset: u64,
+    /// The start of a line not yet fully written.
+    partial: Vec<u8>,
+}
+
+impl Follower {
+    /// Follow the log at `path`. The first [`poll`](Self::poll) returns
+    /// everything already in it.
+    #[must_use]
+    pub fn new(path: &Path, cipher: Option<Cipher>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            cipher,
+            offset: 0,
+            partial: Vec::new(),
+        }
+    }
+
+    /// The entries appended since the last call. Lines that don't parse, or
+    /// are sealed and can't be opened, are skipped.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Io` if the file can't be read.
+    pub fn poll(&mut self) -> Result<Vec<LogEntry>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            // Rewritten by compaction: what's there was already shown.
+            self.offset = len;
+            self.partial.clear();
+            return Ok(Vec::new());
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let read = file.read_to_end(&mut self.partial)?;
+        self.offset += read as u64;
+
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(Vec::new());
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        Ok(String::from_utf8_lossy(&complete)
+            .lines()
+            .filter_map(|line| self.decode(line))
+            .collect())
+    }
+
+    fn decode(&self, line: &str) -> Option<LogEntry> {
+        match self.cipher {
+            Some(ref cipher) if is_sealed(line) => {
+                serde_json::from_slice(&cipher.open(line).ok()?).ok()
+            }
+            _ => serde_json::from_str(line).ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::SharedLogger;
+
+    #[test]
+    fn test_filter_and_follow() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.log");
+        let logger = SharedLogger::new(&path).unwrap();
+        logger.debug("proxy", "Request details");
+        logger.warn("proxy", "Provider error");
+        logger.warn("health", "Provider re-enabled");
+
+        let filter = LogFilter {
+            min_level: Some(LogLevel::Warn),
+            component: Some("proxy".to_string()),
+            ..LogFilter::default()
+        };
+        let entries = logger.storage().recent_logs(usize::MAX).unwrap();
+        let selected = select(entries.clone(), &filter, 10);
+        assert_eq!(selected.len(), 1);
+        assert!(render(&selected[0]).contains("WARN  proxy: Provider error"));
+        assert_eq!(select(entries.clone(), &LogFilter::default(), 2).len(), 2);
+        let future = LogFilter {
+            since: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..LogFilter::default()
+        };
+        assert!(select(entries, &future, 10).is_empty());
+
+        let mut tail = Follower::new(&path, None);
+        assert_eq!(tail.poll().unwrap().len(), 3);
+        assert!(tail.poll().unwrap().is_empty());
+        logger.error("proxy", "Upstream timed out");
+        let appended = tail.poll().unwrap();
+        assert_eq!(appended.len(), 1);
+        assert_eq!(appended[0].message, "Upstream timed out");
+
+        // A compacted (shorter) file is followed from its new end
+        std::fs::write(&path, "").unwrap();
+        assert!(tail.poll().unwrap().is_empty());
+        logger.info("proxy", "After compaction");
+        assert_eq!(tail.poll().unwrap()[0].message, "After compaction");
+    }
+}
@@ -1,19 +1,26 @@
 //! Structured JSONL logging with a ring-buffer for in-memory access.
 //!
 //! Follows the same pattern as twolebot: each log entry is a JSON line appended
-//! to a file, with a bounded in-memory ring buffer for recent access.
+//! to a file, with a bounded in-memory ring buffer for recent access. The file
+//! can be swapped for another [`Storage`] backend, and entries can be copied to
+//! extra [`sinks`](crate::sinks).
 
+use crate::sinks::Sink;
+use crate::storage::{FileStorage, Storage};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 
 const MAX_LOG_ENTRIES: usize = 10_000;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Entries a slow live subscriber may fall behind by before it misses some.
+const LIVE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum LogLevel {
     Debug,
@@ -22,6 +29,34 @@ pub enum LogLevel {
     Error,
 }
 
+impl LogLevel {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" | "warning" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            other => Err(format!(
+                "unknown log level '{other}' (expected debug, info, warn or error)"
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
     pub timestamp: DateTime<Utc>,
@@ -51,58 +86,43 @@ impl LogEntry {
     }
 }
 
-/// Ring-buffer logger that persists to JSONL, matching twolebot's pattern
+/// Ring-buffer logger that persists through a [`Storage`] backend (JSONL file
+/// by default), matching twolebot's pattern
 pub struct Logger {
     entries: VecDeque<LogEntry>,
-    file_path: std::path::PathBuf,
-    writer: Option<BufWriter<File>>,
+    storage: Arc<dyn Storage>,
+    sinks: Vec<Sink>,
 }
 
 impl Logger {
-    /// Create a new logger, loading existing entries from the JSONL file.
+    /// Create a new logger backed by a JSONL file, loading existing entries.
     ///
     /// # Errors
     /// Returns `io::Error` if the file can't be opened or read.
     pub fn new(file_path: impl AsRef<Path>) -> std::io::Result<Self> {
-        let file_path = file_path.as_ref().to_path_buf();
-
-        if let Some(parent) = file_path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-
-        let mut entries = VecDeque::with_capacity(MAX_LOG_ENTRIES);
-
-        if file_path.exists() {
-            let file = File::open(&file_path)?;
-            let reader = BufReader::new(file);
-            for line in reader.lines().map_while(Result::ok) {
-                if let Ok(entry) = serde_json::from_str::<LogEntry>(&line) {
-                    if entries.len() >= MAX_LOG_ENTRIES {
-                        entries.pop_front();
-                    }
-                    entries.push_back(entry);
-                }
-            }
-        }
-
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)?;
-        let writer = BufWriter::new(file);
+        let storage = FileStorage::new(file_path).map_err(std::io::Error::other)?;
+        Ok(Self::with_storage(Arc::new(storage)))
+    }
 
-        Ok(Self {
+    /// Create a logger over any storage backend, loading its recent entries.
+    #[must_use]
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        let entries = storage
+            .recent_logs(MAX_LOG_ENTRIES)
+            .unwrap_or_default()
+            .into();
+        Self {
             entries,
-            file_path,
-            writer: Some(writer),
-        })
+            storage,
+            sinks: Vec::new(),
+        }
     }
 
     pub fn log(&mut self, entry: LogEntry) {
-        if let Some(ref mut writer) = self.writer {
-            if let Ok(json) = serde_json::to_string(&entry) {
-                let _ = writeln!(writer, "{json}");
-                let _ = writer.flush();
+        let _ = self.storage.append_log(&entry);
+        for sink in &self.sinks {
+            if entry.level >= sink.min_level {
+                sink.sink.write(&entry);
             }
         }
         if self.entries.len() >= MAX_LOG_ENTRIES {
@@ -116,47 +136,77 @@ impl Logger {
         self.entries.iter().rev().take(limit).cloned().collect()
     }
 
-    /// Compact the log file, keeping only entries in the ring buffer.
+    /// Compact the stored log, keeping only entries in the ring buffer.
     ///
     /// # Errors
-    /// Returns `io::Error` if the file can't be rewritten.
+    /// Returns `io::Error` if the log can't be rewritten.
     pub fn compact(&mut self) -> std::io::Result<()> {
-        self.writer = None;
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.file_path)?;
-        let mut writer = BufWriter::new(file);
-        for entry in &self.entries {
-            if let Ok(json) = serde_json::to_string(entry) {
-                writeln!(writer, "{json}")?;
-            }
-        }
-        writer.flush()?;
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.file_path)?;
-        self.writer = Some(BufWriter::new(file));
-        Ok(())
+        self.storage
+            .compact_logs(MAX_LOG_ENTRIES)
+            .map_err(std::io::Error::other)
     }
 }
 
 #[derive(Clone)]
-pub struct SharedLogger(Arc<Mutex<Logger>>);
+pub struct SharedLogger {
+    inner: Arc<Mutex<Logger>>,
+    storage: Arc<dyn Storage>,
+    live: broadcast::Sender<LogEntry>,
+    /// When the stored log was last compacted, or the logger created.
+    compacted_at: Arc<Mutex<Instant>>,
+}
 
 impl SharedLogger {
-    /// Create a new thread-safe logger.
+    /// Create a new thread-safe logger backed by a JSONL file.
     ///
     /// # Errors
     /// Returns `io::Error` if the log file can't be opened.
     pub fn new(file_path: impl AsRef<Path>) -> std::io::Result<Self> {
-        Ok(Self(Arc::new(Mutex::new(Logger::new(file_path)?))))
+        let storage = FileStorage::new(file_path).map_err(std::io::Error::other)?;
+        Ok(Self::with_storage(Arc::new(storage)))
+    }
+
+    /// Create a thread-safe logger over any storage backend.
+    #[must_use]
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Logger::with_storage(storage.clone()))),
+            storage,
+            live: broadcast::channel(LIVE_CAPACITY).0,
+            compacted_at: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Receive every entry logged from now on, as the admin dashboard does.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<LogEntry> {
+        self.live.subscribe()
     }
 
-    pub fn log(&self, entry: LogEntry) {
-        if let Ok(mut logger) = self.0.lock() {
+    /// Replace the extra sinks every entry is copied to. Takes effect for the
+    /// next entry logged.
+    pub fn set_sinks(&self, sinks: Vec<Sink>) {
+        if let Ok(mut logger) = self.inner.lock() {
+            logger.sinks = sinks;
+        }
+    }
+
+    /// The storage backend this logger writes through.
+    #[must_use]
+    pub fn storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
+
+    /// Log an entry, adding the current request's fields (see
+    /// [`log_context`](crate::log_context)) to its context.
+    pub fn log(&self, mut entry: LogEntry) {
+        if let Some(request) = crate::log_context::current() {
+            entry.context = Some(merge_context(&request, entry.context.take()));
+        }
+        if self.live.receiver_count() > 0 {
+            let _ = self.live.send(entry.clone());
+        }
+        if let Ok(mut logger) = self.inner.lock() {
             logger.log(entry);
         }
     }
@@ -189,6 +239,54 @@ impl SharedLogger {
 
     #[must_use]
     pub fn recent(&self, limit: usize) -> Vec<LogEntry> {
-        self.0.lock().map(|l| l.recent(limit)).unwrap_or_default()
+        self.inner
+            .lock()
+            .map(|l| l.recent(limit))
+            .unwrap_or_default()
+    }
+
+    /// Compact the stored log, keeping only entries in the ring buffer.
+    /// Logging waits until it's done.
+    ///
+    /// # Errors
+    /// Returns `io::Error` if the log can't be rewritten.
+    pub fn compact(&self) -> std::io::Result<()> {
+        let mut logger = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        logger.compact()?;
+        *self
+            .compacted_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+        Ok(())
+    }
+
+    /// How long ago the stored log was last compacted (or, if it hasn't been,
+    /// the logger created).
+    #[must_use]
+    pub fn since_compacted(&self) -> Duration {
+        self.compacted_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .elapsed()
+    }
+}
+
+/// Combine request fields with an entry's own context. Fields the entry sets
+/// itself win; a non-object context is kept under `detail`.
+fn merge_context(
+    request: &crate::log_context::RequestContext,
+    context: Option<serde_json::Value>,
+) -> serde_json::Value {
+    let mut merged = serde_json::to_value(request).unwrap_or_default();
+    let Some(fields) = merged.as_object_mut() else {
+        return context.unwrap_or_default();
+    };
+    match context {
+        Some(serde_json::Value::Object(own)) => fields.extend(own),
+        Some(other) => {
+            fields.insert("detail".to_string(), other);
+        }
+        None => {}
     }
+    merged
 }
@@ -0,0 +1,197 @@
+//! Stopping agent loops that never hand back to the user.
+//!
+//! An agent that keeps calling tools, each result prompting another call,
+//! can burn through tokens on a pay-per-use backend without anyone watching.
+//! The proxy sees each round trip as a request whose last user message holds
+//! `tool_result`s, so it counts them from the conversation itself: the run of
+//! tool results since the user last wrote. Past `[loop_guard]
+//! max_tool_iterations`, `action = "nudge"` forwards the request with a note
+//! in the system prompt asking the model to stop and check in, and
+//! `action = "stop"` answers it directly with a message ending the turn,
+//! without calling the provider. The user's next message resets the count.
+//!
+//! Requests passed through unchanged to an Anthropic-format provider are not
+//! guarded.
+
+use crate::config::{LoopGuardAction, LoopGuardConfig};
+use crate::logging::SharedLogger;
+use crate::translate::anthropic_types::{
+    ContentBlock, MessageContent, MessagesRequest, MessagesResponse, ResponseContentBlock, Role,
+    SystemBlock, SystemContent, Usage,
+};
+
+/// Added to the system prompt by `nudge` unless `[loop_guard] nudge` is set.
+const DEFAULT_NUDGE: &str = "You have been calling tools for a long time without \
+    hearing from the user. Stop calling tools now: summarize what you have done and \
+    what is left, and ask the user how to continue.";
+
+/// What to do with a request.
+#[derive(Debug)]
+pub enum Guard {
+    /// Forward it as it is.
+    Pass,
+    /// Forward this nudged copy instead.
+    Nudge(Box<MessagesRequest>),
+    /// Answer with this response instead of forwarding it.
+    Stop(Box<MessagesResponse>),
+}
+
+/// How many tool round trips `req` has made since the user last wrote: the
+/// number of user messages at its end that carry tool results.
+#[must_use]
+pub fn tool_iterations(req: &MessagesRequest) -> usize {
+    req.messages
+        .iter()
+        .rev()
+        .filter(|message| message.role == Role::User)
+        .take_while(|message| match &message.content {
+            MessageContent::Text(_) => false,
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .any(|block| matches!(block, ContentBlock::ToolResult { .. })),
+        })
+        .count()
+}
+
+/// Decide what to do with `req` under `config`, logging when the guard acts.
+#[must_use]
+pub fn check(config: &LoopGuardConfig, req: &MessagesRequest, logger: &SharedLogger) -> Guard {
+    let Some(limit) = config.max_tool_iterations else {
+        return Guard::Pass;
+    };
+    let iterations = tool_iterations(req);
+    if iterations <= limit as usize {
+        return Guard::Pass;
+    }
+    match config.action {
+        LoopGuardAction::Nudge => {
+            logger.warn(
+                "loop_guard",
+                format!("{iterations} tool iterations without the user (limit {limit}); nudging"),
+            );
+            let nudge = config.nudge.as_deref().unwrap_or(DEFAULT_NUDGE);
+            Guard::Nudge(Box::new(nudged(req, nudge)))
+        }
+        LoopGuardAction::Stop => {
+            logger.warn(
+                "loop_guard",
+                format!("{iterations} tool iterations without the user (limit {limit}); stopping"),
+            );
+            Guard::Stop(Box::new(stopped(req, iterations)))
+        }
+    }
+}
+
+/// `req` with `nudge` after its system prompt.
+fn nudged(req: &MessagesRequest, nudge: &str) -> MessagesRequest {
+    let mut next = req.clone();
+    let block = SystemBlock::Text {
+        text: nudge.to_string(),
+        cache_control: None,
+    };
+    next.system = Some(match next.system.take() {
+        None => SystemContent::Text(nudge.to_string()),
+        Some(SystemContent::Text(text)) => SystemContent::Blocks(vec![
+            SystemBlock::Text {
+                text,
+                cache_control: None,
+            },
+            block,
+        ]),
+        Some(SystemContent::Blocks(mut blocks)) => {
+            blocks.push(block);
+            SystemContent::Blocks(blocks)
+        }
+    });
+    next
+}
+
+/// The response ending a turn the guard stopped.
+fn stopped(req: &MessagesRequest, iterations: usize) -> MessagesResponse {
+    MessagesResponse {
+        id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+        response_type: "message".to_string(),
+        role: "assistant".to_string(),
+        content: vec![ResponseContentBlock::Text {
+            text: format!(
+                "claude-proxy stopped this turn after {iterations} tool calls in a row \
+                 without a message from you ([loop_guard] max_tool_iterations). \
+                 Reply to continue."
+            ),
+        }],
+        model: req.model.clone(),
+        stop_reason: Some("end_turn".to_string()),
+        stop_sequence: None,
+        usage: Usage::default(),
+        extra: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(tool_rounds: usize) -> MessagesRequest {
+        let mut messages = vec![serde_json::json!({"role": "user", "content": "Fix the tests"})];
+        for i in 0..tool_rounds {
+            messages.push(serde_json::json!({"role": "assistant", "content": [
+                {"type": "tool_use", "id": format!("t{i}"), "name": "bash", "input": {}}
+            ]}));
+            messages.push(serde_json::json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": format!("t{i}"), "content": "FAILED"},
+                {"type": "text", "text": "<system-reminder>...</system-reminder>"}
+            ]}));
+        }
+        serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "system": "You are a coding agent.",
+            "messages": messages,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        let logger = SharedLogger::new("/tmp/claude-proxy-test-loop-guard.log").unwrap();
+        assert_eq!(tool_iterations(&conversation(0)), 0);
+        assert_eq!(tool_iterations(&conversation(4)), 4);
+
+        let mut config = LoopGuardConfig::default();
+        assert!(matches!(
+            check(&config, &conversation(100), &logger),
+            Guard::Pass
+        ));
+
+        config.max_tool_iterations = Some(3);
+        assert!(matches!(
+            check(&config, &conversation(3), &logger),
+            Guard::Pass
+        ));
+        let Guard::Nudge(req) = check(&config, &conversation(4), &logger) else {
+            panic!("past the limit");
+        };
+        let system = req.system.unwrap().as_text();
+        assert!(system.starts_with("You are a coding agent."), "{system}");
+        assert!(system.ends_with(DEFAULT_NUDGE), "{system}");
+
+        config.action = LoopGuardAction::Stop;
+        let Guard::Stop(resp) = check(&config, &conversation(4), &logger) else {
+            panic!("past the limit");
+        };
+        assert_eq!(resp.stop_reason.as_deref(), Some("end_turn"));
+        assert_eq!(resp.model, "claude-sonnet-4-20250514");
+
+        // The user writing again starts the count over
+        let mut req = conversation(4);
+        req.messages.push(
+            serde_json::from_value(serde_json::json!({"role": "assistant", "content": "Done?"}))
+                .unwrap(),
+        );
+        req.messages.push(
+            serde_json::from_value(serde_json::json!({"role": "user", "content": "Keep going"}))
+                .unwrap(),
+        );
+        assert!(matches!(check(&config, &req, &logger), Guard::Pass));
+    }
+}
@@ -1,11 +1,15 @@
-use clap::Parser;
-use claude_proxy::{build_router, AppState, ProxyConfig, SharedLogger};
+use clap::{Parser, Subcommand};
+use claude_proxy::encryption::Cipher;
+use claude_proxy::listen::{self, Bound};
+use claude_proxy::log_view::{self, LogFilter};
+use claude_proxy::{build_router, costs, AppState, ProxyConfig, SharedLogger};
+use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[command(
     name = "claude-proxy",
     about = "Universal API proxy for Claude Code — route through any LLM provider",
@@ -24,13 +28,137 @@ struct Cli {
     #[arg(long)]
     provider: Option<String>,
 
-    /// Log file path
-    #[arg(long, default_value = "claude-proxy.log")]
-    log_file: PathBuf,
+    /// Log file path (overrides config) [default: claude-proxy.log]
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Record upstream exchanges into this directory (overrides config)
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+
+    /// Don't reload the config file when it changes
+    #[arg(long)]
+    no_watch: bool,
+
+    /// After binding, send test requests through the proxy against a mock
+    /// provider and exit non-zero if any fails
+    #[arg(long)]
+    self_test: bool,
 
     /// Print config search paths and exit
     #[arg(long)]
     show_config_paths: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Print token usage and spend from the recorded usage, then exit
+    Stats {
+        /// Only requests since this RFC 3339 time or span back from now (e.g. 24h, 7d)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Only requests from this client session
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Only requests carrying this `x-claude-proxy-tags` tag
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print recent log entries, filtered, and optionally keep following the log
+    Logs {
+        /// Entries at this level and above (debug, info, warn, error)
+        #[arg(long)]
+        level: Option<claude_proxy::logging::LogLevel>,
+
+        /// Entries from this component only (e.g. proxy, health, startup)
+        #[arg(long)]
+        component: Option<String>,
+
+        /// Entries since this RFC 3339 time or span back from now (e.g. 30m, 24h)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Entries before this RFC 3339 time or span back from now
+        #[arg(long)]
+        until: Option<String>,
+
+        /// How many of the latest matching entries to print
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+
+        /// Keep printing new entries as they are logged
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Print entries as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a config file interactively: pick a provider and model mappings
+    Init {
+        /// Where to write the config
+        #[arg(value_name = "FILE", default_value = "claude-proxy.toml")]
+        file: PathBuf,
+    },
+    /// Send a few requests to the configured provider and report what works
+    Test {
+        /// Claude model to test (default: the first mapped model)
+        #[arg(long)]
+        model: Option<String>,
+    },
+    /// List the provider's models, the model mappings, and unmapped Claude models
+    Models {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a log, usage or capture file written with `[encryption]` as plaintext
+    Decrypt {
+        /// The encrypted file
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Check the hash chain of the `[journal]` file, then exit
+    VerifyJournal {
+        /// Journal to check instead of the configured one
+        #[arg(value_name = "FILE")]
+        file: Option<PathBuf>,
+    },
+    /// Replay captured requests against two providers and compare their answers
+    Eval {
+        /// Capture files or directories (default: the configured `[capture] dir`)
+        #[arg(value_name = "PATH")]
+        paths: Vec<PathBuf>,
+
+        /// The first provider to compare
+        #[arg(long, value_name = "PROVIDER")]
+        a: String,
+
+        /// The second provider to compare
+        #[arg(long, value_name = "PROVIDER")]
+        b: String,
+
+        /// Only requests from this client session
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Replay at most this many requests
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 #[tokio::main]
@@ -58,22 +186,203 @@ async fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    let mut config = ProxyConfig::find_and_load(cli.config.as_deref())?;
+    if let Some(Command::Init { ref file }) = cli.command {
+        let stdin = std::io::stdin();
+        claude_proxy::init::run(
+            &mut stdin.lock(),
+            &mut std::io::stdout(),
+            &reqwest::Client::new(),
+            file,
+        )
+        .await?;
+        return Ok(());
+    }
 
-    if let Some(port) = cli.port {
-        config.port = port;
+    let config_path = ProxyConfig::find_path(cli.config.as_deref())?;
+    if cli.command.is_none() {
+        info!("Loading config from {}", config_path.display());
     }
-    if let Some(ref provider) = cli.provider {
-        config.provider.name.clone_from(provider);
-        if let Some(preset) = claude_proxy::providers::ProviderPreset::from_name(provider) {
-            if config.provider.base_url.is_none() {
-                config.provider.base_url = Some(preset.base_url.to_string());
+    let mut config = ProxyConfig::load(&config_path)?;
+    apply_overrides(&cli, &mut config);
+
+    if let Some(Command::Test { ref model }) = cli.command {
+        let checks = claude_proxy::provider_test::run(&config, model.as_deref()).await?;
+        for check in &checks {
+            println!("{check}");
+        }
+        let failed = checks
+            .iter()
+            .filter(|c| c.outcome != claude_proxy::provider_test::Outcome::Pass)
+            .count();
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} checks did not pass", checks.len());
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Models { json }) = cli.command {
+        let client = config
+            .outbound
+            .apply(reqwest::Client::builder().timeout(std::time::Duration::from_secs(30)))?
+            .build()?;
+        let report = claude_proxy::models::report(&config, &client).await?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print!("{}", claude_proxy::models::render(&report));
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::VerifyJournal { ref file }) = cli.command {
+        let Some(path) = file
+            .clone()
+            .or_else(|| config.journal.file.as_ref().map(PathBuf::from))
+        else {
+            anyhow::bail!("No journal to verify: [journal] file is not set");
+        };
+        let entries = claude_proxy::journal::verify(&path)?;
+        println!("{}: {entries} entries, chain intact", path.display());
+        return Ok(());
+    }
+
+    let log_file = cli
+        .log_file
+        .clone()
+        .or_else(|| config.logging.file.as_ref().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("claude-proxy.log"));
+    let cipher = Cipher::from_config(&config.encryption)?;
+
+    if let Some(Command::Decrypt { ref file }) = cli.command {
+        let Some(cipher) = cipher else {
+            anyhow::bail!("No key to decrypt with: [encryption] is not configured");
+        };
+        let content = std::fs::read_to_string(file)?;
+        let mut stdout = std::io::stdout().lock();
+        for line in content.lines() {
+            writeln!(stdout, "{}", cipher.open_line(line)?)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Eval {
+        ref paths,
+        ref a,
+        ref b,
+        ref session,
+        limit,
+        json,
+    }) = cli.command
+    {
+        let paths = if paths.is_empty() {
+            let Some(ref dir) = config.capture.dir else {
+                anyhow::bail!("No captures to replay: give a path or set [capture] dir");
+            };
+            vec![PathBuf::from(dir)]
+        } else {
+            paths.clone()
+        };
+        let mut captures = claude_proxy::eval::load(&paths, cipher.as_ref(), session.as_deref())?;
+        if captures.is_empty() {
+            anyhow::bail!("No captured requests found");
+        }
+        captures.truncate(limit.unwrap_or(usize::MAX));
+        let report = claude_proxy::eval::run(&config, &captures, [a, b]).await?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print!("{}", claude_proxy::eval::render(&report));
+        }
+        return Ok(());
+    }
+
+    let storage = claude_proxy::storage::open(&config.storage, &log_file, cipher.clone())?;
+
+    if let Some(Command::Logs {
+        level,
+        ref component,
+        ref since,
+        ref until,
+        lines,
+        follow,
+        json,
+    }) = cli.command
+    {
+        if follow && config.storage.backend != "file" {
+            anyhow::bail!("--follow reads the JSONL log file; it needs the file storage backend");
+        }
+        let now = chrono::Utc::now();
+        let filter = LogFilter {
+            min_level: level,
+            component: component.clone(),
+            since: since
+                .as_deref()
+                .map(|s| costs::parse_since(s, now))
+                .transpose()?,
+            until: until
+                .as_deref()
+                .map(|s| costs::parse_since(s, now))
+                .transpose()?,
+        };
+        let print = |entry: &claude_proxy::logging::LogEntry| -> anyhow::Result<()> {
+            if json {
+                println!("{}", serde_json::to_string(entry)?);
+            } else {
+                println!("{}", log_view::render(entry));
             }
-            config.provider.api_key_env = preset.default_api_key_env.to_string();
+            Ok(())
+        };
+        if !follow {
+            for entry in log_view::select(storage.recent_logs(usize::MAX)?, &filter, lines) {
+                print(&entry)?;
+            }
+            return Ok(());
+        }
+        // The first poll reads the whole file, so nothing is shown twice or missed
+        let mut follower = log_view::Follower::new(&log_file, cipher);
+        for entry in log_view::select(follower.poll()?, &filter, lines) {
+            print(&entry)?;
+        }
+        loop {
+            for entry in follower.poll()? {
+                if filter.matches(&entry) {
+                    print(&entry)?;
+                }
+            }
+            tokio::select! {
+                () = tokio::time::sleep(std::time::Duration::from_millis(500)) => {}
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    }
+
+    if let Some(Command::Stats {
+        ref since,
+        ref session,
+        ref tag,
+        json,
+    }) = cli.command
+    {
+        let since = since
+            .as_deref()
+            .map(|s| costs::parse_since(s, chrono::Utc::now()))
+            .transpose()?;
+        let report = costs::report(
+            &storage.usage(since)?,
+            since,
+            session.as_deref(),
+            tag.as_deref(),
+        );
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            print!("{}", costs::render_table(&report));
         }
+        return Ok(());
     }
 
-    let logger = SharedLogger::new(&cli.log_file)?;
+    let logger = SharedLogger::with_storage(storage);
+    logger.set_sinks(claude_proxy::sinks::build(&config.logging.sinks)?);
 
     let base_url = config.effective_base_url()?;
     let _api_key = config.resolve_api_key()?;
@@ -82,16 +391,35 @@ async fn main() -> anyhow::Result<()> {
     info!("  Provider:  {}", config.provider.name);
     info!("  Base URL:  {}", base_url);
     info!(
-        "  Format:    {}",
+        "  Format:    {} ({})",
+        config.api_format().as_str(),
         if config.is_anthropic_format() {
-            "anthropic (passthrough)"
+            "passthrough"
         } else {
-            "openai (translate)"
+            "translate"
         }
     );
     info!("  Port:      {}", config.port);
     info!("  Models:    {} mapped", config.models.len());
-    info!("  Log file:  {}", cli.log_file.display());
+    if !config.providers.is_empty() {
+        let mut names: Vec<&str> = config.providers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        info!("  Routes to: {}", names.join(", "));
+    }
+    if let Some(ref dir) = config.record.dir {
+        info!("  Recording: {}", dir);
+    }
+    if let Some(ref dir) = config.capture.dir {
+        info!("  Capturing: {}", dir);
+    }
+    info!("  Log file:  {}", log_file.display());
+    if !config.logging.sinks.is_empty() {
+        info!("  Log sinks: {}", config.logging.sinks.len());
+    }
+    info!("  Storage:   {}", config.storage.backend);
+    if let Some(ref file) = config.registry.file {
+        info!("  Registry:  {}", file);
+    }
 
     logger.info(
         "startup",
@@ -101,32 +429,83 @@ async fn main() -> anyhow::Result<()> {
         ),
     );
 
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(300))
-        .pool_max_idle_per_host(10)
+    let tls = claude_proxy::tls::load(&config.tls)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+
+    let (listener, port) = match listen::bind(config.port, config.port_conflict).await? {
+        Bound::Listening(listener, port) => (listener, port),
+        Bound::Running { port, version } => {
+            info!("claude-proxy v{version} is already running on port {port}; using it");
+            info!("");
+            info!("  ANTHROPIC_BASE_URL={scheme}://localhost:{port} claude");
+            info!("");
+            return Ok(());
+        }
+    };
+    if port != config.port {
+        info!(
+            "  Port {} is in use; listening on {} instead",
+            config.port, port
+        );
+        logger.warn(
+            "startup",
+            format!(
+                "Port {} is in use; listening on {port} instead",
+                config.port
+            ),
+        );
+    }
+
+    let client = config
+        .outbound
+        .apply(
+            reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(300))
+                .pool_max_idle_per_host(10),
+        )?
         .build()?;
 
-    let state = Arc::new(AppState {
-        config: config.clone(),
-        client,
-        logger: logger.clone(),
-    });
+    let state = Arc::new(AppState::new(config.clone(), client, logger.clone()));
+    claude_proxy::health_check::spawn(state.clone());
+    claude_proxy::log_compaction::spawn(state.clone());
+    if !cli.no_watch {
+        let overrides = cli.clone();
+        claude_proxy::reload::watch(state.clone(), config_path.clone(), move |config| {
+            apply_overrides(&overrides, config);
+        });
+        claude_proxy::registry::watch(state.clone());
+        info!("  Watching:  {}", config_path.display());
+    }
 
     let app = build_router(state);
-    let bind_addr = format!("0.0.0.0:{}", config.port);
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
 
-    info!("Listening on http://{}", bind_addr);
+    if cli.self_test {
+        for check in claude_proxy::self_test::run(&config).await? {
+            info!("  Self-test: {} ok", check);
+        }
+    }
+
+    info!("Listening on {scheme}://0.0.0.0:{port}");
     info!("");
-    info!(
-        "  ANTHROPIC_BASE_URL=http://localhost:{} claude",
-        config.port
-    );
+    info!("  ANTHROPIC_BASE_URL={scheme}://localhost:{port} claude");
+    if let Some(tls) = &tls {
+        if config.tls.self_signed {
+            info!("  NODE_EXTRA_CA_CERTS={}", tls.cert.display());
+        }
+    }
     info!("");
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    match tls {
+        Some(tls) => claude_proxy::tls::serve(listener, app, tls.acceptor, shutdown_signal()).await,
+        None => {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_signal())
+            .await?;
+        }
+    }
 
     logger.info("shutdown", "Proxy stopped");
     info!("Shutdown complete");
@@ -134,6 +513,27 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Command-line settings that take precedence over the config file, applied at
+/// startup and again on every reload.
+fn apply_overrides(cli: &Cli, config: &mut ProxyConfig) {
+    if let Some(port) = cli.port {
+        config.port = port;
+    }
+    if let Some(ref provider) = cli.provider {
+        config.provider.name.clone_from(provider);
+        if let Some(preset) = claude_proxy::providers::ProviderPreset::from_name(provider) {
+            if config.provider.base_url.is_none() {
+                config.provider.base_url = Some(preset.base_url.to_string());
+            }
+            config.provider.api_key_env = preset.default_api_key_env.to_string();
+        }
+    }
+
+    if let Some(ref dir) = cli.record {
+        config.record.dir = Some(dir.to_string_lossy().into_owned());
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
@@ -0,0 +1,465 @@
+//! Prometheus metrics, served in the text exposition format at `/metrics`.
+//!
+//! Everything is labelled by upstream provider, and where it applies by the
+//! upstream model:
+//!
+//! - `claude_proxy_requests_total{provider,model,status}` — upstream requests by
+//!   response status
+//! - `claude_proxy_request_duration_seconds{provider,model}` — histogram of time
+//!   to a complete response (the whole stream, for streaming requests)
+//! - `claude_proxy_tokens_total{provider,model,type}` — input/output tokens
+//! - `claude_proxy_tagged_requests_total{provider,model,tag}` and
+//!   `claude_proxy_tagged_tokens_total{provider,model,tag,type}` — completed
+//!   requests and their tokens, once per `x-claude-proxy-tags` tag
+//! - `claude_proxy_response_cache_hits_total{provider,model}` — requests
+//!   answered from the `[response_cache]`
+//! - `claude_proxy_reconnect_replays_total{provider,model}` — re-sent streams
+//!   replayed within `reconnect_secs`
+//! - `claude_proxy_retries_total{provider}` — retries on transient statuses
+//! - `claude_proxy_retry_budget_exhausted_total{provider}` — retries refused
+//!   because the session's retry budget was spent
+//! - `claude_proxy_upstream_errors_total{provider,model,status}` — error
+//!   responses, with `status="network"` for transport failures
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Histogram bucket upper bounds, in seconds.
+const DURATION_BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; DURATION_BUCKETS.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    requests: BTreeMap<Labels, u64>,
+    durations: BTreeMap<Labels, Histogram>,
+    tokens: BTreeMap<Labels, u64>,
+    tagged_requests: BTreeMap<Labels, u64>,
+    tagged_tokens: BTreeMap<Labels, u64>,
+    cache_hits: BTreeMap<Labels, u64>,
+    reconnect_replays: BTreeMap<Labels, u64>,
+    retries: BTreeMap<Labels, u64>,
+    budget_exhausted: BTreeMap<Labels, u64>,
+    upstream_errors: BTreeMap<Labels, u64>,
+}
+
+/// Totals for one provider and upstream model since startup.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ModelTotals {
+    pub provider: String,
+    pub model: String,
+    /// Upstream requests, including those that never got a response.
+    pub requests: u64,
+    /// Error statuses and network failures among `requests`.
+    pub errors: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+/// Shared, in-memory metrics registry.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a completed upstream request and observe its latency.
+    pub fn record_request(&self, provider: &str, model: &str, status: u16, elapsed: Duration) {
+        self.with(|r| {
+            *r.requests
+                .entry(labels(provider, model, Some(&status.to_string())))
+                .or_default() += 1;
+            r.durations
+                .entry(labels(provider, model, None))
+                .or_default()
+                .observe(elapsed.as_secs_f64());
+            if status >= 400 {
+                *r.upstream_errors
+                    .entry(labels(provider, model, Some(&status.to_string())))
+                    .or_default() += 1;
+            }
+        });
+    }
+
+    /// Count a request that never got a response (connection or read failure).
+    pub fn record_network_error(&self, provider: &str, model: &str) {
+        self.with(|r| {
+            *r.upstream_errors
+                .entry(labels(provider, model, Some("network")))
+                .or_default() += 1;
+        });
+    }
+
+    pub fn record_tokens(&self, provider: &str, model: &str, input: u64, output: u64) {
+        self.with(|r| {
+            for (kind, count) in [("input", input), ("output", output)] {
+                let mut key = labels(provider, model, None);
+                key.push(("type", kind.to_string()));
+                *r.tokens.entry(key).or_default() += count;
+            }
+        });
+    }
+
+    /// Count a completed request and its tokens under each of its tags.
+    pub fn record_tagged(
+        &self,
+        provider: &str,
+        model: &str,
+        tags: &[String],
+        input: u64,
+        output: u64,
+    ) {
+        self.with(|r| {
+            for tag in tags {
+                let mut key = labels(provider, model, None);
+                key.push(("tag", tag.clone()));
+                *r.tagged_requests.entry(key.clone()).or_default() += 1;
+                for (kind, count) in [("input", input), ("output", output)] {
+                    let mut key = key.clone();
+                    key.push(("type", kind.to_string()));
+                    *r.tagged_tokens.entry(key).or_default() += count;
+                }
+            }
+        });
+    }
+
+    /// Count a request answered from the response cache.
+    pub fn record_cache_hit(&self, provider: &str, model: &str) {
+        self.with(|r| {
+            *r.cache_hits
+                .entry(labels(provider, model, None))
+                .or_default() += 1;
+        });
+    }
+
+    /// Count a re-sent stream replayed the response it missed.
+    pub fn record_reconnect_replay(&self, provider: &str, model: &str) {
+        self.with(|r| {
+            *r.reconnect_replays
+                .entry(labels(provider, model, None))
+                .or_default() += 1;
+        });
+    }
+
+    pub fn record_retry(&self, provider: &str) {
+        self.with(|r| {
+            *r.retries
+                .entry(vec![("provider", provider.to_string())])
+                .or_default() += 1;
+        });
+    }
+
+    /// Count a retry or fallback refused by the session's retry budget.
+    pub fn record_budget_exhausted(&self, provider: &str) {
+        self.with(|r| {
+            *r.budget_exhausted
+                .entry(vec![("provider", provider.to_string())])
+                .or_default() += 1;
+        });
+    }
+
+    /// Request, error and token totals per provider and model, sorted.
+    #[must_use]
+    pub fn totals(&self) -> Vec<ModelTotals> {
+        let Ok(r) = self.inner.lock() else {
+            return Vec::new();
+        };
+        let mut totals = BTreeMap::new();
+        for (labels, count) in &r.requests {
+            totals_for(&mut totals, labels).requests += count;
+        }
+        for (labels, count) in &r.upstream_errors {
+            let entry = totals_for(&mut totals, labels);
+            entry.errors += count;
+            if label(labels, "status") == Some("network") {
+                entry.requests += count;
+            }
+        }
+        for (labels, count) in &r.tokens {
+            let entry = totals_for(&mut totals, labels);
+            if label(labels, "type") == Some("input") {
+                entry.input_tokens += count;
+            } else {
+                entry.output_tokens += count;
+            }
+        }
+        drop(r);
+        totals.into_values().collect()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let Ok(r) = self.inner.lock() else {
+            return String::new();
+        };
+        let mut out = String::new();
+
+        write_counter(
+            &mut out,
+            "claude_proxy_requests_total",
+            "Upstream requests by response status.",
+            &r.requests,
+        );
+
+        let name = "claude_proxy_request_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "Time to a complete upstream response.",
+            "histogram",
+        );
+        for (labels, hist) in &r.durations {
+            for (count, bound) in hist.buckets.iter().zip(DURATION_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{} {count}",
+                    format_labels(labels, Some(&bound.to_string()))
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{} {}",
+                format_labels(labels, Some("+Inf")),
+                hist.count
+            );
+            let _ = writeln!(
+                out,
+                "{name}_sum{} {}",
+                format_labels(labels, None),
+                hist.sum
+            );
+            let _ = writeln!(
+                out,
+                "{name}_count{} {}",
+                format_labels(labels, None),
+                hist.count
+            );
+        }
+
+        write_counter(
+            &mut out,
+            "claude_proxy_tokens_total",
+            "Tokens processed, by type (input/output).",
+            &r.tokens,
+        );
+        write_counter(
+            &mut out,
+            "claude_proxy_tagged_requests_total",
+            "Completed requests, by client tag.",
+            &r.tagged_requests,
+        );
+        write_counter(
+            &mut out,
+            "claude_proxy_tagged_tokens_total",
+            "Tokens processed, by client tag and type (input/output).",
+            &r.tagged_tokens,
+        );
+        write_counter(
+            &mut out,
+            "claude_proxy_response_cache_hits_total",
+            "Requests answered from the response cache.",
+            &r.cache_hits,
+        );
+        write_counter(
+            &mut out,
+            "claude_proxy_reconnect_replays_total",
+            "Re-sent streams replayed the response they missed.",
+            &r.reconnect_replays,
+        );
+        write_counter(
+            &mut out,
+            "claude_proxy_retries_total",
+            "Retries after transient upstream errors.",
+            &r.retries,
+        );
+        write_counter(
+            &mut out,
+            "claude_proxy_retry_budget_exhausted_total",
+            "Retries refused because the session's retry budget was spent.",
+            &r.budget_exhausted,
+        );
+        write_counter(
+            &mut out,
+            "claude_proxy_upstream_errors_total",
+            "Upstream error responses and network failures.",
+            &r.upstream_errors,
+        );
+        out
+    }
+
+    fn with(&self, f: impl FnOnce(&mut Registry)) {
+        if let Ok(mut registry) = self.inner.lock() {
+            f(&mut registry);
+        }
+    }
+}
+
+fn labels(provider: &str, model: &str, status: Option<&str>) -> Labels {
+    let mut labels = vec![
+        ("provider", provider.to_string()),
+        ("model", model.to_string()),
+    ];
+    if let Some(status) = status {
+        labels.push(("status", status.to_string()));
+    }
+    labels
+}
+
+fn label<'a>(labels: &'a Labels, name: &str) -> Option<&'a str> {
+    labels
+        .iter()
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.as_str())
+}
+
+fn totals_for<'a>(
+    totals: &'a mut BTreeMap<(String, String), ModelTotals>,
+    labels: &Labels,
+) -> &'a mut ModelTotals {
+    let provider = label(labels, "provider").unwrap_or_default().to_string();
+    let model = label(labels, "model").unwrap_or_default().to_string();
+    totals
+        .entry((provider.clone(), model.clone()))
+        .or_insert_with(|| ModelTotals {
+            provider,
+            model,
+            ..ModelTotals::default()
+        })
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, values: &BTreeMap<Labels, u64>) {
+    header(out, name, help, "counter");
+    for (labels, value) in values {
+        let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+    }
+}
+
+/// `{a="x",b="y"}`, with an optional trailing histogram `le` label.
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+        .collect();
+    if let Some(le) = le {
+        parts.push(format!("le=\"{le}\""));
+    }
+    format!("{{{}}}", parts.join(","))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.record_request("groq", "llama", 200, Duration::from_millis(300));
+        metrics.record_request("groq", "llama", 429, Duration::from_millis(50));
+        metrics.record_network_error("groq", "llama");
+        metrics.record_tokens("groq", "llama", 12, 7);
+        metrics.record_retry("groq");
+        metrics.record_budget_exhausted("groq");
+        metrics.record_tagged("groq", "llama", &["project=a".to_string()], 12, 7);
+        metrics.record_reconnect_replay("groq", "llama");
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "claude_proxy_requests_total{provider=\"groq\",model=\"llama\",status=\"200\"} 1"
+        ));
+        assert!(text.contains(
+            "claude_proxy_request_duration_seconds_bucket{provider=\"groq\",model=\"llama\",le=\"0.1\"} 1"
+        ));
+        assert!(text.contains(
+            "claude_proxy_request_duration_seconds_count{provider=\"groq\",model=\"llama\"} 2"
+        ));
+        assert!(text.contains(
+            "claude_proxy_tokens_total{provider=\"groq\",model=\"llama\",type=\"output\"} 7"
+        ));
+        assert!(text.contains(
+            "claude_proxy_tagged_tokens_total{provider=\"groq\",model=\"llama\",tag=\"project=a\",type=\"input\"} 12"
+        ));
+        assert!(text.contains("claude_proxy_retries_total{provider=\"groq\"} 1"));
+        assert!(text
+            .contains("claude_proxy_reconnect_replays_total{provider=\"groq\",model=\"llama\"} 1"));
+        assert!(text.contains("claude_proxy_retry_budget_exhausted_total{provider=\"groq\"} 1"));
+        assert!(text.contains(
+            "claude_proxy_upstream_errors_total{provider=\"groq\",model=\"llama\",status=\"network\"} 1"
+        ));
+        assert!(text.contains("# TYPE claude_proxy_request_duration_seconds histogram"));
+    }
+
+    #[test]
+    fn test_totals() {
+        let metrics = Metrics::new();
+        metrics.record_request("groq", "llama", 200, Duration::from_millis(300));
+        metrics.record_request("groq", "llama", 429, Duration::from_millis(50));
+        metrics.record_network_error("groq", "llama");
+        metrics.record_tokens("groq", "llama", 12, 7);
+        metrics.record_request("openai", "gpt-4o", 200, Duration::from_millis(300));
+
+        assert_eq!(
+            metrics.totals(),
+            [
+                ModelTotals {
+                    provider: "groq".to_string(),
+                    model: "llama".to_string(),
+                    requests: 3,
+                    errors: 2,
+                    input_tokens: 12,
+                    output_tokens: 7,
+                },
+                ModelTotals {
+                    provider: "openai".to_string(),
+                    model: "gpt-4o".to_string(),
+                    requests: 1,
+                    ..ModelTotals::default()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Upgrading config files written for older releases.
+//!
+//! A config file says which shape it was written in with a top-level
+//! `version` (files from before versioning have none, and count as version
+//! 1). [`upgrade`] rewrites an older file's table into the current shape
+//! before it is deserialized, one version step at a time, and says what it
+//! changed so the user can bring the file itself up to date.
+//!
+//! Some changes need no step: a `[models]` entry that is just a backend model
+//! name is still accepted beside the tables that route to a provider.
+
+use crate::error::{ProxyError, Result};
+
+/// The config shape this release reads.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Upgrades from version `n` to `n + 1`, indexed from version 1.
+const STEPS: [fn(&mut toml::Table, &mut Vec<String>); 1] = [v1_to_v2];
+
+/// Bring `table` to [`CURRENT_VERSION`], returning a description of each
+/// change made. A current file comes back untouched with no changes.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if `version` isn't a positive integer, or
+/// names a version newer than this release reads.
+pub fn upgrade(table: &mut toml::Table) -> Result<Vec<String>> {
+    let version = match table.get("version") {
+        None => 1,
+        Some(value) => value
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v > 0)
+            .ok_or_else(|| {
+                ProxyError::config(format!("version must be a positive integer, not {value}"))
+            })?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(ProxyError::config(format!(
+            "Config version {version} is newer than this claude-proxy reads \
+             ({CURRENT_VERSION}); upgrade claude-proxy"
+        )));
+    }
+
+    let mut changes = Vec::new();
+    for step in &STEPS[version as usize - 1..] {
+        step(table, &mut changes);
+    }
+    table.insert("version".to_string(), i64::from(CURRENT_VERSION).into());
+    Ok(changes)
+}
+
+/// `context_overflow = "reject" | "truncate"` in a `[models]` entry became
+/// `context_strategy = "reject" | "truncate-oldest"`.
+fn v1_to_v2(table: &mut toml::Table, changes: &mut Vec<String>) {
+    let Some(models) = table.get_mut("models").and_then(toml::Value::as_table_mut) else {
+        return;
+    };
+    for (claude_model, route) in models.iter_mut() {
+        let Some(route) = route.as_table_mut() else {
+            continue;
+        };
+        let Some(overflow) = route.remove("context_overflow") else {
+            continue;
+        };
+        let strategy = match overflow.as_str() {
+            Some("truncate") => "truncate-oldest".into(),
+            _ => overflow,
+        };
+        changes.push(format!(
+            "[models] \"{claude_model}\": context_overflow is now context_strategy = {strategy}"
+        ));
+        route.entry("context_strategy").or_insert(strategy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> toml::Table {
+        toml.parse().unwrap()
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut old = table(
+            r#"
+[provider]
+name = "groq"
+
+[models]
+"claude-3-5-haiku-20241022" = { model = "llama", context_overflow = "truncate" }
+"claude-sonnet-4-20250514" = { model = "kimi", context_overflow = "reject" }
+"claude-opus-4-20250514" = "kimi"
+"#,
+        );
+        let changes = upgrade(&mut old).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(
+            changes.iter().any(|c| c.contains("truncate-oldest")),
+            "{changes:?}"
+        );
+        assert_eq!(old["version"].as_integer(), Some(2));
+        let models = old["models"].as_table().unwrap();
+        let haiku = models["claude-3-5-haiku-20241022"].as_table().unwrap();
+        assert_eq!(haiku["context_strategy"].as_str(), Some("truncate-oldest"));
+        assert!(!haiku.contains_key("context_overflow"));
+        assert_eq!(models["claude-opus-4-20250514"].as_str(), Some("kimi"));
+
+        // A current file is left as it is
+        let mut current = old.clone();
+        assert!(upgrade(&mut current).unwrap().is_empty());
+        assert_eq!(current, old);
+
+        // A file from a newer release, or a nonsense version, is refused
+        for version in ["3", "0", "\"2\""] {
+            let mut newer = table(&format!("version = {version}"));
+            assert!(upgrade(&mut newer).is_err(), "version = {version}");
+        }
+    }
+}
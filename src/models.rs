@@ -1,12 +1,17 @@
 //! Tools for discovering and selecting models.
 //!
 //! Provides utilities to list available models from an upstream provider,
-//! as well as the known Claude models that Claude Code expects.
+//! as well as the known Claude models that Claude Code expects. [`report`]
+//! combines both with the `[models]` table for `claude-proxy models`.
 
 use crate::config::ProxyConfig;
 use crate::error::{ProxyError, Result};
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::oauth::TokenCache;
+use crate::providers::ApiFormat;
+use crate::proxy::with_headers;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
 
 /// An object representing an OpenAI-compatible model from a `/models` endpoint.
 #[derive(Debug, Deserialize)]
@@ -32,6 +37,19 @@ pub struct AnthropicModel {
     pub display_name: Option<String>,
 }
 
+/// The response from a Gemini `models.list` call.
+#[derive(Debug, Deserialize)]
+pub struct GeminiModelsResponse {
+    #[serde(default)]
+    pub models: Vec<GeminiModel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GeminiModel {
+    /// `models/<id>`.
+    pub name: String,
+}
+
 /// The response from an Anthropic `/v1/models` endpoint.
 #[derive(Debug, Deserialize)]
 pub struct AnthropicModelsResponse {
@@ -40,19 +58,57 @@ pub struct AnthropicModelsResponse {
 
 /// Fetch the list of available models from the configured upstream provider.
 ///
+/// Bedrock lists models on its control-plane API rather than the runtime
+/// endpoint requests go to, so it reports none.
+///
 /// # Errors
 /// Returns `ProxyError::Provider` if the request fails or the response cannot be parsed.
 pub async fn fetch_provider_models(
     config: &ProxyConfig,
     client: &reqwest::Client,
 ) -> Result<Vec<String>> {
-    let api_key = config.resolve_api_key()?;
+    if config.api_format() == ApiFormat::Bedrock {
+        return Ok(Vec::new());
+    }
+    // An OAuth provider's token stands in for its key
+    let api_key = match &config.provider.oauth {
+        Some(oauth) => {
+            TokenCache::new()
+                .token(client, &config.provider.name, oauth)
+                .await?
+        }
+        None => config.resolve_api_key()?,
+    };
     let base_url = config.effective_base_url()?;
 
-    if config.is_anthropic_format() {
+    if config.api_format() == ApiFormat::Gemini {
+        let url = format!("{}/models", base_url.trim_end_matches('/'));
+        let response = with_headers(client.get(&url), &config.provider.headers)
+            .header("x-goog-api-key", api_key)
+            .send()
+            .await
+            .map_err(|e| ProxyError::provider(format!("Failed to fetch Gemini models: {e}")))?;
+
+        let status = response.status().as_u16();
+        if status >= 400 {
+            let body = response.text().await.unwrap_or_default();
+            return Err(ProxyError::provider(format!(
+                "Gemini API returned status {status} when fetching models: {body}"
+            )));
+        }
+
+        let parsed: GeminiModelsResponse = response.json().await.map_err(|e| {
+            ProxyError::provider(format!("Failed to parse Gemini models response: {e}"))
+        })?;
+
+        Ok(parsed
+            .models
+            .into_iter()
+            .map(|m| m.name.trim_start_matches("models/").to_string())
+            .collect())
+    } else if config.is_anthropic_format() {
         let url = format!("{}/v1/models", base_url.trim_end_matches('/'));
-        let response = client
-            .get(&url)
+        let response = with_headers(client.get(&url), &config.provider.headers)
             .header("x-api-key", api_key)
             .header("anthropic-version", "2023-06-01")
             .send()
@@ -74,9 +130,11 @@ pub async fn fetch_provider_models(
         Ok(parsed.data.into_iter().map(|m| m.id).collect())
     } else {
         let url = format!("{}/models", base_url.trim_end_matches('/'));
-        let response = client
-            .get(&url)
-            .header("Authorization", format!("Bearer {api_key}"))
+        let mut request = with_headers(client.get(&url), &config.provider.headers);
+        if !api_key.is_empty() {
+            request = request.header("Authorization", format!("Bearer {api_key}"));
+        }
+        let response = request
             .send()
             .await
             .map_err(|e| ProxyError::provider(format!("Failed to fetch models: {e}")))?;
@@ -136,6 +194,9 @@ pub async fn fetch_anthropic_models(
 #[must_use]
 pub fn known_claude_models() -> Vec<&'static str> {
     vec![
+        "claude-sonnet-4-20250514",
+        "claude-opus-4-20250514",
+        "claude-haiku-4-5-20251001",
         "claude-3-7-sonnet-20250219",
         "claude-3-5-sonnet-20241022",
         "claude-3-5-sonnet-20240620",
@@ -158,3 +219,173 @@ pub fn default_model_mapping(default_backend_model: &str) -> HashMap<String, Str
     }
     map
 }
+
+/// What one provider offers, as listed by [`fetch_provider_models`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderModels {
+    pub provider: String,
+    /// Sorted; empty when the provider couldn't be asked or lists none.
+    pub models: Vec<String>,
+    /// Why the list couldn't be fetched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One `[models]` entry and where it routes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MappingRow {
+    pub claude_model: String,
+    pub provider: String,
+    pub model: String,
+    /// Whether the provider lists the model; `None` when its list is unknown.
+    pub offered: Option<bool>,
+}
+
+/// The report printed by `claude-proxy models`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelsReport {
+    /// The primary provider and every provider a mapping routes to.
+    pub providers: Vec<ProviderModels>,
+    /// Sorted by Claude model name.
+    pub mappings: Vec<MappingRow>,
+    /// Known Claude models with no `[models]` entry. Claude Code asking for one
+    /// sends the name to the primary provider unchanged.
+    pub unmapped: Vec<String>,
+}
+
+/// List the models of every provider the config routes to, alongside the
+/// mapping table and the Claude models left unmapped.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if a mapping names an undeclared provider.
+/// A provider whose models can't be listed is reported, not an error.
+pub async fn report(config: &ProxyConfig, client: &reqwest::Client) -> Result<ModelsReport> {
+    let mut mappings = Vec::with_capacity(config.models.len());
+    for claude_model in config.models.keys() {
+        let route = config.route(claude_model)?;
+        mappings.push(MappingRow {
+            claude_model: claude_model.clone(),
+            provider: route.provider.name.clone(),
+            model: route.model,
+            offered: None,
+        });
+    }
+    mappings.sort_by(|a, b| a.claude_model.cmp(&b.claude_model));
+
+    let mut names = vec![config.provider.name.clone()];
+    for row in &mappings {
+        if !names.contains(&row.provider) {
+            names.push(row.provider.clone());
+        }
+    }
+    let mut providers = Vec::with_capacity(names.len());
+    for name in names {
+        let Some(provider) = config.named_provider(&name) else {
+            continue;
+        };
+        let provider_config = ProxyConfig {
+            provider: provider.clone(),
+            ..config.clone()
+        };
+        providers.push(
+            match fetch_provider_models(&provider_config, client).await {
+                Ok(mut models) => {
+                    models.sort();
+                    ProviderModels {
+                        provider: name,
+                        models,
+                        error: None,
+                    }
+                }
+                Err(e) => ProviderModels {
+                    provider: name,
+                    models: Vec::new(),
+                    error: Some(e.to_string()),
+                },
+            },
+        );
+    }
+
+    let listed: BTreeMap<&str, &[String]> = providers
+        .iter()
+        .filter(|p| !p.models.is_empty())
+        .map(|p| (p.provider.as_str(), p.models.as_slice()))
+        .collect();
+    for row in &mut mappings {
+        row.offered = listed
+            .get(row.provider.as_str())
+            .map(|models| models.contains(&row.model));
+    }
+
+    let unmapped = known_claude_models()
+        .into_iter()
+        .filter(|model| !config.models.contains_key(*model))
+        .map(str::to_string)
+        .collect();
+    Ok(ModelsReport {
+        providers,
+        mappings,
+        unmapped,
+    })
+}
+
+/// The report as plain text.
+#[must_use]
+pub fn render(report: &ModelsReport) -> String {
+    let mut out = String::new();
+    for provider in &report.providers {
+        match provider.error {
+            Some(ref error) => {
+                let _ = writeln!(out, "Couldn't list {}'s models: {error}", provider.provider);
+            }
+            None if provider.models.is_empty() => {
+                let _ = writeln!(out, "{} lists no models", provider.provider);
+            }
+            None => {
+                let _ = writeln!(
+                    out,
+                    "Models on {} ({}):",
+                    provider.provider,
+                    provider.models.len()
+                );
+                for model in &provider.models {
+                    let _ = writeln!(out, "  {model}");
+                }
+            }
+        }
+        out.push('\n');
+    }
+
+    if report.mappings.is_empty() {
+        out.push_str("No [models] mappings\n");
+    } else {
+        let _ = writeln!(
+            out,
+            "{:<32} {:<16} Backend model",
+            "Claude model", "Provider"
+        );
+        for row in &report.mappings {
+            let note = if row.offered == Some(false) {
+                "  (not listed by the provider)"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                out,
+                "{:<32} {:<16} {}{note}",
+                row.claude_model, row.provider, row.model
+            );
+        }
+    }
+
+    if !report.unmapped.is_empty() {
+        let _ = writeln!(
+            out,
+            "\nNo mapping for these Claude Code models (sent to the provider as named):"
+        );
+        for model in &report.unmapped {
+            let _ = writeln!(out, "  {model}");
+        }
+    }
+    out
+}
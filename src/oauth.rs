@@ -0,0 +1,209 @@
+//! Short-lived bearer tokens from an OAuth 2.0 token endpoint.
+//!
+//! A provider with a `[provider.oauth]` table authenticates with access tokens
+//! fetched by the client-credentials grant: the proxy POSTs its `client_id`
+//! and the secret from `client_secret_env` (plus any `scope` and `audience`)
+//! to `token_url`, and sends the token it gets as `Authorization: Bearer`.
+//! Tokens are cached per provider and fetched again [`REFRESH_MARGIN`] before
+//! they expire, so a request never goes out with one about to lapse. A 401
+//! from the provider drops the cached token, and the request is sent once
+//! more with a new one.
+
+use crate::config::OAuthConfig;
+use crate::error::{ProxyError, Result};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// How long before its expiry a token is replaced.
+pub const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// How long a token is trusted when the endpoint doesn't say (`expires_in`).
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    access_token: String,
+    expires_at: Instant,
+}
+
+/// Each provider's current token. Held across the fetch, so concurrent
+/// requests wait for one refresh rather than each making their own.
+#[derive(Debug, Clone, Default)]
+pub struct TokenCache(Arc<Mutex<HashMap<String, Token>>>);
+
+impl TokenCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token for `provider`, fetched from `oauth.token_url` unless the
+    /// cached one is good for longer than [`REFRESH_MARGIN`].
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Config` if the client secret isn't set or the
+    /// endpoint refuses the client, and `ProxyError::Provider` if it can't be
+    /// reached or answers with something other than a token.
+    pub async fn token(
+        &self,
+        client: &reqwest::Client,
+        provider: &str,
+        oauth: &OAuthConfig,
+    ) -> Result<String> {
+        let key = cache_key(provider, oauth);
+        let mut tokens = self.0.lock().await;
+        if let Some(token) = tokens.get(&key) {
+            if token.expires_at > Instant::now() + REFRESH_MARGIN {
+                return Ok(token.access_token.clone());
+            }
+        }
+        let token = fetch(client, provider, oauth).await?;
+        let access_token = token.access_token.clone();
+        tokens.insert(key, token);
+        Ok(access_token)
+    }
+
+    /// Drop `provider`'s cached token, e.g. after the provider refused it.
+    pub async fn invalidate(&self, provider: &str, oauth: &OAuthConfig) {
+        self.0.lock().await.remove(&cache_key(provider, oauth));
+    }
+}
+
+/// Tokens are kept per provider and grant, so a reloaded config with another
+/// client doesn't reuse the old one's token.
+fn cache_key(provider: &str, oauth: &OAuthConfig) -> String {
+    format!("{provider}\n{}\n{}", oauth.token_url, oauth.client_id)
+}
+
+async fn fetch(client: &reqwest::Client, provider: &str, oauth: &OAuthConfig) -> Result<Token> {
+    let secret = std::env::var(&oauth.client_secret_env).map_err(|_| {
+        ProxyError::config(format!(
+            "Environment variable '{}' not set (client_secret_env of provider '{provider}')",
+            oauth.client_secret_env
+        ))
+    })?;
+    let mut form = vec![
+        ("grant_type", "client_credentials"),
+        ("client_id", oauth.client_id.as_str()),
+        ("client_secret", secret.as_str()),
+    ];
+    if let Some(scope) = &oauth.scope {
+        form.push(("scope", scope));
+    }
+    if let Some(audience) = &oauth.audience {
+        form.push(("audience", audience));
+    }
+    let requested_at = Instant::now();
+    let response = client
+        .post(&oauth.token_url)
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| {
+            ProxyError::provider(format!("Token request to {} failed: {e}", oauth.token_url))
+        })?;
+    let status = response.status().as_u16();
+    if status >= 400 {
+        let body = response.text().await.unwrap_or_default();
+        let message = format!(
+            "Token endpoint {} returned status {status} for provider '{provider}': {body}",
+            oauth.token_url
+        );
+        // A refused client won't be accepted on retry; a failing server may be
+        return Err(if status < 500 {
+            ProxyError::config(message)
+        } else {
+            ProxyError::provider(message)
+        });
+    }
+    let token: TokenResponse = response.json().await.map_err(|e| {
+        ProxyError::provider(format!(
+            "Invalid token response from {}: {e}",
+            oauth.token_url
+        ))
+    })?;
+    let lifetime = token
+        .expires_in
+        .map_or(DEFAULT_LIFETIME, Duration::from_secs);
+    Ok(Token {
+        access_token: token.access_token,
+        expires_at: requested_at + lifetime,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::{Form, Json, Router};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn test_token_refresh() {
+        std::env::set_var("CLAUDE_PROXY_TEST_OAUTH_SECRET", "s3cret");
+        let counter = Arc::new(AtomicU32::new(0));
+        let app = Router::new().route(
+            "/token",
+            post(move |Form(form): Form<HashMap<String, String>>| {
+                let counter = counter.clone();
+                async move {
+                    assert_eq!(form["grant_type"], "client_credentials");
+                    assert_eq!(form["client_secret"], "s3cret");
+                    assert_eq!(form["scope"], "inference");
+                    let n = counter.fetch_add(1, Ordering::SeqCst);
+                    // The first token is already inside the refresh margin
+                    let expires_in = if n == 0 { 30 } else { 3600 };
+                    Json(serde_json::json!({
+                        "access_token": format!("token-{n}"),
+                        "token_type": "Bearer",
+                        "expires_in": expires_in,
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let oauth = OAuthConfig {
+            token_url: format!("http://{addr}/token"),
+            client_id: "proxy".to_string(),
+            client_secret_env: "CLAUDE_PROXY_TEST_OAUTH_SECRET".to_string(),
+            scope: Some("inference".to_string()),
+            audience: None,
+        };
+        let client = reqwest::Client::new();
+        let cache = TokenCache::new();
+        let token = |cache: &TokenCache| {
+            let (cache, client, oauth) = (cache.clone(), client.clone(), oauth.clone());
+            async move { cache.token(&client, "gateway", &oauth).await.unwrap() }
+        };
+        assert_eq!(token(&cache).await, "token-0");
+        // About to expire, so it's replaced; the new one is reused
+        assert_eq!(token(&cache).await, "token-1");
+        assert_eq!(token(&cache).await, "token-1");
+        cache.invalidate("gateway", &oauth).await;
+        assert_eq!(token(&cache).await, "token-2");
+
+        let missing = OAuthConfig {
+            client_secret_env: "CLAUDE_PROXY_TEST_OAUTH_UNSET".to_string(),
+            ..oauth
+        };
+        let err = TokenCache::new()
+            .token(&client, "gateway", &missing)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProxyError::Config { .. }));
+    }
+}
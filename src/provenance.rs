@@ -0,0 +1,237 @@
+//! Marking responses with what produced them.
+//!
+//! A transcript of a session through the proxy says `claude-sonnet-4-...`
+//! throughout, whichever backend actually answered. With `[provenance]` the
+//! proxy says which: `header` adds an `x-claude-proxy-provenance` response
+//! header (`provider=groq; model=llama-3.3-70b-versatile; proxy=0.1.0;
+//! request_id=...`), and `footer` ends each response with a small text block
+//! holding the same fields as JSON inside an HTML comment, so they stay in the
+//! saved conversation without showing in rendered Markdown:
+//!
+//! ```text
+//! <!-- claude-proxy-provenance {"provider":"groq","model":"llama-3.3-70b-versatile","proxy":"0.1.0","request_id":"..."} -->
+//! ```
+//!
+//! The provider and model are the ones that served the request after any
+//! fallback. Requests passed through unchanged to an Anthropic-format provider
+//! get the header but no footer.
+
+use crate::log_context;
+use crate::proxy::{self, SseEvent, SseStream};
+use crate::state::AppState;
+use crate::translate::anthropic_types::{
+    Delta, MessagesResponse, ResponseContentBlock, StreamEvent,
+};
+
+use axum::extract::{Request, State};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Response header carrying the provenance fields.
+pub const HEADER: &str = "x-claude-proxy-provenance";
+
+/// What a footer starts with, for finding it in a transcript.
+pub const FOOTER_MARKER: &str = "<!-- claude-proxy-provenance ";
+
+/// Who answered one request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Provenance {
+    pub provider: String,
+    /// The backend model the provider was asked for.
+    pub model: String,
+    /// This proxy's version.
+    pub proxy: String,
+    pub request_id: String,
+}
+
+impl Provenance {
+    /// The provenance of the current request, once a provider serves it.
+    #[must_use]
+    pub fn current() -> Option<Self> {
+        let ctx = log_context::current()?;
+        Some(Self {
+            provider: ctx.provider?,
+            model: ctx.backend_model?,
+            proxy: env!("CARGO_PKG_VERSION").to_string(),
+            request_id: ctx.request_id,
+        })
+    }
+
+    /// The value of the [`HEADER`].
+    #[must_use]
+    pub fn header_value(&self) -> String {
+        format!(
+            "provider={}; model={}; proxy={}; request_id={}",
+            self.provider, self.model, self.proxy, self.request_id
+        )
+    }
+
+    /// The text of the footer block. It starts on a new paragraph, for
+    /// clients that join a response's text blocks.
+    #[must_use]
+    pub fn footer(&self) -> String {
+        let fields = serde_json::to_string(self).unwrap_or_default();
+        format!("\n\n{FOOTER_MARKER}{fields} -->")
+    }
+}
+
+/// Middleware adding the [`HEADER`] to responses a provider served, when
+/// `[provenance] header` is on. Runs inside the request's log context.
+pub async fn add_header(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    if !state.config.load().provenance.header {
+        return response;
+    }
+    if let Some(value) = Provenance::current()
+        .and_then(|provenance| HeaderValue::from_str(&provenance.header_value()).ok())
+    {
+        response.headers_mut().insert(HEADER, value);
+    }
+    response
+}
+
+/// End `resp` with the current request's footer block.
+pub fn stamp_response(resp: &mut MessagesResponse) {
+    if let Some(provenance) = Provenance::current() {
+        resp.content.push(ResponseContentBlock::Text {
+            text: provenance.footer(),
+        });
+    }
+}
+
+/// Add the current request's footer block to `stream`, after its last content
+/// block and before the `message_delta` ending the turn.
+#[must_use]
+pub fn stamp_stream(stream: SseStream) -> SseStream {
+    let Some(provenance) = Provenance::current() else {
+        return stream;
+    };
+    let mut footer = Some(provenance.footer());
+    let mut next_index = 0;
+    Box::pin(stream.flat_map(move |item| {
+        let mut events = Vec::new();
+        if let Ok(event) = &item {
+            match event.event.as_str() {
+                "content_block_start" => {
+                    if let Some(index) = block_index(event) {
+                        next_index = next_index.max(index + 1);
+                    }
+                }
+                "message_delta" if ends_turn(event) => {
+                    if let Some(text) = footer.take() {
+                        events.extend(footer_events(next_index, text).map(Ok));
+                    }
+                }
+                _ => {}
+            }
+        }
+        events.push(item);
+        stream::iter(events)
+    }))
+}
+
+fn block_index(event: &SseEvent) -> Option<usize> {
+    let data: serde_json::Value = serde_json::from_str(&event.data).ok()?;
+    usize::try_from(data["index"].as_u64()?).ok()
+}
+
+/// Whether a `message_delta` is the one with the stop reason, rather than an
+/// interim usage update.
+fn ends_turn(event: &SseEvent) -> bool {
+    serde_json::from_str::<serde_json::Value>(&event.data)
+        .is_ok_and(|data| !data["delta"]["stop_reason"].is_null())
+}
+
+/// A complete text block at `index` holding `text`.
+fn footer_events(index: usize, text: String) -> impl Iterator<Item = SseEvent> {
+    [
+        StreamEvent::ContentBlockStart {
+            index,
+            content_block: ResponseContentBlock::Text {
+                text: String::new(),
+            },
+        },
+        StreamEvent::ContentBlockDelta {
+            index,
+            delta: Delta::TextDelta { text },
+        },
+        StreamEvent::ContentBlockStop { index },
+    ]
+    .into_iter()
+    .filter_map(|event| proxy::to_sse_event(&event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_context::RequestContext;
+
+    fn event(event: &str, data: &serde_json::Value) -> SseEvent {
+        SseEvent {
+            event: event.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stamp_stream() {
+        let mut ctx = RequestContext::new();
+        ctx.request_id = "r1".to_string();
+        let events = log_context::scope(ctx, async {
+            log_context::set_provider("groq", "llama-3.3-70b-versatile");
+            let upstream: SseStream = Box::pin(stream::iter(
+                [
+                    event("message_start", &serde_json::json!({"type": "message_start"})),
+                    event(
+                        "content_block_start",
+                        &serde_json::json!({"type": "content_block_start", "index": 1}),
+                    ),
+                    event(
+                        "message_delta",
+                        &serde_json::json!({"type": "message_delta", "delta": {}}),
+                    ),
+                    event(
+                        "message_delta",
+                        &serde_json::json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}}),
+                    ),
+                    event("message_stop", &serde_json::json!({"type": "message_stop"})),
+                ]
+                .map(Ok),
+            ));
+            stamp_stream(upstream)
+                .map(|e| e.unwrap())
+                .collect::<Vec<_>>()
+                .await
+        })
+        .await;
+
+        let names: Vec<_> = events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "message_start",
+                "content_block_start",
+                "message_delta",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_eq!(block_index(&events[3]), Some(2));
+        assert!(events[4].data.contains(r#"\"provider\":\"groq\""#));
+        assert!(events[4].data.contains(r#"\"request_id\":\"r1\""#));
+
+        // Outside a request there is nothing to stamp
+        assert!(Provenance::current().is_none());
+    }
+}
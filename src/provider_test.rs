@@ -0,0 +1,311 @@
+//! Live checks of the configured provider (`claude-proxy test`).
+//!
+//! Where `--self-test` exercises the proxy against a mock upstream,
+//! [`run`] sends a few small requests to the real provider through
+//! [`proxy::proxy_non_streaming`] and [`proxy::proxy_streaming`], and reports
+//! each part of the setup separately: the API key, reaching the provider,
+//! translating its answer, streaming, and tool calling. A failure early on
+//! skips the checks that depend on it. Nothing is recorded, captured, cached
+//! or written to the usage log.
+
+use crate::config::ProxyConfig;
+use crate::error::{ProxyError, Result};
+use crate::logging::SharedLogger;
+use crate::proxy;
+use crate::self_test::NoStorage;
+use crate::state::AppState;
+use crate::translate::anthropic_types::{MessagesRequest, MessagesResponse, ResponseContentBlock};
+
+use futures::StreamExt;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Model requested when none is given and the config maps none.
+const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+
+/// How long each request may take.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The tool the model is asked to call.
+const TOOL_NAME: &str = "get_weather";
+
+/// Most characters of a reply to show.
+const MAX_REPLY_CHARS: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Not run because a check it depends on failed.
+    Skip,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "PASS",
+            Self::Fail => "FAIL",
+            Self::Skip => "SKIP",
+        })
+    }
+}
+
+/// The result of one check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, outcome: Outcome, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  {:<13} {}", self.outcome, self.name, self.detail)
+    }
+}
+
+/// Run the checks for `model` (else the first mapped model) against the
+/// provider it routes to.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the model routes to an undeclared
+/// provider; every other problem is reported as a failed check.
+pub async fn run(config: &ProxyConfig, model: Option<&str>) -> Result<Vec<Check>> {
+    let model = model
+        .or_else(|| config.models.keys().min().map(String::as_str))
+        .unwrap_or(DEFAULT_MODEL);
+    let route = config.route(model)?;
+    let provider = route.provider.name.clone();
+    let target = format!("{model} → {provider} {}", route.model);
+    let mut checks = Vec::new();
+
+    if let Err(e) = route.provider.resolve_api_key() {
+        checks.push(Check::new("auth", Outcome::Fail, e.to_string()));
+        skip(
+            &mut checks,
+            &["connectivity", "translation", "streaming", "tool calling"],
+        );
+        return Ok(checks);
+    }
+
+    let state = AppState::new(
+        test_config(config),
+        config
+            .outbound
+            .apply(reqwest::Client::builder().timeout(TIMEOUT))?
+            .build()
+            .map_err(ProxyError::Http)?,
+        SharedLogger::with_storage(Arc::new(NoStorage)),
+    );
+
+    let started = Instant::now();
+    match proxy::proxy_non_streaming(&request(model, false, false)?, &state).await {
+        Ok(response) => {
+            checks.push(Check::new("auth", Outcome::Pass, "key accepted"));
+            checks.push(Check::new(
+                "connectivity",
+                Outcome::Pass,
+                format!("{target} answered in {}ms", started.elapsed().as_millis()),
+            ));
+            checks.push(match reply_text(&response) {
+                text if text.trim().is_empty() => {
+                    Check::new("translation", Outcome::Fail, "the reply had no text")
+                }
+                text => Check::new(
+                    "translation",
+                    Outcome::Pass,
+                    format!("replied {}", quote(&text)),
+                ),
+            });
+        }
+        Err(ProxyError::Upstream { status, error }) if matches!(status, 401 | 403) => {
+            checks.push(Check::new(
+                "auth",
+                Outcome::Fail,
+                format!(
+                    "{provider} rejected the key ({status}): {}",
+                    error.error.message
+                ),
+            ));
+            checks.push(Check::new(
+                "connectivity",
+                Outcome::Pass,
+                format!("reached {provider}"),
+            ));
+            skip(&mut checks, &["translation", "streaming", "tool calling"]);
+            return Ok(checks);
+        }
+        Err(e) => {
+            checks.push(Check::new(
+                "auth",
+                Outcome::Skip,
+                "no answer to check the key with",
+            ));
+            checks.push(Check::new(
+                "connectivity",
+                Outcome::Fail,
+                format!("{target}: {e}"),
+            ));
+            skip(&mut checks, &["translation", "streaming", "tool calling"]);
+            return Ok(checks);
+        }
+    }
+
+    checks.push(
+        match streamed(&request(model, true, false)?, &state).await {
+            Ok((events, text)) if !text.trim().is_empty() => Check::new(
+                "streaming",
+                Outcome::Pass,
+                format!("{events} events, replied {}", quote(&text)),
+            ),
+            Ok(_) => Check::new("streaming", Outcome::Fail, "the stream had no text"),
+            Err(e) => Check::new("streaming", Outcome::Fail, e.to_string()),
+        },
+    );
+
+    checks.push(
+        match proxy::proxy_non_streaming(&request(model, false, true)?, &state).await {
+            Ok(response) => tool_check(&response),
+            Err(e) => Check::new("tool calling", Outcome::Fail, e.to_string()),
+        },
+    );
+    Ok(checks)
+}
+
+/// The config with everything that would keep or reuse test traffic off.
+pub(crate) fn test_config(config: &ProxyConfig) -> ProxyConfig {
+    let mut config = config.clone();
+    config.record.dir = None;
+    config.capture.dir = None;
+    config.audit.enabled = false;
+    config.response_cache.enabled = false;
+    config.journal.file = None;
+    config
+}
+
+fn skip(checks: &mut Vec<Check>, names: &[&'static str]) {
+    checks.extend(
+        names
+            .iter()
+            .map(|name| Check::new(name, Outcome::Skip, "skipped after an earlier failure")),
+    );
+}
+
+fn request(model: &str, stream: bool, tools: bool) -> Result<MessagesRequest> {
+    let mut request = serde_json::json!({
+        "model": model,
+        "max_tokens": 256,
+        "stream": stream,
+        "messages": [{"role": "user", "content": "Reply with a short greeting."}],
+    });
+    if tools {
+        request["messages"][0]["content"] =
+            "What's the weather in Paris right now? Use the tool.".into();
+        request["tools"] = serde_json::json!([{
+            "name": TOOL_NAME,
+            "description": "Get the current weather in a city.",
+            "input_schema": {
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"],
+            },
+        }]);
+        request["tool_choice"] = serde_json::json!({"type": "any"});
+    }
+    Ok(serde_json::from_value(request)?)
+}
+
+fn reply_text(response: &MessagesResponse) -> String {
+    response
+        .content
+        .iter()
+        .filter_map(|block| match block {
+            ResponseContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn tool_check(response: &MessagesResponse) -> Check {
+    let call = response.content.iter().find_map(|block| match block {
+        ResponseContentBlock::ToolUse { name, input, .. } => Some((name, input)),
+        _ => None,
+    });
+    match call {
+        Some((name, input)) if name == TOOL_NAME && input["city"].is_string() => Check::new(
+            "tool calling",
+            Outcome::Pass,
+            format!("called {name} with {input}"),
+        ),
+        Some((name, input)) => Check::new(
+            "tool calling",
+            Outcome::Fail,
+            format!("expected a {TOOL_NAME} call with a city, got {name} with {input}"),
+        ),
+        None => Check::new(
+            "tool calling",
+            Outcome::Fail,
+            format!(
+                "no tool call in the reply ({})",
+                quote(&reply_text(response))
+            ),
+        ),
+    }
+}
+
+/// Stream a request to the end, returning the event count and the text.
+async fn streamed(req: &MessagesRequest, state: &AppState) -> Result<(usize, String)> {
+    let mut stream = proxy::proxy_streaming(req, state).await?;
+    let mut events = 0;
+    let mut text = String::new();
+    let mut stopped = false;
+    while let Some(event) = stream.next().await {
+        let event = event?;
+        events += 1;
+        match event.event.as_str() {
+            "message_stop" => stopped = true,
+            "error" => {
+                return Err(ProxyError::provider(format!(
+                    "stream error: {}",
+                    event.data
+                )))
+            }
+            _ => {}
+        }
+        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&event.data) {
+            if let Some(delta) = data["delta"]["text"].as_str() {
+                text.push_str(delta);
+            }
+        }
+    }
+    if !stopped {
+        return Err(ProxyError::provider(format!(
+            "the stream ended after {events} events without message_stop"
+        )));
+    }
+    Ok((events, text))
+}
+
+/// A reply on one line, shortened for the report.
+fn quote(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() > MAX_REPLY_CHARS {
+        format!(
+            "{:?}",
+            text.chars().take(MAX_REPLY_CHARS).collect::<String>() + "…"
+        )
+    } else {
+        format!("{text:?}")
+    }
+}
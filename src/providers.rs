@@ -4,14 +4,67 @@
 //! for the API key. Users specify a provider name in their config and the preset
 //! fills in the details.
 
+/// Wire format spoken by an upstream provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ApiFormat {
+    /// `OpenAI` Chat Completions (translated).
+    OpenAI,
+    /// `OpenAI` Responses API (translated via the `OpenAI` types).
+    OpenAIResponses,
+    /// Anthropic Messages (passthrough).
+    Anthropic,
+    /// Cohere Chat v1 (translated via the `OpenAI` types).
+    Cohere,
+    /// AWS Bedrock Converse (translated via the `OpenAI` types, `SigV4`-signed).
+    Bedrock,
+    /// Google Gemini `generateContent`, on the Gemini API or Vertex AI
+    /// (translated via the `OpenAI` types).
+    Gemini,
+    /// Recorded responses served from disk (see [`crate::recording`]).
+    Replay,
+}
+
+impl ApiFormat {
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "openai" => Some(Self::OpenAI),
+            "openai-responses" => Some(Self::OpenAIResponses),
+            "anthropic" => Some(Self::Anthropic),
+            "cohere" => Some(Self::Cohere),
+            "bedrock" => Some(Self::Bedrock),
+            "gemini" => Some(Self::Gemini),
+            "replay" => Some(Self::Replay),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::OpenAI => "openai",
+            Self::OpenAIResponses => "openai-responses",
+            Self::Anthropic => "anthropic",
+            Self::Cohere => "cohere",
+            Self::Bedrock => "bedrock",
+            Self::Gemini => "gemini",
+            Self::Replay => "replay",
+        }
+    }
+}
+
 /// Built-in provider presets. Each preset defines the base URL and API format
 /// so users only need to specify a provider name in their config.
 #[derive(Debug, Clone)]
 pub struct ProviderPreset {
     pub name: &'static str,
+    /// `{region}` is filled in from the provider's AWS region.
     pub base_url: &'static str,
-    pub format: &'static str, // "openai" or "anthropic"
+    pub format: &'static str, // "openai", "anthropic", "cohere", "bedrock", "gemini" or "replay"
     pub default_api_key_env: &'static str,
+    /// Local servers that run without authentication by default.
+    pub api_key_optional: bool,
 }
 
 const PRESETS: &[ProviderPreset] = &[
@@ -20,48 +73,98 @@ const PRESETS: &[ProviderPreset] = &[
         base_url: "https://api.openai.com/v1",
         format: "openai",
         default_api_key_env: "OPENAI_API_KEY",
+        api_key_optional: false,
     },
     ProviderPreset {
         name: "openrouter",
         base_url: "https://openrouter.ai/api/v1",
         format: "openai",
         default_api_key_env: "OPENROUTER_API_KEY",
+        api_key_optional: false,
     },
     ProviderPreset {
         name: "fireworks",
         base_url: "https://api.fireworks.ai/inference/v1",
         format: "openai",
         default_api_key_env: "FIREWORKS_API_KEY",
+        api_key_optional: false,
     },
     ProviderPreset {
         name: "grok",
         base_url: "https://api.x.ai/v1",
         format: "openai",
         default_api_key_env: "XAI_API_KEY",
+        api_key_optional: false,
     },
     ProviderPreset {
         name: "together",
         base_url: "https://api.together.xyz/v1",
         format: "openai",
         default_api_key_env: "TOGETHER_API_KEY",
+        api_key_optional: false,
     },
     ProviderPreset {
         name: "groq",
         base_url: "https://api.groq.com/openai/v1",
         format: "openai",
         default_api_key_env: "GROQ_API_KEY",
+        api_key_optional: false,
     },
     ProviderPreset {
         name: "anthropic",
         base_url: "https://api.anthropic.com",
         format: "anthropic",
         default_api_key_env: "ANTHROPIC_API_KEY",
+        api_key_optional: false,
     },
     ProviderPreset {
         name: "deepseek",
         base_url: "https://api.deepseek.com/v1",
         format: "openai",
         default_api_key_env: "DEEPSEEK_API_KEY",
+        api_key_optional: false,
+    },
+    ProviderPreset {
+        name: "mistral",
+        base_url: "https://api.mistral.ai/v1",
+        format: "openai",
+        default_api_key_env: "MISTRAL_API_KEY",
+        api_key_optional: false,
+    },
+    ProviderPreset {
+        name: "cohere",
+        base_url: "https://api.cohere.com/v1",
+        format: "cohere",
+        default_api_key_env: "COHERE_API_KEY",
+        api_key_optional: false,
+    },
+    ProviderPreset {
+        name: "bedrock",
+        base_url: "https://bedrock-runtime.{region}.amazonaws.com",
+        format: "bedrock",
+        default_api_key_env: "",
+        api_key_optional: false,
+    },
+    ProviderPreset {
+        name: "gemini",
+        base_url: "https://generativelanguage.googleapis.com/v1beta",
+        format: "gemini",
+        default_api_key_env: "GEMINI_API_KEY",
+        api_key_optional: false,
+    },
+    ProviderPreset {
+        name: "ollama",
+        base_url: "http://localhost:11434/v1",
+        format: "openai",
+        default_api_key_env: "OLLAMA_API_KEY",
+        api_key_optional: true,
+    },
+    ProviderPreset {
+        name: "replay",
+        base_url: "recordings",
+        format: "replay",
+        default_api_key_env: "",
+        api_key_optional: false,
     },
 ];
 
@@ -95,10 +198,43 @@ mod tests {
         assert_eq!(preset.format, "anthropic");
     }
 
+    #[test]
+    fn test_cohere_is_cohere_format() {
+        let preset = ProviderPreset::from_name("cohere").unwrap();
+        assert_eq!(ApiFormat::from_name(preset.format), Some(ApiFormat::Cohere));
+    }
+
+    #[test]
+    fn test_openai_responses_format() {
+        assert_eq!(
+            ApiFormat::from_name("openai-responses"),
+            Some(ApiFormat::OpenAIResponses)
+        );
+        assert_eq!(ApiFormat::OpenAIResponses.as_str(), "openai-responses");
+    }
+
+    #[test]
+    fn test_bedrock_is_bedrock_format() {
+        let preset = ProviderPreset::from_name("bedrock").unwrap();
+        assert_eq!(
+            ApiFormat::from_name(preset.format),
+            Some(ApiFormat::Bedrock)
+        );
+    }
+
+    #[test]
+    fn test_gemini_is_gemini_format() {
+        let preset = ProviderPreset::from_name("gemini").unwrap();
+        assert_eq!(ApiFormat::from_name(preset.format), Some(ApiFormat::Gemini));
+    }
+
     #[test]
     fn test_all_others_are_openai_format() {
         for preset in ProviderPreset::all() {
-            if preset.name != "anthropic" {
+            if !matches!(
+                preset.name,
+                "anthropic" | "cohere" | "bedrock" | "gemini" | "replay"
+            ) {
                 assert_eq!(
                     preset.format, "openai",
                     "Provider {} should be openai format",
@@ -1,37 +1,99 @@
 //! Core proxy logic: forward requests to the configured provider, translating
 //! between Anthropic and `OpenAI` formats as needed.
 //!
-//! Supports non-streaming, streaming (SSE), and direct passthrough modes.
-//! Includes automatic retry with exponential backoff for transient errors.
+//! Supports non-streaming, streaming (SSE), and direct passthrough modes. Cohere,
+//! Bedrock, Gemini and `OpenAI` Responses upstreams are adapted to and from the
+//! `OpenAI` types on the way through, and replay providers answer from recorded
+//! exchanges.
+//! Includes automatic retry with exponential backoff for transient errors, and
+//! falls back along the configured provider chain when retries are exhausted.
+//! Both draw on the session's retry budget (see [`crate::budget`]).
 
-use crate::config::ProxyConfig;
-use crate::error::{ProxyError, Result};
-use crate::logging::SharedLogger;
-use crate::translate::anthropic_types::{ErrorResponse, MessagesRequest, MessagesResponse};
+use crate::audit::{translation_changes, AuditEntry, Change, ChangeKind};
+use crate::aws::{self, Credentials, EventStreamDecoder, SigningScope};
+use crate::capture::{Capture, Capturer};
+use crate::config::{
+    ContextStrategy, ModelMapping, ModelPrice, ModelRoute, OAuthConfig, ProviderConfig,
+    ProxyConfig, ResponseCacheConfig, RetryBudgetConfig, Route,
+};
+use crate::context_window::{self, prompt_tokens};
+use crate::error::{error_type_for_status, ProxyError, Result};
+use crate::images;
+use crate::json_stream;
+use crate::key_pool::KeyPool;
+use crate::log_context;
+use crate::logging::{LogLevel, SharedLogger};
+use crate::loop_guard::{self, Guard};
+use crate::metrics::Metrics;
+use crate::provenance;
+use crate::providers::ApiFormat;
+use crate::recording::{self, request_key, ChunkRecorder, Recorder, Recording};
+use crate::registry::ModelInfo;
+use crate::response_cache::{self, Reconnect, ResponseCache, StreamSlot};
+use crate::routing::{available_routes, COOLDOWN};
+use crate::state::AppState;
+use crate::storage::{Storage, UsageRecord};
+use crate::tokens::estimate_tokens;
+use crate::tool_policy;
+use crate::translate::anthropic_types::{
+    ErrorResponse, MessagesRequest, MessagesResponse, Metadata, StreamEvent, Usage,
+};
+use crate::translate::bedrock::{
+    converse_to_openai, openai_to_converse, ConverseResponse, ConverseStreamState,
+};
+use crate::translate::cohere::{
+    cohere_event_to_chunk, cohere_to_openai, openai_to_cohere, CohereChatResponse, CohereError,
+    CohereStreamEvent,
+};
+use crate::translate::gemini::{GeminiError, GenerateContentResponse};
+use crate::translate::gemini_backend::{gemini_to_openai, openai_to_gemini, GeminiStreamState};
+use crate::translate::openai_responses::{
+    effort_for_budget, openai_to_responses, responses_to_openai, ResponsesResponse,
+    ResponsesStreamEvent, ResponsesStreamState,
+};
 use crate::translate::openai_types::{
-    ChatCompletionChunk, ChatCompletionResponse, ChatErrorResponse,
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatErrorResponse,
 };
-use crate::translate::request::anthropic_to_openai;
-use crate::translate::response::{openai_error_to_anthropic, openai_to_anthropic};
-use crate::translate::streaming::StreamTranslator;
+use crate::translate::prompted_tools::{self, PromptedTools};
+use crate::translate::raw::RawObject;
+use crate::translate::request::{anthropic_to_openai_cached, wrap_system_prompt};
+use crate::translate::response::{openai_error_to_anthropic, openai_to_anthropic_with_options};
+use crate::translate::smoothing::Rechunker;
+use crate::translate::stream_check::StreamChecker;
+use crate::translate::streaming::{response_events, ResponseCollector, StreamTranslator};
+use crate::translate::{cache, documents, grok, mistral, stop_tokens};
+use crate::validation;
 
 use bytes::Bytes;
 use eventsource_stream::Eventsource;
 use futures::stream::{self, Stream};
-#[allow(unused_imports)]
 use futures::StreamExt;
+use std::collections::HashMap;
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const MAX_RETRIES: u32 = 2;
 const RETRYABLE_STATUSES: &[u16] = &[429, 500, 502, 503, 504];
+/// How long a health-check probe may take before it counts as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// First wait between retries; doubled after each one.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest `Retry-After` the proxy waits out before retrying a provider.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(20);
 
-/// Outcome of proxying a non-streaming request.
-pub enum ProxyResult {
-    /// Successful response, translated to Anthropic format.
-    Success(MessagesResponse),
-    /// Provider returned an error, translated to Anthropic error format.
-    Error(ErrorResponse, u16),
-}
+/// `anthropic-version` sent when a passthrough request didn't come with one.
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Request header naming a backend model to use instead of the `[models]`
+/// mapping, for that request only.
+pub const MODEL_OVERRIDE_HEADER: &str = "x-claude-proxy-model";
+
+/// Request header asking for provider-specific data (log probabilities,
+/// citations, cost) under a response's `extra` field, which Anthropic's format
+/// has no place for. Without it the field is left out.
+pub const EXTRA_HEADER: &str = "x-claude-proxy-extra";
 
 /// A single SSE event ready for emission.
 #[derive(Debug, Clone)]
@@ -40,71 +102,367 @@ pub struct SseEvent {
     pub data: String,
 }
 
+impl SseEvent {
+    /// The event as SSE text, for serving it without axum's `Sse`.
+    #[must_use]
+    pub fn framed(&self) -> String {
+        crate::translate::sse::frame(Some(&self.event), &self.data)
+    }
+}
+
 /// Stream of SSE events for a streaming response.
 pub type SseStream =
     Pin<Box<dyn Stream<Item = std::result::Result<SseEvent, std::io::Error>> + Send>>;
 
-/// Forward a non-streaming Anthropic request through the configured provider.
+/// Forward a non-streaming Anthropic request through the routed provider.
 ///
 /// Translates the request to `OpenAI` format, sends it, translates the response
-/// back to Anthropic format. Retries on transient errors (429, 5xx).
+/// back to Anthropic format. Retries on transient errors (429, 5xx); if they
+/// persist, moves on to the next provider in the `fallback` chain. A response
+/// that fails the model's `expect` checks is re-prompted (see [`validation`]).
+/// A conversation past `[loop_guard]`'s limit is nudged or stopped first (see
+/// [`loop_guard`]), and the state's [`hooks`](crate::hooks) see the request,
+/// the response and its tool calls.
 ///
 /// # Errors
 /// Returns `ProxyError::Provider` on network failures, `ProxyError::Translation`
 /// on parse errors.
 pub async fn proxy_non_streaming(
+    req: &MessagesRequest,
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    respond(req, &state.config.load(), state).await
+}
+
+/// [`proxy_non_streaming`] with the request's config snapshot, which every
+/// step of the request reads so a reload can't change it midway.
+pub(crate) async fn respond(
     req: &MessagesRequest,
     config: &ProxyConfig,
-    client: &reqwest::Client,
-    logger: &SharedLogger,
-) -> Result<ProxyResult> {
-    let api_key = config.resolve_api_key()?;
-    let base_url = config.effective_base_url()?;
-    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
-    let openai_req = anthropic_to_openai(req, &config.models);
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    let mut resp = answer(req, config, state).await?;
+    state.hooks.response(&mut resp);
+    if config.provenance.footer {
+        provenance::stamp_response(&mut resp);
+    }
+    if !wants_extra() {
+        resp.extra = None;
+    }
+    Ok(resp)
+}
 
-    logger.info("proxy", format!("POST {} model={}", url, openai_req.model));
+/// Whether the current request asked for [`EXTRA_HEADER`] data.
+fn wants_extra() -> bool {
+    log_context::current().is_some_and(|ctx| ctx.extra)
+}
 
-    let body = serde_json::to_vec(&openai_req)
-        .map_err(|e| ProxyError::translation(format!("Failed to serialize request: {e}")))?;
+/// `events` without the `extra` field of their `message_delta`.
+fn without_extra(events: SseStream) -> SseStream {
+    Box::pin(events.map(|event| {
+        event.map(|mut event| {
+            if event.event == "message_delta" && event.data.contains("\"extra\"") {
+                if let Ok(mut data) = serde_json::from_str::<serde_json::Value>(&event.data) {
+                    if data
+                        .as_object_mut()
+                        .and_then(|d| d.remove("extra"))
+                        .is_some()
+                    {
+                        event.data = data.to_string();
+                    }
+                }
+            }
+            event
+        })
+    }))
+}
 
-    let response = send_with_retry(client, &url, &api_key, &body, logger).await?;
+/// The response of [`proxy_non_streaming`], before the response hooks and the
+/// provenance footer.
+async fn answer(
+    req: &MessagesRequest,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    log_context::set_request(&req.model, user_id(req));
+    let hooked = state.hooks.request(req);
+    let req = hooked.as_ref().unwrap_or(req);
+    let nudged;
+    let req = match loop_guard::check(&config.loop_guard, req, &state.logger) {
+        Guard::Pass => req,
+        Guard::Nudge(next) => {
+            nudged = next;
+            &*nudged
+        }
+        Guard::Stop(resp) => return Ok(*resp),
+    };
+    let routes = translatable_routes(req, config, state)?;
+    let checks = expectations(config, &req.model);
+    let last = routes.len() - 1;
 
-    let status = response.status().as_u16();
-    let resp_body = response
-        .text()
-        .await
-        .map_err(|e| ProxyError::provider(format!("Failed to read response body: {e}")))?;
+    for (i, route) in routes.iter().enumerate() {
+        let provider = route.provider.name.as_str();
+        let result = forward_checked(req, route, checks, config, state).await;
+        match result {
+            Ok(resp) => {
+                state.health.record_success(provider);
+                return Ok(resp);
+            }
+            Err(e) if e.is_retryable() => {
+                note_failure(config, state, provider);
+                if i == last {
+                    return Err(e);
+                }
+                spend_retry(&config.retry_budget, state, provider, &e.to_string())?;
+                warn_fallback(state, provider, &e, &routes[i + 1].provider.name);
+            }
+            Err(e) => return Err(e),
+        }
+    }
 
-    logger.debug(
-        "proxy",
-        format!("Response status={} body_len={}", status, resp_body.len()),
-    );
+    unreachable!("route chain is never empty")
+}
 
-    if status >= 400 {
-        if let Ok(err) = serde_json::from_str::<ChatErrorResponse>(&resp_body) {
-            let anthropic_err = openai_error_to_anthropic(&err);
-            logger.warn("proxy", format!("Provider error: {}", err.error.message));
-            return Ok(ProxyResult::Error(anthropic_err, status));
+/// The model's `expect` settings, if it has any checks.
+fn expectations<'a>(config: &'a ProxyConfig, model: &str) -> Option<&'a ModelRoute> {
+    config
+        .models
+        .get(model)
+        .and_then(ModelMapping::settings)
+        .filter(|settings| !settings.expect.is_empty())
+}
+
+/// Whether a streamed request offers tools to a provider in its chain that has
+/// a tool policy, which needs the whole response to enforce.
+fn polices_tools(req: &MessagesRequest, config: &ProxyConfig) -> bool {
+    req.tools.as_ref().is_some_and(|tools| !tools.is_empty())
+        && config
+            .routes(&req.model)
+            .is_ok_and(|routes| routes.iter().any(|r| r.provider.has_tool_policy()))
+}
+
+/// Send a non-streaming request to one provider, holding the response to the
+/// model's `expect` checks and the provider's tool policy: tools it may not use
+/// are stripped, and a response calling one anyway is re-prompted up to
+/// [`tool_policy::MAX_REPROMPTS`] times, then has those calls cut.
+async fn forward_checked(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    checks: Option<&ModelRoute>,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    if !route.provider.has_tool_policy() {
+        return forward_once(req, route, checks, config, state).await;
+    }
+
+    let provider = route.provider;
+    let mut next = tool_policy::strip(req, provider).unwrap_or_else(|| req.clone());
+    for attempt in 0.. {
+        let resp = forward_once(&next, route, checks, config, state).await?;
+        let denied = tool_policy::denied_calls(&resp, provider).join(", ");
+        if denied.is_empty() {
+            return Ok(resp);
+        }
+        let reason = format!("called {denied}, which it may not use");
+        if attempt == tool_policy::MAX_REPROMPTS
+            || spend_retry(&config.retry_budget, state, &provider.name, &reason).is_err()
+        {
+            state.logger.warn(
+                "tool_policy",
+                format!(
+                    "Response from {} {reason}; removing the call",
+                    provider.name
+                ),
+            );
+            return Ok(tool_policy::remove_denied(resp, provider));
         }
+        state.logger.warn(
+            "tool_policy",
+            format!(
+                "Response from {} {reason}; re-prompting, attempt {}/{}",
+                provider.name,
+                attempt + 1,
+                tool_policy::MAX_REPROMPTS
+            ),
+        );
+        next = tool_policy::reprompt(&next, &resp, provider);
+    }
+    unreachable!("attempts are bounded by MAX_REPROMPTS")
+}
 
-        let anthropic_err = ErrorResponse::api_error(format!(
-            "Provider returned status {}: {}",
-            status,
-            truncate(&resp_body, 500)
-        ));
-        return Ok(ProxyResult::Error(anthropic_err, status));
+/// Send a non-streaming request to one provider, with the model's `expect`
+/// checks if it has any.
+async fn forward_once(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    checks: Option<&ModelRoute>,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    match checks {
+        Some(checks) => forward_validated(req, route, checks, config, state).await,
+        None => forward_non_streaming(req, route, config, state).await,
     }
+}
 
-    let openai_resp: ChatCompletionResponse = serde_json::from_str(&resp_body).map_err(|e| {
-        ProxyError::translation(format!(
-            "Failed to parse provider response: {}. Body: {}",
-            e,
-            truncate(&resp_body, 300)
-        ))
-    })?;
+/// Send a non-streaming request to one provider, re-prompting it up to
+/// `expect_retries` times while the response fails the `expect` checks. The
+/// last response is returned even if it still fails them.
+async fn forward_validated(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    checks: &ModelRoute,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    let logger = &state.logger;
+    let provider = route.provider.name.as_str();
+    let mut retry: Option<MessagesRequest> = None;
+    for attempt in 0.. {
+        let resp =
+            forward_non_streaming(retry.as_ref().unwrap_or(req), route, config, state).await?;
+        let Err(violation) = validation::check(&checks.expect, req, &resp) else {
+            return Ok(resp);
+        };
+        let expectation = violation.expectation.as_str();
+        if attempt == checks.expect_retries {
+            logger.warn(
+                "validation",
+                format!(
+                    "Response from {provider} failed {expectation} ({}) after {attempt} \
+                     re-prompt(s); returning it anyway",
+                    violation.reason
+                ),
+            );
+            return Ok(resp);
+        }
+        logger.warn(
+            "validation",
+            format!(
+                "Response from {provider} failed {expectation} ({}); re-prompting, attempt {}/{}",
+                violation.reason,
+                attempt + 1,
+                checks.expect_retries
+            ),
+        );
+        if spend_retry(&config.retry_budget, state, provider, &violation.reason).is_err() {
+            return Ok(resp);
+        }
+        retry = Some(validation::reprompt(req, &resp, &violation));
+    }
+    unreachable!("attempts are bounded by expect_retries")
+}
+
+/// Send a non-streaming request to one provider.
+async fn forward_non_streaming(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    let logger = &state.logger;
+    log_context::set_provider(&route.provider.name, &route.model);
+    let mut openai_req = translate_for_route(req, route, config, state).await?;
+    inline_images(&mut openai_req, route, config, state).await?;
+    let hook_headers = state
+        .hooks
+        .translated_request(&route.provider.name, &mut openai_req);
+    let tenant_key = log_context::current().and_then(|ctx| ctx.tenant_key);
+    let cache_key = response_cache::key(
+        &config.response_cache,
+        &route.provider.name,
+        tenant_key.as_deref(),
+        &openai_req,
+    );
+    if let Some(resp) =
+        cached_response(config, state, req, route, &openai_req, cache_key.as_deref())
+    {
+        return Ok(resp);
+    }
+    record_audit(state, req, route, &openai_req);
+    let mut capture = PendingCapture::start(state, req, route, &openai_req);
+    let tracker = RequestTracker::new(
+        state,
+        &route.provider.name,
+        &req.model,
+        &openai_req.model,
+        req.stream.unwrap_or(false),
+    );
+
+    let (format, status, body) = if route.provider.api_format() == ApiFormat::Replay {
+        let recording = replay(req, route, logger).await?;
+        (
+            recording.api_format(),
+            recording.status,
+            UpstreamBody::Text(recording.body.unwrap_or_default()),
+        )
+    } else {
+        send_non_streaming(
+            req,
+            route,
+            &openai_req,
+            &hook_headers,
+            config,
+            state,
+            capture.is_some(),
+        )
+        .await
+        .map_err(|e| tracker.network_error(e))?
+    };
+    let mut openai_resp = match body {
+        UpstreamBody::Parsed(openai_resp) => *openai_resp,
+        UpstreamBody::Text(resp_body) => {
+            if let Some(ref mut pending) = capture {
+                pending.capture.status = status;
+                pending.capture.openai_response = serde_json::from_str(&resp_body)
+                    .unwrap_or_else(|_| serde_json::Value::String(resp_body.clone()));
+            }
 
-    let anthropic_resp = openai_to_anthropic(&openai_resp, &req.model)?;
+            if status >= 400 {
+                tracker.failed(status);
+                let anthropic_err = upstream_error(status, &resp_body);
+                logger.warn(
+                    "proxy",
+                    format!("Provider error: {}", anthropic_err.error.message),
+                );
+                if let Some(ref mut pending) = capture {
+                    pending.capture.anthropic_response =
+                        serde_json::to_value(&anthropic_err).unwrap_or_default();
+                }
+                return Err(ProxyError::upstream(status, anthropic_err));
+            }
+            parse_upstream_response(format, &resp_body)?
+        }
+    };
+    if let Some(tools) = prompted_calls(req, route) {
+        tools.rewrite_response(&mut openai_resp);
+    }
+    let options = config.translation.response_options();
+    if openai_resp.choices.len() > 1 {
+        logger.warn(
+            "proxy",
+            format!(
+                "Provider returned {} choices; translating them as multiple_choices = {}",
+                openai_resp.choices.len(),
+                serde_json::to_string(&options.multiple_choices).unwrap_or_default()
+            ),
+        );
+    }
+    if options.strip_stop_tokens {
+        let text = openai_resp
+            .choices
+            .first()
+            .and_then(|c| c.message.content.as_deref())
+            .unwrap_or_default();
+        warn_leaked_stop_tokens(logger, &stop_tokens::trailing(text));
+    }
+
+    let anthropic_resp = openai_to_anthropic_with_options(&openai_resp, &req.model, &options)?;
+    if let Some(ref mut pending) = capture {
+        pending.capture.anthropic_response =
+            serde_json::to_value(&anthropic_resp).unwrap_or_default();
+    }
 
     logger.info(
         "proxy",
@@ -113,217 +471,2453 @@ pub async fn proxy_non_streaming(
             anthropic_resp.usage.input_tokens, anthropic_resp.usage.output_tokens
         ),
     );
+    tracker.completed(&anthropic_resp.usage);
+    if let Some(key) = cache_key {
+        store_response(
+            &state.response_cache,
+            &config.response_cache,
+            &key,
+            &anthropic_resp,
+            logger,
+        );
+    }
 
-    Ok(ProxyResult::Success(anthropic_resp))
+    Ok(anthropic_resp)
 }
 
-/// Forward a streaming Anthropic request, returning a stream of Anthropic SSE events.
-///
-/// The provider's `OpenAI`-format SSE chunks are translated into Anthropic-format
-/// events on the fly via [`StreamTranslator`].
-///
-/// # Errors
-/// Returns `ProxyError::Provider` on network failures, `ProxyError::Config` if
-/// the API key or base URL can't be resolved.
-pub async fn proxy_streaming(
-    req: &MessagesRequest,
+/// A fresh copy of the cached response to this request, if there is one.
+fn cached_response(
     config: &ProxyConfig,
-    client: &reqwest::Client,
+    state: &AppState,
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    openai_req: &ChatCompletionRequest,
+    key: Option<&str>,
+) -> Option<MessagesResponse> {
+    let mut resp = state.response_cache.get(&config.response_cache, key?)?;
+    state.logger.info(
+        "cache",
+        format!("Served {} from the response cache", openai_req.model),
+    );
+    state
+        .metrics
+        .record_cache_hit(&route.provider.name, &openai_req.model);
+    resp.id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+    resp.model.clone_from(&req.model);
+    Some(resp)
+}
+
+fn store_response(
+    cache: &ResponseCache,
+    config: &ResponseCacheConfig,
+    key: &str,
+    resp: &MessagesResponse,
     logger: &SharedLogger,
-) -> Result<SseStream> {
-    let api_key = config.resolve_api_key()?;
-    let base_url = config.effective_base_url()?;
-    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
-    let openai_req = anthropic_to_openai(req, &config.models);
+) {
+    if let Err(e) = cache.put(config, key, resp) {
+        logger.warn("cache", format!("Failed to persist cached response: {e}"));
+    }
+}
 
-    logger.info(
+/// A complete response sent as a stream of events.
+fn replay_response(resp: &MessagesResponse) -> SseStream {
+    let events: Vec<_> = response_events(resp)
+        .iter()
+        .filter_map(to_sse_event)
+        .map(Ok)
+        .collect();
+    Box::pin(stream::iter(events))
+}
+
+/// A provider's non-streaming response body.
+enum UpstreamBody {
+    /// The whole body: an error, or a response that is recorded or captured.
+    Text(String),
+    /// A successful response, parsed as it downloaded.
+    Parsed(Box<ChatCompletionResponse>),
+}
+
+/// Send a translated request upstream and read the response, recording the
+/// exchange if `[record]` is on. A successful response is parsed as it
+/// arrives, unless its text is needed for the recording or `keep_body`.
+async fn send_non_streaming(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    openai_req: &ChatCompletionRequest<'_>,
+    hook_headers: &HashMap<String, String>,
+    config: &ProxyConfig,
+    state: &AppState,
+    keep_body: bool,
+) -> Result<(ApiFormat, u16, UpstreamBody)> {
+    let logger = &state.logger;
+    let base_url = route.provider.effective_base_url()?;
+    let mut auth = UpstreamAuth::for_provider(route.provider, &base_url, &state.keys)?
+        .with_headers(hook_headers);
+    let format = route.provider.api_format();
+    let (url, body) = upstream_request(format, &base_url, openai_req, &route.provider.params)?;
+
+    logger.log_with_context(
+        LogLevel::Info,
         "proxy",
-        format!("POST {} model={} (streaming)", url, openai_req.model),
+        format!("POST {url}"),
+        serde_json::json!({ "upstream_model": openai_req.model, "streaming": false }),
     );
 
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {api_key}"))
-        .header("Content-Type", "application/json")
-        .json(&openai_req)
-        .send()
-        .await
-        .map_err(|e| ProxyError::provider(format!("Streaming request failed: {e}")))?;
+    state
+        .rate_limiter
+        .pace(route.provider, prompt_tokens(openai_req), logger)
+        .await?;
+    let _permit = state.limiter.acquire(route.provider, logger).await?;
+    let response = send_with_retry(
+        &config.retry_budget,
+        state,
+        route.provider,
+        &url,
+        &mut auth,
+        &body,
+    )
+    .await?;
+    keep_response_headers(route.provider, response.headers());
 
     let status = response.status().as_u16();
-
-    if status >= 400 {
-        let body = response.text().await.unwrap_or_default();
-        logger.warn(
+    if status < 400 && !keep_body && !state.recorder.is_enabled() {
+        let openai_resp = parse_upstream_stream(format, response.bytes_stream()).await?;
+        logger.debug(
             "proxy",
-            format!(
-                "Streaming error status={}: {}",
-                status,
-                truncate(&body, 300)
-            ),
+            format!("Response status={status} parsed as it arrived"),
         );
+        return Ok((format, status, UpstreamBody::Parsed(Box::new(openai_resp))));
+    }
+    let resp_body = response
+        .text()
+        .await
+        .map_err(|e| ProxyError::provider(format!("Failed to read response body: {e}")))?;
 
-        let error_event = if let Ok(err) = serde_json::from_str::<ChatErrorResponse>(&body) {
-            openai_error_to_anthropic(&err)
-        } else {
-            ErrorResponse::api_error(format!("Provider returned status {status}"))
-        };
-
-        let error_json = serde_json::to_string(&error_event).unwrap_or_default();
-        let event = SseEvent {
-            event: "error".to_string(),
-            data: error_json,
-        };
+    logger.debug(
+        "proxy",
+        format!("Response status={} body_len={}", status, resp_body.len()),
+    );
 
-        return Ok(Box::pin(stream::once(async move { Ok(event) })));
+    if state.recorder.is_enabled() {
+        let mut recording =
+            Recording::new(request_key(req), &route.provider.name, format, &url, &body);
+        recording.status = status;
+        recording.body = Some(resp_body.clone());
+        save_recording(&state.recorder, logger, &recording);
     }
 
-    let original_model = req.model.clone();
-    let logger_clone = logger.clone();
-    let byte_stream = response.bytes_stream();
-
-    let event_stream = sse_translate_stream(byte_stream, original_model, logger_clone);
+    Ok((format, status, UpstreamBody::Text(resp_body)))
+}
 
-    Ok(Box::pin(event_stream))
+/// Forward a streaming Anthropic request, returning a stream of Anthropic SSE events.
+///
+/// The provider's `OpenAI`-format SSE chunks are translated into Anthropic-format
+/// events on the fly via [`StreamTranslator`]. If the provider still fails with
+/// 429/5xx after retries, before streaming starts, the next provider in the
+/// `fallback` chain is tried. Models with `expect` checks, and requests offering
+/// tools to a provider with a tool policy, are asked non-streaming, so the
+/// whole response can be checked, and the result is replayed as a stream. The
+/// state's [`hooks`](crate::hooks) see the request, each event and each tool
+/// call.
+///
+/// # Errors
+/// Returns `ProxyError::Provider` on network failures, `ProxyError::Config` if
+/// the API key or base URL can't be resolved.
+pub async fn proxy_streaming(req: &MessagesRequest, state: &AppState) -> Result<SseStream> {
+    stream(req, &state.config.load(), state).await
 }
 
-/// Parse an `OpenAI` SSE byte stream and translate chunks into Anthropic SSE events.
-fn sse_translate_stream(
-    byte_stream: impl Stream<Item = std::result::Result<Bytes, reqwest::Error>> + Send + 'static,
-    model: String,
-    logger: SharedLogger,
-) -> impl Stream<Item = std::result::Result<SseEvent, std::io::Error>> + Send + 'static {
-    async_stream::stream! {
-        let mut translator = StreamTranslator::new(&model);
-        let event_stream = byte_stream.eventsource();
+/// [`proxy_streaming`] with the request's config snapshot (see [`respond`]).
+pub(crate) async fn stream(
+    req: &MessagesRequest,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<SseStream> {
+    let stream = state.hooks.stream(stream_routes(req, config, state).await?);
+    if wants_extra() {
+        return Ok(stream);
+    }
+    Ok(without_extra(stream))
+}
 
-        tokio::pin!(event_stream);
+/// The stream of [`proxy_streaming`], before the stream hooks.
+async fn stream_routes(
+    req: &MessagesRequest,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<SseStream> {
+    log_context::set_request(&req.model, user_id(req));
+    if expectations(config, &req.model).is_some() || polices_tools(req, config) {
+        let req = MessagesRequest {
+            stream: Some(false),
+            ..req.clone()
+        };
+        // The stream hooks see the replay, so the response hooks don't run
+        let mut resp = answer(&req, config, state).await?;
+        if config.provenance.footer {
+            provenance::stamp_response(&mut resp);
+        }
+        return Ok(replay_response(&resp));
+    }
+    let hooked = state.hooks.request(req);
+    let req = hooked.as_ref().unwrap_or(req);
+    let nudged;
+    let req = match loop_guard::check(&config.loop_guard, req, &state.logger) {
+        Guard::Pass => req,
+        Guard::Nudge(next) => {
+            nudged = next;
+            &*nudged
+        }
+        Guard::Stop(resp) => return Ok(replay_response(&resp)),
+    };
+    let routes = translatable_routes(req, config, state)?;
+    let last = routes.len() - 1;
 
-        while let Some(event_result) = event_stream.next().await {
-            let event = match event_result {
-                Ok(e) => e,
-                Err(e) => {
-                    logger.error("stream", format!("Byte stream error: {e}"));
-                    break;
+    for (i, route) in routes.iter().enumerate() {
+        let provider = route.provider.name.as_str();
+        match open_stream(req, route, config, state).await {
+            Ok(stream) => {
+                state.health.record_success(provider);
+                if config.provenance.footer {
+                    return Ok(provenance::stamp_stream(stream));
                 }
-            };
-
-            if event.data == "[DONE]" {
-                let events = translator.finish();
-                for e in events {
-                    if let Ok(json) = serde_json::to_string(&e) {
-                        yield Ok(SseEvent {
-                            event: e.event_name().to_string(),
-                            data: json,
-                        });
-                    }
+                return Ok(stream);
+            }
+            Err(e) if e.is_retryable() => {
+                note_failure(config, state, provider);
+                if i == last {
+                    return Err(e);
                 }
-                break;
+                spend_retry(&config.retry_budget, state, provider, &e.to_string())?;
+                warn_fallback(state, provider, &e, &routes[i + 1].provider.name);
             }
+            Err(e) => return Err(e),
+        }
+    }
 
-            let chunk: ChatCompletionChunk = match serde_json::from_str(&event.data) {
-                Ok(c) => c,
-                Err(e) => {
-                    logger.debug("stream", format!("Skipping unparseable chunk: {e}"));
-                    continue;
-                }
-            };
+    unreachable!("route chain is never empty")
+}
 
-            let events = translator.process_chunk(&chunk);
-            for e in events {
-                if let Ok(json) = serde_json::to_string(&e) {
-                    yield Ok(SseEvent {
-                        event: e.event_name().to_string(),
-                        data: json,
-                    });
+/// Open a stream against one provider. An upstream error status is returned as
+/// `ProxyError::Upstream`, before any event is sent, so the caller can fall back.
+async fn open_stream(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<SseStream> {
+    let logger = &state.logger;
+    log_context::set_provider(&route.provider.name, &route.model);
+    let deadline = config
+        .streaming
+        .turn_deadline()
+        .map(|limit| (tokio::time::Instant::now() + limit, limit));
+    let mut openai_req = translate_for_route(req, route, config, state).await?;
+    inline_images(&mut openai_req, route, config, state).await?;
+    let hook_headers = state
+        .hooks
+        .translated_request(&route.provider.name, &mut openai_req);
+    let ctx = log_context::current().unwrap_or_default();
+    let cache_key = response_cache::key(
+        &config.response_cache,
+        &route.provider.name,
+        ctx.tenant_key.as_deref(),
+        &openai_req,
+    );
+    if let Some(resp) =
+        cached_response(config, state, req, route, &openai_req, cache_key.as_deref())
+    {
+        return Ok(replay_response(&resp));
+    }
+    let reconnect_key = response_cache::reconnect_key(
+        &config.response_cache,
+        &route.provider.name,
+        ctx.tenant_key.as_deref(),
+        ctx.session_id.as_deref(),
+        &openai_req,
+    );
+    let slot = match reconnect_key {
+        Some(key) => {
+            match state
+                .response_cache
+                .reconnect(&config.response_cache, &key)
+                .await
+            {
+                Reconnect::Replay(resp) => {
+                    logger.info(
+                        "cache",
+                        format!(
+                            "Replayed {} to a re-sent stream from {}",
+                            resp.id, route.provider.name
+                        ),
+                    );
+                    RequestTracker::new(
+                        state,
+                        &route.provider.name,
+                        &req.model,
+                        &openai_req.model,
+                        true,
+                    )
+                    .replayed(&resp.usage);
+                    return Ok(replay_response(&resp));
                 }
+                Reconnect::Send(slot) => Some(slot),
             }
         }
+        None => None,
+    };
+    record_audit(state, req, route, &openai_req);
+    let mut capture = PendingCapture::start(state, req, route, &openai_req);
+    let tracker = RequestTracker::new(
+        state,
+        &route.provider.name,
+        &req.model,
+        &openai_req.model,
+        req.stream.unwrap_or(false),
+    );
 
-        // Ensure stream is closed even if [DONE] was missing
-        let final_events = translator.finish();
-        for event in final_events {
-            if let Ok(json) = serde_json::to_string(&event) {
-                yield Ok(SseEvent {
-                    event: event.event_name().to_string(),
-                    data: json,
-                });
+    let (format, byte_stream) = if route.provider.api_format() == ApiFormat::Replay {
+        let recording = replay(req, route, logger).await?;
+        if recording.status >= 400 {
+            tracker.failed(recording.status);
+            let body = recording.body.unwrap_or_default();
+            let error =
+                ProxyError::upstream(recording.status, upstream_error(recording.status, &body));
+            capture_error(capture.as_mut(), &error);
+            return Err(error);
+        }
+        let chunks = recording.chunks.clone().unwrap_or_default();
+        let byte_stream: ByteStream =
+            Box::pin(stream::iter(chunks.into_iter().map(|c| Ok(Bytes::from(c)))));
+        (recording.api_format(), byte_stream)
+    } else {
+        match send_streaming(req, route, &openai_req, &hook_headers, config, state).await {
+            Ok(byte_stream) => (route.provider.api_format(), byte_stream),
+            Err(e @ ProxyError::Upstream { status, .. }) => {
+                tracker.failed(status);
+                capture_error(capture.as_mut(), &e);
+                return Err(e);
             }
+            Err(e) => return Err(tracker.network_error(e)),
         }
+    };
 
-        logger.info("stream", "Stream completed");
-    }
-}
-
-/// Forward an Anthropic-format request directly (passthrough mode for Anthropic provider).
-///
-/// # Errors
-/// Returns `ProxyError::Provider` on network failures, `ProxyError::Config` if
-/// credentials can't be resolved.
-pub async fn proxy_passthrough(
-    body: Bytes,
-    headers: &reqwest::header::HeaderMap,
-    config: &ProxyConfig,
-    client: &reqwest::Client,
-    logger: &SharedLogger,
-) -> Result<(u16, reqwest::header::HeaderMap, Bytes)> {
-    let api_key = config.resolve_api_key()?;
-    let base_url = config.effective_base_url()?;
-    let url = format!("{}/v1/messages", base_url.trim_end_matches('/'));
+    let translator = StreamTranslator::new(&req.model)
+        .with_usage_updates(config.streaming.usage_update_interval)
+        .with_thinking_blocks(config.translation.thinking_blocks)
+        .with_think_tags(config.translation.think_tags)
+        .with_stop_token_stripping(config.translation.strip_stop_tokens)
+        .with_output_filters(&config.translation.output_filters());
 
-    logger.info("proxy", format!("Passthrough POST {url}"));
+    let chunks: ChunkStream = match format {
+        ApiFormat::Cohere => Box::pin(cohere_chunks(byte_stream, logger.clone())),
+        ApiFormat::Bedrock => Box::pin(bedrock_chunks(byte_stream, logger.clone())),
+        ApiFormat::Gemini => Box::pin(gemini_chunks(byte_stream, logger.clone())),
+        ApiFormat::OpenAIResponses => Box::pin(responses_chunks(byte_stream, logger.clone())),
+        _ => Box::pin(openai_chunks(byte_stream, logger.clone())),
+    };
+    let chunks: ChunkStream = match prompted_calls(req, route) {
+        Some(tools) => Box::pin(prompted_tool_chunks(chunks, tools)),
+        None => chunks,
+    };
 
-    let mut req_builder = client
-        .post(&url)
-        .header("x-api-key", &api_key)
-        .header("Content-Type", "application/json");
+    if let Some(ref mut pending) = capture {
+        pending.capture.status = 200;
+        pending.capture.openai_response = serde_json::Value::Array(Vec::new());
+        pending.capture.anthropic_response = serde_json::Value::Array(Vec::new());
+    }
+    let detached = slot.is_some();
+    let cache_fill = (cache_key.is_some() || detached).then(|| CacheFill {
+        cache: state.response_cache.clone(),
+        config: config.response_cache.clone(),
+        key: cache_key,
+        slot,
+        collector: ResponseCollector::new(),
+    });
+    let event_stream = sse_translate_stream(
+        chunks,
+        translator,
+        tracker,
+        capture,
+        cache_fill,
+        deadline,
+        config.streaming.rechunker(),
+        logger.clone(),
+    );
 
-    if let Some(version) = headers.get("anthropic-version") {
-        req_builder = req_builder.header("anthropic-version", version);
+    if detached {
+        return Ok(detach(Box::pin(event_stream), logger.clone()));
     }
+    Ok(Box::pin(event_stream))
+}
 
-    let response = req_builder
-        .body(body)
-        .send()
-        .await
-        .map_err(|e| ProxyError::provider(format!("Passthrough request failed: {e}")))?;
+/// Run `events` to the end on a task of its own, so a stream kept for
+/// reconnects is finished and kept even if its client goes away.
+fn detach(mut events: SseStream, logger: SharedLogger) -> SseStream {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(64);
+    let ctx = log_context::current().unwrap_or_default();
+    tokio::spawn(log_context::scope(ctx, async move {
+        let mut client = Some(sender);
+        while let Some(event) = events.next().await {
+            let Some(sender) = &client else {
+                continue;
+            };
+            if sender.send(event).await.is_err() {
+                logger.info(
+                    "stream",
+                    "Client went away; finishing the response in case it reconnects",
+                );
+                client = None;
+            }
+        }
+    }));
+    Box::pin(async_stream::stream! {
+        while let Some(event) = receiver.recv().await {
+            yield event;
+        }
+    })
+}
 
-    let status = response.status().as_u16();
-    let resp_headers = response.headers().clone();
-    let resp_body = response
-        .bytes()
-        .await
-        .map_err(|e| ProxyError::provider(format!("Failed to read passthrough response: {e}")))?;
+/// Open a streaming request upstream, retrying transient failures until the
+/// first chunk arrives (see [`send_stream_attempt`]). The response body is
+/// returned as raw bytes, teed into a recording if `[record]` is on.
+async fn send_streaming(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    openai_req: &ChatCompletionRequest<'_>,
+    hook_headers: &HashMap<String, String>,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<ByteStream> {
+    let logger = &state.logger;
+    let base_url = route.provider.effective_base_url()?;
+    let mut auth = UpstreamAuth::for_provider(route.provider, &base_url, &state.keys)?
+        .with_headers(hook_headers);
+    let format = route.provider.api_format();
+    let (url, body) = upstream_request(format, &base_url, openai_req, &route.provider.params)?;
 
-    logger.info(
+    logger.log_with_context(
+        LogLevel::Info,
         "proxy",
-        format!(
-            "Passthrough response: status={} len={}",
-            status,
-            resp_body.len()
-        ),
+        format!("POST {url}"),
+        serde_json::json!({ "upstream_model": openai_req.model, "streaming": true }),
     );
 
-    Ok((status, resp_headers, resp_body))
-}
+    // Recordings keep stream chunks as text, which Bedrock's binary framing isn't
+    let mut recording = (state.recorder.is_enabled() && format != ApiFormat::Bedrock)
+        .then(|| Recording::new(request_key(req), &route.provider.name, format, &url, &body));
 
-/// Send a POST request with automatic retry on transient failures.
-///
-/// Retries up to [`MAX_RETRIES`] times on status codes in [`RETRYABLE_STATUSES`],
-/// using exponential backoff starting at 500ms.
-async fn send_with_retry(
-    client: &reqwest::Client,
-    url: &str,
-    api_key: &str,
-    body: &[u8],
+    state
+        .rate_limiter
+        .pace(route.provider, prompt_tokens(openai_req), logger)
+        .await?;
+    let permit = state.limiter.acquire(route.provider, logger).await?;
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
+    let (status, byte_stream) = loop {
+        let (cause, delay, error) =
+            match send_stream_attempt(state, route.provider, &url, &mut auth, &body, backoff)
+                .await?
+            {
+                StreamAttempt::Open(status, byte_stream) => break (status, byte_stream),
+                StreamAttempt::Rotated => continue,
+                StreamAttempt::Failed {
+                    status,
+                    body,
+                    delay,
+                } => {
+                    logger.warn(
+                        "proxy",
+                        format!("Streaming error status={status}: {}", truncate(&body, 300)),
+                    );
+                    let error = ProxyError::upstream(status, upstream_error(status, &body));
+                    if delay.is_none() || attempt == MAX_RETRIES {
+                        if let Some(mut recording) = recording.take() {
+                            recording.status = status;
+                            recording.body = Some(body);
+                            save_recording(&state.recorder, logger, &recording);
+                        }
+                    }
+                    (format!("status {status}"), delay, error)
+                }
+                StreamAttempt::Broken {
+                    cause,
+                    delay,
+                    error,
+                } => (cause, delay, error),
+            };
+        match delay {
+            Some(delay) if attempt < MAX_RETRIES => {
+                note_retry(
+                    &config.retry_budget,
+                    state,
+                    &route.provider.name,
+                    attempt,
+                    &cause,
+                    delay,
+                )?;
+                tokio::time::sleep(delay).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            _ => return Err(error),
+        }
+    };
+
+    // The concurrency slot is held until the client has the whole stream
+    let byte_stream: ByteStream = Box::pin(byte_stream.map(move |chunk| {
+        let _ = &permit;
+        chunk
+    }));
+    Ok(match recording {
+        Some(mut recording) => {
+            recording.status = status;
+            Box::pin(record_stream(
+                byte_stream,
+                recording,
+                state.recorder.clone(),
+                logger.clone(),
+            ))
+        }
+        None => byte_stream,
+    })
+}
+
+/// The outcome of one attempt at opening an upstream stream. `delay` is the
+/// wait before a retry, `None` if the failure isn't worth retrying.
+enum StreamAttempt {
+    /// The stream is open and its first chunk arrived.
+    Open(u16, ByteStream),
+    /// An error status, with the response body.
+    Failed {
+        status: u16,
+        body: String,
+        delay: Option<Duration>,
+    },
+    /// The request or stream failed without a status.
+    Broken {
+        cause: String,
+        delay: Option<Duration>,
+        error: ProxyError,
+    },
+    /// The provider refused the key or token, and the request can be sent
+    /// again at once with another (see [`key_pool`](crate::key_pool) and
+    /// [`oauth`](crate::oauth)).
+    Rotated,
+}
+
+/// Send one streaming request and wait for its first chunk: a connection
+/// failure, a retryable status, or a stream that fails before its first chunk
+/// (or opens with an error event carrying a retryable status) can be retried,
+/// as nothing has reached the client yet.
+async fn send_stream_attempt(
+    state: &AppState,
+    provider: &ProviderConfig,
+    url: &str,
+    auth: &mut UpstreamAuth,
+    body: &[u8],
+    backoff: Duration,
+) -> Result<StreamAttempt> {
+    auth.refresh(state, provider).await?;
+    let result = auth
+        .apply(state.client.post(url), "POST", url, body)?
+        .header("Content-Type", "application/json")
+        .body(body.to_vec())
+        .send()
+        .await;
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            return Ok(StreamAttempt::Broken {
+                cause: format!("request failed ({e})"),
+                delay: (e.is_connect() || e.is_timeout()).then_some(backoff),
+                error: ProxyError::provider(format!("Streaming request failed: {e}")),
+            });
+        }
+    };
+    keep_response_headers(provider, response.headers());
+
+    let status = response.status().as_u16();
+    if auth.rotate_on(state, provider, status, response.headers())
+        || auth.renew_on(state, provider, status).await
+    {
+        return Ok(StreamAttempt::Rotated);
+    }
+    if status >= 400 {
+        let delay = retry_delay(response.headers(), backoff)
+            .filter(|_| RETRYABLE_STATUSES.contains(&status));
+        return Ok(StreamAttempt::Failed {
+            status,
+            body: response.text().await.unwrap_or_default(),
+            delay,
+        });
+    }
+
+    let mut byte_stream: ByteStream = Box::pin(
+        response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(std::io::Error::other)),
+    );
+    let first = match byte_stream.next().await {
+        Some(Ok(first)) => first,
+        Some(Err(e)) => {
+            return Ok(StreamAttempt::Broken {
+                cause: format!("stream failed before its first chunk ({e})"),
+                delay: Some(backoff),
+                error: ProxyError::provider(format!("Streaming response failed: {e}")),
+            });
+        }
+        None => Bytes::new(),
+    };
+    if let Some((status, body)) = stream_error(&first) {
+        return Ok(StreamAttempt::Failed {
+            status,
+            body,
+            delay: Some(backoff),
+        });
+    }
+    let byte_stream: ByteStream = Box::pin(stream::once(async { Ok(first) }).chain(byte_stream));
+    Ok(StreamAttempt::Open(status, byte_stream))
+}
+
+/// Headers describing a response body, which the proxy's own response sets.
+const BODY_HEADERS: [&str; 6] = [
+    "content-type",
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+    "connection",
+    "keep-alive",
+];
+
+/// Keep the headers of an upstream response that the provider's
+/// `response_headers` lets through, to be copied onto the client's response.
+/// Those of an earlier attempt, or another provider, are dropped.
+fn keep_response_headers(provider: &ProviderConfig, headers: &reqwest::header::HeaderMap) {
+    let kept = headers
+        .iter()
+        .filter(|(name, _)| {
+            !BODY_HEADERS.contains(&name.as_str()) && provider.passes_response_header(name.as_str())
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    log_context::set_response_headers(kept);
+}
+
+/// An error event opening an SSE stream with a retryable status, as its status
+/// and data. Some providers answer 200 and then report an overload in the
+/// first event (`data: {"error": {"code": 503, ...}}`).
+fn stream_error(chunk: &[u8]) -> Option<(u16, String)> {
+    let text = std::str::from_utf8(chunk).ok()?;
+    let data = text
+        .lines()
+        .find_map(|line| line.strip_prefix("data:"))?
+        .trim();
+    let event: serde_json::Value = serde_json::from_str(data).ok()?;
+    let error = event.get("error")?;
+    let status = ["code", "status"]
+        .iter()
+        .find_map(|key| error.get(key).and_then(serde_json::Value::as_u64))
+        .and_then(|status| u16::try_from(status).ok())?;
+    RETRYABLE_STATUSES
+        .contains(&status)
+        .then(|| (status, data.to_string()))
+}
+
+/// Pass a response stream through unchanged, saving its chunks as a recording.
+fn record_stream(
+    mut byte_stream: ByteStream,
+    recording: Recording,
+    recorder: Recorder,
+    logger: SharedLogger,
+) -> impl Stream<Item = std::result::Result<Bytes, std::io::Error>> + Send + 'static {
+    async_stream::stream! {
+        let mut pending = PendingRecording {
+            recording: Some(recording),
+            chunks: ChunkRecorder::default(),
+            recorder,
+            logger,
+        };
+        while let Some(item) = byte_stream.next().await {
+            if let Ok(ref bytes) = item {
+                pending.chunks.push(bytes);
+            }
+            yield item;
+        }
+    }
+}
+
+/// A stream recording in progress, saved when dropped: the chunk parsers stop
+/// reading at `[DONE]`, usually before the upstream body is exhausted.
+struct PendingRecording {
+    recording: Option<Recording>,
+    chunks: ChunkRecorder,
+    recorder: Recorder,
+    logger: SharedLogger,
+}
+
+impl Drop for PendingRecording {
+    fn drop(&mut self) {
+        if let Some(mut recording) = self.recording.take() {
+            recording.chunks = Some(std::mem::take(&mut self.chunks).finish());
+            save_recording(&self.recorder, &self.logger, &recording);
+        }
+    }
+}
+
+/// Load the recorded exchange for a request from a replay provider's directory.
+async fn replay(
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    logger: &SharedLogger,
+) -> Result<Recording> {
+    let dir = route.provider.effective_base_url()?;
+    let key = request_key(req);
+    logger.log_with_context(
+        LogLevel::Info,
+        "proxy",
+        format!("Replay {key}"),
+        serde_json::json!({ "dir": dir }),
+    );
+    recording::load(Path::new(&dir), &key).await
+}
+
+/// Bookkeeping for one upstream attempt: latency, status and token metrics,
+/// and the usage record saved to storage when the response completes.
+struct RequestTracker {
+    usage: UsageRecord,
+    price: Option<ModelPrice>,
+    started: Instant,
+    storage: Arc<dyn Storage>,
+    metrics: Metrics,
+    logger: SharedLogger,
+}
+
+impl RequestTracker {
+    fn new(
+        state: &AppState,
+        provider: &str,
+        requested_model: &str,
+        upstream_model: &str,
+        streaming: bool,
+    ) -> Self {
+        let ctx = log_context::current();
+        Self {
+            usage: UsageRecord {
+                timestamp: chrono::Utc::now(),
+                provider: provider.to_string(),
+                requested_model: requested_model.to_string(),
+                upstream_model: upstream_model.to_string(),
+                input_tokens: 0,
+                output_tokens: 0,
+                streaming,
+                session_id: ctx.as_ref().and_then(|ctx| ctx.session_id.clone()),
+                tags: ctx.map(|ctx| ctx.tags).unwrap_or_default(),
+                cost: None,
+            },
+            price: state
+                .config
+                .load()
+                .costs
+                .price(provider, upstream_model)
+                .copied()
+                .or_else(|| {
+                    state
+                        .registry
+                        .get(provider, upstream_model)
+                        .and_then(|info| info.price())
+                }),
+            started: Instant::now(),
+            storage: state.storage.clone(),
+            metrics: state.metrics.clone(),
+            logger: state.logger.clone(),
+        }
+    }
+
+    /// The provider answered with an error status.
+    fn failed(&self, status: u16) {
+        self.metrics.record_request(
+            &self.usage.provider,
+            &self.usage.upstream_model,
+            status,
+            self.started.elapsed(),
+        );
+    }
+
+    /// Count a transport failure, passing the error through.
+    fn network_error(&self, e: ProxyError) -> ProxyError {
+        if matches!(e, ProxyError::Provider { .. }) {
+            self.metrics
+                .record_network_error(&self.usage.provider, &self.usage.upstream_model);
+        }
+        e
+    }
+
+    /// The response completed with the given usage.
+    fn completed(mut self, usage: &Usage) {
+        let (provider, model) = (&self.usage.provider, &self.usage.upstream_model);
+        // Cache reads are counted (and priced) as ordinary input tokens
+        let input_tokens = usage.input_tokens + usage.cache_read_input_tokens.unwrap_or(0);
+        self.metrics
+            .record_request(provider, model, 200, self.started.elapsed());
+        self.metrics
+            .record_tokens(provider, model, input_tokens, usage.output_tokens);
+        self.metrics.record_tagged(
+            provider,
+            model,
+            &self.usage.tags,
+            input_tokens,
+            usage.output_tokens,
+        );
+
+        self.usage.input_tokens = input_tokens;
+        self.usage.output_tokens = usage.output_tokens;
+        self.usage.cost = self
+            .price
+            .map(|p| p.cost(input_tokens, usage.output_tokens));
+        self.record_usage();
+    }
+
+    /// An earlier stream's response was replayed to a reconnect: counted, and
+    /// recorded in usage with its tokens at no cost, as the provider wasn't
+    /// called again.
+    fn replayed(mut self, usage: &Usage) {
+        self.metrics
+            .record_reconnect_replay(&self.usage.provider, &self.usage.upstream_model);
+        self.usage.input_tokens = usage.input_tokens + usage.cache_read_input_tokens.unwrap_or(0);
+        self.usage.output_tokens = usage.output_tokens;
+        self.usage.cost = Some(0.0);
+        self.record_usage();
+    }
+
+    fn record_usage(&self) {
+        if let Err(e) = self.storage.record_usage(&self.usage) {
+            self.logger
+                .warn("storage", format!("Failed to record usage: {e}"));
+        }
+    }
+}
+
+fn save_recording(recorder: &Recorder, logger: &SharedLogger, recording: &Recording) {
+    match recorder.save(recording) {
+        Ok(()) => logger.debug("record", format!("Recorded {}", recording.key)),
+        Err(e) => logger.warn(
+            "record",
+            format!("Failed to save recording {}: {e}", recording.key),
+        ),
+    }
+}
+
+/// A `[capture]` of one attempt, saved when dropped so that early returns and
+/// streams the client abandons are captured as far as they got.
+struct PendingCapture {
+    capture: Capture,
+    capturer: Capturer,
+    logger: SharedLogger,
+}
+
+impl PendingCapture {
+    fn start(
+        state: &AppState,
+        req: &MessagesRequest,
+        route: &Route<'_>,
+        openai_req: &ChatCompletionRequest,
+    ) -> Option<Self> {
+        state.capturer.is_enabled().then(|| Self {
+            capture: Capture::new(req, &route.provider.name, openai_req),
+            capturer: state.capturer.clone(),
+            logger: state.logger.clone(),
+        })
+    }
+
+    fn push_chunk(&mut self, chunk: &ChatCompletionChunk, events: &[StreamEvent]) {
+        if let serde_json::Value::Array(ref mut chunks) = self.capture.openai_response {
+            chunks.push(serde_json::to_value(chunk).unwrap_or_default());
+        }
+        self.push_events(events);
+    }
+
+    fn push_events(&mut self, events: &[StreamEvent]) {
+        if let serde_json::Value::Array(ref mut sent) = self.capture.anthropic_response {
+            sent.extend(
+                events
+                    .iter()
+                    .map(|e| serde_json::to_value(e).unwrap_or_default()),
+            );
+        }
+    }
+}
+
+impl Drop for PendingCapture {
+    fn drop(&mut self) {
+        match self.capturer.save(&self.capture) {
+            Ok(Some(path)) => self
+                .logger
+                .debug("capture", format!("Captured {}", path.display())),
+            Ok(None) => {}
+            Err(e) => self.logger.warn(
+                "capture",
+                format!("Failed to save capture {}: {e}", self.capture.request_id),
+            ),
+        }
+    }
+}
+
+/// Note an upstream error response in a capture.
+fn capture_error(capture: Option<&mut PendingCapture>, error: &ProxyError) {
+    if let (Some(pending), ProxyError::Upstream { status, error }) = (capture, error) {
+        pending.capture.status = *status;
+        pending.capture.anthropic_response = serde_json::to_value(error).unwrap_or_default();
+    }
+}
+
+/// The fallback chain for a translated request, reordered by the open
+/// `[[schedule]]` windows: providers with an open breaker are skipped, and
+/// Anthropic-format providers (which only serve passthrough) are left out.
+fn translatable_routes<'a>(
+    req: &MessagesRequest,
+    config: &'a ProxyConfig,
+    state: &AppState,
+) -> Result<Vec<Route<'a>>> {
+    let mut routes = config.routes(&req.model)?;
+    override_model(&mut routes[0]);
+    let routes: Vec<Route<'a>> = routes
+        .into_iter()
+        .filter(|r| !r.provider.is_anthropic_format())
+        .collect();
+    if routes.is_empty() {
+        return Err(ProxyError::config(format!(
+            "No translatable provider configured for model '{}'",
+            req.model
+        )));
+    }
+    let routes = state
+        .schedule
+        .apply(routes, config, &state.logger, &state.journal);
+    Ok(available_routes(
+        routes,
+        &state.health,
+        config.health_check.enabled,
+    ))
+}
+
+/// Apply the request's [`MODEL_OVERRIDE_HEADER`], if any, to its primary route.
+/// Fallback providers keep their own mapping.
+fn override_model(route: &mut Route<'_>) {
+    if let Some(model) = log_context::current().and_then(|ctx| ctx.model_override) {
+        route.model = model;
+    }
+}
+
+fn note_failure(config: &ProxyConfig, state: &AppState, provider: &str) {
+    if state.health.record_failure(provider) {
+        let until = if config.health_check.enabled {
+            "until it passes a health check".to_string()
+        } else {
+            format!("for {}s", COOLDOWN.as_secs())
+        };
+        state.logger.warn(
+            "fallback",
+            format!("Provider {provider} failing repeatedly; skipping it {until}"),
+        );
+        state.journal.record(
+            "provider_tripped",
+            serde_json::json!({ "provider": provider }),
+        );
+    }
+}
+
+/// Spend one retry or fallback from the session's `[retry_budget]`. `cause` is
+/// the failure from `provider` that would be retried.
+///
+/// # Errors
+/// Returns `ProxyError::Overloaded` once the session has spent its budget.
+fn spend_retry(
+    budget: &RetryBudgetConfig,
+    state: &AppState,
+    provider: &str,
+    cause: &str,
+) -> Result<()> {
+    let Some(ctx) = log_context::current() else {
+        return Ok(());
+    };
+    let (Some(session), Some(limit)) = (ctx.session_id, budget.limit(ctx.tenant_key.as_deref()))
+    else {
+        return Ok(());
+    };
+    if state
+        .retry_budget
+        .try_spend(&session, limit, budget.window())
+    {
+        return Ok(());
+    }
+    state.logger.warn(
+        "retry",
+        format!("Session {session} has spent its retry budget; not retrying {provider}: {cause}"),
+    );
+    state.metrics.record_budget_exhausted(provider);
+    Err(ProxyError::overloaded(format!(
+        "Retry budget exhausted: this session already retried {limit} time(s) in the last {}s, \
+         so the proxy is not retrying {provider} ({cause}). Try again shortly.",
+        budget.window_secs
+    )))
+}
+
+fn warn_fallback(state: &AppState, provider: &str, error: &ProxyError, next: &str) {
+    let reason = match error {
+        ProxyError::Upstream { status, .. } => format!("failed with status {status}"),
+        e => format!("unreachable ({e})"),
+    };
+    state.logger.warn(
+        "fallback",
+        format!("Provider {provider} {reason}, trying {next}"),
+    );
+    state.journal.record(
+        "provider_failover",
+        serde_json::json!({ "from": provider, "to": next, "reason": reason }),
+    );
+}
+
+/// Download the request's URL images and send them inline, if the provider
+/// wants them so (`inline_image_urls`), then shrink the inline images as
+/// `[translation.images]` asks.
+async fn inline_images(
+    openai_req: &mut ChatCompletionRequest<'_>,
+    route: &Route<'_>,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<()> {
+    if route.provider.inline_image_urls {
+        let inlined = images::inline_urls(openai_req, &state.client).await?;
+        if inlined > 0 {
+            state
+                .logger
+                .debug("proxy", format!("Inlined {inlined} image(s) given by URL"));
+        }
+    }
+    let images = config.translation.images;
+    if images.is_enabled() {
+        let shrunk = images::shrink(openai_req, images, &state.image_cache).await;
+        if shrunk > 0 {
+            state
+                .logger
+                .debug("proxy", format!("Shrank {shrunk} image(s)"));
+        }
+    }
+    Ok(())
+}
+
+/// Translate a request for one route, applying provider-specific quirks and
+/// the model's context limits.
+///
+/// # Errors
+/// Returns `ProxyError::InvalidRequest` if the prompt doesn't fit the model's
+/// context window (see [`context_window::fit`]).
+async fn translate_for_route<'a>(
+    req: &'a MessagesRequest,
+    route: &Route<'_>,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<ChatCompletionRequest<'a>> {
+    let mut openai_req = anthropic_to_openai_cached(
+        req,
+        &route.model,
+        &state.prefix_cache,
+        config.translation.prefix_cache_entries,
+    );
+    let notes = documents::apply(
+        &mut openai_req,
+        config.translation.documents,
+        &state.document_cache,
+    )
+    .await;
+    for note in notes {
+        state
+            .logger
+            .warn("proxy", format!("Document not sent as text: {note}"));
+    }
+    let settings = route.settings;
+    if settings.is_some_and(|s| !s.supports_images) {
+        let stripped = images::strip(&mut openai_req);
+        if stripped > 0 {
+            state.logger.debug(
+                "proxy",
+                format!("Left out {stripped} image(s) for a model without image input"),
+            );
+        }
+    }
+    if let Some(format) = settings.and_then(|s| s.prompted_tools) {
+        prompted_tools::apply(&mut openai_req, format);
+    }
+    wrap_system_prompt(
+        &mut openai_req,
+        settings
+            .and_then(|s| s.system_prefix.as_deref())
+            .or(config.translation.system_prefix.as_deref()),
+        settings
+            .and_then(|s| s.system_suffix.as_deref())
+            .or(config.translation.system_suffix.as_deref()),
+    );
+    if openai_req.response_format.is_none() {
+        openai_req.response_format = route.settings.and_then(|s| s.response_format.clone());
+    }
+    if let Some(settings) = route.settings {
+        merge_stop_sequences(&mut openai_req, &settings.stop_sequences);
+    }
+    if route.provider.is_openai() {
+        openai_req.prompt_cache_key = cache::prompt_cache_key(req);
+    }
+    if route.provider.is_mistral() {
+        mistral::apply_quirks(&mut openai_req);
+    }
+    if route.provider.is_grok() {
+        grok::apply_extensions(
+            req,
+            &mut openai_req,
+            route.provider.reasoning_effort.as_deref(),
+            route.provider.search_parameters.as_ref(),
+        );
+    }
+    if route.provider.api_format() == ApiFormat::OpenAIResponses {
+        openai_req.reasoning_effort = route
+            .provider
+            .reasoning_effort
+            .clone()
+            .or_else(|| thinking_budget(req).map(|b| effort_for_budget(b).to_string()));
+    }
+    let mut limits = state
+        .registry
+        .get(&route.provider.name, &route.model)
+        .unwrap_or_default();
+    let max_context = settings.and_then(|s| s.max_context);
+    limits.context_window = max_context.or(limits.context_window);
+    limits.max_output_tokens = settings
+        .and_then(|s| s.max_output_tokens)
+        .or(limits.max_output_tokens);
+    // A model's own max_context is enforced even with no strategy set
+    let strategy = settings
+        .and_then(|s| s.context_strategy)
+        .or(config.translation.context_strategy)
+        .or(max_context.map(|_| ContextStrategy::Reject));
+    if let (Some(window), Some(strategy)) = (limits.context_window, strategy) {
+        let dropped = context_window::fit(&mut openai_req, window, strategy)?;
+        if dropped > 0 {
+            state.logger.warn(
+                "proxy",
+                format!(
+                    "Dropped the {dropped} oldest messages to fit {}'s {window} token context",
+                    route.model
+                ),
+            );
+        }
+    }
+    apply_model_limits(&mut openai_req, &limits, &state.logger);
+    openai_req.extra.clone_from(&route.provider.extra_body);
+    Ok(openai_req)
+}
+
+/// Lower `max_tokens` to what the model accepts, per the registry: its
+/// `max_output_tokens`, and the room its context window leaves after the
+/// (estimated) prompt.
+fn apply_model_limits(
+    openai_req: &mut ChatCompletionRequest,
+    info: &ModelInfo,
+    logger: &SharedLogger,
+) {
+    let Some(max_tokens) = openai_req.max_tokens else {
+        return;
+    };
+    let input_tokens = if info.context_window.is_some() {
+        prompt_tokens(openai_req)
+    } else {
+        0
+    };
+    let clamped = info.clamp_max_tokens(max_tokens, input_tokens);
+    if clamped < max_tokens {
+        logger.debug(
+            "proxy",
+            format!(
+                "Lowered max_tokens from {max_tokens} to {clamped} for {}",
+                openai_req.model
+            ),
+        );
+        openai_req.max_tokens = Some(clamped);
+    }
+}
+
+/// The `budget_tokens` of a request with extended thinking enabled.
+fn thinking_budget(req: &MessagesRequest) -> Option<u64> {
+    let thinking = req.thinking.as_ref()?;
+    (thinking["type"] == "enabled").then(|| thinking["budget_tokens"].as_u64().unwrap_or(0))
+}
+
+/// Serialize a request body with `extra` fields merged over it and a
+/// provider's `params` filling in any it still lacks. The merge is done on the
+/// serialized body's top level, leaving the messages unparsed.
+fn with_params(
+    request: &impl serde::Serialize,
+    extra: &serde_json::Map<String, serde_json::Value>,
+    params: &serde_json::Map<String, serde_json::Value>,
+) -> serde_json::Result<Vec<u8>> {
+    let body = serde_json::to_vec(request)?;
+    let mut fields = RawObject::parse(&body)?;
+    for (key, value) in extra {
+        fields.set(key, value)?;
+    }
+    for (key, value) in params {
+        fields.set_default(key, value)?;
+    }
+    fields.to_vec()
+}
+
+/// Add a model's configured stop sequences to those the request asked for.
+fn merge_stop_sequences(openai_req: &mut ChatCompletionRequest, extra: &[String]) {
+    if extra.is_empty() {
+        return;
+    }
+    let stop = openai_req.stop.get_or_insert_with(Vec::new);
+    for sequence in extra {
+        if !stop.contains(sequence) {
+            stop.push(sequence.clone());
+        }
+    }
+}
+
+fn record_audit(
+    state: &AppState,
+    req: &MessagesRequest,
+    route: &Route<'_>,
+    openai_req: &ChatCompletionRequest,
+) {
+    if state.audit.is_enabled() {
+        state.audit.record(AuditEntry::new(
+            &req.model,
+            &route.provider.name,
+            &openai_req.model,
+            req.stream.unwrap_or(false),
+            translation_changes(req, openai_req),
+        ));
+    }
+}
+
+/// Build the upstream URL and JSON body for a translated request.
+fn upstream_request(
+    format: ApiFormat,
+    base_url: &str,
+    openai_req: &ChatCompletionRequest,
+    params: &serde_json::Map<String, serde_json::Value>,
+) -> Result<(String, Vec<u8>)> {
+    let base_url = base_url.trim_end_matches('/');
+    let (url, body) = match format {
+        ApiFormat::Cohere => (
+            format!("{base_url}/chat"),
+            with_params(&openai_to_cohere(openai_req), &openai_req.extra, params),
+        ),
+        ApiFormat::Bedrock => {
+            let action = if openai_req.stream == Some(true) {
+                "converse-stream"
+            } else {
+                "converse"
+            };
+            (
+                format!(
+                    "{base_url}/model/{}/{action}",
+                    aws::uri_encode(&openai_req.model)
+                ),
+                with_params(&openai_to_converse(openai_req), &openai_req.extra, params),
+            )
+        }
+        ApiFormat::Gemini => {
+            let action = if openai_req.stream == Some(true) {
+                "streamGenerateContent?alt=sse"
+            } else {
+                "generateContent"
+            };
+            (
+                format!("{base_url}/models/{}:{action}", openai_req.model),
+                with_params(&openai_to_gemini(openai_req), &openai_req.extra, params),
+            )
+        }
+        ApiFormat::OpenAIResponses => (
+            format!("{base_url}/responses"),
+            with_params(&openai_to_responses(openai_req), &openai_req.extra, params),
+        ),
+        _ if params.is_empty() && openai_req.extra.is_empty() => (
+            format!("{base_url}/chat/completions"),
+            serde_json::to_vec(openai_req),
+        ),
+        _ => (
+            format!("{base_url}/chat/completions"),
+            with_params(openai_req, &openai_req.extra, params),
+        ),
+    };
+    let body =
+        body.map_err(|e| ProxyError::translation(format!("Failed to serialize request: {e}")))?;
+    Ok((url, body))
+}
+
+/// Parse a successful upstream response body into `OpenAI` form.
+fn parse_upstream_response(format: ApiFormat, body: &str) -> Result<ChatCompletionResponse> {
+    let parsed = match format {
+        ApiFormat::Cohere => {
+            serde_json::from_str::<CohereChatResponse>(body).map(|r| cohere_to_openai(&r))
+        }
+        ApiFormat::Bedrock => {
+            serde_json::from_str::<ConverseResponse>(body).map(|r| converse_to_openai(&r))
+        }
+        ApiFormat::Gemini => {
+            serde_json::from_str::<GenerateContentResponse>(body).map(|r| gemini_to_openai(&r))
+        }
+        ApiFormat::OpenAIResponses => {
+            serde_json::from_str::<ResponsesResponse>(body).map(|r| responses_to_openai(&r))
+        }
+        _ => serde_json::from_str(body),
+    };
+    parsed.map_err(|e| {
+        ProxyError::translation(format!(
+            "Failed to parse provider response: {}. Body: {}",
+            e,
+            truncate(body, 300)
+        ))
+    })
+}
+
+/// [`parse_upstream_response`] of a body as it downloads.
+async fn parse_upstream_stream(
+    format: ApiFormat,
+    chunks: impl Stream<Item = reqwest::Result<Bytes>> + Send,
+) -> Result<ChatCompletionResponse> {
+    Ok(match format {
+        ApiFormat::Cohere => {
+            cohere_to_openai(&json_stream::parse::<CohereChatResponse, _, _>(chunks).await?)
+        }
+        ApiFormat::Bedrock => {
+            converse_to_openai(&json_stream::parse::<ConverseResponse, _, _>(chunks).await?)
+        }
+        ApiFormat::Gemini => {
+            gemini_to_openai(&json_stream::parse::<GenerateContentResponse, _, _>(chunks).await?)
+        }
+        ApiFormat::OpenAIResponses => {
+            responses_to_openai(&json_stream::parse::<ResponsesResponse, _, _>(chunks).await?)
+        }
+        _ => json_stream::parse(chunks).await?,
+    })
+}
+
+/// Translate an upstream error body (`OpenAI`, Gemini, or Cohere and Bedrock's
+/// bare `{"message": ...}`) into an Anthropic error.
+fn upstream_error(status: u16, body: &str) -> ErrorResponse {
+    let mut error = if let Ok(err) = serde_json::from_str::<ChatErrorResponse>(body) {
+        openai_error_to_anthropic(&err)
+    } else if let Ok(err) = serde_json::from_str::<GeminiError>(body) {
+        ErrorResponse::api_error(err.error.message)
+    } else if let Ok(err) = serde_json::from_str::<CohereError>(body) {
+        ErrorResponse::api_error(err.message)
+    } else {
+        ErrorResponse::api_error(format!(
+            "Provider returned status {}: {}",
+            status,
+            truncate(body, 500)
+        ))
+    };
+    // Providers rarely use Anthropic's error types; the status says more
+    if error.error.error_type == "api_error" {
+        error.error.error_type = error_type_for_status(status).to_string();
+    }
+    error
+}
+
+/// Translated chunks from a provider stream, whatever its wire format. An
+/// error, if any, is the last item: the upstream connection broke off.
+type ChunkStream = Pin<Box<dyn Stream<Item = Result<ChatCompletionChunk>> + Send>>;
+
+/// The scanner for tool calls in the output of a route whose model has
+/// `prompted_tools` set.
+fn prompted_calls(req: &MessagesRequest, route: &Route<'_>) -> Option<PromptedTools> {
+    let format = route.settings?.prompted_tools?;
+    Some(PromptedTools::new(
+        format,
+        req.tools.as_deref().unwrap_or_default(),
+    ))
+}
+
+/// Turn the tool calls a prompted-tools model writes into its text into tool
+/// call chunks.
+fn prompted_tool_chunks(
+    mut chunks: ChunkStream,
+    tools: PromptedTools,
+) -> impl Stream<Item = Result<ChatCompletionChunk>> + Send + 'static {
+    async_stream::stream! {
+        let mut calls = tools.stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            for chunk in calls.process(chunk) {
+                yield Ok(chunk);
+            }
+        }
+        for chunk in calls.finish() {
+            yield Ok(chunk);
+        }
+    }
+}
+
+/// Let an SSE parser see the last event of a stream that ends cleanly but
+/// without the blank line that ends an event, as servers that close the
+/// connection instead of ending a chunked body (HTTP/1.0 ones, say) may.
+/// Parsers drop an unfinished event; a blank line after a finished one is
+/// ignored. A stream that breaks off isn't given one.
+fn terminate_events(mut byte_stream: ByteStream) -> ByteStream {
+    Box::pin(async_stream::stream! {
+        while let Some(item) = byte_stream.next().await {
+            let broken = item.is_err();
+            yield item;
+            if broken {
+                return;
+            }
+        }
+        yield Ok(Bytes::from_static(b"\n\n"));
+    })
+}
+
+/// The error for an upstream stream that broke off before it was done, for
+/// the client to see rather than a response that looks complete.
+fn broken_stream(e: &impl std::fmt::Display) -> ProxyError {
+    ProxyError::provider(format!("Upstream stream broke off: {e}"))
+}
+
+/// Parse an `OpenAI` SSE byte stream into chunks, ending at `[DONE]`.
+fn openai_chunks(
+    byte_stream: ByteStream,
+    logger: SharedLogger,
+) -> impl Stream<Item = Result<ChatCompletionChunk>> + Send + 'static {
+    async_stream::stream! {
+        let event_stream = terminate_events(byte_stream).eventsource();
+
+        tokio::pin!(event_stream);
+
+        while let Some(event_result) = event_stream.next().await {
+            let event = match event_result {
+                Ok(e) => e,
+                Err(e) => {
+                    yield Err(broken_stream(&e));
+                    break;
+                }
+            };
+
+            if event.data == "[DONE]" {
+                break;
+            }
+
+            match serde_json::from_str::<ChatCompletionChunk>(&event.data) {
+                Ok(chunk) => yield Ok(chunk),
+                Err(e) => logger.debug("stream", format!("Skipping unparseable chunk: {e}")),
+            }
+        }
+    }
+}
+
+/// Parse a Gemini `streamGenerateContent?alt=sse` byte stream into `OpenAI` chunks.
+fn gemini_chunks(
+    byte_stream: ByteStream,
+    logger: SharedLogger,
+) -> impl Stream<Item = Result<ChatCompletionChunk>> + Send + 'static {
+    async_stream::stream! {
+        let event_stream = terminate_events(byte_stream).eventsource();
+        let mut state = GeminiStreamState::new();
+
+        tokio::pin!(event_stream);
+
+        while let Some(event_result) = event_stream.next().await {
+            let event = match event_result {
+                Ok(e) => e,
+                Err(e) => {
+                    yield Err(broken_stream(&e));
+                    break;
+                }
+            };
+
+            match serde_json::from_str::<GenerateContentResponse>(&event.data) {
+                Ok(resp) => {
+                    for chunk in state.process(&resp) {
+                        yield Ok(chunk);
+                    }
+                }
+                Err(e) => logger.debug("stream", format!("Skipping unparseable Gemini chunk: {e}")),
+            }
+        }
+    }
+}
+
+/// Parse an `OpenAI` Responses event stream into `OpenAI` chunks. A failed
+/// response or an error event ends the stream.
+fn responses_chunks(
+    byte_stream: ByteStream,
+    logger: SharedLogger,
+) -> impl Stream<Item = Result<ChatCompletionChunk>> + Send + 'static {
+    async_stream::stream! {
+        let event_stream = terminate_events(byte_stream).eventsource();
+        let mut state = ResponsesStreamState::new();
+
+        tokio::pin!(event_stream);
+
+        while let Some(event_result) = event_stream.next().await {
+            let event = match event_result {
+                Ok(e) => e,
+                Err(e) => {
+                    yield Err(broken_stream(&e));
+                    break;
+                }
+            };
+
+            match serde_json::from_str::<ResponsesStreamEvent>(&event.data) {
+                Ok(ResponsesStreamEvent::Failed { response }) => {
+                    let message = response.error.map(|e| e.message).unwrap_or_default();
+                    logger.error("stream", format!("Response failed: {message}"));
+                    break;
+                }
+                Ok(ResponsesStreamEvent::Error { message }) => {
+                    logger.error("stream", format!("Responses stream error: {message}"));
+                    break;
+                }
+                Ok(event) => {
+                    if let Some(chunk) = state.process(&event) {
+                        yield Ok(chunk);
+                    }
+                }
+                Err(e) => logger.debug("stream", format!("Skipping unparseable Responses event: {e}")),
+            }
+        }
+    }
+}
+
+/// Parse a Cohere NDJSON byte stream into `OpenAI` chunks.
+fn cohere_chunks(
+    byte_stream: ByteStream,
+    logger: SharedLogger,
+) -> impl Stream<Item = Result<ChatCompletionChunk>> + Send + 'static {
+    async_stream::stream! {
+        let mut buf: Vec<u8> = Vec::new();
+
+        tokio::pin!(byte_stream);
+
+        while let Some(bytes_result) = byte_stream.next().await {
+            let bytes = match bytes_result {
+                Ok(b) => b,
+                Err(e) => {
+                    yield Err(broken_stream(&e));
+                    break;
+                }
+            };
+
+            buf.extend_from_slice(&bytes);
+            while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                if let Some(chunk) = parse_cohere_line(&line, &logger) {
+                    yield Ok(chunk);
+                }
+            }
+        }
+
+        if let Some(chunk) = parse_cohere_line(&buf, &logger) {
+            yield Ok(chunk);
+        }
+    }
+}
+
+/// Parse a Bedrock `ConverseStream` eventstream into `OpenAI` chunks. An
+/// exception message (throttling, validation, ...) ends the stream.
+fn bedrock_chunks(
+    byte_stream: ByteStream,
+    logger: SharedLogger,
+) -> impl Stream<Item = Result<ChatCompletionChunk>> + Send + 'static {
+    async_stream::stream! {
+        let mut decoder = EventStreamDecoder::new();
+        let mut state = ConverseStreamState::new();
+
+        tokio::pin!(byte_stream);
+
+        'read: while let Some(bytes_result) = byte_stream.next().await {
+            let bytes = match bytes_result {
+                Ok(b) => b,
+                Err(e) => {
+                    yield Err(broken_stream(&e));
+                    break;
+                }
+            };
+
+            decoder.push(&bytes);
+            loop {
+                let message = match decoder.next_message() {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(e) => {
+                        logger.error("stream", e.to_string());
+                        break 'read;
+                    }
+                };
+                let payload = String::from_utf8_lossy(&message.payload);
+                if message.header(":message-type") != Some("event") {
+                    let kind = message
+                        .header(":exception-type")
+                        .or_else(|| message.header(":error-code"))
+                        .unwrap_or("unknown");
+                    logger.error(
+                        "stream",
+                        format!("Bedrock stream {kind}: {}", truncate(&payload, 300)),
+                    );
+                    break 'read;
+                }
+                let event_type = message.header(":event-type").unwrap_or_default();
+                match serde_json::from_str(&payload) {
+                    Ok(payload) => {
+                        if let Some(chunk) = state.event_to_chunk(event_type, &payload) {
+                            yield Ok(chunk);
+                        }
+                    }
+                    Err(e) => logger.debug(
+                        "stream",
+                        format!("Skipping unparseable Bedrock event: {e}"),
+                    ),
+                }
+            }
+        }
+
+        if let Some(chunk) = state.finish() {
+            yield Ok(chunk);
+        }
+    }
+}
+
+fn parse_cohere_line(line: &[u8], logger: &SharedLogger) -> Option<ChatCompletionChunk> {
+    let line = std::str::from_utf8(line).ok()?.trim();
+    if line.is_empty() {
+        return None;
+    }
+    match serde_json::from_str::<CohereStreamEvent>(line) {
+        Ok(event) => cohere_event_to_chunk(&event),
+        Err(e) => {
+            logger.debug("stream", format!("Skipping unparseable Cohere event: {e}"));
+            None
+        }
+    }
+}
+
+/// Translate a stream of `OpenAI` chunks into Anthropic SSE events. Past the
+/// `[streaming] turn_deadline_secs` deadline the upstream is closed and the turn
+/// ends as if it had hit `max_tokens`.
+#[allow(clippy::too_many_arguments)]
+fn sse_translate_stream(
+    mut chunks: ChunkStream,
+    mut translator: StreamTranslator,
+    tracker: RequestTracker,
+    mut capture: Option<PendingCapture>,
+    mut cache_fill: Option<CacheFill>,
+    deadline: Option<(tokio::time::Instant, Duration)>,
+    mut rechunker: Option<Rechunker>,
+    logger: SharedLogger,
+) -> impl Stream<Item = std::result::Result<SseEvent, std::io::Error>> + Send + 'static {
+    async_stream::stream! {
+        let mut timed_out = false;
+        let mut broken = None;
+        let mut checker = cfg!(debug_assertions).then(StreamChecker::new);
+        loop {
+            // Wake for the turn deadline, or to send deltas held back
+            let flush_at = rechunker
+                .as_ref()
+                .and_then(Rechunker::flush_at)
+                .map(tokio::time::Instant::from_std);
+            let wake = [deadline.map(|(at, _)| at), flush_at].into_iter().flatten().min();
+            let next = match wake {
+                Some(at) => match tokio::time::timeout_at(at, chunks.next()).await {
+                    Ok(next) => next,
+                    Err(_) if flush_at == Some(at) => {
+                        for event in rechunker.as_mut().map(Rechunker::flush).unwrap_or_default() {
+                            if let Some(sse) = to_sse_event(&event) {
+                                yield Ok(sse);
+                            }
+                        }
+                        continue;
+                    }
+                    Err(_) => {
+                        timed_out = true;
+                        break;
+                    }
+                },
+                None => chunks.next().await,
+            };
+            let chunk = match next {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    broken = Some(e);
+                    break;
+                }
+                None => break,
+            };
+            let events = translator.process_chunk(&chunk);
+            if let Some(ref mut pending) = capture {
+                pending.push_chunk(&chunk, &events);
+            }
+            if let Some(ref mut fill) = cache_fill {
+                for event in &events {
+                    fill.collector.push(event);
+                }
+            }
+            check_events(checker.as_mut(), &events, &logger);
+            for event in events {
+                let sends = match rechunker {
+                    Some(ref mut rechunker) => rechunker.push(event, Instant::now()),
+                    None => vec![(Duration::ZERO, event)],
+                };
+                for (pause, event) in sends {
+                    if !pause.is_zero() {
+                        tokio::time::sleep(pause).await;
+                    }
+                    if let Some(sse) = to_sse_event(&event) {
+                        yield Ok(sse);
+                    }
+                }
+            }
+        }
+        for event in rechunker.as_mut().map(Rechunker::flush).unwrap_or_default() {
+            if let Some(sse) = to_sse_event(&event) {
+                yield Ok(sse);
+            }
+        }
+
+        // A stream that broke off ends in an error event, not as a complete
+        // turn, so the client knows to retry
+        if let Some(error) = broken {
+            logger.error("stream", error.to_string());
+            yield Ok(error_event(&tracker.network_error(error)));
+            return;
+        }
+
+        // Ensure stream is closed even if [DONE] was missing
+        let events = if timed_out {
+            drop(chunks);
+            let limit = deadline.map_or(0, |(_, limit)| limit.as_secs());
+            logger.warn(
+                "stream",
+                format!("Turn ran past its {limit}s deadline; cut off as max_tokens"),
+            );
+            translator.cut_off()
+        } else {
+            translator.finish()
+        };
+        if let Some(ref mut pending) = capture {
+            pending.push_events(&events);
+        }
+        check_events(checker.as_mut(), &events, &logger);
+        if let Some(problem) = checker.as_ref().and_then(StreamChecker::finish) {
+            logger.error("stream", format!("Stream broke the event contract: {problem}"));
+        }
+        for event in events {
+            if let Some(sse) = to_sse_event(&event) {
+                yield Ok(sse);
+            }
+        }
+
+        warn_leaked_stop_tokens(&logger, translator.leaked_stop_tokens());
+        let repaired = translator.repaired_tool_calls();
+        if !repaired.is_empty() {
+            logger.warn(
+                "stream",
+                format!(
+                    "Repaired invalid JSON arguments of tool call(s) {}",
+                    repaired.join(", ")
+                ),
+            );
+        }
+        let dropped = translator.dropped_choices();
+        if dropped > 0 {
+            logger.warn(
+                "stream",
+                format!("Provider streamed {dropped} extra choice(s); only the first was kept"),
+            );
+        }
+        logger.info("stream", "Stream completed");
+        tracker.completed(&translator.usage());
+        // Only a stream the provider finished itself is complete enough to cache
+        if let Some(fill) = cache_fill.filter(|_| !timed_out) {
+            if let Some(resp) = fill.collector.finish() {
+                if let Some(key) = &fill.key {
+                    store_response(&fill.cache, &fill.config, key, &resp, &logger);
+                }
+                if let Some(slot) = fill.slot {
+                    slot.finish(&resp);
+                }
+            }
+        }
+    }
+}
+
+/// Log the events that break Anthropic's streaming contract (debug builds
+/// only; see [`stream_check`](crate::translate::stream_check)).
+fn check_events(
+    checker: Option<&mut StreamChecker>,
+    events: &[StreamEvent],
     logger: &SharedLogger,
+) {
+    let Some(checker) = checker else {
+        return;
+    };
+    for event in events {
+        if let Some(problem) = checker.push(event) {
+            logger.error(
+                "stream",
+                format!("Stream broke the event contract: {problem}"),
+            );
+        }
+    }
+}
+
+/// A stream's response on its way into the response cache, or kept for
+/// reconnects (`reconnect_secs`), or both.
+struct CacheFill {
+    cache: ResponseCache,
+    config: ResponseCacheConfig,
+    key: Option<String>,
+    slot: Option<StreamSlot>,
+    collector: ResponseCollector,
+}
+
+/// Point out stop tokens the model leaked (and that were stripped), which the
+/// model's `stop_sequences` can catch upstream instead.
+fn warn_leaked_stop_tokens(logger: &SharedLogger, tokens: &[&str]) {
+    if tokens.is_empty() {
+        return;
+    }
+    logger.warn(
+        "proxy",
+        format!(
+            "Stripped leaked stop token(s) {} from the end of the response; \
+             add them to the model's stop_sequences to stop generation there",
+            tokens.join(" ")
+        ),
+    );
+}
+
+pub(crate) fn to_sse_event(event: &StreamEvent) -> Option<SseEvent> {
+    serde_json::to_string(event).ok().map(|data| SseEvent {
+        event: event.event_name().to_string(),
+        data,
+    })
+}
+
+/// An Anthropic `error` event, which ends a stream that failed part way.
+fn error_event(error: &ProxyError) -> SseEvent {
+    SseEvent {
+        event: "error".to_string(),
+        data: serde_json::to_string(&error.to_error_response()).unwrap_or_default(),
+    }
+}
+
+/// Forward an already-parsed request to its routed provider, whatever the
+/// provider's format. Used by the non-Anthropic inbound endpoints: for
+/// Anthropic-format providers the request is re-serialized and passed through.
+///
+/// # Errors
+/// As [`proxy_non_streaming`] and [`proxy_passthrough`].
+pub async fn proxy_parsed_non_streaming(
+    req: &MessagesRequest,
+    state: &AppState,
+) -> Result<MessagesResponse> {
+    let config = state.config.load();
+    if !config.route(&req.model)?.provider.is_anthropic_format() {
+        return respond(req, &config, state).await;
+    }
+
+    let resp = passthrough_parsed(req, &config, state).await?;
+    let body = collect_body(resp.body).await?;
+    if resp.status >= 400 {
+        return Err(passthrough_error(resp.status, &body));
+    }
+    let mut resp = serde_json::from_slice(&body)?;
+    if config.provenance.footer {
+        provenance::stamp_response(&mut resp);
+    }
+    Ok(resp)
+}
+
+/// Streaming counterpart of [`proxy_parsed_non_streaming`].
+///
+/// # Errors
+/// As [`proxy_streaming`] and [`proxy_passthrough`].
+pub async fn proxy_parsed_streaming(req: &MessagesRequest, state: &AppState) -> Result<SseStream> {
+    let config = state.config.load();
+    if !config.route(&req.model)?.provider.is_anthropic_format() {
+        return stream(req, &config, state).await;
+    }
+
+    let resp = passthrough_parsed(req, &config, state).await?;
+    if resp.status >= 400 {
+        let body = collect_body(resp.body).await?;
+        return Err(passthrough_error(resp.status, &body));
+    }
+
+    let events = terminate_events(resp.body)
+        .eventsource()
+        .map(|event| match event {
+            Ok(e) => Ok(SseEvent {
+                event: e.event,
+                data: e.data,
+            }),
+            Err(e) => Ok(error_event(&broken_stream(&e))),
+        });
+    if config.provenance.footer {
+        return Ok(provenance::stamp_stream(Box::pin(events)));
+    }
+    Ok(Box::pin(events))
+}
+
+/// An Anthropic-format error response as a `ProxyError::Upstream`.
+fn passthrough_error(status: u16, body: &[u8]) -> ProxyError {
+    let error = serde_json::from_slice(body).unwrap_or_else(|_| {
+        ErrorResponse::new(error_type_for_status(status), String::from_utf8_lossy(body))
+    });
+    ProxyError::upstream(status, error)
+}
+
+async fn passthrough_parsed(
+    req: &MessagesRequest,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<PassthroughResponse> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        "anthropic-version",
+        reqwest::header::HeaderValue::from_static(ANTHROPIC_VERSION),
+    );
+    passthrough(
+        Bytes::from(serde_json::to_vec(req)?),
+        &headers,
+        config,
+        state,
+    )
+    .await
+}
+
+async fn collect_body(mut body: ByteStream) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk
+            .map_err(|e| ProxyError::provider(format!("Failed to read response body: {e}")))?;
+        out.extend_from_slice(&chunk);
+    }
+    Ok(out)
+}
+
+/// Forward an Anthropic-format request directly (passthrough mode for Anthropic provider).
+///
+/// The response body is returned as a byte stream rather than buffered, so SSE
+/// events reach the client as the provider emits them.
+///
+/// # Errors
+/// Returns `ProxyError::Provider` on network failures, `ProxyError::Config` if
+/// credentials can't be resolved.
+pub async fn proxy_passthrough(
+    body: Bytes,
+    headers: &reqwest::header::HeaderMap,
+    state: &AppState,
+) -> Result<PassthroughResponse> {
+    passthrough(body, headers, &state.config.load(), state).await
+}
+
+/// [`proxy_passthrough`] with the request's config snapshot (see [`respond`]).
+pub(crate) async fn passthrough(
+    body: Bytes,
+    headers: &reqwest::header::HeaderMap,
+    config: &ProxyConfig,
+    state: &AppState,
+) -> Result<PassthroughResponse> {
+    let (client, logger) = (&state.client, &state.logger);
+    let fields = serde_json::from_slice::<ModelField>(&body).ok();
+    let (requested_model, metadata, streaming) = fields
+        .map(|m| (m.model, m.metadata, m.stream.unwrap_or(false)))
+        .unwrap_or_default();
+    log_context::set_request(
+        &requested_model,
+        metadata.as_ref().and_then(|m| m.user_id.as_deref()),
+    );
+    let mut route = config.route(&requested_model)?;
+    override_model(&mut route);
+    log_context::set_provider(&route.provider.name, &route.model);
+    let pooled = state.keys.pick(route.provider)?;
+    let api_key = match &pooled {
+        Some(pooled) => pooled.key.clone(),
+        None => route.provider.resolve_api_key()?,
+    };
+    let base_url = route.provider.effective_base_url()?;
+    let url = format!("{}/v1/messages", base_url.trim_end_matches('/'));
+
+    if state.audit.is_enabled() {
+        let changes = if route.model == requested_model {
+            Vec::new()
+        } else {
+            vec![Change {
+                kind: ChangeKind::Renamed,
+                field: "model".to_string(),
+                detail: Some(format!("{requested_model} → {}", route.model)),
+            }]
+        };
+        state.audit.record(AuditEntry::new(
+            &requested_model,
+            &route.provider.name,
+            &route.model,
+            streaming,
+            changes,
+        ));
+    }
+
+    // Rewrite the model only if the mapping actually renames it, copying
+    // the rest of the body as it came
+    let body = if route.model == requested_model {
+        body
+    } else {
+        let mut fields = RawObject::parse(&body)?;
+        fields.set("model", &route.model)?;
+        Bytes::from(fields.to_vec()?)
+    };
+
+    logger.log_with_context(
+        LogLevel::Info,
+        "proxy",
+        format!("Passthrough POST {url}"),
+        serde_json::json!({ "upstream_model": route.model }),
+    );
+
+    let mut req_builder = with_headers(client.post(&url), &route.provider.headers)
+        .header("Content-Type", "application/json");
+    req_builder = match &route.provider.oauth {
+        Some(oauth) => {
+            let token = state
+                .tokens
+                .token(client, &route.provider.name, oauth)
+                .await?;
+            req_builder.header("Authorization", format!("Bearer {token}"))
+        }
+        None => req_builder.header("x-api-key", &api_key),
+    };
+    if let Some((name, key)) = idempotency_key(route.provider) {
+        req_builder = req_builder.header(name, key);
+    }
+
+    if let Some(version) = headers.get("anthropic-version") {
+        req_builder = req_builder.header("anthropic-version", version);
+    }
+
+    let tokens = estimate_tokens(&String::from_utf8_lossy(&body));
+    state
+        .rate_limiter
+        .pace(route.provider, tokens, logger)
+        .await?;
+    let permit = state.limiter.acquire(route.provider, logger).await?;
+    let tracker = RequestTracker::new(
+        state,
+        &route.provider.name,
+        &requested_model,
+        &route.model,
+        streaming,
+    );
+    let response = req_builder.body(body).send().await.map_err(|e| {
+        tracker.network_error(ProxyError::provider(format!(
+            "Passthrough request failed: {e}"
+        )))
+    })?;
+
+    let status = response.status().as_u16();
+    let resp_headers = response.headers().clone();
+    keep_response_headers(route.provider, &resp_headers);
+    if let Some(pooled) = &pooled {
+        let retry_after = retry_after(&resp_headers);
+        let _ = state
+            .keys
+            .record(&route.provider.name, &pooled.env, status, retry_after);
+    }
+    if let Some(id) = resp_headers
+        .get(log_context::ANTHROPIC_REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        log_context::set_anthropic_request_id(id);
+    }
+
+    logger.info("proxy", format!("Passthrough response: status={status}"));
+
+    let logger = logger.clone();
+    let mut upstream = response.bytes_stream();
+    let body = async_stream::stream! {
+        let _permit = permit;
+        let mut total = 0usize;
+        let mut scanner = (status < 400).then(|| UsageScanner::new(streaming));
+        while let Some(chunk) = upstream.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    total += bytes.len();
+                    if let Some(ref mut scanner) = scanner {
+                        scanner.push(&bytes);
+                    }
+                    yield Ok(bytes);
+                }
+                Err(e) => {
+                    logger.error("proxy", format!("Passthrough stream error: {e}"));
+                    yield Err(std::io::Error::other(e));
+                    return;
+                }
+            }
+        }
+        logger.info("proxy", format!("Passthrough complete: len={total}"));
+        match scanner {
+            Some(scanner) => tracker.completed(&scanner.finish()),
+            None => tracker.failed(status),
+        }
+    };
+
+    Ok(PassthroughResponse {
+        status,
+        headers: resp_headers,
+        body: Box::pin(body),
+    })
+}
+
+/// Stream of raw response body chunks.
+pub type ByteStream =
+    Pin<Box<dyn Stream<Item = std::result::Result<Bytes, std::io::Error>> + Send>>;
+
+/// An upstream response forwarded without translation.
+pub struct PassthroughResponse {
+    pub status: u16,
+    pub headers: reqwest::header::HeaderMap,
+    pub body: ByteStream,
+}
+
+/// Just the `model` (and `metadata`, `stream`) of a request body, for routing
+/// without a full parse.
+#[derive(serde::Deserialize)]
+pub(crate) struct ModelField {
+    pub(crate) model: String,
+    #[serde(default)]
+    pub(crate) metadata: Option<Metadata>,
+    #[serde(default)]
+    pub(crate) stream: Option<bool>,
+}
+
+/// Picks token usage out of an Anthropic-format response as it passes through:
+/// from `message_start`/`message_delta` events when streaming, else from the
+/// body's `usage`.
+struct UsageScanner {
+    streaming: bool,
+    buffer: Vec<u8>,
+    usage: Usage,
+}
+
+impl UsageScanner {
+    fn new(streaming: bool) -> Self {
+        Self {
+            streaming,
+            buffer: Vec::new(),
+            usage: Usage::default(),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        if !self.streaming {
+            return;
+        }
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let Some(data) = line.strip_prefix(b"data:") else {
+                continue;
+            };
+            if let Ok(event) = serde_json::from_slice::<serde_json::Value>(data) {
+                match event["type"].as_str() {
+                    Some("message_start") => self.apply(&event["message"]["usage"]),
+                    Some("message_delta") => self.apply(&event["usage"]),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Usage {
+        if !self.streaming {
+            if let Ok(body) = serde_json::from_slice::<serde_json::Value>(&self.buffer) {
+                self.apply(&body["usage"]);
+            }
+        }
+        self.usage
+    }
+
+    fn apply(&mut self, usage: &serde_json::Value) {
+        if let Some(input) = usage["input_tokens"].as_u64() {
+            self.usage.input_tokens = input;
+        }
+        if let Some(output) = usage["output_tokens"].as_u64() {
+            self.usage.output_tokens = output;
+        }
+    }
+}
+
+fn user_id(req: &MessagesRequest) -> Option<&str> {
+    req.metadata.as_ref().and_then(|m| m.user_id.as_deref())
+}
+
+/// How requests to a provider are authenticated, and the extra headers
+/// (`[provider.headers]`, and an `idempotency_header`) they carry. Made once
+/// per request, so its retries carry the same idempotency key.
+struct UpstreamAuth {
+    scheme: AuthScheme,
+    /// The variable the key came from, when the provider rotates keys.
+    key_env: Option<String>,
+    /// The grant the bearer token comes from, when the provider uses OAuth.
+    oauth: Option<OAuthConfig>,
+    /// Whether the token has already been replaced after a 401.
+    token_renewed: bool,
+    headers: HashMap<String, String>,
+    idempotency_key: Option<(String, String)>,
+}
+
+enum AuthScheme {
+    /// `Authorization: Bearer <key>`.
+    Bearer(String),
+    /// `x-goog-api-key: <key>`, for the Gemini API. Vertex AI takes a bearer
+    /// access token instead.
+    GoogApiKey(String),
+    /// AWS Signature Version 4, signed afresh for every attempt.
+    SigV4 {
+        credentials: Credentials,
+        region: String,
+    },
+}
+
+impl UpstreamAuth {
+    fn for_provider(provider: &ProviderConfig, base_url: &str, keys: &KeyPool) -> Result<Self> {
+        let mut key_env = None;
+        let mut api_key = || match keys.pick(provider)? {
+            Some(pooled) => {
+                key_env = Some(pooled.env);
+                Ok::<_, ProxyError>(pooled.key)
+            }
+            None => provider.resolve_api_key(),
+        };
+        let scheme = match provider.api_format() {
+            ApiFormat::Bedrock => AuthScheme::SigV4 {
+                credentials: Credentials::from_env()?,
+                region: provider.aws_region()?,
+            },
+            ApiFormat::Gemini if !base_url.contains("aiplatform.googleapis.com") => {
+                AuthScheme::GoogApiKey(api_key()?)
+            }
+            _ => AuthScheme::Bearer(api_key()?),
+        };
+        Ok(Self {
+            scheme,
+            key_env,
+            oauth: provider.oauth.clone(),
+            token_renewed: false,
+            headers: provider.headers.clone(),
+            idempotency_key: idempotency_key(provider),
+        })
+    }
+
+    /// Fetch a bearer token for an OAuth provider, unless the cached one is
+    /// good for a while yet (see [`oauth`](crate::oauth)).
+    async fn refresh(&mut self, state: &AppState, provider: &ProviderConfig) -> Result<()> {
+        if let Some(oauth) = &self.oauth {
+            let token = state
+                .tokens
+                .token(&state.client, &provider.name, oauth)
+                .await?;
+            self.scheme = AuthScheme::Bearer(token);
+        }
+        Ok(())
+    }
+
+    /// Drop an OAuth provider's token if it answered 401 with it, and return
+    /// `true` the first time: the request can be sent again with a new one.
+    async fn renew_on(&mut self, state: &AppState, provider: &ProviderConfig, status: u16) -> bool {
+        let Some(oauth) = self.oauth.as_ref().filter(|_| status == 401) else {
+            return false;
+        };
+        if self.token_renewed {
+            return false;
+        }
+        state.tokens.invalidate(&provider.name, oauth).await;
+        state.logger.warn(
+            "oauth",
+            format!("{} refused its token, fetching a new one", provider.name),
+        );
+        self.token_renewed = true;
+        true
+    }
+
+    /// Count a response's `status` against the rotated key in use, if any. If
+    /// the key was refused and another is available, switch to it and return
+    /// `true`: the request can be sent again at once.
+    fn rotate_on(
+        &mut self,
+        state: &AppState,
+        provider: &ProviderConfig,
+        status: u16,
+        headers: &reqwest::header::HeaderMap,
+    ) -> bool {
+        let Some(env) = self.key_env.as_deref() else {
+            return false;
+        };
+        if !state
+            .keys
+            .record(&provider.name, env, status, retry_after(headers))
+        {
+            return false;
+        }
+        let Some(next) = state.keys.next_available(provider) else {
+            state.logger.warn(
+                "keys",
+                format!(
+                    "{env} got status {status}, and no other key for {} is available",
+                    provider.name
+                ),
+            );
+            return false;
+        };
+        state.logger.warn(
+            "keys",
+            format!("{env} got status {status}, switching to {}", next.env),
+        );
+        if let AuthScheme::Bearer(key) | AuthScheme::GoogApiKey(key) = &mut self.scheme {
+            *key = next.key;
+        }
+        self.key_env = Some(next.env);
+        true
+    }
+
+    /// Also send `headers`, in place of the provider's own of the same name.
+    fn with_headers(mut self, headers: &HashMap<String, String>) -> Self {
+        self.headers.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        self
+    }
+
+    /// Add auth and extra headers to a request sending `body` to `url`; a
+    /// non-empty body is JSON.
+    fn apply(
+        &self,
+        request: reqwest::RequestBuilder,
+        method: &str,
+        url: &str,
+        body: &[u8],
+    ) -> Result<reqwest::RequestBuilder> {
+        let mut request = with_headers(request, &self.headers);
+        if let Some((name, key)) = self.idempotency_key.as_ref().filter(|_| method == "POST") {
+            request = request.header(name, key);
+        }
+        match &self.scheme {
+            // An optional key left unset: send no credentials at all
+            AuthScheme::Bearer(api_key) if api_key.is_empty() => Ok(request),
+            AuthScheme::Bearer(api_key) => {
+                Ok(request.header("Authorization", format!("Bearer {api_key}")))
+            }
+            AuthScheme::GoogApiKey(api_key) => Ok(request.header("x-goog-api-key", api_key)),
+            AuthScheme::SigV4 {
+                credentials,
+                region,
+            } => {
+                let scope = SigningScope {
+                    region,
+                    service: "bedrock",
+                };
+                let content_type: &[(&str, &str)] = if body.is_empty() {
+                    &[]
+                } else {
+                    &[("content-type", "application/json")]
+                };
+                let headers = aws::sign(
+                    credentials,
+                    &scope,
+                    method,
+                    url,
+                    content_type,
+                    body,
+                    chrono::Utc::now(),
+                )?;
+                Ok(headers.into_iter().fold(request, |request, (name, value)| {
+                    request.header(name, value)
+                }))
+            }
+        }
+    }
+}
+
+/// A new key for a provider's `idempotency_header`, with the header's name.
+fn idempotency_key(provider: &ProviderConfig) -> Option<(String, String)> {
+    let name = provider.idempotency_header.clone()?;
+    Some((
+        name,
+        format!("claude-proxy-{}", uuid::Uuid::new_v4().simple()),
+    ))
+}
+
+/// Add a provider's `[provider.headers]` to a request.
+pub(crate) fn with_headers(
+    request: reqwest::RequestBuilder,
+    headers: &HashMap<String, String>,
+) -> reqwest::RequestBuilder {
+    headers.iter().fold(request, |request, (name, value)| {
+        request.header(name, value)
+    })
+}
+
+/// Probe a provider with an authenticated GET of `path` under its base URL,
+/// returning the status it answers with.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the provider's URL or credentials can't be
+/// resolved, and `ProxyError::Provider` if the request fails.
+pub async fn probe(state: &AppState, provider: &ProviderConfig, path: &str) -> Result<u16> {
+    let base_url = provider.effective_base_url()?;
+    let url = format!("{}{path}", base_url.trim_end_matches('/'));
+    let mut auth = UpstreamAuth::for_provider(provider, &base_url, &state.keys)?;
+    auth.refresh(state, provider).await?;
+    let response = auth
+        .apply(state.client.get(&url), "GET", &url, &[])?
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| ProxyError::provider(format!("Probe of {url} failed: {e}")))?;
+    Ok(response.status().as_u16())
+}
+
+/// Send a POST request with automatic retry on transient failures.
+///
+/// Retries up to [`MAX_RETRIES`] times on status codes in [`RETRYABLE_STATUSES`],
+/// waiting as long as the response's `Retry-After` asks, else backing off
+/// exponentially from 500ms. A key the provider refuses is first swapped for
+/// another of its `api_key_envs`, without waiting. An OAuth provider's token
+/// is refreshed before each attempt that needs it, and once after a 401.
+async fn send_with_retry(
+    budget: &RetryBudgetConfig,
+    state: &AppState,
+    provider: &ProviderConfig,
+    url: &str,
+    auth: &mut UpstreamAuth,
+    body: &[u8],
 ) -> Result<reqwest::Response> {
-    let mut delay = std::time::Duration::from_millis(500);
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
 
-    for attempt in 0..=MAX_RETRIES {
-        let resp = client
-            .post(url)
-            .header("Authorization", format!("Bearer {api_key}"))
+    loop {
+        auth.refresh(state, provider).await?;
+        let resp = auth
+            .apply(state.client.post(url), "POST", url, body)?
             .header("Content-Type", "application/json")
             .body(body.to_vec())
             .send()
@@ -332,34 +2926,106 @@ async fn send_with_retry(
 
         let status = resp.status().as_u16();
 
-        if attempt < MAX_RETRIES && RETRYABLE_STATUSES.contains(&status) {
-            logger.warn(
-                "retry",
-                format!(
-                    "Attempt {}/{}: status {}, retrying in {:?}",
-                    attempt + 1,
-                    MAX_RETRIES + 1,
-                    status,
-                    delay
-                ),
-            );
-            // Consume the body so the connection can be reused
+        if auth.rotate_on(state, provider, status, resp.headers())
+            || auth.renew_on(state, provider, status).await
+        {
             let _ = resp.bytes().await;
-            tokio::time::sleep(delay).await;
-            delay *= 2;
             continue;
         }
+        if attempt < MAX_RETRIES && RETRYABLE_STATUSES.contains(&status) {
+            if let Some(delay) = retry_delay(resp.headers(), backoff) {
+                let cause = format!("status {status}");
+                note_retry(budget, state, &provider.name, attempt, &cause, delay)?;
+                // Consume the body so the connection can be reused
+                let _ = resp.bytes().await;
+                tokio::time::sleep(delay).await;
+                backoff *= 2;
+                attempt += 1;
+                continue;
+            }
+        }
 
         return Ok(resp);
     }
+}
+
+/// How long to wait before retrying: the response's `Retry-After` (seconds or
+/// an HTTP date), else `backoff`. `None` when the provider asks for more than
+/// [`MAX_RETRY_AFTER`], a wait better spent on the next provider.
+fn retry_delay(headers: &reqwest::header::HeaderMap, backoff: Duration) -> Option<Duration> {
+    match retry_after(headers) {
+        Some(delay) => (delay <= MAX_RETRY_AFTER).then_some(delay),
+        None => Some(backoff),
+    }
+}
+
+/// The wait a response's `Retry-After` asks for, in seconds or as an HTTP date.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
 
-    unreachable!()
+/// Spend a retry from the session's budget and log it.
+///
+/// # Errors
+/// Returns `ProxyError::Overloaded` once the session has spent its budget.
+fn note_retry(
+    budget: &RetryBudgetConfig,
+    state: &AppState,
+    provider: &str,
+    attempt: u32,
+    cause: &str,
+    delay: Duration,
+) -> Result<()> {
+    spend_retry(budget, state, provider, cause)?;
+    state.logger.warn(
+        "retry",
+        format!(
+            "Attempt {}/{}: {cause}, retrying in {delay:?}",
+            attempt + 1,
+            MAX_RETRIES + 1,
+        ),
+    );
+    state.metrics.record_retry(provider);
+    Ok(())
 }
 
+/// At most `max` bytes of `s`, cut back to a character boundary.
 fn truncate(s: &str, max: usize) -> &str {
     if s.len() <= max {
-        s
-    } else {
-        &s[..max]
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_keeps_whole_characters() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("hello", 3), "hel");
+        // "é" is two bytes: a cut at 2 would split the second one
+        assert_eq!(truncate("aéé", 2), "a");
+        assert_eq!(truncate("aéé", 3), "aé");
+        let body = format!("a{}", "é".repeat(300));
+        assert_eq!(truncate(&body, 300).len(), 299);
     }
 }
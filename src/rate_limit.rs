@@ -0,0 +1,236 @@
+//! Client-side rate limiting of upstream requests.
+//!
+//! A provider with `requests_per_minute` or `tokens_per_minute` set gets a
+//! token bucket for each, holding up to one minute's allowance and refilling
+//! continuously. Each request takes one request and its estimated prompt
+//! tokens (see [`crate::tokens`]) from the buckets; when they run short the
+//! request waits until they have refilled, so traffic is paced below the
+//! provider's limits instead of bouncing off 429s. A request that would wait
+//! longer than the provider's `queue_timeout_secs` fails at once with
+//! `overloaded_error`.
+//!
+//! Waiting requests reserve their share up front (the bucket goes into debt),
+//! so they are let through in the order they arrived.
+
+use crate::config::ProviderConfig;
+use crate::error::{ProxyError, Result};
+use crate::logging::SharedLogger;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// One limit: `level` units available out of `limit` per minute.
+#[derive(Debug)]
+struct Bucket {
+    limit: u64,
+    level: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    #[allow(clippy::cast_precision_loss)]
+    fn new(limit: u64, now: Instant) -> Self {
+        Self {
+            limit,
+            level: limit as f64,
+            updated: now,
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn per_sec(&self) -> f64 {
+        self.limit as f64 / 60.0
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.level = (self.level + elapsed * self.per_sec()).min(self.limit as f64);
+        self.updated = now;
+    }
+
+    /// How long until `cost` units are available. A cost over the limit only
+    /// needs a full bucket, or it could never be met.
+    #[allow(clippy::cast_precision_loss)]
+    fn wait_for(&self, cost: u64) -> Duration {
+        let cost = cost.min(self.limit) as f64;
+        let deficit = cost - self.level;
+        if deficit <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(deficit / self.per_sec())
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn take(&mut self, cost: u64) {
+        self.level -= cost.min(self.limit) as f64;
+    }
+}
+
+/// A provider's buckets; either may be unset.
+#[derive(Debug, Default)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+/// Keep `bucket` in step with the configured `limit`: created full when a
+/// limit is set or changed, dropped when it's unset.
+fn sync(bucket: &mut Option<Bucket>, limit: Option<u64>, now: Instant) {
+    match (bucket.as_ref().map(|b| b.limit), limit) {
+        (current, Some(limit)) if current != Some(limit) => *bucket = Some(Bucket::new(limit, now)),
+        (_, None) => *bucket = None,
+        _ => {}
+    }
+}
+
+/// Shared rate-limit buckets, keyed by provider name.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter(Arc<Mutex<HashMap<String, Buckets>>>);
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait until the provider's limits allow a request of `tokens` estimated
+    /// prompt tokens.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Overloaded` if the wait would exceed the
+    /// provider's queue timeout.
+    pub async fn pace(
+        &self,
+        provider: &ProviderConfig,
+        tokens: u64,
+        logger: &SharedLogger,
+    ) -> Result<()> {
+        let wait = self.reserve_at(provider, tokens, Instant::now())?;
+        if !wait.is_zero() {
+            logger.debug(
+                "ratelimit",
+                format!(
+                    "Pacing request to {} ({tokens} estimated tokens): waiting {:.1}s",
+                    provider.name,
+                    wait.as_secs_f64()
+                ),
+            );
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    /// Take a request's share of the provider's buckets, returning how long it
+    /// must wait before being sent. Nothing is taken if the wait would exceed
+    /// the provider's queue timeout.
+    fn reserve_at(&self, provider: &ProviderConfig, tokens: u64, now: Instant) -> Result<Duration> {
+        if provider.requests_per_minute.is_none() && provider.tokens_per_minute.is_none() {
+            return Ok(Duration::ZERO);
+        }
+        let mut all = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let buckets = all.entry(provider.name.clone()).or_default();
+        sync(&mut buckets.requests, provider.requests_per_minute, now);
+        sync(&mut buckets.tokens, provider.tokens_per_minute, now);
+
+        let mut wait = Duration::ZERO;
+        for (bucket, cost) in [(&mut buckets.requests, 1), (&mut buckets.tokens, tokens)] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                wait = wait.max(bucket.wait_for(cost));
+            }
+        }
+        let timeout = provider.queue_timeout();
+        if wait > timeout {
+            return Err(ProxyError::overloaded(format!(
+                "{} is rate limited by the proxy: the request would wait {}s, \
+                 over the {}s queue timeout",
+                provider.name,
+                wait.as_secs_f64().ceil(),
+                timeout.as_secs()
+            )));
+        }
+        if let Some(bucket) = &mut buckets.requests {
+            bucket.take(1);
+        }
+        if let Some(bucket) = &mut buckets.tokens {
+            bucket.take(tokens);
+        }
+        Ok(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(rpm: Option<u64>, tpm: Option<u64>) -> ProviderConfig {
+        let mut provider: ProviderConfig =
+            serde_json::from_value(serde_json::json!({ "name": "groq" })).unwrap();
+        provider.requests_per_minute = rpm;
+        provider.tokens_per_minute = tpm;
+        provider
+    }
+
+    #[test]
+    fn test_paces_requests_and_tokens() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        // 60 rpm: a burst of 60, then one per second
+        let rpm = provider(Some(60), None);
+        for _ in 0..60 {
+            assert_eq!(limiter.reserve_at(&rpm, 0, now).unwrap(), Duration::ZERO);
+        }
+        assert_eq!(
+            limiter.reserve_at(&rpm, 0, now).unwrap(),
+            Duration::from_secs(1)
+        );
+        // The next waits behind the one already queued
+        assert_eq!(
+            limiter.reserve_at(&rpm, 0, now).unwrap(),
+            Duration::from_secs(2)
+        );
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve_at(&rpm, 0, later).unwrap(), Duration::ZERO);
+
+        // 6000 tpm refills 100 tokens a second; a request over the limit only
+        // needs a full bucket
+        let tpm = provider(None, Some(6000));
+        assert_eq!(limiter.reserve_at(&tpm, 5000, now).unwrap(), Duration::ZERO);
+        assert_eq!(
+            limiter.reserve_at(&tpm, 1500, now).unwrap(),
+            Duration::from_secs(5)
+        );
+        assert!(limiter
+            .reserve_at(&tpm, 9000, now + Duration::from_secs(70))
+            .is_ok());
+
+        // Unlimited providers never wait
+        let open = provider(None, None);
+        assert_eq!(
+            limiter.reserve_at(&open, 1_000_000, now).unwrap(),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_sheds_past_queue_timeout() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        let mut rpm = provider(Some(1), None);
+        rpm.queue_timeout_secs = Some(30);
+        assert!(limiter.reserve_at(&rpm, 0, now).is_ok());
+        let err = limiter.reserve_at(&rpm, 0, now).unwrap_err();
+        assert!(matches!(err, ProxyError::Overloaded { .. }));
+        // Nothing was reserved by the shed request
+        assert_eq!(
+            limiter
+                .reserve_at(&rpm, 0, now + Duration::from_secs(31))
+                .unwrap(),
+            Duration::from_secs(29)
+        );
+    }
+}
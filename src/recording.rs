@@ -0,0 +1,198 @@
+//! Record upstream exchanges to disk and replay them later.
+//!
+//! With `[record] dir` set, every translated request writes one JSON file per
+//! exchange: the upstream URL and request body, the response status, and either
+//! the response body or, for streams, the sequence of chunks as received.
+//!
+//! A provider with `format = "replay"` serves those files instead of calling
+//! out. Its `base_url` is the recordings directory. Recordings are keyed by
+//! the incoming Anthropic request (minus `metadata`, which carries per-session
+//! ids), so a replay works whichever provider made the recording.
+
+use crate::error::{ProxyError, Result};
+use crate::providers::ApiFormat;
+use crate::storage::fnv1a;
+use crate::translate::anthropic_types::MessagesRequest;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// One recorded upstream exchange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub key: String,
+    pub recorded_at: DateTime<Utc>,
+    pub provider: String,
+    /// Wire format of `request` and the response (`"openai"` or `"cohere"`).
+    pub format: String,
+    pub url: String,
+    pub request: serde_json::Value,
+    pub status: u16,
+    /// Response body, for non-streaming exchanges and error responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Response chunks in arrival order, for successful streams. Split on line
+    /// boundaries so multi-byte characters are never cut.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<String>>,
+}
+
+impl Recording {
+    #[must_use]
+    pub fn new(key: String, provider: &str, format: ApiFormat, url: &str, request: &[u8]) -> Self {
+        Self {
+            key,
+            recorded_at: Utc::now(),
+            provider: provider.to_string(),
+            format: format.as_str().to_string(),
+            url: url.to_string(),
+            request: serde_json::from_slice(request).unwrap_or(serde_json::Value::Null),
+            status: 0,
+            body: None,
+            chunks: None,
+        }
+    }
+
+    /// The wire format the response was recorded in.
+    #[must_use]
+    pub fn api_format(&self) -> ApiFormat {
+        ApiFormat::from_name(&self.format).unwrap_or(ApiFormat::OpenAI)
+    }
+}
+
+/// Writes recordings when `[record] dir` is set; a no-op otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    dir: Option<PathBuf>,
+}
+
+impl Recorder {
+    #[must_use]
+    pub fn new(dir: Option<&str>) -> Self {
+        Self {
+            dir: dir.map(PathBuf::from),
+        }
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Write a recording to `<dir>/<key>.json`, replacing any earlier one.
+    /// Synchronous, like the logger, so a stream can be saved as it's dropped.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Io` if the directory or file can't be written.
+    pub fn save(&self, recording: &Recording) -> Result<()> {
+        let Some(ref dir) = self.dir else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)?;
+        let json = serde_json::to_vec_pretty(recording)?;
+        std::fs::write(recording_path(dir, &recording.key), json)?;
+        Ok(())
+    }
+}
+
+/// Load the recording for `key` from a replay directory.
+///
+/// # Errors
+/// Returns `ProxyError::Provider` if there is no recording for the request, so
+/// the fallback chain can move on, and `ProxyError::Json` if it's malformed.
+pub async fn load(dir: &Path, key: &str) -> Result<Recording> {
+    let path = recording_path(dir, key);
+    let data = tokio::fs::read(&path)
+        .await
+        .map_err(|e| ProxyError::provider(format!("No recording at {}: {e}", path.display())))?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Stable key for a request: an FNV-1a hash of its JSON form without `metadata`.
+#[must_use]
+pub fn request_key(req: &MessagesRequest) -> String {
+    let mut value = serde_json::to_value(req).unwrap_or_default();
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("metadata");
+    }
+    // `Value` objects are sorted maps, so the serialization is canonical.
+    let bytes = serde_json::to_vec(&value).unwrap_or_default();
+    format!("{:016x}", fnv1a(&bytes))
+}
+
+fn recording_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{key}.json"))
+}
+
+/// Splits a byte stream into chunks that end on line boundaries.
+#[derive(Debug, Default)]
+pub struct ChunkRecorder {
+    chunks: Vec<String>,
+    pending: Vec<u8>,
+}
+
+impl ChunkRecorder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        if let Some(pos) = self.pending.iter().rposition(|&b| b == b'\n') {
+            let complete: Vec<u8> = self.pending.drain(..=pos).collect();
+            self.chunks
+                .push(String::from_utf8_lossy(&complete).into_owned());
+        }
+    }
+
+    #[must_use]
+    pub fn finish(mut self) -> Vec<String> {
+        if !self.pending.is_empty() {
+            self.chunks
+                .push(String::from_utf8_lossy(&self.pending).into_owned());
+        }
+        self.chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: serde_json::Value) -> MessagesRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_request_key_ignores_metadata() {
+        let a = request(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "metadata": {"user_id": "session-1"},
+            "messages": [{"role": "user", "content": "Hi"}]
+        }));
+        let b = request(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "metadata": {"user_id": "session-2"},
+            "messages": [{"role": "user", "content": "Hi"}]
+        }));
+        let c = request(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hello"}]
+        }));
+
+        assert_eq!(request_key(&a), request_key(&b));
+        assert_ne!(request_key(&a), request_key(&c));
+    }
+
+    #[test]
+    fn test_chunk_recorder_splits_on_lines() {
+        let mut rec = ChunkRecorder::default();
+        let text = "data: {\"x\":\"é\"}\n\n";
+        let (head, tail) = text.as_bytes().split_at(13); // inside the 'é'
+        rec.push(head);
+        rec.push(tail);
+        rec.push(b"data: [DONE]");
+
+        assert_eq!(rec.finish(), vec![text.to_string(), "data: [DONE]".into()]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Model pricing and capability registry.
+//!
+//! `[registry] file` names a TOML or JSON file (by extension) of per-model
+//! prices and limits, kept apart from the main config so it can be refreshed
+//! as providers add models, without a proxy release:
+//!
+//! ```toml
+//! [models."gpt-4o"]
+//! input = 2.5                # USD per million tokens
+//! output = 10.0
+//! context_window = 128000
+//! max_output_tokens = 16384
+//!
+//! [models."groq:llama-3.3-70b-versatile"]   # one provider's copy
+//! input = 0.59
+//! output = 0.79
+//! ```
+//!
+//! Entries are keyed like `[costs.prices]`: by upstream model name, or by
+//! `"<provider>:<model>"`, which wins. A `[costs.prices]` entry wins over the
+//! registry's price. The file is re-read whenever it changes (see [`watch`]),
+//! and on `POST /admin/registry/reload`.
+
+use crate::config::ModelPrice;
+use crate::error::{ProxyError, Result};
+use crate::state::AppState;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How often the registry file's modification time is checked. The path can
+/// change with a config reload, so the file is polled rather than watched.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What the registry knows about one model. Every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// USD per million input tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<f64>,
+    /// USD per million output tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<f64>,
+    /// Input plus output tokens the model accepts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u64>,
+    /// Most output tokens the model accepts in `max_tokens`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u64>,
+}
+
+impl ModelInfo {
+    /// The model's price, if both input and output prices are known.
+    #[must_use]
+    pub fn price(&self) -> Option<ModelPrice> {
+        Some(ModelPrice {
+            input: self.input?,
+            output: self.output?,
+        })
+    }
+
+    /// Clamp a requested `max_tokens` to the model's limits: at most
+    /// `max_output_tokens`, and no more than the context window leaves after
+    /// `input_tokens` (while that leaves any room at all).
+    #[must_use]
+    pub fn clamp_max_tokens(&self, max_tokens: u64, input_tokens: u64) -> u64 {
+        let mut limit = self.max_output_tokens.unwrap_or(u64::MAX);
+        if let Some(room) = self
+            .context_window
+            .and_then(|window| window.checked_sub(input_tokens))
+            .filter(|&room| room > 0)
+        {
+            limit = limit.min(room);
+        }
+        max_tokens.min(limit)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    models: HashMap<String, ModelInfo>,
+}
+
+/// Parse a registry file: JSON if the path ends in `.json`, else TOML.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the file can't be read or parsed.
+pub fn load(path: &Path) -> Result<HashMap<String, ModelInfo>> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        ProxyError::config(format!(
+            "Failed to read model registry {}: {e}",
+            path.display()
+        ))
+    })?;
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let file: RegistryFile = if is_json {
+        serde_json::from_str(&content).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&content).map_err(|e| e.to_string())
+    }
+    .map_err(|e| {
+        ProxyError::config(format!(
+            "Failed to parse model registry {}: {e}",
+            path.display()
+        ))
+    })?;
+    Ok(file.models)
+}
+
+/// The loaded registry, shared across requests and replaced on reload.
+#[derive(Debug, Clone, Default)]
+pub struct ModelRegistry(Arc<RwLock<HashMap<String, ModelInfo>>>);
+
+impl ModelRegistry {
+    #[must_use]
+    pub fn new(models: HashMap<String, ModelInfo>) -> Self {
+        Self(Arc::new(RwLock::new(models)))
+    }
+
+    /// What's known about `model` on `provider`, preferring a provider-specific
+    /// entry.
+    #[must_use]
+    pub fn get(&self, provider: &str, model: &str) -> Option<ModelInfo> {
+        let models = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        models
+            .get(&format!("{provider}:{model}"))
+            .or_else(|| models.get(model))
+            .cloned()
+    }
+
+    /// Every entry, sorted by key.
+    #[must_use]
+    pub fn entries(&self) -> Vec<(String, ModelInfo)> {
+        let models = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        let mut entries: Vec<(String, ModelInfo)> = models
+            .iter()
+            .map(|(key, info)| (key.clone(), info.clone()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Replace the registry's entries.
+    pub fn replace(&self, models: HashMap<String, ModelInfo>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = models;
+    }
+}
+
+/// Load the configured registry file into `state.registry`, returning the
+/// number of entries. With no file configured the registry is emptied.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the file can't be read or parsed; the
+/// registry is left as it was.
+pub fn reload(state: &AppState) -> Result<usize> {
+    let config = state.config.load();
+    let models = match config.registry.file.as_deref() {
+        Some(path) => load(Path::new(path))?,
+        None => HashMap::new(),
+    };
+    let count = models.len();
+    state.registry.replace(models);
+    Ok(count)
+}
+
+/// Re-read the registry whenever the file (or the configured path) changes.
+/// A file that fails to load is logged and the previous entries kept.
+pub fn watch(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut last: Option<(PathBuf, Option<SystemTime>)> = None;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let Some(path) = state.config.load().registry.file.clone().map(PathBuf::from) else {
+                continue;
+            };
+            let modified = tokio::fs::metadata(&path)
+                .await
+                .and_then(|m| m.modified())
+                .ok();
+            let current = Some((path.clone(), modified));
+            if last.is_none() {
+                // The file was loaded at startup
+                last = current;
+                continue;
+            }
+            if modified.is_none() || last == current {
+                continue;
+            }
+            last = current;
+            match reload(&state) {
+                Ok(count) => {
+                    state.logger.info(
+                        "registry",
+                        format!("Reloaded {count} models from {}", path.display()),
+                    );
+                    state.journal.record(
+                        "registry_reloaded",
+                        serde_json::json!({ "source": "file", "models": count }),
+                    );
+                }
+                Err(e) => state.logger.error(
+                    "registry",
+                    format!("Ignoring changed model registry: {e}. Keeping the loaded one."),
+                ),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml_path = dir.path().join("models.toml");
+        std::fs::write(
+            &toml_path,
+            r#"
+[models."gpt-4o"]
+input = 2.5
+output = 10.0
+context_window = 128000
+
+[models."groq:gpt-4o"]
+input = 1.0
+"#,
+        )
+        .unwrap();
+        let registry = ModelRegistry::new(load(&toml_path).unwrap());
+        let info = registry.get("openai", "gpt-4o").unwrap();
+        assert_eq!(
+            info.price(),
+            Some(ModelPrice {
+                input: 2.5,
+                output: 10.0
+            })
+        );
+        assert_eq!(registry.get("groq", "gpt-4o").unwrap().price(), None);
+        assert!(registry.get("openai", "gpt-5").is_none());
+
+        let json_path = dir.path().join("models.json");
+        std::fs::write(
+            &json_path,
+            r#"{"models": {"kimi": {"max_output_tokens": 8192}}}"#,
+        )
+        .unwrap();
+        registry.replace(load(&json_path).unwrap());
+        assert_eq!(registry.entries().len(), 1);
+        assert!(load(&dir.path().join("missing.toml")).is_err());
+    }
+
+    #[test]
+    fn test_clamp_max_tokens() {
+        let info = ModelInfo {
+            context_window: Some(10_000),
+            max_output_tokens: Some(4096),
+            ..ModelInfo::default()
+        };
+        assert_eq!(info.clamp_max_tokens(32_000, 1000), 4096);
+        assert_eq!(info.clamp_max_tokens(32_000, 8000), 2000);
+        assert_eq!(info.clamp_max_tokens(100, 8000), 100);
+        // A prompt over the window is left for the provider to reject
+        assert_eq!(info.clamp_max_tokens(32_000, 12_000), 4096);
+        assert_eq!(ModelInfo::default().clamp_max_tokens(32_000, 0), 32_000);
+    }
+}
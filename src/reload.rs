@@ -0,0 +1,326 @@
+//! Hot reload of the config file.
+//!
+//! [`watch`] subscribes to file-system events for the file (inotify, `FSEvents`
+//! or `ReadDirectoryChangesW`, through [`notify`]) and, when it changes, parses
+//! and validates the new contents and swaps them into [`AppState::config`].
+//! Requests already in flight finish with the config they started with. A file
+//! that fails to parse or validate is logged and ignored, so a half-saved edit
+//! never takes the proxy down.
+//!
+//! Model mappings, providers, fallback, translation options, `[auth]`,
+//! `[retry_budget]`, `[health_check]` and log sinks take effect immediately.
+//! Settings read once at startup — `port`, `max_request_mb`, `[storage]`,
+//! `[record]`, `[capture]`, `[audit]`, the log file, `[journal]`,
+//! `[encryption]`, `[tls]` and `[outbound]` — still need a restart.
+//!
+//! Each reload is recorded in the [`crate::journal`] with the sections it
+//! changed, and a rejected file with the reason.
+
+use crate::config::ProxyConfig;
+use crate::error::Result;
+use crate::state::AppState;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait after a change before reading the file, so an editor that
+/// saves in several writes (or by renaming a temporary file) has finished.
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// Watch `path` and reload it into `state` whenever it changes. `overrides` is
+/// applied to every reloaded config, so command-line flags keep precedence.
+///
+/// The file's directory is watched rather than the file itself, so a save
+/// that replaces the file is still seen. If the watch can't be set up, the
+/// error is logged and the proxy runs on without hot reload.
+pub fn watch<F>(state: Arc<AppState>, path: PathBuf, overrides: F) -> tokio::task::JoinHandle<()>
+where
+    F: Fn(&mut ProxyConfig) + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        let (tx, mut changes) = tokio::sync::mpsc::unbounded_channel();
+        let name = path.file_name().map(OsString::from);
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok_and(|event| changes_file(&event, name.as_deref())) {
+                let _ = tx.send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(directory(&path), RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        // Dropping the watcher would end the events
+        let _watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                state.logger.error(
+                    "config",
+                    format!(
+                        "Can't watch {} for changes: {e}. Restart to apply edits.",
+                        path.display()
+                    ),
+                );
+                return;
+            }
+        };
+        while changes.recv().await.is_some() {
+            tokio::time::sleep(SETTLE).await;
+            while changes.try_recv().is_ok() {}
+            // A missing file is usually an editor mid-save; wait for it to return
+            if !path.exists() {
+                continue;
+            }
+            if let Err(e) = reload(&state, &path, &overrides) {
+                state.logger.error(
+                    "config",
+                    format!(
+                        "Ignoring changed config {}: {e}. Keeping the running config.",
+                        path.display()
+                    ),
+                );
+                state.journal.record(
+                    "config_rejected",
+                    serde_json::json!({ "path": path, "error": e.to_string() }),
+                );
+            }
+        }
+    })
+}
+
+/// The directory holding `path`, which is watched in its place.
+fn directory(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Whether `event` writes, creates or removes the file called `name`. Reads
+/// are ignored, as reloading reads the file itself.
+fn changes_file(event: &notify::Event, name: Option<&OsStr>) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) && event.paths.iter().any(|p| p.file_name() == name)
+}
+
+/// Load `path`, apply `overrides`, and make it the active config.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the file can't be read, parsed or validated,
+/// and `ProxyError::Io` if a log sink can't be opened. The running config is
+/// left untouched on error.
+pub fn reload(state: &AppState, path: &Path, overrides: &impl Fn(&mut ProxyConfig)) -> Result<()> {
+    let mut config = ProxyConfig::load(path)?;
+    overrides(&mut config);
+    config.validate()?;
+    let sinks = crate::sinks::build(&config.logging.sinks)?;
+    crate::keychain::load(&config);
+    crate::key_file::load(&config);
+
+    let previous = state.config.load();
+    let restart_needed = restart_only_changes(&previous, &config);
+    let changed = changed_sections(&previous, &config);
+    let budget = (previous.retry_budget != config.retry_budget).then(|| {
+        serde_json::json!({
+            "old": previous.redacted().retry_budget,
+            "new": config.redacted().retry_budget,
+        })
+    });
+    state.logger.set_sinks(sinks);
+    state.config.store(config);
+
+    state
+        .logger
+        .info("config", format!("Reloaded config from {}", path.display()));
+    if !restart_needed.is_empty() {
+        state.logger.warn(
+            "config",
+            format!(
+                "Changes to {} take effect after a restart",
+                restart_needed.join(", ")
+            ),
+        );
+    }
+    state.journal.record(
+        "config_reloaded",
+        serde_json::json!({ "path": path, "changed": changed }),
+    );
+    if let Some(budget) = budget {
+        state.journal.record("budget_changed", budget);
+    }
+    Ok(())
+}
+
+/// Top-level keys and sections whose values differ between two configs.
+fn changed_sections(old: &ProxyConfig, new: &ProxyConfig) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    let mut changed: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect();
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+/// Sections that changed but are only read at startup.
+fn restart_only_changes(old: &ProxyConfig, new: &ProxyConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if old.port != new.port {
+        changed.push("port");
+    }
+    if old.max_request_mb != new.max_request_mb {
+        changed.push("max_request_mb");
+    }
+    if old.storage.backend != new.storage.backend || old.storage.path != new.storage.path {
+        changed.push("[storage]");
+    }
+    if old.record.dir != new.record.dir {
+        changed.push("[record]");
+    }
+    if old.capture.dir != new.capture.dir {
+        changed.push("[capture]");
+    }
+    if old.audit.enabled != new.audit.enabled || old.audit.capacity != new.audit.capacity {
+        changed.push("[audit]");
+    }
+    if old.logging.file != new.logging.file {
+        changed.push("logging.file");
+    }
+    if old.journal.file != new.journal.file {
+        changed.push("[journal]");
+    }
+    if old.encryption.key_env != new.encryption.key_env
+        || old.encryption.key_file != new.encryption.key_file
+    {
+        changed.push("[encryption]");
+    }
+    if old.tls != new.tls {
+        changed.push("[tls]");
+    }
+    if old.outbound != new.outbound {
+        changed.push("[outbound]");
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::SharedLogger;
+
+    const CONFIG: &str = r#"
+[provider]
+name = "groq"
+
+[models]
+"claude-sonnet-4-20250514" = "llama-3.3-70b-versatile"
+"#;
+
+    #[test]
+    fn test_reload_swaps_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claude-proxy.toml");
+        std::fs::write(&path, CONFIG).unwrap();
+        let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+        let state = AppState::new(
+            ProxyConfig::load(&path).unwrap(),
+            reqwest::Client::new(),
+            logger,
+        );
+        let before = state.config.load();
+
+        std::fs::write(
+            &path,
+            CONFIG.replace("llama-3.3-70b-versatile", "moonshotai/kimi-k2-instruct"),
+        )
+        .unwrap();
+        reload(&state, &path, &|c: &mut ProxyConfig| c.port = 9000).unwrap();
+        let after = state.config.load();
+        assert_eq!(
+            after.route("claude-sonnet-4-20250514").unwrap().model,
+            "moonshotai/kimi-k2-instruct"
+        );
+        assert_eq!(after.port, 9000);
+        assert_eq!(restart_only_changes(&before, &after), vec!["port"]);
+        assert_eq!(changed_sections(&before, &after), vec!["models", "port"]);
+        // Snapshots taken before the reload are unaffected
+        assert_eq!(
+            before.route("claude-sonnet-4-20250514").unwrap().model,
+            "llama-3.3-70b-versatile"
+        );
+
+        std::fs::write(&path, "fallback = [\"nowhere\"]\n".to_string() + CONFIG).unwrap();
+        assert!(reload(&state, &path, &|_: &mut ProxyConfig| {}).is_err());
+        assert_eq!(state.config.load().port, 9000);
+
+        // Sections read only at startup are reported as needing a restart
+        let before = state.config.load();
+        std::fs::write(
+            &path,
+            CONFIG.to_string() + "\n[capture]\ndir = \"captures\"\n",
+        )
+        .unwrap();
+        reload(&state, &path, &|c: &mut ProxyConfig| c.port = 9000).unwrap();
+        assert_eq!(
+            restart_only_changes(&before, &state.config.load()),
+            vec!["[capture]"]
+        );
+
+        let before = state.config.load();
+        std::fs::write(
+            &path,
+            CONFIG.to_string()
+                + "\n[capture]\ndir = \"captures\"\n\n[outbound]\nsocks5 = \"127.0.0.1:1080\"\n",
+        )
+        .unwrap();
+        reload(&state, &path, &|c: &mut ProxyConfig| c.port = 9000).unwrap();
+        assert_eq!(
+            restart_only_changes(&before, &state.config.load()),
+            vec!["[outbound]"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_reloads_on_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claude-proxy.toml");
+        std::fs::write(&path, CONFIG).unwrap();
+        let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+        let state = Arc::new(AppState::new(
+            ProxyConfig::load(&path).unwrap(),
+            reqwest::Client::new(),
+            logger,
+        ));
+        let task = watch(state.clone(), path.clone(), |c: &mut ProxyConfig| {
+            c.port = 9000;
+        });
+
+        let edited = CONFIG.replace("llama-3.3-70b-versatile", "moonshotai/kimi-k2-instruct");
+        // The watch is set up in the background; keep saving until it's seen
+        for _ in 0..50 {
+            std::fs::write(&path, &edited).unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if state.config.load().port == 9000 {
+                break;
+            }
+        }
+        let config = state.config.load();
+        assert_eq!(config.port, 9000);
+        assert_eq!(
+            config.route("claude-sonnet-4-20250514").unwrap().model,
+            "moonshotai/kimi-k2-instruct"
+        );
+        task.abort();
+    }
+}
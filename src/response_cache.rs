@@ -0,0 +1,490 @@
+//! Cache of complete responses to repeated requests.
+//!
+//! With `[response_cache] enabled`, a successful response is kept under a hash
+//! of the provider name, the client's `[auth]` tenant and the translated
+//! upstream request, and an identical request from the same tenant later is
+//! answered from the cache without calling the provider. Only
+//! `temperature = 0` requests are cached unless `any_temperature` is set, since
+//! sampled answers are meant to vary. Streaming and non-streaming requests share
+//! entries: a cached response is replayed as a stream when one is asked for, and
+//! a stream is cached once it has run to `message_stop`.
+//!
+//! Responses are kept in memory, evicting the least recently used beyond
+//! `max_entries`, and expire after `ttl_secs`. With `persist` they are also
+//! written to `[storage]`, so they survive a restart. Claude Code's small
+//! side requests (session titles, topic checks) repeat often and gain the most.
+//!
+//! Separately, `reconnect_secs` keeps each streamed response, at any
+//! temperature, for a short window after it ends, so a client that lost the
+//! connection and sends the same request again, from the same tenant and
+//! session, is replayed the response it missed (same message id) instead of
+//! paying for a second one. Such a stream runs to the end even if its client
+//! goes away, and a request re-sent while it is still running waits for it.
+
+use crate::config::ResponseCacheConfig;
+use crate::storage::Storage;
+use crate::translate::anthropic_types::MessagesResponse;
+use crate::translate::openai_types::ChatCompletionRequest;
+
+use chrono::{DateTime, Utc};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// A cached response and when it was stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    stored_at: DateTime<Utc>,
+    response: MessagesResponse,
+}
+
+impl Entry {
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        chrono::Duration::from_std(ttl).is_ok_and(|ttl| Utc::now() - self.stored_at < ttl)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Each entry with the tick it was last used at.
+    entries: HashMap<String, (Entry, u64)>,
+    tick: u64,
+}
+
+/// A stream kept for reconnects: still running, or its response.
+enum Recent {
+    /// Sends the response once the stream ends; dropped if it fails.
+    Running(watch::Receiver<Option<MessagesResponse>>),
+    Done(Box<Entry>),
+}
+
+/// Shared response cache.
+#[derive(Clone)]
+pub struct ResponseCache {
+    memory: Arc<Mutex<Entries>>,
+    /// Streams kept for `reconnect_secs`, by key.
+    recent: Arc<Mutex<HashMap<String, Recent>>>,
+    storage: Arc<dyn Storage>,
+}
+
+/// What to do with a stream that may be a reconnect: replay the response it
+/// already got, or send it upstream, filling `slot` as it goes.
+pub enum Reconnect {
+    Replay(MessagesResponse),
+    Send(StreamSlot),
+}
+
+/// A stream's place among those kept for reconnects. Dropping it before
+/// [`finish`](Self::finish) gives the place up, and requests waiting on it are
+/// sent upstream themselves.
+pub struct StreamSlot {
+    recent: Arc<Mutex<HashMap<String, Recent>>>,
+    key: String,
+    sender: watch::Sender<Option<MessagesResponse>>,
+    finished: bool,
+}
+
+impl StreamSlot {
+    /// Keep the stream's complete response, and hand it to requests waiting.
+    pub fn finish(mut self, response: &MessagesResponse) {
+        let entry = Entry {
+            stored_at: Utc::now(),
+            response: response.clone(),
+        };
+        self.recent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(self.key.clone(), Recent::Done(Box::new(entry)));
+        let _ = self.sender.send(Some(response.clone()));
+        self.finished = true;
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.recent
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&self.key);
+        }
+    }
+}
+
+/// The cache key for a translated request to `provider` from the client
+/// authenticated with `tenant_key`, or `None` if the request shouldn't be
+/// cached.
+#[must_use]
+pub fn key(
+    config: &ResponseCacheConfig,
+    provider: &str,
+    tenant_key: Option<&str>,
+    openai_req: &ChatCompletionRequest,
+) -> Option<String> {
+    if !config.enabled {
+        return None;
+    }
+    let deterministic = openai_req
+        .temperature
+        .is_some_and(|t| t.abs() < f64::EPSILON);
+    if !deterministic && !config.any_temperature {
+        return None;
+    }
+    request_hash(provider, &[tenant_key], openai_req)
+}
+
+/// The key a streamed request from `tenant_key`'s client session
+/// `session_id` is kept under for reconnects, or `None` if `reconnect_secs`
+/// is off.
+#[must_use]
+pub fn reconnect_key(
+    config: &ResponseCacheConfig,
+    provider: &str,
+    tenant_key: Option<&str>,
+    session_id: Option<&str>,
+    openai_req: &ChatCompletionRequest,
+) -> Option<String> {
+    if config.reconnect_secs == 0 {
+        return None;
+    }
+    request_hash(provider, &[tenant_key, session_id], openai_req)
+}
+
+/// A hash of the provider, whose requests may share it (`scope`), and the
+/// translated request, streamed or not.
+fn request_hash(
+    provider: &str,
+    scope: &[Option<&str>],
+    openai_req: &ChatCompletionRequest,
+) -> Option<String> {
+    let mut value = serde_json::to_value(openai_req).ok()?;
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("stream");
+        obj.remove("stream_options");
+    }
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(provider.as_bytes());
+    ctx.update(b"\n");
+    for part in scope {
+        ctx.update(part.unwrap_or_default().as_bytes());
+        ctx.update(b"\n");
+    }
+    // `Value` objects are sorted maps, so the serialization is canonical.
+    ctx.update(value.to_string().as_bytes());
+    Some(
+        ctx.finish()
+            .as_ref()
+            .iter()
+            .fold(String::new(), |mut key, b| {
+                let _ = write!(key, "{b:02x}");
+                key
+            }),
+    )
+}
+
+impl ResponseCache {
+    #[must_use]
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            memory: Arc::new(Mutex::new(Entries::default())),
+            recent: Arc::new(Mutex::new(HashMap::new())),
+            storage,
+        }
+    }
+
+    /// The response a stream with `key` ended in within `reconnect_secs`,
+    /// waiting for it if it is still running; else a slot to send it upstream.
+    pub async fn reconnect(&self, config: &ResponseCacheConfig, key: &str) -> Reconnect {
+        loop {
+            let running = {
+                let mut recent = self.recent.lock().unwrap_or_else(PoisonError::into_inner);
+                recent.retain(|_, stream| match stream {
+                    Recent::Running(_) => true,
+                    Recent::Done(entry) => entry.is_fresh(config.reconnect_window()),
+                });
+                match recent.get(key) {
+                    Some(Recent::Done(entry)) => return Reconnect::Replay(entry.response.clone()),
+                    Some(Recent::Running(receiver)) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        recent.insert(key.to_string(), Recent::Running(receiver));
+                        return Reconnect::Send(StreamSlot {
+                            recent: self.recent.clone(),
+                            key: key.to_string(),
+                            sender,
+                            finished: false,
+                        });
+                    }
+                }
+            };
+            if let Some(response) = wait_for(running).await {
+                return Reconnect::Replay(response);
+            }
+        }
+    }
+
+    /// The cached response for `key`, if there is a fresh one.
+    #[must_use]
+    pub fn get(&self, config: &ResponseCacheConfig, key: &str) -> Option<MessagesResponse> {
+        {
+            let mut memory = self.memory.lock().unwrap_or_else(PoisonError::into_inner);
+            memory.tick += 1;
+            let tick = memory.tick;
+            match memory.entries.get_mut(key) {
+                Some((entry, _)) if !entry.is_fresh(config.ttl()) => {
+                    memory.entries.remove(key);
+                }
+                Some((entry, used)) => {
+                    *used = tick;
+                    return Some(entry.response.clone());
+                }
+                None => {}
+            }
+        }
+        if !config.persist {
+            return None;
+        }
+        let data = self.storage.cache_get(&storage_key(key)).ok()??;
+        let entry: Entry = serde_json::from_slice(&data).ok()?;
+        if !entry.is_fresh(config.ttl()) {
+            return None;
+        }
+        let response = entry.response.clone();
+        self.remember(config, key, entry);
+        Some(response)
+    }
+
+    /// Cache `response` under `key`.
+    ///
+    /// # Errors
+    /// Returns an error if `persist` is on and the response can't be written
+    /// to storage; it's still cached in memory.
+    pub fn put(
+        &self,
+        config: &ResponseCacheConfig,
+        key: &str,
+        response: &MessagesResponse,
+    ) -> crate::error::Result<()> {
+        let entry = Entry {
+            stored_at: Utc::now(),
+            response: response.clone(),
+        };
+        let persisted = if config.persist {
+            serde_json::to_vec(&entry)
+                .map_err(Into::into)
+                .and_then(|data| self.storage.cache_put(&storage_key(key), &data))
+        } else {
+            Ok(())
+        };
+        self.remember(config, key, entry);
+        persisted
+    }
+
+    fn remember(&self, config: &ResponseCacheConfig, key: &str, entry: Entry) {
+        let mut memory = self.memory.lock().unwrap_or_else(PoisonError::into_inner);
+        memory.tick += 1;
+        let tick = memory.tick;
+        memory.entries.insert(key.to_string(), (entry, tick));
+        while memory.entries.len() > config.max_entries {
+            let Some(oldest) = memory
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            memory.entries.remove(&oldest);
+        }
+    }
+}
+
+/// The response a running stream ends in, or `None` if it fails.
+async fn wait_for(
+    mut running: watch::Receiver<Option<MessagesResponse>>,
+) -> Option<MessagesResponse> {
+    let response = running.wait_for(Option::is_some).await.ok()?;
+    response.clone()
+}
+
+fn storage_key(key: &str) -> String {
+    format!("response:{key}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::SharedLogger;
+    use crate::translate::anthropic_types::Usage;
+
+    fn response(text: &str) -> MessagesResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "msg_1", "type": "message", "role": "assistant",
+            "content": [{"type": "text", "text": text}],
+            "model": "claude-3-5-haiku-20241022",
+            "stop_reason": "end_turn", "stop_sequence": null,
+            "usage": Usage::default(),
+        }))
+        .unwrap()
+    }
+
+    fn request(temperature: Option<f64>, stream: bool) -> ChatCompletionRequest<'static> {
+        serde_json::from_value(serde_json::json!({
+            "model": "llama",
+            "messages": [{"role": "user", "content": "Title this"}],
+            "temperature": temperature,
+            "stream": stream,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_key() {
+        let config = ResponseCacheConfig {
+            enabled: true,
+            ..ResponseCacheConfig::default()
+        };
+        let zero = key(&config, "groq", None, &request(Some(0.0), false)).unwrap();
+        assert_eq!(
+            key(&config, "groq", None, &request(Some(0.0), true)),
+            Some(zero.clone())
+        );
+        assert_ne!(
+            key(&config, "openai", None, &request(Some(0.0), false)),
+            Some(zero)
+        );
+        // Tenants don't share entries
+        assert_ne!(
+            key(
+                &config,
+                "groq",
+                Some("sk-team-a"),
+                &request(Some(0.0), false)
+            ),
+            key(
+                &config,
+                "groq",
+                Some("sk-team-b"),
+                &request(Some(0.0), false)
+            )
+        );
+        assert_eq!(key(&config, "groq", None, &request(Some(1.0), false)), None);
+        assert_eq!(key(&config, "groq", None, &request(None, false)), None);
+
+        let any = ResponseCacheConfig {
+            any_temperature: true,
+            ..config.clone()
+        };
+        assert!(key(&any, "groq", None, &request(Some(1.0), false)).is_some());
+        assert_eq!(
+            key(
+                &ResponseCacheConfig::default(),
+                "groq",
+                None,
+                &request(Some(0.0), false)
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SharedLogger::new(dir.path().join("proxy.log"))
+            .unwrap()
+            .storage();
+        let config = ResponseCacheConfig {
+            reconnect_secs: 60,
+            ..ResponseCacheConfig::default()
+        };
+        let cache = ResponseCache::new(storage);
+        let stream = request(Some(1.0), true);
+        assert!(reconnect_key(&config, "groq", None, None, &stream).is_some());
+        assert!(
+            reconnect_key(&ResponseCacheConfig::default(), "groq", None, None, &stream).is_none()
+        );
+        // Another tenant or session sending the same request isn't a reconnect
+        let key = reconnect_key(&config, "groq", Some("sk-a"), Some("s1"), &stream);
+        assert_ne!(
+            key,
+            reconnect_key(&config, "groq", Some("sk-b"), Some("s1"), &stream)
+        );
+        assert_ne!(
+            key,
+            reconnect_key(&config, "groq", Some("sk-a"), Some("s2"), &stream)
+        );
+
+        // A stream that fails gives its place up
+        let Reconnect::Send(slot) = cache.reconnect(&config, "a").await else {
+            panic!("nothing to replay yet");
+        };
+        drop(slot);
+        let Reconnect::Send(slot) = cache.reconnect(&config, "a").await else {
+            panic!("the failed stream isn't replayed");
+        };
+
+        // A request re-sent while the stream runs waits for its response
+        let waiting = tokio::spawn({
+            let (cache, config) = (cache.clone(), config.clone());
+            async move { cache.reconnect(&config, "a").await }
+        });
+        tokio::task::yield_now().await;
+        slot.finish(&response("A"));
+        let Reconnect::Replay(resp) = waiting.await.unwrap() else {
+            panic!("the waiting request is replayed");
+        };
+        assert_eq!(resp.id, "msg_1");
+        assert!(matches!(
+            cache.reconnect(&config, "a").await,
+            Reconnect::Replay(_)
+        ));
+
+        // Replays end with the window
+        let closed = ResponseCacheConfig {
+            reconnect_secs: 0,
+            ..config
+        };
+        assert!(matches!(
+            cache.reconnect(&closed, "a").await,
+            Reconnect::Send(_)
+        ));
+    }
+
+    #[test]
+    fn test_lru_ttl_and_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SharedLogger::new(dir.path().join("proxy.log"))
+            .unwrap()
+            .storage();
+        let mut config = ResponseCacheConfig {
+            enabled: true,
+            max_entries: 2,
+            ..ResponseCacheConfig::default()
+        };
+        let cache = ResponseCache::new(storage.clone());
+        cache.put(&config, "a", &response("A")).unwrap();
+        cache.put(&config, "b", &response("B")).unwrap();
+        assert!(cache.get(&config, "a").is_some());
+        cache.put(&config, "c", &response("C")).unwrap();
+        // "b" was least recently used
+        assert!(cache.get(&config, "b").is_none());
+        assert!(cache.get(&config, "a").is_some());
+
+        config.ttl_secs = 0;
+        assert!(cache.get(&config, "a").is_none());
+
+        config.ttl_secs = 60;
+        config.persist = true;
+        cache.put(&config, "d", &response("D")).unwrap();
+        let restarted = ResponseCache::new(storage);
+        let cached = restarted.get(&config, "d").unwrap();
+        assert_eq!(
+            serde_json::to_value(cached).unwrap()["content"][0]["text"],
+            "D"
+        );
+    }
+}
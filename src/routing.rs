@@ -0,0 +1,327 @@
+//! Provider selection across the fallback chain.
+//!
+//! [`ProviderHealth`] is a small per-provider circuit breaker: after
+//! [`FAILURE_THRESHOLD`] consecutive failures (429/5xx after retries, or network
+//! errors) a provider is skipped for [`COOLDOWN`], so requests go straight to the
+//! next provider in the chain instead of paying the retry latency every time.
+//!
+//! With `[health_check]` on, an open breaker instead stays open until a
+//! background probe passes (see [`crate::health_check`]). The provider then
+//! warms up: it is offered a growing share of the requests routed to it, and
+//! any failure in the meantime opens the breaker again.
+
+use crate::config::Route;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Consecutive failures before a provider's breaker opens.
+pub const FAILURE_THRESHOLD: u32 = 3;
+
+/// How long an open breaker keeps a provider out of the chain.
+pub const COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct HealthEntry {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    warmup: Option<Warmup>,
+}
+
+/// A provider returning to the chain after passing a health check.
+#[derive(Debug)]
+struct Warmup {
+    since: Instant,
+    duration: Duration,
+    /// Share of its requests the provider gets at the start, in (0, 1].
+    start_share: f64,
+    /// Accumulated share; a request is admitted each time it reaches 1.
+    credit: f64,
+}
+
+impl Warmup {
+    /// Share of requests the provider gets at `now`, ramping linearly to 1.
+    fn share(&self, now: Instant) -> f64 {
+        if self.duration.is_zero() {
+            return 1.0;
+        }
+        let progress =
+            now.saturating_duration_since(self.since).as_secs_f64() / self.duration.as_secs_f64();
+        (self.start_share + (1.0 - self.start_share) * progress).min(1.0)
+    }
+
+    /// Whether the next request goes to the provider. Admitted requests are
+    /// spread evenly rather than drawn at random.
+    fn admit(&mut self, now: Instant) -> bool {
+        self.credit += self.share(now);
+        if self.credit >= 1.0 {
+            self.credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Shared circuit-breaker state, keyed by provider name.
+#[derive(Debug, Clone, Default)]
+pub struct ProviderHealth(Arc<Mutex<HashMap<String, HealthEntry>>>);
+
+impl ProviderHealth {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the provider's breaker is closed (or its cooldown has elapsed).
+    #[must_use]
+    pub fn is_available(&self, provider: &str) -> bool {
+        let Ok(entries) = self.0.lock() else {
+            return true;
+        };
+        entries
+            .get(provider)
+            .and_then(|e| e.open_until)
+            .map_or(true, |until| Instant::now() >= until)
+    }
+
+    /// Whether to offer this request to the provider. An open breaker only
+    /// lapses after its cooldown when `probing` is off; a warming-up provider
+    /// gets its current share of requests.
+    #[must_use]
+    pub fn admit(&self, provider: &str, probing: bool) -> bool {
+        self.admit_at(provider, probing, Instant::now())
+    }
+
+    fn admit_at(&self, provider: &str, probing: bool, now: Instant) -> bool {
+        let Ok(mut entries) = self.0.lock() else {
+            return true;
+        };
+        let Some(entry) = entries.get_mut(provider) else {
+            return true;
+        };
+        if let Some(until) = entry.open_until {
+            return !probing && now >= until;
+        }
+        entry
+            .warmup
+            .as_mut()
+            .map_or(true, |warmup| warmup.admit(now))
+    }
+
+    pub fn record_success(&self, provider: &str) {
+        if let Ok(mut entries) = self.0.lock() {
+            match entries.get_mut(provider) {
+                Some(entry) if entry.warmup.is_some() => entry.consecutive_failures = 0,
+                _ => {
+                    entries.remove(provider);
+                }
+            }
+        }
+    }
+
+    /// Record a failure. Returns `true` if this failure opened the breaker.
+    #[must_use]
+    pub fn record_failure(&self, provider: &str) -> bool {
+        self.record_failure_at(provider, Instant::now())
+    }
+
+    fn record_failure_at(&self, provider: &str, now: Instant) -> bool {
+        let Ok(mut entries) = self.0.lock() else {
+            return false;
+        };
+        let entry = entries.entry(provider.to_string()).or_default();
+        entry.consecutive_failures += 1;
+        // One failure while warming up is enough to take the provider out
+        // again, as is one after the cooldown let it back in
+        let closed = entry.open_until.map_or(true, |until| now >= until);
+        if entry.warmup.take().is_some()
+            || entry.consecutive_failures >= FAILURE_THRESHOLD && closed
+        {
+            entry.open_until = Some(now + COOLDOWN);
+            return true;
+        }
+        false
+    }
+
+    /// Providers whose breaker is open, to be probed.
+    #[must_use]
+    pub fn open_providers(&self) -> Vec<String> {
+        let Ok(entries) = self.0.lock() else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .iter()
+            .filter(|(_, e)| e.open_until.is_some())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Close an open breaker after a passing probe, starting the provider at
+    /// `start_percent` of its requests and ramping up over `warmup`. Returns
+    /// `false` if the breaker wasn't open.
+    #[must_use]
+    pub fn reenable(&self, provider: &str, warmup: Duration, start_percent: u8) -> bool {
+        self.reenable_at(provider, warmup, start_percent, Instant::now())
+    }
+
+    fn reenable_at(
+        &self,
+        provider: &str,
+        warmup: Duration,
+        start_percent: u8,
+        now: Instant,
+    ) -> bool {
+        let Ok(mut entries) = self.0.lock() else {
+            return false;
+        };
+        let Some(entry) = entries.get_mut(provider).filter(|e| e.open_until.is_some()) else {
+            return false;
+        };
+        if warmup.is_zero() {
+            entries.remove(provider);
+            return true;
+        }
+        *entry = HealthEntry {
+            warmup: Some(Warmup {
+                since: now,
+                duration: warmup,
+                start_share: f64::from(start_percent.clamp(1, 100)) / 100.0,
+                credit: 0.5,
+            }),
+            ..HealthEntry::default()
+        };
+        true
+    }
+
+    /// End warm-ups that have run their course, returning those providers.
+    #[must_use]
+    pub fn finish_warmups(&self) -> Vec<String> {
+        let Ok(mut entries) = self.0.lock() else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        let mut done: Vec<String> = entries
+            .iter()
+            .filter(|(_, e)| e.warmup.as_ref().is_some_and(|w| w.share(now) >= 1.0))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &done {
+            entries.remove(name);
+        }
+        done.sort_unstable();
+        done
+    }
+}
+
+/// Order a fallback chain for this request: routes whose breaker is open, or
+/// that sit out this request while warming up, are dropped, unless that would
+/// leave nothing to try. `probing` is whether `[health_check]` is on.
+#[must_use]
+pub fn available_routes<'a>(
+    routes: Vec<Route<'a>>,
+    health: &ProviderHealth,
+    probing: bool,
+) -> Vec<Route<'a>> {
+    let available: Vec<Route<'a>> = routes
+        .iter()
+        .filter(|r| health.admit(&r.provider.name, probing))
+        .cloned()
+        .collect();
+    if available.is_empty() {
+        routes
+    } else {
+        available
+    }
+}
+
+/// Whether an upstream status should move on to the next provider.
+#[must_use]
+pub fn should_fall_back(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let health = ProviderHealth::new();
+        for _ in 1..FAILURE_THRESHOLD {
+            assert!(!health.record_failure("groq"));
+        }
+        assert!(health.is_available("groq"));
+        assert!(health.record_failure("groq"));
+        assert!(!health.is_available("groq"));
+        assert!(health.is_available("fireworks"));
+
+        health.record_success("groq");
+        assert!(health.is_available("groq"));
+    }
+
+    #[test]
+    fn test_breaker_reopens_after_cooldown() {
+        let health = ProviderHealth::new();
+        let now = Instant::now();
+        for _ in 0..FAILURE_THRESHOLD {
+            let _ = health.record_failure_at("groq", now);
+        }
+        assert!(!health.admit_at("groq", false, now));
+
+        // The cooldown lapses and the provider is tried again, but still fails
+        let later = now + COOLDOWN;
+        assert!(health.admit_at("groq", false, later));
+        assert!(health.record_failure_at("groq", later));
+        assert!(!health.admit_at("groq", false, later));
+        // Not tripped again until that cooldown lapses too
+        assert!(!health.record_failure_at("groq", later));
+        assert!(health.record_failure_at("groq", later + COOLDOWN));
+    }
+
+    #[test]
+    fn test_reenable_warms_up() {
+        let health = ProviderHealth::new();
+        assert!(!health.reenable("groq", Duration::from_secs(60), 10));
+        for _ in 0..FAILURE_THRESHOLD {
+            let _ = health.record_failure("groq");
+        }
+        let later = Instant::now() + COOLDOWN;
+        assert!(!health.admit_at("groq", true, later));
+        assert!(health.admit_at("groq", false, later));
+        assert_eq!(health.open_providers(), vec!["groq"]);
+
+        let now = Instant::now();
+        assert!(health.reenable_at("groq", Duration::from_secs(60), 10, now));
+        assert!(health.open_providers().is_empty());
+        let admitted = (0..100)
+            .filter(|_| health.admit_at("groq", true, now))
+            .count();
+        assert_eq!(admitted, 10);
+        let halfway = now + Duration::from_secs(30);
+        let admitted = (0..100)
+            .filter(|_| health.admit_at("groq", true, halfway))
+            .count();
+        assert!((54..=56).contains(&admitted), "{admitted}");
+
+        // Successes keep the warm-up going; a single failure ends it
+        health.record_success("groq");
+        assert!(health.finish_warmups().is_empty());
+        assert!(health.record_failure("groq"));
+        assert_eq!(health.open_providers(), vec!["groq"]);
+
+        assert!(health.reenable("groq", Duration::ZERO, 10));
+        assert!(health.admit("groq", true));
+        assert!(health.finish_warmups().is_empty());
+    }
+
+    #[test]
+    fn test_should_fall_back() {
+        assert!(should_fall_back(429));
+        assert!(should_fall_back(503));
+        assert!(!should_fall_back(400));
+        assert!(!should_fall_back(401));
+    }
+}
@@ -0,0 +1,339 @@
+//! Time windows that reorder the fallback chain (`[[schedule]]`).
+//!
+//! Each window names a provider, a cron expression (`minute hour day month
+//! weekday`) for when it is open, and whether the provider is preferred or
+//! avoided while it is: e.g. sending everything to a cheap batch provider at
+//! night, or keeping traffic off one during its maintenance window. A
+//! preferred provider moves to the front of the request's chain (the routed
+//! provider and `fallback`), and an avoided one is left out unless nothing
+//! else is left. Windows open and close on the first request routed after the
+//! time comes; each change is logged, and recorded in the journal.
+
+use crate::config::{ProxyConfig, Route, ScheduleAction, ScheduleWindow};
+use crate::error::{ProxyError, Result};
+use crate::journal::Journal;
+use crate::logging::SharedLogger;
+
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A parsed cron expression, five fields of `*`, values, `a-b` ranges and
+/// `/n` steps, separated by commas. Months and weekdays may be given by their
+/// three-letter names; Sunday is 0 or 7. As in cron, a time matches when it
+/// matches either the day of month or the weekday if both are restricted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    either_day: bool,
+}
+
+impl Cron {
+    /// # Errors
+    /// Returns `ProxyError::Config` if the expression isn't five valid fields.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(ProxyError::config(format!(
+                "Schedule '{expr}' needs five fields: minute hour day month weekday"
+            )));
+        };
+        let mut weekdays = parse_field(weekday, "weekday", 0, 7, &WEEKDAYS)?;
+        if weekdays & 1 << 7 != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            source: expr.to_string(),
+            minutes: parse_field(minute, "minute", 0, 59, &[])?,
+            hours: parse_field(hour, "hour", 0, 23, &[])?,
+            days: parse_field(day, "day", 1, 31, &[])?,
+            months: parse_field(month, "month", 1, 12, &MONTHS)?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// Whether the minute `time` falls in matches.
+    #[must_use]
+    pub fn matches(&self, time: &(impl Datelike + Timelike)) -> bool {
+        let has = |mask: u64, value: u32| mask & 1 << value != 0;
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        has(self.minutes, time.minute())
+            && has(self.hours, time.hour())
+            && has(self.months, time.month())
+            && day_matches
+    }
+}
+
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = ProxyError;
+
+    fn try_from(expr: String) -> Result<Self> {
+        Self::parse(&expr)
+    }
+}
+
+impl From<Cron> for String {
+    fn from(cron: Cron) -> Self {
+        cron.source
+    }
+}
+
+/// The values one field allows, as a bitmask.
+fn parse_field(field: &str, name: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let invalid = || ProxyError::config(format!("Invalid {name} field '{field}' in schedule"));
+    let value = |s: &str| {
+        names
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(s))
+            .and_then(|i| u32::try_from(i).ok())
+            .map(|i| i + min)
+            .or_else(|| s.parse().ok())
+            .filter(|v| (min..=max).contains(v))
+            .ok_or_else(invalid)
+    };
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: usize = step.parse().ok().filter(|s| *s > 0).ok_or_else(invalid)?;
+                (range, Some(step))
+            }
+            None => (item, None),
+        };
+        let (low, high) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((low, high)) => (value(low)?, value(high)?),
+            // `5/15` runs from 5 to the end of the range
+            None if step.is_some() => (value(range)?, max),
+            None => {
+                let v = value(range)?;
+                (v, v)
+            }
+        };
+        if low > high {
+            return Err(invalid());
+        }
+        for v in (low..=high).step_by(step.unwrap_or(1)) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+/// Whether `window` is open at `now`, read in local time unless it is `utc`.
+#[must_use]
+pub fn is_open(window: &ScheduleWindow, now: DateTime<Utc>) -> bool {
+    if window.utc {
+        window.when.matches(&now)
+    } else {
+        window.when.matches(&now.with_timezone(&Local))
+    }
+}
+
+/// The windows open at the last request routed, to log when that changes.
+#[derive(Debug, Clone, Default)]
+pub struct Schedule(Arc<Mutex<Vec<ScheduleWindow>>>);
+
+impl Schedule {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reorder a request's fallback chain by the windows open now, first
+    /// logging and journaling those that opened or closed since the last one.
+    #[must_use]
+    pub fn apply<'a>(
+        &self,
+        routes: Vec<Route<'a>>,
+        config: &ProxyConfig,
+        logger: &SharedLogger,
+        journal: &Journal,
+    ) -> Vec<Route<'a>> {
+        if config.schedule.is_empty() && self.0.lock().map_or(true, |last| last.is_empty()) {
+            return routes;
+        }
+        let now = Utc::now();
+        let open: Vec<&ScheduleWindow> = config
+            .schedule
+            .iter()
+            .filter(|window| is_open(window, now))
+            .collect();
+        self.note_changes(&open, logger, journal);
+        reorder(routes, config, &open)
+    }
+
+    fn note_changes(&self, open: &[&ScheduleWindow], logger: &SharedLogger, journal: &Journal) {
+        let Ok(mut last) = self.0.lock() else {
+            return;
+        };
+        let opened = open.iter().copied().filter(|w| !last.contains(w));
+        let closed = last.iter().filter(|w| !open.contains(w));
+        for (window, opening) in opened.map(|w| (w, true)).chain(closed.map(|w| (w, false))) {
+            let action = match window.action {
+                ScheduleAction::Prefer => "prefer",
+                ScheduleAction::Avoid => "avoid",
+            };
+            let change = if opening { "opened" } else { "closed" };
+            logger.info(
+                "schedule",
+                format!(
+                    "Window to {action} {} ({}) {change}",
+                    window.provider, window.when
+                ),
+            );
+            journal.record(
+                "schedule_window",
+                serde_json::json!({
+                    "provider": window.provider,
+                    "action": action,
+                    "when": window.when.to_string(),
+                    "open": opening,
+                }),
+            );
+        }
+        *last = open.iter().map(|w| (*w).clone()).collect();
+    }
+}
+
+/// Move preferred providers to the front of the chain, and drop avoided ones
+/// unless they are all there is.
+fn reorder<'a>(
+    routes: Vec<Route<'a>>,
+    config: &ProxyConfig,
+    open: &[&ScheduleWindow],
+) -> Vec<Route<'a>> {
+    let named = |route: &Route, action: ScheduleAction| {
+        open.iter().any(|w| {
+            w.action == action
+                && config
+                    .named_provider(&w.provider)
+                    .is_some_and(|p| p.name == route.provider.name)
+        })
+    };
+    let (avoided, kept): (Vec<_>, Vec<_>) = routes
+        .into_iter()
+        .partition(|r| named(r, ScheduleAction::Avoid));
+    if kept.is_empty() {
+        return avoided;
+    }
+    let (mut preferred, rest): (Vec<_>, Vec<_>) = kept
+        .into_iter()
+        .partition(|r| named(r, ScheduleAction::Prefer));
+    preferred.extend(rest);
+    preferred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderConfig;
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_cron_matches() {
+        // Nights on weekdays
+        let nights = Cron::parse("* 22-23,0-5 * * mon-fri").unwrap();
+        assert!(nights.matches(&at("2026-10-14 23:30"))); // Wednesday
+        assert!(nights.matches(&at("2026-10-15 04:59")));
+        assert!(!nights.matches(&at("2026-10-15 06:00")));
+        assert!(!nights.matches(&at("2026-10-17 23:30"))); // Saturday
+
+        let quarter_hours = Cron::parse("*/15 * * * *").unwrap();
+        assert!(quarter_hours.matches(&at("2026-10-14 10:45")));
+        assert!(!quarter_hours.matches(&at("2026-10-14 10:46")));
+
+        // Both days restricted: either one matches; Sunday may be 7
+        let either = Cron::parse("0 3 1 * 7").unwrap();
+        assert!(either.matches(&at("2026-10-01 03:00"))); // Thursday the 1st
+        assert!(either.matches(&at("2026-10-18 03:00"))); // Sunday
+        assert!(!either.matches(&at("2026-10-19 03:00")));
+        let dec = Cron::parse("* * * DEC *").unwrap();
+        let date = NaiveDate::from_ymd_opt(2026, 12, 25).unwrap();
+        assert!(dec.matches(&date.and_hms_opt(12, 0, 0).unwrap()));
+
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "* 5-2 * * *",
+            "*/0 * * * *",
+            "* * * * fun",
+        ] {
+            assert!(Cron::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut config = ProxyConfig::default();
+        config.provider.name = "fireworks".to_string();
+        for name in ["groq", "batch"] {
+            config.providers.insert(
+                name.to_string(),
+                ProviderConfig {
+                    name: name.to_string(),
+                    ..ProviderConfig::default()
+                },
+            );
+        }
+        config.fallback = vec!["groq".to_string(), "batch".to_string()];
+        let window = |provider: &str, action| ScheduleWindow {
+            provider: provider.to_string(),
+            when: Cron::parse("* * * * *").unwrap(),
+            action,
+            utc: false,
+        };
+        let names = |routes: Vec<Route>| {
+            routes
+                .iter()
+                .map(|r| r.provider.name.clone())
+                .collect::<Vec<_>>()
+        };
+        let prefer = window("batch", ScheduleAction::Prefer);
+        let avoid = window("fireworks", ScheduleAction::Avoid);
+        let routes = config.routes("claude-sonnet-4-5").unwrap();
+        assert_eq!(
+            names(reorder(routes.clone(), &config, &[&prefer, &avoid])),
+            ["batch", "groq"]
+        );
+
+        // Avoided providers are kept when nothing else is left
+        let avoid_all: Vec<ScheduleWindow> = ["fireworks", "groq", "batch"]
+            .iter()
+            .map(|p| window(p, ScheduleAction::Avoid))
+            .collect();
+        let open: Vec<&ScheduleWindow> = avoid_all.iter().collect();
+        assert_eq!(
+            names(reorder(routes, &config, &open)),
+            ["fireworks", "groq", "batch"]
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Startup self-test (`--self-test`).
+//!
+//! [`run`] serves a copy of the full router on a loopback port, with every
+//! provider pointed at a built-in mock `OpenAI`-compatible upstream, and sends
+//! it a health check, a non-streaming and a streaming `/v1/messages` request.
+//! The requests go through the same auth, routing, translation and SSE code
+//! as real traffic, so a broken config or build fails before any client
+//! connects. Nothing is recorded, captured or logged to the real log.
+
+use crate::config::ProxyConfig;
+use crate::error::{ProxyError, Result};
+use crate::logging::{LogEntry, SharedLogger};
+use crate::server::build_router;
+use crate::state::AppState;
+use crate::storage::{Storage, UsageRecord};
+use crate::translate::sse;
+
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The text the mock upstream answers every request with.
+const REPLY: &str = "Self-test OK";
+
+/// Model requested when the config maps none.
+const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+
+/// How long each self-test request may take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run the self-test against `config`, returning a line per passed check.
+///
+/// # Errors
+/// Returns `ProxyError::Other` describing the first check that failed.
+pub async fn run(config: &ProxyConfig) -> Result<Vec<String>> {
+    let (upstream_addr, upstream) = serve(mock_upstream()).await?;
+    let state = Arc::new(AppState::new(
+        test_config(config, &format!("http://{upstream_addr}")),
+        reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(ProxyError::Http)?,
+        SharedLogger::with_storage(Arc::new(NoStorage)),
+    ));
+    let (addr, proxy) = serve(build_router(state)).await?;
+
+    let result = check(config, addr).await;
+    proxy.abort();
+    upstream.abort();
+    result
+}
+
+/// The config under test, with every provider sent to the mock upstream and
+/// nothing written to disk.
+fn test_config(config: &ProxyConfig, upstream_url: &str) -> ProxyConfig {
+    let mut config = config.clone();
+    for provider in std::iter::once(&mut config.provider).chain(config.providers.values_mut()) {
+        provider.base_url = Some(upstream_url.to_string());
+        provider.format = Some("openai".to_string());
+        provider.api_key = Some("self-test".to_string());
+    }
+    config.record.dir = None;
+    config.capture.dir = None;
+    config.audit.enabled = false;
+    config
+}
+
+async fn check(config: &ProxyConfig, addr: SocketAddr) -> Result<Vec<String>> {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(ProxyError::Http)?;
+    let base = format!("http://{addr}");
+    let model = config
+        .models
+        .keys()
+        .min()
+        .map_or(DEFAULT_MODEL, String::as_str);
+    let key = config.auth.accepted_keys().into_iter().next();
+    let mut passed = Vec::new();
+
+    let status = client
+        .get(format!("{base}/health"))
+        .send()
+        .await
+        .map_err(|e| failed(&format!("GET /health: {e}")))?
+        .status();
+    if !status.is_success() {
+        return Err(failed(&format!("GET /health answered {status}")));
+    }
+    passed.push("GET /health".to_string());
+
+    for stream in [false, true] {
+        let kind = if stream { "streaming" } else { "non-streaming" };
+        let mut request = client
+            .post(format!("{base}/v1/messages"))
+            .header("anthropic-version", "2023-06-01")
+            .json(&serde_json::json!({
+                "model": model,
+                "max_tokens": 32,
+                "stream": stream,
+                "messages": [{"role": "user", "content": "Reply with a greeting."}],
+            }));
+        if let Some(ref key) = key {
+            request = request.header("x-api-key", key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| failed(&format!("{kind} request: {e}")))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| failed(&format!("{kind} response: {e}")))?;
+        if !status.is_success() {
+            return Err(failed(&format!("{kind} request answered {status}: {body}")));
+        }
+        let text = if stream {
+            streamed_text(&body)
+        } else {
+            response_text(&body)
+        };
+        if text != REPLY {
+            return Err(failed(&format!(
+                "{kind} request returned {text:?}, expected {REPLY:?}"
+            )));
+        }
+        passed.push(format!("POST /v1/messages ({kind}, model {model})"));
+    }
+    Ok(passed)
+}
+
+fn failed(message: &str) -> ProxyError {
+    ProxyError::other(format!("Self-test failed: {message}"))
+}
+
+/// The text of a non-streaming Anthropic response.
+fn response_text(body: &str) -> String {
+    let Ok(response) = serde_json::from_str::<serde_json::Value>(body) else {
+        return String::new();
+    };
+    response["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|block| block["text"].as_str())
+        .collect()
+}
+
+/// The text deltas of an Anthropic SSE stream, or nothing if the stream
+/// didn't end with `message_stop`.
+fn streamed_text(body: &str) -> String {
+    let events: Vec<serde_json::Value> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .filter_map(|data| serde_json::from_str(data.trim()).ok())
+        .collect();
+    if events.last().and_then(|e| e["type"].as_str()) != Some("message_stop") {
+        return String::new();
+    }
+    events
+        .iter()
+        .filter_map(|e| e["delta"]["text"].as_str())
+        .collect()
+}
+
+async fn serve(app: Router) -> Result<(SocketAddr, tokio::task::JoinHandle<()>)> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let handle = tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    Ok((addr, handle))
+}
+
+/// An `OpenAI`-compatible upstream answering every chat completion with [`REPLY`].
+fn mock_upstream() -> Router {
+    Router::new().route("/chat/completions", post(mock_completion))
+}
+
+async fn mock_completion(Json(request): Json<serde_json::Value>) -> Response {
+    let model = request["model"].as_str().unwrap_or("mock").to_string();
+    if request["stream"].as_bool() != Some(true) {
+        return Json(serde_json::json!({
+            "id": "chatcmpl-self-test",
+            "object": "chat.completion",
+            "created": 0,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": REPLY},
+                "finish_reason": "stop",
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+        }))
+        .into_response();
+    }
+
+    let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| {
+        let chunk = serde_json::json!({
+            "id": "chatcmpl-self-test",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": model,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+        });
+        sse::frame(None, &chunk.to_string())
+    };
+    let body = [
+        chunk(
+            serde_json::json!({"role": "assistant", "content": REPLY}),
+            None,
+        ),
+        chunk(serde_json::json!({}), Some("stop")),
+        sse::frame(None, "[DONE]"),
+    ]
+    .concat();
+    ([("content-type", "text/event-stream")], body).into_response()
+}
+
+/// Storage that keeps nothing, so self-test traffic stays out of the real log
+/// and usage records.
+pub(crate) struct NoStorage;
+
+impl Storage for NoStorage {
+    fn append_log(&self, _entry: &LogEntry) -> Result<()> {
+        Ok(())
+    }
+
+    fn recent_logs(&self, _limit: usize) -> Result<Vec<LogEntry>> {
+        Ok(Vec::new())
+    }
+
+    fn record_usage(&self, _record: &UsageRecord) -> Result<()> {
+        Ok(())
+    }
+
+    fn usage(&self, _since: Option<DateTime<Utc>>) -> Result<Vec<UsageRecord>> {
+        Ok(Vec::new())
+    }
+
+    fn cache_get(&self, _key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn cache_put(&self, _key: &str, _value: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
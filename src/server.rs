@@ -1,45 +1,102 @@
 //! HTTP server with Axum routes for the proxy.
 //!
 //! Exposes `/v1/messages` (the Anthropic Messages API endpoint), `/health`,
-//! and `/v1/models`. Handles both streaming and non-streaming requests.
+//! `/v1/models`, Prometheus `/metrics`, and the admin API and dashboard under
+//! `/admin`. Handles both streaming and non-streaming requests.
+//! Gemini clients can use `/v1beta/models/{model}:generateContent` and
+//! `:streamGenerateContent`, which are translated to Anthropic requests and
+//! routed like any other. So can `OpenAI` clients of `/v1/chat/completions`
+//! and the legacy `/v1/completions` endpoint. Batch clients can use `/v1/messages/batches`
+//! (see [`batches`]).
 
+use crate::admin;
+use crate::auth;
+use crate::auxiliary;
+use crate::batches;
 use crate::config::ProxyConfig;
-use crate::logging::SharedLogger;
+use crate::costs;
+use crate::error::ProxyError;
+use crate::listen;
+use crate::log_context;
+use crate::logging::LogLevel;
+use crate::provenance;
 use crate::proxy;
-use crate::translate::anthropic_types::{ErrorResponse, MessagesRequest};
+use crate::translate::anthropic_types::{
+    ErrorResponse, MessagesRequest, StreamEvent, TOKEN_EFFICIENT_TOOLS_BETA,
+};
+use crate::translate::chat_completions::{self, ChatStreamTranslator};
+use crate::translate::completions::{self, CompletionRequest, CompletionStreamTranslator};
+use crate::translate::gemini::{self, GeminiError, GeminiStreamTranslator, GenerateContentRequest};
+use crate::translate::openai_types::{ChatCompletionRequest, ChatError, ChatErrorResponse};
 
 use axum::body::Body;
-use axum::extract::State;
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
 use axum::http::{HeaderMap, StatusCode};
+use axum::middleware;
 use axum::response::sse::{Event, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use bytes::Bytes;
 use futures::stream::StreamExt;
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
-#[derive(Clone)]
-pub struct AppState {
-    pub config: ProxyConfig,
-    pub client: reqwest::Client,
-    pub logger: SharedLogger,
-}
+pub use crate::state::AppState;
+
+/// Bytes in a megabyte, as `max_request_mb` and the batch limit count them.
+pub(crate) const MB: usize = 1024 * 1024;
 
 pub fn build_router(state: Arc<AppState>) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
+    let body_limit = DefaultBodyLimit::max(state.config.load().max_request_mb * MB);
 
-    Router::new()
+    // Everything but /health and the dashboard page sits behind [auth], when
+    // configured
+    let protected = Router::new()
         .route("/v1/messages", post(handle_messages))
-        .route("/health", get(handle_health))
+        .route("/metrics", get(handle_metrics))
+        .route("/stats", get(handle_stats))
         .route("/v1/models", get(handle_models))
-        .layer(cors)
+        .route("/v1beta/models/:model_action", post(handle_gemini))
+        .route("/v1/completions", post(handle_completions))
+        .route("/v1/chat/completions", post(handle_chat_completions))
+        .merge(batches::router())
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ))
+        .layer(body_limit);
+    // Unrouted paths: Claude Code's telemetry endpoints, or 404
+    let auxiliary = Router::new()
+        .fallback(auxiliary::handle)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ))
+        .layer(body_limit);
+
+    // The admin API is left out of CORS, so other sites' pages can't read it
+    let api = Router::new()
+        .merge(protected)
+        .merge(auxiliary)
+        .route("/health", get(handle_health))
+        .layer(cors);
+
+    Router::new()
+        .merge(api)
+        .nest("/admin", admin::router(state.clone()))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            provenance::add_header,
+        ))
+        .layer(middleware::from_fn(log_context::scope_request))
         .layer(TraceLayer::new_for_http())
         .with_state(state)
 }
@@ -49,69 +106,94 @@ async fn handle_messages(
     headers: HeaderMap,
     body: Bytes,
 ) -> Response {
-    // Anthropic passthrough mode (no translation needed)
-    if state.config.is_anthropic_format() {
-        return handle_passthrough(state, headers, body).await;
+    if let Some(model) = headers
+        .get(proxy::MODEL_OVERRIDE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+    {
+        log_context::set_model_override(model);
+    }
+    if headers
+        .get(proxy::EXTRA_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !matches!(v.trim(), "" | "0" | "false"))
+    {
+        log_context::set_extra();
+    }
+
+    // Anthropic passthrough mode (no translation needed) when the model routes
+    // to an Anthropic-format provider. The request is served from this one
+    // config snapshot throughout.
+    let config = state.config.load();
+    let routes_to_anthropic = serde_json::from_slice::<proxy::ModelField>(&body)
+        .ok()
+        .and_then(|m| config.route(&m.model).ok())
+        .is_some_and(|route| route.provider.is_anthropic_format());
+    if routes_to_anthropic {
+        return handle_passthrough(state, &config, headers, body).await;
     }
 
     // Parse the Anthropic request
     let req: MessagesRequest = match serde_json::from_slice(&body) {
         Ok(r) => r,
         Err(e) => {
-            state
-                .logger
-                .error("server", format!("Failed to parse request: {e}"));
-            let err = ErrorResponse::invalid_request(format!("Invalid request body: {e}"));
-            return (StatusCode::BAD_REQUEST, Json(err)).into_response();
+            return error_response(
+                &state,
+                ProxyError::invalid_request(format!("Invalid request body: {e}")),
+            );
         }
     };
 
     let is_streaming = req.stream.unwrap_or(false);
 
-    state.logger.info(
+    if req.has_beta(TOKEN_EFFICIENT_TOOLS_BETA)
+        || header_has_beta(&headers, TOKEN_EFFICIENT_TOOLS_BETA)
+    {
+        state.logger.debug(
+            "server",
+            "Token-efficient tool use beta requested; not forwarded to translated providers",
+        );
+    }
+
+    log_context::set_request(
+        &req.model,
+        req.metadata.as_ref().and_then(|m| m.user_id.as_deref()),
+    );
+    state.logger.log_with_context(
+        LogLevel::Info,
         "server",
-        format!(
-            "Request: model={} streaming={} messages={}",
-            req.model,
-            is_streaming,
-            req.messages.len()
-        ),
+        "Request",
+        serde_json::json!({ "streaming": is_streaming, "messages": req.messages.len() }),
     );
 
     if is_streaming {
-        handle_streaming(state, &req).await
+        handle_streaming(state, &config, &req).await
     } else {
-        handle_non_streaming(state, &req).await
+        handle_non_streaming(state, &config, &req).await
     }
 }
 
-async fn handle_non_streaming(state: Arc<AppState>, req: &MessagesRequest) -> Response {
-    match proxy::proxy_non_streaming(req, &state.config, &state.client, &state.logger).await {
-        Ok(proxy::ProxyResult::Success(resp)) => Json(resp).into_response(),
-        Ok(proxy::ProxyResult::Error(err, status_code)) => {
-            let status = StatusCode::from_u16(status_code).unwrap_or(StatusCode::BAD_GATEWAY);
-            (status, Json(err)).into_response()
-        }
-        Err(e) => {
-            state.logger.error("server", format!("Proxy error: {e}"));
-            let err = ErrorResponse::api_error(format!("Proxy error: {e}"));
-            (StatusCode::BAD_GATEWAY, Json(err)).into_response()
-        }
+async fn handle_non_streaming(
+    state: Arc<AppState>,
+    config: &ProxyConfig,
+    req: &MessagesRequest,
+) -> Response {
+    match proxy::respond(req, config, &state).await {
+        Ok(resp) => Json(resp).into_response(),
+        Err(e) => error_response(&state, e),
     }
 }
 
-async fn handle_streaming(state: Arc<AppState>, req: &MessagesRequest) -> Response {
-    let sse_stream =
-        match proxy::proxy_streaming(req, &state.config, &state.client, &state.logger).await {
-            Ok(s) => s,
-            Err(e) => {
-                state
-                    .logger
-                    .error("server", format!("Streaming setup error: {e}"));
-                let err = ErrorResponse::api_error(format!("Streaming error: {e}"));
-                return (StatusCode::BAD_GATEWAY, Json(err)).into_response();
-            }
-        };
+async fn handle_streaming(
+    state: Arc<AppState>,
+    config: &ProxyConfig,
+    req: &MessagesRequest,
+) -> Response {
+    let sse_stream = match proxy::stream(req, config, &state).await {
+        Ok(s) => s,
+        // Nothing has been streamed yet, so the error gets its own status
+        Err(e) => return error_response(&state, e),
+    };
 
     let event_stream = sse_stream.map(|result| -> std::result::Result<Event, Infallible> {
         match result {
@@ -125,82 +207,378 @@ async fn handle_streaming(state: Arc<AppState>, req: &MessagesRequest) -> Respon
         .into_response()
 }
 
-async fn handle_passthrough(state: Arc<AppState>, headers: HeaderMap, body: Bytes) -> Response {
+async fn handle_passthrough(
+    state: Arc<AppState>,
+    config: &ProxyConfig,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
     let req_headers = reqwest_headers_from_axum(&headers);
 
-    match proxy::proxy_passthrough(
-        body,
-        &req_headers,
-        &state.config,
-        &state.client,
-        &state.logger,
-    )
-    .await
-    {
-        Ok((status, resp_headers, resp_body)) => {
-            let status_code = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+    match proxy::passthrough(body, &req_headers, config, &state).await {
+        Ok(resp) => {
+            let status_code = StatusCode::from_u16(resp.status).unwrap_or(StatusCode::BAD_GATEWAY);
 
-            let content_type = resp_headers
+            let content_type = resp
+                .headers
                 .get("content-type")
                 .and_then(|v| v.to_str().ok())
                 .unwrap_or("");
 
+            // Forward chunks as they arrive so SSE events aren't held back
             if content_type.contains("text/event-stream") {
                 Response::builder()
                     .status(status_code)
                     .header("content-type", "text/event-stream")
                     .header("cache-control", "no-cache")
-                    .body(Body::from(resp_body))
+                    .body(Body::from_stream(resp.body))
                     .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
             } else {
                 Response::builder()
                     .status(status_code)
                     .header("content-type", "application/json")
-                    .body(Body::from(resp_body))
+                    .body(Body::from_stream(resp.body))
                     .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
             }
         }
+        Err(e) => error_response(&state, e),
+    }
+}
+
+/// `POST /v1beta/models/{model}:generateContent` and `:streamGenerateContent`.
+/// Streaming responses are SSE with `?alt=sse`, otherwise a JSON array of chunks.
+async fn handle_gemini(
+    State(state): State<Arc<AppState>>,
+    Path(model_action): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Response {
+    let (model, is_streaming) = match model_action.rsplit_once(':') {
+        Some((model, "generateContent")) => (model, false),
+        Some((model, "streamGenerateContent")) => (model, true),
+        _ => {
+            return gemini_error(404, format!("Unknown method: {model_action}"));
+        }
+    };
+
+    let gemini_req: GenerateContentRequest = match serde_json::from_slice(&body) {
+        Ok(r) => r,
         Err(e) => {
-            state
-                .logger
-                .error("server", format!("Passthrough error: {e}"));
-            let err = ErrorResponse::api_error(format!("Passthrough error: {e}"));
-            (StatusCode::BAD_GATEWAY, Json(err)).into_response()
+            return gemini_proxy_error(
+                &state,
+                &ProxyError::invalid_request(format!("Invalid request body: {e}")),
+            );
         }
+    };
+    let req = gemini::gemini_to_anthropic(&gemini_req, model, is_streaming);
+
+    log_context::set_request(&req.model, None);
+    state.logger.log_with_context(
+        LogLevel::Info,
+        "server",
+        "Gemini request",
+        serde_json::json!({ "streaming": is_streaming, "messages": req.messages.len() }),
+    );
+
+    if !is_streaming {
+        return match proxy::proxy_parsed_non_streaming(&req, &state).await {
+            Ok(resp) => Json(gemini::anthropic_to_gemini(&resp)).into_response(),
+            Err(e) => gemini_proxy_error(&state, &e),
+        };
+    }
+
+    let sse_stream = match proxy::proxy_parsed_streaming(&req, &state).await {
+        Ok(s) => s,
+        Err(e) => return gemini_proxy_error(&state, &e),
+    };
+
+    let mut translator = GeminiStreamTranslator::new(model);
+    let chunks = sse_stream.filter_map(move |result| {
+        let chunk = result.ok().and_then(|sse_event| {
+            if sse_event.event == "error" {
+                let message = serde_json::from_str::<ErrorResponse>(&sse_event.data)
+                    .map_or(sse_event.data, |e| e.error.message);
+                return serde_json::to_string(&GeminiError::new(500, message)).ok();
+            }
+            let event = serde_json::from_str::<StreamEvent>(&sse_event.data).ok()?;
+            let chunk = translator.process_event(&event)?;
+            serde_json::to_string(&chunk).ok()
+        });
+        futures::future::ready(chunk)
+    });
+
+    if query.get("alt").map(String::as_str) == Some("sse") {
+        let events = chunks.map(|data| Ok::<_, Infallible>(Event::default().data(data)));
+        return Sse::new(events).into_response();
+    }
+
+    let chunks: Vec<String> = chunks.collect().await;
+    Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(format!("[{}]", chunks.join(","))))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+/// `POST /v1/completions`, `OpenAI`'s legacy text completion endpoint.
+/// Streaming responses are SSE ending with `data: [DONE]`.
+async fn handle_completions(State(state): State<Arc<AppState>>, body: Bytes) -> Response {
+    let parsed = serde_json::from_slice::<CompletionRequest>(&body)
+        .map_err(|e| ProxyError::invalid_request(format!("Invalid request body: {e}")))
+        .and_then(|completion_req| {
+            let req = completions::completion_to_anthropic(&completion_req)?;
+            Ok((completion_req, req))
+        });
+    let (completion_req, req) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return openai_proxy_error(&state, &e),
+    };
+    let is_streaming = req.stream.unwrap_or(false);
+    // completion_to_anthropic has checked there is exactly one prompt
+    let echo = completion_req
+        .echo
+        .then(|| completions::prompt_text(&completion_req.prompt).unwrap_or_default());
+
+    log_context::set_request(&req.model, completion_req.user.as_deref());
+    state.logger.log_with_context(
+        LogLevel::Info,
+        "server",
+        "Completions request",
+        serde_json::json!({ "streaming": is_streaming }),
+    );
+
+    if !is_streaming {
+        return match proxy::proxy_parsed_non_streaming(&req, &state).await {
+            Ok(resp) => Json(completions::anthropic_to_completion(&resp, echo)).into_response(),
+            Err(e) => openai_proxy_error(&state, &e),
+        };
+    }
+
+    let sse_stream = match proxy::proxy_parsed_streaming(&req, &state).await {
+        Ok(s) => s,
+        Err(e) => return openai_proxy_error(&state, &e),
+    };
+
+    let mut translator = CompletionStreamTranslator::new(&req.model, echo.map(str::to_string));
+    let chunks = sse_stream.filter_map(move |result| {
+        let chunk = result.ok().and_then(|sse_event| {
+            if sse_event.event == "error" {
+                return serde_json::to_string(&stream_error(sse_event.data)).ok();
+            }
+            let event = serde_json::from_str::<StreamEvent>(&sse_event.data).ok()?;
+            let chunk = translator.process_event(&event)?;
+            serde_json::to_string(&chunk).ok()
+        });
+        futures::future::ready(chunk)
+    });
+    let events = chunks
+        .chain(futures::stream::once(futures::future::ready(
+            "[DONE]".to_string(),
+        )))
+        .map(|data| Ok::<_, Infallible>(Event::default().data(data)));
+    Sse::new(events).into_response()
+}
+
+/// `POST /v1/chat/completions`, for `OpenAI` clients. Streaming responses are
+/// SSE ending with `data: [DONE]`.
+async fn handle_chat_completions(State(state): State<Arc<AppState>>, body: Bytes) -> Response {
+    let parsed = serde_json::from_slice::<ChatCompletionRequest>(&body)
+        .map_err(|e| ProxyError::invalid_request(format!("Invalid request body: {e}")))
+        .and_then(|chat_req| {
+            let req = chat_completions::chat_to_anthropic(&chat_req)?;
+            Ok((chat_req, req))
+        });
+    let (chat_req, req) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return openai_proxy_error(&state, &e),
+    };
+    let is_streaming = req.stream.unwrap_or(false);
+
+    log_context::set_request(&req.model, chat_req.user.as_deref());
+    state.logger.log_with_context(
+        LogLevel::Info,
+        "server",
+        "Chat completions request",
+        serde_json::json!({ "streaming": is_streaming, "messages": req.messages.len() }),
+    );
+
+    if !is_streaming {
+        return match proxy::proxy_parsed_non_streaming(&req, &state).await {
+            Ok(resp) => Json(chat_completions::anthropic_to_chat(&resp)).into_response(),
+            Err(e) => openai_proxy_error(&state, &e),
+        };
+    }
+
+    let sse_stream = match proxy::proxy_parsed_streaming(&req, &state).await {
+        Ok(s) => s,
+        Err(e) => return openai_proxy_error(&state, &e),
+    };
+
+    let mut translator =
+        ChatStreamTranslator::new(&req.model, chat_completions::include_usage(&chat_req));
+    let chunks = sse_stream.filter_map(move |result| {
+        let chunk = result.ok().and_then(|sse_event| {
+            if sse_event.event == "error" {
+                return serde_json::to_string(&stream_error(sse_event.data)).ok();
+            }
+            let event = serde_json::from_str::<StreamEvent>(&sse_event.data).ok()?;
+            let chunk = translator.process_event(&event)?;
+            serde_json::to_string(&chunk).ok()
+        });
+        futures::future::ready(chunk)
+    });
+    let events = chunks
+        .chain(futures::stream::once(futures::future::ready(
+            "[DONE]".to_string(),
+        )))
+        .map(|data| Ok::<_, Infallible>(Event::default().data(data)));
+    Sse::new(events).into_response()
+}
+
+/// Log a failed request and answer it in Anthropic format. Upstream errors
+/// were already logged by the proxy layer.
+fn error_response(state: &AppState, error: ProxyError) -> Response {
+    log_error(state, &error);
+    error.into_response()
+}
+
+/// [`error_response`] for the Gemini endpoints.
+fn gemini_proxy_error(state: &AppState, error: &ProxyError) -> Response {
+    log_error(state, error);
+    let message = error.to_error_response().error.message;
+    gemini_error(error.status().as_u16(), message)
+}
+
+/// [`error_response`] for the `OpenAI`-format endpoints.
+fn openai_proxy_error(state: &AppState, error: &ProxyError) -> Response {
+    log_error(state, error);
+    let body = error.to_error_response().error;
+    (
+        error.status(),
+        Json(openai_error(body.message, &body.error_type)),
+    )
+        .into_response()
+}
+
+/// An Anthropic `error` event's data in `OpenAI` format.
+fn stream_error(data: String) -> ChatErrorResponse {
+    serde_json::from_str::<ErrorResponse>(&data).map_or_else(
+        |_| openai_error(data, "api_error"),
+        |e| openai_error(e.error.message, &e.error.error_type),
+    )
+}
+
+fn openai_error(message: String, error_type: &str) -> ChatErrorResponse {
+    ChatErrorResponse {
+        error: ChatError {
+            message,
+            error_type: error_type.to_string(),
+            code: None,
+        },
     }
 }
 
+fn log_error(state: &AppState, error: &ProxyError) {
+    match error {
+        ProxyError::Upstream { .. } => {}
+        ProxyError::InvalidRequest { .. } => state.logger.warn("server", error.to_string()),
+        _ => state
+            .logger
+            .error("server", format!("Proxy error: {error}")),
+    }
+}
+
+fn gemini_error(status: u16, message: impl Into<String>) -> Response {
+    let code = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+    (code, Json(GeminiError::new(status, message))).into_response()
+}
+
 async fn handle_health() -> Json<serde_json::Value> {
     Json(serde_json::json!({
         "status": "ok",
+        "service": listen::SERVICE,
         "version": env!("CARGO_PKG_VERSION"),
     }))
 }
 
+async fn handle_metrics(State(state): State<Arc<AppState>>) -> Response {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        state.metrics.render(),
+    )
+        .into_response()
+}
+
+#[derive(serde::Deserialize)]
+struct StatsQuery {
+    /// RFC 3339 time or a span back from now (`24h`, `7d`).
+    since: Option<String>,
+    session: Option<String>,
+    tag: Option<String>,
+}
+
+async fn handle_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatsQuery>,
+) -> Response {
+    let since = match query
+        .since
+        .as_deref()
+        .map(|s| costs::parse_since(s, chrono::Utc::now()))
+        .transpose()
+    {
+        Ok(since) => since,
+        Err(e) => return e.into_response(),
+    };
+    match state.storage.usage(since) {
+        Ok(records) => Json(costs::report(
+            &records,
+            since,
+            query.session.as_deref(),
+            query.tag.as_deref(),
+        ))
+        .into_response(),
+        Err(e) => error_response(&state, e),
+    }
+}
+
 async fn handle_models(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
-    let mut models: Vec<serde_json::Value> = state
-        .config
+    let config = state.config.load();
+    let mut models: Vec<serde_json::Value> = config
         .models
-        .keys()
-        .map(|name| {
-            serde_json::json!({
+        .iter()
+        .map(|(name, mapping)| {
+            let provider = mapping.provider().unwrap_or(&config.provider.name);
+            let mut model = serde_json::json!({
                 "id": name,
                 "object": "model",
-                "owned_by": state.config.provider.name.clone(),
-            })
+                "owned_by": provider,
+            });
+            let info = state
+                .registry
+                .get(provider, mapping.model())
+                .unwrap_or_default();
+            if let Some(window) = info.context_window {
+                model["context_window"] = window.into();
+            }
+            if let Some(max_output) = info.max_output_tokens {
+                model["max_output_tokens"] = max_output.into();
+            }
+            model
         })
         .collect();
 
     // Fetch upstream models and append them to the list so Claude Code can see all available models.
-    if let Ok(provider_models) =
-        crate::models::fetch_provider_models(&state.config, &state.client).await
+    if let Ok(provider_models) = crate::models::fetch_provider_models(&config, &state.client).await
     {
         for model in provider_models {
-            if !state.config.models.contains_key(&model) {
+            if !config.models.contains_key(&model) {
                 models.push(serde_json::json!({
                     "id": model,
                     "object": "model",
-                    "owned_by": state.config.provider.name.clone(),
+                    "owned_by": config.provider.name.clone(),
                 }));
             }
         }
@@ -209,6 +587,16 @@ async fn handle_models(State(state): State<Arc<AppState>>) -> Json<serde_json::V
     Json(serde_json::json!({ "data": models, "object": "list" }))
 }
 
+/// Whether the `anthropic-beta` header lists a beta whose name starts with `prefix`.
+fn header_has_beta(headers: &HeaderMap, prefix: &str) -> bool {
+    headers
+        .get_all("anthropic-beta")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|b| b.trim().starts_with(prefix))
+}
+
 fn reqwest_headers_from_axum(headers: &HeaderMap) -> reqwest::header::HeaderMap {
     let mut out = reqwest::header::HeaderMap::new();
     for (key, value) in headers {
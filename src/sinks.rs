@@ -0,0 +1,272 @@
+//! Extra destinations for log entries, configured as `[[logging.sinks]]`.
+//!
+//! The logger always persists to its [`Storage`](crate::storage::Storage); sinks
+//! receive a copy of each entry at or above their `min_level`:
+//!
+//! - `file` — JSONL appended to another path
+//! - `stdout` — one JSON object per line, for container log collectors
+//! - `otlp` — OpenTelemetry logs over OTLP/HTTP JSON (`/v1/logs`)
+//! - `webhook` — each entry sent as a JSON POST
+//!
+//! Network sinks queue entries for a background task and drop them if the
+//! queue is full, so a slow collector never stalls a request. Sinks can be
+//! replaced at runtime with [`SharedLogger::set_sinks`].
+//!
+//! [`SharedLogger::set_sinks`]: crate::logging::SharedLogger::set_sinks
+
+use crate::config::{SinkConfig, SinkKind};
+use crate::error::{ProxyError, Result};
+use crate::logging::{LogEntry, LogLevel};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Entries waiting to be sent by a network sink.
+const QUEUE_CAPACITY: usize = 1024;
+/// Most entries sent in one OTLP request.
+const MAX_BATCH: usize = 100;
+
+/// A destination for log entries.
+pub trait LogSink: Send + Sync {
+    /// Deliver one entry. Called with the logger locked, so it must not block
+    /// for long; failures are ignored.
+    fn write(&self, entry: &LogEntry);
+}
+
+/// A sink together with the lowest level it receives.
+#[derive(Clone)]
+pub struct Sink {
+    pub min_level: LogLevel,
+    pub sink: Arc<dyn LogSink>,
+}
+
+impl Sink {
+    pub fn new(min_level: LogLevel, sink: impl LogSink + 'static) -> Self {
+        Self {
+            min_level,
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+/// Build the sinks described by `[[logging.sinks]]`. Network sinks spawn their
+/// sender task, so this must run inside a Tokio runtime.
+///
+/// # Errors
+/// Returns `ProxyError::Io` if a file sink can't be opened, and
+/// `ProxyError::Config` if a network sink is built outside a runtime.
+pub fn build(configs: &[SinkConfig]) -> Result<Vec<Sink>> {
+    configs
+        .iter()
+        .map(|config| {
+            let sink: Arc<dyn LogSink> = match &config.kind {
+                SinkKind::File { path } => Arc::new(FileSink::open(path)?),
+                SinkKind::Stdout => Arc::new(StdoutSink),
+                SinkKind::Otlp {
+                    endpoint,
+                    headers,
+                    service_name,
+                } => Arc::new(HttpSink::spawn(
+                    endpoint,
+                    headers,
+                    Encoding::Otlp {
+                        service_name: service_name
+                            .clone()
+                            .unwrap_or_else(|| "claude-proxy".to_string()),
+                    },
+                )?),
+                SinkKind::Webhook { url, headers } => {
+                    Arc::new(HttpSink::spawn(url, headers, Encoding::Webhook)?)
+                }
+            };
+            Ok(Sink {
+                min_level: config.min_level.clone(),
+                sink,
+            })
+        })
+        .collect()
+}
+
+/// Appends JSONL to a file.
+pub struct FileSink {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl FileSink {
+    /// Open `path` for appending, creating it and its directory if needed.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Io` if the file can't be opened.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+impl LogSink for FileSink {
+    fn write(&self, entry: &LogEntry) {
+        if let (Ok(json), Ok(mut writer)) = (serde_json::to_string(entry), self.writer.lock()) {
+            let _ = writeln!(writer, "{json}");
+            let _ = writer.flush();
+        }
+    }
+}
+
+/// Writes one JSON object per line to stdout.
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn write(&self, entry: &LogEntry) {
+        if let Ok(json) = serde_json::to_string(entry) {
+            let _ = writeln!(std::io::stdout().lock(), "{json}");
+        }
+    }
+}
+
+/// How an [`HttpSink`] shapes its requests.
+enum Encoding {
+    /// One POST per entry, the entry as JSON.
+    Webhook,
+    /// Batches as an OTLP `ExportLogsServiceRequest`.
+    Otlp { service_name: String },
+}
+
+/// Sends entries over HTTP from a background task.
+pub struct HttpSink {
+    tx: mpsc::Sender<LogEntry>,
+}
+
+impl HttpSink {
+    fn spawn(url: &str, headers: &HashMap<String, String>, encoding: Encoding) -> Result<Self> {
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|_| ProxyError::config("HTTP log sinks need a Tokio runtime"))?;
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        runtime.spawn(send_loop(
+            reqwest::Client::new(),
+            url.to_string(),
+            headers.clone(),
+            encoding,
+            rx,
+        ));
+        Ok(Self { tx })
+    }
+}
+
+impl LogSink for HttpSink {
+    fn write(&self, entry: &LogEntry) {
+        let _ = self.tx.try_send(entry.clone());
+    }
+}
+
+async fn send_loop(
+    client: reqwest::Client,
+    url: String,
+    headers: HashMap<String, String>,
+    encoding: Encoding,
+    mut rx: mpsc::Receiver<LogEntry>,
+) {
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match rx.try_recv() {
+                Ok(entry) => batch.push(entry),
+                Err(_) => break,
+            }
+        }
+
+        let bodies = match &encoding {
+            Encoding::Webhook => batch
+                .iter()
+                .filter_map(|e| serde_json::to_value(e).ok())
+                .collect(),
+            Encoding::Otlp { service_name } => vec![otlp_logs(service_name, &batch)],
+        };
+        for body in bodies {
+            let mut request = client.post(&url).json(&body);
+            for (name, value) in &headers {
+                request = request.header(name, value);
+            }
+            // Nowhere to report a failure: logging it would feed this sink
+            let _ = request.send().await;
+        }
+    }
+}
+
+/// An OTLP/HTTP JSON logs payload for a batch of entries.
+#[must_use]
+pub fn otlp_logs(service_name: &str, entries: &[LogEntry]) -> serde_json::Value {
+    let records: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            let (severity_number, severity_text) = match entry.level {
+                LogLevel::Debug => (5, "DEBUG"),
+                LogLevel::Info => (9, "INFO"),
+                LogLevel::Warn => (13, "WARN"),
+                LogLevel::Error => (17, "ERROR"),
+            };
+            let mut attributes = vec![string_attribute("component", &entry.component)];
+            if let Some(ref context) = entry.context {
+                attributes.push(string_attribute("context", &context.to_string()));
+            }
+            serde_json::json!({
+                "timeUnixNano": entry
+                    .timestamp
+                    .timestamp_nanos_opt()
+                    .unwrap_or_default()
+                    .to_string(),
+                "severityNumber": severity_number,
+                "severityText": severity_text,
+                "body": { "stringValue": entry.message },
+                "attributes": attributes,
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "resourceLogs": [{
+            "resource": {
+                "attributes": [string_attribute("service.name", service_name)]
+            },
+            "scopeLogs": [{
+                "scope": { "name": "claude-proxy", "version": env!("CARGO_PKG_VERSION") },
+                "logRecords": records,
+            }]
+        }]
+    })
+}
+
+fn string_attribute(key: &str, value: &str) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_otlp_payload() {
+        let entry = LogEntry::new(LogLevel::Warn, "proxy", "Provider error")
+            .with_context(serde_json::json!({"status": 429}));
+        let payload = otlp_logs("claude-proxy", &[entry]);
+
+        let resource = &payload["resourceLogs"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "claude-proxy"
+        );
+        let record = &resource["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["severityNumber"], 13);
+        assert_eq!(record["body"]["stringValue"], "Provider error");
+        assert_eq!(record["attributes"][0]["value"]["stringValue"], "proxy");
+        assert!(record["timeUnixNano"].as_str().unwrap().len() > 15);
+    }
+}
@@ -0,0 +1,188 @@
+//! Shared state for the proxy server and forwarding layer.
+
+use crate::audit::AuditLog;
+use crate::batches::Batches;
+use crate::budget::RetryBudget;
+use crate::capture::Capturer;
+use crate::concurrency::ConcurrencyLimiter;
+use crate::config::{ProxyConfig, SharedConfig};
+use crate::encryption::Cipher;
+use crate::hooks::{Hooks, ToolCall, UpstreamRequest};
+use crate::images::ImageCache;
+use crate::journal::Journal;
+use crate::key_pool::KeyPool;
+use crate::logging::SharedLogger;
+use crate::metrics::Metrics;
+use crate::oauth::TokenCache;
+use crate::rate_limit::RateLimiter;
+use crate::recording::Recorder;
+use crate::registry::{self, ModelRegistry};
+use crate::response_cache::ResponseCache;
+use crate::routing::ProviderHealth;
+use crate::schedule::Schedule;
+use crate::storage::Storage;
+use crate::translate::anthropic_types::{MessagesRequest, MessagesResponse, StreamEvent};
+use crate::translate::documents::DocumentCache;
+use crate::translate::prefix_cache::PrefixCache;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Everything a request handler needs: configuration, the upstream HTTP client,
+/// the logger, and runtime state shared across requests.
+#[derive(Clone)]
+pub struct AppState {
+    /// The active configuration; reloaded in place by [`crate::reload`].
+    pub config: SharedConfig,
+    pub client: reqwest::Client,
+    pub logger: SharedLogger,
+    pub health: ProviderHealth,
+    /// The `[[schedule]]` windows open at the last request routed.
+    pub schedule: Schedule,
+    /// Per-provider `max_concurrent_upstream` slots.
+    pub limiter: ConcurrencyLimiter,
+    /// Per-provider `requests_per_minute` / `tokens_per_minute` buckets.
+    pub rate_limiter: RateLimiter,
+    pub retry_budget: RetryBudget,
+    pub audit: AuditLog,
+    pub recorder: Recorder,
+    pub capturer: Capturer,
+    /// Backend for usage records and cached values; the logger's storage.
+    pub storage: Arc<dyn Storage>,
+    pub metrics: Metrics,
+    /// Per-model prices and limits from `[registry] file`.
+    pub registry: ModelRegistry,
+    /// Complete responses to repeated requests (`[response_cache]`).
+    pub response_cache: ResponseCache,
+    /// Translated messages of recent conversations, for their next turn.
+    pub prefix_cache: PrefixCache,
+    /// Recently shrunk images (`[translation.images]`).
+    pub image_cache: ImageCache,
+    /// Recently converted documents (`[translation] documents`).
+    pub document_cache: DocumentCache,
+    /// Config, admin and failover events (`[journal] file`).
+    pub journal: Journal,
+    /// Message batches submitted to `/v1/messages/batches`.
+    pub batches: Batches,
+    /// Closures an embedder registered to see and change traffic.
+    pub hooks: Hooks,
+    /// Rotation and counts for providers with several keys (`api_key_envs`).
+    pub keys: KeyPool,
+    /// Bearer tokens for providers with `[provider.oauth]`.
+    pub tokens: TokenCache,
+}
+
+impl AppState {
+    #[must_use]
+    pub fn new(config: ProxyConfig, client: reqwest::Client, logger: SharedLogger) -> Self {
+        crate::keychain::load(&config);
+        crate::key_file::load(&config);
+        let audit_capacity = if config.audit.enabled {
+            config.audit.capacity
+        } else {
+            0
+        };
+        let recorder = Recorder::new(config.record.dir.as_deref());
+        // Without a usable key, captures are turned off rather than written
+        // in plaintext; startup in `main` fails on the same error.
+        let capturer = match Cipher::from_config(&config.encryption) {
+            Ok(cipher) => Capturer::new(config.capture.dir.as_deref()).with_cipher(cipher),
+            Err(e) => {
+                if config.capture.dir.is_some() {
+                    logger.error("capture", format!("Capture disabled: {e}"));
+                }
+                Capturer::default()
+            }
+        };
+        let storage = logger.storage();
+        let registry = match config.registry.file.as_deref() {
+            Some(path) => registry::load(std::path::Path::new(path)).unwrap_or_else(|e| {
+                logger.error("registry", e.to_string());
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+        let journal = match config.journal.file.as_deref() {
+            Some(path) => {
+                Journal::open(std::path::Path::new(path), logger.clone()).unwrap_or_else(|e| {
+                    logger.error("journal", format!("Journal disabled: {e}"));
+                    Journal::disabled()
+                })
+            }
+            None => Journal::disabled(),
+        };
+        Self {
+            config: SharedConfig::new(config),
+            client,
+            logger,
+            health: ProviderHealth::new(),
+            schedule: Schedule::new(),
+            limiter: ConcurrencyLimiter::new(),
+            rate_limiter: RateLimiter::new(),
+            retry_budget: RetryBudget::new(),
+            audit: AuditLog::new(audit_capacity),
+            recorder,
+            capturer,
+            response_cache: ResponseCache::new(storage.clone()),
+            storage,
+            metrics: Metrics::new(),
+            registry: ModelRegistry::new(registry),
+            prefix_cache: PrefixCache::new(),
+            image_cache: ImageCache::new(),
+            document_cache: DocumentCache::new(),
+            journal,
+            batches: Batches::new(),
+            hooks: Hooks::default(),
+            keys: KeyPool::new(),
+            tokens: TokenCache::new(),
+        }
+    }
+
+    /// Run `hook` on each Anthropic request before it is routed.
+    #[must_use]
+    pub fn on_request(
+        mut self,
+        hook: impl Fn(&mut MessagesRequest) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.add_request(hook);
+        self
+    }
+
+    /// Run `hook` on each request translated for a provider, where it can
+    /// also add upstream headers.
+    #[must_use]
+    pub fn on_translated_request(
+        mut self,
+        hook: impl Fn(&mut UpstreamRequest<'_, '_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.add_translated_request(hook);
+        self
+    }
+
+    /// Run `hook` on each non-streaming response.
+    #[must_use]
+    pub fn on_response(
+        mut self,
+        hook: impl Fn(&mut MessagesResponse) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.add_response(hook);
+        self
+    }
+
+    /// Run `hook` on each event of a streamed response.
+    #[must_use]
+    pub fn on_stream_event(
+        mut self,
+        hook: impl Fn(&mut StreamEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.add_stream_event(hook);
+        self
+    }
+
+    /// Run `hook` on each tool call in a response before the client sees it,
+    /// where it can rewrite the call's input.
+    #[must_use]
+    pub fn on_tool_use(mut self, hook: impl Fn(&mut ToolCall<'_>) + Send + Sync + 'static) -> Self {
+        self.hooks.add_tool_use(hook);
+        self
+    }
+}
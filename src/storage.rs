@@ -0,0 +1,585 @@
+//! Pluggable persistence for logs, usage records and cached values.
+//!
+//! The logger and the proxy write through a [`Storage`]. [`FileStorage`] (JSONL
+//! files next to the log file) is the default; `SqliteStorage` is available
+//! with the `sqlite` feature. Embedders can implement the trait for Postgres,
+//! Redis, etc. and hand it to [`SharedLogger::with_storage`].
+//!
+//! Methods are synchronous, like the logger that calls them. Implementations
+//! over a network store should buffer writes or hand them to a background task.
+//!
+//! [`SharedLogger::with_storage`]: crate::logging::SharedLogger::with_storage
+
+use crate::config::StorageConfig;
+use crate::encryption::{is_sealed, Cipher, SEALED_PREFIX};
+use crate::error::{ProxyError, Result};
+use crate::logging::LogEntry;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Token usage of one completed request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    pub provider: String,
+    pub requested_model: String,
+    pub upstream_model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub streaming: bool,
+    /// The client session the request belonged to, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Tags the client sent in `x-claude-proxy-tags`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Cost in USD at the `[costs]` prices in effect; unset for unpriced models.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+/// Backend for everything the proxy persists.
+pub trait Storage: Send + Sync {
+    /// Append one log entry.
+    ///
+    /// # Errors
+    /// Returns an error if the entry can't be written.
+    fn append_log(&self, entry: &LogEntry) -> Result<()>;
+
+    /// The most recent `limit` log entries, oldest first.
+    ///
+    /// # Errors
+    /// Returns an error if the log can't be read.
+    fn recent_logs(&self, limit: usize) -> Result<Vec<LogEntry>>;
+
+    /// Drop all but the most recent `keep` log entries. A no-op by default.
+    ///
+    /// # Errors
+    /// Returns an error if the log can't be rewritten.
+    fn compact_logs(&self, keep: usize) -> Result<()> {
+        let _ = keep;
+        Ok(())
+    }
+
+    /// How many bytes the stored log takes, or `None` if the backend can't
+    /// tell (the default).
+    ///
+    /// # Errors
+    /// Returns an error if the log's size can't be read.
+    fn log_size(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Record the token usage of a completed request.
+    ///
+    /// # Errors
+    /// Returns an error if the record can't be written.
+    fn record_usage(&self, record: &UsageRecord) -> Result<()>;
+
+    /// Usage records at or after `since` (all of them when `None`), oldest first.
+    ///
+    /// # Errors
+    /// Returns an error if the records can't be read.
+    fn usage(&self, since: Option<DateTime<Utc>>) -> Result<Vec<UsageRecord>>;
+
+    /// Look up a cached value.
+    ///
+    /// # Errors
+    /// Returns an error if the cache can't be read.
+    fn cache_get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store a cached value, replacing any earlier one.
+    ///
+    /// # Errors
+    /// Returns an error if the value can't be written.
+    fn cache_put(&self, key: &str, value: &[u8]) -> Result<()>;
+}
+
+/// Open the storage backend selected by `[storage]`. The file backend keeps
+/// its files beside `log_file`, encrypted with `cipher` if given.
+///
+/// # Errors
+/// Returns `ProxyError::Config` for an unknown backend (or `sqlite` without the
+/// `sqlite` feature, or with a `cipher`), and `ProxyError::Storage` if the
+/// backend can't be opened.
+pub fn open(
+    config: &StorageConfig,
+    log_file: &Path,
+    cipher: Option<Cipher>,
+) -> Result<Arc<dyn Storage>> {
+    if cipher.is_some() && config.backend != "file" {
+        return Err(ProxyError::config(
+            "Encryption is only supported by the file storage backend",
+        ));
+    }
+    match config.backend.as_str() {
+        "file" => Ok(Arc::new(FileStorage::new(log_file)?.with_cipher(cipher))),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(Arc::new(SqliteStorage::open(
+            config.path.as_deref().unwrap_or("claude-proxy.db"),
+        )?)),
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => Err(ProxyError::config(
+            "Storage backend 'sqlite' needs claude-proxy built with the 'sqlite' feature",
+        )),
+        other => Err(ProxyError::config(format!(
+            "Unknown storage backend '{other}'. Known backends: file, sqlite"
+        ))),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// File storage
+// ---------------------------------------------------------------------------
+
+/// JSONL files: the log at the given path, usage records in
+/// `<name>.usage.jsonl` beside it, and cached values as files under
+/// `<name>.cache/`. With a [`Cipher`], every line and cached value is
+/// encrypted.
+pub struct FileStorage {
+    log_path: PathBuf,
+    usage_path: PathBuf,
+    cache_dir: PathBuf,
+    log_writer: Mutex<BufWriter<File>>,
+    usage_writer: Mutex<BufWriter<File>>,
+    cipher: Option<Cipher>,
+}
+
+impl FileStorage {
+    /// Open (creating if needed) the files for a log at `log_path`.
+    ///
+    /// # Errors
+    /// Returns `ProxyError::Io` if the files can't be opened.
+    pub fn new(log_path: impl AsRef<Path>) -> Result<Self> {
+        let log_path = log_path.as_ref().to_path_buf();
+        if let Some(parent) = log_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let usage_path = log_path.with_extension("usage.jsonl");
+        let cache_dir = log_path.with_extension("cache");
+
+        Ok(Self {
+            log_writer: Mutex::new(append_writer(&log_path)?),
+            usage_writer: Mutex::new(append_writer(&usage_path)?),
+            log_path,
+            usage_path,
+            cache_dir,
+            cipher: None,
+        })
+    }
+
+    /// Encrypt what's written from now on with `cipher` (and read back what
+    /// was).
+    #[must_use]
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// A record as it's stored: a JSON line, sealed if encrypting.
+    fn encode<T: Serialize>(&self, value: &T) -> Result<String> {
+        let json = serde_json::to_string(value)?;
+        match self.cipher {
+            Some(ref cipher) => cipher.seal(json.as_bytes()),
+            None => Ok(json),
+        }
+    }
+
+    fn cache_path(&self, key: &str) -> PathBuf {
+        self.cache_dir
+            .join(format!("{:016x}", fnv1a(key.as_bytes())))
+    }
+}
+
+impl Storage for FileStorage {
+    fn append_log(&self, entry: &LogEntry) -> Result<()> {
+        append_line(&self.log_writer, &self.encode(entry)?)
+    }
+
+    fn recent_logs(&self, limit: usize) -> Result<Vec<LogEntry>> {
+        let mut entries = read_jsonl::<LogEntry>(&self.log_path, self.cipher.as_ref())?;
+        let skip = entries.len().saturating_sub(limit);
+        Ok(entries.split_off(skip))
+    }
+
+    fn compact_logs(&self, keep: usize) -> Result<()> {
+        let mut writer = lock(&self.log_writer)?;
+        writer.flush()?;
+        let entries = self.recent_logs(keep)?;
+
+        let mut file = BufWriter::new(File::create(&self.log_path)?);
+        for entry in &entries {
+            writeln!(file, "{}", self.encode(entry)?)?;
+        }
+        file.flush()?;
+        *writer = append_writer(&self.log_path)?;
+        Ok(())
+    }
+
+    fn log_size(&self) -> Result<Option<u64>> {
+        lock(&self.log_writer)?.flush()?;
+        Ok(Some(std::fs::metadata(&self.log_path)?.len()))
+    }
+
+    fn record_usage(&self, record: &UsageRecord) -> Result<()> {
+        append_line(&self.usage_writer, &self.encode(record)?)
+    }
+
+    fn usage(&self, since: Option<DateTime<Utc>>) -> Result<Vec<UsageRecord>> {
+        let records = read_jsonl::<UsageRecord>(&self.usage_path, self.cipher.as_ref())?;
+        Ok(records
+            .into_iter()
+            .filter(|r| since.map_or(true, |t| r.timestamp >= t))
+            .collect())
+    }
+
+    fn cache_get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let value = match std::fs::read(self.cache_path(key)) {
+            Ok(value) => value,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match self.cipher {
+            Some(ref cipher) if value.starts_with(SEALED_PREFIX.as_bytes()) => {
+                cipher.open(&String::from_utf8_lossy(&value)).map(Some)
+            }
+            // Written before encryption was turned on; drop it rather than
+            // serve plaintext back into an encrypted store.
+            Some(_) => Ok(None),
+            None => Ok(Some(value)),
+        }
+    }
+
+    fn cache_put(&self, key: &str, value: &[u8]) -> Result<()> {
+        std::fs::create_dir_all(&self.cache_dir)?;
+        match self.cipher {
+            Some(ref cipher) => std::fs::write(self.cache_path(key), cipher.seal(value)?)?,
+            None => std::fs::write(self.cache_path(key), value)?,
+        }
+        Ok(())
+    }
+}
+
+fn append_writer(path: &Path) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+fn append_line(writer: &Mutex<BufWriter<File>>, line: &str) -> Result<()> {
+    let mut writer = lock(writer)?;
+    writeln!(writer, "{line}")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parse a JSONL file, skipping lines that don't parse (or are encrypted and
+/// can't be opened with `cipher`). A missing file is empty.
+fn read_jsonl<T: serde::de::DeserializeOwned>(
+    path: &Path,
+    cipher: Option<&Cipher>,
+) -> Result<Vec<T>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(std::result::Result::ok)
+        .filter_map(|line| match cipher {
+            Some(cipher) if is_sealed(&line) => cipher.open(&line).ok(),
+            _ => Some(line.into_bytes()),
+        })
+        .filter_map(|line| serde_json::from_slice(&line).ok())
+        .collect())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<std::sync::MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| ProxyError::storage("storage lock poisoned"))
+}
+
+/// 64-bit FNV-1a: a stable hash for file names and keys.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+// ---------------------------------------------------------------------------
+// SQLite storage
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{lock, Storage, UsageRecord};
+    use crate::error::{ProxyError, Result};
+    use crate::logging::LogEntry;
+
+    use chrono::{DateTime, Utc};
+    use rusqlite::{params, Connection, OptionalExtension};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS logs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp TEXT NOT NULL,
+            entry TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS usage (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp TEXT NOT NULL,
+            record TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS usage_timestamp ON usage (timestamp);
+        CREATE TABLE IF NOT EXISTS cache (
+            key TEXT PRIMARY KEY,
+            value BLOB NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+    ";
+
+    /// A single `SQLite` database holding logs, usage and cache tables. Entries
+    /// are stored as JSON alongside an RFC 3339 timestamp for range queries.
+    pub struct SqliteStorage {
+        conn: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        /// Open (creating if needed) the database at `path`.
+        ///
+        /// # Errors
+        /// Returns `ProxyError::Storage` if the database can't be opened.
+        pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+            Self::from_connection(Connection::open(path).map_err(storage_error)?)
+        }
+
+        /// An in-memory database, for tests.
+        ///
+        /// # Errors
+        /// Returns `ProxyError::Storage` if the schema can't be created.
+        pub fn in_memory() -> Result<Self> {
+            Self::from_connection(Connection::open_in_memory().map_err(storage_error)?)
+        }
+
+        fn from_connection(conn: Connection) -> Result<Self> {
+            conn.execute_batch(SCHEMA).map_err(storage_error)?;
+            Ok(Self {
+                conn: Mutex::new(conn),
+            })
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        fn append_log(&self, entry: &LogEntry) -> Result<()> {
+            let json = serde_json::to_string(entry)?;
+            lock(&self.conn)?
+                .execute(
+                    "INSERT INTO logs (timestamp, entry) VALUES (?1, ?2)",
+                    params![entry.timestamp.to_rfc3339(), json],
+                )
+                .map_err(storage_error)?;
+            Ok(())
+        }
+
+        fn recent_logs(&self, limit: usize) -> Result<Vec<LogEntry>> {
+            let conn = lock(&self.conn)?;
+            let mut stmt = conn
+                .prepare("SELECT entry FROM logs ORDER BY id DESC LIMIT ?1")
+                .map_err(storage_error)?;
+            let rows = stmt
+                .query_map([i64::try_from(limit).unwrap_or(i64::MAX)], |row| {
+                    row.get::<_, String>(0)
+                })
+                .map_err(storage_error)?;
+            let mut entries: Vec<LogEntry> = rows
+                .filter_map(std::result::Result::ok)
+                .filter_map(|json| serde_json::from_str(&json).ok())
+                .collect();
+            entries.reverse();
+            Ok(entries)
+        }
+
+        fn compact_logs(&self, keep: usize) -> Result<()> {
+            lock(&self.conn)?
+                .execute(
+                    "DELETE FROM logs WHERE id NOT IN \
+                     (SELECT id FROM logs ORDER BY id DESC LIMIT ?1)",
+                    [i64::try_from(keep).unwrap_or(i64::MAX)],
+                )
+                .map_err(storage_error)?;
+            Ok(())
+        }
+
+        fn log_size(&self) -> Result<Option<u64>> {
+            let size: i64 = lock(&self.conn)?
+                .query_row(
+                    "SELECT COALESCE(SUM(LENGTH(entry)), 0) FROM logs",
+                    [],
+                    |row| row.get(0),
+                )
+                .map_err(storage_error)?;
+            Ok(u64::try_from(size).ok())
+        }
+
+        fn record_usage(&self, record: &UsageRecord) -> Result<()> {
+            let json = serde_json::to_string(record)?;
+            lock(&self.conn)?
+                .execute(
+                    "INSERT INTO usage (timestamp, record) VALUES (?1, ?2)",
+                    params![record.timestamp.to_rfc3339(), json],
+                )
+                .map_err(storage_error)?;
+            Ok(())
+        }
+
+        fn usage(&self, since: Option<DateTime<Utc>>) -> Result<Vec<UsageRecord>> {
+            // RFC 3339 timestamps in UTC sort lexicographically
+            let since = since.map_or_else(String::new, |t| t.to_rfc3339());
+            let conn = lock(&self.conn)?;
+            let mut stmt = conn
+                .prepare("SELECT record FROM usage WHERE timestamp >= ?1 ORDER BY id")
+                .map_err(storage_error)?;
+            let rows = stmt
+                .query_map([since], |row| row.get::<_, String>(0))
+                .map_err(storage_error)?;
+            Ok(rows
+                .filter_map(std::result::Result::ok)
+                .filter_map(|json| serde_json::from_str(&json).ok())
+                .collect())
+        }
+
+        fn cache_get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            lock(&self.conn)?
+                .query_row("SELECT value FROM cache WHERE key = ?1", [key], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(storage_error)
+        }
+
+        fn cache_put(&self, key: &str, value: &[u8]) -> Result<()> {
+            lock(&self.conn)?
+                .execute(
+                    "INSERT INTO cache (key, value, updated_at) VALUES (?1, ?2, ?3) \
+                     ON CONFLICT (key) DO UPDATE SET value = ?2, updated_at = ?3",
+                    params![key, value, Utc::now().to_rfc3339()],
+                )
+                .map_err(storage_error)?;
+            Ok(())
+        }
+    }
+
+    #[allow(clippy::needless_pass_by_value)]
+    fn storage_error(e: rusqlite::Error) -> ProxyError {
+        ProxyError::storage(format!("SQLite: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::LogLevel;
+
+    fn usage_record(model: &str) -> UsageRecord {
+        UsageRecord {
+            timestamp: Utc::now(),
+            provider: "fireworks".to_string(),
+            requested_model: "claude-sonnet-4-20250514".to_string(),
+            upstream_model: model.to_string(),
+            input_tokens: 10,
+            output_tokens: 5,
+            streaming: false,
+            session_id: None,
+            tags: Vec::new(),
+            cost: None,
+        }
+    }
+
+    fn exercise(storage: &dyn Storage) {
+        for i in 0..3 {
+            storage
+                .append_log(&LogEntry::new(LogLevel::Info, "test", format!("entry {i}")))
+                .unwrap();
+        }
+        let logs = storage.recent_logs(2).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[1].message, "entry 2");
+
+        let size = storage.log_size().unwrap().unwrap();
+        storage.compact_logs(1).unwrap();
+        assert!(storage.log_size().unwrap().unwrap() < size);
+        storage
+            .append_log(&LogEntry::new(LogLevel::Info, "test", "entry 3"))
+            .unwrap();
+        let logs = storage.recent_logs(10).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].message, "entry 2");
+
+        let before = Utc::now();
+        storage.record_usage(&usage_record("kimi-k2")).unwrap();
+        assert_eq!(storage.usage(None).unwrap().len(), 1);
+        assert!(storage
+            .usage(Some(before + chrono::Duration::hours(1)))
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(storage.cache_get("k").unwrap(), None);
+        storage.cache_put("k", b"one").unwrap();
+        storage.cache_put("k", b"two").unwrap();
+        assert_eq!(storage.cache_get("k").unwrap(), Some(b"two".to_vec()));
+    }
+
+    #[test]
+    fn test_file_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(dir.path().join("proxy.log")).unwrap();
+        exercise(&storage);
+        assert!(dir.path().join("proxy.usage.jsonl").exists());
+    }
+
+    #[test]
+    fn test_encrypted_file_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("proxy.log");
+        std::fs::write(&log, "{\"timestamp\":\"2025-01-01T00:00:00Z\",\"level\":\"info\",\"component\":\"test\",\"message\":\"plaintext\"}\n").unwrap();
+        let cipher = Cipher::from_hex(&"ab".repeat(32)).unwrap();
+        let storage = FileStorage::new(&log)
+            .unwrap()
+            .with_cipher(Some(cipher.clone()));
+        // Entries from before encryption was turned on are still read
+        assert_eq!(storage.recent_logs(10).unwrap()[0].message, "plaintext");
+        exercise(&storage);
+
+        for path in [log.clone(), dir.path().join("proxy.usage.jsonl")] {
+            let content = std::fs::read_to_string(&path).unwrap();
+            assert!(
+                content.lines().all(crate::encryption::is_sealed),
+                "{content}"
+            );
+        }
+        // Without the key the encrypted entries are unreadable
+        let plain = FileStorage::new(&log).unwrap();
+        assert!(plain.recent_logs(10).unwrap().is_empty());
+        assert!(plain.usage(None).unwrap().is_empty());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_storage() {
+        exercise(&SqliteStorage::in_memory().unwrap());
+    }
+}
@@ -0,0 +1,203 @@
+//! Serving HTTPS (`[tls]`).
+//!
+//! Plain HTTP is fine while Claude Code runs on the same machine, but not
+//! across a network, and some environments refuse it outright. With `cert`
+//! and `key` the proxy serves HTTPS with that certificate; with `self_signed`
+//! it generates one for `localhost` and any `hosts` (an ECDSA P-256 key, made
+//! in-process), and keeps it in `dir` so clients only have to trust it once (e.g.
+//! with `NODE_EXTRA_CA_CERTS`). Delete `dir` to generate a new one. HTTP/1.1
+//! and HTTP/2 are both offered.
+
+use crate::config::TlsConfig;
+use crate::error::{ProxyError, Result};
+
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use chrono::Datelike;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// How long a self-signed certificate is valid for, in days. Apple platforms
+/// refuse server certificates valid for longer than 825 days.
+const SELF_SIGNED_DAYS: u64 = 825;
+
+/// A loaded certificate, ready to accept connections with.
+#[derive(Clone)]
+pub struct Tls {
+    pub acceptor: TlsAcceptor,
+    /// The certificate file, for clients that need to be told to trust it.
+    pub cert: PathBuf,
+}
+
+/// The certificate `config` names, generating a self-signed one first if
+/// asked; `None` when TLS is off.
+///
+/// # Errors
+/// Returns `ProxyError::Config` if the certificate or key can't be read, or a
+/// self-signed certificate can't be generated.
+pub fn load(config: &TlsConfig) -> Result<Option<Tls>> {
+    let (cert, key) = match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => (PathBuf::from(cert), PathBuf::from(key)),
+        _ if config.self_signed => self_signed(Path::new(&config.dir), &config.hosts)?,
+        _ => return Ok(None),
+    };
+    let chain = CertificateDer::pem_file_iter(&cert)
+        .and_then(Iterator::collect::<std::result::Result<Vec<_>, _>>)
+        .map_err(|e| {
+            ProxyError::config(format!("Can't read certificate {}: {e}", cert.display()))
+        })?;
+    if chain.is_empty() {
+        return Err(ProxyError::config(format!(
+            "No certificate found in {}",
+            cert.display()
+        )));
+    }
+    let private_key = PrivateKeyDer::from_pem_file(&key).map_err(|e| {
+        ProxyError::config(format!("Can't read private key {}: {e}", key.display()))
+    })?;
+    let mut server = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(|e| ProxyError::config(format!("TLS setup failed: {e}")))?
+    .with_no_client_auth()
+    .with_single_cert(chain, private_key)
+    .map_err(|e| {
+        ProxyError::config(format!(
+            "Certificate {} doesn't go with key {}: {e}",
+            cert.display(),
+            key.display()
+        ))
+    })?;
+    server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Some(Tls {
+        acceptor: TlsAcceptor::from(Arc::new(server)),
+        cert,
+    }))
+}
+
+/// The self-signed certificate and key in `dir`, generated if not there yet.
+fn self_signed(dir: &Path, hosts: &[String]) -> Result<(PathBuf, PathBuf)> {
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    if cert.exists() && key.exists() {
+        return Ok((cert, key));
+    }
+    std::fs::create_dir_all(dir).map_err(|e| {
+        ProxyError::config(format!("Can't create TLS directory {}: {e}", dir.display()))
+    })?;
+    let generated = generate(hosts).map_err(|e| {
+        ProxyError::config(format!("Can't generate a self-signed certificate: {e}"))
+    })?;
+    // A key left by an interrupted run, without its certificate
+    let _ = std::fs::remove_file(&key);
+    write_private(&key, generated.key.as_bytes()).map_err(|e| {
+        ProxyError::config(format!("Can't write private key {}: {e}", key.display()))
+    })?;
+    std::fs::write(&cert, generated.cert).map_err(|e| {
+        ProxyError::config(format!("Can't write certificate {}: {e}", cert.display()))
+    })?;
+    Ok((cert, key))
+}
+
+/// A certificate and its key, PEM-encoded.
+struct Generated {
+    cert: String,
+    key: String,
+}
+
+/// A certificate for `localhost`, its loopback addresses and `hosts`, valid
+/// from today for [`SELF_SIGNED_DAYS`].
+fn generate(hosts: &[String]) -> std::result::Result<Generated, rcgen::Error> {
+    let names: Vec<String> = ["localhost", "127.0.0.1", "::1"]
+        .iter()
+        .map(|host| (*host).to_string())
+        .chain(hosts.iter().cloned())
+        .collect();
+    // Names that parse as IP addresses become IP address entries
+    let mut params = rcgen::CertificateParams::new(names)?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "claude-proxy");
+    let today = chrono::Utc::now().date_naive();
+    let ymd = |date: chrono::NaiveDate| {
+        rcgen::date_time_ymd(date.year(), date.month() as u8, date.day() as u8)
+    };
+    params.not_before = ymd(today);
+    params.not_after = ymd(today + chrono::Days::new(SELF_SIGNED_DAYS));
+    let key = rcgen::KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+    Ok(Generated {
+        cert: cert.pem(),
+        key: key.serialize_pem(),
+    })
+}
+
+/// Write `contents` to a new file at `path` that only its owner can read,
+/// from the moment it is created.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+/// Serve `app` over TLS on `listener` until `shutdown` completes, then wait
+/// for open connections to finish. Connections that fail the handshake, such
+/// as plain HTTP ones, are dropped. Requests carry the client's address as
+/// [`ConnectInfo`], as with `into_make_service_with_connect_info`.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    acceptor: TlsAcceptor,
+    shutdown: impl Future<Output = ()>,
+) {
+    let graceful = GracefulShutdown::new();
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Out of file descriptors, say; wait rather than spin
+                    tracing::warn!("Accepting a connection failed: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(app.clone().layer(Extension(ConnectInfo(peer))));
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::debug!("TLS handshake failed: {e}");
+                    return;
+                }
+            };
+            let connection = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .into_owned();
+            let _ = watcher.watch(connection).await;
+        });
+    }
+    graceful.shutdown().await;
+}
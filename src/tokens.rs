@@ -0,0 +1,58 @@
+//! Cheap token-count estimates.
+//!
+//! The proxy never has the backend's tokenizer, so anywhere it needs a token
+//! count before the provider reports one (live usage meters, pacing, context
+//! checks) it uses the usual ~4 characters per token heuristic.
+
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Estimate the number of tokens in `text`. Non-empty text is at least one token.
+#[must_use]
+pub fn estimate_tokens(text: &str) -> u64 {
+    let chars = text.chars().count();
+    chars.div_ceil(CHARS_PER_TOKEN) as u64
+}
+
+/// Estimate the tokens in `value`'s JSON, as [`estimate_tokens`] would, without
+/// building the JSON: a prompt can be megabytes.
+#[must_use]
+pub fn estimate_json_tokens(value: &impl serde::Serialize) -> u64 {
+    let mut counter = CharCounter(0);
+    if serde_json::to_writer(&mut counter, value).is_err() {
+        return 0;
+    }
+    counter.0.div_ceil(CHARS_PER_TOKEN) as u64
+}
+
+/// Counts the characters of the UTF-8 written to it.
+struct CharCounter(usize);
+
+impl std::io::Write for CharCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Every character has exactly one byte that isn't a continuation byte
+        self.0 += buf.iter().filter(|&&b| b & 0xC0 != 0x80).count();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("hi"), 1);
+        assert_eq!(estimate_tokens("hello world!"), 3);
+
+        let value = serde_json::json!({"text": "héllo wörld", "n": [1, 2]});
+        assert_eq!(
+            estimate_json_tokens(&value),
+            estimate_tokens(&value.to_string())
+        );
+    }
+}
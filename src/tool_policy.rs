@@ -0,0 +1,239 @@
+//! Keeping tools away from providers that shouldn't have them.
+//!
+//! A provider's `allow_tools` and `deny_tools` (e.g. `deny_tools = ["Bash"]`
+//! for a provider you don't trust to run commands on your machine) limit the
+//! tools it sees. Before a request is translated for it, [`strip`] removes
+//! the definitions of tools it may not use. A model can still name a tool it
+//! wasn't offered, so a response calling one is not returned: each of its
+//! calls gets a synthesized error `tool_result` saying the tool isn't
+//! available, and the model is asked again ([`reprompt`]), up to
+//! [`MAX_REPROMPTS`] times. If it persists, the calls are cut from the
+//! response ([`remove_denied`]) so the client never runs them.
+//!
+//! Checking needs the whole response, so streamed requests that offer tools
+//! to such a provider are sent upstream non-streaming and replayed as a
+//! stream, as for `expect` checks.
+
+use crate::config::ProviderConfig;
+use crate::translate::anthropic_types::{
+    ContentBlock, Message, MessageContent, MessagesRequest, MessagesResponse, ResponseContentBlock,
+    Role, ToolChoice, ToolChoiceAuto, ToolResultContent,
+};
+
+/// Times a model that calls a tool it may not use is asked again.
+pub const MAX_REPROMPTS: u32 = 2;
+
+/// `req` without the tools `provider` may not use, or `None` if it may use
+/// all of them. A `tool_choice` naming a removed tool becomes `auto`.
+#[must_use]
+pub fn strip(req: &MessagesRequest, provider: &ProviderConfig) -> Option<MessagesRequest> {
+    let tools = req.tools.as_ref()?;
+    if !provider.has_tool_policy() || tools.iter().all(|t| provider.allows_tool(&t.name)) {
+        return None;
+    }
+    let mut next = req.clone();
+    let kept: Vec<_> = tools
+        .iter()
+        .filter(|t| provider.allows_tool(&t.name))
+        .cloned()
+        .collect();
+    next.tool_choice = match next.tool_choice.take() {
+        _ if kept.is_empty() => None,
+        Some(ToolChoice::Specific(choice)) if !provider.allows_tool(&choice.name) => {
+            Some(ToolChoice::Auto(ToolChoiceAuto {
+                choice_type: "auto".to_string(),
+            }))
+        }
+        choice => choice,
+    };
+    next.tools = (!kept.is_empty()).then_some(kept);
+    Some(next)
+}
+
+/// The names of the tools `resp` calls that `provider` may not use.
+#[must_use]
+pub fn denied_calls<'a>(resp: &'a MessagesResponse, provider: &ProviderConfig) -> Vec<&'a str> {
+    resp.content
+        .iter()
+        .filter_map(|block| match block {
+            ResponseContentBlock::ToolUse { name, .. } if !provider.allows_tool(name) => {
+                Some(name.as_str())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// `req` followed by `resp` and a user turn answering each of its tool calls
+/// with an error: the tool isn't available, or, for the calls it may make,
+/// that they weren't run alongside the rejected ones.
+#[must_use]
+pub fn reprompt(
+    req: &MessagesRequest,
+    resp: &MessagesResponse,
+    provider: &ProviderConfig,
+) -> MessagesRequest {
+    let mut next = req.clone();
+    let mut said = Vec::new();
+    let mut results = Vec::new();
+    for block in &resp.content {
+        match block {
+            ResponseContentBlock::Text { text } if !text.is_empty() => {
+                said.push(ContentBlock::Text {
+                    text: text.clone(),
+                    cache_control: None,
+                });
+            }
+            ResponseContentBlock::ToolUse { id, name, input } => {
+                said.push(ContentBlock::ToolUse {
+                    id: id.clone(),
+                    name: name.clone(),
+                    input: input.clone(),
+                });
+                let error = if provider.allows_tool(name) {
+                    "Not run, because this turn also called a tool that isn't available. \
+                     Call it again if you still need it."
+                        .to_string()
+                } else {
+                    format!(
+                        "The tool `{name}` is not available. Use one of the tools offered, \
+                         or continue without it."
+                    )
+                };
+                results.push(ContentBlock::ToolResult {
+                    tool_use_id: id.clone(),
+                    content: Some(ToolResultContent::Text(error)),
+                    is_error: Some(true),
+                    cache_control: None,
+                });
+            }
+            _ => {}
+        }
+    }
+    next.messages.push(Message {
+        role: Role::Assistant,
+        content: MessageContent::Blocks(said),
+    });
+    next.messages.push(Message {
+        role: Role::User,
+        content: MessageContent::Blocks(results),
+    });
+    next
+}
+
+/// `resp` without its calls to tools `provider` may not use, and a note
+/// saying so. A turn left without tool calls ends instead of waiting on them.
+#[must_use]
+pub fn remove_denied(mut resp: MessagesResponse, provider: &ProviderConfig) -> MessagesResponse {
+    let denied: Vec<String> = denied_calls(&resp, provider)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    if denied.is_empty() {
+        return resp;
+    }
+    resp.content.retain(|block| {
+        !matches!(block, ResponseContentBlock::ToolUse { name, .. } if denied.contains(name))
+    });
+    resp.content.push(ResponseContentBlock::Text {
+        text: format!(
+            "[claude-proxy removed a call to {}, which {} may not use]",
+            denied.join(", "),
+            provider.name
+        ),
+    });
+    let calls_left = resp
+        .content
+        .iter()
+        .any(|block| matches!(block, ResponseContentBlock::ToolUse { .. }));
+    if !calls_left && resp.stop_reason.as_deref() == Some("tool_use") {
+        resp.stop_reason = Some("end_turn".to_string());
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> ProviderConfig {
+        ProviderConfig {
+            name: "untrusted".to_string(),
+            deny_tools: vec!["Bash".to_string(), "mcp__*".to_string()],
+            ..ProviderConfig::default()
+        }
+    }
+
+    fn response(calls: &[&str]) -> MessagesResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "msg_1", "type": "message", "role": "assistant", "model": "m",
+            "content": calls.iter().enumerate().map(|(i, name)| serde_json::json!({
+                "type": "tool_use", "id": format!("t{i}"), "name": name, "input": {}
+            })).collect::<Vec<_>>(),
+            "stop_reason": "tool_use", "stop_sequence": null,
+            "usage": {"input_tokens": 1, "output_tokens": 1},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_policy() {
+        let provider = provider();
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514", "max_tokens": 100,
+            "messages": [{"role": "user", "content": "List the files"}],
+            "tools": [
+                {"name": "Bash", "input_schema": {}},
+                {"name": "Read", "input_schema": {}},
+                {"name": "mcp__github__search", "input_schema": {}},
+            ],
+            "tool_choice": {"type": "tool", "name": "Bash"},
+        }))
+        .unwrap();
+
+        let stripped = strip(&req, &provider).unwrap();
+        let names: Vec<_> = stripped.tools.iter().flatten().map(|t| &t.name).collect();
+        assert_eq!(names, ["Read"]);
+        assert!(matches!(stripped.tool_choice, Some(ToolChoice::Auto(_))));
+        assert!(strip(&stripped, &provider).is_none());
+        assert!(strip(&req, &ProviderConfig::default()).is_none());
+
+        let resp = response(&["Read", "Bash"]);
+        assert_eq!(denied_calls(&resp, &provider), ["Bash"]);
+        let retry = reprompt(&stripped, &resp, &provider);
+        let Some(Message {
+            role: Role::User,
+            content: MessageContent::Blocks(results),
+        }) = retry.messages.last()
+        else {
+            panic!("ends with the tool results");
+        };
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| matches!(
+            r,
+            ContentBlock::ToolResult {
+                is_error: Some(true),
+                ..
+            }
+        )));
+
+        let cut = remove_denied(response(&["Bash"]), &provider);
+        assert_eq!(cut.stop_reason.as_deref(), Some("end_turn"));
+        assert!(matches!(
+            &cut.content[..],
+            [ResponseContentBlock::Text { .. }]
+        ));
+        let kept = remove_denied(response(&["Read", "Bash"]), &provider);
+        assert_eq!(kept.stop_reason.as_deref(), Some("tool_use"));
+
+        // An allow list lets only its tools through, and the deny list still applies
+        let allowing = ProviderConfig {
+            allow_tools: vec!["Read".to_string(), "Bash".to_string()],
+            deny_tools: vec!["Bash".to_string()],
+            ..ProviderConfig::default()
+        };
+        assert!(allowing.allows_tool("Read"));
+        assert!(!allowing.allows_tool("Bash"));
+        assert!(!allowing.allows_tool("Write"));
+    }
+}
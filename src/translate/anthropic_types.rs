@@ -57,7 +57,21 @@ pub enum SystemContent {
 #[serde(tag = "type")]
 pub enum SystemBlock {
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+}
+
+/// Prompt caching breakpoint: the prompt up to and including the marked
+/// block may be cached.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheControl {
+    #[serde(rename = "type")]
+    pub cache_type: String, // "ephemeral"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,9 +99,25 @@ pub enum MessageContent {
 #[non_exhaustive]
 pub enum ContentBlock {
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     #[serde(rename = "image")]
     Image { source: ImageSource },
+    #[serde(rename = "document")]
+    Document {
+        source: DocumentSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        context: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        citations: Option<serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     #[serde(rename = "tool_use")]
     ToolUse {
         id: String,
@@ -101,6 +131,8 @@ pub enum ContentBlock {
         content: Option<ToolResultContent>,
         #[serde(skip_serializing_if = "Option::is_none")]
         is_error: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
     },
     #[serde(rename = "thinking")]
     Thinking {
@@ -115,14 +147,41 @@ pub enum ContentBlock {
 pub enum ToolResultContent {
     Text(String),
     Blocks(Vec<ContentBlock>),
+    /// Any other shape: a single unwrapped block, a block list containing types
+    /// we don't model, or structured JSON. Sent by clients using the
+    /// token-efficient tool-use beta.
+    Raw(serde_json::Value),
 }
 
+/// An image's data: `base64` with its `media_type`, or a `url`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageSource {
     #[serde(rename = "type")]
     pub source_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub media_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// A document's data: a `base64` PDF, plain `text`, `content` blocks, a `url`
+/// or an uploaded `file`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSource {
+    #[serde(rename = "type")]
+    pub source_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub media_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub data: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -130,7 +189,34 @@ pub struct Tool {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// Absent for server tools (e.g. `web_search`), which are configured by
+    /// their own fields instead.
+    #[serde(default)]
     pub input_schema: serde_json::Value,
+    /// Server tool type (e.g. `web_search_20250305`); `None` or `"custom"` for
+    /// client tools.
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub tool_type: Option<String>,
+    /// Server tool settings (`max_uses`, `allowed_domains`, ...).
+    #[serde(flatten)]
+    pub extra: std::collections::HashMap<String, serde_json::Value>,
+}
+
+impl Tool {
+    /// Whether this is an Anthropic-hosted server tool rather than a client
+    /// tool the model calls through function calling.
+    #[must_use]
+    pub fn is_server_tool(&self) -> bool {
+        self.tool_type.as_deref().is_some_and(|t| t != "custom")
+    }
+
+    /// Whether this is the Anthropic web search server tool.
+    #[must_use]
+    pub fn is_web_search(&self) -> bool {
+        self.tool_type
+            .as_deref()
+            .is_some_and(|t| t.starts_with("web_search"))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -176,8 +262,21 @@ pub struct MessagesResponse {
     pub stop_reason: Option<String>,
     pub stop_sequence: Option<String>,
     pub usage: Usage,
+    /// Provider-specific data with no Anthropic equivalent (see [`Extra`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra: Option<Extra>,
 }
 
+/// Provider-specific data about a response, surfaced under `extra` for
+/// clients that ask for it with an `x-claude-proxy-extra` header. Keys are
+/// stable and present only when the provider sent them:
+///
+/// - `system_fingerprint`: the backend configuration that served the request
+/// - `logprobs`: token log probabilities, as the provider reported them
+/// - `citations`: sources the answer drew on (Perplexity, Grok)
+/// - `cost`: what the request cost, in the provider's unit (`OpenRouter`)
+pub type Extra = serde_json::Map<String, serde_json::Value>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[non_exhaustive]
@@ -190,6 +289,14 @@ pub enum ResponseContentBlock {
         name: String,
         input: serde_json::Value,
     },
+    /// Reasoning from a model that exposes it (`reasoning_content`). Providers
+    /// don't sign their reasoning, so `signature` is empty.
+    #[serde(rename = "thinking")]
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -225,6 +332,9 @@ pub enum StreamEvent {
     MessageDelta {
         delta: MessageDeltaBody,
         usage: DeltaUsage,
+        /// The response's [`Extra`] data, on the final delta.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        extra: Option<Extra>,
     },
     #[serde(rename = "message_stop")]
     MessageStop,
@@ -239,6 +349,8 @@ pub enum Delta {
     TextDelta { text: String },
     #[serde(rename = "input_json_delta")]
     InputJsonDelta { partial_json: String },
+    #[serde(rename = "thinking_delta")]
+    ThinkingDelta { thinking: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -252,6 +364,8 @@ pub struct MessageDeltaBody {
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DeltaUsage {
     pub output_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u64>,
 }
 
 // ---------------------------------------------------------------------------
@@ -294,6 +408,10 @@ impl ErrorResponse {
     pub fn overloaded(msg: impl Into<String>) -> Self {
         Self::new("overloaded_error", msg)
     }
+
+    pub fn authentication(msg: impl Into<String>) -> Self {
+        Self::new("authentication_error", msg)
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -308,7 +426,7 @@ impl SystemContent {
             SystemContent::Blocks(blocks) => blocks
                 .iter()
                 .map(|b| match b {
-                    SystemBlock::Text { text } => text.as_str(),
+                    SystemBlock::Text { text, .. } => text.as_str(),
                 })
                 .collect::<Vec<_>>()
                 .join("\n"),
@@ -316,11 +434,34 @@ impl SystemContent {
     }
 }
 
+/// Beta name prefix Claude Code uses for token-efficient tool use.
+pub const TOKEN_EFFICIENT_TOOLS_BETA: &str = "token-efficient-tools";
+
+impl MessagesRequest {
+    /// Whether the request body opts into a beta whose name starts with `prefix`.
+    #[must_use]
+    pub fn has_beta(&self, prefix: &str) -> bool {
+        match &self.betas {
+            Some(serde_json::Value::String(s)) => {
+                s.split(',').any(|b| b.trim().starts_with(prefix))
+            }
+            Some(serde_json::Value::Array(items)) => items
+                .iter()
+                .filter_map(serde_json::Value::as_str)
+                .any(|b| b.starts_with(prefix)),
+            _ => false,
+        }
+    }
+}
+
 impl MessageContent {
     #[must_use]
     pub fn blocks(&self) -> Vec<ContentBlock> {
         match self {
-            MessageContent::Text(t) => vec![ContentBlock::Text { text: t.clone() }],
+            MessageContent::Text(t) => vec![ContentBlock::Text {
+                text: t.clone(),
+                cache_control: None,
+            }],
             MessageContent::Blocks(b) => b.clone(),
         }
     }
@@ -0,0 +1,777 @@
+//! Adapter for the [AWS Bedrock Converse API](https://docs.aws.amazon.com/bedrock/latest/APIReference/API_runtime_Converse.html).
+//!
+//! Converse gives every Bedrock-hosted model, Anthropic's included, one
+//! request shape: content blocks keyed by type (`text`, `image`, `toolUse`,
+//! `toolResult`), system prompts kept apart from the strictly alternating
+//! `user`/`assistant` turns, and tool results sent in a user turn. Like the
+//! Cohere adapter, this module converts the already-translated `OpenAI`
+//! request into that shape and Converse responses and stream events back into
+//! `OpenAI` types, so the existing response translator and
+//! [`StreamTranslator`](super::streaming::StreamTranslator) handle the rest.
+//!
+//! Stream events arrive one per eventstream message (see
+//! [`crate::aws::EventStreamDecoder`]), named by the message's `:event-type`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::openai_types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatContent, ChatMessage,
+    ChatToolCall, ChatToolCallFunction, ChatToolChoice, ChatUsage, Choice, ChoiceMessage,
+    ChunkChoice, ChunkDelta, ChunkToolCall, ChunkToolCallFunction, ContentPart, FileDetail,
+};
+
+// ---------------------------------------------------------------------------
+// Request types (what we send TO Bedrock)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConverseRequest {
+    pub messages: Vec<ConverseMessage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub system: Vec<SystemBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inference_config: Option<InferenceConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConverseMessage {
+    pub role: String, // "user" or "assistant"
+    pub content: Vec<ContentBlock>,
+}
+
+/// A content block: exactly one field is set. Kinds we don't translate (e.g.
+/// documents, citations) are ignored when reading responses.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentBlock {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<DocumentBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_use: Option<ToolUseBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_result: Option<ToolResultBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<ReasoningContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageBlock {
+    pub format: String, // "png", "jpeg", "gif" or "webp"
+    pub source: ImageSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSource {
+    /// Base64-encoded image data.
+    pub bytes: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentBlock {
+    pub format: String, // "pdf", "txt", "md", ...
+    /// Letters, digits, single spaces, hyphens, parentheses and brackets.
+    pub name: String,
+    pub source: DocumentSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSource {
+    /// Base64-encoded document data.
+    pub bytes: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolUseBlock {
+    pub tool_use_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub input: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolResultBlock {
+    pub tool_use_id: String,
+    pub content: Vec<ToolResultContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolResultContent {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReasoningContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_text: Option<ReasoningText>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReasoningText {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemBlock {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolConfig {
+    pub tools: Vec<BedrockTool>,
+    /// `{"auto": {}}`, `{"any": {}}` or `{"tool": {"name": ...}}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BedrockTool {
+    pub tool_spec: ToolSpec,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolSpec {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: InputSchema,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputSchema {
+    pub json: serde_json::Value,
+}
+
+// ---------------------------------------------------------------------------
+// Response types (what we receive FROM Bedrock)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConverseResponse {
+    pub output: ConverseOutput,
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConverseOutput {
+    #[serde(default)]
+    pub message: Option<ConverseMessage>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+// ---------------------------------------------------------------------------
+// Translation
+// ---------------------------------------------------------------------------
+
+/// Convert a translated `OpenAI` request into a Converse request.
+///
+/// System messages become `system` blocks; tool messages become `toolResult`
+/// blocks in a user turn; consecutive turns of the same role are merged, since
+/// Converse requires them to alternate.
+#[must_use]
+pub fn openai_to_converse(req: &ChatCompletionRequest) -> ConverseRequest {
+    let mut system = Vec::new();
+    let mut messages: Vec<ConverseMessage> = Vec::new();
+
+    for msg in &req.messages {
+        let (role, blocks) = match msg.role.as_str() {
+            "system" => {
+                system.extend(
+                    content_blocks(msg)
+                        .into_iter()
+                        .filter_map(|b| b.text)
+                        .map(|text| SystemBlock { text }),
+                );
+                continue;
+            }
+            "assistant" => {
+                let mut blocks = content_blocks(msg);
+                for call in msg.tool_calls.iter().flatten() {
+                    blocks.push(ContentBlock {
+                        tool_use: Some(ToolUseBlock {
+                            tool_use_id: call.id.clone(),
+                            name: call.function.name.clone(),
+                            input: serde_json::from_str(&call.function.arguments)
+                                .unwrap_or_else(|_| serde_json::json!({})),
+                        }),
+                        ..ContentBlock::default()
+                    });
+                }
+                ("assistant", blocks)
+            }
+            "tool" => {
+                let text = content_blocks(msg)
+                    .into_iter()
+                    .filter_map(|b| b.text)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let block = ContentBlock {
+                    tool_result: Some(ToolResultBlock {
+                        tool_use_id: msg.tool_call_id.clone().unwrap_or_default(),
+                        content: vec![ToolResultContent { text }],
+                    }),
+                    ..ContentBlock::default()
+                };
+                ("user", vec![block])
+            }
+            _ => ("user", content_blocks(msg)),
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        match messages.last_mut() {
+            Some(last) if last.role == role => last.content.extend(blocks),
+            _ => messages.push(ConverseMessage {
+                role: role.to_string(),
+                content: blocks,
+            }),
+        }
+    }
+
+    let inference_config = InferenceConfig {
+        max_tokens: req.max_tokens,
+        temperature: req.temperature,
+        top_p: req.top_p,
+        stop_sequences: req.stop.clone(),
+    };
+
+    let tool_config = req
+        .tools
+        .as_ref()
+        .filter(|t| !t.is_empty())
+        .map(|tools| ToolConfig {
+            tools: tools
+                .iter()
+                .map(|t| BedrockTool {
+                    tool_spec: ToolSpec {
+                        name: t.function.name.clone(),
+                        description: t.function.description.as_deref().map(str::to_string),
+                        input_schema: InputSchema {
+                            json: t.function.parameters.clone().into_owned(),
+                        },
+                    },
+                })
+                .collect(),
+            tool_choice: req.tool_choice.as_ref().and_then(tool_choice),
+        });
+
+    ConverseRequest {
+        messages,
+        system,
+        inference_config: Some(inference_config),
+        tool_config,
+    }
+}
+
+/// Convert a Converse response into an `OpenAI` chat completion.
+#[must_use]
+pub fn converse_to_openai(resp: &ConverseResponse) -> ChatCompletionResponse {
+    let blocks = resp
+        .output
+        .message
+        .as_ref()
+        .map_or(&[][..], |m| m.content.as_slice());
+
+    let text: String = blocks.iter().filter_map(|b| b.text.as_deref()).collect();
+    let reasoning: String = blocks
+        .iter()
+        .filter_map(|b| b.reasoning_content.as_ref()?.reasoning_text.as_ref())
+        .map(|r| r.text.as_str())
+        .collect();
+    let tool_calls: Vec<ChatToolCall> = blocks
+        .iter()
+        .filter_map(|b| b.tool_use.as_ref())
+        .map(|t| ChatToolCall {
+            id: t.tool_use_id.clone(),
+            call_type: "function".to_string(),
+            function: ChatToolCallFunction {
+                name: t.name.clone(),
+                arguments: t.input.to_string(),
+            },
+        })
+        .collect();
+
+    ChatCompletionResponse {
+        id: String::new(),
+        object: "chat.completion".to_string(),
+        created: 0,
+        model: String::new(),
+        choices: vec![Choice {
+            index: 0,
+            message: ChoiceMessage {
+                role: "assistant".to_string(),
+                content: Some(text),
+                reasoning_content: (!reasoning.is_empty()).then_some(reasoning),
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            },
+            finish_reason: Some(map_stop_reason(resp.stop_reason.as_deref())),
+            logprobs: None,
+        }],
+        usage: resp.usage.as_ref().map(chat_usage),
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+/// Map a Converse `stopReason` to the `OpenAI` `finish_reason`.
+#[must_use]
+pub fn map_stop_reason(reason: Option<&str>) -> String {
+    match reason {
+        Some("tool_use") => "tool_calls",
+        Some("max_tokens") => "length",
+        Some("content_filtered" | "guardrail_intervened") => "content_filter",
+        _ => "stop",
+    }
+    .to_string()
+}
+
+/// Translates `ConverseStream` events into `OpenAI` chunks.
+///
+/// Converse numbers content blocks across text and tool use, while `OpenAI`
+/// numbers tool calls on their own, so tool blocks are re-indexed. Usage
+/// arrives in a `metadata` event after `messageStop`, so the finish reason is
+/// held back until then.
+#[derive(Debug, Default)]
+pub struct ConverseStreamState {
+    tool_indexes: HashMap<u64, u64>,
+    stop_reason: Option<String>,
+}
+
+impl ConverseStreamState {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Translate one event, if it carries anything we translate.
+    pub fn event_to_chunk(
+        &mut self,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> Option<ChatCompletionChunk> {
+        let block_index = payload["contentBlockIndex"].as_u64().unwrap_or_default();
+        match event_type {
+            "messageStart" => Some(chunk(
+                ChunkDelta {
+                    role: Some("assistant".to_string()),
+                    ..ChunkDelta::default()
+                },
+                None,
+                None,
+            )),
+            "contentBlockStart" => {
+                let tool_use = &payload["start"]["toolUse"];
+                let id = tool_use["toolUseId"].as_str()?;
+                let index = self.tool_indexes.len() as u64;
+                self.tool_indexes.insert(block_index, index);
+                Some(tool_chunk(ChunkToolCall {
+                    index,
+                    id: Some(id.to_string()),
+                    call_type: Some("function".to_string()),
+                    function: Some(ChunkToolCallFunction {
+                        name: tool_use["name"].as_str().map(str::to_string),
+                        arguments: Some(String::new()),
+                    }),
+                }))
+            }
+            "contentBlockDelta" => {
+                let delta = &payload["delta"];
+                if let Some(text) = delta["text"].as_str() {
+                    return Some(chunk(
+                        ChunkDelta {
+                            content: Some(text.to_string()),
+                            ..ChunkDelta::default()
+                        },
+                        None,
+                        None,
+                    ));
+                }
+                if let Some(text) = delta["reasoningContent"]["text"].as_str() {
+                    return Some(chunk(
+                        ChunkDelta {
+                            reasoning_content: Some(text.to_string()),
+                            ..ChunkDelta::default()
+                        },
+                        None,
+                        None,
+                    ));
+                }
+                let input = delta["toolUse"]["input"].as_str()?;
+                Some(tool_chunk(ChunkToolCall {
+                    index: *self.tool_indexes.get(&block_index)?,
+                    id: None,
+                    call_type: None,
+                    function: Some(ChunkToolCallFunction {
+                        name: None,
+                        arguments: Some(input.to_string()),
+                    }),
+                }))
+            }
+            "messageStop" => {
+                self.stop_reason = Some(map_stop_reason(payload["stopReason"].as_str()));
+                None
+            }
+            "metadata" => {
+                let usage: TokenUsage =
+                    serde_json::from_value(payload["usage"].clone()).unwrap_or_default();
+                Some(chunk(
+                    ChunkDelta::default(),
+                    self.stop_reason.take(),
+                    Some(chat_usage(&usage)),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// The held-back finish, if the stream ended without a `metadata` event.
+    pub fn finish(&mut self) -> Option<ChatCompletionChunk> {
+        self.stop_reason
+            .take()
+            .map(|reason| chunk(ChunkDelta::default(), Some(reason), None))
+    }
+}
+
+fn chunk(
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+    usage: Option<ChatUsage>,
+) -> ChatCompletionChunk {
+    ChatCompletionChunk {
+        id: String::new(),
+        object: "chat.completion.chunk".to_string(),
+        created: 0,
+        model: String::new(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+            logprobs: None,
+        }],
+        usage,
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+fn tool_chunk(call: ChunkToolCall) -> ChatCompletionChunk {
+    chunk(
+        ChunkDelta {
+            tool_calls: Some(vec![call]),
+            ..ChunkDelta::default()
+        },
+        None,
+        None,
+    )
+}
+
+fn chat_usage(usage: &TokenUsage) -> ChatUsage {
+    ChatUsage {
+        prompt_tokens: usage.input_tokens,
+        completion_tokens: usage.output_tokens,
+        total_tokens: usage.input_tokens + usage.output_tokens,
+        ..ChatUsage::default()
+    }
+}
+
+fn tool_choice(choice: &ChatToolChoice) -> Option<serde_json::Value> {
+    match choice {
+        ChatToolChoice::String(s) if s == "required" => Some(serde_json::json!({ "any": {} })),
+        ChatToolChoice::String(s) if s == "auto" => Some(serde_json::json!({ "auto": {} })),
+        // Converse has no "none"; leaving the choice unset lets the model decide
+        ChatToolChoice::String(_) => None,
+        ChatToolChoice::Specific(s) => {
+            Some(serde_json::json!({ "tool": { "name": s.function.name } }))
+        }
+    }
+}
+
+/// Text and image blocks for a message's content. Converse rejects blank text
+/// blocks, and only accepts images inline, so those are dropped.
+fn content_blocks(msg: &ChatMessage) -> Vec<ContentBlock> {
+    let text = |text: &str| {
+        (!text.trim().is_empty()).then(|| ContentBlock {
+            text: Some(text.to_string()),
+            ..ContentBlock::default()
+        })
+    };
+    match &msg.content {
+        Some(ChatContent::Text(t)) => text(t).into_iter().collect(),
+        Some(ChatContent::Parts(parts)) => parts
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::Text { text: t } => text(t),
+                ContentPart::ImageUrl { image_url } => {
+                    image_block(&image_url.url).map(|image| ContentBlock {
+                        image: Some(image),
+                        ..ContentBlock::default()
+                    })
+                }
+                ContentPart::File { file } => document_block(file).map(|document| ContentBlock {
+                    document: Some(document),
+                    ..ContentBlock::default()
+                }),
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// An image block from a `data:image/<format>;base64,...` URL.
+fn image_block(url: &str) -> Option<ImageBlock> {
+    let (header, data) = url.strip_prefix("data:image/")?.split_once(',')?;
+    let format = header.strip_suffix(";base64")?;
+    Some(ImageBlock {
+        format: if format == "jpg" { "jpeg" } else { format }.to_string(),
+        source: ImageSource {
+            bytes: data.to_string(),
+        },
+    })
+}
+
+/// A PDF document block from a file part's `data:application/pdf;base64,...`
+/// URL, named after the file as far as Bedrock's naming rules allow.
+fn document_block(file: &FileDetail) -> Option<DocumentBlock> {
+    let data = file
+        .file_data
+        .strip_prefix("data:application/pdf;base64,")?;
+    let stem = file
+        .filename
+        .as_deref()
+        .map_or("document", |name| name.strip_suffix(".pdf").unwrap_or(name));
+    let name = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-()[]".contains(c) {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(DocumentBlock {
+        format: "pdf".to_string(),
+        name: if name.is_empty() {
+            "document".to_string()
+        } else {
+            name
+        },
+        source: DocumentSource {
+            bytes: data.to_string(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::openai_types::{ChatFunction, ChatTool, ImageUrlDetail};
+    use std::borrow::Cow;
+
+    fn msg(role: &str, content: Option<&str>) -> ChatMessage<'static> {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.map(|c| ChatContent::Text(c.to_string().into())),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_tool_conversation_to_converse() {
+        let mut assistant = msg("assistant", Some("Checking."));
+        assistant.tool_calls = Some(vec![ChatToolCall {
+            id: "toolu_1".to_string(),
+            call_type: "function".to_string(),
+            function: ChatToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: "{\"city\":\"London\"}".to_string(),
+            },
+        }]);
+        let mut tool = msg("tool", Some("sunny"));
+        tool.tool_call_id = Some("toolu_1".to_string());
+        let mut image = msg("user", None);
+        image.content = Some(ChatContent::Parts(vec![
+            ContentPart::Text {
+                text: "And this?".into(),
+            },
+            ContentPart::ImageUrl {
+                image_url: ImageUrlDetail {
+                    url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                    detail: None,
+                },
+            },
+        ]));
+
+        let req = ChatCompletionRequest {
+            model: "anthropic.claude-3-5-sonnet-20240620-v1:0".to_string(),
+            messages: vec![
+                msg("system", Some("Be brief")),
+                msg("user", Some("Weather?")),
+                assistant,
+                tool,
+                image,
+            ],
+            max_tokens: Some(100),
+            temperature: None,
+            top_p: None,
+            stream: None,
+            stream_options: None,
+            tools: Some(vec![ChatTool {
+                tool_type: "function".to_string(),
+                function: ChatFunction {
+                    name: "get_weather".to_string(),
+                    description: None,
+                    parameters: Cow::Owned(serde_json::json!({"type": "object"})),
+                },
+            }]),
+            tool_choice: Some(ChatToolChoice::String("required".to_string())),
+            stop: None,
+            user: None,
+            reasoning_effort: None,
+            search_parameters: None,
+            response_format: None,
+            prompt_cache_key: None,
+            extra: serde_json::Map::new(),
+        };
+
+        let converse = serde_json::to_value(openai_to_converse(&req)).unwrap();
+
+        assert_eq!(converse["system"][0]["text"], "Be brief");
+        let messages = converse["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[1]["content"][1]["toolUse"]["input"]["city"],
+            "London"
+        );
+        // The tool result and the next user message share one user turn
+        let last = &messages[2]["content"];
+        assert_eq!(last[0]["toolResult"]["toolUseId"], "toolu_1");
+        assert_eq!(last[0]["toolResult"]["content"][0]["text"], "sunny");
+        assert_eq!(last[2]["image"]["format"], "png");
+        assert_eq!(converse["inferenceConfig"]["maxTokens"], 100);
+        assert_eq!(
+            converse["toolConfig"]["toolChoice"],
+            serde_json::json!({"any": {}})
+        );
+        assert_eq!(
+            converse["toolConfig"]["tools"][0]["toolSpec"]["inputSchema"]["json"]["type"],
+            "object"
+        );
+    }
+
+    #[test]
+    fn test_converse_response_to_openai() {
+        let resp: ConverseResponse = serde_json::from_value(serde_json::json!({
+            "output": {"message": {"role": "assistant", "content": [
+                {"reasoningContent": {"reasoningText": {"text": "Hmm.", "signature": "x"}}},
+                {"text": "Let me check."},
+                {"toolUse": {"toolUseId": "tooluse_1", "name": "search", "input": {"q": "rust"}}}
+            ]}},
+            "stopReason": "tool_use",
+            "usage": {"inputTokens": 12, "outputTokens": 4, "totalTokens": 16},
+            "metrics": {"latencyMs": 300}
+        }))
+        .unwrap();
+
+        let openai = converse_to_openai(&resp);
+        let choice = &openai.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(choice.message.content.as_deref(), Some("Let me check."));
+        assert_eq!(choice.message.reasoning_content.as_deref(), Some("Hmm."));
+        let call = &choice.message.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.id, "tooluse_1");
+        assert_eq!(call.function.arguments, "{\"q\":\"rust\"}");
+        assert_eq!(openai.usage.unwrap().prompt_tokens, 12);
+    }
+
+    #[test]
+    fn test_stream_events_to_chunks() {
+        let events = [
+            ("messageStart", serde_json::json!({"role": "assistant"})),
+            (
+                "contentBlockDelta",
+                serde_json::json!({"contentBlockIndex": 0, "delta": {"text": "Hi"}}),
+            ),
+            (
+                "contentBlockStop",
+                serde_json::json!({"contentBlockIndex": 0}),
+            ),
+            (
+                "contentBlockStart",
+                serde_json::json!({"contentBlockIndex": 1, "start": {"toolUse": {"toolUseId": "t1", "name": "search"}}}),
+            ),
+            (
+                "contentBlockDelta",
+                serde_json::json!({"contentBlockIndex": 1, "delta": {"toolUse": {"input": "{\"q\":"}}}),
+            ),
+            ("messageStop", serde_json::json!({"stopReason": "tool_use"})),
+            (
+                "metadata",
+                serde_json::json!({"usage": {"inputTokens": 7, "outputTokens": 3}}),
+            ),
+        ];
+
+        let mut state = ConverseStreamState::new();
+        let chunks: Vec<_> = events
+            .iter()
+            .filter_map(|(event, payload)| state.event_to_chunk(event, payload))
+            .collect();
+
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[1].choices[0].delta.content.as_deref(), Some("Hi"));
+        let start = &chunks[2].choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert_eq!((start.index, start.id.as_deref()), (0, Some("t1")));
+        let args = &chunks[3].choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(args.index, 0);
+        let last = &chunks[4];
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(last.usage.as_ref().unwrap().completion_tokens, 3);
+        assert!(state.finish().is_none());
+    }
+}
@@ -0,0 +1,123 @@
+//! Prompt caching hints.
+//!
+//! Claude Code marks cache breakpoints with `cache_control` on tools, system
+//! blocks and message blocks. Providers that cache automatically (`OpenAI`,
+//! `DeepSeek`, Fireworks) need no markers, but `OpenAI` routes requests to a
+//! cache by `prompt_cache_key`; [`prompt_cache_key`] derives one from the
+//! prompt up to the first breakpoint, so requests that share that prefix —
+//! every turn of a session, and sessions with the same system prompt — land
+//! on the same cache.
+
+use std::fmt::Write;
+
+use ring::digest;
+use serde_json::{json, Value};
+
+use super::anthropic_types::{ContentBlock, MessagesRequest, SystemBlock, SystemContent};
+
+/// A `prompt_cache_key` for the request, if it has any cache breakpoint.
+#[must_use]
+pub fn prompt_cache_key(req: &MessagesRequest) -> Option<String> {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    for (segment, breakpoint) in prompt_segments(req) {
+        ctx.update(segment.to_string().as_bytes());
+        if breakpoint {
+            let key = ctx.finish().as_ref()[..16]
+                .iter()
+                .fold(String::new(), |mut key, b| {
+                    let _ = write!(key, "{b:02x}");
+                    key
+                });
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// The prompt in cache order (tools, system, messages), each piece paired
+/// with whether it carries a cache breakpoint.
+fn prompt_segments(req: &MessagesRequest) -> Vec<(Value, bool)> {
+    let mut segments = Vec::new();
+    for tool in req.tools.iter().flatten() {
+        let marked = tool.extra.contains_key("cache_control");
+        segments.push((serde_json::to_value(tool).unwrap_or_default(), marked));
+    }
+    match &req.system {
+        Some(SystemContent::Text(text)) => segments.push((json!(text), false)),
+        Some(SystemContent::Blocks(blocks)) => {
+            for block in blocks {
+                let SystemBlock::Text { cache_control, .. } = block;
+                let marked = cache_control.is_some();
+                segments.push((serde_json::to_value(block).unwrap_or_default(), marked));
+            }
+        }
+        None => {}
+    }
+    for message in &req.messages {
+        for block in message.content.blocks() {
+            let marked = matches!(
+                block,
+                ContentBlock::Text {
+                    cache_control: Some(_),
+                    ..
+                } | ContentBlock::ToolResult {
+                    cache_control: Some(_),
+                    ..
+                }
+            );
+            segments.push((json!([message.role, block]), marked));
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: Value) -> MessagesRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn test_key_from_first_breakpoint() {
+        let system = json!([
+            {"type": "text", "text": "You are Claude Code.", "cache_control": {"type": "ephemeral"}}
+        ]);
+        let turn1 = request(json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "system": system,
+            "messages": [{"role": "user", "content": [
+                {"type": "text", "text": "Hi", "cache_control": {"type": "ephemeral"}}
+            ]}]
+        }));
+        let turn2 = request(json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "system": system,
+            "messages": [
+                {"role": "user", "content": "Hi"},
+                {"role": "assistant", "content": "Hello!"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "Fix the bug", "cache_control": {"type": "ephemeral"}}
+                ]}
+            ]
+        }));
+
+        let key = prompt_cache_key(&turn1).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(prompt_cache_key(&turn2).as_ref(), Some(&key));
+    }
+
+    #[test]
+    fn test_no_key_without_breakpoints() {
+        let req = request(json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "system": "You are Claude Code.",
+            "messages": [{"role": "user", "content": "Hi"}]
+        }));
+        assert_eq!(prompt_cache_key(&req), None);
+    }
+}
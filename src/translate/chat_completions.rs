@@ -0,0 +1,766 @@
+//! `OpenAI` Chat Completions requests translated to Anthropic, and back.
+//!
+//! Backs the inbound `/v1/chat/completions` endpoint, the reverse of the
+//! proxy's usual direction: tools that speak `OpenAI` send a
+//! [`ChatCompletionRequest`], which becomes a [`MessagesRequest`] routed like
+//! any other (to an Anthropic backend, or translated back out to another
+//! provider), and the Anthropic response (or stream events) is translated into
+//! `chat.completion` objects. The wire types are the ones in
+//! [`super::openai_types`]. Only one choice per request is supported.
+
+use std::collections::HashMap;
+
+use super::anthropic_types::{
+    ContentBlock, Delta, DocumentSource, ImageSource, Message, MessageContent, MessagesRequest,
+    MessagesResponse, Metadata, ResponseContentBlock, Role, StreamEvent, SystemContent, Tool,
+    ToolChoice, ToolChoiceAuto, ToolChoiceSpecific, ToolResultContent, Usage,
+};
+use super::openai_types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatContent, ChatMessage,
+    ChatToolCall, ChatToolCallFunction, ChatToolChoice, ChatUsage, Choice, ChoiceMessage,
+    ChunkChoice, ChunkDelta, ChunkToolCall, ChunkToolCallFunction, ContentPart,
+    PromptTokensDetails, ResponseFormat,
+};
+use crate::error::{ProxyError, Result};
+
+/// `max_tokens` when the request sets neither it nor `max_completion_tokens`.
+const DEFAULT_MAX_TOKENS: u64 = 8192;
+
+/// Thinking budgets for `reasoning_effort`, inside the ranges
+/// [`super::openai_responses::effort_for_budget`] maps back to the same effort.
+const EFFORT_BUDGETS: [(&str, u64); 3] = [("low", 2048), ("medium", 8192), ("high", 24_576)];
+
+/// The smallest thinking budget Anthropic accepts.
+const MIN_THINKING_BUDGET: u64 = 1024;
+
+// ---------------------------------------------------------------------------
+// Chat Completions → Anthropic
+// ---------------------------------------------------------------------------
+
+/// Translate a Chat Completions request into an Anthropic Messages request.
+///
+/// System and developer messages become the system prompt, tool messages
+/// become `tool_result` blocks, and consecutive messages of the same role are
+/// merged, as Anthropic needs turns to alternate.
+///
+/// # Errors
+/// Returns `ProxyError::InvalidRequest` for more than one choice, or a
+/// message role Anthropic has no equivalent of.
+pub fn chat_to_anthropic(req: &ChatCompletionRequest) -> Result<MessagesRequest> {
+    if req
+        .extra
+        .get("n")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(1)
+        != 1
+    {
+        return Err(ProxyError::invalid_request(
+            "Only n = 1 is supported on /v1/chat/completions",
+        ));
+    }
+
+    let mut system = Vec::new();
+    let mut messages: Vec<Message> = Vec::new();
+    for message in &req.messages {
+        let (role, blocks) = match message.role.as_str() {
+            "system" | "developer" => {
+                system.push(content_text(message.content.as_ref()));
+                continue;
+            }
+            "user" => (Role::User, content_blocks(message.content.as_ref())),
+            "assistant" => (Role::Assistant, assistant_blocks(message)),
+            "tool" => (Role::User, vec![tool_result(message)]),
+            other => {
+                return Err(ProxyError::invalid_request(format!(
+                    "Unsupported message role: {other}"
+                )))
+            }
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+        match messages.last_mut() {
+            Some(Message {
+                role: last_role,
+                content: MessageContent::Blocks(last),
+            }) if *last_role == role => last.extend(blocks),
+            _ => messages.push(Message {
+                role,
+                content: MessageContent::Blocks(blocks),
+            }),
+        }
+    }
+    system.retain(|text| !text.is_empty());
+
+    let tools: Vec<Tool> = req
+        .tools
+        .iter()
+        .flatten()
+        .map(|tool| Tool {
+            name: tool.function.name.clone(),
+            description: tool.function.description.as_deref().map(str::to_string),
+            input_schema: match tool.function.parameters.as_ref() {
+                serde_json::Value::Null => serde_json::json!({"type": "object", "properties": {}}),
+                schema => schema.clone(),
+            },
+            tool_type: None,
+            extra: HashMap::new(),
+        })
+        .collect();
+
+    let max_tokens = req
+        .extra
+        .get("max_completion_tokens")
+        .and_then(serde_json::Value::as_u64)
+        .or(req.max_tokens)
+        .unwrap_or(DEFAULT_MAX_TOKENS);
+
+    let mut extra = HashMap::new();
+    if let Some(output_format) = req.response_format.as_ref().and_then(output_format) {
+        extra.insert("output_format".to_string(), output_format);
+    }
+
+    Ok(MessagesRequest {
+        model: req.model.clone(),
+        max_tokens,
+        messages,
+        system: (!system.is_empty()).then(|| SystemContent::Text(system.join("\n\n"))),
+        stream: Some(req.stream.unwrap_or(false)),
+        temperature: req.temperature,
+        top_p: req.top_p,
+        top_k: None,
+        tools: (!tools.is_empty()).then_some(tools),
+        tool_choice: req.tool_choice.as_ref().and_then(translate_tool_choice),
+        metadata: req.user.as_ref().map(|user| Metadata {
+            user_id: Some(user.clone()),
+            extra: HashMap::new(),
+        }),
+        stop_sequences: req.stop.clone().filter(|s| !s.is_empty()),
+        thinking: req
+            .reasoning_effort
+            .as_deref()
+            .and_then(|effort| thinking(effort, max_tokens)),
+        betas: None,
+        context_management: None,
+        reasoning_effort: None,
+        extra,
+    })
+}
+
+/// Whether the client asked for a final chunk with the token usage.
+#[must_use]
+pub fn include_usage(req: &ChatCompletionRequest) -> bool {
+    req.stream_options.as_ref().is_some_and(|o| o.include_usage)
+}
+
+/// The text of a system or tool message.
+fn content_text(content: Option<&ChatContent>) -> String {
+    match content {
+        Some(ChatContent::Text(text)) => text.to_string(),
+        Some(ChatContent::Parts(parts)) => parts
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Text { text } => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => String::new(),
+    }
+}
+
+fn content_blocks(content: Option<&ChatContent>) -> Vec<ContentBlock> {
+    match content {
+        Some(ChatContent::Text(text)) if text.is_empty() => Vec::new(),
+        Some(ChatContent::Text(text)) => vec![text_block(text)],
+        Some(ChatContent::Parts(parts)) => parts.iter().filter_map(part_block).collect(),
+        None => Vec::new(),
+    }
+}
+
+fn part_block(part: &ContentPart) -> Option<ContentBlock> {
+    match part {
+        ContentPart::Text { text } if text.is_empty() => None,
+        ContentPart::Text { text } => Some(text_block(text)),
+        ContentPart::ImageUrl { image_url } => {
+            let source = match inline_data(&image_url.url) {
+                Some((media_type, data)) => ImageSource {
+                    source_type: "base64".to_string(),
+                    media_type: media_type.to_string(),
+                    data: data.to_string(),
+                    url: None,
+                },
+                None => ImageSource {
+                    source_type: "url".to_string(),
+                    media_type: String::new(),
+                    data: String::new(),
+                    url: Some(image_url.url.clone()),
+                },
+            };
+            Some(ContentBlock::Image { source })
+        }
+        ContentPart::File { file } => {
+            let (media_type, data) = inline_data(&file.file_data)?;
+            Some(ContentBlock::Document {
+                source: DocumentSource {
+                    source_type: "base64".to_string(),
+                    media_type: media_type.to_string(),
+                    data: data.to_string(),
+                    content: None,
+                    url: None,
+                    file_id: None,
+                },
+                title: file.filename.clone(),
+                context: None,
+                citations: None,
+                cache_control: None,
+            })
+        }
+    }
+}
+
+fn assistant_blocks(message: &ChatMessage) -> Vec<ContentBlock> {
+    let mut blocks = content_blocks(message.content.as_ref());
+    blocks.extend(
+        message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| ContentBlock::ToolUse {
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                input: serde_json::from_str(&call.function.arguments)
+                    .unwrap_or_else(|_| serde_json::json!({})),
+            }),
+    );
+    blocks
+}
+
+fn tool_result(message: &ChatMessage) -> ContentBlock {
+    ContentBlock::ToolResult {
+        tool_use_id: message.tool_call_id.clone().unwrap_or_default(),
+        content: Some(ToolResultContent::Text(content_text(
+            message.content.as_ref(),
+        ))),
+        is_error: None,
+        cache_control: None,
+    }
+}
+
+fn text_block(text: &str) -> ContentBlock {
+    ContentBlock::Text {
+        text: text.to_string(),
+        cache_control: None,
+    }
+}
+
+/// The media type and base64 data of a `data:` URL.
+fn inline_data(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("data:")?.split_once(";base64,")
+}
+
+fn translate_tool_choice(choice: &ChatToolChoice) -> Option<ToolChoice> {
+    let auto = |t: &str| {
+        Some(ToolChoice::Auto(ToolChoiceAuto {
+            choice_type: t.to_string(),
+        }))
+    };
+    match choice {
+        ChatToolChoice::String(s) => match s.as_str() {
+            "required" => auto("any"),
+            "none" => auto("none"),
+            "auto" => auto("auto"),
+            _ => None,
+        },
+        ChatToolChoice::Specific(specific) => Some(ToolChoice::Specific(ToolChoiceSpecific {
+            choice_type: "tool".to_string(),
+            name: specific.function.name.clone(),
+        })),
+    }
+}
+
+/// Anthropic `output_format` for a `response_format`; grammars have none.
+fn output_format(format: &ResponseFormat) -> Option<serde_json::Value> {
+    match format {
+        ResponseFormat::JsonSchema { json_schema } => Some(serde_json::json!({
+            "type": "json_schema",
+            "schema": json_schema.schema,
+        })),
+        ResponseFormat::JsonObject {
+            schema: Some(schema),
+        } => Some(serde_json::json!({"type": "json_schema", "schema": schema})),
+        ResponseFormat::JsonObject { schema: None } => Some(serde_json::json!({"type": "json"})),
+        ResponseFormat::Text | ResponseFormat::Grammar { .. } => None,
+    }
+}
+
+/// Extended thinking for a `reasoning_effort`, with a budget that leaves room
+/// for an answer within `max_tokens`.
+fn thinking(effort: &str, max_tokens: u64) -> Option<serde_json::Value> {
+    let (_, budget) = EFFORT_BUDGETS.iter().find(|(e, _)| *e == effort)?;
+    let budget = (*budget).min(max_tokens / 2);
+    (budget >= MIN_THINKING_BUDGET)
+        .then(|| serde_json::json!({"type": "enabled", "budget_tokens": budget}))
+}
+
+// ---------------------------------------------------------------------------
+// Anthropic → Chat Completions
+// ---------------------------------------------------------------------------
+
+/// Translate an Anthropic response into a `chat.completion` response.
+/// Thinking is returned as `reasoning_content`.
+#[must_use]
+pub fn anthropic_to_chat(resp: &MessagesResponse) -> ChatCompletionResponse {
+    let mut text = String::new();
+    let mut reasoning = String::new();
+    let mut tool_calls = Vec::new();
+    for block in &resp.content {
+        match block {
+            ResponseContentBlock::Text { text: t } => text.push_str(t),
+            ResponseContentBlock::Thinking { thinking, .. } => reasoning.push_str(thinking),
+            ResponseContentBlock::ToolUse { id, name, input } => tool_calls.push(ChatToolCall {
+                id: id.clone(),
+                call_type: "function".to_string(),
+                function: ChatToolCallFunction {
+                    name: name.clone(),
+                    arguments: input.to_string(),
+                },
+            }),
+        }
+    }
+
+    ChatCompletionResponse {
+        id: chat_id(&resp.id),
+        object: "chat.completion".to_string(),
+        created: now(),
+        model: resp.model.clone(),
+        choices: vec![Choice {
+            index: 0,
+            message: ChoiceMessage {
+                role: "assistant".to_string(),
+                content: (!text.is_empty() || tool_calls.is_empty()).then_some(text),
+                reasoning_content: (!reasoning.is_empty()).then_some(reasoning),
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            },
+            finish_reason: map_stop_reason(resp.stop_reason.as_deref()),
+            logprobs: None,
+        }],
+        usage: Some(usage(&resp.usage, resp.usage.output_tokens)),
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+/// Map an Anthropic `stop_reason` to a Chat Completions `finish_reason`.
+#[must_use]
+pub fn map_stop_reason(stop_reason: Option<&str>) -> Option<String> {
+    stop_reason.map(|r| {
+        match r {
+            "max_tokens" => "length",
+            "tool_use" => "tool_calls",
+            "refusal" => "content_filter",
+            _ => "stop",
+        }
+        .to_string()
+    })
+}
+
+fn usage(input: &Usage, output_tokens: u64) -> ChatUsage {
+    let cached = input.cache_read_input_tokens.unwrap_or(0);
+    let prompt_tokens =
+        input.input_tokens + cached + input.cache_creation_input_tokens.unwrap_or(0);
+    ChatUsage {
+        prompt_tokens,
+        completion_tokens: output_tokens,
+        total_tokens: prompt_tokens + output_tokens,
+        prompt_tokens_details: (cached > 0).then_some(PromptTokensDetails {
+            cached_tokens: cached,
+        }),
+        prompt_cache_hit_tokens: None,
+        cost: None,
+    }
+}
+
+fn chat_id(message_id: &str) -> String {
+    format!(
+        "chatcmpl-{}",
+        message_id.strip_prefix("msg_").unwrap_or(message_id)
+    )
+}
+
+fn now() -> u64 {
+    u64::try_from(chrono::Utc::now().timestamp()).unwrap_or_default()
+}
+
+/// Converts Anthropic stream events into `chat.completion.chunk`s.
+///
+/// Text and thinking deltas are passed on as they arrive, and tool calls are
+/// streamed as `OpenAI` does: the id and name first, then argument fragments,
+/// numbered by call rather than by content block.
+#[derive(Debug, Default)]
+pub struct ChatStreamTranslator {
+    id: String,
+    model: String,
+    created: u64,
+    include_usage: bool,
+    input: Usage,
+    output_tokens: u64,
+    /// Content block index → tool call index.
+    tool_calls: HashMap<usize, u64>,
+}
+
+impl ChatStreamTranslator {
+    /// A translator for a stream of `model`, ending with a usage chunk if
+    /// `include_usage`.
+    #[must_use]
+    pub fn new(model: &str, include_usage: bool) -> Self {
+        Self {
+            id: chat_id(&uuid::Uuid::new_v4().simple().to_string()),
+            model: model.to_string(),
+            created: now(),
+            include_usage,
+            ..Self::default()
+        }
+    }
+
+    /// Translate one event. Returns `None` for events with nothing to emit.
+    pub fn process_event(&mut self, event: &StreamEvent) -> Option<ChatCompletionChunk> {
+        match event {
+            StreamEvent::MessageStart { message } => {
+                self.id = chat_id(&message.id);
+                self.input = message.usage.clone();
+                Some(self.chunk(
+                    ChunkDelta {
+                        role: Some("assistant".to_string()),
+                        content: Some(String::new()),
+                        ..ChunkDelta::default()
+                    },
+                    None,
+                ))
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => match content_block {
+                ResponseContentBlock::Text { text } if !text.is_empty() => {
+                    Some(self.content(text.clone()))
+                }
+                ResponseContentBlock::Thinking { thinking, .. } if !thinking.is_empty() => {
+                    Some(self.reasoning(thinking.clone()))
+                }
+                ResponseContentBlock::ToolUse { id, name, .. } => {
+                    let call = self.tool_calls.len() as u64;
+                    self.tool_calls.insert(*index, call);
+                    Some(self.tool_call(ChunkToolCall {
+                        index: call,
+                        id: Some(id.clone()),
+                        call_type: Some("function".to_string()),
+                        function: Some(ChunkToolCallFunction {
+                            name: Some(name.clone()),
+                            arguments: Some(String::new()),
+                        }),
+                    }))
+                }
+                ResponseContentBlock::Text { .. } | ResponseContentBlock::Thinking { .. } => None,
+            },
+            StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                Delta::TextDelta { text } => Some(self.content(text.clone())),
+                Delta::ThinkingDelta { thinking } => Some(self.reasoning(thinking.clone())),
+                Delta::InputJsonDelta { partial_json } => {
+                    let call = *self.tool_calls.get(index)?;
+                    Some(self.tool_call(ChunkToolCall {
+                        index: call,
+                        id: None,
+                        call_type: None,
+                        function: Some(ChunkToolCallFunction {
+                            name: None,
+                            arguments: Some(partial_json.clone()),
+                        }),
+                    }))
+                }
+            },
+            StreamEvent::MessageDelta { delta, usage, .. } => {
+                self.output_tokens = usage.output_tokens;
+                // Interim usage updates carry no stop reason
+                let finish_reason = map_stop_reason(delta.stop_reason.as_deref())?;
+                Some(self.chunk(ChunkDelta::default(), Some(finish_reason)))
+            }
+            StreamEvent::MessageStop if self.include_usage => Some(ChatCompletionChunk {
+                choices: Vec::new(),
+                usage: Some(usage(&self.input, self.output_tokens)),
+                ..self.chunk(ChunkDelta::default(), None)
+            }),
+            _ => None,
+        }
+    }
+
+    fn content(&self, text: String) -> ChatCompletionChunk {
+        let delta = ChunkDelta {
+            content: Some(text),
+            ..ChunkDelta::default()
+        };
+        self.chunk(delta, None)
+    }
+
+    fn reasoning(&self, text: String) -> ChatCompletionChunk {
+        let delta = ChunkDelta {
+            reasoning_content: Some(text),
+            ..ChunkDelta::default()
+        };
+        self.chunk(delta, None)
+    }
+
+    fn tool_call(&self, call: ChunkToolCall) -> ChatCompletionChunk {
+        let delta = ChunkDelta {
+            tool_calls: Some(vec![call]),
+            ..ChunkDelta::default()
+        };
+        self.chunk(delta, None)
+    }
+
+    fn chunk(&self, delta: ChunkDelta, finish_reason: Option<String>) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta,
+                finish_reason,
+                logprobs: None,
+            }],
+            usage: None,
+            system_fingerprint: None,
+            citations: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::anthropic_types::{DeltaUsage, MessageDeltaBody};
+
+    #[test]
+    fn test_chat_request_to_anthropic() {
+        let req: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What's the weather here?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0K"}}
+                ]},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function",
+                     "function": {"name": "weather", "arguments": "{\"city\":\"Oslo\"}"}},
+                    {"id": "call_2", "type": "function",
+                     "function": {"name": "weather", "arguments": "{\"city\":\"Bergen\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "Rain"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "Sun"},
+                {"role": "user", "content": "Thanks"}
+            ],
+            "tools": [{"type": "function", "function": {
+                "name": "weather",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }}],
+            "tool_choice": "required",
+            "max_completion_tokens": 4096,
+            "reasoning_effort": "high",
+            "stop": "END",
+            "response_format": {"type": "json_object"},
+            "user": "u-1",
+            "stream": true
+        }))
+        .unwrap();
+
+        let out = chat_to_anthropic(&req).unwrap();
+
+        assert_eq!(out.max_tokens, 4096);
+        assert_eq!(out.stream, Some(true));
+        assert!(matches!(out.system, Some(SystemContent::Text(ref t)) if t == "Be brief."));
+        assert_eq!(out.stop_sequences, Some(vec!["END".to_string()]));
+        assert!(matches!(out.tool_choice, Some(ToolChoice::Auto(ref c)) if c.choice_type == "any"));
+        assert_eq!(out.tools.as_ref().unwrap()[0].name, "weather");
+        assert_eq!(out.thinking.as_ref().unwrap()["budget_tokens"], 2048);
+        assert_eq!(out.extra["output_format"]["type"], "json");
+        assert_eq!(out.metadata.unwrap().user_id.as_deref(), Some("u-1"));
+
+        // The tool results and the next user turn share one user message
+        let roles: Vec<&Role> = out.messages.iter().map(|m| &m.role).collect();
+        assert_eq!(roles, [&Role::User, &Role::Assistant, &Role::User]);
+        let MessageContent::Blocks(ref first) = out.messages[0].content else {
+            panic!("expected blocks");
+        };
+        assert!(
+            matches!(&first[1], ContentBlock::Image { source } if source.media_type == "image/png")
+        );
+        let MessageContent::Blocks(ref calls) = out.messages[1].content else {
+            panic!("expected blocks");
+        };
+        assert!(
+            matches!(&calls[1], ContentBlock::ToolUse { input, .. } if input["city"] == "Bergen")
+        );
+        let MessageContent::Blocks(ref results) = out.messages[2].content else {
+            panic!("expected blocks");
+        };
+        assert_eq!(results.len(), 3);
+        assert!(
+            matches!(&results[1], ContentBlock::ToolResult { tool_use_id, .. } if tool_use_id == "call_2")
+        );
+
+        let many: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "n": 2
+        }))
+        .unwrap();
+        assert!(chat_to_anthropic(&many).is_err());
+    }
+
+    #[test]
+    fn test_anthropic_response_to_chat() {
+        let resp = MessagesResponse {
+            id: "msg_abc".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![
+                ResponseContentBlock::Thinking {
+                    thinking: "Look it up.".to_string(),
+                    signature: String::new(),
+                },
+                ResponseContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "weather".to_string(),
+                    input: serde_json::json!({"city": "Oslo"}),
+                },
+            ],
+            model: "claude-sonnet-4-5".to_string(),
+            stop_reason: Some("tool_use".to_string()),
+            stop_sequence: None,
+            usage: Usage {
+                input_tokens: 10,
+                output_tokens: 5,
+                cache_creation_input_tokens: None,
+                cache_read_input_tokens: Some(90),
+            },
+            extra: None,
+        };
+
+        let out = anthropic_to_chat(&resp);
+
+        assert_eq!(out.id, "chatcmpl-abc");
+        let choice = &out.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(choice.message.content, None);
+        assert_eq!(
+            choice.message.reasoning_content.as_deref(),
+            Some("Look it up.")
+        );
+        let call = &choice.message.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.function.arguments, r#"{"city":"Oslo"}"#);
+        let usage = out.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 100);
+        assert_eq!(usage.total_tokens, 105);
+        assert_eq!(usage.prompt_tokens_details.unwrap().cached_tokens, 90);
+    }
+
+    #[test]
+    fn test_chat_stream() {
+        let mut translator = ChatStreamTranslator::new("claude-sonnet-4-5", true);
+        let message = MessagesResponse {
+            id: "msg_abc".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: Vec::new(),
+            model: "claude-sonnet-4-5".to_string(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Usage {
+                input_tokens: 12,
+                ..Usage::default()
+            },
+            extra: None,
+        };
+        let events = [
+            StreamEvent::MessageStart { message },
+            StreamEvent::ContentBlockStart {
+                index: 0,
+                content_block: ResponseContentBlock::Text {
+                    text: String::new(),
+                },
+            },
+            StreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: Delta::TextDelta {
+                    text: "Checking.".to_string(),
+                },
+            },
+            StreamEvent::ContentBlockStop { index: 0 },
+            StreamEvent::ContentBlockStart {
+                index: 1,
+                content_block: ResponseContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "weather".to_string(),
+                    input: serde_json::json!({}),
+                },
+            },
+            StreamEvent::ContentBlockDelta {
+                index: 1,
+                delta: Delta::InputJsonDelta {
+                    partial_json: "{\"city\":".to_string(),
+                },
+            },
+            StreamEvent::ContentBlockDelta {
+                index: 1,
+                delta: Delta::InputJsonDelta {
+                    partial_json: "\"Oslo\"}".to_string(),
+                },
+            },
+            StreamEvent::ContentBlockStop { index: 1 },
+            StreamEvent::MessageDelta {
+                delta: MessageDeltaBody {
+                    stop_reason: Some("tool_use".to_string()),
+                    stop_sequence: None,
+                },
+                usage: DeltaUsage {
+                    output_tokens: 7,
+                    cache_read_input_tokens: None,
+                },
+                extra: None,
+            },
+            StreamEvent::MessageStop,
+        ];
+
+        let chunks: Vec<ChatCompletionChunk> = events
+            .iter()
+            .filter_map(|e| translator.process_event(e))
+            .collect();
+
+        assert_eq!(chunks.len(), 7);
+        assert!(chunks.iter().all(|c| c.id == "chatcmpl-abc"));
+        assert_eq!(
+            chunks[0].choices[0].delta.role.as_deref(),
+            Some("assistant")
+        );
+        assert_eq!(
+            chunks[1].choices[0].delta.content.as_deref(),
+            Some("Checking.")
+        );
+        let arguments: String = chunks[2..5]
+            .iter()
+            .flat_map(|c| c.choices[0].delta.tool_calls.iter().flatten())
+            .filter(|call| call.index == 0)
+            .filter_map(|call| call.function.as_ref()?.arguments.as_deref())
+            .collect();
+        assert_eq!(arguments, r#"{"city":"Oslo"}"#);
+        assert_eq!(
+            chunks[5].choices[0].finish_reason.as_deref(),
+            Some("tool_calls")
+        );
+        assert!(chunks[6].choices.is_empty());
+        assert_eq!(chunks[6].usage.as_ref().unwrap().total_tokens, 19);
+    }
+}
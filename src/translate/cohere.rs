@@ -0,0 +1,618 @@
+//! Adapter for the [Cohere Chat API](https://docs.cohere.com/reference/chat) (v1).
+//!
+//! Cohere splits the conversation into a `preamble` (system prompt), a
+//! `chat_history`, and the latest `message`, identifies tool calls by name and
+//! parameters rather than by id, and streams newline-delimited JSON events
+//! instead of SSE. Rather than duplicate the Anthropic translation, this module
+//! converts the already-translated `OpenAI` request into Cohere's shape, and
+//! Cohere responses and stream events back into `OpenAI` types, so the existing
+//! response translator and [`StreamTranslator`](super::streaming::StreamTranslator)
+//! handle the rest.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::openai_types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatContent, ChatMessage,
+    ChatToolCall, ChatToolCallFunction, ChatUsage, Choice, ChoiceMessage, ChunkChoice, ChunkDelta,
+    ChunkToolCall, ChunkToolCallFunction, ContentPart,
+};
+
+// ---------------------------------------------------------------------------
+// Request types (what we send TO Cohere)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereChatRequest {
+    pub model: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preamble: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chat_history: Vec<CohereMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<CohereTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_results: Option<Vec<CohereToolResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereMessage {
+    pub role: String, // "USER", "CHATBOT", "SYSTEM", "TOOL"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<CohereToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_results: Option<Vec<CohereToolResult>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereTool {
+    pub name: String,
+    pub description: String,
+    pub parameter_definitions: BTreeMap<String, CohereParameter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereParameter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(rename = "type")]
+    pub param_type: String,
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereToolCall {
+    pub name: String,
+    #[serde(default)]
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereToolResult {
+    pub call: CohereToolCall,
+    pub outputs: Vec<serde_json::Value>,
+}
+
+// ---------------------------------------------------------------------------
+// Response types (what we receive FROM Cohere)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereChatResponse {
+    #[serde(default)]
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<CohereToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<CohereMeta>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CohereMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub billed_units: Option<CohereBilledUnits>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CohereBilledUnits {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+/// One line of a Cohere NDJSON stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event_type")]
+pub enum CohereStreamEvent {
+    #[serde(rename = "stream-start")]
+    StreamStart {
+        #[serde(default)]
+        generation_id: Option<String>,
+    },
+    #[serde(rename = "text-generation")]
+    TextGeneration { text: String },
+    #[serde(rename = "tool-calls-generation")]
+    ToolCallsGeneration { tool_calls: Vec<CohereToolCall> },
+    #[serde(rename = "stream-end")]
+    StreamEnd {
+        #[serde(default)]
+        finish_reason: Option<String>,
+        #[serde(default)]
+        response: Option<CohereChatResponse>,
+    },
+    /// Citations, search results, tool-call chunks, etc. — nothing we translate.
+    #[serde(other)]
+    Other,
+}
+
+/// Cohere's error body: just a message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohereError {
+    pub message: String,
+}
+
+// ---------------------------------------------------------------------------
+// Translation
+// ---------------------------------------------------------------------------
+
+/// Convert a translated `OpenAI` request into a Cohere chat request.
+///
+/// System messages become the `preamble`; the trailing user message (or trailing
+/// tool results) becomes `message` / `tool_results`; everything before it goes
+/// into `chat_history`. Tool results are matched back to their calls by id,
+/// since Cohere identifies calls by name and parameters.
+#[must_use]
+pub fn openai_to_cohere(req: &ChatCompletionRequest) -> CohereChatRequest {
+    let mut preamble_parts: Vec<String> = Vec::new();
+    let mut history: Vec<CohereMessage> = Vec::new();
+    let mut calls_by_id: HashMap<&str, CohereToolCall> = HashMap::new();
+
+    for msg in &req.messages {
+        match msg.role.as_str() {
+            "system" => preamble_parts.push(content_text(msg)),
+            "assistant" => {
+                let tool_calls = msg.tool_calls.as_ref().map(|calls| {
+                    calls
+                        .iter()
+                        .map(|tc| {
+                            let call = CohereToolCall {
+                                name: tc.function.name.clone(),
+                                parameters: serde_json::from_str(&tc.function.arguments)
+                                    .unwrap_or_else(|_| serde_json::json!({})),
+                            };
+                            calls_by_id.insert(tc.id.as_str(), call.clone());
+                            call
+                        })
+                        .collect()
+                });
+                history.push(CohereMessage {
+                    role: "CHATBOT".to_string(),
+                    message: Some(content_text(msg)),
+                    tool_calls,
+                    tool_results: None,
+                });
+            }
+            "tool" => {
+                let call = msg
+                    .tool_call_id
+                    .as_deref()
+                    .and_then(|id| calls_by_id.get(id).cloned())
+                    .unwrap_or_else(|| CohereToolCall {
+                        name: msg.name.clone().unwrap_or_default(),
+                        parameters: serde_json::json!({}),
+                    });
+                let result = CohereToolResult {
+                    call,
+                    outputs: vec![tool_output(&content_text(msg))],
+                };
+
+                // Consecutive tool messages share one TOOL turn
+                match history.last_mut() {
+                    Some(CohereMessage {
+                        role,
+                        tool_results: Some(results),
+                        ..
+                    }) if role == "TOOL" => results.push(result),
+                    _ => history.push(CohereMessage {
+                        role: "TOOL".to_string(),
+                        message: None,
+                        tool_calls: None,
+                        tool_results: Some(vec![result]),
+                    }),
+                }
+            }
+            _ => history.push(CohereMessage {
+                role: "USER".to_string(),
+                message: Some(content_text(msg)),
+                tool_calls: None,
+                tool_results: None,
+            }),
+        }
+    }
+
+    let (message, tool_results) = match history.pop() {
+        Some(last) if last.role == "USER" => (last.message.unwrap_or_default(), None),
+        Some(last) if last.role == "TOOL" => (String::new(), last.tool_results),
+        Some(last) => {
+            history.push(last);
+            (String::new(), None)
+        }
+        None => (String::new(), None),
+    };
+
+    let tools = req.tools.as_ref().map(|tools| {
+        tools
+            .iter()
+            .map(|t| CohereTool {
+                name: t.function.name.clone(),
+                description: t
+                    .function
+                    .description
+                    .as_deref()
+                    .unwrap_or_default()
+                    .to_string(),
+                parameter_definitions: parameter_definitions(&t.function.parameters),
+            })
+            .collect()
+    });
+
+    CohereChatRequest {
+        model: req.model.clone(),
+        message,
+        preamble: if preamble_parts.is_empty() {
+            None
+        } else {
+            Some(preamble_parts.join("\n\n"))
+        },
+        chat_history: history,
+        tools,
+        tool_results,
+        max_tokens: req.max_tokens,
+        temperature: req.temperature,
+        p: req.top_p,
+        stop_sequences: req.stop.clone(),
+        stream: req.stream,
+    }
+}
+
+/// Convert a Cohere chat response into an `OpenAI` chat completion.
+#[must_use]
+pub fn cohere_to_openai(resp: &CohereChatResponse) -> ChatCompletionResponse {
+    let tool_calls: Option<Vec<ChatToolCall>> = resp
+        .tool_calls
+        .as_ref()
+        .filter(|calls| !calls.is_empty())
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|c| ChatToolCall {
+                    id: synthesize_call_id(),
+                    call_type: "function".to_string(),
+                    function: ChatToolCallFunction {
+                        name: c.name.clone(),
+                        arguments: c.parameters.to_string(),
+                    },
+                })
+                .collect()
+        });
+
+    let finish_reason = map_finish_reason(resp.finish_reason.as_deref(), tool_calls.is_some());
+
+    ChatCompletionResponse {
+        id: resp
+            .response_id
+            .clone()
+            .or_else(|| resp.generation_id.clone())
+            .unwrap_or_default(),
+        object: "chat.completion".to_string(),
+        created: 0,
+        model: String::new(),
+        choices: vec![Choice {
+            index: 0,
+            message: ChoiceMessage {
+                role: "assistant".to_string(),
+                content: Some(resp.text.clone()),
+                reasoning_content: None,
+                tool_calls,
+            },
+            finish_reason: Some(finish_reason),
+            logprobs: None,
+        }],
+        usage: usage(resp.meta.as_ref()),
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+/// Convert one Cohere stream event into an `OpenAI` chunk, if it carries anything
+/// we translate.
+#[must_use]
+pub fn cohere_event_to_chunk(event: &CohereStreamEvent) -> Option<ChatCompletionChunk> {
+    match event {
+        CohereStreamEvent::StreamStart { .. } => Some(chunk(ChunkDelta::default(), None, None)),
+        CohereStreamEvent::TextGeneration { text } => Some(chunk(
+            ChunkDelta {
+                content: Some(text.clone()),
+                ..ChunkDelta::default()
+            },
+            None,
+            None,
+        )),
+        CohereStreamEvent::ToolCallsGeneration { tool_calls } if !tool_calls.is_empty() => {
+            let calls = tool_calls
+                .iter()
+                .enumerate()
+                .map(|(i, c)| ChunkToolCall {
+                    index: i as u64,
+                    id: Some(synthesize_call_id()),
+                    call_type: Some("function".to_string()),
+                    function: Some(ChunkToolCallFunction {
+                        name: Some(c.name.clone()),
+                        arguments: Some(c.parameters.to_string()),
+                    }),
+                })
+                .collect();
+            Some(chunk(
+                ChunkDelta {
+                    tool_calls: Some(calls),
+                    ..ChunkDelta::default()
+                },
+                None,
+                None,
+            ))
+        }
+        CohereStreamEvent::StreamEnd {
+            finish_reason,
+            response,
+        } => {
+            let has_tool_calls = response
+                .as_ref()
+                .and_then(|r| r.tool_calls.as_ref())
+                .is_some_and(|c| !c.is_empty());
+            Some(chunk(
+                ChunkDelta::default(),
+                Some(map_finish_reason(finish_reason.as_deref(), has_tool_calls)),
+                response.as_ref().and_then(|r| usage(r.meta.as_ref())),
+            ))
+        }
+        CohereStreamEvent::ToolCallsGeneration { .. } | CohereStreamEvent::Other => None,
+    }
+}
+
+/// Map a Cohere `finish_reason` to the `OpenAI` equivalent.
+#[must_use]
+pub fn map_finish_reason(reason: Option<&str>, has_tool_calls: bool) -> String {
+    if has_tool_calls {
+        return "tool_calls".to_string();
+    }
+    match reason {
+        Some("MAX_TOKENS") => "length".to_string(),
+        Some("ERROR_TOXIC") => "content_filter".to_string(),
+        _ => "stop".to_string(),
+    }
+}
+
+fn chunk(
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+    usage: Option<ChatUsage>,
+) -> ChatCompletionChunk {
+    ChatCompletionChunk {
+        id: String::new(),
+        object: "chat.completion.chunk".to_string(),
+        created: 0,
+        model: String::new(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+            logprobs: None,
+        }],
+        usage,
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+fn usage(meta: Option<&CohereMeta>) -> Option<ChatUsage> {
+    meta.and_then(|m| m.billed_units.as_ref())
+        .map(|b| ChatUsage {
+            prompt_tokens: b.input_tokens,
+            completion_tokens: b.output_tokens,
+            total_tokens: b.input_tokens + b.output_tokens,
+            ..ChatUsage::default()
+        })
+}
+
+/// Cohere tool calls carry no id, but Anthropic `tool_use` blocks need one.
+fn synthesize_call_id() -> String {
+    format!("call_{}", uuid::Uuid::new_v4().simple())
+}
+
+fn content_text(msg: &ChatMessage) -> String {
+    match &msg.content {
+        Some(ChatContent::Text(t)) => t.to_string(),
+        Some(ChatContent::Parts(parts)) => parts
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::Text { text } => Some(text.as_ref()),
+                ContentPart::ImageUrl { .. } | ContentPart::File { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => String::new(),
+    }
+}
+
+/// Cohere expects tool outputs as JSON objects.
+fn tool_output(text: &str) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(v @ serde_json::Value::Object(_)) => v,
+        _ => serde_json::json!({ "output": text }),
+    }
+}
+
+/// Flatten a JSON Schema object into Cohere's `parameter_definitions`.
+fn parameter_definitions(schema: &serde_json::Value) -> BTreeMap<String, CohereParameter> {
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(serde_json::Value::as_array)
+        .map(|r| r.iter().filter_map(serde_json::Value::as_str).collect())
+        .unwrap_or_default();
+
+    schema
+        .get("properties")
+        .and_then(serde_json::Value::as_object)
+        .map(|props| {
+            props
+                .iter()
+                .map(|(name, prop)| {
+                    let param_type = match prop.get("type").and_then(serde_json::Value::as_str) {
+                        Some("integer") => "int",
+                        Some("number") => "float",
+                        Some("boolean") => "bool",
+                        Some("array") => "list",
+                        Some("object") => "dict",
+                        _ => "str",
+                    };
+                    (
+                        name.clone(),
+                        CohereParameter {
+                            description: prop
+                                .get("description")
+                                .and_then(serde_json::Value::as_str)
+                                .map(String::from),
+                            param_type: param_type.to_string(),
+                            required: required.contains(&name.as_str()),
+                        },
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::openai_types::{ChatFunction, ChatTool};
+    use std::borrow::Cow;
+
+    fn msg(role: &str, content: Option<&str>) -> ChatMessage<'static> {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.map(|c| ChatContent::Text(c.to_string().into())),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_tool_conversation_to_cohere() {
+        let mut assistant = msg("assistant", Some("Checking."));
+        assistant.tool_calls = Some(vec![ChatToolCall {
+            id: "toolu_1".to_string(),
+            call_type: "function".to_string(),
+            function: ChatToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: "{\"city\":\"London\"}".to_string(),
+            },
+        }]);
+        let mut tool = msg("tool", Some("sunny"));
+        tool.tool_call_id = Some("toolu_1".to_string());
+
+        let req = ChatCompletionRequest {
+            model: "command-r-plus".to_string(),
+            messages: vec![
+                msg("system", Some("Be brief")),
+                msg("user", Some("Weather?")),
+                assistant,
+                tool,
+            ],
+            max_tokens: Some(100),
+            temperature: None,
+            top_p: None,
+            stream: None,
+            stream_options: None,
+            tools: Some(vec![ChatTool {
+                tool_type: "function".to_string(),
+                function: ChatFunction {
+                    name: "get_weather".to_string(),
+                    description: None,
+                    parameters: Cow::Owned(serde_json::json!({
+                        "type": "object",
+                        "properties": {"city": {"type": "string"}},
+                        "required": ["city"]
+                    })),
+                },
+            }]),
+            tool_choice: None,
+            stop: None,
+            user: None,
+            reasoning_effort: None,
+            search_parameters: None,
+            response_format: None,
+            prompt_cache_key: None,
+            extra: serde_json::Map::new(),
+        };
+
+        let cohere = openai_to_cohere(&req);
+
+        assert_eq!(cohere.preamble.as_deref(), Some("Be brief"));
+        assert_eq!(cohere.message, "");
+        assert_eq!(cohere.chat_history.len(), 2);
+        assert_eq!(cohere.chat_history[1].role, "CHATBOT");
+
+        let results = cohere.tool_results.unwrap();
+        assert_eq!(results[0].call.name, "get_weather");
+        assert_eq!(results[0].call.parameters["city"], "London");
+        assert_eq!(results[0].outputs[0]["output"], "sunny");
+
+        let tools = cohere.tools.unwrap();
+        assert!(tools[0].parameter_definitions["city"].required);
+        assert_eq!(tools[0].parameter_definitions["city"].param_type, "str");
+    }
+
+    #[test]
+    fn test_cohere_response_to_openai() {
+        let resp: CohereChatResponse = serde_json::from_value(serde_json::json!({
+            "text": "",
+            "response_id": "abc",
+            "finish_reason": "COMPLETE",
+            "tool_calls": [{"name": "search", "parameters": {"q": "rust"}}],
+            "meta": {"billed_units": {"input_tokens": 12, "output_tokens": 4}}
+        }))
+        .unwrap();
+
+        let openai = cohere_to_openai(&resp);
+        let choice = &openai.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let call = &choice.message.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.function.name, "search");
+        assert_eq!(call.function.arguments, "{\"q\":\"rust\"}");
+        assert_eq!(openai.usage.unwrap().prompt_tokens, 12);
+    }
+
+    #[test]
+    fn test_stream_events_to_chunks() {
+        let events: Vec<CohereStreamEvent> = [
+            r#"{"event_type":"stream-start","generation_id":"g1"}"#,
+            r#"{"event_type":"text-generation","text":"Hi"}"#,
+            r#"{"event_type":"citation-generation","citations":[]}"#,
+            r#"{"event_type":"stream-end","finish_reason":"MAX_TOKENS","response":{"text":"Hi"}}"#,
+        ]
+        .iter()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+        let chunks: Vec<_> = events.iter().filter_map(cohere_event_to_chunk).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].choices[0].delta.content.as_deref(), Some("Hi"));
+        assert_eq!(
+            chunks[2].choices[0].finish_reason.as_deref(),
+            Some("length")
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! `OpenAI` legacy `/v1/completions` types and translation to/from Anthropic.
+//!
+//! Backs the inbound `/v1/completions` endpoint some editor plugins still use:
+//! the prompt becomes a single user turn of a [`MessagesRequest`], asked to be
+//! continued, so it goes through the same routing and upstream translation as
+//! Anthropic requests. The Anthropic response (or stream events) is translated
+//! back into `text_completion` objects. Only one prompt and one choice per
+//! request are supported.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::anthropic_types::{
+    Delta, Message, MessageContent, MessagesRequest, MessagesResponse, ResponseContentBlock, Role,
+    StreamEvent, SystemContent,
+};
+use crate::error::{ProxyError, Result};
+
+/// `max_tokens` when the request doesn't set one, as in the `OpenAI` API.
+const DEFAULT_MAX_TOKENS: u64 = 16;
+
+/// Tells a chat model to act as a completion model.
+const CONTINUE_INSTRUCTION: &str = "Continue the text in the user's message from exactly \
+     where it ends. Reply with only the continuation: don't repeat the text, and add no \
+     commentary or formatting.";
+
+// ---------------------------------------------------------------------------
+// Request types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionRequest {
+    pub model: String,
+    pub prompt: Prompt,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Stop>,
+    /// Return the prompt in front of the completion.
+    #[serde(default)]
+    pub echo: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// A prompt: a string, or an array holding one string.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Prompt {
+    Text(String),
+    Batch(Vec<String>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+// ---------------------------------------------------------------------------
+// Response types
+// ---------------------------------------------------------------------------
+
+/// A `text_completion` response, or one chunk of a streamed one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionResponse {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<CompletionUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionChoice {
+    pub text: String,
+    pub index: u32,
+    /// Always null: log probabilities aren't available.
+    pub logprobs: Option<serde_json::Value>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+// ---------------------------------------------------------------------------
+// Completions → Anthropic
+// ---------------------------------------------------------------------------
+
+/// Translate a completions request into an Anthropic Messages request.
+///
+/// # Errors
+/// Returns `ProxyError::InvalidRequest` for more than one prompt or choice.
+pub fn completion_to_anthropic(req: &CompletionRequest) -> Result<MessagesRequest> {
+    if req.n.unwrap_or(1) != 1 {
+        return Err(ProxyError::invalid_request(
+            "Only n = 1 is supported on /v1/completions",
+        ));
+    }
+
+    let mut text = prompt_text(&req.prompt)?.to_string();
+    let mut system = CONTINUE_INSTRUCTION.to_string();
+    if let Some(suffix) = req.suffix.as_deref().filter(|s| !s.is_empty()) {
+        system.push_str(
+            " The continuation will be followed by the text in <suffix>, so it must lead into it.",
+        );
+        text = format!("{text}\n\n<suffix>{suffix}</suffix>");
+    }
+
+    let stop_sequences = req.stop.clone().map(|stop| match stop {
+        Stop::One(s) => vec![s],
+        Stop::Many(v) => v,
+    });
+
+    Ok(MessagesRequest {
+        model: req.model.clone(),
+        max_tokens: req.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        messages: vec![Message {
+            role: Role::User,
+            content: MessageContent::Text(text),
+        }],
+        system: Some(SystemContent::Text(system)),
+        stream: Some(req.stream.unwrap_or(false)),
+        temperature: req.temperature,
+        top_p: req.top_p,
+        top_k: None,
+        tools: None,
+        tool_choice: None,
+        metadata: None,
+        stop_sequences: stop_sequences.filter(|s| !s.is_empty()),
+        thinking: None,
+        betas: None,
+        context_management: None,
+        reasoning_effort: None,
+        extra: HashMap::new(),
+    })
+}
+
+/// The prompt's text.
+///
+/// # Errors
+/// Returns `ProxyError::InvalidRequest` unless there is exactly one prompt.
+pub fn prompt_text(prompt: &Prompt) -> Result<&str> {
+    match prompt {
+        Prompt::Text(text) => Ok(text),
+        Prompt::Batch(prompts) if prompts.len() == 1 => Ok(&prompts[0]),
+        Prompt::Batch(_) => Err(ProxyError::invalid_request(
+            "Only one prompt per request is supported on /v1/completions",
+        )),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Anthropic → Completions
+// ---------------------------------------------------------------------------
+
+/// Translate an Anthropic response into a `text_completion` response, with
+/// `echo`ed prompt text in front if requested.
+#[must_use]
+pub fn anthropic_to_completion(resp: &MessagesResponse, echo: Option<&str>) -> CompletionResponse {
+    let text: String = echo
+        .into_iter()
+        .chain(resp.content.iter().filter_map(|block| match block {
+            ResponseContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        }))
+        .collect();
+
+    CompletionResponse {
+        id: completion_id(&resp.id),
+        object: "text_completion".to_string(),
+        created: chrono::Utc::now().timestamp(),
+        model: resp.model.clone(),
+        choices: vec![CompletionChoice {
+            text,
+            index: 0,
+            logprobs: None,
+            finish_reason: map_stop_reason(resp.stop_reason.as_deref()),
+        }],
+        usage: Some(CompletionUsage {
+            prompt_tokens: resp.usage.input_tokens,
+            completion_tokens: resp.usage.output_tokens,
+            total_tokens: resp.usage.input_tokens + resp.usage.output_tokens,
+        }),
+    }
+}
+
+/// Map an Anthropic `stop_reason` to a completions `finish_reason`.
+#[must_use]
+pub fn map_stop_reason(stop_reason: Option<&str>) -> Option<String> {
+    stop_reason.map(|r| match r {
+        "max_tokens" => "length".to_string(),
+        _ => "stop".to_string(),
+    })
+}
+
+fn completion_id(message_id: &str) -> String {
+    format!(
+        "cmpl-{}",
+        message_id.strip_prefix("msg_").unwrap_or(message_id)
+    )
+}
+
+/// Converts Anthropic stream events into streamed `text_completion` chunks.
+/// Only answer text is streamed; thinking and tool calls are left out.
+#[derive(Debug)]
+pub struct CompletionStreamTranslator {
+    id: String,
+    model: String,
+    created: i64,
+    echo: Option<String>,
+}
+
+impl CompletionStreamTranslator {
+    /// A translator for a stream of `model`, sending `echo` as the first text.
+    #[must_use]
+    pub fn new(model: &str, echo: Option<String>) -> Self {
+        Self {
+            id: completion_id(&uuid::Uuid::new_v4().simple().to_string()),
+            model: model.to_string(),
+            created: chrono::Utc::now().timestamp(),
+            echo,
+        }
+    }
+
+    /// Translate one event. Returns `None` for events with nothing to emit.
+    pub fn process_event(&mut self, event: &StreamEvent) -> Option<CompletionResponse> {
+        match event {
+            StreamEvent::MessageStart { message } => {
+                self.id = completion_id(&message.id);
+                let echo = self.echo.take()?;
+                Some(self.chunk(echo, None))
+            }
+            StreamEvent::ContentBlockStart {
+                content_block: ResponseContentBlock::Text { text },
+                ..
+            } if !text.is_empty() => Some(self.chunk(text.clone(), None)),
+            StreamEvent::ContentBlockDelta {
+                delta: Delta::TextDelta { text },
+                ..
+            } => Some(self.chunk(text.clone(), None)),
+            StreamEvent::MessageDelta { delta, .. } => {
+                // Interim usage updates carry no stop reason
+                let finish_reason = map_stop_reason(delta.stop_reason.as_deref())?;
+                Some(self.chunk(String::new(), Some(finish_reason)))
+            }
+            _ => None,
+        }
+    }
+
+    fn chunk(&self, text: String, finish_reason: Option<String>) -> CompletionResponse {
+        CompletionResponse {
+            id: self.id.clone(),
+            object: "text_completion".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![CompletionChoice {
+                text,
+                index: 0,
+                logprobs: None,
+                finish_reason,
+            }],
+            usage: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::anthropic_types::{DeltaUsage, MessageDeltaBody, Usage};
+
+    #[test]
+    fn test_completion_request_to_anthropic() {
+        let req: CompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-haiku-4-5",
+            "prompt": ["def fib(n):"],
+            "suffix": "\n\nprint(fib(10))",
+            "max_tokens": 64,
+            "stop": "\n\n",
+            "stream": true
+        }))
+        .unwrap();
+
+        let out = completion_to_anthropic(&req).unwrap();
+
+        assert_eq!(out.model, "claude-haiku-4-5");
+        assert_eq!(out.max_tokens, 64);
+        assert_eq!(out.stream, Some(true));
+        assert_eq!(out.stop_sequences, Some(vec!["\n\n".to_string()]));
+        assert!(matches!(out.system, Some(SystemContent::Text(ref t)) if t.contains("<suffix>")));
+        assert!(matches!(
+            out.messages[0].content,
+            MessageContent::Text(ref t) if t == "def fib(n):\n\n<suffix>\n\nprint(fib(10))</suffix>"
+        ));
+
+        let batch: CompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-haiku-4-5",
+            "prompt": ["a", "b"]
+        }))
+        .unwrap();
+        assert!(completion_to_anthropic(&batch).is_err());
+    }
+
+    #[test]
+    fn test_completion_stream() {
+        let mut translator =
+            CompletionStreamTranslator::new("claude-haiku-4-5", Some("1, 2,".into()));
+        let message = MessagesResponse {
+            id: "msg_abc".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: Vec::new(),
+            model: "claude-haiku-4-5".to_string(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Usage::default(),
+            extra: None,
+        };
+        let events = [
+            StreamEvent::MessageStart { message },
+            StreamEvent::ContentBlockStart {
+                index: 0,
+                content_block: ResponseContentBlock::Text {
+                    text: String::new(),
+                },
+            },
+            StreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: Delta::TextDelta {
+                    text: " 3, 4".to_string(),
+                },
+            },
+            StreamEvent::ContentBlockStop { index: 0 },
+            StreamEvent::MessageDelta {
+                delta: MessageDeltaBody {
+                    stop_reason: Some("max_tokens".to_string()),
+                    stop_sequence: None,
+                },
+                usage: DeltaUsage {
+                    output_tokens: 4,
+                    cache_read_input_tokens: None,
+                },
+                extra: None,
+            },
+            StreamEvent::MessageStop,
+        ];
+
+        let chunks: Vec<CompletionResponse> = events
+            .iter()
+            .filter_map(|e| translator.process_event(e))
+            .collect();
+
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| c.id == "cmpl-abc"));
+        let text: String = chunks.iter().map(|c| c.choices[0].text.as_str()).collect();
+        assert_eq!(text, "1, 2, 3, 4");
+        assert_eq!(
+            chunks[2].choices[0].finish_reason.as_deref(),
+            Some("length")
+        );
+    }
+}
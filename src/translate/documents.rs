@@ -0,0 +1,489 @@
+//! Document (PDF) content blocks.
+//!
+//! Claude Code sends PDFs as `document` blocks, from the user or from a tool
+//! reading a file. A plain-text document is translated to text. A PDF becomes
+//! an `OpenAI` `file` part, in a user message: a tool message can only carry
+//! text, so a PDF read by a tool follows its result in a user message of its
+//! own. Few backends read file parts, so by `[translation] documents` the
+//! proxy then either extracts the PDF's text itself (`text`, the default),
+//! sends its pages' images along with their text (`images`, for scanned PDFs
+//! and vision models), passes the file on (`file`, for `OpenAI`, Gemini,
+//! Bedrock and `OpenRouter`) or leaves a note in its place (`drop`).
+//!
+//! Reading a PDF is slow work, so it runs on a blocking thread, and the
+//! results are kept in a [`DocumentCache`]: a conversation resends its
+//! documents every turn, but each is read once.
+
+use super::anthropic_types::{ContentBlock, DocumentSource, MessageContent};
+use super::openai_types::{
+    ChatCompletionRequest, ChatContent, ContentPart, FileDetail, ImageUrlDetail,
+};
+use super::pdf;
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Converted documents kept by a [`DocumentCache`].
+const CACHED_DOCUMENTS: usize = 16;
+
+/// Why a document wasn't sent to a model that can't take it.
+const UNREADABLE: &str = "this model can't read documents";
+
+/// What a PDF document becomes (`[translation] documents`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentStrategy {
+    /// The text extracted from it by the proxy.
+    #[default]
+    Text,
+    /// The images its pages draw, each followed by the page's text. Pages
+    /// aren't rendered, so this suits scanned PDFs: text and drawings on a
+    /// page only reach the model as extracted text.
+    Images,
+    /// A `file` part, for backends that read PDFs themselves.
+    File,
+    /// A note that a document was left out.
+    Drop,
+}
+
+/// A document block as message content: text for plain-text and content
+/// documents, a `file` part for a PDF, and a note for what the backend can't
+/// be sent (a URL or an uploaded file id).
+#[must_use]
+pub fn part(
+    source: &DocumentSource,
+    title: Option<&str>,
+    context: Option<&str>,
+) -> ContentPart<'static> {
+    let text = |text: String| ContentPart::Text { text: text.into() };
+    match source.source_type.as_str() {
+        "text" => text(framed(title, context, &source.data)),
+        "content" => {
+            let body = match &source.content {
+                Some(MessageContent::Text(body)) => body.clone(),
+                Some(MessageContent::Blocks(blocks)) => blocks
+                    .iter()
+                    .filter_map(|block| match block {
+                        ContentBlock::Text { text, .. } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                None => String::new(),
+            };
+            text(framed(title, context, &body))
+        }
+        "base64" => ContentPart::File {
+            file: FileDetail {
+                filename: Some(filename(title)),
+                file_data: format!("data:{};base64,{}", source.media_type, source.data),
+            },
+        },
+        "url" => text(placeholder(
+            title.or(source.url.as_deref()).unwrap_or("document"),
+            "documents given by URL aren't fetched",
+        )),
+        _ => text(placeholder(
+            &filename(title),
+            "uploaded files can't be sent to this model",
+        )),
+    }
+}
+
+/// What a tool result says of a document `part` that follows it in a user
+/// message; a text part is the text itself.
+#[must_use]
+pub fn tool_result_text<'a>(part: &'a ContentPart<'_>) -> Cow<'a, str> {
+    match part {
+        ContentPart::Text { text } => Cow::Borrowed(text),
+        ContentPart::File { file } => Cow::Owned(format!(
+            "[Document \"{}\" attached in the next message]",
+            file.filename.as_deref().unwrap_or("document")
+        )),
+        ContentPart::ImageUrl { .. } => Cow::Borrowed(""),
+    }
+}
+
+/// A file part converted under a strategy, or why it couldn't be.
+type Converted = Result<Arc<[ContentPart<'static>]>, &'static str>;
+
+/// Carry out `strategy` on the file parts of a request, returning notes on
+/// documents that were dropped or had no text to extract. Each document is
+/// read once, on a blocking thread, unless `cache` already has it.
+pub async fn apply(
+    openai_req: &mut ChatCompletionRequest<'_>,
+    strategy: DocumentStrategy,
+    cache: &DocumentCache,
+) -> Vec<String> {
+    let mut notes = Vec::new();
+    if strategy == DocumentStrategy::File {
+        return notes;
+    }
+    let mut converted: HashMap<u64, Converted> = HashMap::new();
+    let mut misses: HashMap<u64, FileDetail> = HashMap::new();
+    // Dropped documents aren't read at all
+    let files = files(openai_req).filter(|_| strategy != DocumentStrategy::Drop);
+    for file in files {
+        let key = DocumentCache::key(file, strategy);
+        if converted.contains_key(&key) || misses.contains_key(&key) {
+            continue;
+        }
+        match cache.get(key) {
+            Some(result) => {
+                converted.insert(key, result);
+            }
+            None => {
+                misses.insert(key, file.clone());
+            }
+        }
+    }
+    if !misses.is_empty() {
+        let processed = tokio::task::spawn_blocking(move || {
+            misses
+                .into_iter()
+                .map(|(key, file)| (key, convert(&file, strategy).map(Arc::from)))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        for (key, result) in processed {
+            cache.insert(key, result.clone());
+            converted.insert(key, result);
+        }
+    }
+
+    for message in &mut openai_req.messages {
+        let Some(ChatContent::Parts(parts)) = &mut message.content else {
+            continue;
+        };
+        if !parts
+            .iter()
+            .any(|part| matches!(part, ContentPart::File { .. }))
+        {
+            continue;
+        }
+        *parts = std::mem::take(parts)
+            .into_iter()
+            .flat_map(|part| match part {
+                ContentPart::File { file } => {
+                    let result = match converted.get(&DocumentCache::key(&file, strategy)) {
+                        Some(result) => result.clone(),
+                        None if strategy == DocumentStrategy::Drop => Err(UNREADABLE),
+                        None => Err("it couldn't be read"),
+                    };
+                    converted_parts(&file, strategy, result, &mut notes)
+                }
+                part => vec![part],
+            })
+            .collect();
+    }
+    notes
+}
+
+/// The file parts of a request.
+fn files<'r>(openai_req: &'r ChatCompletionRequest<'_>) -> impl Iterator<Item = &'r FileDetail> {
+    openai_req
+        .messages
+        .iter()
+        .filter_map(|m| match &m.content {
+            Some(ChatContent::Parts(parts)) => Some(parts.iter()),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|part| match part {
+            ContentPart::File { file } => Some(file),
+            ContentPart::Text { .. } | ContentPart::ImageUrl { .. } => None,
+        })
+}
+
+/// What a file part becomes under `strategy`. This is where PDFs are read.
+fn convert(
+    file: &FileDetail,
+    strategy: DocumentStrategy,
+) -> Result<Vec<ContentPart<'static>>, &'static str> {
+    let name = file.filename.as_deref().unwrap_or("document");
+    match strategy {
+        DocumentStrategy::Text => {
+            file_text(&file.file_data).map(|text| vec![text_part(framed(Some(name), None, &text))])
+        }
+        DocumentStrategy::Images => file_pages(name, &file.file_data),
+        DocumentStrategy::File | DocumentStrategy::Drop => Err(UNREADABLE),
+    }
+}
+
+/// The parts a file part is replaced with: those it was converted to, or a
+/// placeholder, noting in `notes` why it wasn't sent.
+fn converted_parts(
+    file: &FileDetail,
+    strategy: DocumentStrategy,
+    result: Converted,
+    notes: &mut Vec<String>,
+) -> Vec<ContentPart<'static>> {
+    let name = file.filename.as_deref().unwrap_or("document");
+    match result {
+        Ok(parts) => parts.to_vec(),
+        Err(reason) => {
+            let note = if strategy == DocumentStrategy::Drop {
+                "dropped"
+            } else {
+                reason
+            };
+            notes.push(format!("{name}: {note}"));
+            vec![text_part(placeholder(name, reason))]
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    /// Each document's conversion and when it was last used.
+    entries: HashMap<u64, (Converted, u64)>,
+    tick: u64,
+}
+
+/// Recently converted documents, by a hash of their name, data and strategy.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentCache {
+    inner: Arc<Mutex<CacheEntries>>,
+}
+
+impl DocumentCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(file: &FileDetail, strategy: DocumentStrategy) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        file.filename.hash(&mut hasher);
+        file.file_data.hash(&mut hasher);
+        strategy.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn get(&self, key: u64) -> Option<Converted> {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tick += 1;
+        let tick = inner.tick;
+        let (result, used) = inner.entries.get_mut(&key)?;
+        *used = tick;
+        Some(result.clone())
+    }
+
+    fn insert(&self, key: u64, result: Converted) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tick += 1;
+        let tick = inner.tick;
+        if inner.entries.len() >= CACHED_DOCUMENTS {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(key, (result, tick));
+    }
+}
+
+fn text_part(text: String) -> ContentPart<'static> {
+    ContentPart::Text { text: text.into() }
+}
+
+/// The media type and bytes of a file sent as a base64 `data:` URL.
+fn file_bytes(file_data: &str) -> Result<(&str, Vec<u8>), &'static str> {
+    let (media_type, data) = file_data
+        .strip_prefix("data:")
+        .and_then(|url| url.split_once(";base64,"))
+        .ok_or("not inline base64 data")?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|_| "not valid base64")?;
+    Ok((media_type, bytes))
+}
+
+/// The text of a file sent as a base64 `data:` URL.
+fn file_text(file_data: &str) -> Result<String, &'static str> {
+    let (media_type, bytes) = file_bytes(file_data)?;
+    if media_type.starts_with("text/") {
+        return Ok(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    if media_type != "application/pdf" {
+        return Err("not a PDF");
+    }
+    pdf::extract_text(&bytes).ok_or("no text could be extracted from it")
+}
+
+/// A PDF's pages as parts: each page's images, then its text, between tags
+/// naming the document. A text file is sent as its text.
+fn file_pages(name: &str, file_data: &str) -> Result<Vec<ContentPart<'static>>, &'static str> {
+    let (media_type, bytes) = file_bytes(file_data)?;
+    if media_type.starts_with("text/") {
+        let text = String::from_utf8_lossy(&bytes);
+        return Ok(vec![text_part(framed(Some(name), None, &text))]);
+    }
+    if media_type != "application/pdf" {
+        return Err("not a PDF");
+    }
+    let pages = pdf::pages(&bytes).ok_or("it couldn't be read")?;
+    let mut parts = vec![text_part(format!("<document name=\"{name}\">"))];
+    for page in pages {
+        parts.extend(page.images.into_iter().map(|image| ContentPart::ImageUrl {
+            image_url: ImageUrlDetail {
+                url: format!(
+                    "data:{};base64,{}",
+                    image.media_type,
+                    base64::engine::general_purpose::STANDARD.encode(image.data)
+                ),
+                detail: None,
+            },
+        }));
+        if !page.text.is_empty() {
+            parts.push(text_part(page.text));
+        }
+    }
+    if parts.len() == 1 {
+        return Err("no text or images could be extracted from it");
+    }
+    parts.push(text_part("</document>".to_string()));
+    Ok(parts)
+}
+
+/// A document's text, wrapped in tags naming it.
+fn framed(name: Option<&str>, context: Option<&str>, text: &str) -> String {
+    let open = match name {
+        Some(name) => format!("<document name=\"{name}\">"),
+        None => "<document>".to_string(),
+    };
+    match context {
+        Some(context) => format!("{open}\n{context}\n\n{text}\n</document>"),
+        None => format!("{open}\n{text}\n</document>"),
+    }
+}
+
+fn placeholder(name: &str, reason: &str) -> String {
+    format!("[Document \"{name}\" omitted: {reason}]")
+}
+
+/// A PDF's file name, from its title.
+fn filename(title: Option<&str>) -> String {
+    match title {
+        Some(title) if title.to_ascii_lowercase().ends_with(".pdf") => title.to_string(),
+        Some(title) => format!("{title}.pdf"),
+        None => "document.pdf".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(files: &[(&str, String)]) -> ChatCompletionRequest<'static> {
+        let parts: Vec<_> = files
+            .iter()
+            .map(|(name, data)| {
+                serde_json::json!({"type": "file", "file": {"filename": name, "file_data": data}})
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "messages": [{"role": "user", "content": parts}]
+        }))
+        .unwrap()
+    }
+
+    fn texts(req: &ChatCompletionRequest<'_>) -> Vec<String> {
+        let Some(ChatContent::Parts(parts)) = &req.messages[0].content else {
+            panic!("no parts");
+        };
+        parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => text.to_string(),
+                _ => "(file)".to_string(),
+            })
+            .collect()
+    }
+
+    fn data_url(media_type: &str, data: &[u8]) -> String {
+        format!(
+            "data:{media_type};base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(data)
+        )
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let pdf = b"%PDF-1.4\n1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj\n\
+            2 0 obj << /Type /Pages /Kids [3 0 R] >> endobj\n\
+            3 0 obj << /Type /Page /Contents 4 0 R >> endobj\n\
+            4 0 obj << /Length 28 >>\nstream\nBT (Quarterly report) Tj ET\nendstream endobj\n\
+            trailer << /Root 1 0 R >>\n%%EOF\n";
+        let files = [
+            ("report.pdf", data_url("application/pdf", pdf)),
+            ("scan.pdf", data_url("application/pdf", b"%PDF-1.4\n%%EOF")),
+            ("notes.txt", data_url("text/plain", b"Some notes")),
+        ];
+
+        let cache = DocumentCache::new();
+        let mut req = request(&files);
+        let notes = apply(&mut req, DocumentStrategy::Text, &cache).await;
+        assert_eq!(
+            texts(&req),
+            [
+                "<document name=\"report.pdf\">\nQuarterly report\n</document>",
+                "[Document \"scan.pdf\" omitted: no text could be extracted from it]",
+                "<document name=\"notes.txt\">\nSome notes\n</document>",
+            ]
+        );
+        assert_eq!(notes, ["scan.pdf: no text could be extracted from it"]);
+
+        // The next turn's copies are taken from the cache
+        assert_eq!(cache.inner.lock().unwrap().entries.len(), 3);
+        let mut again = request(&files);
+        assert_eq!(
+            apply(&mut again, DocumentStrategy::Text, &cache).await,
+            notes
+        );
+        assert_eq!(texts(&again), texts(&req));
+        assert_eq!(cache.inner.lock().unwrap().entries.len(), 3);
+
+        let mut req = request(&files);
+        let notes = apply(&mut req, DocumentStrategy::Drop, &cache).await;
+        assert_eq!(notes.len(), 3);
+        assert_eq!(
+            texts(&req)[0],
+            "[Document \"report.pdf\" omitted: this model can't read documents]"
+        );
+
+        let mut req = request(&files);
+        let notes = apply(&mut req, DocumentStrategy::Images, &cache).await;
+        assert_eq!(
+            texts(&req),
+            [
+                "<document name=\"report.pdf\">",
+                "Quarterly report",
+                "</document>",
+                "[Document \"scan.pdf\" omitted: no text or images could be extracted from it]",
+                "<document name=\"notes.txt\">\nSome notes\n</document>",
+            ]
+        );
+        assert_eq!(
+            notes,
+            ["scan.pdf: no text or images could be extracted from it"]
+        );
+
+        let mut req = request(&files);
+        assert!(apply(&mut req, DocumentStrategy::File, &cache)
+            .await
+            .is_empty());
+        assert_eq!(texts(&req), ["(file)"; 3]);
+    }
+}
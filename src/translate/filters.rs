@@ -0,0 +1,404 @@
+//! Regex post-processing of model output text.
+//!
+//! `[translation] output_filters` picks built-in rules and `output_rules` adds
+//! custom ones; each rule replaces every match of its pattern, in order. Rules
+//! apply to the response text of non-streaming responses and, through
+//! [`StreamFilter`], to text deltas as they stream — with the same result,
+//! because a stream holds back only the text a match could still be starting
+//! in, so a match split across deltas is replaced whole.
+//!
+//! Thinking and tool-call content is never filtered.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+use regex_automata::hybrid::dfa::{Cache, DFA};
+use regex_automata::hybrid::LazyStateID;
+use regex_automata::{Anchored, Input};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ProxyError, Result};
+
+/// Built-in filters, by config name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinFilter {
+    /// `<think>...</think>` reasoning that some models write into the answer.
+    ThinkTags,
+    /// Leaked chat-template tokens: `<|im_end|>`, `<|eot_id|>`, `</s>`, and
+    /// the role headers that follow `<|im_start|>` and `<|start_header_id|>`.
+    ChatTemplate,
+    /// Trailing spaces at line ends and runs of blank lines.
+    Whitespace,
+}
+
+impl BuiltinFilter {
+    fn patterns(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::ThinkTags => &[(r"(?s)<think>.*?</think>\s*", "")],
+            Self::ChatTemplate => &[(
+                r"<\|im_start\|>[a-z]*\n?|<\|start_header_id\|>[a-z]*<\|end_header_id\|>\n*|<\|[a-z_]{1,32}\|>|</s>",
+                "",
+            )],
+            Self::Whitespace => &[(r"[ \t]+\n", "\n"), (r"\n{3,}", "\n\n")],
+        }
+    }
+
+    fn rules(self) -> &'static [OutputRule] {
+        static COMPILED: [OnceLock<Vec<OutputRule>>; 3] =
+            [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+        COMPILED[self as usize].get_or_init(|| {
+            self.patterns()
+                .iter()
+                .map(|(pattern, replacement)| {
+                    OutputRule::new(pattern, replacement).expect("built-in filters compile")
+                })
+                .collect()
+        })
+    }
+}
+
+/// A custom rule: every match of `pattern` is replaced with `replacement`,
+/// which can refer to capture groups as `$1` or `$name`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RuleSpec", into = "RuleSpec")]
+pub struct OutputRule {
+    regex: Regex,
+    /// The same pattern as a lazy DFA, to tell whether a match could still be
+    /// in progress at the end of streamed text.
+    dfa: DFA,
+    replacement: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RuleSpec {
+    pattern: String,
+    #[serde(default)]
+    replacement: String,
+}
+
+impl OutputRule {
+    /// # Errors
+    /// Returns `ProxyError::Config` if `pattern` is not a valid regex.
+    pub fn new(pattern: &str, replacement: &str) -> Result<Self> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            ProxyError::config(format!("Invalid output rule '{pattern}': {e}"))
+        };
+        let regex = Regex::new(pattern).map_err(|e| invalid(&e))?;
+        let dfa = DFA::builder()
+            .configure(DFA::config().unicode_word_boundary(true))
+            .build(pattern)
+            .map_err(|e| invalid(&e))?;
+        Ok(Self {
+            regex,
+            dfa,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+impl TryFrom<RuleSpec> for OutputRule {
+    type Error = ProxyError;
+
+    fn try_from(spec: RuleSpec) -> Result<Self> {
+        Self::new(&spec.pattern, &spec.replacement)
+    }
+}
+
+impl From<OutputRule> for RuleSpec {
+    fn from(rule: OutputRule) -> Self {
+        Self {
+            pattern: rule.regex.as_str().to_string(),
+            replacement: rule.replacement,
+        }
+    }
+}
+
+/// The rules to apply to a response's text, in order.
+#[derive(Debug, Clone, Default)]
+pub struct OutputFilters(Vec<OutputRule>);
+
+impl OutputFilters {
+    /// The built-in filters' rules, followed by the custom rules.
+    #[must_use]
+    pub fn new(builtins: &[BuiltinFilter], rules: &[OutputRule]) -> Self {
+        Self(
+            builtins
+                .iter()
+                .flat_map(|b| b.rules())
+                .chain(rules)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Filter a complete text.
+    #[must_use]
+    pub fn apply(&self, text: &str) -> String {
+        if self.is_empty() {
+            return text.to_string();
+        }
+        let mut stream = self.stream();
+        let mut out = stream.push(text);
+        out.push_str(&stream.flush());
+        out
+    }
+
+    /// A filter for text arriving in pieces.
+    #[must_use]
+    pub fn stream(&self) -> StreamFilter {
+        StreamFilter {
+            stages: self.0.iter().cloned().map(Stage::new).collect(),
+        }
+    }
+}
+
+/// Applies [`OutputFilters`] to streamed text, one stage per rule.
+#[derive(Debug)]
+pub struct StreamFilter {
+    stages: Vec<Stage>,
+}
+
+impl StreamFilter {
+    /// Filter the next piece of text, returning what can be emitted now.
+    pub fn push(&mut self, text: &str) -> String {
+        let mut out = text.to_string();
+        for stage in &mut self.stages {
+            if out.is_empty() {
+                break;
+            }
+            out = stage.push(&out);
+        }
+        out
+    }
+
+    /// Release everything held back, e.g. when the text block ends. Text pushed
+    /// afterwards is filtered as a fresh text.
+    pub fn flush(&mut self) -> String {
+        let mut out = String::new();
+        for stage in &mut self.stages {
+            out = stage.push(&out);
+            out.push_str(&stage.flush());
+        }
+        out
+    }
+}
+
+/// One rule's share of a [`StreamFilter`].
+///
+/// `buf[start..]` is text not yet emitted; the character before `start` is
+/// kept so assertions like `\b` and `(?m)^` see what preceded it. No match can
+/// begin in `buf[start..checked]` and run past the end of the buffer, so that
+/// text is final once the rule has been applied to it.
+#[derive(Debug)]
+struct Stage {
+    rule: OutputRule,
+    cache: Cache,
+    buf: String,
+    start: usize,
+    checked: usize,
+    /// DFA state of a possible match starting at `checked`, and how far it has
+    /// been walked.
+    walk: Option<(LazyStateID, usize)>,
+}
+
+impl Stage {
+    fn new(rule: OutputRule) -> Self {
+        Self {
+            cache: rule.dfa.create_cache(),
+            rule,
+            buf: String::new(),
+            start: 0,
+            checked: 0,
+            walk: None,
+        }
+    }
+
+    fn push(&mut self, text: &str) -> String {
+        if text.is_empty() {
+            return String::new();
+        }
+        self.buf.push_str(text);
+        self.advance_checked();
+        let out = self.replace_until(self.checked);
+        self.trim();
+        out
+    }
+
+    fn flush(&mut self) -> String {
+        let out = self.replace_until(self.buf.len());
+        self.buf.clear();
+        self.start = 0;
+        self.checked = 0;
+        self.walk = None;
+        out
+    }
+
+    /// Move `checked` up to the first position where a match may still be
+    /// in progress at the end of the buffer.
+    fn advance_checked(&mut self) {
+        let end = self.buf.len();
+        while self.checked < end {
+            let (mut sid, from) = if let Some(walk) = self.walk.take() {
+                walk
+            } else {
+                let input = Input::new(&self.buf)
+                    .range(self.checked..)
+                    .anchored(Anchored::Yes);
+                match self.rule.dfa.start_state_forward(&mut self.cache, &input) {
+                    Ok(sid) => (sid, self.checked),
+                    // Can't tell; hold the rest back until more arrives
+                    Err(_) => return,
+                }
+            };
+            let mut dead = sid.is_dead();
+            for &byte in &self.buf.as_bytes()[from..] {
+                if dead {
+                    break;
+                }
+                match self.rule.dfa.next_state(&mut self.cache, sid, byte) {
+                    Ok(next) if next.is_quit() => return,
+                    Ok(next) => {
+                        sid = next;
+                        dead = sid.is_dead();
+                    }
+                    Err(_) => return,
+                }
+            }
+            if !dead {
+                self.walk = Some((sid, end));
+                return;
+            }
+            self.checked += self.buf[self.checked..]
+                .chars()
+                .next()
+                .map_or(1, char::len_utf8);
+        }
+    }
+
+    /// Apply the rule to `buf[start..limit]`, stopping short of a match that
+    /// runs past `limit`, and mark the text taken as emitted.
+    fn replace_until(&mut self, limit: usize) -> String {
+        let mut out = String::new();
+        let mut last = self.start;
+        let mut pos = self.start;
+        let mut cut = limit;
+        while pos < cut {
+            let Some(caps) = self.rule.regex.captures_at(&self.buf, pos) else {
+                break;
+            };
+            let Some(m) = caps.get(0) else { break };
+            if m.start() >= cut {
+                break;
+            }
+            if m.end() > cut {
+                cut = m.start();
+                break;
+            }
+            out.push_str(&self.buf[last..m.start()]);
+            caps.expand(&self.rule.replacement, &mut out);
+            last = m.end();
+            pos = if m.is_empty() {
+                m.end() + self.buf[m.end()..].chars().next().map_or(1, char::len_utf8)
+            } else {
+                m.end()
+            };
+        }
+        out.push_str(&self.buf[last.min(cut)..cut]);
+        self.start = cut;
+        out
+    }
+
+    /// Drop emitted text, keeping one character of look-behind context.
+    fn trim(&mut self) {
+        let context = self.buf[..self.start]
+            .chars()
+            .next_back()
+            .map_or(0, char::len_utf8);
+        let drop = self.start - context;
+        if drop == 0 {
+            return;
+        }
+        self.buf.drain(..drop);
+        self.start -= drop;
+        self.checked -= drop;
+        if let Some((_, walked)) = &mut self.walk {
+            *walked -= drop;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streamed(filters: &OutputFilters, pieces: &[&str]) -> String {
+        let mut stream = filters.stream();
+        let mut out: String = pieces.iter().map(|p| stream.push(p)).collect();
+        out.push_str(&stream.flush());
+        out
+    }
+
+    #[test]
+    fn test_builtin_filters() {
+        let filters = OutputFilters::new(
+            &[
+                BuiltinFilter::ThinkTags,
+                BuiltinFilter::ChatTemplate,
+                BuiltinFilter::Whitespace,
+            ],
+            &[],
+        );
+        let text = "<think>plan\nsteps</think>\n\nHello  \n\n\n\nworld<|im_end|>";
+        assert_eq!(filters.apply(text), "Hello\n\nworld");
+    }
+
+    #[test]
+    fn test_stream_matches_split_across_deltas() {
+        let filters = OutputFilters::new(
+            &[BuiltinFilter::ThinkTags, BuiltinFilter::ChatTemplate],
+            &[OutputRule::new(r"(\w+)@example\.com", "$1@…").unwrap()],
+        );
+        let text = "<think>hmm</think>Mail bob@example.com<|eot_id|> now";
+        let expected = filters.apply(text);
+        assert_eq!(expected, "Mail bob@… now");
+
+        // Every split point, and one byte at a time
+        for i in 0..=text.len() {
+            assert_eq!(streamed(&filters, &[&text[..i], &text[i..]]), expected);
+        }
+        let bytes: Vec<String> = text.chars().map(String::from).collect();
+        let pieces: Vec<&str> = bytes.iter().map(String::as_str).collect();
+        assert_eq!(streamed(&filters, &pieces), expected);
+    }
+
+    #[test]
+    fn test_stream_emits_eagerly() {
+        let filters = OutputFilters::new(&[BuiltinFilter::ThinkTags], &[]);
+        let mut stream = filters.stream();
+        assert_eq!(stream.push("Plain text"), "Plain text");
+        assert_eq!(stream.push(" then <thi"), " then ");
+        assert_eq!(stream.push("nk>secret"), "");
+        assert_eq!(stream.push("</think> done"), "done");
+        // An unclosed tag is released as-is when the text ends
+        assert_eq!(stream.push(" <think>never closed"), " ");
+        assert_eq!(stream.flush(), "<think>never closed");
+    }
+
+    #[test]
+    fn test_invalid_rule_is_config_error() {
+        let err = toml::from_str::<Rules>("rules = [{ pattern = \"(unclosed\" }]").unwrap_err();
+        assert!(err.to_string().contains("Invalid output rule"));
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Rules {
+        rules: Vec<OutputRule>,
+    }
+}
@@ -0,0 +1,824 @@
+//! Google Gemini `generateContent` types and translation to/from Anthropic.
+//!
+//! Backs the inbound `/v1beta/models/{model}:generateContent` endpoints: Gemini
+//! requests are translated into [`MessagesRequest`] so they go through the same
+//! routing and upstream translation as Anthropic requests, and the Anthropic
+//! response (or stream events) is translated back. The same wire types serve
+//! the Gemini upstream backend in [`super::gemini_backend`].
+//!
+//! Gemini function calls usually carry no id, so ids are minted for them and
+//! matched to `functionResponse` parts by function name, in call order.
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::anthropic_types::{
+    ContentBlock, Delta, ImageSource, Message, MessageContent, MessagesRequest, MessagesResponse,
+    ResponseContentBlock, Role, StreamEvent, SystemContent, Tool, ToolChoice, ToolChoiceAuto,
+    ToolChoiceSpecific, ToolResultContent,
+};
+
+/// `max_tokens` when the request doesn't set `maxOutputTokens`.
+const DEFAULT_MAX_TOKENS: u64 = 8192;
+
+// ---------------------------------------------------------------------------
+// Request types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentRequest {
+    #[serde(default)]
+    pub contents: Vec<GeminiContent>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "system_instruction"
+    )]
+    pub system_instruction: Option<GeminiContent>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<GeminiTool>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "tool_config"
+    )]
+    pub tool_config: Option<GeminiToolConfig>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "generation_config"
+    )]
+    pub generation_config: Option<GenerationConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeminiContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>, // "user", "model" (or legacy "function")
+    #[serde(default)]
+    pub parts: Vec<GeminiPart>,
+}
+
+/// One part of a content entry. Exactly one of the data fields is set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiPart {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "inline_data"
+    )]
+    pub inline_data: Option<InlineData>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "function_call"
+    )]
+    pub function_call: Option<FunctionCall>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "function_response"
+    )]
+    pub function_response: Option<FunctionResponse>,
+    /// Marks `text` as model reasoning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thought: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineData {
+    #[serde(alias = "mime_type")]
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub response: serde_json::Value,
+}
+
+/// A tool entry. Only function declarations are translated; built-in Gemini
+/// tools (`googleSearch`, `codeExecution`) are ignored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiTool {
+    #[serde(default, alias = "function_declarations")]
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionDeclaration {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "parameters_json_schema"
+    )]
+    pub parameters_json_schema: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiToolConfig {
+    #[serde(default, alias = "function_calling_config")]
+    pub function_calling_config: Option<FunctionCallingConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCallingConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>, // "AUTO", "ANY", "NONE"
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        alias = "allowed_function_names"
+    )]
+    pub allowed_function_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "top_p")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none", alias = "top_k")]
+    pub top_k: Option<u64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "max_output_tokens"
+    )]
+    pub max_output_tokens: Option<u64>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "stop_sequences"
+    )]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "response_mime_type"
+    )]
+    pub response_mime_type: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "response_schema"
+    )]
+    pub response_schema: Option<serde_json::Value>,
+}
+
+// ---------------------------------------------------------------------------
+// Response types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_metadata: Option<UsageMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    #[serde(default)]
+    pub content: GeminiContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default)]
+    pub index: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: u64,
+    #[serde(default)]
+    pub candidates_token_count: u64,
+    #[serde(default)]
+    pub total_token_count: u64,
+}
+
+/// Gemini error envelope: `{"error": {"code", "message", "status"}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiError {
+    pub error: GeminiErrorBody,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiErrorBody {
+    pub code: u16,
+    pub message: String,
+    pub status: String,
+}
+
+impl GeminiError {
+    pub fn new(code: u16, message: impl Into<String>) -> Self {
+        let status = match code {
+            400 => "INVALID_ARGUMENT",
+            401 => "UNAUTHENTICATED",
+            403 => "PERMISSION_DENIED",
+            404 => "NOT_FOUND",
+            429 => "RESOURCE_EXHAUSTED",
+            500 => "INTERNAL",
+            503 | 529 => "UNAVAILABLE",
+            504 => "DEADLINE_EXCEEDED",
+            _ => "UNKNOWN",
+        };
+        Self {
+            error: GeminiErrorBody {
+                code,
+                message: message.into(),
+                status: status.to_string(),
+            },
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Gemini → Anthropic
+// ---------------------------------------------------------------------------
+
+/// Translate a Gemini request for `model` into an Anthropic Messages request.
+#[must_use]
+pub fn gemini_to_anthropic(
+    req: &GenerateContentRequest,
+    model: &str,
+    stream: bool,
+) -> MessagesRequest {
+    let system = req.system_instruction.as_ref().and_then(|c| {
+        let text = part_texts(&c.parts);
+        (!text.is_empty()).then_some(SystemContent::Text(text))
+    });
+
+    let mut ids = CallIds::default();
+    let messages = req
+        .contents
+        .iter()
+        .map(|c| translate_content(c, &mut ids))
+        .filter(|m| !matches!(&m.content, MessageContent::Blocks(b) if b.is_empty()))
+        .collect();
+
+    let tools: Vec<Tool> = req
+        .tools
+        .iter()
+        .flat_map(|t| &t.function_declarations)
+        .map(|f| Tool {
+            name: f.name.clone(),
+            description: f.description.clone(),
+            input_schema: f
+                .parameters_json_schema
+                .clone()
+                .or_else(|| f.parameters.clone().map(normalize_schema))
+                .unwrap_or_else(|| serde_json::json!({"type": "object", "properties": {}})),
+            tool_type: None,
+            extra: HashMap::new(),
+        })
+        .collect();
+
+    let tool_choice = req
+        .tool_config
+        .as_ref()
+        .and_then(|c| c.function_calling_config.as_ref())
+        .and_then(translate_calling_config);
+
+    let gen = req.generation_config.clone().unwrap_or_default();
+    let mut extra = HashMap::new();
+    if gen.response_mime_type.as_deref() == Some("application/json") {
+        let output_format = match gen.response_schema {
+            Some(schema) => serde_json::json!({
+                "type": "json_schema",
+                "schema": normalize_schema(schema),
+            }),
+            None => serde_json::json!({"type": "json"}),
+        };
+        extra.insert("output_format".to_string(), output_format);
+    }
+
+    MessagesRequest {
+        model: model.to_string(),
+        max_tokens: gen.max_output_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        messages,
+        system,
+        stream: Some(stream),
+        temperature: gen.temperature,
+        top_p: gen.top_p,
+        top_k: gen.top_k,
+        tools: (!tools.is_empty()).then_some(tools),
+        tool_choice,
+        metadata: None,
+        stop_sequences: gen.stop_sequences,
+        thinking: None,
+        betas: None,
+        context_management: None,
+        reasoning_effort: None,
+        extra,
+    }
+}
+
+/// Mints ids for function calls and pairs responses with them by name.
+#[derive(Default)]
+struct CallIds {
+    next: usize,
+    pending: HashMap<String, VecDeque<String>>,
+}
+
+impl CallIds {
+    fn call(&mut self, call: &FunctionCall) -> String {
+        let id = call.id.clone().unwrap_or_else(|| {
+            self.next += 1;
+            format!("toolu_gemini_{}", self.next)
+        });
+        self.pending
+            .entry(call.name.clone())
+            .or_default()
+            .push_back(id.clone());
+        id
+    }
+
+    fn response(&mut self, resp: &FunctionResponse) -> String {
+        if let Some(ref id) = resp.id {
+            return id.clone();
+        }
+        self.pending
+            .get_mut(&resp.name)
+            .and_then(VecDeque::pop_front)
+            .unwrap_or_else(|| {
+                self.next += 1;
+                format!("toolu_gemini_{}", self.next)
+            })
+    }
+}
+
+fn translate_content(content: &GeminiContent, ids: &mut CallIds) -> Message {
+    let role = match content.role.as_deref() {
+        Some("model") => Role::Assistant,
+        _ => Role::User,
+    };
+
+    let mut blocks = Vec::new();
+    for part in &content.parts {
+        if let Some(ref text) = part.text {
+            // Earlier reasoning isn't replayed: there's no signature to send back
+            if part.thought != Some(true) && !text.is_empty() {
+                blocks.push(ContentBlock::Text {
+                    text: text.clone(),
+                    cache_control: None,
+                });
+            }
+        } else if let Some(ref data) = part.inline_data {
+            blocks.push(ContentBlock::Image {
+                source: ImageSource {
+                    source_type: "base64".to_string(),
+                    media_type: data.mime_type.clone(),
+                    data: data.data.clone(),
+                    url: None,
+                },
+            });
+        } else if let Some(ref call) = part.function_call {
+            blocks.push(ContentBlock::ToolUse {
+                id: ids.call(call),
+                name: call.name.clone(),
+                input: call.args.clone(),
+            });
+        } else if let Some(ref resp) = part.function_response {
+            let text = match &resp.response {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            blocks.push(ContentBlock::ToolResult {
+                tool_use_id: ids.response(resp),
+                content: Some(ToolResultContent::Text(text)),
+                is_error: None,
+                cache_control: None,
+            });
+        }
+    }
+
+    Message {
+        role,
+        content: MessageContent::Blocks(blocks),
+    }
+}
+
+fn translate_calling_config(config: &FunctionCallingConfig) -> Option<ToolChoice> {
+    let choice = |t: &str| {
+        Some(ToolChoice::Auto(ToolChoiceAuto {
+            choice_type: t.to_string(),
+        }))
+    };
+    match config.mode.as_deref().map(str::to_uppercase).as_deref() {
+        Some("ANY") => match config.allowed_function_names.as_slice() {
+            [name] => Some(ToolChoice::Specific(ToolChoiceSpecific {
+                choice_type: "tool".to_string(),
+                name: name.clone(),
+            })),
+            _ => choice("any"),
+        },
+        Some("NONE") => choice("none"),
+        Some("AUTO") => choice("auto"),
+        _ => None,
+    }
+}
+
+/// Gemini's OpenAPI-subset schemas use upper-case type names (`"OBJECT"`);
+/// JSON Schema needs them lower-case.
+fn normalize_schema(mut schema: serde_json::Value) -> serde_json::Value {
+    fn walk(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, v) in map.iter_mut() {
+                    if key == "type" {
+                        if let serde_json::Value::String(t) = v {
+                            *t = t.to_lowercase();
+                            continue;
+                        }
+                    }
+                    walk(v);
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(walk),
+            _ => {}
+        }
+    }
+    walk(&mut schema);
+    schema
+}
+
+fn part_texts(parts: &[GeminiPart]) -> String {
+    parts
+        .iter()
+        .filter_map(|p| p.text.as_deref())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// ---------------------------------------------------------------------------
+// Anthropic → Gemini
+// ---------------------------------------------------------------------------
+
+/// Translate an Anthropic response into a Gemini `generateContent` response.
+#[must_use]
+pub fn anthropic_to_gemini(resp: &MessagesResponse) -> GenerateContentResponse {
+    let parts = resp
+        .content
+        .iter()
+        .map(|block| match block {
+            ResponseContentBlock::Text { text } => GeminiPart {
+                text: Some(text.clone()),
+                ..GeminiPart::default()
+            },
+            ResponseContentBlock::Thinking { thinking, .. } => GeminiPart {
+                text: Some(thinking.clone()),
+                thought: Some(true),
+                ..GeminiPart::default()
+            },
+            ResponseContentBlock::ToolUse { id, name, input } => GeminiPart {
+                function_call: Some(FunctionCall {
+                    id: Some(id.clone()),
+                    name: name.clone(),
+                    args: input.clone(),
+                }),
+                ..GeminiPart::default()
+            },
+        })
+        .collect();
+
+    GenerateContentResponse {
+        candidates: vec![candidate(
+            parts,
+            map_stop_reason(resp.stop_reason.as_deref()),
+        )],
+        usage_metadata: Some(usage(resp.usage.input_tokens, resp.usage.output_tokens)),
+        model_version: Some(resp.model.clone()),
+    }
+}
+
+/// Map an Anthropic `stop_reason` to a Gemini `finishReason`.
+#[must_use]
+pub fn map_stop_reason(stop_reason: Option<&str>) -> Option<String> {
+    stop_reason.map(|r| match r {
+        "max_tokens" => "MAX_TOKENS".to_string(),
+        "refusal" => "SAFETY".to_string(),
+        _ => "STOP".to_string(),
+    })
+}
+
+fn candidate(parts: Vec<GeminiPart>, finish_reason: Option<String>) -> Candidate {
+    Candidate {
+        content: GeminiContent {
+            role: Some("model".to_string()),
+            parts,
+        },
+        finish_reason,
+        index: 0,
+    }
+}
+
+fn usage(input: u64, output: u64) -> UsageMetadata {
+    UsageMetadata {
+        prompt_token_count: input,
+        candidates_token_count: output,
+        total_token_count: input + output,
+    }
+}
+
+/// Converts Anthropic stream events into Gemini `streamGenerateContent` chunks.
+///
+/// Text deltas become text chunks as they arrive; tool calls are buffered until
+/// their block closes, since Gemini sends each function call whole.
+#[derive(Debug, Default)]
+pub struct GeminiStreamTranslator {
+    model: String,
+    input_tokens: u64,
+    tool_calls: HashMap<usize, (String, String, String)>,
+}
+
+impl GeminiStreamTranslator {
+    #[must_use]
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Translate one event. Returns `None` for events with nothing to emit.
+    pub fn process_event(&mut self, event: &StreamEvent) -> Option<GenerateContentResponse> {
+        match event {
+            StreamEvent::MessageStart { message } => {
+                self.input_tokens = message.usage.input_tokens;
+                None
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => match content_block {
+                ResponseContentBlock::Text { text } if !text.is_empty() => {
+                    Some(self.chunk(text_part(text), None))
+                }
+                ResponseContentBlock::ToolUse { id, name, .. } => {
+                    self.tool_calls
+                        .insert(*index, (id.clone(), name.clone(), String::new()));
+                    None
+                }
+                ResponseContentBlock::Thinking { thinking, .. } if !thinking.is_empty() => {
+                    Some(self.chunk(thought_part(thinking), None))
+                }
+                ResponseContentBlock::Text { .. } | ResponseContentBlock::Thinking { .. } => None,
+            },
+            StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                Delta::TextDelta { text } => Some(self.chunk(text_part(text), None)),
+                Delta::ThinkingDelta { thinking } => Some(self.chunk(thought_part(thinking), None)),
+                Delta::InputJsonDelta { partial_json } => {
+                    if let Some(call) = self.tool_calls.get_mut(index) {
+                        call.2.push_str(partial_json);
+                    }
+                    None
+                }
+            },
+            StreamEvent::ContentBlockStop { index } => {
+                let (id, name, json) = self.tool_calls.remove(index)?;
+                let args = serde_json::from_str(&json).unwrap_or_else(|_| serde_json::json!({}));
+                let part = GeminiPart {
+                    function_call: Some(FunctionCall {
+                        id: Some(id),
+                        name,
+                        args,
+                    }),
+                    ..GeminiPart::default()
+                };
+                Some(self.chunk(vec![part], None))
+            }
+            StreamEvent::MessageDelta {
+                delta, usage: u, ..
+            } => {
+                // Interim usage updates carry no stop reason
+                let finish_reason = map_stop_reason(delta.stop_reason.as_deref())?;
+                let mut chunk = self.chunk(Vec::new(), Some(finish_reason));
+                chunk.usage_metadata = Some(usage(self.input_tokens, u.output_tokens));
+                Some(chunk)
+            }
+            _ => None,
+        }
+    }
+
+    fn chunk(
+        &self,
+        parts: Vec<GeminiPart>,
+        finish_reason: Option<String>,
+    ) -> GenerateContentResponse {
+        GenerateContentResponse {
+            candidates: vec![candidate(parts, finish_reason)],
+            usage_metadata: None,
+            model_version: Some(self.model.clone()),
+        }
+    }
+}
+
+fn thought_part(text: &str) -> Vec<GeminiPart> {
+    vec![GeminiPart {
+        text: Some(text.to_string()),
+        thought: Some(true),
+        ..GeminiPart::default()
+    }]
+}
+
+fn text_part(text: &str) -> Vec<GeminiPart> {
+    vec![GeminiPart {
+        text: Some(text.to_string()),
+        ..GeminiPart::default()
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::anthropic_types::{DeltaUsage, MessageDeltaBody, Usage};
+
+    #[test]
+    fn test_gemini_request_to_anthropic() {
+        let req: GenerateContentRequest = serde_json::from_value(serde_json::json!({
+            "systemInstruction": {"parts": [{"text": "Be brief."}]},
+            "contents": [
+                {"role": "user", "parts": [{"text": "Weather in Paris?"}]},
+                {"role": "model", "parts": [
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}
+                ]},
+                {"role": "user", "parts": [
+                    {"functionResponse": {"name": "get_weather", "response": {"temp": 18}}}
+                ]}
+            ],
+            "tools": [{"functionDeclarations": [{
+                "name": "get_weather",
+                "parameters": {"type": "OBJECT", "properties": {"city": {"type": "STRING"}}}
+            }]}],
+            "toolConfig": {"functionCallingConfig": {"mode": "ANY", "allowedFunctionNames": ["get_weather"]}},
+            "generationConfig": {"maxOutputTokens": 256, "temperature": 0.2}
+        }))
+        .unwrap();
+
+        let out = gemini_to_anthropic(&req, "gemini-2.0-flash", false);
+
+        assert_eq!(out.model, "gemini-2.0-flash");
+        assert_eq!(out.max_tokens, 256);
+        assert!(matches!(out.system, Some(SystemContent::Text(ref t)) if t == "Be brief."));
+        assert_eq!(out.messages.len(), 3);
+        assert_eq!(out.messages[1].role, Role::Assistant);
+
+        let blocks = out.messages[1].content.blocks();
+        let ContentBlock::ToolUse { ref id, .. } = blocks[0] else {
+            panic!("expected tool_use");
+        };
+        let results = out.messages[2].content.blocks();
+        assert!(matches!(
+            results[0],
+            ContentBlock::ToolResult { ref tool_use_id, .. } if tool_use_id == id
+        ));
+
+        let tools = out.tools.unwrap();
+        assert_eq!(tools[0].input_schema["type"], "object");
+        assert_eq!(
+            tools[0].input_schema["properties"]["city"]["type"],
+            "string"
+        );
+        assert!(
+            matches!(out.tool_choice, Some(ToolChoice::Specific(ref t)) if t.name == "get_weather")
+        );
+    }
+
+    #[test]
+    fn test_anthropic_response_to_gemini() {
+        let resp = MessagesResponse {
+            id: "msg_1".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![
+                ResponseContentBlock::Text {
+                    text: "Checking.".to_string(),
+                },
+                ResponseContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "get_weather".to_string(),
+                    input: serde_json::json!({"city": "Paris"}),
+                },
+            ],
+            model: "gemini-2.0-flash".to_string(),
+            stop_reason: Some("tool_use".to_string()),
+            stop_sequence: None,
+            usage: Usage {
+                input_tokens: 10,
+                output_tokens: 5,
+                ..Usage::default()
+            },
+            extra: None,
+        };
+
+        let json = serde_json::to_value(anthropic_to_gemini(&resp)).unwrap();
+
+        assert_eq!(json["candidates"][0]["content"]["role"], "model");
+        assert_eq!(
+            json["candidates"][0]["content"]["parts"][0]["text"],
+            "Checking."
+        );
+        assert_eq!(
+            json["candidates"][0]["content"]["parts"][1]["functionCall"]["args"]["city"],
+            "Paris"
+        );
+        assert_eq!(json["candidates"][0]["finishReason"], "STOP");
+        assert_eq!(json["usageMetadata"]["totalTokenCount"], 15);
+    }
+
+    #[test]
+    fn test_stream_translator_buffers_tool_calls() {
+        let mut t = GeminiStreamTranslator::new("gemini-2.0-flash");
+
+        let text = t.process_event(&StreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: Delta::TextDelta {
+                text: "Hi".to_string(),
+            },
+        });
+        assert_eq!(
+            text.unwrap().candidates[0].content.parts[0].text.as_deref(),
+            Some("Hi")
+        );
+
+        assert!(t
+            .process_event(&StreamEvent::ContentBlockStart {
+                index: 1,
+                content_block: ResponseContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "get_weather".to_string(),
+                    input: serde_json::json!({}),
+                },
+            })
+            .is_none());
+        assert!(t
+            .process_event(&StreamEvent::ContentBlockDelta {
+                index: 1,
+                delta: Delta::InputJsonDelta {
+                    partial_json: "{\"city\":\"Paris\"}".to_string(),
+                },
+            })
+            .is_none());
+        let call = t
+            .process_event(&StreamEvent::ContentBlockStop { index: 1 })
+            .unwrap();
+        let fc = call.candidates[0].content.parts[0]
+            .function_call
+            .as_ref()
+            .unwrap();
+        assert_eq!(fc.args["city"], "Paris");
+
+        let done = t
+            .process_event(&StreamEvent::MessageDelta {
+                delta: MessageDeltaBody {
+                    stop_reason: Some("end_turn".to_string()),
+                    stop_sequence: None,
+                },
+                usage: DeltaUsage {
+                    output_tokens: 7,
+                    cache_read_input_tokens: None,
+                },
+                extra: None,
+            })
+            .unwrap();
+        assert_eq!(done.candidates[0].finish_reason.as_deref(), Some("STOP"));
+        assert_eq!(done.usage_metadata.unwrap().candidates_token_count, 7);
+    }
+}
@@ -0,0 +1,513 @@
+//! Adapter for Google Gemini (`generateContent`) as an upstream, on either the
+//! Gemini API or Vertex AI.
+//!
+//! Like the Cohere and Bedrock adapters, this converts the already-translated
+//! `OpenAI` request into Gemini's shape (`contents` of `user`/`model` turns,
+//! `systemInstruction`, function declarations) and Gemini responses and
+//! `streamGenerateContent?alt=sse` chunks back into `OpenAI` types. The wire
+//! types are shared with the inbound endpoints in [`super::gemini`].
+//!
+//! Function results are sent as `functionResponse` parts, which Gemini matches
+//! to calls by function name, so the name is looked up from the assistant turn
+//! that made the call. Calls that come back without an id get one minted.
+
+use std::collections::HashMap;
+
+use super::gemini::{
+    Candidate, FunctionCall, FunctionCallingConfig, FunctionDeclaration, FunctionResponse,
+    GeminiContent, GeminiPart, GeminiTool, GeminiToolConfig, GenerateContentRequest,
+    GenerateContentResponse, GenerationConfig, InlineData, UsageMetadata,
+};
+use super::openai_types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatContent, ChatMessage,
+    ChatToolCall, ChatToolCallFunction, ChatToolChoice, ChatUsage, Choice, ChoiceMessage,
+    ChunkChoice, ChunkDelta, ChunkToolCall, ChunkToolCallFunction, ContentPart, ResponseFormat,
+};
+
+/// Convert a translated `OpenAI` request into a Gemini `generateContent` request.
+///
+/// System messages become the `systemInstruction`; tool messages become
+/// `functionResponse` parts in a user turn; consecutive turns of the same role
+/// are merged, so all responses to one round of calls arrive together.
+#[must_use]
+pub fn openai_to_gemini(req: &ChatCompletionRequest) -> GenerateContentRequest {
+    let call_names: HashMap<&str, &str> = req
+        .messages
+        .iter()
+        .flat_map(|m| m.tool_calls.iter().flatten())
+        .map(|c| (c.id.as_str(), c.function.name.as_str()))
+        .collect();
+
+    let mut system = Vec::new();
+    let mut contents: Vec<GeminiContent> = Vec::new();
+
+    for msg in &req.messages {
+        let (role, parts) = match msg.role.as_str() {
+            "system" => {
+                system.extend(content_parts(msg));
+                continue;
+            }
+            "assistant" => {
+                let mut parts = content_parts(msg);
+                for call in msg.tool_calls.iter().flatten() {
+                    parts.push(GeminiPart {
+                        function_call: Some(FunctionCall {
+                            id: Some(call.id.clone()),
+                            name: call.function.name.clone(),
+                            args: serde_json::from_str(&call.function.arguments)
+                                .unwrap_or_else(|_| serde_json::json!({})),
+                        }),
+                        ..GeminiPart::default()
+                    });
+                }
+                ("model", parts)
+            }
+            "tool" => {
+                let id = msg.tool_call_id.clone().unwrap_or_default();
+                let name = call_names
+                    .get(id.as_str())
+                    .map(|n| (*n).to_string())
+                    .or_else(|| msg.name.clone())
+                    .unwrap_or_default();
+                let output: String = content_parts(msg)
+                    .into_iter()
+                    .filter_map(|p| p.text)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let part = GeminiPart {
+                    function_response: Some(FunctionResponse {
+                        id: Some(id),
+                        name,
+                        response: serde_json::json!({ "content": output }),
+                    }),
+                    ..GeminiPart::default()
+                };
+                ("user", vec![part])
+            }
+            _ => ("user", content_parts(msg)),
+        };
+        if parts.is_empty() {
+            continue;
+        }
+        match contents.last_mut() {
+            Some(last) if last.role.as_deref() == Some(role) => last.parts.extend(parts),
+            _ => contents.push(GeminiContent {
+                role: Some(role.to_string()),
+                parts,
+            }),
+        }
+    }
+
+    let json_output = matches!(
+        req.response_format,
+        Some(ResponseFormat::JsonObject { .. } | ResponseFormat::JsonSchema { .. })
+    );
+    let generation_config = GenerationConfig {
+        temperature: req.temperature,
+        top_p: req.top_p,
+        top_k: None,
+        max_output_tokens: req.max_tokens,
+        stop_sequences: req.stop.clone(),
+        response_mime_type: json_output.then(|| "application/json".to_string()),
+        response_schema: None,
+    };
+
+    let declarations: Vec<FunctionDeclaration> = req
+        .tools
+        .iter()
+        .flatten()
+        .map(|t| FunctionDeclaration {
+            name: t.function.name.clone(),
+            description: t.function.description.as_deref().map(str::to_string),
+            parameters: None,
+            // Full JSON Schema; `parameters` only takes Gemini's OpenAPI subset
+            parameters_json_schema: Some(t.function.parameters.clone().into_owned()),
+        })
+        .collect();
+    let tool_config = req
+        .tool_choice
+        .as_ref()
+        .filter(|_| !declarations.is_empty())
+        .map(|choice| GeminiToolConfig {
+            function_calling_config: Some(calling_config(choice)),
+        });
+
+    GenerateContentRequest {
+        contents,
+        system_instruction: (!system.is_empty()).then_some(GeminiContent {
+            role: None,
+            parts: system,
+        }),
+        tools: if declarations.is_empty() {
+            Vec::new()
+        } else {
+            vec![GeminiTool {
+                function_declarations: declarations,
+            }]
+        },
+        tool_config,
+        generation_config: Some(generation_config),
+    }
+}
+
+/// Convert a Gemini response into an `OpenAI` chat completion.
+#[must_use]
+pub fn gemini_to_openai(resp: &GenerateContentResponse) -> ChatCompletionResponse {
+    let candidate = resp.candidates.first();
+    let parts = candidate.map_or(&[][..], |c| c.content.parts.as_slice());
+
+    let text: String = parts
+        .iter()
+        .filter(|p| p.thought != Some(true))
+        .filter_map(|p| p.text.as_deref())
+        .collect();
+    let reasoning: String = parts
+        .iter()
+        .filter(|p| p.thought == Some(true))
+        .filter_map(|p| p.text.as_deref())
+        .collect();
+    let tool_calls: Vec<ChatToolCall> = parts
+        .iter()
+        .filter_map(|p| p.function_call.as_ref())
+        .map(|call| ChatToolCall {
+            id: call_id(call),
+            call_type: "function".to_string(),
+            function: ChatToolCallFunction {
+                name: call.name.clone(),
+                arguments: call.args.to_string(),
+            },
+        })
+        .collect();
+
+    let finish_reason = map_finish_reason(candidate, !tool_calls.is_empty());
+    ChatCompletionResponse {
+        id: String::new(),
+        object: "chat.completion".to_string(),
+        created: 0,
+        model: resp.model_version.clone().unwrap_or_default(),
+        choices: vec![Choice {
+            index: 0,
+            message: ChoiceMessage {
+                role: "assistant".to_string(),
+                content: Some(text),
+                reasoning_content: (!reasoning.is_empty()).then_some(reasoning),
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            },
+            finish_reason: Some(finish_reason),
+            logprobs: None,
+        }],
+        usage: resp.usage_metadata.as_ref().map(chat_usage),
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+/// Map a candidate's Gemini `finishReason` to the `OpenAI` `finish_reason`.
+/// Gemini reports `STOP` after function calls too.
+fn map_finish_reason(candidate: Option<&Candidate>, called_tools: bool) -> String {
+    match candidate.and_then(|c| c.finish_reason.as_deref()) {
+        _ if called_tools => "tool_calls",
+        Some("MAX_TOKENS") => "length",
+        Some("SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII") => {
+            "content_filter"
+        }
+        _ => "stop",
+    }
+    .to_string()
+}
+
+/// Translates `streamGenerateContent` chunks into `OpenAI` chunks.
+///
+/// Gemini sends each function call whole, so a call becomes one tool-call
+/// chunk carrying its full arguments. Usage is cumulative on every chunk; the
+/// one with a `finishReason` is final.
+#[derive(Debug, Default)]
+pub struct GeminiStreamState {
+    tool_calls: u64,
+}
+
+impl GeminiStreamState {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Translate one streamed response into zero or more `OpenAI` chunks.
+    pub fn process(&mut self, resp: &GenerateContentResponse) -> Vec<ChatCompletionChunk> {
+        let Some(candidate) = resp.candidates.first() else {
+            return Vec::new();
+        };
+        let mut chunks = Vec::new();
+        for part in &candidate.content.parts {
+            if let Some(call) = &part.function_call {
+                let index = self.tool_calls;
+                self.tool_calls += 1;
+                let id = call_id(call);
+                chunks.push(chunk(
+                    ChunkDelta {
+                        tool_calls: Some(vec![ChunkToolCall {
+                            index,
+                            id: Some(id),
+                            call_type: Some("function".to_string()),
+                            function: Some(ChunkToolCallFunction {
+                                name: Some(call.name.clone()),
+                                arguments: Some(call.args.to_string()),
+                            }),
+                        }]),
+                        ..ChunkDelta::default()
+                    },
+                    None,
+                    None,
+                ));
+            } else if let Some(text) = part.text.as_ref().filter(|t| !t.is_empty()) {
+                let delta = if part.thought == Some(true) {
+                    ChunkDelta {
+                        reasoning_content: Some(text.clone()),
+                        ..ChunkDelta::default()
+                    }
+                } else {
+                    ChunkDelta {
+                        content: Some(text.clone()),
+                        ..ChunkDelta::default()
+                    }
+                };
+                chunks.push(chunk(delta, None, None));
+            }
+        }
+        if candidate.finish_reason.is_some() {
+            chunks.push(chunk(
+                ChunkDelta::default(),
+                Some(map_finish_reason(Some(candidate), self.tool_calls > 0)),
+                resp.usage_metadata.as_ref().map(chat_usage),
+            ));
+        }
+        chunks
+    }
+}
+
+fn chunk(
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+    usage: Option<ChatUsage>,
+) -> ChatCompletionChunk {
+    ChatCompletionChunk {
+        id: String::new(),
+        object: "chat.completion.chunk".to_string(),
+        created: 0,
+        model: String::new(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+            logprobs: None,
+        }],
+        usage,
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+fn chat_usage(usage: &UsageMetadata) -> ChatUsage {
+    ChatUsage {
+        prompt_tokens: usage.prompt_token_count,
+        completion_tokens: usage.candidates_token_count,
+        total_tokens: usage.total_token_count,
+        ..ChatUsage::default()
+    }
+}
+
+fn call_id(call: &FunctionCall) -> String {
+    call.id
+        .clone()
+        .unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple()))
+}
+
+fn calling_config(choice: &ChatToolChoice) -> FunctionCallingConfig {
+    let (mode, allowed) = match choice {
+        ChatToolChoice::String(s) if s == "required" => ("ANY", Vec::new()),
+        ChatToolChoice::String(s) if s == "none" => ("NONE", Vec::new()),
+        ChatToolChoice::String(_) => ("AUTO", Vec::new()),
+        ChatToolChoice::Specific(s) => ("ANY", vec![s.function.name.clone()]),
+    };
+    FunctionCallingConfig {
+        mode: Some(mode.to_string()),
+        allowed_function_names: allowed,
+    }
+}
+
+/// Text and inline image parts for a message's content. Images must be data
+/// URLs; Gemini only fetches Cloud Storage URIs, so others are dropped.
+fn content_parts(msg: &ChatMessage) -> Vec<GeminiPart> {
+    let text = |text: &str| {
+        (!text.is_empty()).then(|| GeminiPart {
+            text: Some(text.to_string()),
+            ..GeminiPart::default()
+        })
+    };
+    match &msg.content {
+        Some(ChatContent::Text(t)) => text(t).into_iter().collect(),
+        Some(ChatContent::Parts(parts)) => parts
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::Text { text: t } => text(t),
+                ContentPart::ImageUrl { image_url } => inline_data(&image_url.url),
+                ContentPart::File { file } => inline_data(&file.file_data),
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// An inline part from a base64 `data:` URL (an image, or a PDF).
+fn inline_data(url: &str) -> Option<GeminiPart> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    Some(GeminiPart {
+        inline_data: Some(InlineData {
+            mime_type: header.strip_suffix(";base64")?.to_string(),
+            data: data.to_string(),
+        }),
+        ..GeminiPart::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::openai_types::{ChatFunction, ChatTool};
+    use std::borrow::Cow;
+
+    fn msg(role: &str, content: Option<&str>) -> ChatMessage<'static> {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.map(|c| ChatContent::Text(c.to_string().into())),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_tool_conversation_to_gemini() {
+        let mut assistant = msg("assistant", None);
+        assistant.tool_calls = Some(vec![ChatToolCall {
+            id: "toolu_1".to_string(),
+            call_type: "function".to_string(),
+            function: ChatToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: "{\"city\":\"Paris\"}".to_string(),
+            },
+        }]);
+        let mut tool = msg("tool", Some("rainy"));
+        tool.tool_call_id = Some("toolu_1".to_string());
+
+        let req = ChatCompletionRequest {
+            model: "gemini-2.5-pro".to_string(),
+            messages: vec![
+                msg("system", Some("Be brief")),
+                msg("user", Some("Weather in Paris?")),
+                assistant,
+                tool,
+            ],
+            max_tokens: Some(256),
+            temperature: Some(0.2),
+            top_p: None,
+            stream: None,
+            stream_options: None,
+            tools: Some(vec![ChatTool {
+                tool_type: "function".to_string(),
+                function: ChatFunction {
+                    name: "get_weather".to_string(),
+                    description: Some("Current weather".into()),
+                    parameters: Cow::Owned(
+                        serde_json::json!({"type": "object", "additionalProperties": false}),
+                    ),
+                },
+            }]),
+            tool_choice: Some(ChatToolChoice::String("required".to_string())),
+            stop: None,
+            user: None,
+            reasoning_effort: None,
+            search_parameters: None,
+            response_format: None,
+            prompt_cache_key: None,
+            extra: serde_json::Map::new(),
+        };
+
+        let gemini = serde_json::to_value(openai_to_gemini(&req)).unwrap();
+
+        assert_eq!(gemini["systemInstruction"]["parts"][0]["text"], "Be brief");
+        let contents = gemini["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(
+            contents[1]["parts"][0]["functionCall"]["args"]["city"],
+            "Paris"
+        );
+        let response = &contents[2]["parts"][0]["functionResponse"];
+        assert_eq!(response["name"], "get_weather");
+        assert_eq!(response["response"]["content"], "rainy");
+        assert_eq!(gemini["generationConfig"]["maxOutputTokens"], 256);
+        assert!(gemini["generationConfig"].get("topP").is_none());
+        let declaration = &gemini["tools"][0]["functionDeclarations"][0];
+        assert_eq!(
+            declaration["parametersJsonSchema"]["additionalProperties"],
+            false
+        );
+        assert_eq!(gemini["toolConfig"]["functionCallingConfig"]["mode"], "ANY");
+    }
+
+    #[test]
+    fn test_gemini_response_to_openai() {
+        let resp: GenerateContentResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "Thinking it over.", "thought": true},
+                    {"text": "Let me look."},
+                    {"functionCall": {"name": "search", "args": {"q": "rust"}}}
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 5, "totalTokenCount": 25},
+            "modelVersion": "gemini-2.5-flash"
+        }))
+        .unwrap();
+
+        let openai = gemini_to_openai(&resp);
+        let choice = &openai.choices[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(choice.message.content.as_deref(), Some("Let me look."));
+        assert_eq!(
+            choice.message.reasoning_content.as_deref(),
+            Some("Thinking it over.")
+        );
+        let call = &choice.message.tool_calls.as_ref().unwrap()[0];
+        assert!(call.id.starts_with("call_"));
+        assert_eq!(call.function.arguments, "{\"q\":\"rust\"}");
+        assert_eq!(openai.usage.unwrap().completion_tokens, 5);
+    }
+
+    #[test]
+    fn test_stream_chunks() {
+        let responses: Vec<GenerateContentResponse> = [
+            serde_json::json!({"candidates": [{"content": {"role": "model", "parts": [{"text": "Bon"}]}}]}),
+            serde_json::json!({
+                "candidates": [{"content": {"role": "model", "parts": [{"text": "jour"}]}, "finishReason": "MAX_TOKENS"}],
+                "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 2, "totalTokenCount": 6}
+            }),
+        ]
+        .into_iter()
+        .map(|v| serde_json::from_value(v).unwrap())
+        .collect();
+
+        let mut state = GeminiStreamState::new();
+        let chunks: Vec<_> = responses.iter().flat_map(|r| state.process(r)).collect();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].choices[0].delta.content.as_deref(), Some("jour"));
+        assert_eq!(
+            chunks[2].choices[0].finish_reason.as_deref(),
+            Some("length")
+        );
+        assert_eq!(chunks[2].usage.as_ref().unwrap().total_tokens, 6);
+    }
+}
@@ -0,0 +1,121 @@
+//! xAI Grok extensions to the `OpenAI` Chat Completions format.
+//!
+//! Grok accepts two extra request fields:
+//!
+//! - `reasoning_effort` (`"low"` / `"high"`) on its reasoning models. Only
+//!   injected when configured, since non-reasoning Grok models reject it.
+//! - `search_parameters` for Live Search. An Anthropic `web_search` server tool
+//!   in the request maps onto it (`max_uses` → `max_search_results`, domain
+//!   filters → web source filters); otherwise the configured default is sent.
+
+use super::anthropic_types::{MessagesRequest, Tool};
+use super::openai_types::{ChatCompletionRequest, SearchParameters};
+
+/// Add Grok's extension fields to a translated request.
+pub fn apply_extensions(
+    req: &MessagesRequest,
+    out: &mut ChatCompletionRequest,
+    reasoning_effort: Option<&str>,
+    search: Option<&SearchParameters>,
+) {
+    out.reasoning_effort = reasoning_effort.map(str::to_string);
+
+    let web_search = req
+        .tools
+        .as_deref()
+        .and_then(|tools| tools.iter().find(|t| t.is_web_search()));
+
+    out.search_parameters = match web_search {
+        Some(tool) => Some(web_search_parameters(tool, search)),
+        None => search.cloned(),
+    };
+}
+
+/// Live Search settings for an Anthropic `web_search` tool, layered over the
+/// configured defaults.
+fn web_search_parameters(tool: &Tool, defaults: Option<&SearchParameters>) -> SearchParameters {
+    let mut params = defaults.cloned().unwrap_or_default();
+    if matches!(params.mode.as_deref(), None | Some("off")) {
+        params.mode = Some("auto".to_string());
+    }
+    if let Some(max_uses) = tool
+        .extra
+        .get("max_uses")
+        .and_then(serde_json::Value::as_u64)
+    {
+        params.max_search_results = u32::try_from(max_uses).ok();
+    }
+
+    let allowed = tool.extra.get("allowed_domains").filter(|v| !v.is_null());
+    let blocked = tool.extra.get("blocked_domains").filter(|v| !v.is_null());
+    if allowed.is_some() || blocked.is_some() {
+        let mut source = serde_json::json!({ "type": "web" });
+        if let Some(domains) = allowed {
+            source["allowed_websites"] = domains.clone();
+        }
+        if let Some(domains) = blocked {
+            source["excluded_websites"] = domains.clone();
+        }
+        params.sources = Some(vec![source]);
+    }
+    if params.return_citations.is_none() {
+        params.return_citations = Some(true);
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::request::anthropic_to_openai_for_model;
+
+    #[test]
+    fn test_web_search_tool_maps_to_search_parameters() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "What happened today?"}],
+            "tools": [{
+                "type": "web_search_20250305",
+                "name": "web_search",
+                "max_uses": 5,
+                "allowed_domains": ["reuters.com"]
+            }]
+        }))
+        .unwrap();
+
+        let mut out = anthropic_to_openai_for_model(&req, "grok-3-mini");
+        assert!(out.tools.is_none(), "server tools are not functions");
+
+        apply_extensions(&req, &mut out, Some("high"), None);
+
+        assert_eq!(out.reasoning_effort.as_deref(), Some("high"));
+        let search = out.search_parameters.unwrap();
+        assert_eq!(search.mode.as_deref(), Some("auto"));
+        assert_eq!(search.max_search_results, Some(5));
+        assert_eq!(
+            search.sources.unwrap()[0]["allowed_websites"],
+            serde_json::json!(["reuters.com"])
+        );
+    }
+
+    #[test]
+    fn test_configured_search_defaults_without_tool() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Hi"}]
+        }))
+        .unwrap();
+        let defaults = SearchParameters {
+            mode: Some("on".to_string()),
+            ..SearchParameters::default()
+        };
+
+        let mut out = anthropic_to_openai_for_model(&req, "grok-4");
+        apply_extensions(&req, &mut out, None, Some(&defaults));
+
+        assert!(out.reasoning_effort.is_none());
+        assert_eq!(out.search_parameters, Some(defaults));
+    }
+}
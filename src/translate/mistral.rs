@@ -0,0 +1,125 @@
+//! Mistral La Plateforme quirks.
+//!
+//! Mistral speaks the `OpenAI` Chat Completions format with two differences that
+//! break translated requests:
+//!
+//! - Tool call ids must be exactly 9 characters of `[a-zA-Z0-9]`. Ids that
+//!   Claude Code sends back from earlier turns (`toolu_...`, or ids minted by
+//!   another provider before a fallback) are rejected with a 400, so they are
+//!   rewritten. The rewrite is a hash of the original id, which keeps an
+//!   assistant `tool_calls` entry and its `tool` result message paired.
+//! - Fields outside Mistral's schema (`user`, `stream_options`) are rejected
+//!   with a 422 rather than ignored.
+
+use crate::translate::openai_types::ChatCompletionRequest;
+
+const ID_LEN: usize = 9;
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Rewrite a translated request so Mistral accepts it.
+pub fn apply_quirks(req: &mut ChatCompletionRequest) {
+    req.user = None;
+    req.stream_options = None;
+
+    for msg in &mut req.messages {
+        if let Some(ref mut calls) = msg.tool_calls {
+            for call in calls {
+                call.id = tool_call_id(&call.id);
+            }
+        }
+        if let Some(ref mut id) = msg.tool_call_id {
+            *id = tool_call_id(id);
+        }
+    }
+}
+
+/// Map an arbitrary tool call id to a Mistral-compatible one. Ids that are
+/// already valid are kept as-is.
+#[must_use]
+pub fn tool_call_id(id: &str) -> String {
+    if id.len() == ID_LEN && id.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return id.to_string();
+    }
+
+    // FNV-1a: stable across runs and Rust versions, unlike `DefaultHasher`.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in id.bytes() {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    let base = ALPHABET.len() as u64;
+    (0..ID_LEN)
+        .map(|_| {
+            let c = ALPHABET[(hash % base) as usize] as char;
+            hash /= base;
+            c
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::openai_types::{
+        ChatContent, ChatMessage, ChatToolCall, ChatToolCallFunction, StreamOptions,
+    };
+
+    #[test]
+    fn test_tool_call_ids_remapped_consistently() {
+        let mut req = ChatCompletionRequest {
+            model: "mistral-large-latest".to_string(),
+            messages: vec![
+                ChatMessage {
+                    role: "assistant".to_string(),
+                    content: None,
+                    tool_calls: Some(vec![ChatToolCall {
+                        id: "toolu_01A09q90qw90lq917835lq9".to_string(),
+                        call_type: "function".to_string(),
+                        function: ChatToolCallFunction {
+                            name: "get_weather".to_string(),
+                            arguments: "{}".to_string(),
+                        },
+                    }]),
+                    tool_call_id: None,
+                    name: None,
+                },
+                ChatMessage {
+                    role: "tool".to_string(),
+                    content: Some(ChatContent::Text("15 degrees".into())),
+                    tool_calls: None,
+                    tool_call_id: Some("toolu_01A09q90qw90lq917835lq9".to_string()),
+                    name: None,
+                },
+            ],
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            stream: Some(true),
+            stream_options: Some(StreamOptions {
+                include_usage: true,
+            }),
+            tools: None,
+            tool_choice: None,
+            stop: None,
+            user: Some("user-1".to_string()),
+            reasoning_effort: None,
+            search_parameters: None,
+            response_format: None,
+            prompt_cache_key: None,
+            extra: serde_json::Map::new(),
+        };
+
+        apply_quirks(&mut req);
+
+        let call_id = &req.messages[0].tool_calls.as_ref().unwrap()[0].id;
+        assert_eq!(call_id.len(), 9);
+        assert!(call_id.bytes().all(|b| b.is_ascii_alphanumeric()));
+        assert_eq!(req.messages[1].tool_call_id.as_ref(), Some(call_id));
+        assert!(req.user.is_none());
+        assert!(req.stream_options.is_none());
+
+        assert_eq!(tool_call_id("D681PevKs"), "D681PevKs");
+        assert_ne!(tool_call_id("toolu_a"), tool_call_id("toolu_b"));
+    }
+}
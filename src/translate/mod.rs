@@ -4,7 +4,29 @@
 //! between the two API formats. All translation functions are pure (no I/O).
 
 pub mod anthropic_types;
+pub mod bedrock;
+pub mod cache;
+pub mod chat_completions;
+pub mod cohere;
+pub mod completions;
+pub mod documents;
+pub mod filters;
+pub mod gemini;
+pub mod gemini_backend;
+pub mod grok;
+pub mod mistral;
+pub mod openai_responses;
 pub mod openai_types;
+pub mod pdf;
+pub mod prefix_cache;
+pub mod prompted_tools;
+pub mod raw;
 pub mod request;
 pub mod response;
+pub mod smoothing;
+pub mod sse;
+pub mod stop_tokens;
+pub mod stream_check;
 pub mod streaming;
+pub mod think;
+pub mod tool_args;
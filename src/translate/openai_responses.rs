@@ -0,0 +1,760 @@
+//! Adapter for the [`OpenAI` Responses API](https://platform.openai.com/docs/api-reference/responses)
+//! (`POST /responses`), used with `format = "openai-responses"`.
+//!
+//! The o-series and gpt-5 models are only fully featured on this API. Like the
+//! Cohere and Gemini adapters, this converts the already-translated `OpenAI`
+//! request into Responses `input` items (messages, `function_call`s and
+//! `function_call_output`s, with system messages as `instructions`), and
+//! responses and streaming events back into `OpenAI` types.
+//!
+//! Reasoning is requested with a summary; `reasoning` output items (their
+//! summary, or the raw reasoning text where a model exposes it) come back as
+//! `reasoning_content`, so they can become thinking blocks. Requests are sent
+//! with `store: false`: nothing is kept upstream, and reasoning isn't carried
+//! from one turn to the next. Stop sequences aren't supported by the API and
+//! are dropped.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::openai_types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatContent, ChatMessage,
+    ChatToolCall, ChatToolCallFunction, ChatToolChoice, ChatUsage, Choice, ChoiceMessage,
+    ChunkChoice, ChunkDelta, ChunkToolCall, ChunkToolCallFunction, ContentPart,
+    PromptTokensDetails, ResponseFormat,
+};
+
+// ---------------------------------------------------------------------------
+// Request types (what we send TO the Responses API)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponsesRequest {
+    pub model: String,
+    pub input: Vec<InputItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ResponsesTool>,
+    /// `"auto"`, `"required"`, `"none"` or `{"type": "function", "name": ...}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<ReasoningConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<TextConfig>,
+    pub store: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputItem {
+    Message {
+        role: String,
+        content: Vec<InputContent>,
+    },
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    FunctionCallOutput {
+        call_id: String,
+        output: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputContent {
+    InputText {
+        text: String,
+    },
+    InputImage {
+        image_url: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+    InputFile {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        file_data: String,
+    },
+    /// Text of an earlier assistant turn.
+    OutputText {
+        text: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponsesTool {
+    #[serde(rename = "type")]
+    pub tool_type: String, // always "function"
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReasoningConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effort: Option<String>,
+    /// `"auto"`, `"concise"` or `"detailed"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// Output format: `{"type": "json_schema", "name", "schema", "strict"}`,
+/// `{"type": "json_object"}` or `{"type": "text"}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextConfig {
+    pub format: serde_json::Value,
+}
+
+// ---------------------------------------------------------------------------
+// Response types (what we receive FROM the Responses API)
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponsesResponse {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub model: String,
+    /// `"completed"`, `"incomplete"`, `"failed"`, ...
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub incomplete_details: Option<IncompleteDetails>,
+    #[serde(default)]
+    pub output: Vec<OutputItem>,
+    #[serde(default)]
+    pub usage: Option<ResponsesUsage>,
+    #[serde(default)]
+    pub error: Option<ResponsesError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncompleteDetails {
+    /// `"max_output_tokens"` or `"content_filter"`.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputItem {
+    Message {
+        #[serde(default)]
+        content: Vec<OutputContent>,
+    },
+    Reasoning {
+        #[serde(default)]
+        summary: Vec<ReasoningText>,
+        /// Raw reasoning, from models that expose it.
+        #[serde(default)]
+        content: Vec<ReasoningText>,
+    },
+    FunctionCall {
+        call_id: String,
+        name: String,
+        #[serde(default)]
+        arguments: String,
+    },
+    /// Built-in tool calls and anything newer.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputContent {
+    OutputText {
+        text: String,
+    },
+    Refusal {
+        refusal: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// A `summary_text` or `reasoning_text` part of a reasoning item.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReasoningText {
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponsesUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+    #[serde(default)]
+    pub input_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponsesError {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Server-sent events of a streamed response, by their `type`. Events without
+/// anything to translate (part and item boundaries, `*.done` events carrying
+/// text already streamed) are `Other`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResponsesStreamEvent {
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded { output_index: u64, item: OutputItem },
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta { delta: String },
+    #[serde(rename = "response.refusal.delta")]
+    RefusalDelta { delta: String },
+    #[serde(rename = "response.reasoning_summary_text.delta")]
+    ReasoningSummaryTextDelta { delta: String },
+    #[serde(rename = "response.reasoning_summary_part.added")]
+    ReasoningSummaryPartAdded { summary_index: u64 },
+    #[serde(rename = "response.reasoning_text.delta")]
+    ReasoningTextDelta { delta: String },
+    #[serde(rename = "response.function_call_arguments.delta")]
+    FunctionCallArgumentsDelta { output_index: u64, delta: String },
+    #[serde(rename = "response.completed")]
+    Completed { response: ResponsesResponse },
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: ResponsesResponse },
+    #[serde(rename = "response.failed")]
+    Failed { response: ResponsesResponse },
+    #[serde(rename = "error")]
+    Error {
+        #[serde(default)]
+        message: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+// ---------------------------------------------------------------------------
+// Translation
+// ---------------------------------------------------------------------------
+
+/// Convert a translated `OpenAI` request into a Responses API request.
+#[must_use]
+pub fn openai_to_responses(req: &ChatCompletionRequest) -> ResponsesRequest {
+    let mut instructions = Vec::new();
+    let mut input = Vec::new();
+
+    for msg in &req.messages {
+        match msg.role.as_str() {
+            "system" | "developer" => instructions.push(content_text(msg)),
+            "assistant" => {
+                let text = content_text(msg);
+                if !text.is_empty() {
+                    input.push(InputItem::Message {
+                        role: "assistant".to_string(),
+                        content: vec![InputContent::OutputText { text }],
+                    });
+                }
+                for call in msg.tool_calls.iter().flatten() {
+                    input.push(InputItem::FunctionCall {
+                        call_id: call.id.clone(),
+                        name: call.function.name.clone(),
+                        arguments: call.function.arguments.clone(),
+                    });
+                }
+            }
+            "tool" => input.push(InputItem::FunctionCallOutput {
+                call_id: msg.tool_call_id.clone().unwrap_or_default(),
+                output: content_text(msg),
+            }),
+            role => input.push(InputItem::Message {
+                role: role.to_string(),
+                content: input_content(msg),
+            }),
+        }
+    }
+
+    let tools = req
+        .tools
+        .iter()
+        .flatten()
+        .map(|t| ResponsesTool {
+            tool_type: "function".to_string(),
+            name: t.function.name.clone(),
+            description: t.function.description.as_deref().map(str::to_string),
+            parameters: t.function.parameters.clone().into_owned(),
+        })
+        .collect();
+
+    let tool_choice = req.tool_choice.as_ref().map(|choice| match choice {
+        ChatToolChoice::String(mode) => serde_json::Value::String(mode.clone()),
+        ChatToolChoice::Specific(specific) => {
+            serde_json::json!({"type": "function", "name": specific.function.name})
+        }
+    });
+
+    ResponsesRequest {
+        model: req.model.clone(),
+        input,
+        instructions: (!instructions.is_empty()).then(|| instructions.join("\n\n")),
+        max_output_tokens: req.max_tokens,
+        temperature: req.temperature,
+        top_p: req.top_p,
+        stream: req.stream,
+        tools,
+        tool_choice,
+        reasoning: req.reasoning_effort.as_ref().map(|effort| ReasoningConfig {
+            effort: Some(effort.clone()),
+            summary: Some("auto".to_string()),
+        }),
+        text: req
+            .response_format
+            .as_ref()
+            .and_then(text_format)
+            .map(|format| TextConfig { format }),
+        store: false,
+        user: req.user.clone(),
+        prompt_cache_key: req.prompt_cache_key.clone(),
+    }
+}
+
+/// The Responses `text.format` for a `response_format`. Grammars have no
+/// equivalent.
+fn text_format(format: &ResponseFormat) -> Option<serde_json::Value> {
+    match format {
+        ResponseFormat::Text => Some(serde_json::json!({"type": "text"})),
+        ResponseFormat::JsonObject { .. } => Some(serde_json::json!({"type": "json_object"})),
+        ResponseFormat::JsonSchema { json_schema } => {
+            let mut format = serde_json::json!({
+                "type": "json_schema",
+                "name": json_schema.name,
+                "schema": json_schema.schema,
+            });
+            if let Some(strict) = json_schema.strict {
+                format["strict"] = serde_json::Value::Bool(strict);
+            }
+            Some(format)
+        }
+        ResponseFormat::Grammar { .. } => None,
+    }
+}
+
+/// The Responses reasoning effort for an Anthropic extended-thinking budget.
+#[must_use]
+pub fn effort_for_budget(budget_tokens: u64) -> &'static str {
+    match budget_tokens {
+        0..=4095 => "low",
+        4096..=16383 => "medium",
+        _ => "high",
+    }
+}
+
+/// Convert a Responses API response into an `OpenAI` chat completion.
+#[must_use]
+pub fn responses_to_openai(resp: &ResponsesResponse) -> ChatCompletionResponse {
+    let mut text = String::new();
+    let mut reasoning = Vec::new();
+    let mut tool_calls = Vec::new();
+    for item in &resp.output {
+        match item {
+            OutputItem::Message { content } => {
+                for part in content {
+                    match part {
+                        OutputContent::OutputText { text: t } => text.push_str(t),
+                        OutputContent::Refusal { refusal } => text.push_str(refusal),
+                        OutputContent::Other => {}
+                    }
+                }
+            }
+            OutputItem::Reasoning { summary, content } => {
+                let parts = if summary.is_empty() { content } else { summary };
+                reasoning.extend(parts.iter().map(|p| p.text.as_str()));
+            }
+            OutputItem::FunctionCall {
+                call_id,
+                name,
+                arguments,
+            } => tool_calls.push(ChatToolCall {
+                id: call_id.clone(),
+                call_type: "function".to_string(),
+                function: ChatToolCallFunction {
+                    name: name.clone(),
+                    arguments: arguments.clone(),
+                },
+            }),
+            OutputItem::Other => {}
+        }
+    }
+
+    let reasoning = reasoning.join("\n\n");
+    ChatCompletionResponse {
+        id: resp.id.clone(),
+        object: "chat.completion".to_string(),
+        created: 0,
+        model: resp.model.clone(),
+        choices: vec![Choice {
+            index: 0,
+            message: ChoiceMessage {
+                role: "assistant".to_string(),
+                content: Some(text),
+                reasoning_content: (!reasoning.is_empty()).then_some(reasoning),
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            },
+            finish_reason: Some(finish_reason(resp)),
+            logprobs: None,
+        }],
+        usage: resp.usage.as_ref().map(chat_usage),
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+/// The `OpenAI` `finish_reason` of a finished response.
+fn finish_reason(resp: &ResponsesResponse) -> String {
+    let called_tools = resp
+        .output
+        .iter()
+        .any(|item| matches!(item, OutputItem::FunctionCall { .. }));
+    let incomplete = resp
+        .incomplete_details
+        .as_ref()
+        .and_then(|d| d.reason.as_deref());
+    match incomplete {
+        Some("max_output_tokens") => "length",
+        Some("content_filter") => "content_filter",
+        _ if called_tools => "tool_calls",
+        _ => "stop",
+    }
+    .to_string()
+}
+
+fn chat_usage(usage: &ResponsesUsage) -> ChatUsage {
+    ChatUsage {
+        prompt_tokens: usage.input_tokens,
+        completion_tokens: usage.output_tokens,
+        total_tokens: usage.total_tokens,
+        prompt_tokens_details: usage.input_tokens_details.clone(),
+        prompt_cache_hit_tokens: None,
+        cost: None,
+    }
+}
+
+/// Translates Responses streaming events into `OpenAI` chunks.
+///
+/// Function calls are numbered in the order their output items are added, and
+/// their argument deltas are matched to them by output index. The final
+/// `response.completed` (or `response.incomplete`) event carries the finish
+/// reason and usage.
+#[derive(Debug, Default)]
+pub struct ResponsesStreamState {
+    tool_calls: HashMap<u64, u64>,
+}
+
+impl ResponsesStreamState {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Translate one event into an `OpenAI` chunk, if it carries anything.
+    /// Failures (`response.failed`, `error`) are left to the caller.
+    pub fn process(&mut self, event: &ResponsesStreamEvent) -> Option<ChatCompletionChunk> {
+        let delta = match event {
+            ResponsesStreamEvent::OutputItemAdded {
+                output_index,
+                item:
+                    OutputItem::FunctionCall {
+                        call_id,
+                        name,
+                        arguments,
+                    },
+            } => {
+                let index = self.tool_calls.len() as u64;
+                self.tool_calls.insert(*output_index, index);
+                tool_call_delta(
+                    index,
+                    Some(call_id.clone()),
+                    Some(name.clone()),
+                    arguments.clone(),
+                )
+            }
+            ResponsesStreamEvent::FunctionCallArgumentsDelta {
+                output_index,
+                delta,
+            } => {
+                let index = *self.tool_calls.get(output_index)?;
+                tool_call_delta(index, None, None, delta.clone())
+            }
+            ResponsesStreamEvent::OutputTextDelta { delta }
+            | ResponsesStreamEvent::RefusalDelta { delta } => ChunkDelta {
+                content: Some(delta.clone()),
+                ..ChunkDelta::default()
+            },
+            ResponsesStreamEvent::ReasoningSummaryTextDelta { delta }
+            | ResponsesStreamEvent::ReasoningTextDelta { delta } => ChunkDelta {
+                reasoning_content: Some(delta.clone()),
+                ..ChunkDelta::default()
+            },
+            // Separate the parts of a reasoning summary
+            ResponsesStreamEvent::ReasoningSummaryPartAdded { summary_index }
+                if *summary_index > 0 =>
+            {
+                ChunkDelta {
+                    reasoning_content: Some("\n\n".to_string()),
+                    ..ChunkDelta::default()
+                }
+            }
+            ResponsesStreamEvent::Completed { response }
+            | ResponsesStreamEvent::Incomplete { response } => {
+                let mut final_chunk = chunk(&response.model, ChunkDelta::default());
+                final_chunk.choices[0].finish_reason = Some(finish_reason(response));
+                final_chunk.usage = response.usage.as_ref().map(chat_usage);
+                return Some(final_chunk);
+            }
+            _ => return None,
+        };
+        Some(chunk("", delta))
+    }
+}
+
+fn tool_call_delta(
+    index: u64,
+    id: Option<String>,
+    name: Option<String>,
+    arguments: String,
+) -> ChunkDelta {
+    ChunkDelta {
+        tool_calls: Some(vec![ChunkToolCall {
+            index,
+            call_type: id.as_ref().map(|_| "function".to_string()),
+            id,
+            function: Some(ChunkToolCallFunction {
+                name,
+                arguments: Some(arguments),
+            }),
+        }]),
+        ..ChunkDelta::default()
+    }
+}
+
+fn chunk(model: &str, delta: ChunkDelta) -> ChatCompletionChunk {
+    ChatCompletionChunk {
+        id: String::new(),
+        object: "chat.completion.chunk".to_string(),
+        created: 0,
+        model: model.to_string(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason: None,
+            logprobs: None,
+        }],
+        usage: None,
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+fn content_text(msg: &ChatMessage) -> String {
+    match &msg.content {
+        Some(ChatContent::Text(text)) => text.to_string(),
+        Some(ChatContent::Parts(parts)) => parts
+            .iter()
+            .filter_map(|p| match p {
+                ContentPart::Text { text } => Some(text.as_ref()),
+                ContentPart::ImageUrl { .. } | ContentPart::File { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => String::new(),
+    }
+}
+
+fn input_content(msg: &ChatMessage) -> Vec<InputContent> {
+    match &msg.content {
+        Some(ChatContent::Text(text)) => vec![InputContent::InputText {
+            text: text.to_string(),
+        }],
+        Some(ChatContent::Parts(parts)) => parts
+            .iter()
+            .map(|p| match p {
+                ContentPart::Text { text } => InputContent::InputText {
+                    text: text.to_string(),
+                },
+                ContentPart::ImageUrl { image_url } => InputContent::InputImage {
+                    image_url: image_url.url.clone(),
+                    detail: image_url.detail.clone(),
+                },
+                ContentPart::File { file } => InputContent::InputFile {
+                    filename: file.filename.clone(),
+                    file_data: file.file_data.clone(),
+                },
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::anthropic_types::MessagesRequest;
+    use crate::translate::request::anthropic_to_openai_for_model;
+
+    #[test]
+    fn test_request_to_responses() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 1024,
+            "system": "Be brief.",
+            "messages": [
+                {"role": "user", "content": "Weather in Paris?"},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "Checking."},
+                    {"type": "tool_use", "id": "call_1", "name": "get_weather", "input": {"city": "Paris"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "call_1", "content": "18C"}
+                ]}
+            ],
+            "tools": [{"name": "get_weather", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "any"}
+        }))
+        .unwrap();
+        let mut openai_req = anthropic_to_openai_for_model(&req, "o4-mini");
+        openai_req.reasoning_effort = Some("high".to_string());
+
+        let out = serde_json::to_value(openai_to_responses(&openai_req)).unwrap();
+
+        assert_eq!(out["model"], "o4-mini");
+        assert_eq!(out["instructions"], "Be brief.");
+        assert_eq!(out["max_output_tokens"], 1024);
+        assert_eq!(out["store"], false);
+        assert_eq!(out["reasoning"]["effort"], "high");
+        assert_eq!(out["reasoning"]["summary"], "auto");
+        assert_eq!(out["tools"][0]["name"], "get_weather");
+        assert_eq!(out["tool_choice"], "required");
+        let types: Vec<&str> = out["input"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "message",
+                "message",
+                "function_call",
+                "function_call_output"
+            ]
+        );
+        assert_eq!(out["input"][0]["content"][0]["type"], "input_text");
+        assert_eq!(out["input"][1]["content"][0]["type"], "output_text");
+        assert_eq!(out["input"][2]["arguments"], "{\"city\":\"Paris\"}");
+        assert_eq!(out["input"][3]["call_id"], "call_1");
+        assert_eq!(out["input"][3]["output"], "18C");
+    }
+
+    #[test]
+    fn test_response_to_openai() {
+        let resp: ResponsesResponse = serde_json::from_value(serde_json::json!({
+            "id": "resp_1",
+            "object": "response",
+            "model": "o4-mini",
+            "status": "completed",
+            "output": [
+                {"type": "reasoning", "id": "rs_1", "summary": [
+                    {"type": "summary_text", "text": "Need the weather."}
+                ]},
+                {"type": "message", "id": "msg_1", "role": "assistant", "content": [
+                    {"type": "output_text", "text": "Let me check.", "annotations": []}
+                ]},
+                {"type": "function_call", "id": "fc_1", "call_id": "call_1",
+                 "name": "get_weather", "arguments": "{\"city\":\"Paris\"}"},
+                {"type": "web_search_call", "id": "ws_1"}
+            ],
+            "usage": {"input_tokens": 50, "input_tokens_details": {"cached_tokens": 10},
+                      "output_tokens": 20, "total_tokens": 70}
+        }))
+        .unwrap();
+
+        let out = responses_to_openai(&resp);
+
+        let choice = &out.choices[0];
+        assert_eq!(choice.message.content.as_deref(), Some("Let me check."));
+        assert_eq!(
+            choice.message.reasoning_content.as_deref(),
+            Some("Need the weather.")
+        );
+        assert_eq!(choice.message.tool_calls.as_ref().unwrap()[0].id, "call_1");
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let usage = out.usage.unwrap();
+        assert_eq!(usage.uncached_prompt_tokens(), 40);
+        assert_eq!(usage.completion_tokens, 20);
+    }
+
+    #[test]
+    fn test_stream_events_to_chunks() {
+        let events = [
+            serde_json::json!({"type": "response.created", "response": {"id": "resp_1"}}),
+            serde_json::json!({"type": "response.reasoning_summary_text.delta", "delta": "Hmm."}),
+            serde_json::json!({"type": "response.output_text.delta", "delta": "Hi"}),
+            serde_json::json!({"type": "response.output_item.added", "output_index": 2, "item": {
+                "type": "function_call", "id": "fc_1", "call_id": "call_1",
+                "name": "get_weather", "arguments": ""
+            }}),
+            serde_json::json!({"type": "response.function_call_arguments.delta",
+                               "output_index": 2, "delta": "{\"city\""}),
+            serde_json::json!({"type": "response.incomplete", "response": {
+                "model": "o4-mini",
+                "incomplete_details": {"reason": "max_output_tokens"},
+                "output": [],
+                "usage": {"input_tokens": 5, "output_tokens": 7, "total_tokens": 12}
+            }}),
+        ];
+        let mut state = ResponsesStreamState::new();
+        let chunks: Vec<ChatCompletionChunk> = events
+            .into_iter()
+            .map(|e| serde_json::from_value::<ResponsesStreamEvent>(e).unwrap())
+            .filter_map(|e| state.process(&e))
+            .collect();
+
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(
+            chunks[0].choices[0].delta.reasoning_content.as_deref(),
+            Some("Hmm.")
+        );
+        assert_eq!(chunks[1].choices[0].delta.content.as_deref(), Some("Hi"));
+        let call = &chunks[2].choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert_eq!((call.index, call.id.as_deref()), (0, Some("call_1")));
+        let args = &chunks[3].choices[0].delta.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(args.index, 0);
+        assert_eq!(
+            chunks[4].choices[0].finish_reason.as_deref(),
+            Some("length")
+        );
+        assert_eq!(chunks[4].usage.as_ref().unwrap().completion_tokens, 7);
+    }
+}
@@ -2,17 +2,26 @@
 //!
 //! These types represent both the request format (what we send to the provider)
 //! and the response format (what the provider sends back), including streaming chunks.
+//!
+//! Request messages and tools borrow their text, descriptions and schemas from
+//! the Anthropic request they were translated from where they can (`Cow`), so a
+//! multi-megabyte conversation isn't copied to be translated. Deserialized
+//! requests own everything and are `ChatCompletionRequest<'static>`.
+//!
+//! The inbound `/v1/chat/completions` endpoint reads requests and writes
+//! responses with the same types (see [`super::chat_completions`]).
 
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 // ---------------------------------------------------------------------------
 // Request types (what we send TO the provider)
 // ---------------------------------------------------------------------------
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChatCompletionRequest {
+pub struct ChatCompletionRequest<'a> {
     pub model: String,
-    pub messages: Vec<ChatMessage>,
+    pub messages: Vec<ChatMessage<'a>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -24,13 +33,98 @@ pub struct ChatCompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream_options: Option<StreamOptions>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tools: Option<Vec<ChatTool>>,
+    pub tools: Option<Vec<ChatTool<'a>>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<ChatToolChoice>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "one_or_many"
+    )]
     pub stop: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+    /// Reasoning effort for reasoning models (xAI Grok, `OpenAI` o-series).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    /// xAI Live Search settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search_parameters: Option<SearchParameters>,
+    /// Constrained decoding (JSON mode, JSON schema, or grammar).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Groups requests sharing a cacheable prompt prefix (`OpenAI`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_key: Option<String>,
+    /// Fields with no typed equivalent, serialized after the others (and so
+    /// replacing any of the same name when the body is built as a `Value`).
+    #[serde(flatten, default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A `stop` given as one string or an array of them, as clients may send it.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stop {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(
+        Option::<Stop>::deserialize(deserializer)?.map(|stop| match stop {
+            Stop::One(s) => vec![s],
+            Stop::Many(v) => v,
+        }),
+    )
+}
+
+/// `response_format` values. `JsonObject` with a `schema` and `Grammar` are
+/// Fireworks/Together extensions; `JsonSchema` is the standard `OpenAI` shape.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        schema: Option<serde_json::Value>,
+    },
+    JsonSchema {
+        json_schema: JsonSchemaFormat,
+    },
+    /// GBNF grammar.
+    Grammar {
+        grammar: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// xAI Live Search parameters (`search_parameters` on Grok chat completions).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchParameters {
+    /// `"auto"`, `"on"` or `"off"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// Data sources, e.g. `{"type": "web", "allowed_websites": [...]}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sources: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_search_results: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_citations: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_date: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,10 +133,10 @@ pub struct StreamOptions {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChatMessage {
+pub struct ChatMessage<'a> {
     pub role: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<ChatContent>,
+    pub content: Option<ChatContent<'a>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ChatToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -53,18 +147,20 @@ pub struct ChatMessage {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
-pub enum ChatContent {
-    Text(String),
-    Parts(Vec<ContentPart>),
+pub enum ChatContent<'a> {
+    Text(Cow<'a, str>),
+    Parts(Vec<ContentPart<'a>>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
-pub enum ContentPart {
+pub enum ContentPart<'a> {
     #[serde(rename = "text")]
-    Text { text: String },
+    Text { text: Cow<'a, str> },
     #[serde(rename = "image_url")]
     ImageUrl { image_url: ImageUrlDetail },
+    #[serde(rename = "file")]
+    File { file: FileDetail },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,19 +170,66 @@ pub struct ImageUrlDetail {
     pub detail: Option<String>,
 }
 
+/// A file sent inline, as a `data:` URL in `file_data`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChatTool {
+pub struct FileDetail {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    pub file_data: String,
+}
+
+impl ChatMessage<'_> {
+    /// The message with everything it borrows copied, to outlive its source.
+    #[must_use]
+    pub fn into_owned(self) -> ChatMessage<'static> {
+        ChatMessage {
+            role: self.role,
+            content: self.content.map(ChatContent::into_owned),
+            tool_calls: self.tool_calls,
+            tool_call_id: self.tool_call_id,
+            name: self.name,
+        }
+    }
+}
+
+impl ChatContent<'_> {
+    #[must_use]
+    pub fn into_owned(self) -> ChatContent<'static> {
+        match self {
+            ChatContent::Text(text) => ChatContent::Text(Cow::Owned(text.into_owned())),
+            ChatContent::Parts(parts) => {
+                ChatContent::Parts(parts.into_iter().map(ContentPart::into_owned).collect())
+            }
+        }
+    }
+}
+
+impl ContentPart<'_> {
+    #[must_use]
+    pub fn into_owned(self) -> ContentPart<'static> {
+        match self {
+            ContentPart::Text { text } => ContentPart::Text {
+                text: Cow::Owned(text.into_owned()),
+            },
+            ContentPart::ImageUrl { image_url } => ContentPart::ImageUrl { image_url },
+            ContentPart::File { file } => ContentPart::File { file },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatTool<'a> {
     #[serde(rename = "type")]
     pub tool_type: String, // always "function"
-    pub function: ChatFunction,
+    pub function: ChatFunction<'a>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChatFunction {
+pub struct ChatFunction<'a> {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub description: Option<String>,
-    pub parameters: serde_json::Value,
+    pub description: Option<Cow<'a, str>>,
+    pub parameters: Cow<'a, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -136,6 +279,11 @@ pub struct ChatCompletionResponse {
     pub choices: Vec<Choice>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub usage: Option<ChatUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// Source URLs (Perplexity, Grok).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub citations: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -143,6 +291,8 @@ pub struct Choice {
     pub index: u64,
     pub message: ChoiceMessage,
     pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -165,6 +315,38 @@ pub struct ChatUsage {
     pub completion_tokens: u64,
     #[serde(default)]
     pub total_tokens: u64,
+    /// Prompt tokens served from the provider's cache (`OpenAI`, Fireworks).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    /// Prompt tokens served from `DeepSeek`'s context cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_hit_tokens: Option<u64>,
+    /// What the request cost (`OpenRouter`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptTokensDetails {
+    #[serde(default)]
+    pub cached_tokens: u64,
+}
+
+impl ChatUsage {
+    /// Prompt tokens read from cache, if the provider reports them.
+    #[must_use]
+    pub fn cached_tokens(&self) -> Option<u64> {
+        self.prompt_cache_hit_tokens
+            .or_else(|| self.prompt_tokens_details.as_ref().map(|d| d.cached_tokens))
+    }
+
+    /// Prompt tokens that were not read from cache, i.e. Anthropic's
+    /// `input_tokens`.
+    #[must_use]
+    pub fn uncached_prompt_tokens(&self) -> u64 {
+        self.prompt_tokens
+            .saturating_sub(self.cached_tokens().unwrap_or(0))
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -181,6 +363,11 @@ pub struct ChatCompletionChunk {
     pub choices: Vec<ChunkChoice>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub usage: Option<ChatUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// Source URLs (Perplexity, Grok).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub citations: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -188,6 +375,8 @@ pub struct ChunkChoice {
     pub index: u64,
     pub delta: ChunkDelta,
     pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -0,0 +1,1399 @@
+//! Extracting the text and images of a PDF, for `[translation] documents`.
+//!
+//! Not a PDF reader: just enough of one to find each page's content streams,
+//! decode them, and pull out the strings they show, mapped to Unicode through
+//! the fonts' `ToUnicode` maps where they have one, and the images they draw.
+//! Objects are found by scanning the file rather than through its
+//! cross-reference table, which also copes with files whose table is damaged.
+//! Pages aren't rendered: a scanned page is its image, but text and drawings
+//! on a page aren't turned into pixels. Encrypted files are left alone.
+
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::rc::Rc;
+
+/// Largest decoded stream, in bytes.
+const MAX_STREAM_BYTES: usize = 64 * 1024 * 1024;
+
+/// Most bytes decoded from one PDF's streams, or put together from them into
+/// pages' and forms' content, all told. A file whose pages use one stream
+/// many times over stops being read here.
+const MAX_DOCUMENT_BYTES: usize = 128 * 1024 * 1024;
+
+/// How deeply arrays and dictionaries may nest.
+const MAX_NESTING: usize = 64;
+
+/// How deeply form `XObject`s may draw one another.
+const MAX_FORM_DEPTH: usize = 8;
+
+/// Most images taken from one PDF, as models accept only so many.
+const MAX_IMAGES: usize = 20;
+
+/// Largest image taken from a PDF, in pixels along either side.
+const MAX_IMAGE_SIDE: usize = 10_000;
+
+/// What one page of a PDF shows.
+#[derive(Debug, Default)]
+pub struct Page {
+    /// The page's text, empty if it shows none.
+    pub text: String,
+    /// The images the page draws, such as the scan of a scanned page.
+    pub images: Vec<Image>,
+}
+
+/// An image from a PDF, encoded for a model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// `image/jpeg` or `image/png`.
+    pub media_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// The text of a PDF's pages, separated by blank lines, or `None` if it isn't
+/// a PDF, is encrypted, or shows no text.
+#[must_use]
+pub fn extract_text(data: &[u8]) -> Option<String> {
+    let pages: Vec<String> = read(data, false)?
+        .into_iter()
+        .map(|page| page.text)
+        .filter(|text| !text.is_empty())
+        .collect();
+    if pages.is_empty() {
+        None
+    } else {
+        Some(pages.join("\n\n"))
+    }
+}
+
+/// The text and images of a PDF's pages, or `None` if it isn't a PDF or is
+/// encrypted. Only JPEG and 8-bit gray or RGB images are kept, and no more
+/// than 20 of them.
+#[must_use]
+pub fn pages(data: &[u8]) -> Option<Vec<Page>> {
+    read(data, true)
+}
+
+fn read(data: &[u8], with_images: bool) -> Option<Vec<Page>> {
+    if !data.starts_with(b"%PDF") {
+        return None;
+    }
+    let doc = Document::scan(data, MAX_DOCUMENT_BYTES);
+    if doc.trailers.iter().any(|t| t.contains_key("Encrypt")) {
+        return None;
+    }
+
+    let mut budget = if with_images { MAX_IMAGES } else { 0 };
+    let pages = doc
+        .pages()
+        .into_iter()
+        .map(|(page, resources)| {
+            let mut text = Text::default();
+            let content = doc.contents(page.get("Contents"));
+            doc.run(&content, resources, &mut text, 0);
+            let images: Vec<Image> = text
+                .images
+                .iter()
+                .filter_map(|num| doc.image(*num))
+                .take(budget)
+                .collect();
+            budget -= images.len();
+            Page {
+                text: text.finish(),
+                images,
+            }
+        })
+        .collect();
+    Some(pages)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Object {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(Vec<u8>),
+    Name(String),
+    Array(Vec<Object>),
+    Dict(Dict),
+    Ref(u32),
+    Keyword(String),
+}
+
+type Dict = HashMap<String, Object>;
+
+impl Object {
+    fn as_dict(&self) -> Option<&Dict> {
+        match self {
+            Self::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        match self {
+            Self::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn as_usize(&self) -> Option<usize> {
+        // Lengths and offsets are whole numbers
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        self.as_number()
+            .filter(|n| *n >= 0.0 && n.fract() == 0.0)
+            .map(|n| n as usize)
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+fn is_regular(b: u8) -> bool {
+    !is_whitespace(b) && !is_delimiter(b)
+}
+
+/// Reads objects and content stream tokens.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    nesting: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self {
+            data,
+            pos,
+            nesting: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &[u8]) -> bool {
+        self.data[self.pos.min(self.data.len())..].starts_with(s)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if b == b'%' {
+                while self.peek().is_some_and(|b| b != b'\n' && b != b'\r') {
+                    self.pos += 1;
+                }
+            } else if is_whitespace(b) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn regular_run(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(is_regular) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    /// The next object, or `None` at the end of the data.
+    fn object(&mut self) -> Option<Object> {
+        self.skip_whitespace();
+        let b = self.peek()?;
+        Some(match b {
+            b'/' => {
+                self.pos += 1;
+                Object::Name(self.name())
+            }
+            b'(' => {
+                self.pos += 1;
+                Object::Str(self.literal_string())
+            }
+            b'<' if self.starts_with(b"<<") => {
+                self.pos += 2;
+                Object::Dict(self.dict()?)
+            }
+            b'<' => {
+                self.pos += 1;
+                Object::Str(self.hex_string())
+            }
+            b'[' => {
+                self.pos += 1;
+                Object::Array(self.array()?)
+            }
+            _ if is_regular(b) => self.word(),
+            // A stray `]`, `>>`, `)`, `{` or `}`
+            _ => {
+                self.pos += if self.starts_with(b">>") { 2 } else { 1 };
+                Object::Keyword(char::from(b).to_string())
+            }
+        })
+    }
+
+    fn name(&mut self) -> String {
+        let run = self.regular_run();
+        let mut name = Vec::with_capacity(run.len());
+        let mut i = 0;
+        while i < run.len() {
+            let escaped = (run[i] == b'#')
+                .then(|| run.get(i + 1..i + 3))
+                .flatten()
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+            if let Some(b) = escaped {
+                name.push(b);
+                i += 3;
+            } else {
+                name.push(run[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8_lossy(&name).into_owned()
+    }
+
+    fn literal_string(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut depth = 0;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'(' => {
+                    depth += 1;
+                    out.push(b);
+                }
+                b')' if depth == 0 => break,
+                b')' => {
+                    depth -= 1;
+                    out.push(b);
+                }
+                b'\\' => {
+                    let Some(e) = self.peek() else { break };
+                    self.pos += 1;
+                    match e {
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'0'..=b'7' => {
+                            let mut code = u32::from(e - b'0');
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(d @ b'0'..=b'7') => {
+                                        code = code * 8 + u32::from(d - b'0');
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            out.push(u8::try_from(code & 0xff).unwrap_or_default());
+                        }
+                        // A line continuation
+                        b'\r' => {
+                            if self.peek() == Some(b'\n') {
+                                self.pos += 1;
+                            }
+                        }
+                        b'\n' => {}
+                        _ => out.push(e),
+                    }
+                }
+                _ => out.push(b),
+            }
+        }
+        out
+    }
+
+    fn hex_string(&mut self) -> Vec<u8> {
+        let mut digits = Vec::new();
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            if b == b'>' {
+                break;
+            }
+            if let Some(d) = char::from(b).to_digit(16) {
+                digits.push(u8::try_from(d).unwrap_or_default());
+            }
+        }
+        if digits.len() % 2 == 1 {
+            digits.push(0);
+        }
+        digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect()
+    }
+
+    fn nest(&mut self) -> Option<()> {
+        self.nesting += 1;
+        (self.nesting <= MAX_NESTING).then_some(())
+    }
+
+    fn array(&mut self) -> Option<Vec<Object>> {
+        self.nest()?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => break,
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'>') if self.starts_with(b">>") => break,
+                _ => items.push(self.object()?),
+            }
+        }
+        self.nesting -= 1;
+        Some(items)
+    }
+
+    fn dict(&mut self) -> Option<Dict> {
+        self.nest()?;
+        let mut dict = Dict::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek().is_none() {
+                break;
+            }
+            if self.starts_with(b">>") {
+                self.pos += 2;
+                break;
+            }
+            if let Object::Name(key) = self.object()? {
+                let value = self.object()?;
+                dict.insert(key, value);
+            }
+        }
+        self.nesting -= 1;
+        Some(dict)
+    }
+
+    /// A number, reference, boolean, null or keyword.
+    fn word(&mut self) -> Object {
+        let run = self.regular_run();
+        let text = String::from_utf8_lossy(run);
+        if run.iter().all(u8::is_ascii_digit) {
+            if let Some(reference) = self.reference(&text) {
+                return reference;
+            }
+        }
+        match &*text {
+            "true" => Object::Bool(true),
+            "false" => Object::Bool(false),
+            "null" => Object::Null,
+            _ if run[0].is_ascii_digit() || matches!(run[0], b'+' | b'-' | b'.') => text
+                .parse()
+                .map_or_else(|_| Object::Keyword(text.into_owned()), Object::Number),
+            _ => Object::Keyword(text.into_owned()),
+        }
+    }
+
+    /// An `N G R` reference, given `N`, if `G R` follows.
+    fn reference(&mut self, num: &str) -> Option<Object> {
+        let start = self.pos;
+        self.skip_whitespace();
+        let generation = self.regular_run();
+        if !generation.is_empty() && generation.iter().all(u8::is_ascii_digit) {
+            self.skip_whitespace();
+            if self.peek() == Some(b'R')
+                && self
+                    .data
+                    .get(self.pos + 1)
+                    .map_or(true, |b| !is_regular(*b))
+            {
+                self.pos += 1;
+                if let Ok(num) = num.parse() {
+                    return Some(Object::Ref(num));
+                }
+            }
+        }
+        self.pos = start;
+        None
+    }
+
+    /// Skip an inline image's data, after its `BI`.
+    fn skip_inline_image(&mut self) {
+        let ends = |data: &[u8], at: usize, word: &[u8]| {
+            data.get(at.wrapping_sub(1))
+                .is_some_and(|b| is_whitespace(*b))
+                && data[at..].starts_with(word)
+                && data
+                    .get(at + word.len())
+                    .map_or(true, |b| is_whitespace(*b))
+        };
+        while self.pos < self.data.len() && !ends(self.data, self.pos, b"ID") {
+            self.pos += 1;
+        }
+        while self.pos < self.data.len() && !ends(self.data, self.pos, b"EI") {
+            self.pos += 1;
+        }
+        self.pos = (self.pos + 2).min(self.data.len());
+    }
+}
+
+/// The objects of a PDF file.
+struct Document {
+    objects: HashMap<u32, Object>,
+    /// The raw data of stream objects, by object number.
+    streams: HashMap<u32, Vec<u8>>,
+    /// Streams already decoded, by object number, so that one used on several
+    /// pages is decoded once.
+    decoded: RefCell<HashMap<u32, Rc<[u8]>>>,
+    /// Bytes that may still be decoded or put together into content.
+    budget: Cell<usize>,
+    /// Trailer and cross-reference stream dictionaries.
+    trailers: Vec<Dict>,
+}
+
+impl Document {
+    /// Find the objects in `data`, later definitions replacing earlier ones
+    /// as in an incrementally updated file. At most `budget` bytes will be
+    /// decoded from it.
+    fn scan(data: &[u8], budget: usize) -> Self {
+        let mut doc = Self {
+            objects: HashMap::new(),
+            streams: HashMap::new(),
+            decoded: RefCell::new(HashMap::new()),
+            budget: Cell::new(budget),
+            trailers: Vec::new(),
+        };
+        let mut pos = 0;
+        while let Some(at) = find(data, b"obj", pos) {
+            pos = at + 3;
+            let Some(num) = object_number(data, at) else {
+                continue;
+            };
+            if data.get(pos).is_some_and(|b| is_regular(*b)) {
+                continue;
+            }
+            let mut parser = Parser::new(data, pos);
+            let Some(object) = parser.object() else {
+                continue;
+            };
+            parser.skip_whitespace();
+            if let (Object::Dict(dict), true) = (&object, parser.starts_with(b"stream")) {
+                let (raw, end) = stream_data(data, parser.pos + 6, dict);
+                doc.streams.insert(num, raw.to_vec());
+                parser.pos = end;
+            }
+            pos = parser.pos;
+            doc.objects.insert(num, object);
+        }
+
+        let mut pos = 0;
+        while let Some(at) = find(data, b"trailer", pos) {
+            pos = at + 7;
+            if let Some(Object::Dict(dict)) = Parser::new(data, pos).object() {
+                doc.trailers.push(dict);
+            }
+        }
+        doc.unpack_object_streams();
+        for object in doc.objects.values() {
+            if let Some(dict) = object.as_dict().filter(|d| is_type(d, "XRef")) {
+                doc.trailers.push(dict.clone());
+            }
+        }
+        doc
+    }
+
+    /// Add the objects kept in object streams.
+    fn unpack_object_streams(&mut self) {
+        let mut unpacked = Vec::new();
+        for (num, object) in &self.objects {
+            let Some(dict) = object.as_dict().filter(|d| is_type(d, "ObjStm")) else {
+                continue;
+            };
+            let Some(data) = self.stream(*num) else {
+                continue;
+            };
+            let count = dict.get("N").and_then(Object::as_usize).unwrap_or(0);
+            let first = dict.get("First").and_then(Object::as_usize).unwrap_or(0);
+            let mut header = Parser::new(&data, 0);
+            for _ in 0..count {
+                let (Some(Object::Number(num)), Some(offset)) = (header.object(), header.object())
+                else {
+                    break;
+                };
+                let Some(offset) = offset.as_usize() else {
+                    break;
+                };
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let num = num as u32;
+                if let Some(object) = Parser::new(&data, first + offset).object() {
+                    unpacked.push((num, object));
+                }
+            }
+        }
+        for (num, object) in unpacked {
+            self.objects.entry(num).or_insert(object);
+        }
+    }
+
+    fn resolve<'a>(&'a self, mut object: &'a Object) -> &'a Object {
+        for _ in 0..8 {
+            match object {
+                Object::Ref(num) => match self.objects.get(num) {
+                    Some(target) => object = target,
+                    None => return &Object::Null,
+                },
+                _ => break,
+            }
+        }
+        object
+    }
+
+    fn dict<'a>(&'a self, object: Option<&'a Object>) -> Option<&'a Dict> {
+        object.map(|o| self.resolve(o)).and_then(Object::as_dict)
+    }
+
+    /// The filters of a stream, in the order they are undone.
+    fn filters<'a>(&'a self, dict: &'a Dict) -> Option<Vec<&'a str>> {
+        match dict.get("Filter").map(|f| self.resolve(f)) {
+            None => Some(Vec::new()),
+            Some(Object::Name(name)) => Some(vec![name.as_str()]),
+            Some(Object::Array(names)) => Some(names.iter().filter_map(Object::as_name).collect()),
+            Some(_) => None,
+        }
+    }
+
+    /// Take `bytes` from the budget, returning whether there were that many
+    /// left.
+    fn spend(&self, bytes: usize) -> bool {
+        let left = self.budget.get();
+        self.budget.set(left.saturating_sub(bytes));
+        bytes <= left
+    }
+
+    /// The decoded data of stream object `num`, or `None` if it can't be
+    /// decoded or the budget is spent. A stream is cut off at the budget.
+    fn stream(&self, num: u32) -> Option<Rc<[u8]>> {
+        if let Some(data) = self.decoded.borrow().get(&num) {
+            return Some(data.clone());
+        }
+        let raw = self.streams.get(&num)?;
+        let dict = self.objects.get(&num)?.as_dict()?;
+        let limit = MAX_STREAM_BYTES.min(self.budget.get());
+        if limit == 0 {
+            return None;
+        }
+        let mut data = raw.clone();
+        for filter in self.filters(dict)? {
+            data = match filter {
+                "FlateDecode" | "Fl" => inflate(&data, limit),
+                "ASCIIHexDecode" | "AHx" => Parser::new(&data, 0).hex_string(),
+                "ASCII85Decode" | "A85" => ascii85(&data),
+                // Image and other filters
+                _ => return None,
+            };
+        }
+        data.truncate(limit);
+        self.spend(data.len());
+        let data: Rc<[u8]> = data.into();
+        self.decoded.borrow_mut().insert(num, data.clone());
+        Some(data)
+    }
+
+    /// The pages in order, each with its (possibly inherited) resources.
+    fn pages(&self) -> Vec<(&Dict, Option<&Dict>)> {
+        let mut pages = Vec::new();
+        let root = self
+            .trailers
+            .iter()
+            .rev()
+            .find_map(|t| self.dict(t.get("Root")))
+            .or_else(|| {
+                self.objects
+                    .values()
+                    .filter_map(Object::as_dict)
+                    .find(|d| is_type(d, "Catalog"))
+            });
+        if let Some(tree) = root.and_then(|root| root.get("Pages")) {
+            self.collect_pages(tree, None, &mut pages, &mut HashSet::new());
+        }
+        if pages.is_empty() {
+            // No usable page tree: every page object, in object order
+            let mut nums: Vec<&u32> = self.objects.keys().collect();
+            nums.sort_unstable();
+            for num in nums {
+                if let Some(page) = self.objects[num].as_dict().filter(|d| is_type(d, "Page")) {
+                    pages.push((page, self.dict(page.get("Resources"))));
+                }
+            }
+        }
+        pages
+    }
+
+    fn collect_pages<'a>(
+        &'a self,
+        node: &'a Object,
+        inherited: Option<&'a Dict>,
+        pages: &mut Vec<(&'a Dict, Option<&'a Dict>)>,
+        seen: &mut HashSet<u32>,
+    ) {
+        if let Object::Ref(num) = node {
+            if !seen.insert(*num) {
+                return;
+            }
+        }
+        let Some(dict) = self.resolve(node).as_dict() else {
+            return;
+        };
+        let resources = self.dict(dict.get("Resources")).or(inherited);
+        match dict.get("Kids").map(|k| self.resolve(k)) {
+            Some(Object::Array(kids)) => {
+                for kid in kids {
+                    self.collect_pages(kid, resources, pages, seen);
+                }
+            }
+            _ => pages.push((dict, resources)),
+        }
+    }
+
+    /// A page's content, from one stream or an array of them.
+    fn contents(&self, contents: Option<&Object>) -> Vec<u8> {
+        let streams = match contents {
+            Some(Object::Ref(num)) => match self.objects.get(num) {
+                Some(Object::Array(refs)) => refs.clone(),
+                _ => vec![Object::Ref(*num)],
+            },
+            Some(Object::Array(refs)) => refs.clone(),
+            _ => Vec::new(),
+        };
+        let mut content = Vec::new();
+        for stream in streams {
+            if let Some(data) = match stream {
+                Object::Ref(num) => self.stream(num),
+                _ => None,
+            } {
+                if !self.spend(data.len() + 1) {
+                    break;
+                }
+                content.extend_from_slice(&data);
+                content.push(b'\n');
+            }
+        }
+        content
+    }
+
+    fn fonts(&self, resources: Option<&Dict>) -> HashMap<String, Font> {
+        let Some(fonts) = self.dict(resources.and_then(|r| r.get("Font"))) else {
+            return HashMap::new();
+        };
+        fonts
+            .iter()
+            .filter_map(|(name, font)| {
+                let font = self.resolve(font).as_dict()?;
+                Some((name.clone(), self.font(font)))
+            })
+            .collect()
+    }
+
+    fn font(&self, dict: &Dict) -> Font {
+        let composite = dict.get("Subtype").and_then(Object::as_name) == Some("Type0");
+        let cmap = match dict.get("ToUnicode") {
+            Some(Object::Ref(num)) => self.stream(*num).map(|data| CMap::parse(&data)),
+            _ => None,
+        };
+        Font {
+            code_bytes: match &cmap {
+                Some(cmap) if cmap.code_bytes > 0 => cmap.code_bytes,
+                _ if composite => 2,
+                _ => 1,
+            },
+            cmap: cmap.map(|cmap| cmap.map),
+        }
+    }
+
+    /// Interpret a content stream, adding the text it shows to `text`.
+    fn run(&self, content: &[u8], resources: Option<&Dict>, text: &mut Text, depth: usize) {
+        let fonts = self.fonts(resources);
+        let mut font: Option<&Font> = None;
+        let mut operands: Vec<Object> = Vec::new();
+        let mut parser = Parser::new(content, 0);
+        while let Some(object) = parser.object() {
+            let Object::Keyword(op) = object else {
+                operands.push(object);
+                continue;
+            };
+            match (op.as_str(), &operands[..]) {
+                ("Tf", [Object::Name(name), ..]) => font = fonts.get(name),
+                ("Tj", [.., Object::Str(s)]) => text.show(font, s),
+                ("'" | "\"", [.., Object::Str(s)]) => {
+                    text.newline();
+                    text.show(font, s);
+                }
+                ("TJ", [.., Object::Array(items)]) => {
+                    for item in items {
+                        match item {
+                            Object::Str(s) => text.show(font, s),
+                            Object::Number(n) if *n < -200.0 => text.space(),
+                            _ => {}
+                        }
+                    }
+                }
+                ("Td" | "TD", [.., _, Object::Number(ty)]) if *ty != 0.0 => text.newline(),
+                ("T*", _) => text.newline(),
+                ("Tm", [.., Object::Number(y)]) => text.move_to(*y),
+                ("Do", [Object::Name(name), ..]) if depth < MAX_FORM_DEPTH => {
+                    self.draw(name, resources, text, depth);
+                }
+                ("BI", _) => parser.skip_inline_image(),
+                _ => {}
+            }
+            operands.clear();
+        }
+    }
+
+    /// Draw an `XObject`: run a form's content, or note an image.
+    fn draw(&self, name: &str, resources: Option<&Dict>, text: &mut Text, depth: usize) {
+        let xobjects = self.dict(resources.and_then(|r| r.get("XObject")));
+        let Some(Object::Ref(num)) = xobjects.and_then(|x| x.get(name)) else {
+            return;
+        };
+        let Some(xobject) = self.objects.get(num).and_then(Object::as_dict) else {
+            return;
+        };
+        match xobject.get("Subtype").and_then(Object::as_name) {
+            Some("Form") => {
+                // Each run of a form counts, as forms can draw one another
+                // many times over
+                if let Some(content) = self.stream(*num).filter(|c| self.spend(c.len())) {
+                    let form_resources = self.dict(xobject.get("Resources")).or(resources);
+                    self.run(&content, form_resources, text, depth + 1);
+                }
+            }
+            Some("Image") if !text.images.contains(num) => text.images.push(*num),
+            _ => {}
+        }
+    }
+
+    /// Image `XObject` `num` as a JPEG or PNG: JPEG (`DCTDecode`) data is
+    /// passed on as it is, and 8-bit gray or RGB samples are encoded as PNG.
+    /// Other encodings and color spaces, and masks, are left out.
+    fn image(&self, num: u32) -> Option<Image> {
+        let dict = self.objects.get(&num)?.as_dict()?;
+        if let ["DCTDecode" | "DCT"] = self.filters(dict)?[..] {
+            return Some(Image {
+                media_type: "image/jpeg",
+                data: self.streams.get(&num)?.clone(),
+            });
+        }
+        let predicted = self
+            .dict(dict.get("DecodeParms"))
+            .and_then(|parms| parms.get("Predictor"))
+            .and_then(Object::as_number)
+            .is_some_and(|predictor| predictor > 1.0);
+        let number = |key| {
+            dict.get(key)
+                .map(|o| self.resolve(o))
+                .and_then(Object::as_usize)
+        };
+        let (width, height) = (number("Width")?, number("Height")?);
+        if predicted
+            || number("BitsPerComponent")? != 8
+            || width > MAX_IMAGE_SIDE
+            || height > MAX_IMAGE_SIDE
+        {
+            return None;
+        }
+        let color = dict.get("ColorSpace").map(|o| self.resolve(o));
+        let components = match color.and_then(Object::as_name)? {
+            "DeviceGray" | "G" => 1,
+            "DeviceRGB" | "RGB" => 3,
+            _ => return None,
+        };
+        let data = self.stream(num)?;
+        let samples = data[..data.len().min(width * height * components)].to_vec();
+        let (width, height) = (u32::try_from(width).ok()?, u32::try_from(height).ok()?);
+        let image = if components == 1 {
+            image::DynamicImage::ImageLuma8(image::GrayImage::from_raw(width, height, samples)?)
+        } else {
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_raw(width, height, samples)?)
+        };
+        let mut data = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut data, image::ImageFormat::Png).ok()?;
+        Some(Image {
+            media_type: "image/png",
+            data: data.into_inner(),
+        })
+    }
+}
+
+fn is_type(dict: &Dict, name: &str) -> bool {
+    dict.get("Type").and_then(Object::as_name) == Some(name)
+}
+
+fn find(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|at| from + at)
+}
+
+/// The number of the object whose `N G obj` header ends with the `obj` at
+/// `at`, if it is one.
+fn object_number(data: &[u8], at: usize) -> Option<u32> {
+    let mut i = at;
+    let mut back = |digits: bool| {
+        let end = i;
+        while i > 0
+            && (data[i - 1].is_ascii_digit() == digits && (digits || is_whitespace(data[i - 1])))
+        {
+            i -= 1;
+        }
+        (i < end).then_some(i..end)
+    };
+    back(false)?;
+    back(true)?;
+    back(false)?;
+    let num = back(true)?;
+    if num.start > 0 && is_regular(data[num.start - 1]) {
+        return None;
+    }
+    std::str::from_utf8(&data[num]).ok()?.parse().ok()
+}
+
+/// A stream's raw data, which starts after the line ending that follows
+/// `stream`, and where parsing resumes after it.
+fn stream_data<'a>(data: &'a [u8], mut start: usize, dict: &Dict) -> (&'a [u8], usize) {
+    if data.get(start) == Some(&b'\r') {
+        start += 1;
+    }
+    if data.get(start) == Some(&b'\n') {
+        start += 1;
+    }
+    start = start.min(data.len());
+    // Trust `/Length` if `endstream` is where it says
+    if let Some(len) = dict.get("Length").and_then(Object::as_usize) {
+        if let Some(end) = start.checked_add(len).filter(|end| *end <= data.len()) {
+            let mut after = Parser::new(data, end);
+            after.skip_whitespace();
+            if after.starts_with(b"endstream") {
+                return (&data[start..end], after.pos + 9);
+            }
+        }
+    }
+    match find(data, b"endstream", start) {
+        Some(at) => {
+            let mut end = at;
+            if end > start && data[end - 1] == b'\n' {
+                end -= 1;
+            }
+            if end > start && data[end - 1] == b'\r' {
+                end -= 1;
+            }
+            (&data[start..end], at + 9)
+        }
+        None => (&data[start..], data.len()),
+    }
+}
+
+/// Decompress zlib-wrapped data (as `FlateDecode` is), or raw DEFLATE data if
+/// there's no zlib header, producing at most `limit` bytes. A damaged stream
+/// yields what was decoded before the damage, as PDF readers do.
+fn inflate(data: &[u8], limit: usize) -> Vec<u8> {
+    let limit = u64::try_from(limit).unwrap_or(u64::MAX);
+    let mut out = Vec::new();
+    // An error leaves what was decoded so far in `out`
+    match data {
+        [cmf, flg, rest @ ..]
+            if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 =>
+        {
+            if ZlibDecoder::new(data)
+                .take(limit)
+                .read_to_end(&mut out)
+                .is_err()
+            {
+                // Often just a bad Adler-32 trailer, which loses the last
+                // block; the DEFLATE data itself may be fine
+                out.clear();
+                let _ = DeflateDecoder::new(rest).take(limit).read_to_end(&mut out);
+            }
+        }
+        _ => {
+            let _ = DeflateDecoder::new(data).take(limit).read_to_end(&mut out);
+        }
+    }
+    out
+}
+
+fn ascii85(data: &[u8]) -> Vec<u8> {
+    fn flush(group: &mut Vec<u8>, out: &mut Vec<u8>) {
+        let n = group.len();
+        if n > 1 {
+            group.resize(5, b'u' - b'!');
+            let value = group
+                .iter()
+                .fold(0u32, |v, d| v.wrapping_mul(85).wrapping_add(u32::from(*d)));
+            out.extend_from_slice(&value.to_be_bytes()[..n - 1]);
+        }
+        group.clear();
+    }
+
+    let mut out = Vec::new();
+    let mut group = Vec::with_capacity(5);
+    for &b in data {
+        match b {
+            b'~' => break,
+            b'z' if group.is_empty() => out.extend_from_slice(&[0; 4]),
+            b'!'..=b'u' => {
+                group.push(b - b'!');
+                if group.len() == 5 {
+                    flush(&mut group, &mut out);
+                }
+            }
+            _ => {}
+        }
+    }
+    flush(&mut group, &mut out);
+    out
+}
+
+/// How a font's character codes map to text.
+struct Font {
+    code_bytes: usize,
+    cmap: Option<HashMap<u32, String>>,
+}
+
+/// A font's `ToUnicode` map.
+struct CMap {
+    map: HashMap<u32, String>,
+    /// The length of its codes, or 0 if it maps none.
+    code_bytes: usize,
+}
+
+impl CMap {
+    fn parse(data: &[u8]) -> Self {
+        let mut cmap = Self {
+            map: HashMap::new(),
+            code_bytes: 0,
+        };
+        let mut parser = Parser::new(data, 0);
+        let mut section = String::new();
+        let mut pending: Vec<Object> = Vec::new();
+        while let Some(object) = parser.object() {
+            if let Object::Keyword(word) = object {
+                section = word;
+                pending.clear();
+                continue;
+            }
+            pending.push(object);
+            match (section.as_str(), &pending[..]) {
+                ("beginbfchar", [Object::Str(src), Object::Str(dst)]) => {
+                    cmap.insert(src, code(src), utf16(dst));
+                }
+                ("beginbfrange", [Object::Str(lo), Object::Str(hi), dst]) => {
+                    let (lo_code, hi_code) = (code(lo), code(hi));
+                    for (i, c) in
+                        (lo_code..=hi_code.min(lo_code.saturating_add(0xffff))).enumerate()
+                    {
+                        let text = match dst {
+                            Object::Str(dst) => {
+                                let mut units = utf16_units(dst);
+                                if let Some(last) = units.last_mut() {
+                                    *last = last.wrapping_add(u16::try_from(i).unwrap_or_default());
+                                }
+                                String::from_utf16_lossy(&units)
+                            }
+                            Object::Array(dsts) => match dsts.get(i) {
+                                Some(Object::Str(dst)) => utf16(dst),
+                                _ => continue,
+                            },
+                            _ => break,
+                        };
+                        cmap.insert(lo, c, text);
+                    }
+                }
+                (_, [_, _, _]) | ("beginbfchar", [_, _]) => {}
+                _ => continue,
+            }
+            pending.clear();
+        }
+        cmap
+    }
+
+    fn insert(&mut self, src: &[u8], code: u32, text: String) {
+        if self.code_bytes == 0 {
+            self.code_bytes = src.len().clamp(1, 4);
+        }
+        self.map.insert(code, text);
+    }
+}
+
+fn code(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |c, b| c << 8 | u32::from(*b))
+}
+
+fn utf16_units(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+        .collect()
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    String::from_utf16_lossy(&utf16_units(bytes))
+}
+
+/// The characters of `WinAnsiEncoding` that differ from Latin-1.
+fn win_ansi(b: u8) -> char {
+    match b {
+        0x80 => '€',
+        0x85 => '…',
+        0x91 => '‘',
+        0x92 => '’',
+        0x93 => '“',
+        0x94 => '”',
+        0x95 => '•',
+        0x96 => '–',
+        0x97 => '—',
+        0x99 => '™',
+        _ => char::from(b),
+    }
+}
+
+/// The text of a page as it is shown, and the images it draws.
+#[derive(Default)]
+struct Text {
+    out: String,
+    /// The vertical position of the current line, once set by `Tm`.
+    line_y: Option<f64>,
+    /// The image objects drawn, in the order first drawn.
+    images: Vec<u32>,
+}
+
+impl Text {
+    fn show(&mut self, font: Option<&Font>, s: &[u8]) {
+        let code_bytes = font.map_or(1, |f| f.code_bytes);
+        let cmap = font.and_then(|f| f.cmap.as_ref());
+        for chunk in s.chunks(code_bytes) {
+            match cmap.and_then(|cmap| cmap.get(&code(chunk))) {
+                Some(text) => self.out.push_str(text),
+                // Without a map, a multi-byte code is a glyph id
+                None if code_bytes == 1 && chunk[0] >= 0x20 => self.out.push(win_ansi(chunk[0])),
+                None => {}
+            }
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.truncate(self.out.trim_end_matches(' ').len());
+            self.out.push('\n');
+        }
+    }
+
+    fn move_to(&mut self, y: f64) {
+        if self.line_y.map_or(true, |line_y| (line_y - y).abs() > 1.0) {
+            self.newline();
+        }
+        self.line_y = Some(y);
+    }
+
+    fn finish(self) -> String {
+        let mut page = String::new();
+        let mut blank = 0;
+        for line in self.out.lines().map(str::trim_end) {
+            if line.is_empty() {
+                blank += 1;
+                continue;
+            }
+            if !page.is_empty() {
+                page.push_str(if blank > 0 { "\n\n" } else { "\n" });
+            }
+            page.push_str(line);
+            blank = 0;
+        }
+        page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PDF of numbered `objects`, object 1 being the catalog.
+    fn pdf(objects: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut out = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        for (num, object) in objects {
+            out.extend_from_slice(format!("{num} 0 obj\n").as_bytes());
+            out.extend_from_slice(object);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        out.extend_from_slice(b"trailer\n<< /Root 1 0 R >>\n%%EOF\n");
+        out
+    }
+
+    fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = format!("<< {dict} /Length {} >>\nstream\n", data.len()).into_bytes();
+        out.extend_from_slice(data);
+        out.extend_from_slice(b"\nendstream");
+        out
+    }
+
+    /// `data` as zlib data in a stored (uncompressed) block.
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        let len = u16::try_from(data.len()).unwrap();
+        let mut out = vec![0x78, 0x01, 0x01];
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    #[test]
+    fn test_extract_text() {
+        let doc = pdf(&[
+            (1, b"<< /Type /Catalog /Pages 2 0 R >>".to_vec()),
+            (
+                2,
+                b"<< /Type /Pages /Kids [3 0 R 5 0 R] /Count 2 /Resources << /Font << /F1 7 0 R >> >> >>"
+                    .to_vec(),
+            ),
+            (3, b"<< /Type /Page /Parent 2 0 R /Contents 4 0 R >>".to_vec()),
+            (
+                4,
+                stream(
+                    "",
+                    b"BT /F1 12 Tf 72 700 Td (Hello, \\(world\\)) Tj 0 -14 Td [(Kern)-20(ed)-400(words)] TJ ET\n\
+                      BI /W 2 /H 2 /BPC 8 ID \x00) Tj (\xff EI\n\
+                      BT 1 0 0 1 72 600 Tm (Caf\\351 \\223quoted\\224) Tj ET",
+                ),
+            ),
+            (5, b"<< /Type /Page /Parent 2 0 R /Contents [6 0 R] >>".to_vec()),
+            (
+                6,
+                stream(
+                    "/Filter /FlateDecode",
+                    &zlib_stored(b"BT /F1 12 Tf (Page two) Tj ET"),
+                ),
+            ),
+            (7, b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_vec()),
+        ]);
+        assert_eq!(
+            extract_text(&doc).unwrap(),
+            "Hello, (world)\nKerned words\nCafé “quoted”\n\nPage two"
+        );
+    }
+
+    #[test]
+    fn test_composite_fonts_and_object_streams() {
+        let cmap = b"/CIDInit /ProcSet findresource begin\n\
+            begincmap\n1 begincodespacerange <0000> <FFFF> endcodespacerange\n\
+            2 beginbfchar\n<0001> <0048>\n<0002> <0069>\nendbfchar\n\
+            1 beginbfrange\n<0010> <0012> <03B1>\nendbfrange\n\
+            endcmap";
+        // The page and its font are kept in an object stream
+        let page = "<< /Type /Page /Parent 2 0 R /Contents 4 0 R >> ";
+        let font = "<< /Type /Font /Subtype /Type0 /Encoding /Identity-H /ToUnicode 6 0 R >>";
+        let header = format!("3 0 5 {} ", page.len());
+        let packed = format!("{header}{page}{font}");
+        let doc = pdf(&[
+            (1, b"<< /Type /Catalog /Pages 2 0 R >>".to_vec()),
+            (
+                2,
+                b"<< /Type /Pages /Kids [3 0 R] /Resources << /Font << /F1 5 0 R >> /XObject << /X1 8 0 R >> >> >>"
+                    .to_vec(),
+            ),
+            (
+                4,
+                stream(
+                    "/Filter /ASCIIHexDecode",
+                    b"42 54 20 2f 46 31 20 31 32 20 54 66 20 3c 30 30 30 31 30 30 30 32 3e 20 54 6a 20 45 54 20 2f 58 31 20 44 6f>",
+                ),
+            ),
+            (6, stream("", cmap)),
+            (
+                7,
+                stream(
+                    &format!("/Type /ObjStm /N 2 /First {}", header.len()),
+                    packed.as_bytes(),
+                ),
+            ),
+            (
+                8,
+                stream(
+                    "/Type /XObject /Subtype /Form",
+                    b"BT /F1 10 Tf 0 -20 Td <001000110012> Tj ET",
+                ),
+            ),
+        ]);
+        assert_eq!(extract_text(&doc).unwrap(), "Hi\nαβγ");
+    }
+
+    #[test]
+    fn test_no_text() {
+        assert_eq!(extract_text(b"not a pdf"), None);
+
+        let encrypted = [
+            pdf(&[(1, b"<< /Type /Catalog >>".to_vec())]),
+            b"trailer\n<< /Root 1 0 R /Encrypt 2 0 R >>\n".to_vec(),
+        ]
+        .concat();
+        assert_eq!(extract_text(&encrypted), None);
+
+        // A page that only draws
+        let drawing = pdf(&[
+            (1, b"<< /Type /Catalog /Pages 2 0 R >>".to_vec()),
+            (2, b"<< /Type /Pages /Kids [3 0 R] >>".to_vec()),
+            (3, b"<< /Type /Page /Contents 4 0 R >>".to_vec()),
+            (4, stream("", b"0 0 m 100 100 l S")),
+        ]);
+        assert_eq!(extract_text(&drawing), None);
+
+        // Damage doesn't panic
+        let mut damaged = drawing.clone();
+        damaged.truncate(damaged.len() / 2);
+        let _ = extract_text(&damaged);
+        let _ = extract_text(&[b"%PDF-1.4\n1 0 obj ".as_slice(), &[b'['; 1000]].concat());
+    }
+
+    #[test]
+    fn test_pages() {
+        let doc = pdf(&[
+            (1, b"<< /Type /Catalog /Pages 2 0 R >>".to_vec()),
+            (2, b"<< /Type /Pages /Kids [3 0 R 5 0 R] >>".to_vec()),
+            (
+                3,
+                b"<< /Type /Page /Contents 4 0 R /Resources << /XObject << /Im1 6 0 R /Im2 7 0 R /Im3 8 0 R >> >> >>"
+                    .to_vec(),
+            ),
+            (4, stream("", b"q /Im1 Do Q q /Im2 Do Q q /Im1 Do Q q /Im3 Do Q")),
+            (5, b"<< /Type /Page /Contents 9 0 R >>".to_vec()),
+            (
+                6,
+                stream(
+                    "/Type /XObject /Subtype /Image /Width 1 /Height 1 /Filter /DCTDecode",
+                    b"\xff\xd8 jpeg \xff\xd9",
+                ),
+            ),
+            (
+                7,
+                stream(
+                    "/Type /XObject /Subtype /Image /Width 2 /Height 1 /BitsPerComponent 8 \
+                     /ColorSpace /DeviceRGB /Filter /FlateDecode",
+                    &zlib_stored(&[255, 0, 0, 0, 0, 255]),
+                ),
+            ),
+            // CMYK isn't encoded
+            (
+                8,
+                stream(
+                    "/Type /XObject /Subtype /Image /Width 1 /Height 1 /BitsPerComponent 8 \
+                     /ColorSpace /DeviceCMYK",
+                    &[0, 0, 0, 0],
+                ),
+            ),
+            (9, stream("", b"BT (Page two) Tj ET")),
+        ]);
+        let pages = pages(&doc).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].text, "");
+        assert_eq!(pages[0].images.len(), 2);
+        assert_eq!(
+            pages[0].images[0],
+            Image {
+                media_type: "image/jpeg",
+                data: b"\xff\xd8 jpeg \xff\xd9".to_vec(),
+            }
+        );
+        assert_eq!(pages[0].images[1].media_type, "image/png");
+        let png = image::load_from_memory(&pages[0].images[1].data)
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(png.dimensions(), (2, 1));
+        assert_eq!(png.get_pixel(1, 0).0, [0, 0, 255]);
+        assert_eq!(pages[1].text, "Page two");
+        assert!(pages[1].images.is_empty());
+
+        // Text extraction doesn't decode images
+        assert_eq!(extract_text(&doc).unwrap(), "Page two");
+    }
+
+    #[test]
+    fn test_decode_budget() {
+        let doc = pdf(&[
+            (1, b"<< /Type /Catalog /Pages 2 0 R >>".to_vec()),
+            (2, b"<< /Type /Pages /Kids [3 0 R] >>".to_vec()),
+            (
+                3,
+                b"<< /Type /Page /Contents [4 0 R 4 0 R 4 0 R 4 0 R] >>".to_vec(),
+            ),
+            (
+                4,
+                stream("/Filter /FlateDecode", &zlib_stored(b"BT (Hello) Tj ET")),
+            ),
+        ]);
+        let doc = Document::scan(&doc, 50);
+        let (page, _) = doc.pages()[0];
+        // 16 bytes to decode the stream, then 17 for each copy into the
+        // page's content: two copies fit in the 34 left
+        assert_eq!(
+            doc.contents(page.get("Contents")),
+            b"BT (Hello) Tj ET\nBT (Hello) Tj ET\n"
+        );
+        // The stream was decoded once
+        assert_eq!(doc.decoded.borrow().len(), 1);
+        assert_eq!(doc.budget.get(), 0);
+    }
+
+    #[test]
+    fn test_inflate() {
+        use flate2::write::{DeflateEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        let lines = (0..40)
+            .map(|i| format!("0 -14 Td (Line {i}: the quick brown fox) Tj\n"))
+            .collect::<Vec<_>>()
+            .concat();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(lines.as_bytes()).unwrap();
+        let zlib = zlib.finish().unwrap();
+        assert_eq!(inflate(&zlib, usize::MAX), lines.as_bytes());
+
+        // Raw DEFLATE data has no zlib header
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(lines.as_bytes()).unwrap();
+        assert_eq!(
+            inflate(&raw.finish().unwrap(), usize::MAX),
+            lines.as_bytes()
+        );
+
+        // The output stops at the limit
+        assert_eq!(inflate(&zlib, 10), lines.as_bytes()[..10]);
+
+        // A truncated stream gives what was decoded before it ends
+        let out = inflate(&zlib[..zlib.len() / 2], usize::MAX);
+        assert!(!out.is_empty() && lines.as_bytes().starts_with(&out));
+    }
+
+    #[test]
+    fn test_ascii85() {
+        assert_eq!(ascii85(b"87cURD]j7BEbo7~>"), b"Hello world");
+        assert_eq!(ascii85(b"z~>"), [0; 4]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Cache of translated conversation prefixes.
+//!
+//! Claude Code resends the whole conversation every turn, so most of each
+//! request's messages were translated for the one before. A [`PrefixCache`]
+//! keeps the translated `OpenAI` messages of recent conversations under a hash
+//! of the Anthropic messages they came from. A new turn looks up the longest
+//! prefix of its messages it has a translation for, translates only the
+//! messages after it, and stores the whole for the turn after.
+//!
+//! Prefixes are hashed by streaming each message's JSON into a hasher, so no
+//! copy of the conversation is made to compute the key. Entries beyond the
+//! capacity (`[translation] prefix_cache_entries`) are evicted least recently
+//! used first.
+
+use super::anthropic_types::Message;
+use super::openai_types::ChatMessage;
+use super::request::translate_conversation;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A prefix's key: its length in messages and their hash.
+type Key = (usize, u64);
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Each translated prefix with the tick it was last used at.
+    entries: HashMap<Key, (Arc<Vec<ChatMessage<'static>>>, u64)>,
+    tick: u64,
+}
+
+/// Shared cache of translated conversation prefixes.
+#[derive(Debug, Clone, Default)]
+pub struct PrefixCache {
+    inner: Arc<Mutex<Entries>>,
+}
+
+impl PrefixCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Translate `messages`, reusing the translation of their longest cached
+    /// prefix, and keep the result among at most `capacity` entries. With a
+    /// capacity of 0 nothing is cached. Messages after the cached prefix
+    /// borrow from `messages`; the cache keeps its own copy.
+    #[must_use]
+    pub fn translate<'a>(&self, messages: &'a [Message], capacity: usize) -> Vec<ChatMessage<'a>> {
+        if capacity == 0 || messages.is_empty() {
+            return translate_conversation(messages);
+        }
+        let keys = prefix_keys(messages);
+        let cached = {
+            let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            inner.tick += 1;
+            let tick = inner.tick;
+            keys.iter().rev().find_map(|key| {
+                inner.entries.get_mut(key).map(|(translated, used)| {
+                    *used = tick;
+                    (key.0, Arc::clone(translated))
+                })
+            })
+        };
+        let (done, mut translated) = match cached {
+            Some((len, prefix)) if len == messages.len() => return prefix.as_ref().clone(),
+            Some((len, prefix)) => (len, prefix.as_ref().clone()),
+            None => (0, Vec::new()),
+        };
+        translated.extend(translate_conversation(&messages[done..]));
+        let owned = translated.iter().cloned().map(ChatMessage::into_owned);
+        self.insert(keys[keys.len() - 1], owned.collect(), capacity);
+        translated
+    }
+
+    /// How many prefixes are cached.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entries
+            .len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn insert(&self, key: Key, translated: Vec<ChatMessage<'static>>, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.entries.insert(key, (Arc::new(translated), tick));
+        while inner.entries.len() > capacity {
+            let Some(oldest) = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+    }
+}
+
+/// The key of every prefix of `messages`, shortest first.
+fn prefix_keys(messages: &[Message]) -> Vec<Key> {
+    let mut hasher = HashWriter(DefaultHasher::new());
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, message)| {
+            // A message that can't be serialized hashes as nothing; its
+            // position still separates it from its neighbours
+            let _ = serde_json::to_writer(&mut hasher, message);
+            hasher.0.write_u8(0);
+            (i + 1, hasher.0.finish())
+        })
+        .collect()
+}
+
+/// Feeds written bytes to a hasher.
+struct HashWriter(DefaultHasher);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::request::translate_messages;
+
+    fn conversation(turns: usize) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for turn in 0..turns {
+            messages.push(
+                serde_json::from_value(serde_json::json!({
+                    "role": "user",
+                    "content": format!("Question {turn}"),
+                }))
+                .unwrap(),
+            );
+            messages.push(
+                serde_json::from_value(serde_json::json!({
+                    "role": "assistant",
+                    "content": [
+                        {"type": "text", "text": format!("Answer {turn}")},
+                        {"type": "tool_use", "id": format!("call_{turn}"), "name": "read", "input": {"turn": turn}},
+                    ],
+                }))
+                .unwrap(),
+            );
+        }
+        messages
+    }
+
+    fn uncached(messages: &[Message]) -> String {
+        serde_json::to_string(&translate_messages(messages, 1)).unwrap()
+    }
+
+    #[test]
+    fn test_reuses_prefix() {
+        let cache = PrefixCache::new();
+        let messages = conversation(3);
+        let first = cache.translate(&messages[..3], 8);
+        assert_eq!(
+            serde_json::to_string(&first).unwrap(),
+            uncached(&messages[..3])
+        );
+        assert_eq!(cache.len(), 1);
+
+        // The next turn extends it: the same result as translating afresh
+        let second = cache.translate(&messages, 8);
+        assert_eq!(serde_json::to_string(&second).unwrap(), uncached(&messages));
+        assert_eq!(cache.len(), 2);
+        // A repeat is an exact hit, stored nowhere new
+        assert_eq!(cache.translate(&messages, 8).len(), second.len());
+        assert_eq!(cache.len(), 2);
+
+        // An edited earlier message shares no prefix past the edit
+        let mut edited = messages.clone();
+        edited[0].content = serde_json::from_value(serde_json::json!("Edited")).unwrap();
+        assert_eq!(
+            serde_json::to_string(&cache.translate(&edited, 8)).unwrap(),
+            uncached(&edited)
+        );
+
+        // The least recently used entries go first
+        let small = PrefixCache::new();
+        for turns in 1..=3 {
+            let _ = small.translate(&conversation(turns), 2);
+        }
+        assert_eq!(small.len(), 2);
+        assert!(PrefixCache::new().translate(&messages, 0).len() > 1);
+    }
+}
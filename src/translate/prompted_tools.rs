@@ -0,0 +1,778 @@
+//! Tool calling for models without function calling.
+//!
+//! Base models, and backends that serve a model without its tool template,
+//! can't be sent `tools`. With a model's `prompted_tools` set, the proxy
+//! describes the tools in the system prompt instead, with a convention for
+//! calling them: Hermes-style JSON in `<tool_call>` tags, or XML `<invoke>`
+//! blocks. Earlier calls and their results are written into the conversation
+//! the same way, as text. The model's output is scanned for calls as it
+//! streams, and each one found becomes an `OpenAI` tool call, which the rest
+//! of the proxy turns into a `tool_use` block like any other.
+//!
+//! A block that doesn't parse, or names a tool that wasn't offered, is passed
+//! through as text. Text after the first call is dropped: the turn ends with
+//! its calls, as it would with native tool use.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use super::anthropic_types::Tool;
+use super::openai_types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatContent, ChatMessage,
+    ChatTool, ChatToolCall, ChatToolCallFunction, ChatToolChoice, ChunkChoice, ChunkDelta,
+    ChunkToolCall, ChunkToolCallFunction, ContentPart,
+};
+use super::request::wrap_system_prompt;
+use super::think::partial_tag_len;
+
+/// How a prompted-tools model writes its tool calls (`prompted_tools`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolPrompt {
+    /// `<tool_call>{"name": ..., "arguments": {...}}</tool_call>`, the
+    /// convention of Hermes, Qwen and many other chat templates.
+    Json,
+    /// `<invoke name="..."><parameter name="...">...</parameter></invoke>`,
+    /// with string values written as they are.
+    Xml,
+}
+
+impl ToolPrompt {
+    fn open_tag(self) -> &'static str {
+        match self {
+            Self::Json => "<tool_call>",
+            Self::Xml => "<invoke",
+        }
+    }
+
+    fn close_tag(self) -> &'static str {
+        match self {
+            Self::Json => "</tool_call>",
+            Self::Xml => "</invoke>",
+        }
+    }
+}
+
+/// Opens the block a tool's result is written in. Also a stop sequence, so
+/// the model ends its turn at its calls rather than imagining their results.
+const RESULT_TAG: &str = "<tool_result";
+
+/// Opens an argument in an XML call.
+const PARAMETER_TAG: &str = "<parameter name=\"";
+
+/// Rewrite a translated request for a model that calls tools in its text:
+/// the offered tools are described in the system prompt, and earlier calls
+/// and results are written into the messages, in `format`'s convention.
+pub fn apply(openai_req: &mut ChatCompletionRequest<'_>, format: ToolPrompt) {
+    let tools = openai_req.tools.take().unwrap_or_default();
+    let choice = openai_req.tool_choice.take();
+    let rewrote = rewrite_history(&mut openai_req.messages, format);
+    let offered =
+        !tools.is_empty() && !matches!(&choice, Some(ChatToolChoice::String(s)) if s == "none");
+    if offered {
+        let instructions = instructions(&tools, choice.as_ref(), format);
+        wrap_system_prompt(openai_req, None, Some(&instructions));
+    }
+    if offered || rewrote {
+        let stop = openai_req.stop.get_or_insert_with(Vec::new);
+        if !stop.iter().any(|s| s == RESULT_TAG) {
+            stop.push(RESULT_TAG.to_string());
+        }
+    }
+}
+
+/// The system prompt section describing `tools` and how to call them.
+fn instructions(tools: &[ChatTool], choice: Option<&ChatToolChoice>, format: ToolPrompt) -> String {
+    let mut text = String::from("# Tools\n\nYou can call these tools:\n");
+    for tool in tools {
+        let function = &tool.function;
+        let _ = write!(text, "\n## {}\n", function.name);
+        if let Some(description) = &function.description {
+            text.push_str(description);
+            text.push('\n');
+        }
+        let _ = writeln!(text, "Parameters (JSON schema): {}", function.parameters);
+    }
+    text.push_str("\nTo call a tool, write:\n\n");
+    text.push_str(match format {
+        ToolPrompt::Json => {
+            "<tool_call>\n{\"name\": \"tool name\", \"arguments\": {\"parameter\": \"value\"}}\n</tool_call>\n\n\
+             The arguments are a JSON object matching the tool's schema."
+        }
+        ToolPrompt::Xml => {
+            "<invoke name=\"tool name\">\n<parameter name=\"parameter\">value</parameter>\n</invoke>\n\n\
+             Write one parameter element per argument. Write string values as they \
+             are, without quotes or escaping, and any other value as JSON."
+        }
+    });
+    text.push_str(
+        " Write one block per call, and nothing after your calls: the results come back \
+         in <tool_result> blocks in the next message. Only call the tools listed here.",
+    );
+    match choice {
+        Some(ChatToolChoice::String(s)) if s == "required" => {
+            text.push_str("\n\nYou must call at least one tool in this reply.");
+        }
+        Some(ChatToolChoice::Specific(specific)) => {
+            let _ = write!(
+                text,
+                "\n\nYou must call the {} tool in this reply.",
+                specific.function.name
+            );
+        }
+        _ => {}
+    }
+    text
+}
+
+/// Write the assistant tool calls and tool results in `messages` as text,
+/// merging each result into the user message around it. Returns whether
+/// there were any.
+fn rewrite_history(messages: &mut Vec<ChatMessage<'_>>, format: ToolPrompt) -> bool {
+    if !messages
+        .iter()
+        .any(|m| m.tool_calls.is_some() || m.role == "tool")
+    {
+        return false;
+    }
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut rewritten: Vec<ChatMessage<'_>> = Vec::with_capacity(messages.len());
+    let mut after_result = false;
+    for mut message in messages.drain(..) {
+        let result = message.role == "tool";
+        if let Some(calls) = message.tool_calls.take() {
+            let mut text = content_text(message.content.as_ref());
+            for call in &calls {
+                names.insert(call.id.clone(), call.function.name.clone());
+                if !text.is_empty() {
+                    text.push_str("\n\n");
+                }
+                text.push_str(&render_call(&call.function, format));
+            }
+            message.content = Some(ChatContent::Text(text.into()));
+        } else if result {
+            let name = message
+                .tool_call_id
+                .take()
+                .and_then(|id| names.get(&id).cloned())
+                .unwrap_or_default();
+            let open = format!("{RESULT_TAG} name=\"{name}\">\n");
+            let close = "\n</tool_result>";
+            message.role = "user".to_string();
+            message.name = None;
+            message.content = Some(match message.content.take() {
+                Some(ChatContent::Parts(parts)) => {
+                    let mut wrapped = vec![ContentPart::Text { text: open.into() }];
+                    wrapped.extend(parts);
+                    wrapped.push(ContentPart::Text { text: close.into() });
+                    ChatContent::Parts(wrapped)
+                }
+                content => ChatContent::Text(
+                    format!("{open}{}{close}", content_text(content.as_ref())).into(),
+                ),
+            });
+        }
+        match rewritten.last_mut() {
+            Some(last)
+                if last.role == "user" && message.role == "user" && (result || after_result) =>
+            {
+                append_content(&mut last.content, message.content);
+            }
+            _ => rewritten.push(message),
+        }
+        after_result = result;
+    }
+    *messages = rewritten;
+    true
+}
+
+/// The text of a message's content, its text parts joined.
+fn content_text(content: Option<&ChatContent>) -> String {
+    match content {
+        Some(ChatContent::Text(text)) => text.to_string(),
+        Some(ChatContent::Parts(parts)) => parts
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Text { text } => Some(text.as_ref()),
+                ContentPart::ImageUrl { .. } | ContentPart::File { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => String::new(),
+    }
+}
+
+/// Add `next` to the end of `content`, a blank line between their texts.
+fn append_content<'a>(content: &mut Option<ChatContent<'a>>, next: Option<ChatContent<'a>>) {
+    let Some(next) = next else {
+        return;
+    };
+    *content = Some(match (content.take(), next) {
+        (None, next) => next,
+        (Some(ChatContent::Text(a)), ChatContent::Text(b)) => {
+            ChatContent::Text(Cow::Owned(format!("{a}\n\n{b}")))
+        }
+        (Some(a), b) => {
+            let mut parts = into_parts(a);
+            parts.extend(into_parts(b));
+            ChatContent::Parts(parts)
+        }
+    });
+}
+
+fn into_parts(content: ChatContent<'_>) -> Vec<ContentPart<'_>> {
+    match content {
+        ChatContent::Text(text) => vec![ContentPart::Text { text }],
+        ChatContent::Parts(parts) => parts,
+    }
+}
+
+/// A tool call written in `format`'s convention.
+fn render_call(function: &ChatToolCallFunction, format: ToolPrompt) -> String {
+    let arguments: Value = serde_json::from_str(&function.arguments)
+        .unwrap_or_else(|_| Value::String(function.arguments.clone()));
+    match format {
+        ToolPrompt::Json => format!(
+            "<tool_call>\n{{\"name\": {}, \"arguments\": {arguments}}}\n</tool_call>",
+            Value::String(function.name.clone())
+        ),
+        ToolPrompt::Xml => {
+            let mut text = format!("<invoke name=\"{}\">\n", function.name);
+            if let Value::Object(arguments) = arguments {
+                for (key, value) in arguments {
+                    let value = match value {
+                        Value::String(s) => s,
+                        value => value.to_string(),
+                    };
+                    let _ = writeln!(text, "<parameter name=\"{key}\">{value}</parameter>");
+                }
+            }
+            text.push_str("</invoke>");
+            text
+        }
+    }
+}
+
+/// Recognises a prompted-tools model's calls in its output.
+#[derive(Debug, Clone)]
+pub struct PromptedTools {
+    format: ToolPrompt,
+    /// The offered tools by name, with the names of their string parameters.
+    tools: HashMap<String, Vec<String>>,
+}
+
+/// A settled run of model output.
+#[derive(Debug, Clone)]
+enum Output {
+    Text(String),
+    Call(ChatToolCallFunction),
+}
+
+impl PromptedTools {
+    /// Calls in `format` to the client tools among `tools`.
+    #[must_use]
+    pub fn new(format: ToolPrompt, tools: &[Tool]) -> Self {
+        let tools = tools
+            .iter()
+            .filter(|tool| !tool.is_server_tool())
+            .map(|tool| (tool.name.clone(), string_parameters(&tool.input_schema)))
+            .collect();
+        Self { format, tools }
+    }
+
+    /// Turn the calls written in a complete response's text into tool calls.
+    pub fn rewrite_response(&self, resp: &mut ChatCompletionResponse) {
+        for choice in &mut resp.choices {
+            let Some(content) = choice.message.content.take() else {
+                continue;
+            };
+            let mut parser = BlockParser::new(self.format);
+            let mut blocks = parser.push(&content);
+            blocks.extend(parser.finish());
+
+            let mut text = String::new();
+            let mut calls = Vec::new();
+            for output in blocks.into_iter().map(|block| self.settle(block)) {
+                match output {
+                    Output::Call(function) => calls.push(ChatToolCall {
+                        id: call_id(),
+                        call_type: "function".to_string(),
+                        function,
+                    }),
+                    Output::Text(s) if calls.is_empty() => text.push_str(&s),
+                    Output::Text(_) => {}
+                }
+            }
+            if calls.is_empty() {
+                choice.message.content = Some(text);
+                continue;
+            }
+            let text = text.trim_end();
+            choice.message.content = (!text.is_empty()).then(|| text.to_string());
+            choice
+                .message
+                .tool_calls
+                .get_or_insert_with(Vec::new)
+                .extend(calls);
+            if matches!(choice.finish_reason.as_deref(), None | Some("stop")) {
+                choice.finish_reason = Some("tool_calls".to_string());
+            }
+        }
+    }
+
+    /// A scanner for the calls in a streamed response.
+    #[must_use]
+    pub fn stream(&self) -> PromptedToolStream {
+        PromptedToolStream {
+            parser: BlockParser::new(self.format),
+            tools: self.clone(),
+            calls: 0,
+            last: None,
+        }
+    }
+
+    /// A block as a call if it is one, else as the text it was.
+    fn settle(&self, block: Block) -> Output {
+        match block {
+            Block::Text(text) => Output::Text(text),
+            Block::Call { body, closed } => self.parse_call(&body).map_or_else(
+                || {
+                    let close = if closed { self.format.close_tag() } else { "" };
+                    Output::Text(format!("{}{body}{close}", self.format.open_tag()))
+                },
+                Output::Call,
+            ),
+        }
+    }
+
+    /// The call written in a block's body, if it is one to an offered tool.
+    fn parse_call(&self, body: &str) -> Option<ChatToolCallFunction> {
+        let (name, arguments) = match self.format {
+            ToolPrompt::Json => {
+                let mut call: serde_json::Map<String, Value> =
+                    serde_json::from_str(body.trim()).ok()?;
+                let name = call.remove("name")?.as_str()?.to_string();
+                let arguments = match call.remove("arguments") {
+                    None => Value::Object(serde_json::Map::new()),
+                    // Some models write the arguments as a JSON string
+                    Some(Value::String(s)) => serde_json::from_str(&s).ok()?,
+                    Some(arguments) => arguments,
+                };
+                (name, arguments)
+            }
+            ToolPrompt::Xml => {
+                let rest = body.trim_start().strip_prefix("name=\"")?;
+                let (name, rest) = rest.split_once('"')?;
+                let mut rest = rest.trim_start().strip_prefix('>')?;
+                let strings = self.tools.get(name)?;
+                let mut arguments = serde_json::Map::new();
+                while let Some(start) = rest.find(PARAMETER_TAG) {
+                    let (key, after) = rest[start + PARAMETER_TAG.len()..].split_once("\">")?;
+                    let (value, after) = after.split_once("</parameter>")?;
+                    let value = if strings.iter().any(|s| s == key) {
+                        Value::String(value.to_string())
+                    } else {
+                        serde_json::from_str(value.trim())
+                            .unwrap_or_else(|_| Value::String(value.to_string()))
+                    };
+                    arguments.insert(key.to_string(), value);
+                    rest = after;
+                }
+                (name.to_string(), Value::Object(arguments))
+            }
+        };
+        if !self.tools.contains_key(&name) || !arguments.is_object() {
+            return None;
+        }
+        Some(ChatToolCallFunction {
+            name,
+            arguments: arguments.to_string(),
+        })
+    }
+}
+
+/// The properties of a JSON schema that take strings.
+fn string_parameters(schema: &Value) -> Vec<String> {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    properties
+        .iter()
+        .filter(|(_, property)| match &property["type"] {
+            Value::String(t) => t == "string",
+            Value::Array(types) => types.iter().any(|t| t == "string"),
+            _ => false,
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+fn call_id() -> String {
+    format!("call_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Turns the calls in a prompted-tools model's streamed text into tool call
+/// chunks, in order with the text around them.
+#[derive(Debug)]
+pub struct PromptedToolStream {
+    tools: PromptedTools,
+    parser: BlockParser,
+    calls: u64,
+    /// The last chunk seen, whose id and model later chunks are made with.
+    last: Option<ChatCompletionChunk>,
+}
+
+impl PromptedToolStream {
+    /// Process the next chunk, returning the chunks to pass on in its place.
+    pub fn process(&mut self, mut chunk: ChatCompletionChunk) -> Vec<ChatCompletionChunk> {
+        let Some(choice) = chunk.choices.first_mut() else {
+            return vec![chunk];
+        };
+        let content = choice.delta.content.take();
+        let finish_reason = choice.finish_reason.take();
+        let mut blocks = content
+            .map(|text| self.parser.push(&text))
+            .unwrap_or_default();
+        if finish_reason.is_some() {
+            blocks.extend(self.parser.finish());
+        }
+        self.last = Some(chunk.clone());
+
+        let mut chunks = vec![chunk];
+        chunks.extend(self.pieces(blocks));
+        if let Some(reason) = finish_reason {
+            let reason = if self.calls > 0 && reason == "stop" {
+                "tool_calls".to_string()
+            } else {
+                reason
+            };
+            chunks.push(self.chunk(|choice| choice.finish_reason = Some(reason)));
+        }
+        chunks
+    }
+
+    /// Flush what is held back at the end of a stream that ended without a
+    /// finish reason.
+    pub fn finish(&mut self) -> Vec<ChatCompletionChunk> {
+        let blocks = self.parser.finish();
+        self.pieces(blocks)
+    }
+
+    fn pieces(&mut self, blocks: Vec<Block>) -> Vec<ChatCompletionChunk> {
+        let mut chunks = Vec::new();
+        for output in blocks.into_iter().map(|block| self.tools.settle(block)) {
+            match output {
+                Output::Text(text) if self.calls == 0 => {
+                    chunks.push(self.chunk(|choice| choice.delta.content = Some(text)));
+                }
+                Output::Text(_) => {}
+                Output::Call(function) => {
+                    let index = self.calls;
+                    self.calls += 1;
+                    chunks.push(self.chunk(|choice| {
+                        choice.delta.tool_calls = Some(vec![ChunkToolCall {
+                            index,
+                            id: Some(call_id()),
+                            call_type: Some("function".to_string()),
+                            function: Some(ChunkToolCallFunction {
+                                name: Some(function.name),
+                                arguments: Some(function.arguments),
+                            }),
+                        }]);
+                    }));
+                }
+            }
+        }
+        chunks
+    }
+
+    /// An empty chunk like the last one seen, filled in by `fill`.
+    fn chunk(&self, fill: impl FnOnce(&mut ChunkChoice)) -> ChatCompletionChunk {
+        let mut chunk = self.last.clone().unwrap_or_else(|| ChatCompletionChunk {
+            id: String::new(),
+            object: "chat.completion.chunk".to_string(),
+            created: 0,
+            model: String::new(),
+            choices: Vec::new(),
+            usage: None,
+            system_fingerprint: None,
+            citations: None,
+        });
+        chunk.usage = None;
+        chunk.choices = vec![ChunkChoice {
+            index: 0,
+            delta: ChunkDelta::default(),
+            finish_reason: None,
+            logprobs: None,
+        }];
+        fill(&mut chunk.choices[0]);
+        chunk
+    }
+}
+
+/// A run of model output, before calls are parsed: text, or the body of a
+/// call block and whether it was closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Block {
+    Text(String),
+    Call { body: String, closed: bool },
+}
+
+/// Incremental splitter of model output into text and call blocks. A tag
+/// split across deltas is held back until it can be told apart from text.
+#[derive(Debug)]
+struct BlockParser {
+    open: &'static str,
+    close: &'static str,
+    in_call: bool,
+    pending: String,
+}
+
+impl BlockParser {
+    fn new(format: ToolPrompt) -> Self {
+        Self {
+            open: format.open_tag(),
+            close: format.close_tag(),
+            in_call: false,
+            pending: String::new(),
+        }
+    }
+
+    /// Feed the next delta, returning the blocks that are now settled.
+    fn push(&mut self, text: &str) -> Vec<Block> {
+        self.pending.push_str(text);
+        let mut blocks = Vec::new();
+        loop {
+            let tag = if self.in_call { self.close } else { self.open };
+            if let Some(pos) = self.pending.find(tag) {
+                let before: String = self.pending.drain(..pos).collect();
+                self.pending.drain(..tag.len());
+                if self.in_call {
+                    blocks.push(Block::Call {
+                        body: before,
+                        closed: true,
+                    });
+                } else if !before.is_empty() {
+                    blocks.push(Block::Text(before));
+                }
+                self.in_call = !self.in_call;
+            } else if self.in_call {
+                // A call's body is only settled by its closing tag
+                return blocks;
+            } else {
+                let keep = partial_tag_len(&self.pending, tag);
+                let settled: String = self.pending.drain(..self.pending.len() - keep).collect();
+                if !settled.is_empty() {
+                    blocks.push(Block::Text(settled));
+                }
+                return blocks;
+            }
+        }
+    }
+
+    /// Settle whatever is held back; an unclosed call block ends here.
+    fn finish(&mut self) -> Vec<Block> {
+        let rest = std::mem::take(&mut self.pending);
+        let in_call = std::mem::take(&mut self.in_call);
+        if in_call {
+            vec![Block::Call {
+                body: rest,
+                closed: false,
+            }]
+        } else if rest.is_empty() {
+            Vec::new()
+        } else {
+            vec![Block::Text(rest)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tools(format: ToolPrompt) -> PromptedTools {
+        let tools: Vec<Tool> = serde_json::from_value(serde_json::json!([
+            {"name": "Read", "description": "Read a file", "input_schema": {
+                "type": "object",
+                "properties": {"path": {"type": "string"}, "limit": {"type": "integer"}},
+            }},
+            {"name": "web_search", "type": "web_search_20250305"},
+        ]))
+        .unwrap();
+        PromptedTools::new(format, &tools)
+    }
+
+    fn response(content: &str) -> ChatCompletionResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "r", "object": "chat.completion", "model": "m",
+            "choices": [{"index": 0, "finish_reason": "stop",
+                         "message": {"role": "assistant", "content": content}}],
+        }))
+        .unwrap()
+    }
+
+    fn chunk(content: &str, finish_reason: Option<&str>) -> ChatCompletionChunk {
+        serde_json::from_value(serde_json::json!({
+            "id": "c", "object": "chat.completion.chunk", "model": "m",
+            "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": finish_reason}],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut req: ChatCompletionRequest<'static> = serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Read a.txt"},
+                {"role": "assistant", "content": "Reading.", "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": {"name": "Read", "arguments": "{\"path\":\"a.txt\"}"},
+                }]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "hello"},
+                {"role": "user", "content": "Thanks"},
+            ],
+            "tools": [{"type": "function", "function": {
+                "name": "Read", "description": "Read a file", "parameters": {"type": "object"},
+            }}],
+            "tool_choice": "required",
+        }))
+        .unwrap();
+        apply(&mut req, ToolPrompt::Json);
+
+        assert!(req.tools.is_none() && req.tool_choice.is_none());
+        assert_eq!(req.stop.as_deref(), Some(&[RESULT_TAG.to_string()][..]));
+        let text = |i: usize| content_text(req.messages[i].content.as_ref());
+        let system = text(0);
+        assert!(system.starts_with("Be brief.\n\n# Tools"), "{system}");
+        assert!(system.contains("## Read\nRead a file\n"));
+        assert!(system.contains("You must call at least one tool"));
+
+        let roles: Vec<&str> = req.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        assert!(req.messages[2].tool_calls.is_none());
+        assert_eq!(
+            text(2),
+            "Reading.\n\n<tool_call>\n{\"name\": \"Read\", \"arguments\": {\"path\":\"a.txt\"}}\n</tool_call>"
+        );
+        assert_eq!(
+            text(3),
+            "<tool_result name=\"Read\">\nhello\n</tool_result>\n\nThanks"
+        );
+
+        // No tools and no history leave the request alone
+        let mut plain: ChatCompletionRequest<'static> = serde_json::from_value(
+            serde_json::json!({"model": "m", "messages": [{"role": "user", "content": "Hi"}]}),
+        )
+        .unwrap();
+        apply(&mut plain, ToolPrompt::Xml);
+        assert_eq!(plain.messages.len(), 1);
+        assert!(plain.stop.is_none());
+    }
+
+    #[test]
+    fn test_rewrite_response() {
+        let tools = tools(ToolPrompt::Json);
+        let mut resp = response(
+            "Let me look.\n<tool_call>\n{\"name\": \"Read\", \"arguments\": {\"path\": \"a.txt\"}}\n</tool_call>\nDone.",
+        );
+        tools.rewrite_response(&mut resp);
+        let choice = &resp.choices[0];
+        assert_eq!(choice.message.content.as_deref(), Some("Let me look."));
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "Read");
+        assert_eq!(calls[0].function.arguments, r#"{"path":"a.txt"}"#);
+
+        // Unknown tools, bad JSON and server tools stay text
+        for text in [
+            "<tool_call>{\"name\": \"Write\", \"arguments\": {}}</tool_call>",
+            "<tool_call>{not json}</tool_call>",
+            "<tool_call>{\"name\": \"web_search\"}</tool_call>",
+        ] {
+            let mut resp = response(text);
+            tools.rewrite_response(&mut resp);
+            assert_eq!(resp.choices[0].message.content.as_deref(), Some(text));
+            assert!(resp.choices[0].message.tool_calls.is_none());
+            assert_eq!(resp.choices[0].finish_reason.as_deref(), Some("stop"));
+        }
+
+        // String arguments and an unclosed block still make a call
+        let mut resp = response(
+            "<tool_call>{\"name\": \"Read\", \"arguments\": \"{\\\"path\\\": \\\"b\\\"}\"}",
+        );
+        tools.rewrite_response(&mut resp);
+        let calls = resp.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].function.arguments, r#"{"path":"b"}"#);
+        assert!(resp.choices[0].message.content.is_none());
+    }
+
+    #[test]
+    fn test_xml_arguments_typed_by_schema() {
+        let tools = tools(ToolPrompt::Xml);
+        let mut resp = response(
+            "<invoke name=\"Read\">\n<parameter name=\"path\">42</parameter>\n\
+             <parameter name=\"limit\">10</parameter>\n</invoke>",
+        );
+        tools.rewrite_response(&mut resp);
+        let calls = resp.choices[0].message.tool_calls.as_ref().unwrap();
+        let arguments: Value = serde_json::from_str(&calls[0].function.arguments).unwrap();
+        assert_eq!(arguments, serde_json::json!({"path": "42", "limit": 10}));
+
+        // A call in the history renders back in the same convention
+        let function = ChatToolCallFunction {
+            name: "Read".to_string(),
+            arguments: r#"{"limit":10,"path":"a b"}"#.to_string(),
+        };
+        assert_eq!(
+            render_call(&function, ToolPrompt::Xml),
+            "<invoke name=\"Read\">\n<parameter name=\"limit\">10</parameter>\n\
+             <parameter name=\"path\">a b</parameter>\n</invoke>"
+        );
+    }
+
+    #[test]
+    fn test_stream_calls_split_across_chunks() {
+        let text = "Reading.<tool_call>{\"name\": \"Read\", \"arguments\": {\"path\": \"a\"}}</tool_call>\n";
+        for i in 1..text.len() {
+            let (a, b) = text.split_at(i);
+            let mut stream = tools(ToolPrompt::Json).stream();
+            let mut chunks = stream.process(chunk(a, None));
+            chunks.extend(stream.process(chunk(b, Some("stop"))));
+
+            let content: String = chunks
+                .iter()
+                .filter_map(|c| c.choices[0].delta.content.as_deref())
+                .collect();
+            assert_eq!(content, "Reading.", "split at {i}");
+            let calls: Vec<&ChunkToolCall> = chunks
+                .iter()
+                .filter_map(|c| c.choices[0].delta.tool_calls.as_ref())
+                .flatten()
+                .collect();
+            assert_eq!(calls.len(), 1, "split at {i}");
+            let function = calls[0].function.as_ref().unwrap();
+            assert_eq!(function.name.as_deref(), Some("Read"));
+            assert_eq!(function.arguments.as_deref(), Some(r#"{"path":"a"}"#));
+            let last = chunks.last().unwrap();
+            assert_eq!(last.choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        }
+
+        // Plain text passes straight through, an incomplete tag held back
+        let mut stream = tools(ToolPrompt::Json).stream();
+        let chunks = stream.process(chunk("1 < 2 <tool", None));
+        assert_eq!(
+            chunks[1].choices[0].delta.content.as_deref(),
+            Some("1 < 2 ")
+        );
+        let chunks = stream.finish();
+        assert_eq!(chunks[0].choices[0].delta.content.as_deref(), Some("<tool"));
+    }
+}
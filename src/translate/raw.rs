@@ -0,0 +1,104 @@
+//! Editing a JSON body's top-level fields without parsing what's in them.
+//!
+//! Renaming the model of a passthrough request, or merging a provider's extra
+//! fields into a translated one, only touches the top level of the body. A
+//! [`RawObject`] parses just that level: each field's value stays the JSON
+//! text it was, borrowed from the body, and is written back out unchanged. A
+//! conversation of megabytes is copied once, into the new body, rather than
+//! parsed into a `serde_json::Value` tree and serialized again.
+
+use serde::Serialize;
+use serde_json::value::RawValue;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// A JSON object's fields, their values unparsed; see the module docs.
+#[derive(Debug)]
+pub struct RawObject<'a> {
+    fields: BTreeMap<String, Cow<'a, RawValue>>,
+}
+
+impl<'a> RawObject<'a> {
+    /// The fields of the JSON object in `body`.
+    ///
+    /// # Errors
+    /// Returns the parse error if `body` isn't a JSON object.
+    pub fn parse(body: &'a [u8]) -> serde_json::Result<Self> {
+        let fields: BTreeMap<String, &'a RawValue> = serde_json::from_slice(body)?;
+        Ok(Self {
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key, Cow::Borrowed(value)))
+                .collect(),
+        })
+    }
+
+    /// Set `key` to `value`, replacing any value it had.
+    ///
+    /// # Errors
+    /// Returns the error if `value` can't be serialized.
+    pub fn set(&mut self, key: &str, value: &impl Serialize) -> serde_json::Result<()> {
+        let value = serde_json::value::to_raw_value(value)?;
+        self.fields.insert(key.to_string(), Cow::Owned(value));
+        Ok(())
+    }
+
+    /// Set `key` to `value` unless it already has one.
+    ///
+    /// # Errors
+    /// Returns the error if `value` can't be serialized.
+    pub fn set_default(&mut self, key: &str, value: &impl Serialize) -> serde_json::Result<()> {
+        if !self.fields.contains_key(key) {
+            self.set(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// The raw JSON of `key`'s value, if it has one.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(|value| value.get())
+    }
+
+    /// The object as JSON.
+    ///
+    /// # Errors
+    /// Returns the error if writing the JSON fails.
+    pub fn to_vec(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&self.fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_top_level() {
+        let body =
+            r#"{"model":"claude","messages":[{"role":"user","content":"café  \"x\""}],"n":1}"#;
+        let mut object = RawObject::parse(body.as_bytes()).unwrap();
+        // Values are kept exactly as written, escapes and spacing included
+        assert_eq!(
+            object.get("messages"),
+            Some(r#"[{"role":"user","content":"café  \"x\""}]"#)
+        );
+
+        object.set("model", &"kimi").unwrap();
+        object.set_default("n", &2).unwrap();
+        object.set_default("temperature", &0.5).unwrap();
+        let out: serde_json::Value = serde_json::from_slice(&object.to_vec().unwrap()).unwrap();
+        assert_eq!(
+            out,
+            serde_json::json!({
+                "model": "kimi",
+                "messages": [{"role": "user", "content": "café  \"x\""}],
+                "n": 1,
+                "temperature": 0.5,
+            })
+        );
+
+        assert!(RawObject::parse(b"[1, 2]").is_err());
+    }
+}
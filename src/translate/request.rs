@@ -1,64 +1,110 @@
 //! Translate Anthropic Messages API requests into `OpenAI` Chat Completions requests.
 //!
-//! Handles system messages, multi-part content (text, images), tool use, tool results,
+//! Handles system messages, multi-part content (text, images, documents), tool use, tool results,
 //! and tool choice mapping. A single Anthropic message can expand into multiple `OpenAI`
 //! messages (e.g. a user message with `tool_result` blocks becomes separate `tool`-role messages).
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::hash::BuildHasher;
+use std::num::NonZeroUsize;
+use std::thread;
 
 use super::anthropic_types::{
-    ContentBlock, Message, MessagesRequest, Role, ToolChoice, ToolChoiceAuto, ToolChoiceSpecific,
+    ContentBlock, Message, MessageContent, MessagesRequest, Role, SystemContent, ToolChoice,
+    ToolChoiceAuto, ToolChoiceSpecific, ToolResultContent,
 };
+use super::documents;
 use super::openai_types::{
     ChatCompletionRequest, ChatContent, ChatFunction, ChatMessage, ChatTool, ChatToolCall,
     ChatToolCallFunction, ChatToolChoice, ChatToolChoiceFunction, ChatToolChoiceSpecific,
-    ContentPart, ImageUrlDetail, StreamOptions,
+    ContentPart, ImageUrlDetail, JsonSchemaFormat, ResponseFormat, StreamOptions,
 };
+use super::prefix_cache::PrefixCache;
 
 /// Translate an Anthropic Messages API request into an `OpenAI` Chat Completions request.
-/// Pure function: takes the request + model mapping, returns the translated request.
-pub fn anthropic_to_openai<S: BuildHasher>(
-    req: &MessagesRequest,
+/// Pure function: takes the request + model mapping, returns the translated request,
+/// which borrows the request's message text and tool schemas.
+pub fn anthropic_to_openai<'a, S: BuildHasher>(
+    req: &'a MessagesRequest,
     model_map: &HashMap<String, String, S>,
-) -> ChatCompletionRequest {
-    let target_model = model_map
-        .get(&req.model)
-        .cloned()
-        .unwrap_or_else(|| req.model.clone());
+) -> ChatCompletionRequest<'a> {
+    let target_model = model_map.get(&req.model).unwrap_or(&req.model);
+    anthropic_to_openai_for_model(req, target_model)
+}
 
-    let mut messages = Vec::new();
+/// Translate an Anthropic request for an already-resolved backend model.
+/// Used when routing has picked the provider and model (see [`crate::config::ProxyConfig::route`]).
+#[must_use]
+pub fn anthropic_to_openai_for_model<'a>(
+    req: &'a MessagesRequest,
+    target_model: &str,
+) -> ChatCompletionRequest<'a> {
+    let conversation = translate_conversation(&req.messages);
+    with_conversation(req, target_model, conversation)
+}
+
+/// As [`anthropic_to_openai_for_model`], reusing the translation of the
+/// conversation's earlier turns from `cache` (keeping up to `capacity` of them).
+#[must_use]
+pub fn anthropic_to_openai_cached<'a>(
+    req: &'a MessagesRequest,
+    target_model: &str,
+    cache: &PrefixCache,
+    capacity: usize,
+) -> ChatCompletionRequest<'a> {
+    let conversation = cache.translate(&req.messages, capacity);
+    with_conversation(req, target_model, conversation)
+}
+
+/// The translated request around its already translated `conversation`.
+fn with_conversation<'a>(
+    req: &'a MessagesRequest,
+    target_model: &str,
+    conversation: Vec<ChatMessage<'a>>,
+) -> ChatCompletionRequest<'a> {
+    let mut messages = Vec::with_capacity(conversation.len() + 1);
 
     if let Some(ref system) = req.system {
+        let text = match system {
+            SystemContent::Text(text) => Cow::Borrowed(text.as_str()),
+            SystemContent::Blocks(_) => Cow::Owned(system.as_text()),
+        };
         messages.push(ChatMessage {
             role: "system".to_string(),
-            content: Some(ChatContent::Text(system.as_text())),
+            content: Some(ChatContent::Text(text)),
             tool_calls: None,
             tool_call_id: None,
             name: None,
         });
     }
+    messages.extend(conversation);
 
-    for msg in &req.messages {
-        let mut translated = translate_message(msg);
-        messages.append(&mut translated);
-    }
-
-    let tools = req.tools.as_ref().map(|tools| {
-        tools
+    // Server tools (web search etc.) run on Anthropic's side and have no
+    // function-calling equivalent; backends that support them map them
+    // separately (see `translate::grok`).
+    let tools = req.tools.as_ref().and_then(|tools| {
+        let functions: Vec<ChatTool> = tools
             .iter()
+            .filter(|t| !t.is_server_tool())
             .map(|t| ChatTool {
                 tool_type: "function".to_string(),
                 function: ChatFunction {
                     name: t.name.clone(),
-                    description: t.description.clone(),
-                    parameters: t.input_schema.clone(),
+                    description: t.description.as_deref().map(Cow::Borrowed),
+                    parameters: Cow::Borrowed(&t.input_schema),
                 },
             })
-            .collect()
+            .collect();
+        (!functions.is_empty()).then_some(functions)
     });
 
-    let tool_choice = req.tool_choice.as_ref().map(translate_tool_choice);
+    // Without function tools left, a tool_choice would be rejected upstream
+    let tool_choice = req
+        .tool_choice
+        .as_ref()
+        .filter(|_| tools.is_some())
+        .map(translate_tool_choice);
 
     let stream_options = req.stream.filter(|s| *s).map(|_| StreamOptions {
         include_usage: true,
@@ -67,7 +113,7 @@ pub fn anthropic_to_openai<S: BuildHasher>(
     let user = req.metadata.as_ref().and_then(|m| m.user_id.clone());
 
     ChatCompletionRequest {
-        model: target_model,
+        model: target_model.to_string(),
         messages,
         max_tokens: Some(req.max_tokens),
         temperature: req.temperature,
@@ -78,56 +124,194 @@ pub fn anthropic_to_openai<S: BuildHasher>(
         tool_choice,
         stop: req.stop_sequences.clone(),
         user,
+        reasoning_effort: None,
+        search_parameters: None,
+        response_format: translate_output_format(req),
+        prompt_cache_key: None,
+        extra: serde_json::Map::new(),
     }
 }
 
+/// Put `prefix` before and `suffix` after the request's system prompt, each
+/// separated from it by a blank line. A request without one gets a system
+/// message of its own.
+pub fn wrap_system_prompt(
+    openai_req: &mut ChatCompletionRequest<'_>,
+    prefix: Option<&str>,
+    suffix: Option<&str>,
+) {
+    if prefix.is_none() && suffix.is_none() {
+        return;
+    }
+    let system = match openai_req.messages.first() {
+        Some(message) if message.role == "system" => message.content.as_ref(),
+        _ => None,
+    };
+    let current = match system {
+        Some(ChatContent::Text(text)) => text.as_ref(),
+        _ => "",
+    };
+    let text = [prefix, Some(current), suffix]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let content = Some(ChatContent::Text(Cow::Owned(text)));
+    match openai_req.messages.first_mut() {
+        Some(message) if message.role == "system" => message.content = content,
+        _ => openai_req.messages.insert(
+            0,
+            ChatMessage {
+                role: "system".to_string(),
+                content,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+            },
+        ),
+    }
+}
+
+/// Map an Anthropic structured-output request (`output_format` with a JSON
+/// schema) to an `OpenAI` `response_format`.
+fn translate_output_format(req: &MessagesRequest) -> Option<ResponseFormat> {
+    let format = req.extra.get("output_format")?;
+    match format.get("type").and_then(serde_json::Value::as_str) {
+        Some("json_schema") => Some(ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: "output".to_string(),
+                schema: format.get("schema").cloned().unwrap_or_default(),
+                strict: Some(true),
+            },
+        }),
+        Some("json" | "json_object") => Some(ResponseFormat::JsonObject { schema: None }),
+        _ => None,
+    }
+}
+
+/// Conversations shorter than this are translated on the calling thread.
+const PARALLEL_MIN_MESSAGES: usize = 256;
+
+/// The most threads one conversation is translated on.
+const MAX_TRANSLATION_THREADS: usize = 8;
+
+/// Translate a conversation's messages, on several threads if it is long.
+pub(super) fn translate_conversation(messages: &[Message]) -> Vec<ChatMessage<'_>> {
+    let threads = if messages.len() < PARALLEL_MIN_MESSAGES {
+        1
+    } else {
+        thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(MAX_TRANSLATION_THREADS)
+    };
+    translate_messages(messages, threads)
+}
+
+/// Translate `messages` in order, splitting them into up to `threads`
+/// consecutive chunks translated at once. Each message translates on its own,
+/// so the result is the same however they're split.
+#[must_use]
+pub fn translate_messages<'a>(messages: &'a [Message], threads: usize) -> Vec<ChatMessage<'a>> {
+    let chunk_size = messages.len().div_ceil(threads.max(1)).max(1);
+    if chunk_size >= messages.len() {
+        return messages.iter().flat_map(translate_message).collect();
+    }
+    let translate = |chunk: &'a [Message]| -> Vec<ChatMessage<'a>> {
+        chunk.iter().flat_map(translate_message).collect()
+    };
+    thread::scope(|scope| {
+        let mut chunks = messages.chunks(chunk_size);
+        let first = chunks.next().unwrap_or_default();
+        let rest: Vec<_> = chunks
+            .map(|chunk| scope.spawn(move || translate(chunk)))
+            .collect();
+        // The calling thread takes the first chunk rather than waiting idle
+        let mut translated = translate(first);
+        for handle in rest {
+            match handle.join() {
+                Ok(chunk) => translated.extend(chunk),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        translated
+    })
+}
+
 /// A single Anthropic message can expand to multiple `OpenAI` messages
 /// (e.g. a user message with `tool_results` becomes separate tool-role messages).
-fn translate_message(msg: &Message) -> Vec<ChatMessage> {
-    let blocks = msg.content.blocks();
-
-    match msg.role {
-        Role::User => translate_user_message(&blocks),
-        Role::Assistant => translate_assistant_message(&blocks),
+pub(super) fn translate_message(msg: &Message) -> Vec<ChatMessage<'_>> {
+    match (&msg.content, &msg.role) {
+        (MessageContent::Text(text), role) => vec![ChatMessage {
+            role: match role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+            }
+            .to_string(),
+            content: Some(ChatContent::Text(Cow::Borrowed(text))),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }],
+        (MessageContent::Blocks(blocks), Role::User) => translate_user_message(blocks),
+        (MessageContent::Blocks(blocks), Role::Assistant) => translate_assistant_message(blocks),
     }
 }
 
-fn translate_user_message(blocks: &[ContentBlock]) -> Vec<ChatMessage> {
+fn translate_user_message(blocks: &[ContentBlock]) -> Vec<ChatMessage<'_>> {
     let mut messages = Vec::new();
     let mut content_parts: Vec<ContentPart> = Vec::new();
+    // Documents read by tools, sent after the tool messages
+    let mut attachments: Vec<ContentPart> = Vec::new();
 
     for block in blocks {
         match block {
-            ContentBlock::Text { text } => {
-                content_parts.push(ContentPart::Text { text: text.clone() });
+            ContentBlock::Text { text, .. } => {
+                content_parts.push(ContentPart::Text {
+                    text: Cow::Borrowed(text),
+                });
             }
             ContentBlock::Image { source } => {
-                let data_uri = format!("data:{};base64,{}", source.media_type, source.data);
+                // A URL source is passed on as is; backends that can't fetch
+                // it have it inlined by the proxy (`inline_image_urls`)
+                let url = source.url.clone().unwrap_or_else(|| {
+                    format!("data:{};base64,{}", source.media_type, source.data)
+                });
                 content_parts.push(ContentPart::ImageUrl {
-                    image_url: ImageUrlDetail {
-                        url: data_uri,
-                        detail: None,
-                    },
+                    image_url: ImageUrlDetail { url, detail: None },
                 });
             }
+            ContentBlock::Document {
+                source,
+                title,
+                context,
+                ..
+            } => {
+                content_parts.push(documents::part(
+                    source,
+                    title.as_deref(),
+                    context.as_deref(),
+                ));
+            }
             ContentBlock::ToolResult {
                 tool_use_id,
                 content,
                 is_error,
+                ..
             } => {
                 // Flush any accumulated content parts as a user message first
                 if !content_parts.is_empty() {
                     messages.push(ChatMessage {
                         role: "user".to_string(),
-                        content: Some(collapse_content_parts(&content_parts)),
+                        content: Some(collapse_content_parts(std::mem::take(&mut content_parts))),
                         tool_calls: None,
                         tool_call_id: None,
                         name: None,
                     });
-                    content_parts.clear();
                 }
 
-                let result_text = tool_result_to_string(content.as_ref(), *is_error);
+                let result_text = tool_result_text(content.as_ref(), *is_error);
+                attachments.extend(tool_result_documents(content.as_ref()));
 
                 messages.push(ChatMessage {
                     role: "tool".to_string(),
@@ -141,10 +325,11 @@ fn translate_user_message(blocks: &[ContentBlock]) -> Vec<ChatMessage> {
         }
     }
 
+    content_parts.extend(attachments);
     if !content_parts.is_empty() {
         messages.push(ChatMessage {
             role: "user".to_string(),
-            content: Some(collapse_content_parts(&content_parts)),
+            content: Some(collapse_content_parts(content_parts)),
             tool_calls: None,
             tool_call_id: None,
             name: None,
@@ -155,7 +340,7 @@ fn translate_user_message(blocks: &[ContentBlock]) -> Vec<ChatMessage> {
     if messages.is_empty() {
         messages.push(ChatMessage {
             role: "user".to_string(),
-            content: Some(ChatContent::Text(String::new())),
+            content: Some(ChatContent::Text(Cow::Borrowed(""))),
             tool_calls: None,
             tool_call_id: None,
             name: None,
@@ -165,14 +350,14 @@ fn translate_user_message(blocks: &[ContentBlock]) -> Vec<ChatMessage> {
     messages
 }
 
-fn translate_assistant_message(blocks: &[ContentBlock]) -> Vec<ChatMessage> {
-    let mut text_parts: Vec<String> = Vec::new();
+fn translate_assistant_message(blocks: &[ContentBlock]) -> Vec<ChatMessage<'_>> {
+    let mut text_parts: Vec<&str> = Vec::new();
     let mut tool_calls: Vec<ChatToolCall> = Vec::new();
 
     for block in blocks {
         match block {
-            ContentBlock::Text { text } => {
-                text_parts.push(text.clone());
+            ContentBlock::Text { text, .. } => {
+                text_parts.push(text);
             }
             ContentBlock::ToolUse { id, name, input } => {
                 tool_calls.push(ChatToolCall {
@@ -180,20 +365,21 @@ fn translate_assistant_message(blocks: &[ContentBlock]) -> Vec<ChatMessage> {
                     call_type: "function".to_string(),
                     function: ChatToolCallFunction {
                         name: name.clone(),
-                        arguments: serde_json::to_string(input).unwrap_or_default(),
+                        arguments: tool_input_to_arguments(input),
                     },
                 });
             }
             ContentBlock::Thinking { .. }
             | ContentBlock::Image { .. }
+            | ContentBlock::Document { .. }
             | ContentBlock::ToolResult { .. } => {}
         }
     }
 
-    let content = if text_parts.is_empty() {
-        None
-    } else {
-        Some(ChatContent::Text(text_parts.join("")))
+    let content = match text_parts[..] {
+        [] => None,
+        [text] => Some(ChatContent::Text(Cow::Borrowed(text))),
+        _ => Some(ChatContent::Text(Cow::Owned(text_parts.concat()))),
     };
 
     let tool_calls_opt = if tool_calls.is_empty() {
@@ -211,41 +397,100 @@ fn translate_assistant_message(blocks: &[ContentBlock]) -> Vec<ChatMessage> {
     }]
 }
 
-fn collapse_content_parts(parts: &[ContentPart]) -> ChatContent {
-    if parts.len() == 1 {
-        if let ContentPart::Text { text } = &parts[0] {
-            return ChatContent::Text(text.clone());
-        }
+fn collapse_content_parts(mut parts: Vec<ContentPart<'_>>) -> ChatContent<'_> {
+    if let [ContentPart::Text { text }] = parts.as_mut_slice() {
+        return ChatContent::Text(std::mem::take(text));
     }
-    ChatContent::Parts(parts.to_vec())
+    ChatContent::Parts(parts)
 }
 
-fn tool_result_to_string(
-    content: Option<&super::anthropic_types::ToolResultContent>,
-    is_error: Option<bool>,
-) -> String {
-    let prefix = if is_error == Some(true) {
-        "ERROR: "
-    } else {
-        ""
-    };
-
-    match content {
-        Some(super::anthropic_types::ToolResultContent::Text(t)) => {
-            format!("{prefix}{t}")
-        }
-        Some(super::anthropic_types::ToolResultContent::Blocks(blocks)) => {
-            let text: String = blocks
+/// A tool result as text, borrowed from the request where it's a single text
+/// (the usual case, and the bulk of a Claude Code conversation).
+fn tool_result_text(content: Option<&ToolResultContent>, is_error: Option<bool>) -> Cow<'_, str> {
+    let text = match content {
+        Some(ToolResultContent::Text(t)) => Cow::Borrowed(t.as_str()),
+        Some(ToolResultContent::Blocks(blocks)) => {
+            let texts: Vec<Cow<'_, str>> = blocks
                 .iter()
                 .filter_map(|b| match b {
-                    ContentBlock::Text { text } => Some(text.as_str()),
+                    ContentBlock::Text { text, .. } => Some(Cow::Borrowed(text.as_str())),
+                    ContentBlock::Document {
+                        source,
+                        title,
+                        context,
+                        ..
+                    } => {
+                        let part = documents::part(source, title.as_deref(), context.as_deref());
+                        Some(Cow::Owned(documents::tool_result_text(&part).into_owned()))
+                    }
                     _ => None,
                 })
-                .collect::<Vec<_>>()
-                .join("\n");
-            format!("{prefix}{text}")
+                .collect();
+            match &texts[..] {
+                [Cow::Borrowed(text)] => Cow::Borrowed(*text),
+                _ => Cow::Owned(texts.join("\n")),
+            }
         }
-        None => format!("{prefix}(no content)"),
+        Some(ToolResultContent::Raw(value)) => Cow::Owned(raw_tool_result_text(value)),
+        None => Cow::Borrowed("(no content)"),
+    };
+    if is_error == Some(true) {
+        Cow::Owned(format!("ERROR: {text}"))
+    } else {
+        text
+    }
+}
+
+/// The PDFs in a tool result, as file parts to send after it.
+fn tool_result_documents(content: Option<&ToolResultContent>) -> Vec<ContentPart<'static>> {
+    let Some(ToolResultContent::Blocks(blocks)) = content else {
+        return Vec::new();
+    };
+    blocks
+        .iter()
+        .filter_map(|b| match b {
+            ContentBlock::Document {
+                source,
+                title,
+                context,
+                ..
+            } => Some(documents::part(
+                source,
+                title.as_deref(),
+                context.as_deref(),
+            )),
+            _ => None,
+        })
+        .filter(|part| matches!(part, ContentPart::File { .. }))
+        .collect()
+}
+
+/// Flatten a non-standard `tool_result` payload into text. Text blocks (wrapped
+/// or not) contribute their text; anything else is forwarded as compact JSON.
+fn raw_tool_result_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .map(raw_tool_result_text)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        serde_json::Value::Object(obj) => match (obj.get("type"), obj.get("text")) {
+            (Some(t), Some(serde_json::Value::String(text))) if t == "text" => text.clone(),
+            _ => value.to_string(),
+        },
+        other => other.to_string(),
+    }
+}
+
+/// Tool inputs normally arrive as JSON objects, but token-efficient clients may
+/// send them pre-encoded as a JSON string. Avoid double-encoding those.
+fn tool_input_to_arguments(input: &serde_json::Value) -> String {
+    match input {
+        serde_json::Value::String(s) if serde_json::from_str::<serde_json::Value>(s).is_ok() => {
+            s.clone()
+        }
+        other => serde_json::to_string(other).unwrap_or_default(),
     }
 }
 
@@ -318,9 +563,11 @@ mod tests {
                         tool_use_id: "toolu_1".to_string(),
                         content: Some(ToolResultContent::Text("result 1".to_string())),
                         is_error: None,
+                        cache_control: None,
                     },
                     ContentBlock::Text {
                         text: "Now continue".to_string(),
+                        cache_control: None,
                     },
                 ]),
             }],
@@ -376,4 +623,267 @@ mod tests {
         let result = anthropic_to_openai(&req, &HashMap::new());
         assert_eq!(result.model, "some-unknown-model");
     }
+
+    #[test]
+    fn test_output_format_maps_to_response_format() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "Extract the city"}],
+            "output_format": {
+                "type": "json_schema",
+                "schema": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }
+        }))
+        .unwrap();
+
+        let result = anthropic_to_openai(&req, &HashMap::new());
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["response_format"]["type"], "json_schema");
+        assert_eq!(
+            json["response_format"]["json_schema"]["schema"]["properties"]["city"]["type"],
+            "string"
+        );
+    }
+
+    #[test]
+    fn test_server_tools_only() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "What's new in Rust?"}],
+            "tools": [{"type": "web_search_20250305", "name": "web_search", "max_uses": 3}],
+            "tool_choice": {"type": "auto"}
+        }))
+        .unwrap();
+
+        let result = anthropic_to_openai(&req, &HashMap::new());
+        assert!(result.tools.is_none());
+        assert!(result.tool_choice.is_none());
+    }
+
+    #[test]
+    fn test_image_sources() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": [
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/cat.jpg"}},
+                {"type": "text", "text": "Same cat?"}
+            ]}]
+        }))
+        .unwrap();
+
+        let result = anthropic_to_openai(&req, &HashMap::new());
+        let json = serde_json::to_value(&result).unwrap();
+        let content = &json["messages"][0]["content"];
+        assert_eq!(
+            content[0]["image_url"]["url"],
+            "data:image/png;base64,iVBORw0KGgo="
+        );
+        assert_eq!(
+            content[1]["image_url"]["url"],
+            "https://example.com/cat.jpg"
+        );
+        assert_eq!(content[2]["text"], "Same cat?");
+    }
+
+    #[test]
+    fn test_documents() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "max_tokens": 100,
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "document", "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBERi0="},
+                     "title": "spec", "citations": {"enabled": true}},
+                    {"type": "document", "source": {"type": "text", "media_type": "text/plain", "data": "Plain notes"},
+                     "title": "notes.txt", "context": "Meeting notes"},
+                    {"type": "document", "source": {"type": "url", "url": "https://example.com/a.pdf"}},
+                    {"type": "text", "text": "Summarize these"}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "Read", "input": {"file_path": "b.pdf"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": [
+                        {"type": "text", "text": "PDF file read"},
+                        {"type": "document", "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBERi0="},
+                         "title": "b.pdf"}
+                    ]},
+                    {"type": "text", "text": "And this one?"}
+                ]}
+            ]
+        }))
+        .unwrap();
+
+        let result = anthropic_to_openai(&req, &HashMap::new());
+        let json = serde_json::to_value(&result).unwrap();
+        let content = &json["messages"][0]["content"];
+        assert_eq!(content[0]["type"], "file");
+        assert_eq!(content[0]["file"]["filename"], "spec.pdf");
+        assert_eq!(
+            content[0]["file"]["file_data"],
+            "data:application/pdf;base64,JVBERi0="
+        );
+        assert_eq!(
+            content[1]["text"],
+            "<document name=\"notes.txt\">\nMeeting notes\n\nPlain notes\n</document>"
+        );
+        assert!(content[2]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("[Document \"https://example.com/a.pdf\" omitted"));
+
+        // A tool's PDF follows its result, in the user message after it
+        assert_eq!(json["messages"][2]["role"], "tool");
+        assert_eq!(
+            json["messages"][2]["content"],
+            "PDF file read\n[Document \"b.pdf\" attached in the next message]"
+        );
+        let content = &json["messages"][3]["content"];
+        assert_eq!(content[0]["text"], "And this one?");
+        assert_eq!(content[1]["file"]["filename"], "b.pdf");
+    }
+
+    #[test]
+    fn test_token_efficient_tool_result_shapes() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "max_tokens": 100,
+            "betas": ["token-efficient-tools-2025-02-19"],
+            "messages": [
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "read", "input": "{\"path\":\"a.rs\"}"}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1",
+                     "content": {"type": "text", "text": "fn main() {}"}},
+                    {"type": "tool_result", "tool_use_id": "toolu_2",
+                     "content": [{"type": "text", "text": "a"}, {"type": "tool_reference", "name": "x"}]}
+                ]}
+            ]
+        }))
+        .unwrap();
+
+        assert!(req.has_beta(TOKEN_EFFICIENT_TOOLS_BETA));
+        assert!(req.extra.is_empty());
+
+        let result = anthropic_to_openai(&req, &HashMap::new());
+        let call = &result.messages[0].tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.function.arguments, "{\"path\":\"a.rs\"}");
+
+        assert_eq!(result.messages[1].role, "tool");
+        assert!(matches!(
+            result.messages[1].content,
+            Some(ChatContent::Text(ref t)) if t == "fn main() {}"
+        ));
+        assert!(matches!(
+            result.messages[2].content,
+            Some(ChatContent::Text(ref t)) if t == "a\n{\"name\":\"x\",\"type\":\"tool_reference\"}"
+        ));
+    }
+
+    #[test]
+    fn test_parallel_translation() {
+        let mut messages = Vec::new();
+        for turn in 0..300 {
+            messages.push(serde_json::json!({"role": "assistant", "content": [
+                {"type": "text", "text": format!("Reading {turn}")},
+                {"type": "tool_use", "id": format!("toolu_{turn}"), "name": "read", "input": {"turn": turn}}
+            ]}));
+            messages.push(serde_json::json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": format!("toolu_{turn}"), "content": "ok"},
+                {"type": "text", "text": "Next"}
+            ]}));
+        }
+        let messages: Vec<Message> = serde_json::from_value(messages.into()).unwrap();
+
+        // However the conversation is split, the translation is the same, in order
+        let sequential = serde_json::to_value(translate_messages(&messages, 1)).unwrap();
+        assert_eq!(sequential.as_array().unwrap().len(), 900);
+        for threads in [2, 7, 1000] {
+            assert_eq!(
+                serde_json::to_value(translate_messages(&messages, threads)).unwrap(),
+                sequential
+            );
+        }
+        assert_eq!(
+            serde_json::to_value(translate_conversation(&messages)).unwrap(),
+            sequential
+        );
+    }
+
+    #[test]
+    fn test_borrows_request_text() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "system": "Be brief.",
+            "messages": [
+                {"role": "user", "content": "Read it"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "read", "input": {}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "fn main() {}"},
+                    {"type": "tool_result", "tool_use_id": "toolu_2", "content": "failed", "is_error": true}
+                ]}
+            ],
+            "tools": [{"name": "read", "description": "Read a file", "input_schema": {"type": "object"}}]
+        }))
+        .unwrap();
+        let result = anthropic_to_openai(&req, &HashMap::new());
+
+        // Text, tool results and tool schemas are the request's, not copies
+        let borrowed = |message: &ChatMessage| {
+            matches!(message.content, Some(ChatContent::Text(Cow::Borrowed(_))))
+        };
+        assert!(borrowed(&result.messages[0]));
+        assert!(borrowed(&result.messages[1]));
+        assert!(borrowed(&result.messages[3]));
+        let function = &result.tools.as_ref().unwrap()[0].function;
+        assert!(matches!(function.parameters, Cow::Borrowed(_)));
+        assert!(matches!(function.description, Some(Cow::Borrowed(_))));
+        // An error result gets its prefix in a text of its own
+        assert!(matches!(
+            result.messages[4].content,
+            Some(ChatContent::Text(Cow::Owned(ref t))) if t == "ERROR: failed"
+        ));
+    }
+
+    #[test]
+    fn test_wrap_system_prompt() {
+        let req: MessagesRequest = serde_json::from_value(serde_json::json!({
+            "model": "m", "max_tokens": 10, "system": "Be brief.",
+            "messages": [{"role": "user", "content": "Hi"}]
+        }))
+        .unwrap();
+        let system = |out: &ChatCompletionRequest| match &out.messages[0].content {
+            Some(ChatContent::Text(text)) if out.messages[0].role == "system" => text.to_string(),
+            other => panic!("no system prompt: {other:?}"),
+        };
+
+        let mut out = anthropic_to_openai(&req, &HashMap::new());
+        wrap_system_prompt(&mut out, Some("Use JSON tool calls."), Some("Stay terse."));
+        assert_eq!(
+            system(&out),
+            "Use JSON tool calls.\n\nBe brief.\n\nStay terse."
+        );
+        assert_eq!(out.messages.len(), 2);
+
+        // Without a system prompt, the text is one of its own
+        let mut req = req;
+        req.system = None;
+        let mut out = anthropic_to_openai(&req, &HashMap::new());
+        wrap_system_prompt(&mut out, None, Some("Stay terse."));
+        assert_eq!(system(&out), "Stay terse.");
+        assert_eq!(out.messages[1].role, "user");
+
+        // Neither leaves the request alone
+        let mut out = anthropic_to_openai(&req, &HashMap::new());
+        wrap_system_prompt(&mut out, None, None);
+        assert_eq!(out.messages.len(), 1);
+    }
 }
@@ -3,10 +3,115 @@
 //! Handles text content, tool calls, finish reason mapping, usage statistics,
 //! and error translation. Supports `reasoning_content` from reasoning models.
 
-use super::anthropic_types::{ErrorResponse, MessagesResponse, ResponseContentBlock, Usage};
-use super::openai_types::{ChatCompletionResponse, ChatErrorResponse};
+use super::anthropic_types::{ErrorResponse, Extra, MessagesResponse, ResponseContentBlock, Usage};
+use super::filters::OutputFilters;
+use super::openai_types::{ChatCompletionResponse, ChatErrorResponse, Choice, ChoiceMessage};
+use super::stop_tokens;
+use super::think::{self, ThinkTags};
+use super::tool_args::ToolArgs;
 use crate::error::ProxyError;
 
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Options for response translation.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseOptions {
+    /// Emit `reasoning_content` as a `thinking` block instead of folding it
+    /// into the text.
+    pub thinking_blocks: bool,
+    /// Handling of inline `<think>` blocks in the response text.
+    pub think_tags: ThinkTags,
+    /// Post-processing applied to the response text.
+    pub filters: OutputFilters,
+    /// Strip stop tokens leaked at the end of the response text.
+    pub strip_stop_tokens: bool,
+    /// Which choice a response with several becomes.
+    pub multiple_choices: MultipleChoices,
+}
+
+/// What a response with more than one choice (a request with `n` > 1, e.g.
+/// set through `params`) becomes (`[translation] multiple_choices`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultipleChoices {
+    /// The first choice; the others are dropped.
+    #[default]
+    First,
+    /// The choice that ended best: with tool calls, then at a natural stop,
+    /// then any other reason, then cut off (`length`, `content_filter`).
+    /// The first of equals wins.
+    ByFinishReason,
+    /// Every choice as one: their text and reasoning joined by blank lines,
+    /// and all their tool calls.
+    Concatenate,
+}
+
+/// The choice a response stands for, by `mode`.
+#[must_use]
+pub fn select_choice(
+    resp: &ChatCompletionResponse,
+    mode: MultipleChoices,
+) -> Option<Cow<'_, Choice>> {
+    let first = resp.choices.first()?;
+    if resp.choices.len() == 1 {
+        return Some(Cow::Borrowed(first));
+    }
+    match mode {
+        MultipleChoices::First => Some(Cow::Borrowed(first)),
+        MultipleChoices::ByFinishReason => resp
+            .choices
+            .iter()
+            .min_by_key(|choice| finish_rank(choice.finish_reason.as_deref()))
+            .map(Cow::Borrowed),
+        MultipleChoices::Concatenate => Some(Cow::Owned(concatenate(&resp.choices))),
+    }
+}
+
+/// How well a choice ended; lower is better.
+fn finish_rank(reason: Option<&str>) -> u8 {
+    match reason {
+        Some("tool_calls" | "function_call") => 0,
+        Some("stop") => 1,
+        Some("length" | "content_filter") => 3,
+        _ => 2,
+    }
+}
+
+fn concatenate(choices: &[Choice]) -> Choice {
+    let join = |part: fn(&Choice) -> Option<&str>| {
+        let parts: Vec<&str> = choices
+            .iter()
+            .filter_map(part)
+            .filter(|s| !s.is_empty())
+            .collect();
+        (!parts.is_empty()).then(|| parts.join("\n\n"))
+    };
+    let tool_calls: Vec<_> = choices
+        .iter()
+        .filter_map(|choice| choice.message.tool_calls.clone())
+        .flatten()
+        .collect();
+    let finish_reason = if tool_calls.is_empty() {
+        choices
+            .iter()
+            .find_map(|choice| choice.finish_reason.clone())
+    } else {
+        Some("tool_calls".to_string())
+    };
+    Choice {
+        index: 0,
+        message: ChoiceMessage {
+            role: choices[0].message.role.clone(),
+            content: join(|choice| choice.message.content.as_deref()),
+            reasoning_content: join(|choice| choice.message.reasoning_content.as_deref()),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        },
+        finish_reason,
+        logprobs: None,
+    }
+}
+
 /// Translate an `OpenAI` Chat Completion response into an Anthropic Messages response.
 /// Pure function: `original_model` is what Claude Code originally requested.
 ///
@@ -16,40 +121,88 @@ pub fn openai_to_anthropic(
     resp: &ChatCompletionResponse,
     original_model: &str,
 ) -> Result<MessagesResponse, ProxyError> {
-    let choice = resp.choices.first();
+    openai_to_anthropic_with_options(resp, original_model, &ResponseOptions::default())
+}
+
+/// [`openai_to_anthropic`] with explicit [`ResponseOptions`].
+///
+/// # Errors
+/// Returns `ProxyError::Translation` if tool call arguments are invalid JSON.
+pub fn openai_to_anthropic_with_options(
+    resp: &ChatCompletionResponse,
+    original_model: &str,
+    options: &ResponseOptions,
+) -> Result<MessagesResponse, ProxyError> {
+    let choice = select_choice(resp, options.multiple_choices);
+    let choice = choice.as_deref();
 
     let mut content: Vec<ResponseContentBlock> = Vec::new();
 
     if let Some(c) = choice {
-        // Use content if available, otherwise fall back to reasoning_content
-        // (reasoning models like Kimi K2.5 may put the response there)
-        let text = c
+        let answer = c.message.content.as_deref().filter(|s| !s.is_empty());
+        let reasoning = c
             .message
-            .content
+            .reasoning_content
             .as_deref()
-            .filter(|s| !s.is_empty())
-            .or_else(|| {
-                c.message
-                    .reasoning_content
-                    .as_deref()
-                    .filter(|s| !s.is_empty())
-            });
+            .filter(|s| !s.is_empty());
+        let has_tool_calls = c.message.tool_calls.as_ref().is_some_and(|t| !t.is_empty());
+
+        let split = answer
+            .filter(|_| options.think_tags != ThinkTags::Keep)
+            .map(think::split);
+        let (inline_thinking, answer) = match &split {
+            Some((thinking, text)) => (
+                Some(thinking.as_str()).filter(|s| !s.is_empty()),
+                Some(text.as_str()).filter(|s| !s.is_empty()),
+            ),
+            None => (None, answer),
+        };
+
+        // Reasoning models like Kimi K2.5 may put the whole response in
+        // reasoning_content; with nothing else to show, it's the answer.
+        let text = if options.thinking_blocks && (answer.is_some() || has_tool_calls) {
+            if let Some(thinking) = reasoning {
+                content.push(ResponseContentBlock::Thinking {
+                    thinking: thinking.to_string(),
+                    signature: String::new(),
+                });
+            }
+            answer
+        } else {
+            answer.or(reasoning)
+        };
 
-        if let Some(text) = text {
-            content.push(ResponseContentBlock::Text {
-                text: text.to_string(),
+        if let Some(thinking) =
+            inline_thinking.filter(|_| options.think_tags == ThinkTags::Thinking)
+        {
+            content.push(ResponseContentBlock::Thinking {
+                thinking: thinking.to_string(),
+                signature: String::new(),
             });
         }
 
+        if let Some(mut text) = text.map(|t| options.filters.apply(t)) {
+            if options.strip_stop_tokens {
+                stop_tokens::strip_trailing(&mut text);
+            }
+            if !text.is_empty() {
+                content.push(ResponseContentBlock::Text { text });
+            }
+        }
+
         if let Some(ref tool_calls) = c.message.tool_calls {
             for tc in tool_calls {
-                let input: serde_json::Value = serde_json::from_str(&tc.function.arguments)
-                    .map_err(|e| {
-                        ProxyError::translation(format!(
-                            "Invalid JSON in tool call '{}' arguments: {e}",
-                            tc.function.name
-                        ))
-                    })?;
+                // Empty, cut-off or double-encoded arguments are repaired as
+                // they are when streamed
+                let mut args = ToolArgs::new();
+                let mut arguments = args.push(&tc.function.arguments);
+                arguments.push_str(&args.finish().rest);
+                let input: serde_json::Value = serde_json::from_str(&arguments).map_err(|e| {
+                    ProxyError::translation(format!(
+                        "Invalid JSON in tool call '{}' arguments: {e}",
+                        tc.function.name
+                    ))
+                })?;
 
                 content.push(ResponseContentBlock::ToolUse {
                     id: tc.id.clone(),
@@ -72,10 +225,10 @@ pub fn openai_to_anthropic(
         .map_or_else(|| "end_turn".to_string(), map_finish_reason);
 
     let usage = resp.usage.as_ref().map_or_else(Usage::default, |u| Usage {
-        input_tokens: u.prompt_tokens,
+        input_tokens: u.uncached_prompt_tokens(),
         output_tokens: u.completion_tokens,
         cache_creation_input_tokens: None,
-        cache_read_input_tokens: None,
+        cache_read_input_tokens: u.cached_tokens(),
     });
 
     // Use the OpenAI response ID, prefixed to look like an Anthropic ID
@@ -90,9 +243,29 @@ pub fn openai_to_anthropic(
         stop_reason: Some(stop_reason),
         stop_sequence: None,
         usage,
+        extra: extra(resp, choice),
     })
 }
 
+/// The provider-specific data of a response that has no place in Anthropic's
+/// format, for clients that ask for it.
+fn extra(resp: &ChatCompletionResponse, choice: Option<&Choice>) -> Option<Extra> {
+    let mut extra = Extra::new();
+    if let Some(fingerprint) = &resp.system_fingerprint {
+        extra.insert("system_fingerprint".into(), fingerprint.clone().into());
+    }
+    if let Some(logprobs) = choice.and_then(|c| c.logprobs.clone()) {
+        extra.insert("logprobs".into(), logprobs);
+    }
+    if let Some(citations) = &resp.citations {
+        extra.insert("citations".into(), citations.clone());
+    }
+    if let Some(cost) = resp.usage.as_ref().and_then(|u| u.cost) {
+        extra.insert("cost".into(), cost.into());
+    }
+    (!extra.is_empty()).then_some(extra)
+}
+
 /// Map `OpenAI` `finish_reason` to Anthropic `stop_reason`
 /// Map `OpenAI` `finish_reason` to Anthropic `stop_reason`.
 #[must_use]
@@ -140,12 +313,16 @@ mod tests {
                     tool_calls: None,
                 },
                 finish_reason,
+                logprobs: None,
             }],
             usage: Some(ChatUsage {
                 prompt_tokens: 10,
                 completion_tokens: 20,
                 total_tokens: 30,
+                ..ChatUsage::default()
             }),
+            system_fingerprint: None,
+            citations: None,
         }
     }
 
@@ -192,8 +369,11 @@ mod tests {
                     }]),
                 },
                 finish_reason: Some("tool_calls".to_string()),
+                logprobs: None,
             }],
             usage: None,
+            system_fingerprint: None,
+            citations: None,
         };
 
         let result = openai_to_anthropic(&resp, "test-model").unwrap();
@@ -210,6 +390,257 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tool_call_arguments_repaired() {
+        // No arguments, cut-off arguments and double-encoded arguments
+        for (arguments, expected) in [
+            ("", serde_json::json!({})),
+            ("{\"city\": \"Lon", serde_json::json!({})),
+            (
+                "{\"city\": \"London\", \"days\": 3",
+                serde_json::json!({"city": "London", "days": 3}),
+            ),
+            ("\"{\\\"city\\\": 1}\"", serde_json::json!({"city": 1})),
+        ] {
+            let mut resp = make_response(None, Some("tool_calls".to_string()));
+            resp.choices[0].message.tool_calls = Some(vec![ChatToolCall {
+                id: "call_1".to_string(),
+                call_type: "function".to_string(),
+                function: ChatToolCallFunction {
+                    name: "get_weather".to_string(),
+                    arguments: arguments.to_string(),
+                },
+            }]);
+            let result = openai_to_anthropic(&resp, "test-model").unwrap();
+            let ResponseContentBlock::ToolUse { input, .. } = &result.content[0] else {
+                panic!("Expected tool_use content block");
+            };
+            assert_eq!(input, &expected, "{arguments}");
+        }
+    }
+
+    #[test]
+    fn test_multiple_choices() {
+        let mut resp = make_response(Some("Cut off".to_string()), Some("length".to_string()));
+        let mut second = resp.choices[0].clone();
+        second.index = 1;
+        second.message.content = Some("Done.".to_string());
+        second.finish_reason = Some("stop".to_string());
+        let mut third = second.clone();
+        third.index = 2;
+        third.message.content = None;
+        third.finish_reason = Some("tool_calls".to_string());
+        third.message.tool_calls = Some(vec![ChatToolCall {
+            id: "call_1".to_string(),
+            call_type: "function".to_string(),
+            function: ChatToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }]);
+        resp.choices.extend([second, third.clone()]);
+        let translate = |resp: &ChatCompletionResponse, multiple_choices| {
+            let options = ResponseOptions {
+                multiple_choices,
+                ..ResponseOptions::default()
+            };
+            openai_to_anthropic_with_options(resp, "test-model", &options).unwrap()
+        };
+
+        let result = translate(&resp, MultipleChoices::First);
+        assert_eq!(result.content.len(), 1);
+        assert!(
+            matches!(&result.content[0], ResponseContentBlock::Text { text } if text == "Cut off")
+        );
+        assert_eq!(result.stop_reason.as_deref(), Some("max_tokens"));
+
+        // The tool call beats the natural stop, which beats the cut-off text
+        let result = translate(&resp, MultipleChoices::ByFinishReason);
+        assert_eq!(result.content.len(), 1);
+        assert!(
+            matches!(&result.content[0], ResponseContentBlock::ToolUse { name, .. } if name == "get_weather")
+        );
+        assert_eq!(result.stop_reason.as_deref(), Some("tool_use"));
+        resp.choices.pop();
+        let result = translate(&resp, MultipleChoices::ByFinishReason);
+        assert!(
+            matches!(&result.content[0], ResponseContentBlock::Text { text } if text == "Done.")
+        );
+        assert_eq!(result.stop_reason.as_deref(), Some("end_turn"));
+
+        resp.choices.push(third);
+        let result = translate(&resp, MultipleChoices::Concatenate);
+        assert_eq!(result.content.len(), 2);
+        assert!(
+            matches!(&result.content[0], ResponseContentBlock::Text { text } if text == "Cut off\n\nDone.")
+        );
+        assert!(matches!(
+            &result.content[1],
+            ResponseContentBlock::ToolUse { .. }
+        ));
+        assert_eq!(result.stop_reason.as_deref(), Some("tool_use"));
+    }
+
+    #[test]
+    fn test_reasoning_content_as_thinking_block() {
+        let mut resp = make_response(Some("42".to_string()), Some("stop".to_string()));
+        resp.choices[0].message.reasoning_content = Some("6 times 7.".to_string());
+
+        let options = ResponseOptions {
+            thinking_blocks: true,
+            ..ResponseOptions::default()
+        };
+        let result = openai_to_anthropic_with_options(&resp, "test-model", &options).unwrap();
+        assert_eq!(result.content.len(), 2);
+        assert!(matches!(
+            &result.content[0],
+            ResponseContentBlock::Thinking { thinking, .. } if thinking == "6 times 7."
+        ));
+        assert!(matches!(&result.content[1], ResponseContentBlock::Text { text } if text == "42"));
+
+        // Reasoning-only responses keep it as the answer text
+        resp.choices[0].message.content = None;
+        let result = openai_to_anthropic_with_options(&resp, "test-model", &options).unwrap();
+        assert!(matches!(
+            &result.content[0],
+            ResponseContentBlock::Text { text } if text == "6 times 7."
+        ));
+    }
+
+    #[test]
+    fn test_output_filters() {
+        use crate::translate::filters::{BuiltinFilter, OutputFilters};
+
+        let resp = make_response(
+            Some("<think>Let me see.</think>\nParis.<|im_end|>".to_string()),
+            Some("stop".to_string()),
+        );
+        let options = ResponseOptions {
+            filters: OutputFilters::new(
+                &[BuiltinFilter::ThinkTags, BuiltinFilter::ChatTemplate],
+                &[],
+            ),
+            ..ResponseOptions::default()
+        };
+        let result = openai_to_anthropic_with_options(&resp, "test-model", &options).unwrap();
+        assert!(
+            matches!(&result.content[0], ResponseContentBlock::Text { text } if text == "Paris.")
+        );
+    }
+
+    #[test]
+    fn test_inline_think_tags() {
+        let resp = make_response(
+            Some("<think>Capital of France.</think>\n\nParis.".to_string()),
+            Some("stop".to_string()),
+        );
+        let options = ResponseOptions {
+            think_tags: ThinkTags::Thinking,
+            ..ResponseOptions::default()
+        };
+        let result = openai_to_anthropic_with_options(&resp, "test-model", &options).unwrap();
+        assert_eq!(result.content.len(), 2);
+        assert!(matches!(
+            &result.content[0],
+            ResponseContentBlock::Thinking { thinking, .. } if thinking == "Capital of France."
+        ));
+        assert!(
+            matches!(&result.content[1], ResponseContentBlock::Text { text } if text == "Paris.")
+        );
+
+        let options = ResponseOptions {
+            think_tags: ThinkTags::Strip,
+            ..ResponseOptions::default()
+        };
+        let result = openai_to_anthropic_with_options(&resp, "test-model", &options).unwrap();
+        assert_eq!(result.content.len(), 1);
+        assert!(
+            matches!(&result.content[0], ResponseContentBlock::Text { text } if text == "Paris.")
+        );
+    }
+
+    #[test]
+    fn test_leaked_stop_tokens_stripped() {
+        let resp = make_response(
+            Some("Paris.<|eot_id|>".to_string()),
+            Some("stop".to_string()),
+        );
+        let options = ResponseOptions {
+            strip_stop_tokens: true,
+            ..ResponseOptions::default()
+        };
+        let result = openai_to_anthropic_with_options(&resp, "test-model", &options).unwrap();
+        assert!(
+            matches!(&result.content[0], ResponseContentBlock::Text { text } if text == "Paris.")
+        );
+    }
+
+    #[test]
+    fn test_cached_tokens_in_usage() {
+        let mut resp = make_response(Some("Hi".to_string()), Some("stop".to_string()));
+        let usage: ChatUsage = serde_json::from_value(serde_json::json!({
+            "prompt_tokens": 1000,
+            "completion_tokens": 20,
+            "total_tokens": 1020,
+            "prompt_tokens_details": {"cached_tokens": 768}
+        }))
+        .unwrap();
+        resp.usage = Some(usage);
+        let result = openai_to_anthropic(&resp, "test-model").unwrap();
+        assert_eq!(result.usage.input_tokens, 232);
+        assert_eq!(result.usage.cache_read_input_tokens, Some(768));
+
+        // DeepSeek reports hits and misses at the top level
+        let usage: ChatUsage = serde_json::from_value(serde_json::json!({
+            "prompt_tokens": 1000,
+            "completion_tokens": 20,
+            "total_tokens": 1020,
+            "prompt_cache_hit_tokens": 900,
+            "prompt_cache_miss_tokens": 100
+        }))
+        .unwrap();
+        resp.usage = Some(usage);
+        let result = openai_to_anthropic(&resp, "test-model").unwrap();
+        assert_eq!(result.usage.input_tokens, 100);
+        assert_eq!(result.usage.cache_read_input_tokens, Some(900));
+
+        // No cache details: nothing to report
+        let result = openai_to_anthropic(&make_response(None, None), "test-model").unwrap();
+        assert_eq!(result.usage.input_tokens, 10);
+        assert_eq!(result.usage.cache_read_input_tokens, None);
+    }
+
+    #[test]
+    fn test_extra() {
+        let plain = openai_to_anthropic(&make_response(None, None), "test-model").unwrap();
+        assert!(plain.extra.is_none());
+        assert!(!serde_json::to_string(&plain).unwrap().contains("extra"));
+
+        let resp: ChatCompletionResponse = serde_json::from_value(serde_json::json!({
+            "id": "gen-1",
+            "object": "chat.completion",
+            "model": "sonar",
+            "system_fingerprint": "fp_44709d6fcb",
+            "citations": ["https://example.com/a"],
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hi"},
+                "finish_reason": "stop",
+                "logprobs": {"content": [{"token": "Hi", "logprob": -0.1}]}
+            }],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6, "cost": 0.0002}
+        }))
+        .unwrap();
+        let extra = openai_to_anthropic(&resp, "test-model")
+            .unwrap()
+            .extra
+            .unwrap();
+        assert_eq!(extra["system_fingerprint"], "fp_44709d6fcb");
+        assert_eq!(extra["citations"][0], "https://example.com/a");
+        assert_eq!(extra["logprobs"]["content"][0]["token"], "Hi");
+        assert_eq!(extra["cost"], 0.0002);
+    }
+
     #[test]
     fn test_finish_reason_mapping() {
         assert_eq!(map_finish_reason("stop"), "end_turn");
@@ -0,0 +1,314 @@
+//! Re-chunking of text deltas, for smoother streaming.
+//!
+//! Some backends send a whole paragraph in one chunk, so Claude Code's output
+//! jumps a screenful at a time. A [`Smoother`] splits text and thinking deltas
+//! longer than `max_chars` into pieces of at most that many characters, cut
+//! after a space where one falls in the piece, and says how long to pause
+//! between them. The pauses for one delta add up to at most [`MAX_PACING`], so
+//! a huge delta is sent in bigger steps rather than slowly.
+//!
+//! Others send one token per chunk, and the client parses an event for each.
+//! A [`Coalescer`] does the opposite: it holds back consecutive deltas to the
+//! same block and sends them as one once its window has passed, or as soon as
+//! any other event comes.
+//!
+//! A stream uses one or the other, as a [`Rechunker`].
+
+use super::anthropic_types::{Delta, StreamEvent};
+
+use std::time::{Duration, Instant};
+
+/// The longest all the pauses within one delta may take.
+pub const MAX_PACING: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Smoother {
+    max_chars: usize,
+    delay: Duration,
+}
+
+impl Smoother {
+    /// Split deltas longer than `max_chars` (at least 1), pausing up to `delay`
+    /// between the pieces.
+    #[must_use]
+    pub fn new(max_chars: usize, delay: Duration) -> Self {
+        Self {
+            max_chars: max_chars.max(1),
+            delay,
+        }
+    }
+
+    /// The event as one or more events with the same effect: a long text or
+    /// thinking delta in pieces, anything else as it is.
+    #[must_use]
+    pub fn split(&self, event: StreamEvent) -> Vec<StreamEvent> {
+        let StreamEvent::ContentBlockDelta { index, delta } = event else {
+            return vec![event];
+        };
+        let (text, rebuild): (String, fn(String) -> Delta) = match delta {
+            Delta::TextDelta { text } if text.chars().count() > self.max_chars => {
+                (text, |text| Delta::TextDelta { text })
+            }
+            Delta::ThinkingDelta { thinking } if thinking.chars().count() > self.max_chars => {
+                (thinking, |thinking| Delta::ThinkingDelta { thinking })
+            }
+            delta => return vec![StreamEvent::ContentBlockDelta { index, delta }],
+        };
+        pieces(&text, self.max_chars)
+            .into_iter()
+            .map(|piece| StreamEvent::ContentBlockDelta {
+                index,
+                delta: rebuild(piece.to_string()),
+            })
+            .collect()
+    }
+
+    /// The pause between consecutive pieces of a delta split into `pieces`.
+    #[must_use]
+    pub fn pause(&self, pieces: usize) -> Duration {
+        let gaps = u32::try_from(pieces.saturating_sub(1)).unwrap_or(u32::MAX);
+        if gaps == 0 {
+            return Duration::ZERO;
+        }
+        self.delay.min(MAX_PACING / gaps)
+    }
+}
+
+/// How a stream's deltas are re-chunked before they're sent.
+#[derive(Debug)]
+pub enum Rechunker {
+    Split(Smoother),
+    Coalesce(Coalescer),
+}
+
+impl Rechunker {
+    /// The events to send for `event`, come at `now`, each with the pause to
+    /// take before sending it.
+    pub fn push(&mut self, event: StreamEvent, now: Instant) -> Vec<(Duration, StreamEvent)> {
+        match self {
+            Self::Split(smoother) => {
+                let pieces = smoother.split(event);
+                let pause = smoother.pause(pieces.len());
+                pieces
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, piece)| (if i == 0 { Duration::ZERO } else { pause }, piece))
+                    .collect()
+            }
+            Self::Coalesce(coalescer) => coalescer
+                .push(event, now)
+                .into_iter()
+                .map(|event| (Duration::ZERO, event))
+                .collect(),
+        }
+    }
+
+    /// When events held back are due, if any are.
+    #[must_use]
+    pub fn flush_at(&self) -> Option<Instant> {
+        match self {
+            Self::Split(_) => None,
+            Self::Coalesce(coalescer) => coalescer.flush_at(),
+        }
+    }
+
+    /// The events held back, to send now.
+    pub fn flush(&mut self) -> Vec<StreamEvent> {
+        match self {
+            Self::Split(_) => Vec::new(),
+            Self::Coalesce(coalescer) => coalescer.flush(),
+        }
+    }
+}
+
+/// `text` in pieces of at most `max_chars` characters, each but the last cut
+/// after its last space if it has one.
+fn pieces(text: &str, max_chars: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while let Some((limit, _)) = rest.char_indices().nth(max_chars) {
+        let cut = match rest[..limit].rfind(char::is_whitespace) {
+            Some(space) if space > 0 => {
+                space + rest[space..].chars().next().map_or(1, char::len_utf8)
+            }
+            _ => limit,
+        };
+        pieces.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    if !rest.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+/// Merges runs of small deltas; see the module docs.
+#[derive(Debug)]
+pub struct Coalescer {
+    window: Duration,
+    /// The block index and merged delta held back, and when the first of the
+    /// deltas merged into it came.
+    pending: Option<(usize, Delta, Instant)>,
+}
+
+impl Coalescer {
+    /// Hold deltas back for up to `window`.
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: None,
+        }
+    }
+
+    /// The events to send now that `event` has come at `now`: none while a
+    /// delta is held back, else whatever was held followed by the event.
+    pub fn push(&mut self, event: StreamEvent, now: Instant) -> Vec<StreamEvent> {
+        let StreamEvent::ContentBlockDelta { index, delta } = event else {
+            let mut events = self.flush();
+            events.push(event);
+            return events;
+        };
+        let unmerged = match self.pending {
+            Some((pending_index, ref mut pending, _)) if pending_index == index => {
+                append(pending, delta).err()
+            }
+            _ => Some(delta),
+        };
+        let mut events = Vec::new();
+        if let Some(delta) = unmerged {
+            events = self.flush();
+            self.pending = Some((index, delta, now));
+        }
+        if self.flush_at().is_some_and(|at| now >= at) {
+            events.extend(self.flush());
+        }
+        events
+    }
+
+    /// When the held-back delta is due, if there is one.
+    #[must_use]
+    pub fn flush_at(&self) -> Option<Instant> {
+        self.pending
+            .as_ref()
+            .map(|(_, _, since)| *since + self.window)
+    }
+
+    /// The held-back delta, if any, to send now.
+    pub fn flush(&mut self) -> Vec<StreamEvent> {
+        self.pending
+            .take()
+            .map(|(index, delta, _)| StreamEvent::ContentBlockDelta { index, delta })
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Append `delta` to `pending` if they're the same kind, else hand it back.
+fn append(pending: &mut Delta, delta: Delta) -> Result<(), Delta> {
+    match (pending, delta) {
+        (Delta::TextDelta { text }, Delta::TextDelta { text: more }) => text.push_str(&more),
+        (Delta::ThinkingDelta { thinking }, Delta::ThinkingDelta { thinking: more }) => {
+            thinking.push_str(&more);
+        }
+        (Delta::InputJsonDelta { partial_json }, Delta::InputJsonDelta { partial_json: more }) => {
+            partial_json.push_str(&more);
+        }
+        (_, delta) => return Err(delta),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_delta(text: &str) -> StreamEvent {
+        StreamEvent::ContentBlockDelta {
+            index: 1,
+            delta: Delta::TextDelta {
+                text: text.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let smoother = Smoother::new(10, Duration::from_millis(20));
+        let events = smoother.split(text_delta("The quick brown fox jumps over the lazy dög."));
+        let texts: Vec<&str> = events
+            .iter()
+            .map(|event| match event {
+                StreamEvent::ContentBlockDelta {
+                    index: 1,
+                    delta: Delta::TextDelta { text },
+                } => text.as_str(),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                "The quick ",
+                "brown fox ",
+                "jumps ",
+                "over the ",
+                "lazy dög."
+            ]
+        );
+        // No space to cut at: cut at the limit, on a character boundary
+        assert_eq!(pieces("ééééé", 2), ["éé", "éé", "é"]);
+
+        // Short deltas and other events pass through
+        assert_eq!(smoother.split(text_delta("Hi")).len(), 1);
+        assert_eq!(smoother.split(StreamEvent::MessageStop).len(), 1);
+
+        assert_eq!(smoother.pause(1), Duration::ZERO);
+        assert_eq!(smoother.pause(5), Duration::from_millis(20));
+        assert_eq!(smoother.pause(101), Duration::from_millis(2));
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let window = Duration::from_millis(50);
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(window);
+        assert!(coalescer.push(text_delta("Hel"), start).is_empty());
+        assert!(coalescer.push(text_delta("lo"), start).is_empty());
+        assert_eq!(coalescer.flush_at(), Some(start + window));
+
+        // Another event sends the merged delta ahead of it
+        let events = coalescer.push(StreamEvent::ContentBlockStop { index: 1 }, start);
+        assert!(matches!(
+            &events[..],
+            [
+                StreamEvent::ContentBlockDelta {
+                    index: 1,
+                    delta: Delta::TextDelta { text },
+                },
+                StreamEvent::ContentBlockStop { index: 1 },
+            ] if text == "Hello"
+        ));
+        assert_eq!(coalescer.flush_at(), None);
+
+        // Deltas still coming once the window has passed go out together
+        assert!(coalescer.push(text_delta("a"), start).is_empty());
+        let events = coalescer.push(text_delta("b"), start + window);
+        assert!(matches!(
+            &events[..],
+            [StreamEvent::ContentBlockDelta { delta: Delta::TextDelta { text }, .. }] if text == "ab"
+        ));
+
+        // A delta to another block isn't merged
+        assert!(coalescer.push(text_delta("x"), start).is_empty());
+        let other = StreamEvent::ContentBlockDelta {
+            index: 2,
+            delta: Delta::TextDelta {
+                text: "y".to_string(),
+            },
+        };
+        assert_eq!(coalescer.push(other, start).len(), 1);
+        assert_eq!(coalescer.flush().len(), 1);
+        assert!(coalescer.flush().is_empty());
+    }
+}
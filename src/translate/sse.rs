@@ -0,0 +1,107 @@
+//! Server-sent event framing, for serving translated streams without axum.
+//!
+//! [`encode_events`] turns [`StreamEvent`]s into the text Anthropic clients
+//! read: an `event:` line naming the event, its JSON on a `data:` line, and a
+//! blank line after each. [`frame`] and [`comment`] build arbitrary events and
+//! `:` comment lines (e.g. keep-alives). Line breaks in data are split over
+//! several `data:` lines, as the SSE format requires, so a client's parser
+//! rejoins them exactly.
+
+use super::anthropic_types::StreamEvent;
+
+/// One event: an optional `event:` line, then `data` over one `data:` line per
+/// line it holds, then the blank line that ends the event.
+#[must_use]
+pub fn frame(event: Option<&str>, data: &str) -> String {
+    let mut out = String::with_capacity(data.len() + 32);
+    if let Some(event) = event {
+        out.push_str("event: ");
+        // A line break would end the field early
+        out.extend(event.chars().filter(|c| !matches!(c, '\r' | '\n')));
+        out.push('\n');
+    }
+    for line in split_lines(data) {
+        out.push_str("data: ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('\n');
+    out
+}
+
+/// A comment, ignored by clients: one `:` line per line of `text`. Sent on
+/// its own it keeps an idle connection open.
+#[must_use]
+pub fn comment(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 4);
+    for line in split_lines(text) {
+        out.push(':');
+        if !line.is_empty() {
+            out.push(' ');
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out.push('\n');
+    out
+}
+
+/// A stream event, named by its type and carrying its JSON.
+#[must_use]
+pub fn encode_event(event: &StreamEvent) -> String {
+    let data = serde_json::to_string(event).unwrap_or_else(|_| "{}".to_string());
+    frame(Some(event.event_name()), &data)
+}
+
+/// A sequence of stream events, framed one after another.
+#[must_use]
+pub fn encode_events<'a>(events: impl IntoIterator<Item = &'a StreamEvent>) -> String {
+    events.into_iter().map(encode_event).collect()
+}
+
+/// `text` split at `\r\n`, `\r` and `\n`, the line ends SSE recognizes. Empty
+/// text is a single empty line.
+fn split_lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(['\r', '\n']) {
+        lines.push(&rest[..i]);
+        let skip = if rest[i..].starts_with("\r\n") { 2 } else { 1 };
+        rest = &rest[i + skip..];
+    }
+    lines.push(rest);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::anthropic_types::Delta;
+
+    #[test]
+    fn test_framing() {
+        assert_eq!(
+            encode_events(&[
+                StreamEvent::ContentBlockDelta {
+                    index: 0,
+                    delta: Delta::TextDelta {
+                        text: "a\nb".to_string(),
+                    },
+                },
+                StreamEvent::MessageStop,
+            ]),
+            "event: content_block_delta\n\
+             data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"a\\nb\"}}\n\n\
+             event: message_stop\n\
+             data: {\"type\":\"message_stop\"}\n\n"
+        );
+        assert_eq!(
+            frame(Some("log"), "line 1\r\nline 2\n"),
+            "event: log\ndata: line 1\ndata: line 2\ndata: \n\n"
+        );
+        assert_eq!(frame(None, "[DONE]"), "data: [DONE]\n\n");
+        assert_eq!(frame(Some("a\nb"), ""), "event: ab\ndata: \n\n");
+        assert_eq!(comment("keep-alive"), ": keep-alive\n\n");
+        assert_eq!(comment("a\n"), ": a\n:\n\n");
+    }
+}
@@ -0,0 +1,161 @@
+//! Stop tokens leaked at the end of model output.
+//!
+//! Some backends serve models with a chat template or tokenizer config that
+//! doesn't treat the model's end-of-turn token as special, so the raw token
+//! (`<|eot_id|>`, `</s>`, `[/INST]`, ...) ends up as the last thing in the
+//! text. These are stripped from the end of the response text — in streams by
+//! holding back a trailing run that may turn out to be such tokens — and
+//! reported so the model's `stop_sequences` can be fixed.
+
+/// End-of-turn markers of common open-weight chat templates.
+pub const LEAKED_STOP_TOKENS: &[&str] = &[
+    "<|eot_id|>",
+    "<|eom_id|>",
+    "<|end_of_text|>",
+    "<|im_end|>",
+    "<|endoftext|>",
+    "<|end|>",
+    "<|return|>",
+    "<end_of_turn>",
+    "<｜end▁of▁sentence｜>",
+    "</s>",
+    "[/INST]",
+];
+
+/// Split `text` into the content and a trailing run of stop tokens and
+/// whitespace, returning the content and the tokens found (last first).
+fn split_trailing(text: &str) -> (&str, Vec<&'static str>) {
+    let mut rest = text;
+    let mut tokens = Vec::new();
+    loop {
+        let trimmed = rest.trim_end();
+        match LEAKED_STOP_TOKENS
+            .iter()
+            .find(|token| trimmed.ends_with(*token))
+        {
+            Some(token) => {
+                rest = &trimmed[..trimmed.len() - token.len()];
+                tokens.push(*token);
+            }
+            None if tokens.is_empty() => return (text, tokens),
+            None => return (trimmed, tokens),
+        }
+    }
+}
+
+/// The stop tokens `text` ends with, last first.
+#[must_use]
+pub fn trailing(text: &str) -> Vec<&'static str> {
+    split_trailing(text).1
+}
+
+/// Remove stop tokens (and the whitespace around them) from the end of `text`,
+/// returning the tokens removed.
+pub fn strip_trailing(text: &mut String) -> Vec<&'static str> {
+    let (content, tokens) = split_trailing(text);
+    let len = content.len();
+    text.truncate(len);
+    tokens
+}
+
+/// Streaming counterpart of [`strip_trailing`]: text passes through, except
+/// for a trailing run of whitespace and (possibly partial) stop tokens, which
+/// is held back until more text follows it or the text ends.
+#[derive(Debug, Default)]
+pub struct StopTokenTrimmer {
+    held: String,
+    leaked: Vec<&'static str>,
+}
+
+impl StopTokenTrimmer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next piece of text, returning what can be emitted now.
+    pub fn push(&mut self, text: &str) -> String {
+        self.held.push_str(text);
+        let rest = self.held.split_off(settled_len(&self.held));
+        std::mem::replace(&mut self.held, rest)
+    }
+
+    /// The text has ended: return the held-back text minus any stop tokens.
+    pub fn finish(&mut self) -> String {
+        let mut rest = std::mem::take(&mut self.held);
+        let tokens = strip_trailing(&mut rest);
+        self.leaked.extend(tokens);
+        rest
+    }
+
+    /// Stop tokens stripped so far.
+    #[must_use]
+    pub fn leaked(&self) -> &[&'static str] {
+        &self.leaked
+    }
+}
+
+/// Longest stop token, in bytes.
+const MAX_TOKEN_LEN: usize = 32;
+
+/// Length of the prefix of `text` that can't be part of a trailing run of
+/// stop tokens: the run of complete tokens and whitespace at the end, and the
+/// start of another token after it, are held back.
+fn settled_len(text: &str) -> usize {
+    let partial = text
+        .char_indices()
+        .rev()
+        .take_while(|&(i, _)| text.len() - i <= MAX_TOKEN_LEN)
+        .filter(|&(i, _)| {
+            LEAKED_STOP_TOKENS
+                .iter()
+                .any(|token| token.starts_with(&text[i..]))
+        })
+        .last()
+        .map_or(text.len(), |(i, _)| i);
+    split_trailing(&text[..partial]).0.trim_end().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_trailing() {
+        let mut text = "The answer is 4.<|eot_id|>\n".to_string();
+        assert_eq!(strip_trailing(&mut text), vec!["<|eot_id|>"]);
+        assert_eq!(text, "The answer is 4.");
+
+        let mut text = "Done. </s> [/INST]".to_string();
+        assert_eq!(strip_trailing(&mut text), vec!["[/INST]", "</s>"]);
+        assert_eq!(text, "Done.");
+
+        let mut text = "Use </s> to end a sequence.\n".to_string();
+        assert!(strip_trailing(&mut text).is_empty());
+        assert_eq!(text, "Use </s> to end a sequence.\n");
+    }
+
+    #[test]
+    fn test_trimmer_every_split() {
+        let text = "Done.\n<|eot_id|>";
+        for i in 1..text.len() {
+            let (a, b) = text.split_at(i);
+            let mut trimmer = StopTokenTrimmer::new();
+            let mut out = trimmer.push(a);
+            out += &trimmer.push(b);
+            out += &trimmer.finish();
+            assert_eq!(out, "Done.", "split at {i}");
+            assert_eq!(trimmer.leaked(), ["<|eot_id|>"]);
+        }
+    }
+
+    #[test]
+    fn test_trimmer_passes_text_through() {
+        let mut trimmer = StopTokenTrimmer::new();
+        assert_eq!(trimmer.push("Use </s> "), "Use");
+        assert_eq!(trimmer.push("to end <"), " </s> to end");
+        assert_eq!(trimmer.push("b>it</b>\n"), " <b>it</b>");
+        assert_eq!(trimmer.finish(), "\n");
+        assert!(trimmer.leaked().is_empty());
+    }
+}
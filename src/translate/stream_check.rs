@@ -0,0 +1,261 @@
+//! Checks that a stream of events keeps Anthropic's streaming contract.
+//!
+//! Claude Code trusts the event order: a delta for a block it hasn't seen
+//! started, or a second `message_start`, corrupts the turn rather than failing
+//! it. A [`StreamChecker`] follows the events sent to the client and reports
+//! the first event that breaks a rule:
+//!
+//! - `message_start` comes first (before anything but `ping`), and only once
+//! - content blocks are numbered 0, 1, 2, … in the order they start
+//! - deltas and `content_block_stop` only go to started, unstopped blocks,
+//!   and a delta's type matches its block's (`text_delta` to `text`, …)
+//! - the `message_delta` with the `stop_reason` comes once, after every block
+//!   has stopped, and `message_stop` follows it
+//! - nothing follows `message_stop`, and a stream doesn't end without one
+//!
+//! Debug builds run every translated stream through one and log violations.
+
+use super::anthropic_types::{Delta, ResponseContentBlock, StreamEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Text,
+    ToolUse,
+    Thinking,
+}
+
+impl BlockKind {
+    fn of(block: &ResponseContentBlock) -> Self {
+        match block {
+            ResponseContentBlock::Text { .. } => Self::Text,
+            ResponseContentBlock::ToolUse { .. } => Self::ToolUse,
+            ResponseContentBlock::Thinking { .. } => Self::Thinking,
+        }
+    }
+
+    fn accepts(self, delta: &Delta) -> bool {
+        matches!(
+            (self, delta),
+            (Self::Text, Delta::TextDelta { .. })
+                | (Self::ToolUse, Delta::InputJsonDelta { .. })
+                | (Self::Thinking, Delta::ThinkingDelta { .. })
+        )
+    }
+}
+
+/// Follows a stream event by event; see the module docs for the rules.
+#[derive(Debug, Default)]
+pub struct StreamChecker {
+    started: bool,
+    /// Every block started so far, by index, and whether it's still open.
+    blocks: Vec<(BlockKind, bool)>,
+    stop_reason_sent: bool,
+    stopped: bool,
+    /// Events seen, for locating violations.
+    seen: usize,
+}
+
+impl StreamChecker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the next event, returning how it breaks the contract, if it does.
+    pub fn push(&mut self, event: &StreamEvent) -> Option<String> {
+        self.seen += 1;
+        let position = self.seen;
+        self.check(event)
+            .map(|problem| format!("event {position} ({}): {problem}", event.event_name()))
+    }
+
+    /// Check that the stream was complete, once it has ended.
+    #[must_use]
+    pub fn finish(&self) -> Option<String> {
+        if self.stopped {
+            None
+        } else if !self.started {
+            Some("the stream ended without message_start".to_string())
+        } else {
+            Some("the stream ended without message_stop".to_string())
+        }
+    }
+
+    fn check(&mut self, event: &StreamEvent) -> Option<String> {
+        if self.stopped {
+            return Some("sent after message_stop".to_string());
+        }
+        if !self.started && !matches!(event, StreamEvent::MessageStart { .. } | StreamEvent::Ping) {
+            return Some("sent before message_start".to_string());
+        }
+        match event {
+            StreamEvent::MessageStart { .. } => {
+                if self.started {
+                    return Some("a second message_start".to_string());
+                }
+                self.started = true;
+            }
+            StreamEvent::Ping => {}
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => {
+                if self.stop_reason_sent {
+                    return Some("block started after the stop_reason".to_string());
+                }
+                if *index != self.blocks.len() {
+                    return Some(format!(
+                        "block {index} started where block {} was expected",
+                        self.blocks.len()
+                    ));
+                }
+                self.blocks.push((BlockKind::of(content_block), true));
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => match self.blocks.get(*index) {
+                None => return Some(format!("delta for block {index}, which never started")),
+                Some((_, false)) => {
+                    return Some(format!("delta for block {index}, which already stopped"))
+                }
+                Some((kind, true)) if !kind.accepts(delta) => {
+                    return Some(format!(
+                        "{kind:?} block {index} given a {}",
+                        delta_type(delta)
+                    ))
+                }
+                Some(_) => {}
+            },
+            StreamEvent::ContentBlockStop { index } => match self.blocks.get_mut(*index) {
+                None => return Some(format!("block {index} stopped but never started")),
+                Some((_, open)) if !*open => {
+                    return Some(format!("block {index} stopped twice"));
+                }
+                Some((_, open)) => *open = false,
+            },
+            StreamEvent::MessageDelta { delta, .. } => {
+                if delta.stop_reason.is_some() {
+                    if self.stop_reason_sent {
+                        return Some("a second stop_reason".to_string());
+                    }
+                    if let Some(open) = self.open_blocks() {
+                        return Some(format!("stop_reason sent while {open} still open"));
+                    }
+                    self.stop_reason_sent = true;
+                }
+            }
+            StreamEvent::MessageStop => {
+                if let Some(open) = self.open_blocks() {
+                    return Some(format!("message_stop while {open} still open"));
+                }
+                if !self.stop_reason_sent {
+                    return Some("message_stop without a stop_reason".to_string());
+                }
+                self.stopped = true;
+            }
+        }
+        None
+    }
+
+    /// The blocks not yet stopped, e.g. "blocks 1, 2", if any.
+    fn open_blocks(&self) -> Option<String> {
+        let open: Vec<String> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, open))| *open)
+            .map(|(index, _)| index.to_string())
+            .collect();
+        match open.len() {
+            0 => None,
+            1 => Some(format!("block {}", open[0])),
+            _ => Some(format!("blocks {}", open.join(", "))),
+        }
+    }
+}
+
+fn delta_type(delta: &Delta) -> &'static str {
+    match delta {
+        Delta::TextDelta { .. } => "text_delta",
+        Delta::InputJsonDelta { .. } => "input_json_delta",
+        Delta::ThinkingDelta { .. } => "thinking_delta",
+    }
+}
+
+/// Every violation in a complete stream, in order.
+#[must_use]
+pub fn check(events: &[StreamEvent]) -> Vec<String> {
+    let mut checker = StreamChecker::new();
+    let mut problems: Vec<String> = events.iter().filter_map(|e| checker.push(e)).collect();
+    problems.extend(checker.finish());
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::anthropic_types::{MessagesResponse, Usage};
+    use crate::translate::streaming::response_events;
+
+    fn response() -> MessagesResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "msg_1", "type": "message", "role": "assistant",
+            "content": [
+                {"type": "thinking", "thinking": "Look it up.", "signature": ""},
+                {"type": "text", "text": "Searching."},
+                {"type": "tool_use", "id": "call_1", "name": "search", "input": {"q": "rust"}},
+            ],
+            "model": "claude-sonnet-4-20250514",
+            "stop_reason": "tool_use", "stop_sequence": null,
+            "usage": Usage::default(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_valid_stream() {
+        assert!(check(&response_events(&response())).is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let events = response_events(&response());
+        let first = |events: &[StreamEvent]| check(events).into_iter().next().unwrap_or_default();
+
+        // Dropped message_start
+        assert!(first(&events[1..]).contains("before message_start"));
+        // Repeated message_start
+        let mut twice = events.clone();
+        twice.insert(1, events[0].clone());
+        assert!(first(&twice).contains("second message_start"));
+        // Reused block index: the second block started as 0 again
+        let mut reused = events.clone();
+        reused[4] = StreamEvent::ContentBlockStart {
+            index: 0,
+            content_block: ResponseContentBlock::Text {
+                text: String::new(),
+            },
+        };
+        assert!(first(&reused).contains("block 0 started where block 1 was expected"));
+        // Delta before its block started
+        let mut early = events.clone();
+        early.swap(1, 2);
+        assert!(first(&early).contains("delta for block 0, which never started"));
+        // Wrong delta type
+        let mut mismatched = events.clone();
+        mismatched[2] = StreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: Delta::TextDelta {
+                text: "x".to_string(),
+            },
+        };
+        assert!(first(&mismatched).contains("Thinking block 0 given a text_delta"));
+        // Block left open
+        let mut open = events.clone();
+        open.remove(events.len() - 3);
+        assert!(first(&open).contains("stop_reason sent while block 2 still open"));
+        // Two message_stops, and a truncated stream
+        let mut stops = events.clone();
+        stops.push(StreamEvent::MessageStop);
+        assert!(first(&stops).contains("after message_stop"));
+        assert!(first(&events[..events.len() - 1]).contains("without message_stop"));
+    }
+}
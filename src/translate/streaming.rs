@@ -5,20 +5,69 @@
 //! corresponding Anthropic stream events (`message_start`, `content_block_delta`, etc.).
 
 use super::anthropic_types::{
-    Delta, DeltaUsage, MessageDeltaBody, MessagesResponse, ResponseContentBlock, StreamEvent, Usage,
+    Delta, DeltaUsage, Extra, MessageDeltaBody, MessagesResponse, ResponseContentBlock,
+    StreamEvent, Usage,
 };
-use super::openai_types::ChatCompletionChunk;
+use super::filters::{OutputFilters, StreamFilter};
+use super::openai_types::{ChatCompletionChunk, ChunkToolCall};
 use super::response::map_finish_reason;
+use super::stop_tokens::StopTokenTrimmer;
+use super::think::{ThinkSegment, ThinkTagParser, ThinkTags};
+use super::tool_args::ToolArgs;
+use crate::tokens::estimate_tokens;
+
+use std::borrow::Cow;
 
 /// Tracks state of an in-progress tool call being streamed
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct ActiveToolCall {
+    /// The `OpenAI` `index` its deltas carry.
+    openai_index: u64,
+    /// The Anthropic content block it streams into, fixed when it starts.
     anthropic_block_index: usize,
-    #[allow(dead_code)]
     id: String,
-    #[allow(dead_code)]
     name: String,
-    emitted_start: bool,
+    args: ToolArgs,
+}
+
+/// The text or thinking block currently receiving deltas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpenBlock {
+    None,
+    Text,
+    Thinking,
+}
+
+/// Where a stream stands, handed from one [`StreamTranslator`] to the next
+/// when a request is retried or fails over mid-stream. See
+/// [`StreamTranslator::suspend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamState {
+    model: String,
+    msg_id: String,
+    /// Whether the client has been sent `message_start`.
+    started: bool,
+    /// The index the next content block takes.
+    next_block_index: usize,
+    input_tokens: u64,
+    cache_read_tokens: Option<u64>,
+    estimated_output_tokens: u64,
+    last_usage_update: u64,
+}
+
+impl StreamState {
+    /// Whether the client has been sent `message_start`, i.e. whether the
+    /// stream has begun.
+    #[must_use]
+    pub fn started(&self) -> bool {
+        self.started
+    }
+
+    /// The index the next content block takes.
+    #[must_use]
+    pub fn next_block_index(&self) -> usize {
+        self.next_block_index
+    }
 }
 
 /// State machine that translates `OpenAI` streaming chunks into Anthropic SSE events.
@@ -37,10 +86,23 @@ pub struct StreamTranslator {
     started: bool,
     finished: bool,
     content_block_index: usize,
-    in_text_block: bool,
+    open_block: OpenBlock,
+    thinking_blocks: bool,
+    text_filter: Option<StreamFilter>,
+    stop_tokens: Option<StopTokenTrimmer>,
+    think_tags: ThinkTags,
+    think_parser: ThinkTagParser,
     active_tool_calls: Vec<ActiveToolCall>,
+    repaired_tool_calls: Vec<String>,
+    choice_index: Option<u64>,
+    dropped_choices: Vec<u64>,
     input_tokens: u64,
     output_tokens: u64,
+    cache_read_tokens: Option<u64>,
+    estimated_output_tokens: u64,
+    usage_update_interval: u64,
+    last_usage_update: u64,
+    extra: Extra,
 }
 
 impl StreamTranslator {
@@ -52,10 +114,154 @@ impl StreamTranslator {
             started: false,
             finished: false,
             content_block_index: 0,
-            in_text_block: false,
+            open_block: OpenBlock::None,
+            thinking_blocks: false,
+            text_filter: None,
+            stop_tokens: None,
+            think_tags: ThinkTags::Keep,
+            think_parser: ThinkTagParser::new(),
             active_tool_calls: Vec::new(),
+            repaired_tool_calls: Vec::new(),
+            choice_index: None,
+            dropped_choices: Vec::new(),
             input_tokens: 0,
             output_tokens: 0,
+            cache_read_tokens: None,
+            estimated_output_tokens: 0,
+            usage_update_interval: 0,
+            last_usage_update: 0,
+            extra: Extra::new(),
+        }
+    }
+
+    /// A translator that continues a suspended stream: no second
+    /// `message_start` (if one was sent), the same message id, and content
+    /// blocks numbered on from the last one. Options such as output filters
+    /// are set on it as on a new translator.
+    #[must_use]
+    pub fn resume_from(state: StreamState) -> Self {
+        Self {
+            msg_id: state.msg_id,
+            started: state.started,
+            content_block_index: state.next_block_index,
+            input_tokens: state.input_tokens,
+            cache_read_tokens: state.cache_read_tokens,
+            estimated_output_tokens: state.estimated_output_tokens,
+            last_usage_update: state.last_usage_update,
+            ..Self::new(&state.model)
+        }
+    }
+
+    /// Stop translating this upstream stream so another can take over, e.g.
+    /// when the provider fails mid-stream and the request is retried. Held-back
+    /// text is flushed and open blocks are closed (tool arguments completed as
+    /// at the end of a stream); send the returned events to the client, then
+    /// translate the retried stream with [`resume_from`](Self::resume_from).
+    /// This translator emits nothing more.
+    pub fn suspend(&mut self) -> (Vec<StreamEvent>, StreamState) {
+        let mut events = Vec::new();
+        if self.started && !self.finished {
+            self.flush_content(&mut events);
+            self.close_thinking_block(&mut events);
+            if self.open_block == OpenBlock::Text {
+                events.push(StreamEvent::ContentBlockStop {
+                    index: self.content_block_index,
+                });
+                self.content_block_index += 1;
+                self.open_block = OpenBlock::None;
+            }
+            self.close_tool_calls(&mut events);
+        }
+        self.finished = true;
+        let state = StreamState {
+            model: self.model.clone(),
+            msg_id: self.msg_id.clone(),
+            started: self.started,
+            next_block_index: self.content_block_index,
+            input_tokens: self.input_tokens,
+            cache_read_tokens: self.cache_read_tokens,
+            estimated_output_tokens: self.estimated_output_tokens,
+            last_usage_update: self.last_usage_update,
+        };
+        (events, state)
+    }
+
+    /// Emit an interim `message_delta` carrying an estimated `output_tokens` count
+    /// every `interval` estimated tokens, so context meters update mid-stream.
+    /// An interval of 0 disables interim updates.
+    #[must_use]
+    pub fn with_usage_updates(mut self, interval: u64) -> Self {
+        self.usage_update_interval = interval;
+        self
+    }
+
+    /// Stream `reasoning_content` as a `thinking` block rather than as text.
+    #[must_use]
+    pub fn with_thinking_blocks(mut self, enabled: bool) -> Self {
+        self.thinking_blocks = enabled;
+        self
+    }
+
+    /// Post-process text deltas with `filters`. Text a match may still be
+    /// starting in is held back until it is resolved or the text block ends.
+    #[must_use]
+    pub fn with_output_filters(mut self, filters: &OutputFilters) -> Self {
+        self.text_filter = (!filters.is_empty()).then(|| filters.stream());
+        self
+    }
+
+    /// Strip stop tokens leaked at the end of text blocks. Whitespace and
+    /// anything that may be the start of one is held back until it's known
+    /// not to end the block.
+    #[must_use]
+    pub fn with_stop_token_stripping(mut self, enabled: bool) -> Self {
+        self.stop_tokens = enabled.then(StopTokenTrimmer::new);
+        self
+    }
+
+    /// Stop tokens stripped from the stream so far.
+    #[must_use]
+    pub fn leaked_stop_tokens(&self) -> &[&'static str] {
+        self.stop_tokens
+            .as_ref()
+            .map_or(&[], StopTokenTrimmer::leaked)
+    }
+
+    /// Names of the tool calls whose arguments weren't valid JSON and were
+    /// repaired.
+    #[must_use]
+    pub fn repaired_tool_calls(&self) -> &[String] {
+        &self.repaired_tool_calls
+    }
+
+    /// How many choices besides the first the backend streamed (for `n` > 1);
+    /// only the first is translated.
+    #[must_use]
+    pub fn dropped_choices(&self) -> usize {
+        self.dropped_choices.len()
+    }
+
+    /// Split inline `<think>` blocks out of the text, emitting them as
+    /// `thinking` blocks or dropping them.
+    #[must_use]
+    pub fn with_think_tags(mut self, mode: ThinkTags) -> Self {
+        self.think_tags = mode;
+        self
+    }
+
+    /// Token usage so far: as reported by the provider, or the running output
+    /// estimate if it hasn't reported any.
+    #[must_use]
+    pub fn usage(&self) -> Usage {
+        Usage {
+            input_tokens: self.input_tokens,
+            output_tokens: if self.output_tokens == 0 {
+                self.estimated_output_tokens
+            } else {
+                self.output_tokens
+            },
+            cache_creation_input_tokens: None,
+            cache_read_input_tokens: self.cache_read_tokens,
         }
     }
 
@@ -69,9 +275,11 @@ impl StreamTranslator {
 
         // Capture usage if provided
         if let Some(ref usage) = chunk.usage {
-            self.input_tokens = usage.prompt_tokens;
+            self.input_tokens = usage.uncached_prompt_tokens();
             self.output_tokens = usage.completion_tokens;
+            self.cache_read_tokens = usage.cached_tokens();
         }
+        self.collect_extra(chunk);
 
         // Emit message_start on first chunk
         if !self.started {
@@ -80,122 +288,72 @@ impl StreamTranslator {
             self.started = true;
         }
 
-        let Some(choice) = chunk.choices.first() else {
+        // A backend asked for several choices streams them interleaved, each
+        // by its index; the first one seen is followed
+        let Some(followed) = self
+            .choice_index
+            .or_else(|| chunk.choices.first().map(|c| c.index))
+        else {
+            return events;
+        };
+        self.choice_index = Some(followed);
+        for choice in &chunk.choices {
+            if choice.index != followed && !self.dropped_choices.contains(&choice.index) {
+                self.dropped_choices.push(choice.index);
+            }
+        }
+        let Some(choice) = chunk.choices.iter().find(|c| c.index == followed) else {
             return events;
         };
 
         // Handle text content deltas.
         // Some reasoning models (Kimi K2.5, DeepSeek R1) stream chain-of-thought
-        // in `reasoning_content` and the final answer in `content`. We emit both
-        // as text deltas so Claude Code sees the full response.
-        let effective_content = choice
+        // in `reasoning_content` and the final answer in `content`. Unless
+        // thinking blocks are enabled, we emit both as text deltas so Claude
+        // Code sees the full response.
+        let content = choice.delta.content.as_deref().filter(|s| !s.is_empty());
+        let reasoning = choice
             .delta
-            .content
+            .reasoning_content
             .as_deref()
-            .filter(|s| !s.is_empty())
-            .or_else(|| {
-                choice
-                    .delta
-                    .reasoning_content
-                    .as_deref()
-                    .filter(|s| !s.is_empty())
-            });
+            .filter(|s| !s.is_empty());
 
-        if let Some(content) = effective_content {
-            if !self.in_text_block {
-                events.push(StreamEvent::ContentBlockStart {
-                    index: self.content_block_index,
-                    content_block: ResponseContentBlock::Text {
-                        text: String::new(),
-                    },
-                });
-                self.in_text_block = true;
+        let effective_content = if self.thinking_blocks {
+            if let Some(thinking) = reasoning {
+                self.push_thinking_delta(thinking, &mut events);
+            }
+            content.map(Cow::Borrowed)
+        } else {
+            match (reasoning, content) {
+                (Some(reasoning), Some(content)) => {
+                    Some(Cow::Owned(format!("{reasoning}{content}")))
+                }
+                (reasoning, content) => content.or(reasoning).map(Cow::Borrowed),
             }
+        };
 
-            events.push(StreamEvent::ContentBlockDelta {
-                index: self.content_block_index,
-                delta: Delta::TextDelta {
-                    text: content.to_string(),
-                },
-            });
+        if let Some(content) = effective_content.as_deref() {
+            self.estimated_output_tokens += estimate_tokens(content);
+            if self.think_tags == ThinkTags::Keep {
+                self.push_text(content, &mut events);
+            } else {
+                let segments = self.think_parser.push(content);
+                self.push_segments(segments, &mut events);
+            }
         }
 
         // Handle tool call deltas
         if let Some(ref tool_calls) = choice.delta.tool_calls {
             for tc in tool_calls {
-                let tc_index = tc.index as usize;
-
-                // Check if this is a new tool call (has an id)
-                if tc.id.is_some() {
-                    // Close text block if open
-                    if self.in_text_block {
-                        events.push(StreamEvent::ContentBlockStop {
-                            index: self.content_block_index,
-                        });
-                        self.content_block_index += 1;
-                        self.in_text_block = false;
-                    }
-
-                    let tool_id = tc.id.clone().unwrap_or_default();
-                    let tool_name = tc
-                        .function
-                        .as_ref()
-                        .and_then(|f| f.name.clone())
-                        .unwrap_or_default();
-
-                    // Emit content_block_start for this tool_use
-                    events.push(StreamEvent::ContentBlockStart {
-                        index: self.content_block_index,
-                        content_block: ResponseContentBlock::ToolUse {
-                            id: tool_id.clone(),
-                            name: tool_name.clone(),
-                            input: serde_json::Value::Object(serde_json::Map::new()),
-                        },
-                    });
-
-                    // Ensure our active_tool_calls vec is big enough
-                    while self.active_tool_calls.len() <= tc_index {
-                        self.active_tool_calls.push(ActiveToolCall {
-                            anthropic_block_index: 0,
-                            id: String::new(),
-                            name: String::new(),
-                            emitted_start: false,
-                        });
-                    }
-
-                    self.active_tool_calls[tc_index] = ActiveToolCall {
-                        anthropic_block_index: self.content_block_index,
-                        id: tool_id,
-                        name: tool_name,
-                        emitted_start: true,
-                    };
-                }
-
-                // Emit argument deltas
-                if let Some(ref func) = tc.function {
-                    if let Some(ref args) = func.arguments {
-                        if !args.is_empty() {
-                            let block_idx = if tc_index < self.active_tool_calls.len() {
-                                self.active_tool_calls[tc_index].anthropic_block_index
-                            } else {
-                                self.content_block_index
-                            };
-
-                            events.push(StreamEvent::ContentBlockDelta {
-                                index: block_idx,
-                                delta: Delta::InputJsonDelta {
-                                    partial_json: args.clone(),
-                                },
-                            });
-                        }
-                    }
-                }
+                self.push_tool_call_delta(tc, &mut events);
             }
         }
 
         // Handle finish
         if let Some(ref reason) = choice.finish_reason {
             events.append(&mut self.make_finish_events(reason));
+        } else if let Some(event) = self.make_usage_update() {
+            events.push(event);
         }
 
         events
@@ -203,18 +361,140 @@ impl StreamTranslator {
 
     /// Call when the stream ends (on `[DONE]`) to flush any remaining events.
     pub fn finish(&mut self) -> Vec<StreamEvent> {
+        self.finish_with("stop")
+    }
+
+    /// End the stream early, as if the model had run out of tokens: whatever
+    /// was generated is flushed, open blocks are closed, and the stop reason is
+    /// `max_tokens`.
+    pub fn cut_off(&mut self) -> Vec<StreamEvent> {
+        self.finish_with("length")
+    }
+
+    fn finish_with(&mut self, reason: &str) -> Vec<StreamEvent> {
         if self.finished {
             return Vec::new();
         }
 
         if !self.started {
             let mut events = vec![self.make_message_start()];
-            events.append(&mut self.make_finish_events("stop"));
+            events.append(&mut self.make_finish_events(reason));
             return events;
         }
 
         // If we haven't finished normally (no finish_reason seen), close now
-        self.make_finish_events("stop")
+        self.make_finish_events(reason)
+    }
+
+    /// Pass answer text through the output filter, if any.
+    fn push_text(&mut self, text: &str, events: &mut Vec<StreamEvent>) {
+        let text = match self.text_filter.as_mut() {
+            Some(filter) => filter.push(text),
+            None => text.to_string(),
+        };
+        let text = match self.stop_tokens.as_mut() {
+            Some(trimmer) => trimmer.push(&text),
+            None => text,
+        };
+        self.push_text_delta(text, events);
+    }
+
+    fn push_segments(&mut self, segments: Vec<ThinkSegment>, events: &mut Vec<StreamEvent>) {
+        for segment in segments {
+            match segment {
+                ThinkSegment::Text(text) => self.push_text(&text, events),
+                ThinkSegment::Thinking(thinking) => {
+                    if self.think_tags == ThinkTags::Thinking {
+                        self.emit_thinking_delta(thinking, events);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Emit everything held back for tag parsing and filtering.
+    fn flush_content(&mut self, events: &mut Vec<StreamEvent>) {
+        let segments = self.think_parser.finish();
+        self.push_segments(segments, events);
+        self.flush_text(events);
+    }
+
+    fn push_text_delta(&mut self, text: String, events: &mut Vec<StreamEvent>) {
+        if text.is_empty() {
+            return;
+        }
+        self.close_thinking_block(events);
+
+        if self.open_block != OpenBlock::Text {
+            events.push(StreamEvent::ContentBlockStart {
+                index: self.content_block_index,
+                content_block: ResponseContentBlock::Text {
+                    text: String::new(),
+                },
+            });
+            self.open_block = OpenBlock::Text;
+        }
+
+        events.push(StreamEvent::ContentBlockDelta {
+            index: self.content_block_index,
+            delta: Delta::TextDelta { text },
+        });
+    }
+
+    /// Emit text the output filter and stop-token trimmer were holding back.
+    fn flush_text(&mut self, events: &mut Vec<StreamEvent>) {
+        let mut text = self
+            .text_filter
+            .as_mut()
+            .map(StreamFilter::flush)
+            .unwrap_or_default();
+        if let Some(trimmer) = self.stop_tokens.as_mut() {
+            text = trimmer.push(&text);
+            text += &trimmer.finish();
+        }
+        self.push_text_delta(text, events);
+    }
+
+    fn push_thinking_delta(&mut self, thinking: &str, events: &mut Vec<StreamEvent>) {
+        self.estimated_output_tokens += estimate_tokens(thinking);
+        self.emit_thinking_delta(thinking.to_string(), events);
+    }
+
+    fn emit_thinking_delta(&mut self, thinking: String, events: &mut Vec<StreamEvent>) {
+        self.flush_text(events);
+
+        if self.open_block == OpenBlock::Text {
+            events.push(StreamEvent::ContentBlockStop {
+                index: self.content_block_index,
+            });
+            self.content_block_index += 1;
+            self.open_block = OpenBlock::None;
+        }
+        if self.open_block != OpenBlock::Thinking {
+            events.push(StreamEvent::ContentBlockStart {
+                index: self.content_block_index,
+                content_block: ResponseContentBlock::Thinking {
+                    thinking: String::new(),
+                    signature: String::new(),
+                },
+            });
+            self.open_block = OpenBlock::Thinking;
+        }
+
+        events.push(StreamEvent::ContentBlockDelta {
+            index: self.content_block_index,
+            delta: Delta::ThinkingDelta { thinking },
+        });
+    }
+
+    fn close_thinking_block(&mut self, events: &mut Vec<StreamEvent>) {
+        if self.open_block == OpenBlock::Thinking {
+            events.push(StreamEvent::ContentBlockStop {
+                index: self.content_block_index,
+            });
+            self.content_block_index += 1;
+            self.open_block = OpenBlock::None;
+        }
     }
 
     fn make_message_start(&self) -> StreamEvent {
@@ -233,10 +513,33 @@ impl StreamTranslator {
                     cache_creation_input_tokens: None,
                     cache_read_input_tokens: None,
                 },
+                extra: None,
             },
         }
     }
 
+    /// Interim usage report, once the estimate has grown by a full interval.
+    fn make_usage_update(&mut self) -> Option<StreamEvent> {
+        if self.usage_update_interval == 0
+            || self.estimated_output_tokens < self.last_usage_update + self.usage_update_interval
+        {
+            return None;
+        }
+        self.last_usage_update = self.estimated_output_tokens;
+
+        Some(StreamEvent::MessageDelta {
+            delta: MessageDeltaBody {
+                stop_reason: None,
+                stop_sequence: None,
+            },
+            usage: DeltaUsage {
+                output_tokens: self.estimated_output_tokens,
+                cache_read_input_tokens: None,
+            },
+            extra: None,
+        })
+    }
+
     fn make_finish_events(&mut self, reason: &str) -> Vec<StreamEvent> {
         if self.finished {
             return Vec::new();
@@ -245,23 +548,17 @@ impl StreamTranslator {
 
         let mut events = Vec::new();
 
-        // Close text block if open
-        if self.in_text_block {
+        // Close text or thinking block if open
+        self.flush_content(&mut events);
+        self.close_thinking_block(&mut events);
+        if self.open_block == OpenBlock::Text {
             events.push(StreamEvent::ContentBlockStop {
                 index: self.content_block_index,
             });
-            self.in_text_block = false;
+            self.open_block = OpenBlock::None;
         }
 
-        // Close any open tool blocks
-        for tc in &self.active_tool_calls {
-            if tc.emitted_start {
-                events.push(StreamEvent::ContentBlockStop {
-                    index: tc.anthropic_block_index,
-                });
-            }
-        }
-        self.active_tool_calls.clear();
+        self.close_tool_calls(&mut events);
 
         events.push(StreamEvent::MessageDelta {
             delta: MessageDeltaBody {
@@ -269,20 +566,340 @@ impl StreamTranslator {
                 stop_sequence: None,
             },
             usage: DeltaUsage {
-                output_tokens: self.output_tokens,
+                // Providers that never report usage would otherwise reset the
+                // meter to zero after our interim estimates.
+                output_tokens: if self.output_tokens == 0 && self.usage_update_interval > 0 {
+                    self.estimated_output_tokens
+                } else {
+                    self.output_tokens
+                },
+                cache_read_input_tokens: self.cache_read_tokens,
             },
+            extra: (!self.extra.is_empty()).then(|| std::mem::take(&mut self.extra)),
         });
 
         events.push(StreamEvent::MessageStop);
 
         events
     }
+
+    /// Note a chunk's provider-specific data, for the final `message_delta`.
+    /// Streamed log probabilities come a few tokens per chunk, and are joined.
+    fn collect_extra(&mut self, chunk: &ChatCompletionChunk) {
+        if let Some(fingerprint) = &chunk.system_fingerprint {
+            self.extra
+                .insert("system_fingerprint".into(), fingerprint.clone().into());
+        }
+        if let Some(citations) = &chunk.citations {
+            self.extra.insert("citations".into(), citations.clone());
+        }
+        if let Some(cost) = chunk.usage.as_ref().and_then(|u| u.cost) {
+            self.extra.insert("cost".into(), cost.into());
+        }
+        let followed = self
+            .choice_index
+            .or_else(|| chunk.choices.first().map(|c| c.index));
+        let Some(logprobs) = chunk
+            .choices
+            .iter()
+            .find(|c| Some(c.index) == followed)
+            .and_then(|c| c.logprobs.as_ref())
+        else {
+            return;
+        };
+        let seen = self
+            .extra
+            .get_mut("logprobs")
+            .and_then(|seen| seen.get_mut("content"))
+            .and_then(serde_json::Value::as_array_mut);
+        let more = logprobs
+            .get("content")
+            .and_then(serde_json::Value::as_array);
+        if let (Some(seen), Some(more)) = (seen, more) {
+            seen.extend(more.iter().cloned());
+        } else {
+            self.extra.insert("logprobs".into(), logprobs.clone());
+        }
+    }
+
+    /// Route one tool call delta to its content block.
+    ///
+    /// Each `OpenAI` tool call index gets its own block, numbered when the
+    /// call is first seen, so deltas of parallel calls may arrive interleaved.
+    /// A delta with a new id at an index already in use starts a new call
+    /// there (some providers number every call 0), closing the one before.
+    fn push_tool_call_delta(&mut self, tc: &ChunkToolCall, events: &mut Vec<StreamEvent>) {
+        let position = self
+            .active_tool_calls
+            .iter()
+            .position(|call| call.openai_index == tc.index);
+        let position = match (position, tc.id.as_deref()) {
+            (Some(i), Some(id)) if !id.is_empty() && id != self.active_tool_calls[i].id => {
+                let call = self.active_tool_calls.remove(i);
+                self.close_tool_call(call, events);
+                self.start_tool_call(tc, events)
+            }
+            (Some(i), _) => i,
+            (None, _) => self.start_tool_call(tc, events),
+        };
+
+        // Emit argument deltas, as far as they are known to be valid
+        let Some(args) = tc
+            .function
+            .as_ref()
+            .and_then(|f| f.arguments.as_deref())
+            .filter(|args| !args.is_empty())
+        else {
+            return;
+        };
+        self.estimated_output_tokens += estimate_tokens(args);
+        let call = &mut self.active_tool_calls[position];
+        let partial_json = call.args.push(args);
+        if !partial_json.is_empty() {
+            events.push(StreamEvent::ContentBlockDelta {
+                index: call.anthropic_block_index,
+                delta: Delta::InputJsonDelta { partial_json },
+            });
+        }
+    }
+
+    /// Open a `tool_use` block for a call first seen in `tc`, returning its
+    /// position in `active_tool_calls`.
+    fn start_tool_call(&mut self, tc: &ChunkToolCall, events: &mut Vec<StreamEvent>) -> usize {
+        // Close text or thinking block if open
+        self.flush_content(events);
+        self.close_thinking_block(events);
+        if self.open_block == OpenBlock::Text {
+            events.push(StreamEvent::ContentBlockStop {
+                index: self.content_block_index,
+            });
+            self.content_block_index += 1;
+            self.open_block = OpenBlock::None;
+        }
+
+        let id = tc
+            .id
+            .clone()
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| format!("call_{}", uuid::Uuid::new_v4().simple()));
+        let name = tc
+            .function
+            .as_ref()
+            .and_then(|f| f.name.clone())
+            .unwrap_or_default();
+        let index = self.content_block_index;
+        self.content_block_index += 1;
+        events.push(StreamEvent::ContentBlockStart {
+            index,
+            content_block: ResponseContentBlock::ToolUse {
+                id: id.clone(),
+                name: name.clone(),
+                input: serde_json::Value::Object(serde_json::Map::new()),
+            },
+        });
+        self.active_tool_calls.push(ActiveToolCall {
+            openai_index: tc.index,
+            anthropic_block_index: index,
+            id,
+            name,
+            args: ToolArgs::new(),
+        });
+        self.active_tool_calls.len() - 1
+    }
+
+    /// Complete the arguments of any open tool blocks and close them.
+    fn close_tool_calls(&mut self, events: &mut Vec<StreamEvent>) {
+        for call in std::mem::take(&mut self.active_tool_calls) {
+            self.close_tool_call(call, events);
+        }
+    }
+
+    fn close_tool_call(&mut self, mut call: ActiveToolCall, events: &mut Vec<StreamEvent>) {
+        let end = call.args.finish();
+        if end.repaired {
+            self.repaired_tool_calls.push(call.name);
+        }
+        if !end.rest.is_empty() {
+            events.push(StreamEvent::ContentBlockDelta {
+                index: call.anthropic_block_index,
+                delta: Delta::InputJsonDelta {
+                    partial_json: end.rest,
+                },
+            });
+        }
+        events.push(StreamEvent::ContentBlockStop {
+            index: call.anthropic_block_index,
+        });
+    }
+}
+
+/// The events that stream a complete response: each block is started empty,
+/// filled by a single delta and stopped.
+#[must_use]
+pub fn response_events(resp: &MessagesResponse) -> Vec<StreamEvent> {
+    let mut events = vec![StreamEvent::MessageStart {
+        message: MessagesResponse {
+            content: Vec::new(),
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Usage {
+                output_tokens: 0,
+                ..resp.usage.clone()
+            },
+            ..resp.clone()
+        },
+    }];
+
+    for (index, block) in resp.content.iter().enumerate() {
+        let (start, delta) = match block {
+            ResponseContentBlock::Text { text } => (
+                ResponseContentBlock::Text {
+                    text: String::new(),
+                },
+                Delta::TextDelta { text: text.clone() },
+            ),
+            ResponseContentBlock::ToolUse { id, name, input } => (
+                ResponseContentBlock::ToolUse {
+                    id: id.clone(),
+                    name: name.clone(),
+                    input: serde_json::json!({}),
+                },
+                Delta::InputJsonDelta {
+                    partial_json: input.to_string(),
+                },
+            ),
+            ResponseContentBlock::Thinking { thinking, .. } => (
+                ResponseContentBlock::Thinking {
+                    thinking: String::new(),
+                    signature: String::new(),
+                },
+                Delta::ThinkingDelta {
+                    thinking: thinking.clone(),
+                },
+            ),
+        };
+        events.push(StreamEvent::ContentBlockStart {
+            index,
+            content_block: start,
+        });
+        events.push(StreamEvent::ContentBlockDelta { index, delta });
+        events.push(StreamEvent::ContentBlockStop { index });
+    }
+
+    events.push(StreamEvent::MessageDelta {
+        delta: MessageDeltaBody {
+            stop_reason: resp.stop_reason.clone(),
+            stop_sequence: resp.stop_sequence.clone(),
+        },
+        usage: DeltaUsage {
+            output_tokens: resp.usage.output_tokens,
+            cache_read_input_tokens: resp.usage.cache_read_input_tokens,
+        },
+        extra: resp.extra.clone(),
+    });
+    events.push(StreamEvent::MessageStop);
+    events
+}
+
+/// Rebuilds the complete response from a stream of events, the inverse of
+/// [`response_events`].
+#[derive(Debug, Default)]
+pub struct ResponseCollector {
+    response: Option<MessagesResponse>,
+    /// Tool input JSON received so far, by block index.
+    partial_json: std::collections::HashMap<usize, String>,
+    stopped: bool,
+}
+
+impl ResponseCollector {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, event: &StreamEvent) {
+        if let StreamEvent::MessageStart { message } = event {
+            self.response = Some(message.clone());
+            return;
+        }
+        let Some(response) = self.response.as_mut() else {
+            return;
+        };
+        match event {
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => {
+                if *index == response.content.len() {
+                    response.content.push(content_block.clone());
+                }
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => {
+                match (response.content.get_mut(*index), delta) {
+                    (
+                        Some(ResponseContentBlock::Text { text }),
+                        Delta::TextDelta { text: more },
+                    ) => {
+                        text.push_str(more);
+                    }
+                    (
+                        Some(ResponseContentBlock::Thinking { thinking, .. }),
+                        Delta::ThinkingDelta { thinking: more },
+                    ) => thinking.push_str(more),
+                    (
+                        Some(ResponseContentBlock::ToolUse { .. }),
+                        Delta::InputJsonDelta { partial_json },
+                    ) => {
+                        self.partial_json
+                            .entry(*index)
+                            .or_default()
+                            .push_str(partial_json);
+                    }
+                    _ => {}
+                }
+            }
+            StreamEvent::ContentBlockStop { index } => {
+                if let (Some(ResponseContentBlock::ToolUse { input, .. }), Some(json)) = (
+                    response.content.get_mut(*index),
+                    self.partial_json.remove(index),
+                ) {
+                    *input = serde_json::from_str(&json).unwrap_or(serde_json::Value::String(json));
+                }
+            }
+            StreamEvent::MessageDelta {
+                delta,
+                usage,
+                extra,
+            } => {
+                if delta.stop_reason.is_some() {
+                    response.stop_reason.clone_from(&delta.stop_reason);
+                    response.stop_sequence.clone_from(&delta.stop_sequence);
+                }
+                if extra.is_some() {
+                    response.extra.clone_from(extra);
+                }
+                response.usage.output_tokens = usage.output_tokens;
+                if usage.cache_read_input_tokens.is_some() {
+                    response.usage.cache_read_input_tokens = usage.cache_read_input_tokens;
+                }
+            }
+            StreamEvent::MessageStop => self.stopped = true,
+            StreamEvent::MessageStart { .. } | StreamEvent::Ping => {}
+        }
+    }
+
+    /// The response, if the stream got as far as `message_stop`.
+    #[must_use]
+    pub fn finish(self) -> Option<MessagesResponse> {
+        self.response.filter(|_| self.stopped)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::translate::openai_types::*;
+    use crate::translate::stream_check;
 
     fn text_chunk(id: &str, content: &str, finish: Option<&str>) -> ChatCompletionChunk {
         ChatCompletionChunk {
@@ -299,8 +916,11 @@ mod tests {
                     tool_calls: None,
                 },
                 finish_reason: finish.map(String::from),
+                logprobs: None,
             }],
             usage: None,
+            system_fingerprint: None,
+            citations: None,
         }
     }
 
@@ -366,8 +986,11 @@ mod tests {
                     }]),
                 },
                 finish_reason: None,
+                logprobs: None,
             }],
             usage: None,
+            system_fingerprint: None,
+            citations: None,
         };
 
         let events = translator.process_chunk(&tool_chunk);
@@ -380,6 +1003,417 @@ mod tests {
         assert!(event_names.contains(&"content_block_delta")); // argument delta
     }
 
+    #[test]
+    fn test_tool_call_arguments_repaired() {
+        let tool_chunk = |id: Option<&str>, args: &str, finish: Option<&str>| ChatCompletionChunk {
+            id: "c1".to_string(),
+            object: "chat.completion.chunk".to_string(),
+            created: 0,
+            model: "test".to_string(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta: ChunkDelta {
+                    role: None,
+                    content: None,
+                    reasoning_content: None,
+                    tool_calls: Some(vec![ChunkToolCall {
+                        index: 0,
+                        id: id.map(String::from),
+                        call_type: None,
+                        function: Some(ChunkToolCallFunction {
+                            name: id.map(|_| "search".to_string()),
+                            arguments: Some(args.to_string()),
+                        }),
+                    }]),
+                },
+                finish_reason: finish.map(String::from),
+                logprobs: None,
+            }],
+            usage: None,
+            system_fingerprint: None,
+            citations: None,
+        };
+
+        // The arguments arrive twice over
+        let mut translator = StreamTranslator::new("test-model");
+        let mut events =
+            translator.process_chunk(&tool_chunk(Some("call_1"), "{\"q\": \"ru", None));
+        events.extend(translator.process_chunk(&tool_chunk(None, "st\"}", None)));
+        events.extend(translator.process_chunk(&tool_chunk(
+            None,
+            "{\"q\": \"rust\"}",
+            Some("tool_calls"),
+        )));
+
+        let input: String = events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::ContentBlockDelta {
+                    delta: Delta::InputJsonDelta { partial_json },
+                    ..
+                } => Some(partial_json.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(input, "{\"q\": \"rust\"}");
+        assert_eq!(translator.repaired_tool_calls(), ["search"]);
+    }
+
+    /// A chunk of tool call deltas, `(index, id, arguments)` each; a call
+    /// with an id is named `tool_<id>`.
+    fn tool_calls_chunk(calls: &[(u64, Option<&str>, &str)]) -> ChatCompletionChunk {
+        let mut chunk = text_chunk("c1", "", None);
+        chunk.choices[0].delta.content = None;
+        chunk.choices[0].delta.tool_calls = Some(
+            calls
+                .iter()
+                .map(|&(index, id, args)| ChunkToolCall {
+                    index,
+                    id: id.map(String::from),
+                    call_type: None,
+                    function: Some(ChunkToolCallFunction {
+                        name: id.map(|id| format!("tool_{id}")),
+                        arguments: Some(args.to_string()),
+                    }),
+                })
+                .collect(),
+        );
+        chunk
+    }
+
+    /// Each tool block's name and streamed input, by block index.
+    fn tool_inputs(events: &[StreamEvent]) -> Vec<(usize, String, String)> {
+        let mut blocks: Vec<(usize, String, String)> = Vec::new();
+        for event in events {
+            match event {
+                StreamEvent::ContentBlockStart {
+                    index,
+                    content_block: ResponseContentBlock::ToolUse { name, .. },
+                } => blocks.push((*index, name.clone(), String::new())),
+                StreamEvent::ContentBlockDelta {
+                    index,
+                    delta: Delta::InputJsonDelta { partial_json },
+                } => {
+                    let block = blocks.iter_mut().find(|b| b.0 == *index).unwrap();
+                    block.2.push_str(partial_json);
+                }
+                _ => {}
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_interleaved_tool_calls() {
+        // Two calls start in one chunk, then their arguments alternate
+        let mut translator = StreamTranslator::new("test-model");
+        let mut events = translator.process_chunk(&text_chunk("c1", "Both.", None));
+        events.extend(translator.process_chunk(&tool_calls_chunk(&[
+            (0, Some("a"), "{\"x\":"),
+            (1, Some("b"), "{\"y\":"),
+        ])));
+        events.extend(translator.process_chunk(&tool_calls_chunk(&[(1, None, "2}")])));
+        events.extend(translator.process_chunk(&tool_calls_chunk(&[(0, None, "1}")])));
+        // A third call, announced with its id on every delta
+        events.extend(translator.process_chunk(&tool_calls_chunk(&[(2, Some("c"), "{")])));
+        events.extend(translator.process_chunk(&tool_calls_chunk(&[(2, Some("c"), "}")])));
+        events.extend(translator.process_chunk(&text_chunk("c1", "", Some("tool_calls"))));
+
+        assert!(stream_check::check(&events).is_empty(), "{events:?}");
+        assert_eq!(started_blocks(&events), [0, 1, 2, 3]);
+        assert_eq!(
+            tool_inputs(&events),
+            [
+                (1, "tool_a".to_string(), "{\"x\":1}".to_string()),
+                (2, "tool_b".to_string(), "{\"y\":2}".to_string()),
+                (3, "tool_c".to_string(), "{}".to_string()),
+            ]
+        );
+        assert!(translator.repaired_tool_calls().is_empty());
+    }
+
+    #[test]
+    fn test_tool_calls_sharing_an_index() {
+        // Every call numbered 0, told apart by id; text after them gets a
+        // block of its own
+        let mut translator = StreamTranslator::new("test-model");
+        let mut events = translator.process_chunk(&tool_calls_chunk(&[(0, Some("a"), "{}")]));
+        events.extend(translator.process_chunk(&tool_calls_chunk(&[(0, Some("b"), "{\"q\"")])));
+        events.extend(translator.process_chunk(&tool_calls_chunk(&[(0, None, ":1}")])));
+        events.extend(translator.process_chunk(&text_chunk("c1", "Done.", Some("tool_calls"))));
+
+        assert!(stream_check::check(&events).is_empty(), "{events:?}");
+        assert_eq!(started_blocks(&events), [0, 1, 2]);
+        assert_eq!(
+            tool_inputs(&events),
+            [
+                (0, "tool_a".to_string(), "{}".to_string()),
+                (1, "tool_b".to_string(), "{\"q\":1}".to_string()),
+            ]
+        );
+        assert!(events.iter().any(|e| matches!(
+            e,
+            StreamEvent::ContentBlockDelta {
+                index: 2,
+                delta: Delta::TextDelta { .. }
+            }
+        )));
+    }
+
+    #[test]
+    fn test_multiple_choices_follow_the_first() {
+        // Choices 1 and 0 interleaved, one chunk carrying both; choice 1
+        // finishing doesn't end the stream
+        let other = |content: &str, finish| {
+            let mut chunk = text_chunk("c1", content, finish);
+            chunk.choices[0].index = 1;
+            chunk
+        };
+        let mut translator = StreamTranslator::new("test-model");
+        let mut events = translator.process_chunk(&text_chunk("c1", "Hel", None));
+        events.extend(translator.process_chunk(&other("Bonj", None)));
+        let mut both = other("our", Some("length"));
+        both.choices.extend(text_chunk("c1", "lo", None).choices);
+        events.extend(translator.process_chunk(&both));
+        events.extend(translator.process_chunk(&text_chunk("c1", ".", Some("stop"))));
+        events.extend(translator.finish());
+
+        assert!(stream_check::check(&events).is_empty(), "{events:?}");
+        let text: String = events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::ContentBlockDelta {
+                    delta: Delta::TextDelta { text },
+                    ..
+                } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, "Hello.");
+        assert!(events.iter().any(|e| matches!(
+            e,
+            StreamEvent::MessageDelta { delta, .. } if delta.stop_reason.as_deref() == Some("end_turn")
+        )));
+        assert_eq!(translator.dropped_choices(), 1);
+    }
+
+    #[test]
+    fn test_interim_usage_updates() {
+        let mut translator = StreamTranslator::new("test-model").with_usage_updates(5);
+
+        // 8 chars ≈ 2 tokens: below the interval
+        let events = translator.process_chunk(&text_chunk("c1", "abcdefgh", None));
+        assert!(!events.iter().any(|e| e.event_name() == "message_delta"));
+
+        // 16 more chars ≈ 4 tokens: crosses 5
+        let events = translator.process_chunk(&text_chunk("c1", "abcdefghijklmnop", None));
+        assert_eq!(events.len(), 2);
+        match &events[1] {
+            StreamEvent::MessageDelta { delta, usage, .. } => {
+                assert!(delta.stop_reason.is_none());
+                assert_eq!(usage.output_tokens, 6);
+            }
+            other => panic!("Expected message_delta, got {other:?}"),
+        }
+
+        // No usage from provider: the final delta carries the estimate
+        let events = translator.finish();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            StreamEvent::MessageDelta { usage, .. } if usage.output_tokens == 6
+        )));
+    }
+
+    #[test]
+    fn test_reasoning_as_thinking_block() {
+        let mut translator = StreamTranslator::new("test-model").with_thinking_blocks(true);
+
+        let mut reasoning = text_chunk("c1", "", None);
+        reasoning.choices[0].delta.content = None;
+        reasoning.choices[0].delta.reasoning_content = Some("Let me think.".to_string());
+        let events = translator.process_chunk(&reasoning);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            StreamEvent::ContentBlockStart {
+                index: 0,
+                content_block: ResponseContentBlock::Thinking { .. }
+            }
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            StreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: Delta::ThinkingDelta { .. }
+            }
+        )));
+
+        // The answer closes the thinking block and opens a text block after it
+        let events = translator.process_chunk(&text_chunk("c1", "42", None));
+        assert!(matches!(
+            events[0],
+            StreamEvent::ContentBlockStop { index: 0 }
+        ));
+        assert!(matches!(
+            events[1],
+            StreamEvent::ContentBlockStart {
+                index: 1,
+                content_block: ResponseContentBlock::Text { .. }
+            }
+        ));
+        assert!(matches!(
+            events[2],
+            StreamEvent::ContentBlockDelta {
+                index: 1,
+                delta: Delta::TextDelta { .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_output_filters_across_deltas() {
+        use crate::translate::filters::BuiltinFilter;
+
+        let filters = OutputFilters::new(&[BuiltinFilter::ThinkTags], &[]);
+        let mut translator = StreamTranslator::new("test-model").with_output_filters(&filters);
+
+        let mut text = String::new();
+        for piece in ["Hi <thi", "nk>hidden</th", "ink>there", ""] {
+            let finish = piece.is_empty().then_some("stop");
+            for event in translator.process_chunk(&text_chunk("c1", piece, finish)) {
+                if let StreamEvent::ContentBlockDelta {
+                    delta: Delta::TextDelta { text: t },
+                    ..
+                } = event
+                {
+                    text.push_str(&t);
+                }
+            }
+        }
+        assert_eq!(text, "Hi there");
+    }
+
+    #[test]
+    fn test_think_tags_as_thinking_block() {
+        let mut translator =
+            StreamTranslator::new("test-model").with_think_tags(ThinkTags::Thinking);
+
+        let mut events = Vec::new();
+        for piece in ["<thi", "nk>Plan.</thi", "nk>\n\nAns", "wer", ""] {
+            let finish = piece.is_empty().then_some("stop");
+            events.extend(translator.process_chunk(&text_chunk("c1", piece, finish)));
+        }
+
+        let mut blocks: Vec<(usize, &str, String)> = Vec::new();
+        for event in &events {
+            match event {
+                StreamEvent::ContentBlockStart {
+                    index,
+                    content_block,
+                } => {
+                    let kind = match content_block {
+                        ResponseContentBlock::Thinking { .. } => "thinking",
+                        _ => "text",
+                    };
+                    blocks.push((*index, kind, String::new()));
+                }
+                StreamEvent::ContentBlockDelta { index, delta } => {
+                    let block = blocks.last_mut().unwrap();
+                    assert_eq!(block.0, *index);
+                    match delta {
+                        Delta::ThinkingDelta { thinking } => block.2.push_str(thinking),
+                        Delta::TextDelta { text } => block.2.push_str(text),
+                        Delta::InputJsonDelta { .. } => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(
+            blocks,
+            vec![
+                (0, "thinking", "Plan.".to_string()),
+                (1, "text", "Answer".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leaked_stop_token_stripped() {
+        let mut translator = StreamTranslator::new("test-model").with_stop_token_stripping(true);
+
+        let mut text = String::new();
+        for piece in ["Done.", "\n<|eot", "_id|>", ""] {
+            let finish = piece.is_empty().then_some("stop");
+            for event in translator.process_chunk(&text_chunk("c1", piece, finish)) {
+                if let StreamEvent::ContentBlockDelta {
+                    delta: Delta::TextDelta { text: t },
+                    ..
+                } = event
+                {
+                    text.push_str(&t);
+                }
+            }
+        }
+        assert_eq!(text, "Done.");
+        assert_eq!(translator.leaked_stop_tokens(), ["<|eot_id|>"]);
+    }
+
+    #[test]
+    fn test_extra_on_final_delta() {
+        let mut translator = StreamTranslator::new("test-model");
+        let logprobs =
+            |token: &str| serde_json::json!({"content": [{"token": token, "logprob": -0.5}]});
+        let mut first = text_chunk("c1", "Hello", None);
+        first.system_fingerprint = Some("fp_1".to_string());
+        first.choices[0].logprobs = Some(logprobs("Hello"));
+        let mut last = text_chunk("c1", " world", Some("stop"));
+        last.choices[0].logprobs = Some(logprobs(" world"));
+        last.citations = Some(serde_json::json!(["https://example.com"]));
+
+        let mut events = translator.process_chunk(&first);
+        events.extend(translator.process_chunk(&last));
+        let extra = events
+            .iter()
+            .find_map(|event| match event {
+                StreamEvent::MessageDelta { extra, .. } => extra.clone(),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(extra["system_fingerprint"], "fp_1");
+        assert_eq!(extra["citations"][0], "https://example.com");
+        let tokens: Vec<&str> = extra["logprobs"]["content"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|t| t["token"].as_str())
+            .collect();
+        assert_eq!(tokens, ["Hello", " world"]);
+
+        // Kept when the response is rebuilt from its events
+        let mut collector = ResponseCollector::new();
+        for event in &events {
+            collector.push(event);
+        }
+        assert_eq!(collector.finish().unwrap().extra, Some(extra));
+    }
+
+    #[test]
+    fn test_cut_off() {
+        let mut translator = StreamTranslator::new("test-model");
+        translator.process_chunk(&text_chunk("c1", "Still going", None));
+        let events = translator.cut_off();
+
+        assert!(matches!(
+            &events[..],
+            [
+                StreamEvent::ContentBlockStop { index: 0 },
+                StreamEvent::MessageDelta { delta, .. },
+                StreamEvent::MessageStop,
+            ] if delta.stop_reason.as_deref() == Some("max_tokens")
+        ));
+        assert!(translator.finish().is_empty());
+    }
+
     #[test]
     fn test_finish_without_chunks() {
         let mut translator = StreamTranslator::new("test-model");
@@ -393,4 +1427,189 @@ mod tests {
         assert!(event_names.contains(&"message_delta"));
         assert!(event_names.contains(&"message_stop"));
     }
+
+    /// Block indices of the `content_block_start` events, in order.
+    fn started_blocks(events: &[StreamEvent]) -> Vec<usize> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::ContentBlockStart { index, .. } => Some(*index),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resume_after_text() {
+        let mut first = StreamTranslator::new("test-model");
+        let mut events = first.process_chunk(&text_chunk("c1", "Let me", None));
+        let (closing, state) = first.suspend();
+        assert_eq!(
+            closing
+                .iter()
+                .map(StreamEvent::event_name)
+                .collect::<Vec<_>>(),
+            ["content_block_stop"]
+        );
+        events.extend(closing);
+        assert!(state.started());
+        assert_eq!(state.next_block_index(), 1);
+        // The suspended translator is done
+        assert!(first
+            .process_chunk(&text_chunk("c1", "more", None))
+            .is_empty());
+        assert!(first.finish().is_empty());
+
+        let mut second = StreamTranslator::resume_from(state);
+        events.extend(second.process_chunk(&text_chunk("c2", "Let me check", None)));
+        events.extend(second.process_chunk(&text_chunk("c2", "", Some("stop"))));
+
+        assert!(stream_check::check(&events).is_empty());
+        assert_eq!(started_blocks(&events), [0, 1]);
+        let StreamEvent::MessageStart { ref message } = events[0] else {
+            panic!("expected message_start first");
+        };
+        assert_eq!(message.id, second.msg_id);
+    }
+
+    #[test]
+    fn test_resume_after_tool_call() {
+        let tool_chunk = ChatCompletionChunk {
+            id: "c1".to_string(),
+            object: "chat.completion.chunk".to_string(),
+            created: 0,
+            model: "test".to_string(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta: ChunkDelta {
+                    role: None,
+                    content: None,
+                    reasoning_content: None,
+                    tool_calls: Some(vec![ChunkToolCall {
+                        index: 0,
+                        id: Some("call_1".to_string()),
+                        call_type: None,
+                        function: Some(ChunkToolCallFunction {
+                            name: Some("search".to_string()),
+                            arguments: Some("{\"q\": \"ru".to_string()),
+                        }),
+                    }]),
+                },
+                finish_reason: None,
+                logprobs: None,
+            }],
+            usage: None,
+            system_fingerprint: None,
+            citations: None,
+        };
+
+        let mut first = StreamTranslator::new("test-model");
+        let mut events = first.process_chunk(&text_chunk("c1", "Searching.", None));
+        events.extend(first.process_chunk(&tool_chunk));
+        let (closing, state) = first.suspend();
+        // The cut-off arguments are completed before the block is closed
+        assert!(closing.iter().any(|e| matches!(
+            e,
+            StreamEvent::ContentBlockDelta {
+                index: 1,
+                delta: Delta::InputJsonDelta { .. }
+            }
+        )));
+        assert!(matches!(
+            closing.last(),
+            Some(StreamEvent::ContentBlockStop { index: 1 })
+        ));
+        events.extend(closing);
+        assert_eq!(first.repaired_tool_calls(), ["search"]);
+
+        let mut second = StreamTranslator::resume_from(state);
+        events.extend(second.process_chunk(&tool_chunk));
+        events.extend(second.finish());
+        assert!(stream_check::check(&events).is_empty());
+        assert_eq!(started_blocks(&events), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_resume_before_start() {
+        let mut first = StreamTranslator::new("test-model");
+        let (closing, state) = first.suspend();
+        assert!(closing.is_empty());
+        assert!(!state.started());
+
+        // Nothing reached the client, so the retry starts the message itself
+        let mut second = StreamTranslator::resume_from(state);
+        let events = second.process_chunk(&text_chunk("c2", "Hi", None));
+        assert_eq!(events[0].event_name(), "message_start");
+        assert_eq!(started_blocks(&events), [0]);
+    }
+
+    #[test]
+    fn test_response_events() {
+        let resp = MessagesResponse {
+            id: "msg_1".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content: vec![
+                ResponseContentBlock::Text {
+                    text: "Checking.".to_string(),
+                },
+                ResponseContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "get_weather".to_string(),
+                    input: serde_json::json!({"city": "Paris"}),
+                },
+            ],
+            model: "test-model".to_string(),
+            stop_reason: Some("tool_use".to_string()),
+            stop_sequence: None,
+            usage: Usage {
+                input_tokens: 10,
+                output_tokens: 5,
+                ..Usage::default()
+            },
+            extra: None,
+        };
+        let events = response_events(&resp);
+
+        let event_names: Vec<&str> = events.iter().map(StreamEvent::event_name).collect();
+        assert_eq!(
+            event_names,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert!(matches!(
+            &events[5],
+            StreamEvent::ContentBlockDelta {
+                index: 1,
+                delta: Delta::InputJsonDelta { partial_json },
+            } if partial_json == r#"{"city":"Paris"}"#
+        ));
+        assert!(matches!(
+            &events[7],
+            StreamEvent::MessageDelta { delta, usage, .. }
+                if delta.stop_reason.as_deref() == Some("tool_use") && usage.output_tokens == 5
+        ));
+
+        let mut collector = ResponseCollector::new();
+        for event in &events[..events.len() - 1] {
+            collector.push(event);
+        }
+        let mut incomplete = ResponseCollector::new();
+        incomplete.push(&events[0]);
+        assert!(incomplete.finish().is_none());
+        collector.push(&StreamEvent::MessageStop);
+        assert_eq!(
+            serde_json::to_value(collector.finish().unwrap()).unwrap(),
+            serde_json::to_value(&resp).unwrap()
+        );
+    }
 }
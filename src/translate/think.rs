@@ -0,0 +1,192 @@
+//! Inline `<think>...</think>` reasoning in model output.
+//!
+//! Many open-weight reasoning models (`DeepSeek` R1 distills, `QwQ`, Qwen3)
+//! write their chain-of-thought into `content` wrapped in `<think>` tags
+//! instead of using `reasoning_content`. [`ThinkTagParser`] splits that text
+//! into answer and reasoning segments as it streams; a tag split across
+//! deltas is held back until it can be told apart from ordinary text.
+
+use serde::{Deserialize, Serialize};
+
+const OPEN_TAG: &str = "<think>";
+const CLOSE_TAG: &str = "</think>";
+
+/// What to do with inline `<think>` blocks (`[translation] think_tags`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkTags {
+    /// Pass the text through untouched.
+    #[default]
+    Keep,
+    /// Emit the reasoning as Anthropic `thinking` blocks.
+    Thinking,
+    /// Drop the reasoning.
+    Strip,
+}
+
+/// A run of output text, by kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThinkSegment {
+    Text(String),
+    Thinking(String),
+}
+
+/// Incremental splitter for text containing `<think>` blocks.
+#[derive(Debug, Default)]
+pub struct ThinkTagParser {
+    in_think: bool,
+    /// Drop whitespace before the answer, up to the first visible character.
+    trim_start: bool,
+    pending: String,
+}
+
+impl ThinkTagParser {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next delta, returning the segments that are now settled.
+    pub fn push(&mut self, text: &str) -> Vec<ThinkSegment> {
+        self.pending.push_str(text);
+        let mut segments = Vec::new();
+        loop {
+            let tag = if self.in_think { CLOSE_TAG } else { OPEN_TAG };
+            if let Some(pos) = self.pending.find(tag) {
+                let before: String = self.pending.drain(..pos).collect();
+                self.emit(&before, &mut segments);
+                self.pending.drain(..tag.len());
+                self.in_think = !self.in_think;
+                self.trim_start = !self.in_think;
+            } else {
+                let keep = partial_tag_len(&self.pending, tag);
+                let settled: String = self.pending.drain(..self.pending.len() - keep).collect();
+                self.emit(&settled, &mut segments);
+                return segments;
+            }
+        }
+    }
+
+    /// Settle whatever is held back; an unclosed `<think>` block ends here.
+    pub fn finish(&mut self) -> Vec<ThinkSegment> {
+        let rest = std::mem::take(&mut self.pending);
+        let mut segments = Vec::new();
+        self.emit(&rest, &mut segments);
+        segments
+    }
+
+    fn emit(&mut self, text: &str, segments: &mut Vec<ThinkSegment>) {
+        let text = if self.in_think || !self.trim_start {
+            text
+        } else {
+            let trimmed = text.trim_start();
+            self.trim_start = trimmed.is_empty();
+            trimmed
+        };
+        if text.is_empty() {
+            return;
+        }
+        match (segments.last_mut(), self.in_think) {
+            (Some(ThinkSegment::Thinking(s)), true) | (Some(ThinkSegment::Text(s)), false) => {
+                s.push_str(text);
+            }
+            (_, true) => segments.push(ThinkSegment::Thinking(text.to_string())),
+            (_, false) => segments.push(ThinkSegment::Text(text.to_string())),
+        }
+    }
+}
+
+/// Split a complete response into `(reasoning, answer)`.
+#[must_use]
+pub fn split(text: &str) -> (String, String) {
+    let mut parser = ThinkTagParser::new();
+    let mut segments = parser.push(text);
+    segments.append(&mut parser.finish());
+
+    let (mut thinking, mut answer) = (String::new(), String::new());
+    for segment in segments {
+        match segment {
+            ThinkSegment::Thinking(s) => thinking.push_str(&s),
+            ThinkSegment::Text(s) => answer.push_str(&s),
+        }
+    }
+    (thinking, answer)
+}
+
+/// Length of the longest suffix of `text` that could be the start of `tag`.
+pub(crate) fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len())
+        .rev()
+        .find(|&n| text.ends_with(&tag[..n]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(deltas: &[&str]) -> Vec<ThinkSegment> {
+        let mut parser = ThinkTagParser::new();
+        let mut segments: Vec<ThinkSegment> = Vec::new();
+        for delta in deltas {
+            segments.extend(parser.push(delta));
+        }
+        segments.extend(parser.finish());
+
+        // Merge adjacent segments of the same kind, as a client would see them
+        let mut merged: Vec<ThinkSegment> = Vec::new();
+        for segment in segments {
+            match (merged.last_mut(), segment) {
+                (Some(ThinkSegment::Text(a)), ThinkSegment::Text(b))
+                | (Some(ThinkSegment::Thinking(a)), ThinkSegment::Thinking(b)) => a.push_str(&b),
+                (_, segment) => merged.push(segment),
+            }
+        }
+        merged
+    }
+
+    #[test]
+    fn test_split() {
+        let (thinking, answer) = split("<think>\nThe user wants X.\n</think>\n\nHere is X.");
+        assert_eq!(thinking, "\nThe user wants X.\n");
+        assert_eq!(answer, "Here is X.");
+
+        let (thinking, answer) = split("No reasoning here, 1 < 2.");
+        assert_eq!(thinking, "");
+        assert_eq!(answer, "No reasoning here, 1 < 2.");
+    }
+
+    #[test]
+    fn test_tags_split_across_deltas() {
+        let text = "<think>plan it</think>\n\nDone <b>now</b>";
+        let expected = vec![
+            ThinkSegment::Thinking("plan it".to_string()),
+            ThinkSegment::Text("Done <b>now</b>".to_string()),
+        ];
+        for i in 1..text.len() {
+            let (a, b) = text.split_at(i);
+            assert_eq!(collect(&[a, b]), expected, "split at {i}");
+        }
+        let chars: Vec<String> = text.chars().map(String::from).collect();
+        let chars: Vec<&str> = chars.iter().map(String::as_str).collect();
+        assert_eq!(collect(&chars), expected);
+    }
+
+    #[test]
+    fn test_holds_back_only_tag_prefixes() {
+        let mut parser = ThinkTagParser::new();
+        assert_eq!(
+            parser.push("Hello <thi"),
+            vec![ThinkSegment::Text("Hello ".to_string())]
+        );
+        assert_eq!(
+            parser.push("s is fine"),
+            vec![ThinkSegment::Text("<this is fine".to_string())]
+        );
+        assert_eq!(
+            parser.push("<think>unfinished"),
+            vec![ThinkSegment::Thinking("unfinished".to_string())]
+        );
+        assert_eq!(parser.finish(), Vec::new());
+    }
+}
@@ -0,0 +1,349 @@
+//! Validation and repair of streamed tool-call arguments.
+//!
+//! Anthropic clients concatenate a tool call's `input_json_delta` fragments and
+//! parse the result when the block stops, so a single bad fragment makes the
+//! whole call unusable. Some providers stream arguments that never become
+//! valid JSON: cut off mid-value, with a trailing comma, or sent twice over.
+//!
+//! [`ToolArgs`] scans the fragments as they arrive and only lets through text
+//! up to the last point where the object so far could be closed off cleanly
+//! (after a complete value, or an opening bracket). Whatever the upstream sends
+//! past the first syntax error, or after the object is complete, is dropped.
+//! When the call ends, the held-back rest is emitted if it completes the
+//! object; otherwise the object is closed at the last clean point.
+
+/// The result of ending a tool call's arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolArgsEnd {
+    /// Text still to emit; together with what was emitted, a JSON object.
+    pub rest: String,
+    /// Whether the upstream's arguments had to be changed.
+    pub repaired: bool,
+}
+
+/// Streaming scanner for one tool call's arguments: text passes through up to
+/// the last clean point of the JSON object, the rest is held back.
+#[derive(Debug, Default)]
+pub struct ToolArgs {
+    text: String,
+    emitted: usize,
+    scanner: Scanner,
+}
+
+impl ToolArgs {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next fragment, returning what can be emitted now.
+    pub fn push(&mut self, fragment: &str) -> String {
+        let start = self.text.len();
+        self.text.push_str(fragment);
+        for (i, c) in self.text[start..].char_indices() {
+            self.scanner.scan(c, start + i);
+        }
+        self.take_settled()
+    }
+
+    /// The arguments have ended: return the text that completes the object.
+    pub fn finish(&mut self) -> ToolArgsEnd {
+        self.scanner.finish(self.text.len());
+        if self.scanner.is_complete() {
+            return ToolArgsEnd {
+                rest: self.take_settled(),
+                repaired: false,
+            };
+        }
+        if self.scanner.safe == 0 {
+            // Nothing usable was emitted: try the whole text as a
+            // double-encoded object, else fall back to no arguments.
+            let repaired = !self.text.trim().is_empty();
+            return ToolArgsEnd {
+                rest: decode_string(&self.text).unwrap_or_else(|| "{}".to_string()),
+                repaired,
+            };
+        }
+        let mut rest = self.take_settled();
+        rest.extend(self.scanner.safe_stack.iter().rev().map(|c| match c {
+            Container::Object => '}',
+            Container::Array => ']',
+        }));
+        ToolArgsEnd {
+            rest,
+            repaired: true,
+        }
+    }
+
+    fn take_settled(&mut self) -> String {
+        let settled = self.text[self.emitted..self.scanner.safe].to_string();
+        self.emitted = self.scanner.safe;
+        settled
+    }
+}
+
+/// An object encoded as a JSON string (`"{\"q\": 1}"`), as some providers send.
+fn decode_string(text: &str) -> Option<String> {
+    let inner: String = serde_json::from_str(text.trim()).ok()?;
+    let value: serde_json::Value = serde_json::from_str(&inner).ok()?;
+    value.is_object().then(|| value.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Object,
+    Array,
+}
+
+/// What the scanner expects next, outside strings and bare tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Expect {
+    /// The opening `{` of the arguments.
+    #[default]
+    Start,
+    KeyOrEnd,
+    Key,
+    Colon,
+    ValueOrEnd,
+    Value,
+    CommaOrEnd,
+    /// The arguments object is complete.
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Backslash,
+    Hex(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InString {
+    key: bool,
+    escape: Escape,
+}
+
+/// Strict JSON syntax scanner tracking the last byte offset (`safe`) at which
+/// the text so far can be closed into a valid object by appending brackets.
+#[derive(Debug, Default)]
+struct Scanner {
+    expect: Expect,
+    stack: Vec<Container>,
+    string: Option<InString>,
+    /// A number or literal being read.
+    token: String,
+    failed: bool,
+    safe: usize,
+    safe_stack: Vec<Container>,
+}
+
+impl Scanner {
+    fn is_complete(&self) -> bool {
+        !self.failed && self.expect == Expect::Done
+    }
+
+    /// Scan `c`, found at byte offset `at`.
+    fn scan(&mut self, c: char, at: usize) {
+        if self.failed {
+            return;
+        }
+        if let Some(string) = self.string {
+            self.scan_string(string, c, at);
+            return;
+        }
+        if !self.token.is_empty() {
+            if is_token_char(c) {
+                self.token.push(c);
+                return;
+            }
+            if !self.end_token(at) {
+                return;
+            }
+        }
+        if c.is_ascii_whitespace() {
+            return;
+        }
+
+        let after = at + c.len_utf8();
+        match (self.expect, c) {
+            (Expect::Start | Expect::Value | Expect::ValueOrEnd, '{') => {
+                self.open(Container::Object, after);
+            }
+            (Expect::Value | Expect::ValueOrEnd, '[') => self.open(Container::Array, after),
+            (Expect::KeyOrEnd | Expect::CommaOrEnd, '}')
+                if self.stack.last() == Some(&Container::Object) =>
+            {
+                self.close(after);
+            }
+            (Expect::ValueOrEnd | Expect::CommaOrEnd, ']')
+                if self.stack.last() == Some(&Container::Array) =>
+            {
+                self.close(after);
+            }
+            (Expect::CommaOrEnd, ',') => {
+                self.expect = if self.stack.last() == Some(&Container::Object) {
+                    Expect::Key
+                } else {
+                    Expect::Value
+                };
+            }
+            (Expect::Colon, ':') => self.expect = Expect::Value,
+            (Expect::Key | Expect::KeyOrEnd, '"') => self.open_string(true),
+            (Expect::Value | Expect::ValueOrEnd, '"') => self.open_string(false),
+            (Expect::Value | Expect::ValueOrEnd, c) if c == '-' || c.is_ascii_alphanumeric() => {
+                self.token.push(c);
+            }
+            _ => self.failed = true,
+        }
+    }
+
+    fn scan_string(&mut self, mut string: InString, c: char, at: usize) {
+        string.escape = match string.escape {
+            Escape::None if c == '"' => {
+                self.string = None;
+                if string.key {
+                    self.expect = Expect::Colon;
+                } else {
+                    self.value_done(at + 1);
+                }
+                return;
+            }
+            Escape::None if c == '\\' => Escape::Backslash,
+            Escape::None if c < ' ' => {
+                self.failed = true;
+                return;
+            }
+            Escape::None => Escape::None,
+            Escape::Backslash if c == 'u' => Escape::Hex(4),
+            Escape::Backslash if matches!(c, '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't') => {
+                Escape::None
+            }
+            Escape::Hex(left) if c.is_ascii_hexdigit() => {
+                if left == 1 {
+                    Escape::None
+                } else {
+                    Escape::Hex(left - 1)
+                }
+            }
+            Escape::Backslash | Escape::Hex(_) => {
+                self.failed = true;
+                return;
+            }
+        };
+        self.string = Some(string);
+    }
+
+    /// The input has ended: a number or literal at the very end is complete.
+    fn finish(&mut self, at: usize) {
+        if !self.failed && !self.token.is_empty() {
+            self.end_token(at);
+        }
+    }
+
+    /// Check the number or literal ending at `at`, returning whether it's valid.
+    fn end_token(&mut self, at: usize) -> bool {
+        let token = std::mem::take(&mut self.token);
+        let valid = matches!(token.as_str(), "true" | "false" | "null")
+            || serde_json::from_str::<serde_json::Number>(&token).is_ok();
+        if valid {
+            self.value_done(at);
+        } else {
+            self.failed = true;
+        }
+        valid
+    }
+
+    fn open(&mut self, container: Container, after: usize) {
+        self.stack.push(container);
+        self.expect = match container {
+            Container::Object => Expect::KeyOrEnd,
+            Container::Array => Expect::ValueOrEnd,
+        };
+        self.mark_safe(after);
+    }
+
+    fn close(&mut self, after: usize) {
+        self.stack.pop();
+        self.value_done(after);
+    }
+
+    fn open_string(&mut self, key: bool) {
+        self.string = Some(InString {
+            key,
+            escape: Escape::None,
+        });
+    }
+
+    fn value_done(&mut self, after: usize) {
+        self.expect = if self.stack.is_empty() {
+            Expect::Done
+        } else {
+            Expect::CommaOrEnd
+        };
+        self.mark_safe(after);
+    }
+
+    fn mark_safe(&mut self, at: usize) {
+        self.safe = at;
+        self.safe_stack.clone_from(&self.stack);
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stream `fragments` through a [`ToolArgs`], returning everything emitted
+    /// and whether it was repaired.
+    fn stream(fragments: &[&str]) -> (String, bool) {
+        let mut args = ToolArgs::new();
+        let mut out: String = fragments.iter().map(|f| args.push(f)).collect();
+        let end = args.finish();
+        out.push_str(&end.rest);
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&out)
+            .unwrap_or_else(|e| panic!("{out:?} is not an object: {e}"));
+        (out, end.repaired)
+    }
+
+    #[test]
+    fn test_valid_arguments_pass_through() {
+        let json = r#"{"path": "a\"bé.rs", "n": -1.5e3, "flags": [true, null, {}], "x": 0}"#;
+        for split in (0..=json.len()).filter(|&i| json.is_char_boundary(i)) {
+            let (out, repaired) = stream(&[&json[..split], &json[split..]]);
+            assert_eq!(out, json);
+            assert!(!repaired);
+        }
+
+        let mut args = ToolArgs::new();
+        assert_eq!(args.push(r#"{"city": "Par"#), "{");
+        assert_eq!(args.push(r#"is", "days""#), r#""city": "Paris""#);
+        assert_eq!(args.push(": 3}"), r#", "days": 3}"#);
+        assert_eq!(args.finish().rest, "");
+    }
+
+    #[test]
+    fn test_repairs() {
+        let cases = [
+            (
+                vec![r#"{"city": "Paris", "days": "#],
+                r#"{"city": "Paris"}"#,
+            ),
+            (vec![r#"{"a": [1, 2"#], r#"{"a": [1, 2]}"#),
+            (vec![r#"{"a": [1, tr"#], r#"{"a": [1]}"#),
+            (vec![r#"{"a": 1,}"#], r#"{"a": 1}"#),
+            (vec![r#"{"a": 1}"#, r#"{"a": 1}"#], r#"{"a": 1}"#),
+            (vec![r#""{\"a\": 1}""#], r#"{"a":1}"#),
+            (vec!["{\"a\": \"tab\tin string\"}"], "{}"),
+            (vec!["not json"], "{}"),
+        ];
+        for (fragments, expected) in cases {
+            assert_eq!(stream(&fragments), (expected.to_string(), true));
+        }
+        assert_eq!(stream(&[]), ("{}".to_string(), false));
+    }
+}
@@ -0,0 +1,240 @@
+//! Response expectations and re-prompting.
+//!
+//! A `[models]` table can list what every response for that model must satisfy
+//! (`expect = ["valid_json"]`). A response that falls short is not returned:
+//! the proxy tells the model what was wrong and asks again, up to
+//! `expect_retries` times, then returns the last response as is. Checking
+//! needs the whole response, so streamed requests for such a model are sent
+//! upstream non-streaming and replayed to the client as a stream.
+
+use serde::{Deserialize, Serialize};
+
+use crate::translate::anthropic_types::{
+    ContentBlock, Message, MessageContent, MessagesRequest, MessagesResponse, ResponseContentBlock,
+    Role,
+};
+
+/// Something every response must satisfy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expectation {
+    /// Some text or a tool call.
+    NonEmpty,
+    /// The text parses as JSON (a surrounding Markdown code fence is allowed).
+    /// Not checked when the model called a tool instead.
+    ValidJson,
+    /// At least one tool call. Only checked when the request offers tools.
+    ToolCall,
+}
+
+impl Expectation {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NonEmpty => "non_empty",
+            Self::ValidJson => "valid_json",
+            Self::ToolCall => "tool_call",
+        }
+    }
+}
+
+/// A response that failed an expectation, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub expectation: Expectation,
+    pub reason: String,
+}
+
+/// Check `resp` against each expectation in turn, returning the first failure.
+///
+/// # Errors
+/// Returns the [`Violation`] of the first expectation the response fails.
+pub fn check(
+    expectations: &[Expectation],
+    req: &MessagesRequest,
+    resp: &MessagesResponse,
+) -> Result<(), Violation> {
+    let text = response_text(resp);
+    let has_tool_call = resp
+        .content
+        .iter()
+        .any(|b| matches!(b, ResponseContentBlock::ToolUse { .. }));
+    let offers_tools = req
+        .tools
+        .as_ref()
+        .is_some_and(|tools| tools.iter().any(|t| !t.is_server_tool()));
+
+    for &expectation in expectations {
+        let reason = match expectation {
+            Expectation::NonEmpty if text.trim().is_empty() && !has_tool_call => {
+                Some("the response was empty".to_string())
+            }
+            Expectation::ValidJson if !has_tool_call => {
+                serde_json::from_str::<serde_json::Value>(strip_code_fence(&text))
+                    .err()
+                    .map(|e| format!("the response was not valid JSON ({e})"))
+            }
+            Expectation::ToolCall if offers_tools && !has_tool_call => {
+                Some("the response did not call a tool".to_string())
+            }
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            return Err(Violation {
+                expectation,
+                reason,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The request to send next: `req` followed by the rejected answer's text and
+/// a user turn explaining what was wrong with it.
+#[must_use]
+pub fn reprompt(
+    req: &MessagesRequest,
+    resp: &MessagesResponse,
+    violation: &Violation,
+) -> MessagesRequest {
+    let mut next = req.clone();
+    let text = response_text(resp);
+    if !text.trim().is_empty() {
+        next.messages.push(Message {
+            role: Role::Assistant,
+            content: MessageContent::Text(text),
+        });
+    }
+    let instruction = match violation.expectation {
+        Expectation::NonEmpty => "Please answer again.",
+        Expectation::ValidJson => "Reply again with only valid JSON and no other text.",
+        Expectation::ToolCall => "Respond again by calling one of the available tools.",
+    };
+    next.messages.push(Message {
+        role: Role::User,
+        content: MessageContent::Blocks(vec![ContentBlock::Text {
+            text: format!(
+                "Your previous response was rejected: {}. {instruction}",
+                violation.reason
+            ),
+            cache_control: None,
+        }]),
+    });
+    next
+}
+
+fn response_text(resp: &MessagesResponse) -> String {
+    resp.content
+        .iter()
+        .filter_map(|b| match b {
+            ResponseContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The body of a Markdown code fence around the whole text, else the text.
+fn strip_code_fence(text: &str) -> &str {
+    let trimmed = text.trim();
+    trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map_or(trimmed, |body| {
+            // Drop the info string (```json)
+            body.split_once('\n').map_or(body, |(_, code)| code)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::anthropic_types::Usage;
+
+    fn request(tools: bool) -> MessagesRequest {
+        let mut body = serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "List three colors as JSON"}]
+        });
+        if tools {
+            body["tools"] = serde_json::json!([{
+                "name": "get_weather",
+                "input_schema": {"type": "object"}
+            }]);
+        }
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn response(content: Vec<ResponseContentBlock>) -> MessagesResponse {
+        MessagesResponse {
+            id: "msg_1".to_string(),
+            response_type: "message".to_string(),
+            role: "assistant".to_string(),
+            content,
+            model: "claude-sonnet-4-20250514".to_string(),
+            stop_reason: Some("end_turn".to_string()),
+            stop_sequence: None,
+            usage: Usage::default(),
+            extra: None,
+        }
+    }
+
+    fn text(text: &str) -> MessagesResponse {
+        response(vec![ResponseContentBlock::Text {
+            text: text.to_string(),
+        }])
+    }
+
+    #[test]
+    fn test_checks() {
+        let all = [
+            Expectation::NonEmpty,
+            Expectation::ValidJson,
+            Expectation::ToolCall,
+        ];
+        let req = request(false);
+        assert!(check(&all, &req, &text("[\"red\", \"green\", \"blue\"]")).is_ok());
+        assert!(check(&all, &req, &text("```json\n{\"a\": 1}\n```")).is_ok());
+        assert_eq!(
+            check(&all, &req, &text("  ")).unwrap_err().expectation,
+            Expectation::NonEmpty
+        );
+        assert_eq!(
+            check(&all, &req, &text("Sure! Red, green, blue."))
+                .unwrap_err()
+                .expectation,
+            Expectation::ValidJson
+        );
+
+        let req = request(true);
+        assert_eq!(
+            check(&all, &req, &text("{}")).unwrap_err().expectation,
+            Expectation::ToolCall
+        );
+        let call = response(vec![ResponseContentBlock::ToolUse {
+            id: "toolu_1".to_string(),
+            name: "get_weather".to_string(),
+            input: serde_json::json!({}),
+        }]);
+        assert!(check(&all, &req, &call).is_ok());
+    }
+
+    #[test]
+    fn test_reprompt() {
+        let req = request(false);
+        let resp = text("Sure! Red, green, blue.");
+        let violation = check(&[Expectation::ValidJson], &req, &resp).unwrap_err();
+        let next = reprompt(&req, &resp, &violation);
+
+        assert_eq!(next.messages.len(), 3);
+        assert_eq!(next.messages[1].role, Role::Assistant);
+        let MessageContent::Blocks(ref blocks) = next.messages[2].content else {
+            panic!("expected blocks");
+        };
+        let ContentBlock::Text { ref text, .. } = blocks[0] else {
+            panic!("expected text");
+        };
+        assert!(text
+            .starts_with("Your previous response was rejected: the response was not valid JSON"));
+    }
+}
@@ -1,35 +1,27 @@
-use claude_proxy::config::{ParamsConfig, ProviderConfig, ProxyConfig};
+use claude_proxy::config::{PortConflict, ProviderConfig, ProxyConfig};
 use claude_proxy::logging::SharedLogger;
 use claude_proxy::proxy;
 use claude_proxy::translate::anthropic_types::*;
+use claude_proxy::AppState;
 use futures::StreamExt;
 use std::collections::HashMap;
 
 fn fireworks_config() -> ProxyConfig {
-    let mut models = HashMap::new();
-    models.insert(
-        "claude-sonnet-4-20250514".to_string(),
-        "accounts/fireworks/models/kimi-k2p5".to_string(),
-    );
-    models.insert(
-        "test-model".to_string(),
-        "accounts/fireworks/models/kimi-k2p5".to_string(),
-    );
-
-    ProxyConfig {
-        port: 0,
-        provider: ProviderConfig {
-            name: "fireworks".to_string(),
-            base_url: Some("https://api.fireworks.ai/inference/v1".to_string()),
-            api_key: None,
-            api_key_env: "FIREWORKS_API_KEY".to_string(),
-            format: Some("openai".to_string()),
-        },
-        models,
-        params: ParamsConfig {
-            drop: vec!["betas".to_string(), "context_management".to_string()],
-        },
-    }
+    ProxyConfig::builder()
+        .port(0)
+        .provider("fireworks")
+        .base_url("https://api.fireworks.ai/inference/v1")
+        .api_key_env("FIREWORKS_API_KEY")
+        .format("openai")
+        .map_model(
+            "claude-sonnet-4-20250514",
+            "accounts/fireworks/models/kimi-k2p5",
+        )
+        .map_model("test-model", "accounts/fireworks/models/kimi-k2p5")
+        .drop_param("betas")
+        .drop_param("context_management")
+        .build()
+        .unwrap()
 }
 
 fn simple_request(model: &str, prompt: &str) -> MessagesRequest {
@@ -93,6 +85,8 @@ fn tool_request() -> MessagesRequest {
                 },
                 "required": ["city"]
             }),
+            tool_type: None,
+            extra: HashMap::default(),
         }]),
         tool_choice: Some(ToolChoice::Auto(ToolChoiceAuto {
             choice_type: "auto".to_string(),
@@ -145,12 +139,16 @@ fn test_response_translation() {
                 tool_calls: None,
             },
             finish_reason: Some("stop".to_string()),
+            logprobs: None,
         }],
         usage: Some(ChatUsage {
             prompt_tokens: 5,
             completion_tokens: 3,
             total_tokens: 8,
+            ..ChatUsage::default()
         }),
+        system_fingerprint: None,
+        citations: None,
     };
 
     let result = openai_to_anthropic(&openai_resp, "claude-sonnet-4-20250514").unwrap();
@@ -184,8 +182,11 @@ fn test_stream_translator_basic() {
                 tool_calls: None,
             },
             finish_reason: None,
+            logprobs: None,
         }],
         usage: None,
+        system_fingerprint: None,
+        citations: None,
     };
 
     let events = translator.process_chunk(&chunk);
@@ -205,111 +206,4478 @@ fn test_stream_translator_basic() {
     assert!(has_stop);
 }
 
-// ────────────────────────────────────────────────────────────────
-// Integration tests (need FIREWORKS_API_KEY)
-// ────────────────────────────────────────────────────────────────
+#[tokio::test]
+async fn test_passthrough_streams_incrementally() {
+    use axum::body::Body;
+    use axum::routing::post;
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    // Mock Anthropic upstream: sends message_start, then holds the stream open
+    // until the test has seen it arrive through the proxy.
+    let release = Arc::new(Notify::new());
+    let upstream_release = release.clone();
+    let upstream = axum::Router::new().route(
+        "/v1/messages",
+        post(move || {
+            let release = upstream_release.clone();
+            async move {
+                let body = async_stream::stream! {
+                    yield Ok::<_, std::io::Error>(
+                        "event: message_start\ndata: {\"type\":\"message_start\"}\n\n",
+                    );
+                    release.notified().await;
+                    yield Ok("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n");
+                };
+                axum::response::Response::builder()
+                    .header("content-type", "text/event-stream")
+                    .header("request-id", "req_011CUpstream")
+                    .body(Body::from_stream(body))
+                    .unwrap()
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider = ProviderConfig {
+        name: "anthropic".to_string(),
+        base_url: Some(format!("http://{upstream_addr}")),
+        api_key: Some("test-key".to_string()),
+        format: Some("anthropic".to_string()),
+        ..config.provider
+    };
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-passthrough.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/messages"))
+        .json(&serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 10,
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hi"}],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    // Anthropic's request id reaches the client
+    assert_eq!(resp.headers()["request-id"], "req_011CUpstream");
+
+    let mut body = resp.bytes_stream();
+    let first = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+        .await
+        .expect("first event was buffered until the stream ended")
+        .unwrap()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&first).contains("message_start"));
+
+    release.notify_one();
+    let mut remaining = Vec::new();
+    while let Some(chunk) = body.next().await {
+        remaining.extend_from_slice(&chunk.unwrap());
+    }
+    assert!(String::from_utf8_lossy(&remaining).contains("message_stop"));
+}
 
 #[tokio::test]
-#[ignore = "requires FIREWORKS_API_KEY"]
-async fn test_non_streaming_fireworks() {
-    let config = fireworks_config();
+async fn test_passthrough_usage_is_costed() {
+    use axum::routing::post;
+    use claude_proxy::config::ModelPrice;
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/v1/messages",
+        post(|| async {
+            let body = concat!(
+                "event: message_start\n",
+                "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":1000,\"output_tokens\":1}}}\n\n",
+                "event: message_delta\n",
+                "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":500}}\n\n",
+                "event: message_stop\n",
+                "data: {\"type\":\"message_stop\"}\n\n",
+            );
+            ([("content-type", "text/event-stream")], body)
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider = ProviderConfig {
+        name: "anthropic".to_string(),
+        base_url: Some(format!("http://{upstream_addr}")),
+        api_key: Some("test-key".to_string()),
+        format: Some("anthropic".to_string()),
+        ..config.provider
+    };
+    config.costs.prices.insert(
+        "anthropic:accounts/fireworks/models/kimi-k2p5".to_string(),
+        ModelPrice {
+            input: 3.0,
+            output: 15.0,
+        },
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
     let client = reqwest::Client::new();
-    let logger = SharedLogger::new("/tmp/claude-proxy-test.log").unwrap();
-    let req = simple_request("test-model", "Say 'hello' and nothing else.");
 
-    let result = proxy::proxy_non_streaming(&req, &config, &client, &logger).await;
+    let resp = client
+        .post(format!("http://{addr}/v1/messages"))
+        .header("x-claude-code-session-id", "sess-1")
+        .json(&serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 10,
+            "stream": true,
+            "messages": [{"role": "user", "content": "Hi"}],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp.text().await.unwrap().contains("message_stop"));
 
-    match result {
-        Ok(proxy::ProxyResult::Success(resp)) => {
-            assert_eq!(resp.response_type, "message");
-            assert_eq!(resp.role, "assistant");
-            assert!(!resp.content.is_empty());
-            println!("Response: {:?}", resp.content);
-            println!(
-                "Usage: in={} out={}",
-                resp.usage.input_tokens, resp.usage.output_tokens
-            );
-        }
-        Ok(proxy::ProxyResult::Error(err, status)) => {
-            panic!("Provider error ({status}): {err:?}");
-        }
-        Err(e) => {
-            panic!("Proxy error: {e}");
-        }
-    }
+    let report: serde_json::Value = client
+        .get(format!("http://{addr}/stats?since=1h&session=sess-1"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let total = &report["total"];
+    assert_eq!(total["requests"], 1);
+    assert_eq!(total["input_tokens"], 1000);
+    assert_eq!(total["output_tokens"], 500);
+    // 1000 × $3/M + 500 × $15/M
+    assert!((total["cost"].as_f64().unwrap() - 0.0105).abs() < 1e-9);
+    assert_eq!(report["by_session"]["sess-1"]["requests"], 1);
+
+    let resp = client
+        .get(format!("http://{addr}/stats?since=yesterday"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
 }
 
 #[tokio::test]
-#[ignore = "requires FIREWORKS_API_KEY"]
-async fn test_streaming_fireworks() {
-    let config = fireworks_config();
+async fn test_gemini_endpoint_roundtrip() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // Mock OpenAI-format upstream that answers every request with a tool call
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            axum::Json(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "mock",
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                        }]
+                    },
+                    "finish_reason": "tool_calls"
+                }],
+                "usage": {"prompt_tokens": 12, "completion_tokens": 8, "total_tokens": 20}
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-gemini.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+
+    let body: serde_json::Value = reqwest::Client::new()
+        .post(format!(
+            "http://{addr}/v1beta/models/gemini-2.0-flash:generateContent"
+        ))
+        .json(&serde_json::json!({
+            "contents": [{"role": "user", "parts": [{"text": "Weather in Paris?"}]}],
+            "tools": [{"functionDeclarations": [{
+                "name": "get_weather",
+                "parameters": {"type": "OBJECT", "properties": {"city": {"type": "STRING"}}}
+            }]}]
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let part = &body["candidates"][0]["content"]["parts"][0];
+    assert_eq!(part["functionCall"]["name"], "get_weather");
+    assert_eq!(part["functionCall"]["args"]["city"], "Paris");
+    assert_eq!(body["usageMetadata"]["totalTokenCount"], 20);
+}
+
+#[tokio::test]
+async fn test_completions_endpoint() {
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // Mock OpenAI-format upstream answering "3, 4", streamed or not
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert_eq!(body["messages"][1]["content"], "1, 2,");
+            if body["stream"] == true {
+                let sse = concat!(
+                    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+                    "\"choices\":[{\"index\":0,\"delta\":{\"content\":\" 3,\"},\"finish_reason\":null}]}\n\n",
+                    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+                    "\"choices\":[{\"index\":0,\"delta\":{\"content\":\" 4\"},\"finish_reason\":\"length\"}]}\n\n",
+                    "data: [DONE]\n\n",
+                );
+                return ([("content-type", "text/event-stream")], sse).into_response();
+            }
+            axum::Json(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "mock",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": " 3, 4"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 30, "completion_tokens": 4, "total_tokens": 34}
+            }))
+            .into_response()
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-completions.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
     let client = reqwest::Client::new();
-    let logger = SharedLogger::new("/tmp/claude-proxy-test-stream.log").unwrap();
-    let req = streaming_request("test-model", "Count from 1 to 5.");
 
-    let stream = proxy::proxy_streaming(&req, &config, &client, &logger)
+    let body: serde_json::Value = client
+        .post(format!("http://{addr}/v1/completions"))
+        .json(&serde_json::json!({
+            "model": "claude-haiku-4-5",
+            "prompt": "1, 2,",
+            "echo": true
+        }))
+        .send()
         .await
-        .expect("Failed to start stream");
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["object"], "text_completion");
+    assert_eq!(body["choices"][0]["text"], "1, 2, 3, 4");
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    assert_eq!(body["usage"]["total_tokens"], 34);
 
-    let events: Vec<_> = stream
-        .collect::<Vec<_>>()
+    let sse = client
+        .post(format!("http://{addr}/v1/completions"))
+        .json(&serde_json::json!({
+            "model": "claude-haiku-4-5",
+            "prompt": ["1, 2,"],
+            "max_tokens": 4,
+            "stream": true
+        }))
+        .send()
         .await
-        .into_iter()
-        .filter_map(std::result::Result::ok)
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let data: Vec<&str> = sse
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(data.last(), Some(&"[DONE]"));
+    let chunks: Vec<serde_json::Value> = data[..data.len() - 1]
+        .iter()
+        .map(|d| serde_json::from_str(d).unwrap())
+        .collect();
+    let text: String = chunks
+        .iter()
+        .filter_map(|c| c["choices"][0]["text"].as_str())
         .collect();
+    assert_eq!(text, " 3, 4");
+    assert_eq!(
+        chunks.last().unwrap()["choices"][0]["finish_reason"],
+        "length"
+    );
 
-    assert!(!events.is_empty(), "Stream produced no events");
+    let resp = client
+        .post(format!("http://{addr}/v1/completions"))
+        .json(&serde_json::json!({"model": "claude-haiku-4-5", "prompt": ["a", "b"]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+}
 
-    let event_names: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
-    println!("Stream events: {event_names:?}");
+#[tokio::test]
+async fn test_chat_completions_endpoint() {
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use std::sync::Arc;
 
-    assert!(
-        event_names.contains(&"message_start"),
-        "Missing message_start"
+    // Mock Anthropic upstream calling a tool, streamed or not
+    let upstream = axum::Router::new().route(
+        "/v1/messages",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert_eq!(body["system"], "Be brief.");
+            assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+            if body["stream"] == true {
+                let sse = concat!(
+                    "event: message_start\n",
+                    "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-sonnet-4-5\",\"stop_reason\":null,\"stop_sequence\":null,\"usage\":{\"input_tokens\":20,\"output_tokens\":1}}}\n\n",
+                    "event: content_block_start\n",
+                    "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"weather\",\"input\":{}}}\n\n",
+                    "event: content_block_delta\n",
+                    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"city\\\": \\\"Oslo\\\"}\"}}\n\n",
+                    "event: content_block_stop\n",
+                    "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+                    "event: message_delta\n",
+                    "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":9}}\n\n",
+                    "event: message_stop\n",
+                    "data: {\"type\":\"message_stop\"}\n\n",
+                );
+                return ([("content-type", "text/event-stream")], sse).into_response();
+            }
+            axum::Json(serde_json::json!({
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Oslo"}}],
+                "model": "claude-sonnet-4-5",
+                "stop_reason": "tool_use",
+                "stop_sequence": null,
+                "usage": {"input_tokens": 20, "output_tokens": 9}
+            }))
+            .into_response()
+        }),
     );
-    assert!(
-        event_names.contains(&"message_stop"),
-        "Missing message_stop"
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider = ProviderConfig {
+        name: "anthropic".to_string(),
+        base_url: Some(format!("http://{upstream_addr}")),
+        api_key: Some("test-key".to_string()),
+        format: Some("anthropic".to_string()),
+        ..config.provider
+    };
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-chat-completions.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let client = reqwest::Client::new();
+    let request = serde_json::json!({
+        "model": "claude-sonnet-4-5",
+        "messages": [
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": "Weather in Oslo?"}
+        ],
+        "tools": [{"type": "function", "function": {
+            "name": "weather",
+            "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+        }}]
+    });
+
+    let body: serde_json::Value = client
+        .post(format!("http://{addr}/v1/chat/completions"))
+        .json(&request)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["object"], "chat.completion");
+    let choice = &body["choices"][0];
+    assert_eq!(choice["finish_reason"], "tool_calls");
+    assert_eq!(choice["message"]["tool_calls"][0]["id"], "toolu_1");
+    assert_eq!(
+        choice["message"]["tool_calls"][0]["function"]["arguments"],
+        r#"{"city":"Oslo"}"#
     );
-    assert!(
-        event_names.contains(&"content_block_delta"),
-        "Missing content deltas"
+    assert_eq!(body["usage"]["total_tokens"], 29);
+
+    let mut streaming = request;
+    streaming["stream"] = true.into();
+    streaming["stream_options"] = serde_json::json!({"include_usage": true});
+    let sse = client
+        .post(format!("http://{addr}/v1/chat/completions"))
+        .json(&streaming)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let data: Vec<&str> = sse
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(data.last(), Some(&"[DONE]"));
+    let chunks: Vec<serde_json::Value> = data[..data.len() - 1]
+        .iter()
+        .map(|d| serde_json::from_str(d).unwrap())
+        .collect();
+    assert!(chunks
+        .iter()
+        .all(|c| c["object"] == "chat.completion.chunk"));
+    let arguments: String = chunks
+        .iter()
+        .filter_map(|c| c["choices"][0]["delta"]["tool_calls"][0]["function"]["arguments"].as_str())
+        .collect();
+    assert_eq!(arguments, r#"{"city": "Oslo"}"#);
+    assert!(chunks
+        .iter()
+        .any(|c| c["choices"][0]["finish_reason"] == "tool_calls"));
+    assert_eq!(chunks.last().unwrap()["usage"]["total_tokens"], 29);
+
+    // A bad request is answered in OpenAI's error format
+    let resp = client
+        .post(format!("http://{addr}/v1/chat/completions"))
+        .json(&serde_json::json!({"model": "claude-sonnet-4-5", "messages": "Hi"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let error: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(error["error"]["type"], "invalid_request_error");
+}
+
+#[tokio::test]
+async fn test_record_then_replay_stream() {
+    use axum::routing::post;
+
+    let sse = concat!(
+        "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+        "\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Bonjour\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+        "\"choices\":[{\"index\":0,\"delta\":{\"content\":\" à tous\"},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: [DONE]\n\n",
+    );
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(move || async move { ([("content-type", "text/event-stream")], sse) }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let dir = std::env::temp_dir().join(format!("claude-proxy-rec-{}", std::process::id()));
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-record.log").unwrap();
+    let mut req = simple_request("test-model", "Greet everyone in French.");
+    req.stream = Some(true);
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.record.dir = Some(dir.to_string_lossy().into_owned());
+    let state = AppState::new(config, reqwest::Client::new(), logger.clone());
+    let recorded = streamed_text(proxy::proxy_streaming(&req, &state).await.unwrap()).await;
+    assert_eq!(recorded, "Bonjour à tous");
+
+    // Replay from the recordings, with no upstream involved
+    let mut config = fireworks_config();
+    config.provider = ProviderConfig {
+        name: "replay".to_string(),
+        base_url: Some(dir.to_string_lossy().into_owned()),
+        api_key_env: String::new(),
+        ..ProviderConfig::default()
+    };
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+    let replayed = streamed_text(proxy::proxy_streaming(&req, &state).await.unwrap()).await;
+    assert_eq!(replayed, recorded);
+
+    // A request that was never recorded has nothing to replay
+    let other = simple_request("test-model", "Something else");
+    assert!(proxy::proxy_non_streaming(&other, &state).await.is_err());
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[tokio::test]
+async fn test_capture_stream() {
+    use axum::routing::post;
+
+    let sse = concat!(
+        "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+        "\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: [DONE]\n\n",
+    );
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(move || async move { ([("content-type", "text/event-stream")], sse) }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.capture.dir = Some(dir.path().to_string_lossy().into_owned());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-capture.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hi");
+
+    let day = std::fs::read_dir(dir.path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let file = std::fs::read_dir(day.path())
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let capture: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(file.path()).unwrap()).unwrap();
+    assert_eq!(capture["provider"], "fireworks");
+    assert_eq!(capture["anthropic_request"]["model"], "test-model");
+    assert_eq!(
+        capture["openai_request"]["model"],
+        "accounts/fireworks/models/kimi-k2p5"
     );
+    assert_eq!(
+        capture["openai_response"][0]["choices"][0]["delta"]["content"],
+        "Hi"
+    );
+    let events = capture["anthropic_response"].as_array().unwrap();
+    assert_eq!(events.first().unwrap()["type"], "message_start");
+    assert_eq!(events.last().unwrap()["type"], "message_stop");
 }
 
 #[tokio::test]
-#[ignore = "requires FIREWORKS_API_KEY"]
-async fn test_tool_use_fireworks() {
-    let config = fireworks_config();
+async fn test_admin_dashboard() {
+    use std::sync::Arc;
+
+    let mut config = fireworks_config();
+    config.auth.keys = vec!["sk-proxy-admin".to_string()];
+    config.provider.api_key = Some("fw-secret".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-admin.log").unwrap();
+    let state = Arc::new(AppState::new(
+        config,
+        reqwest::Client::new(),
+        logger.clone(),
+    ));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
     let client = reqwest::Client::new();
-    let logger = SharedLogger::new("/tmp/claude-proxy-test-tools.log").unwrap();
-    let req = tool_request();
 
-    let result = proxy::proxy_non_streaming(&req, &config, &client, &logger).await;
+    // The page itself holds no data and needs no key
+    let resp = client
+        .get(format!("http://{addr}/admin"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("<title>claude-proxy</title>"));
 
-    match result {
-        Ok(proxy::ProxyResult::Success(resp)) => {
-            println!("Tool response: {:?}", resp.content);
+    let resp = client
+        .get(format!("http://{addr}/admin/summary"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
 
-            let has_tool_use = resp
-                .content
-                .iter()
-                .any(|b| matches!(b, ResponseContentBlock::ToolUse { .. }));
+    let get = |path: &'static str| {
+        let client = client.clone();
+        async move {
+            let resp = client
+                .get(format!("http://{addr}{path}"))
+                .header("x-api-key", "sk-proxy-admin")
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), 200, "{path}");
+            resp.json::<serde_json::Value>().await.unwrap()
+        }
+    };
+    let summary = get("/admin/summary").await;
+    assert_eq!(summary["provider"], "fireworks");
+    assert_eq!(summary["models"][0]["name"], "claude-sonnet-4-20250514");
 
-            // The model may or may not call the tool - just verify we got a valid response
-            assert_eq!(resp.response_type, "message");
-            if has_tool_use {
-                println!("Model correctly used the tool");
-                assert_eq!(resp.stop_reason, Some("tool_use".to_string()));
-            } else {
-                println!("Model responded with text (didn't use tool)");
-            }
+    let config = get("/admin/config").await;
+    assert_eq!(config["provider"]["api_key"], "<redacted>");
+    assert_eq!(config["auth"]["keys"][0], "<redacted>");
+
+    logger.info("test", "Dashboard check");
+    let logs = get("/admin/logs?limit=5").await;
+    assert!(logs["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|e| e["message"] == "Dashboard check"));
+
+    let compacted: serde_json::Value = client
+        .post(format!("http://{addr}/admin/logs/compact"))
+        .header("x-api-key", "sk-proxy-admin")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(compacted["bytes_after"].as_u64().unwrap() > 0);
+    assert!(logger.since_compacted() < std::time::Duration::from_secs(5));
+
+    // Live updates: a summary straight away, then new log entries as they come
+    let mut events = client
+        .get(format!("http://{addr}/admin/events"))
+        .header("x-api-key", "sk-proxy-admin")
+        .send()
+        .await
+        .unwrap()
+        .bytes_stream();
+    let mut received = String::new();
+    while !received.contains("event: summary") {
+        received.push_str(&String::from_utf8_lossy(
+            &events.next().await.unwrap().unwrap(),
+        ));
+    }
+    logger.warn("test", "Live entry");
+    while !received.contains("Live entry") {
+        received.push_str(&String::from_utf8_lossy(
+            &events.next().await.unwrap().unwrap(),
+        ));
+    }
+    assert!(received.contains("event: log"));
+
+    // Other sites' pages may call the API, but not read the admin API
+    let cors = |path: &'static str| {
+        let client = client.clone();
+        async move {
+            client
+                .get(format!("http://{addr}{path}"))
+                .header("origin", "https://example.com")
+                .header("x-api-key", "sk-proxy-admin")
+                .send()
+                .await
+                .unwrap()
+                .headers()
+                .contains_key("access-control-allow-origin")
         }
-        Ok(proxy::ProxyResult::Error(err, status)) => {
-            panic!("Provider error ({status}): {err:?}");
+    };
+    assert!(cors("/v1/models").await);
+    assert!(!cors("/admin/summary").await);
+}
+
+#[tokio::test]
+async fn test_admin_api_without_auth() {
+    use std::sync::Arc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = Arc::new(AppState::new(
+        fireworks_config(),
+        reqwest::Client::new(),
+        logger,
+    ));
+    let client = reqwest::Client::new();
+
+    // Served to a client on the same machine
+    let addr = spawn_server(claude_proxy::build_router(state.clone())).await;
+    let resp = client
+        .get(format!("http://{addr}/admin/summary"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    // Refused to one whose address isn't known to be local
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = claude_proxy::build_router(state);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let resp = client
+        .get(format!("http://{addr}/admin/config"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 403);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "permission_error");
+    let resp = client
+        .get(format!("http://{addr}/admin"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn test_turn_deadline_cuts_off_stream() {
+    use axum::routing::post;
+
+    // Mock upstream that sends a little text, then stalls
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            let body = async_stream::stream! {
+                yield Ok::<_, std::io::Error>(concat!(
+                    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+                    "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Thinking about\"},\"finish_reason\":null}]}\n\n",
+                ));
+                futures::future::pending::<()>().await;
+            };
+            (
+                [("content-type", "text/event-stream")],
+                axum::body::Body::from_stream(body),
+            )
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.streaming.turn_deadline_secs = 1;
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-deadline.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let mut stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let mut text = String::new();
+    let mut stop_reason = None;
+    while let Some(event) = stream.next().await {
+        let data: serde_json::Value = serde_json::from_str(&event.unwrap().data).unwrap();
+        if let Some(t) = data["delta"]["text"].as_str() {
+            text.push_str(t);
         }
-        Err(e) => {
-            panic!("Proxy error: {e}");
+        if let Some(reason) = data["delta"]["stop_reason"].as_str() {
+            stop_reason = Some(reason.to_string());
+        }
+    }
+    assert_eq!(text, "Thinking about");
+    assert_eq!(stop_reason.as_deref(), Some("max_tokens"));
+}
+
+#[tokio::test]
+async fn test_stream_smoothing() {
+    use axum::routing::post;
+
+    // Mock upstream sending a whole paragraph in one chunk
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            (
+                [("content-type", "text/event-stream")],
+                concat!(
+                    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+                    "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"One long paragraph, all at once.\"},\"finish_reason\":\"stop\"}]}\n\n",
+                    "data: [DONE]\n\n",
+                ),
+            )
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.streaming.smooth_max_chars = 8;
+    config.streaming.smooth_delay_ms = 1;
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-smoothing.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let mut stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let mut deltas = Vec::new();
+    while let Some(event) = stream.next().await {
+        let data: serde_json::Value = serde_json::from_str(&event.unwrap().data).unwrap();
+        if let Some(t) = data["delta"]["text"].as_str() {
+            deltas.push(t.to_string());
+        }
+    }
+    assert_eq!(deltas.concat(), "One long paragraph, all at once.");
+    assert!(deltas.iter().all(|d| d.chars().count() <= 8), "{deltas:?}");
+    assert_eq!(deltas.len(), 5);
+}
+
+#[tokio::test]
+async fn test_stream_coalescing() {
+    use axum::routing::post;
+
+    // Mock upstream sending a token per chunk, with a pause mid-stream
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            let frame = |content: &str| {
+                Ok::<_, std::io::Error>(format!(
+                    "data: {{\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",\
+                     \"choices\":[{{\"index\":0,\"delta\":{{\"content\":\"{content}\"}},\"finish_reason\":null}}]}}\n\n"
+                ))
+            };
+            let body = async_stream::stream! {
+                for token in ["One", " token", " at", " a", " time."] {
+                    yield frame(token);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                for token in [" Then", " more."] {
+                    yield frame(token);
+                }
+                yield Ok("data: [DONE]\n\n".to_string());
+            };
+            (
+                [("content-type", "text/event-stream")],
+                axum::body::Body::from_stream(body),
+            )
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.streaming.coalesce_ms = 100;
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-coalescing.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let mut stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let mut deltas = Vec::new();
+    while let Some(event) = stream.next().await {
+        let data: serde_json::Value = serde_json::from_str(&event.unwrap().data).unwrap();
+        if let Some(t) = data["delta"]["text"].as_str() {
+            deltas.push(t.to_string());
+        }
+    }
+    // The first run is sent when the window passes, not held for the second
+    assert_eq!(deltas, ["One token at a time.", " Then more."]);
+}
+
+#[tokio::test]
+async fn test_misbehaving_upstream_streams() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // A mock upstream speaking raw HTTP: it reads the request, writes the
+    // response in parts with pauses between them, then closes the connection
+    async fn spawn_raw_server(parts: Vec<String>) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let parts = parts.clone();
+                tokio::spawn(async move {
+                    // Read the whole request, or closing the socket resets it
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 4096];
+                    loop {
+                        let n = socket.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                        if let Some(end) = text.find("\r\n\r\n") {
+                            let length = text
+                                .lines()
+                                .find_map(|line| line.strip_prefix("content-length:"))
+                                .map_or(0, |n| n.trim().parse::<usize>().unwrap());
+                            if request.len() >= end + 4 + length {
+                                break;
+                            }
+                        }
+                    }
+                    for part in parts {
+                        socket.write_all(part.as_bytes()).await.unwrap();
+                        socket.flush().await.unwrap();
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    async fn stream_from(upstream_addr: std::net::SocketAddr) -> (String, Vec<serde_json::Value>) {
+        let mut config = fireworks_config();
+        config.provider.base_url = Some(format!("http://{upstream_addr}"));
+        config.provider.api_key = Some("test-key".to_string());
+        let logger = SharedLogger::new("/tmp/claude-proxy-test-misbehaving.log").unwrap();
+        let state = AppState::new(config, reqwest::Client::new(), logger);
+        let mut stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+            .await
+            .unwrap();
+        let mut text = String::new();
+        let mut events = Vec::new();
+        while let Some(event) = stream.next().await {
+            let data: serde_json::Value = serde_json::from_str(&event.unwrap().data).unwrap();
+            if let Some(t) = data["delta"]["text"].as_str() {
+                text.push_str(t);
+            }
+            events.push(data);
+        }
+        (text, events)
+    }
+
+    let chunk = |content: &str, finish: &str| {
+        format!(
+            "data: {{\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",\
+             \"choices\":[{{\"index\":0,\"delta\":{{\"content\":\"{content}\"}},\"finish_reason\":{finish}}}]}}"
+        )
+    };
+
+    // An HTTP/1.0 server ends the body by closing the connection, here
+    // without a blank line after the last event or a [DONE]
+    let upstream_addr = spawn_raw_server(vec![
+        "HTTP/1.0 200 OK\r\ncontent-type: text/event-stream\r\n\r\n".to_string(),
+        format!("{}\n\n", chunk("Hel", "null")),
+        chunk("lo", "\"stop\""),
+    ])
+    .await;
+    let (text, events) = stream_from(upstream_addr).await;
+    assert_eq!(text, "Hello");
+    assert_eq!(events[events.len() - 2]["delta"]["stop_reason"], "end_turn");
+    assert_eq!(events.last().unwrap()["type"], "message_stop");
+
+    // A chunked body cut off part way is an error, not the end of the turn
+    let first = format!("{}\n\n", chunk("Hel", "null"));
+    let upstream_addr = spawn_raw_server(vec![
+        "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n"
+            .to_string(),
+        format!("{:x}\r\n{first}\r\n", first.len()),
+        "40\r\ndata: {\"id\":\"c1\"".to_string(),
+    ])
+    .await;
+    let (text, events) = stream_from(upstream_addr).await;
+    assert_eq!(text, "Hel");
+    let last = events.last().unwrap();
+    assert_eq!(last["type"], "error");
+    assert_eq!(last["error"]["type"], "api_error");
+    assert!(last["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("Upstream stream broke off"));
+    assert!(!events.iter().any(|e| e["type"] == "message_stop"));
+}
+
+#[tokio::test]
+async fn test_auxiliary_endpoints() {
+    use axum::http::{HeaderMap, Uri};
+    use axum::routing::post;
+    use claude_proxy::config::AuxiliaryAction;
+    use std::sync::Arc;
+
+    let client = reqwest::Client::new();
+    let post_event = |addr: std::net::SocketAddr| {
+        client
+            .post(format!("http://{addr}/api/event_logging/batch?v=1"))
+            .header("x-api-key", "sk-ant-client")
+            .json(&serde_json::json!({"events": []}))
+            .send()
+    };
+
+    // Discarded by default
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-auxiliary.log").unwrap();
+    let state = Arc::new(AppState::new(
+        fireworks_config(),
+        reqwest::Client::new(),
+        logger.clone(),
+    ));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let resp = post_event(addr).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.json::<serde_json::Value>().await.unwrap(),
+        serde_json::json!({})
+    );
+
+    let resp = client
+        .get(format!("http://{addr}/v1/nothing-here"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "not_found_error");
+
+    // Forwarded with path, query and the client's headers
+    let upstream = axum::Router::new().route(
+        "/api/event_logging/batch",
+        post(|uri: Uri, headers: HeaderMap| async move {
+            assert_eq!(uri.query(), Some("v=1"));
+            assert_eq!(headers["x-api-key"], "sk-ant-client");
+            (axum::http::StatusCode::ACCEPTED, "{\"ok\":true}")
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+    let mut config = fireworks_config();
+    config.auxiliary.action = AuxiliaryAction::Forward;
+    config.auxiliary.forward_url = format!("http://{upstream_addr}/");
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let resp = post_event(addr).await.unwrap();
+    assert_eq!(resp.status(), 202);
+    assert_eq!(resp.text().await.unwrap(), "{\"ok\":true}");
+}
+
+#[tokio::test]
+async fn test_health_check_reenables_provider() {
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::get;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let healthy = Arc::new(AtomicBool::new(false));
+    let upstream_healthy = healthy.clone();
+    let upstream = axum::Router::new().route(
+        "/v1/models",
+        get(move |headers: HeaderMap| async move {
+            assert_eq!(headers["authorization"], "Bearer test-key");
+            if upstream_healthy.load(Ordering::SeqCst) {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}/v1"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.health_check.enabled = true;
+    let dir = tempfile::tempdir().unwrap();
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = AppState::new(config.clone(), reqwest::Client::new(), logger.clone());
+    for _ in 0..claude_proxy::routing::FAILURE_THRESHOLD {
+        let _ = state.health.record_failure("fireworks");
+    }
+
+    claude_proxy::health_check::check(&state, &config).await;
+    assert_eq!(state.health.open_providers(), vec!["fireworks"]);
+
+    healthy.store(true, Ordering::SeqCst);
+    claude_proxy::health_check::check(&state, &config).await;
+    assert!(state.health.open_providers().is_empty());
+    assert!(logger
+        .recent(5)
+        .iter()
+        .any(|e| e.component == "health" && e.message.contains("at 10% of its requests")));
+}
+
+#[tokio::test]
+async fn test_self_test() {
+    let mut config = fireworks_config();
+    config.auth.keys = vec!["sk-proxy-test".to_string()];
+    let passed = claude_proxy::self_test::run(&config).await.unwrap();
+    assert_eq!(passed.len(), 3);
+    assert!(passed[2].contains("streaming, model claude-sonnet-4-20250514"));
+
+    // A mapping to an undeclared provider fails the request
+    let mut config = fireworks_config();
+    config.models.insert(
+        "claude-sonnet-4-20250514".to_string(),
+        toml::from_str::<HashMap<String, claude_proxy::config::ModelMapping>>(
+            r#"m = { model = "kimi", provider = "nowhere" }"#,
+        )
+        .unwrap()
+        .remove("m")
+        .unwrap(),
+    );
+    let error = claude_proxy::self_test::run(&config).await.unwrap_err();
+    assert!(error.to_string().contains("Self-test failed"), "{error}");
+}
+
+#[tokio::test]
+async fn test_model_context_limits() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert_eq!(body["max_tokens"], 64);
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.models.insert(
+        "claude-sonnet-4-20250514".to_string(),
+        toml::from_str::<HashMap<String, claude_proxy::config::ModelMapping>>(
+            r#"m = { model = "kimi", max_context = 1000, max_output_tokens = 64 }"#,
+        )
+        .unwrap()
+        .remove("m")
+        .unwrap(),
+    );
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-context-limits.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+
+    // max_tokens is lowered to the model's max_output_tokens
+    let mut req = simple_request("claude-sonnet-4-20250514", "Hello");
+    req.max_tokens = 1000;
+    proxy::proxy_non_streaming(&req, &state).await.unwrap();
+
+    // A prompt over max_context is answered as Anthropic would, not forwarded
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let resp = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/messages"))
+        .json(&simple_request(
+            "claude-sonnet-4-20250514",
+            &"word ".repeat(2000),
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("prompt is too long: "));
+}
+
+#[tokio::test]
+async fn test_system_prompt_injection() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // The model's own prefix replaces the global one; the global suffix stays
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert_eq!(body["messages"][0]["role"], "system");
+            assert_eq!(
+                body["messages"][0]["content"],
+                "Emit tool calls as JSON.\n\nYou are a helpful assistant. Respond very briefly.\n\nBe terse."
+            );
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.translation.system_prefix = Some("Ignored.".to_string());
+    config.translation.system_suffix = Some("Be terse.".to_string());
+    config.models.insert(
+        "claude-sonnet-4-20250514".to_string(),
+        toml::from_str::<HashMap<String, claude_proxy::config::ModelMapping>>(
+            r#"m = { model = "kimi", system_prefix = "Emit tool calls as JSON." }"#,
+        )
+        .unwrap()
+        .remove("m")
+        .unwrap(),
+    );
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-system-prompt.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+
+    let req = simple_request("claude-sonnet-4-20250514", "Hello");
+    proxy::proxy_non_streaming(&req, &state).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_context_truncation() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // The oldest turn is dropped, and the system prompt says so
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            let messages = body["messages"].as_array().unwrap();
+            assert_eq!(messages.len(), 2);
+            assert!(messages[0]["content"]
+                .as_str()
+                .unwrap()
+                .contains("The 2 oldest messages"));
+            assert_eq!(messages[1]["content"], "Hello");
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.translation.context_strategy =
+        Some(claude_proxy::config::ContextStrategy::TruncateOldest);
+    config.models.insert(
+        "claude-sonnet-4-20250514".to_string(),
+        toml::from_str::<HashMap<String, claude_proxy::config::ModelMapping>>(
+            r#"m = { model = "kimi", max_context = 1000 }"#,
+        )
+        .unwrap()
+        .remove("m")
+        .unwrap(),
+    );
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-context-truncation.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+
+    let mut req = simple_request("claude-sonnet-4-20250514", &"word ".repeat(2000));
+    req.messages.push(Message {
+        role: Role::Assistant,
+        content: MessageContent::Text("Noted.".to_string()),
+    });
+    req.messages.push(Message {
+        role: Role::User,
+        content: MessageContent::Text("Hello".to_string()),
+    });
+    proxy::proxy_non_streaming(&req, &state).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_model_registry() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // The registry caps the model's output at 20 tokens
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert_eq!(body["max_tokens"], 20);
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 1_000_000, "completion_tokens": 0, "total_tokens": 1_000_000},
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let registry = dir.path().join("models.toml");
+    std::fs::write(
+        &registry,
+        "[models.\"accounts/fireworks/models/kimi-k2p5\"]\ninput = 2.0\noutput = 8.0\nmax_output_tokens = 20\n",
+    )
+    .unwrap();
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.registry.file = Some(registry.to_string_lossy().into_owned());
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+
+    let mut req = simple_request("claude-sonnet-4-20250514", "Hello");
+    req.max_tokens = 1000;
+    proxy::proxy_non_streaming(&req, &state).await.unwrap();
+    let usage = state.storage.usage(None).unwrap();
+    assert_eq!(usage.last().unwrap().cost, Some(2.0));
+
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let client = reqwest::Client::new();
+    let models: serde_json::Value = client
+        .get(format!("http://{addr}/v1/models"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let sonnet = models["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["id"] == "claude-sonnet-4-20250514")
+        .unwrap();
+    assert_eq!(sonnet["max_output_tokens"], 20);
+
+    // Refreshed on request
+    std::fs::write(&registry, "{").unwrap();
+    let resp = client
+        .post(format!("http://{addr}/admin/registry/reload"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 422);
+    std::fs::write(
+        &registry,
+        "[models.a]\ninput = 1.0\n[models.b]\ncontext_window = 8192\n",
+    )
+    .unwrap();
+    let resp: serde_json::Value = client
+        .post(format!("http://{addr}/admin/registry/reload"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(resp["models"], 2);
+    let registry: serde_json::Value = client
+        .get(format!("http://{addr}/admin/registry"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(registry["models"]["b"]["context_window"], 8192);
+}
+
+/// Concatenate the text deltas of an Anthropic SSE stream.
+async fn streamed_text(mut stream: proxy::SseStream) -> String {
+    let mut text = String::new();
+    while let Some(event) = stream.next().await {
+        let data: serde_json::Value = serde_json::from_str(&event.unwrap().data).unwrap();
+        if let Some(t) = data["delta"]["text"].as_str() {
+            text.push_str(t);
+        }
+    }
+    text
+}
+
+#[tokio::test]
+async fn test_inbound_auth() {
+    use std::sync::Arc;
+
+    let mut config = fireworks_config();
+    config.auth.keys = vec!["sk-proxy-test".to_string()];
+    let dir = tempfile::tempdir().unwrap();
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = Arc::new(AppState::new(
+        config,
+        reqwest::Client::new(),
+        logger.clone(),
+    ));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .post(format!("http://{addr}/v1/messages"))
+        .json(&serde_json::json!({"model": "test-model", "max_tokens": 1, "messages": []}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["type"], "error");
+    assert_eq!(body["error"]["type"], "authentication_error");
+
+    let resp = client
+        .get(format!("http://{addr}/v1/models"))
+        .header("x-api-key", "wrong")
+        .header("x-request-id", "req-auth-1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+    assert_eq!(resp.headers()["x-request-id"], "req-auth-1");
+    // The rejection is logged with the request's context
+    let entry = logger
+        .recent(10)
+        .into_iter()
+        .find(|e| e.component == "auth")
+        .unwrap();
+    assert_eq!(entry.context.unwrap()["request_id"], "req-auth-1");
+
+    let resp = client
+        .get(format!("http://{addr}/v1/models"))
+        .bearer_auth("sk-proxy-test")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let resp = client
+        .get(format!("http://{addr}/health"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn test_streaming_retries_transient_failures() {
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // A 503 asking for an immediate retry, then a stream opening with an
+    // overload error event, then a good stream
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let upstream_attempts = attempts.clone();
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(move || async move {
+            let sse = [("content-type", "text/event-stream")];
+            match upstream_attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => (StatusCode::SERVICE_UNAVAILABLE, [("retry-after", "0")], "busy").into_response(),
+                1 => (sse, "data: {\"error\":{\"code\":502,\"message\":\"Provider returned error\"}}\n\n")
+                    .into_response(),
+                _ => (
+                    sse,
+                    concat!(
+                        "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+                        "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"finish_reason\":\"stop\"}]}\n\n",
+                        "data: [DONE]\n\n",
+                    ),
+                )
+                    .into_response(),
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-stream-retry.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hello");
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_upstream_error_status_is_forwarded() {
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                axum::Json(serde_json::json!({"error": {"message": "Rate limit reached"}})),
+            )
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-errors.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let client = reqwest::Client::new();
+
+    // Streaming requests fail before the stream starts, so they get a real status too
+    for stream in [false, true] {
+        let mut req = simple_request("test-model", "Hello");
+        req.stream = Some(stream);
+        let resp = client
+            .post(format!("http://{addr}/v1/messages"))
+            .json(&req)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 429, "stream = {stream}");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert_eq!(body["error"]["message"], "Rate limit reached");
+    }
+
+    let resp = client
+        .post(format!("http://{addr}/v1/messages"))
+        .header("content-type", "application/json")
+        .body("{not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+}
+
+#[tokio::test]
+async fn test_upstream_error_body_cut_between_characters() {
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // Byte 500 falls inside an "é", where the error message cuts the body
+    let body = format!("a{}", "é".repeat(300));
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(move || async move { (StatusCode::BAD_REQUEST, body) }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let dir = tempfile::tempdir().unwrap();
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/messages"))
+        .json(&simple_request("test-model", "Hello"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        format!("Provider returned status 400: a{}", "é".repeat(249))
+    );
+}
+
+#[tokio::test]
+async fn test_retry_budget_sheds_load() {
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async { (StatusCode::SERVICE_UNAVAILABLE, "busy") }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.retry_budget.per_session = Some(1);
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-budget.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state.clone())).await;
+
+    // The first retry is within budget, the second is refused
+    let resp = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/messages"))
+        .header("x-claude-code-session-id", "session-1")
+        .json(&simple_request("test-model", "Hello"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 529);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "overloaded_error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("Retry budget exhausted"));
+    assert!(state
+        .metrics
+        .render()
+        .contains("claude_proxy_retry_budget_exhausted_total{provider=\"fireworks\"} 1"));
+}
+
+#[tokio::test]
+async fn test_bedrock_converse_stream() {
+    use axum::http::{HeaderMap, Uri};
+
+    let events = [
+        ("messageStart", r#"{"role":"assistant"}"#),
+        (
+            "contentBlockDelta",
+            r#"{"contentBlockIndex":0,"delta":{"text":"Hola"}}"#,
+        ),
+        (
+            "contentBlockDelta",
+            r#"{"contentBlockIndex":0,"delta":{"text":" mundo"}}"#,
+        ),
+        ("contentBlockStop", r#"{"contentBlockIndex":0}"#),
+        ("messageStop", r#"{"stopReason":"end_turn"}"#),
+        (
+            "metadata",
+            r#"{"usage":{"inputTokens":9,"outputTokens":2,"totalTokens":11}}"#,
+        ),
+    ];
+    let body: Vec<u8> = events
+        .iter()
+        .flat_map(|(event, payload)| eventstream_message(event, payload))
+        .collect();
+    let upstream = axum::Router::new().fallback(move |uri: Uri, headers: HeaderMap| {
+        let body = body.clone();
+        async move {
+            assert_eq!(
+                uri.path(),
+                "/model/accounts%2Ffireworks%2Fmodels%2Fkimi-k2p5/converse-stream"
+            );
+            let auth = headers["authorization"].to_str().unwrap();
+            assert!(auth.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+            assert!(auth.contains("/eu-west-1/bedrock/aws4_request"));
+            (
+                [("content-type", "application/vnd.amazon.eventstream")],
+                body,
+            )
+        }
+    });
+    let upstream_addr = spawn_server(upstream).await;
+
+    std::env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    std::env::set_var(
+        "AWS_SECRET_ACCESS_KEY",
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+    );
+    let mut config = fireworks_config();
+    config.provider.name = "bedrock".to_string();
+    config.provider.format = Some("bedrock".to_string());
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.region = Some("eu-west-1".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-bedrock.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let events: Vec<proxy::SseEvent> = stream.map(Result::unwrap).collect().await;
+    let text: String = events
+        .iter()
+        .filter_map(|e| {
+            let data: serde_json::Value = serde_json::from_str(&e.data).ok()?;
+            data["delta"]["text"].as_str().map(str::to_string)
+        })
+        .collect();
+    assert_eq!(text, "Hola mundo");
+
+    let delta = events.iter().find(|e| e.event == "message_delta").unwrap();
+    let data: serde_json::Value = serde_json::from_str(&delta.data).unwrap();
+    assert_eq!(data["delta"]["stop_reason"], "end_turn");
+    assert_eq!(data["usage"]["output_tokens"], 2);
+}
+
+#[tokio::test]
+async fn test_bedrock_stream_exception() {
+    // Byte 300 of the payload, where the log message cuts it, falls inside an "é"
+    let payload = format!(r#"{{"message":"a{}"}}"#, "é".repeat(200));
+    let mut body = eventstream_message("messageStart", r#"{"role":"assistant"}"#);
+    body.extend(eventstream_message(
+        "contentBlockDelta",
+        r#"{"contentBlockIndex":0,"delta":{"text":"Hola"}}"#,
+    ));
+    body.extend(eventstream_exception("throttlingException", &payload));
+    let upstream = axum::Router::new().fallback(move || {
+        let body = body.clone();
+        async move {
+            (
+                [("content-type", "application/vnd.amazon.eventstream")],
+                body,
+            )
+        }
+    });
+    let upstream_addr = spawn_server(upstream).await;
+
+    std::env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    std::env::set_var(
+        "AWS_SECRET_ACCESS_KEY",
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+    );
+    let mut config = fireworks_config();
+    config.provider.name = "bedrock".to_string();
+    config.provider.format = Some("bedrock".to_string());
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.region = Some("eu-west-1".to_string());
+    let dir = tempfile::tempdir().unwrap();
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger.clone());
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let events: Vec<proxy::SseEvent> = stream.map(Result::unwrap).collect().await;
+    assert!(events.iter().any(|e| e.data.contains("Hola")));
+    let logged = logger.recent(10);
+    let entry = logged
+        .iter()
+        .find(|e| e.message.starts_with("Bedrock stream throttlingException"))
+        .unwrap();
+    assert!(entry.message.ends_with('é'));
+}
+
+#[tokio::test]
+async fn test_gemini_backend() {
+    use axum::http::{HeaderMap, Uri};
+
+    let gemini = |uri: Uri, headers: HeaderMap, body: String| async move {
+        assert_eq!(headers["x-goog-api-key"], "test-key");
+        // extra_body and params are merged into Gemini bodies as well
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["safetySettings"][0]["threshold"], "BLOCK_NONE");
+        assert_eq!(body["cachedContent"], "cachedContents/abc");
+        if uri.path().ends_with(":streamGenerateContent") {
+            assert_eq!(uri.query(), Some("alt=sse"));
+            let sse = concat!(
+                "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Hello\"}]}}]}\r\n\r\n",
+                "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\" there\"}]},\"finishReason\":\"STOP\"}],",
+                "\"usageMetadata\":{\"promptTokenCount\":3,\"candidatesTokenCount\":2,\"totalTokenCount\":5}}\r\n\r\n",
+            );
+            ([("content-type", "text/event-stream")], sse.to_string())
+        } else {
+            assert_eq!(uri.path(), "/models/gemini-2.5-flash:generateContent");
+            let body = serde_json::json!({
+                "candidates": [{"content": {"role": "model", "parts": [{"text": "Hi!"}]}, "finishReason": "STOP"}],
+                "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 1, "totalTokenCount": 4}
+            });
+            ([("content-type", "application/json")], body.to_string())
+        }
+    };
+    let upstream = axum::Router::new().fallback(gemini);
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.name = "gemini".to_string();
+    config.provider.format = Some("gemini".to_string());
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.extra_body = serde_json::json!({
+        "safetySettings": [{"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_NONE"}],
+    })
+    .as_object()
+    .unwrap()
+    .clone();
+    config.provider.params = serde_json::json!({"cachedContent": "cachedContents/abc"})
+        .as_object()
+        .unwrap()
+        .clone();
+    config
+        .models
+        .insert("test-model".to_string(), "gemini-2.5-flash".into());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-gemini-backend.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(resp.stop_reason.as_deref(), Some("end_turn"));
+    assert_eq!(resp.usage.output_tokens, 1);
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hello there");
+}
+
+#[tokio::test]
+async fn test_openai_responses_backend() {
+    use axum::routing::post;
+
+    let upstream = axum::Router::new().route(
+        "/responses",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert_eq!(body["model"], "gpt-5");
+            assert_eq!(body["store"], false);
+            assert_eq!(body["input"][0]["content"][0]["text"], "Hi");
+            if body["stream"] == true {
+                let sse = concat!(
+                    "event: response.created\ndata: {\"type\":\"response.created\",\"response\":{\"id\":\"resp_1\"}}\n\n",
+                    "event: response.output_text.delta\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\"Hello\"}\n\n",
+                    "event: response.output_text.delta\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\" there\"}\n\n",
+                    "event: response.completed\ndata: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",",
+                    "\"model\":\"gpt-5\",\"output\":[],\"usage\":{\"input_tokens\":3,\"output_tokens\":2,\"total_tokens\":5}}}\n\n",
+                );
+                return ([("content-type", "text/event-stream")], sse.to_string());
+            }
+            let body = serde_json::json!({
+                "id": "resp_1",
+                "object": "response",
+                "model": "gpt-5",
+                "status": "completed",
+                "output": [{"type": "message", "role": "assistant",
+                            "content": [{"type": "output_text", "text": "Hi!"}]}],
+                "usage": {"input_tokens": 3, "output_tokens": 1, "total_tokens": 4}
+            });
+            ([("content-type", "application/json")], body.to_string())
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.name = "openai".to_string();
+    config.provider.format = Some("openai-responses".to_string());
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config
+        .models
+        .insert("test-model".to_string(), "gpt-5".into());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-openai-responses.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(resp.stop_reason.as_deref(), Some("end_turn"));
+    assert_eq!(resp.usage.output_tokens, 1);
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hello there");
+}
+
+#[tokio::test]
+async fn test_ollama_without_api_key() {
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(
+            |headers: HeaderMap, axum::Json(body): axum::Json<serde_json::Value>| async move {
+                assert!(headers.get("authorization").is_none());
+                assert_eq!(body["keep_alive"], "30m");
+                // The request's own max_tokens wins over the provider params
+                assert_eq!(body["max_tokens"], 50);
+                axum::Json(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": "qwen3:8b",
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "Hi!"},
+                        "finish_reason": "stop"
+                    }]
+                }))
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.name = "ollama".to_string();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key_env = "CLAUDE_PROXY_TEST_UNSET_KEY".to_string();
+    config.provider.params = serde_json::json!({"keep_alive": "30m", "max_tokens": 1})
+        .as_object()
+        .unwrap()
+        .clone();
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-ollama.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert!(matches!(&resp.content[0], ResponseContentBlock::Text { text } if text == "Hi!"));
+}
+
+#[tokio::test]
+async fn test_provider_extra_body() {
+    use axum::routing::post;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                assert_eq!(body["provider"]["order"], serde_json::json!(["Fireworks"]));
+                assert_eq!(body["context_length_exceeded_behavior"], "truncate");
+                // extra_body replaces the translated max_tokens; params don't
+                assert_eq!(body["max_tokens"], 8);
+                assert_eq!(body["keep_alive"], "30m");
+                axum::Json(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": "llama",
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "Hi!"},
+                        "finish_reason": "stop"
+                    }]
+                }))
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.extra_body = serde_json::json!({
+        "provider": {"order": ["Fireworks"]},
+        "context_length_exceeded_behavior": "truncate",
+        "max_tokens": 8,
+    })
+    .as_object()
+    .unwrap()
+    .clone();
+    config.provider.params = serde_json::json!({"keep_alive": "30m", "max_tokens": 1})
+        .as_object()
+        .unwrap()
+        .clone();
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-extra-body.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert!(matches!(&resp.content[0], ResponseContentBlock::Text { text } if text == "Hi!"));
+}
+
+#[tokio::test]
+async fn test_provider_headers() {
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(
+            |headers: HeaderMap, axum::Json(body): axum::Json<serde_json::Value>| async move {
+                assert_eq!(headers["http-referer"], "https://example.com");
+                assert_eq!(headers["x-title"], "Proxy");
+                assert_eq!(headers["authorization"], "Bearer test-key");
+                if body["stream"] == true {
+                    return (
+                        [("content-type", "text/event-stream")],
+                        concat!(
+                            "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+                            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi!\"},\"finish_reason\":\"stop\"}]}\n\n",
+                            "data: [DONE]\n\n",
+                        )
+                        .to_string(),
+                    );
+                }
+                (
+                    [("content-type", "application/json")],
+                    serde_json::json!({
+                        "id": "chatcmpl-1",
+                        "object": "chat.completion",
+                        "created": 0,
+                        "model": "llama",
+                        "choices": [{
+                            "index": 0,
+                            "message": {"role": "assistant", "content": "Hi!"},
+                            "finish_reason": "stop"
+                        }]
+                    })
+                    .to_string(),
+                )
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.headers = HashMap::from([
+        (
+            "HTTP-Referer".to_string(),
+            "https://example.com".to_string(),
+        ),
+        ("X-Title".to_string(), "Proxy".to_string()),
+    ]);
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-headers.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert!(matches!(&resp.content[0], ResponseContentBlock::Text { text } if text == "Hi!"));
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hi!");
+}
+
+#[tokio::test]
+async fn test_model_override_header() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // Mock upstream answering with the model it was asked for
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                axum::Json(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": body["model"],
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": body["model"]},
+                        "finish_reason": "stop"
+                    }]
+                }))
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-model-override.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+
+    let answer = |model_override: Option<&'static str>| async move {
+        let mut request = reqwest::Client::new()
+            .post(format!("http://{addr}/v1/messages"))
+            .json(&simple_request("claude-sonnet-4-20250514", "Hi"));
+        if let Some(model) = model_override {
+            request = request.header("x-claude-proxy-model", model);
+        }
+        let body: serde_json::Value = request.send().await.unwrap().json().await.unwrap();
+        assert_eq!(body["model"], "claude-sonnet-4-20250514");
+        body["content"][0]["text"].as_str().unwrap().to_string()
+    };
+
+    assert_eq!(answer(None).await, "accounts/fireworks/models/kimi-k2p5");
+    assert_eq!(
+        answer(Some("accounts/fireworks/models/glm-4p6")).await,
+        "accounts/fireworks/models/glm-4p6"
+    );
+}
+
+#[tokio::test]
+async fn test_expectation_reprompts() {
+    use axum::routing::post;
+
+    // Mock upstream that only answers in JSON once told off
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                assert_eq!(body["stream"], false);
+                let messages = body["messages"].as_array().unwrap();
+                let content = if messages.len() > 1 {
+                    r#"{"colors": ["red", "green", "blue"]}"#
+                } else {
+                    "Sure! Red, green and blue."
+                };
+                axum::Json(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": body["model"],
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": content},
+                        "finish_reason": "stop"
+                    }]
+                }))
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.models.insert(
+        "test-model".to_string(),
+        serde_json::from_value(serde_json::json!({
+            "model": "accounts/fireworks/models/kimi-k2p5",
+            "expect": ["valid_json"]
+        }))
+        .unwrap(),
+    );
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-expect.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Colors?"), &state)
+        .await
+        .unwrap();
+    assert_eq!(
+        streamed_text(stream).await,
+        r#"{"colors": ["red", "green", "blue"]}"#
+    );
+}
+
+/// Frame an event in `application/vnd.amazon.eventstream` encoding. The
+/// checksums are left zeroed; the proxy doesn't verify them.
+fn eventstream_message(event_type: &str, payload: &str) -> Vec<u8> {
+    eventstream_frame(
+        &[
+            (":event-type", event_type),
+            (":content-type", "application/json"),
+            (":message-type", "event"),
+        ],
+        payload,
+    )
+}
+
+/// Frame a Bedrock stream exception, as [`eventstream_message`] does events.
+fn eventstream_exception(exception_type: &str, payload: &str) -> Vec<u8> {
+    eventstream_frame(
+        &[
+            (":exception-type", exception_type),
+            (":content-type", "application/json"),
+            (":message-type", "exception"),
+        ],
+        payload,
+    )
+}
+
+fn eventstream_frame(header_values: &[(&str, &str)], payload: &str) -> Vec<u8> {
+    let mut headers = Vec::new();
+    for &(name, value) in header_values {
+        headers.push(u8::try_from(name.len()).unwrap());
+        headers.extend_from_slice(name.as_bytes());
+        headers.push(7); // string
+        headers.extend_from_slice(&u16::try_from(value.len()).unwrap().to_be_bytes());
+        headers.extend_from_slice(value.as_bytes());
+    }
+    let total = u32::try_from(16 + headers.len() + payload.len()).unwrap();
+    let mut message = Vec::new();
+    message.extend_from_slice(&total.to_be_bytes());
+    message.extend_from_slice(&u32::try_from(headers.len()).unwrap().to_be_bytes());
+    message.extend_from_slice(&[0; 4]);
+    message.extend_from_slice(&headers);
+    message.extend_from_slice(payload.as_bytes());
+    message.extend_from_slice(&[0; 4]);
+    message
+}
+
+async fn spawn_server(app: axum::Router) -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_sheds_queued_requests() {
+    use axum::body::Body;
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // A stream that stays open for 3s, holding the provider's only slot
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            let body = async_stream::stream! {
+                yield Ok::<_, std::io::Error>(
+                    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hi\"},\"finish_reason\":null}]}\n\n",
+                );
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+                yield Ok("data: [DONE]\n\n");
+            };
+            axum::response::Response::builder()
+                .header("content-type", "text/event-stream")
+                .body(Body::from_stream(body))
+                .unwrap()
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.max_concurrent_upstream = Some(1);
+    config.provider.queue_timeout_secs = Some(1);
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-concurrency.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let client = reqwest::Client::new();
+
+    let mut streaming = simple_request("test-model", "Hello");
+    streaming.stream = Some(true);
+    let first = client
+        .post(format!("http://{addr}/v1/messages"))
+        .json(&streaming)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(first.status(), 200);
+
+    let resp = client
+        .post(format!("http://{addr}/v1/messages"))
+        .json(&simple_request("test-model", "Hello"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 529);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "overloaded_error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("limit of 1 concurrent requests"));
+    drop(first);
+}
+
+#[tokio::test]
+async fn test_rate_limit_paces_and_sheds() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.requests_per_minute = Some(1);
+    config.provider.queue_timeout_secs = Some(5);
+    let mut invalid = config.clone();
+    invalid.provider.tokens_per_minute = Some(0);
+    assert!(invalid.validate().is_err());
+
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-rate-limit.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let req = simple_request("test-model", "Hello");
+    proxy::proxy_non_streaming(&req, &state).await.unwrap();
+
+    // The next slot is a minute away, past the queue timeout
+    let err = proxy::proxy_non_streaming(&req, &state).await.unwrap_err();
+    assert_eq!(err.status().as_u16(), 529);
+    assert!(err.to_string().contains("rate limited"));
+}
+
+#[tokio::test]
+async fn test_request_tags() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12},
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state.clone())).await;
+    let client = reqwest::Client::new();
+
+    for tags in ["project=billing, task=a", "project=search"] {
+        let resp = client
+            .post(format!("http://{addr}/v1/messages"))
+            .header("x-claude-proxy-tags", tags)
+            .json(&simple_request("test-model", "Hello"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+
+    let usage = state.storage.usage(None).unwrap();
+    assert_eq!(usage[0].tags, ["project=billing", "task=a"]);
+    let report: serde_json::Value = client
+        .get(format!("http://{addr}/stats?tag=project%3Dbilling"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["total"]["requests"], 1);
+    assert_eq!(report["by_tag"]["task=a"]["input_tokens"], 10);
+    assert!(state.metrics.render().contains(
+        "claude_proxy_tagged_requests_total{provider=\"fireworks\",model=\"accounts/fireworks/models/kimi-k2p5\",tag=\"project=search\"} 1"
+    ));
+    let logs = state.logger.recent(50);
+    assert!(logs
+        .iter()
+        .filter_map(|entry| entry.context.as_ref())
+        .any(|context| context["tags"] == serde_json::json!(["project=search"])));
+}
+
+#[tokio::test]
+async fn test_response_cache() {
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use claude_proxy::log_context::{self, RequestContext};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Answers streams with "Bonjour" and other requests with "Hi"
+    let calls = Arc::new(AtomicUsize::new(0));
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post({
+            let calls = calls.clone();
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                if body["stream"] == true {
+                    let sse = concat!(
+                        "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",",
+                        "\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Bonjour\"},\"finish_reason\":\"stop\"}]}\n\n",
+                        "data: [DONE]\n\n",
+                    );
+                    return ([("content-type", "text/event-stream")], sse).into_response();
+                }
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6},
+                }))
+                .into_response()
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.response_cache.enabled = true;
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-response-cache.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    // A repeated request is answered from the cache, streamed or not
+    let req = simple_request("test-model", "Title this conversation");
+    let first = proxy::proxy_non_streaming(&req, &state).await.unwrap();
+    let second = proxy::proxy_non_streaming(&req, &state).await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_ne!(first.id, second.id);
+    let mut streaming = req.clone();
+    streaming.stream = Some(true);
+    let text = streamed_text(proxy::proxy_streaming(&streaming, &state).await.unwrap()).await;
+    assert_eq!(text, "Hi");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // A completed stream is cached too
+    let streaming = streaming_request("test-model", "Greet me");
+    let text = streamed_text(proxy::proxy_streaming(&streaming, &state).await.unwrap()).await;
+    assert_eq!(text, "Bonjour");
+    let cached = proxy::proxy_non_streaming(&simple_request("test-model", "Greet me"), &state)
+        .await
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&cached.content).unwrap()[0]["text"],
+        "Bonjour"
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // Sampled requests always go upstream
+    let mut sampled = req.clone();
+    sampled.temperature = Some(0.7);
+    proxy::proxy_non_streaming(&sampled, &state).await.unwrap();
+    proxy::proxy_non_streaming(&sampled, &state).await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    // Tenants don't share cached responses
+    for tenant in ["sk-team-a", "sk-team-b"] {
+        let ctx = RequestContext {
+            tenant_key: Some(tenant.to_string()),
+            ..RequestContext::new()
+        };
+        log_context::scope(ctx, proxy::proxy_non_streaming(&req, &state))
+            .await
+            .unwrap();
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 6);
+    assert!(state
+        .metrics
+        .render()
+        .contains("claude_proxy_response_cache_hits_total{provider=\"fireworks\",model=\"accounts/fireworks/models/kimi-k2p5\"} 3"));
+}
+
+#[tokio::test]
+async fn test_reconnect_replays_stream() {
+    use claude_proxy::log_context::{self, RequestContext};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    let calls = Arc::new(AtomicU32::new(0));
+    let counter = calls.clone();
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let chunk = |content: &str, finish: Option<&str>| {
+                    serde_json::json!({
+                        "id": "c1", "object": "chat.completion.chunk", "created": 0, "model": "mock",
+                        "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": finish}],
+                    })
+                };
+                let sse = format!(
+                    "data: {}\n\ndata: {}\n\ndata: [DONE]\n\n",
+                    chunk("Hello", None),
+                    chunk(" again", Some("stop"))
+                );
+                ([("content-type", "text/event-stream")], sse)
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.response_cache.reconnect_secs = 60;
+    let dir = tempfile::tempdir().unwrap();
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+    let mut req = streaming_request("test-model", "Hi");
+    req.temperature = Some(1.0);
+
+    // The client goes away after the first event
+    let mut stream = proxy::proxy_streaming(&req, &state).await.unwrap();
+    let first: serde_json::Value =
+        serde_json::from_str(&stream.next().await.unwrap().unwrap().data).unwrap();
+    let id = first["message"]["id"].as_str().unwrap().to_string();
+    drop(stream);
+
+    // Sent again, it gets the same message without another upstream call
+    let mut stream = proxy::proxy_streaming(&req, &state).await.unwrap();
+    let mut text = String::new();
+    let mut replayed_id = None;
+    while let Some(event) = stream.next().await {
+        let data: serde_json::Value = serde_json::from_str(&event.unwrap().data).unwrap();
+        if let Some(id) = data["message"]["id"].as_str() {
+            replayed_id = Some(id.to_string());
+        }
+        if let Some(t) = data["delta"]["text"].as_str() {
+            text.push_str(t);
+        }
+    }
+    assert_eq!(text, "Hello again");
+    assert_eq!(replayed_id, Some(id));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // The replay is counted, and recorded in usage at no cost
+    assert!(state.metrics.render().contains(
+        "claude_proxy_reconnect_replays_total{provider=\"fireworks\",model=\"accounts/fireworks/models/kimi-k2p5\"} 1"
+    ));
+    let usage = state.storage.usage(None).unwrap();
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage[1].cost, Some(0.0));
+
+    // Another conversation is sent upstream
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Bye"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hello again");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // So is the same request from another tenant
+    let ctx = RequestContext {
+        tenant_key: Some("sk-team-b".to_string()),
+        ..RequestContext::new()
+    };
+    let stream = log_context::scope(ctx, proxy::proxy_streaming(&req, &state))
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hello again");
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_tls_self_signed() {
+    use claude_proxy::config::TlsConfig;
+    use std::sync::Arc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = TlsConfig {
+        self_signed: true,
+        dir: dir.path().join("tls").to_string_lossy().into_owned(),
+        hosts: vec!["proxy.internal".to_string()],
+        ..TlsConfig::default()
+    };
+    let tls = claude_proxy::tls::load(&config).unwrap().unwrap();
+    let pem = std::fs::read(&tls.cert).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let key = std::fs::metadata(dir.path().join("tls/key.pem")).unwrap();
+        assert_eq!(key.permissions().mode() & 0o777, 0o600);
+    }
+    // Loading again reuses the certificate
+    let again = claude_proxy::tls::load(&config).unwrap().unwrap();
+    assert_eq!(std::fs::read(&again.cert).unwrap(), pem);
+
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = Arc::new(AppState::new(
+        fireworks_config(),
+        reqwest::Client::new(),
+        logger,
+    ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(claude_proxy::tls::serve(
+        listener,
+        claude_proxy::build_router(state),
+        tls.acceptor,
+        async {
+            let _ = stopped.await;
+        },
+    ));
+
+    let client = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(&pem).unwrap())
+        .build()
+        .unwrap();
+    let health: serde_json::Value = client
+        .get(format!("https://localhost:{port}/health"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(health["service"], "claude-proxy");
+    // The loopback address is named in the certificate too
+    assert!(client
+        .get(format!("https://127.0.0.1:{port}/health"))
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .is_success());
+    // Plain HTTP is refused
+    assert!(reqwest::get(format!("http://localhost:{port}/health"))
+        .await
+        .is_err());
+
+    stop.send(()).unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_journal_records_failover() {
+    use axum::http::StatusCode;
+    use axum::routing::post;
+
+    let failing = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async { (StatusCode::SERVICE_UNAVAILABLE, "busy") }),
+    );
+    let failing_addr = spawn_server(failing).await;
+    let backup = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+            }))
+        }),
+    );
+    let backup_addr = spawn_server(backup).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let journal = dir.path().join("journal.jsonl");
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{failing_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.providers.insert(
+        "backup".to_string(),
+        ProviderConfig {
+            name: "backup".to_string(),
+            base_url: Some(format!("http://{backup_addr}")),
+            ..config.provider.clone()
+        },
+    );
+    config.fallback = vec!["backup".to_string()];
+    config.journal.file = Some(journal.to_string_lossy().into_owned());
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    proxy::proxy_non_streaming(&simple_request("test-model", "Hello"), &state)
+        .await
+        .unwrap();
+
+    assert_eq!(claude_proxy::journal::verify(&journal).unwrap(), 1);
+    let entry: claude_proxy::journal::JournalEntry =
+        serde_json::from_str(std::fs::read_to_string(&journal).unwrap().trim()).unwrap();
+    assert_eq!(entry.event, "provider_failover");
+    assert_eq!(entry.detail["from"], "fireworks");
+    assert_eq!(entry.detail["to"], "backup");
+}
+
+#[tokio::test]
+async fn test_schedule_prefers_provider() {
+    use axum::routing::post;
+    use claude_proxy::config::{ScheduleAction, ScheduleWindow};
+    use claude_proxy::schedule::Cron;
+
+    let answering = |text: &'static str| {
+        axum::Router::new().route(
+            "/chat/completions",
+            post(move || async move {
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": text}, "finish_reason": "stop"}],
+                }))
+            }),
+        )
+    };
+    let primary_addr = spawn_server(answering("primary")).await;
+    let batch_addr = spawn_server(answering("batch")).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let journal = dir.path().join("journal.jsonl");
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{primary_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.providers.insert(
+        "batch".to_string(),
+        ProviderConfig {
+            name: "batch".to_string(),
+            base_url: Some(format!("http://{batch_addr}")),
+            ..config.provider.clone()
+        },
+    );
+    config.fallback = vec!["batch".to_string()];
+    // Open all day, every day
+    config.schedule = vec![ScheduleWindow {
+        provider: "batch".to_string(),
+        when: Cron::parse("* * * * *").unwrap(),
+        action: ScheduleAction::Prefer,
+        utc: true,
+    }];
+    config.journal.file = Some(journal.to_string_lossy().into_owned());
+    let logger = SharedLogger::new(dir.path().join("proxy.log")).unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let text = |resp: &claude_proxy::translate::anthropic_types::MessagesResponse| {
+        serde_json::to_value(&resp.content[0]).unwrap()["text"].clone()
+    };
+    for _ in 0..2 {
+        let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+            .await
+            .unwrap();
+        assert_eq!(text(&resp), "batch");
+    }
+
+    // The window opening is journaled once
+    assert_eq!(claude_proxy::journal::verify(&journal).unwrap(), 1);
+    let entry: claude_proxy::journal::JournalEntry =
+        serde_json::from_str(std::fs::read_to_string(&journal).unwrap().trim()).unwrap();
+    assert_eq!(entry.event, "schedule_window");
+    assert_eq!(entry.detail["provider"], "batch");
+    assert_eq!(entry.detail["open"], true);
+}
+
+#[tokio::test]
+async fn test_extra_on_request() {
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    let sse = concat!(
+        "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"model\":\"mock\",\"system_fingerprint\":\"fp_1\",",
+        "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}],",
+        "\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":1,\"total_tokens\":4,\"cost\":0.5}}\n\n",
+        "data: [DONE]\n\n",
+    );
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+            if body["stream"] == true {
+                return ([("content-type", "text/event-stream")], sse).into_response();
+            }
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "model": "mock",
+                "system_fingerprint": "fp_1",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4, "cost": 0.5},
+            }))
+            .into_response()
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-extra.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let client = reqwest::Client::new();
+    let send = |stream: bool, extra: bool| {
+        let mut request =
+            client
+                .post(format!("http://{addr}/v1/messages"))
+                .json(&serde_json::json!({
+                    "model": "test-model", "max_tokens": 10, "stream": stream,
+                    "messages": [{"role": "user", "content": "Hi"}],
+                }));
+        if extra {
+            request = request.header(proxy::EXTRA_HEADER, "1");
+        }
+        async move { request.send().await.unwrap().text().await.unwrap() }
+    };
+
+    // Left out unless asked for
+    let plain: serde_json::Value = serde_json::from_str(&send(false, false).await).unwrap();
+    assert!(plain.get("extra").is_none());
+    assert!(!send(true, false).await.contains("extra"));
+
+    let resp: serde_json::Value = serde_json::from_str(&send(false, true).await).unwrap();
+    assert_eq!(resp["extra"]["system_fingerprint"], "fp_1");
+    assert_eq!(resp["extra"]["cost"], 0.5);
+    let streamed = send(true, true).await;
+    let delta = streamed
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .find(|event| event["type"] == "message_delta")
+        .unwrap();
+    assert_eq!(delta["extra"]["system_fingerprint"], "fp_1");
+    assert_eq!(delta["extra"]["cost"], 0.5);
+}
+
+#[tokio::test]
+async fn test_outbound_proxy() {
+    use axum::routing::post;
+    use claude_proxy::config::OutboundConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "via proxy"}, "finish_reason": "stop"}],
+            }))
+        }),
+    );
+    // An HTTP proxy gets the request with its full URL, and answers it itself
+    let http_proxy = spawn_server(upstream.clone()).await;
+    let upstream_addr = spawn_server(upstream).await;
+
+    // A SOCKS5 proxy that sends every connection on to the upstream
+    let socks = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let socks_addr = socks.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = socks.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut greeting = [0; 2];
+                client.read_exact(&mut greeting).await.unwrap();
+                let mut methods = vec![0; greeting[1] as usize];
+                client.read_exact(&mut methods).await.unwrap();
+                client.write_all(&[5, 0]).await.unwrap();
+                let mut head = [0; 4];
+                client.read_exact(&mut head).await.unwrap();
+                let len = match head[3] {
+                    1 => 4,
+                    4 => 16,
+                    _ => usize::from(client.read_u8().await.unwrap()),
+                };
+                let mut target = vec![0; len + 2];
+                client.read_exact(&mut target).await.unwrap();
+                let host = String::from_utf8_lossy(&target[..len]).into_owned();
+                assert_eq!(host, "provider.invalid");
+                client
+                    .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+                let mut upstream = tokio::net::TcpStream::connect(upstream_addr).await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            });
+        }
+    });
+
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-outbound.log").unwrap();
+    for outbound in [
+        OutboundConfig {
+            https_proxy: Some(http_proxy.to_string()),
+            ..OutboundConfig::default()
+        },
+        OutboundConfig {
+            socks5: Some(socks_addr.to_string()),
+            ..OutboundConfig::default()
+        },
+    ] {
+        let mut config = fireworks_config();
+        // Only reachable through the proxy
+        config.provider.base_url = Some("http://provider.invalid".to_string());
+        config.provider.api_key = Some("test-key".to_string());
+        config.outbound = outbound;
+        config.validate().unwrap();
+        let client = config
+            .outbound
+            .apply(reqwest::Client::builder())
+            .unwrap()
+            .build()
+            .unwrap();
+        let state = AppState::new(config, client, logger.clone());
+        let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+            .await
+            .unwrap();
+        assert!(serde_json::to_string(&resp.content)
+            .unwrap()
+            .contains("via proxy"));
+    }
+
+    let mut config = fireworks_config();
+    config.outbound.https_proxy = Some(http_proxy.to_string());
+    config.outbound.socks5 = Some(socks_addr.to_string());
+    assert!(config.validate().is_err());
+    config.outbound.https_proxy = None;
+    config.outbound.socks5 = Some("http://127.0.0.1:1080".to_string());
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_provider_test_reports_checks() {
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use claude_proxy::provider_test::{self, Outcome};
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            if body["stream"] == true {
+                let chunk = serde_json::json!({
+                    "id": "c1", "object": "chat.completion.chunk", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "delta": {"content": "Hello!"}, "finish_reason": "stop"}],
+                });
+                return (
+                    [("content-type", "text/event-stream")],
+                    format!("data: {chunk}\n\ndata: [DONE]\n\n"),
+                )
+                    .into_response();
+            }
+            if body["tools"].is_array() {
+                return axum::Json(serde_json::json!({
+                    "id": "c2", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": null, "tool_calls": [{
+                        "id": "call_1", "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"},
+                    }]}, "finish_reason": "tool_calls"}],
+                    "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+                }))
+                .into_response();
+            }
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hello!"}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2},
+            }))
+            .into_response()
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let checks = provider_test::run(&config, Some("test-model"))
+        .await
+        .unwrap();
+    let outcomes: Vec<(&str, Outcome)> = checks.iter().map(|c| (c.name, c.outcome)).collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            ("auth", Outcome::Pass),
+            ("connectivity", Outcome::Pass),
+            ("translation", Outcome::Pass),
+            ("streaming", Outcome::Pass),
+            ("tool calling", Outcome::Pass),
+        ]
+    );
+    assert!(checks[4].detail.contains("get_weather"), "{}", checks[4]);
+
+    // A rejected key fails auth and skips the rest
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(|| async {
+            (
+                axum::http::StatusCode::UNAUTHORIZED,
+                axum::Json(serde_json::json!({"error": {"message": "bad key"}})),
+            )
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    let checks = provider_test::run(&config, Some("test-model"))
+        .await
+        .unwrap();
+    assert_eq!(checks[0].outcome, Outcome::Fail);
+    assert!(
+        checks[0].detail.contains("rejected the key (401)"),
+        "{}",
+        checks[0]
+    );
+    assert_eq!(checks[4].outcome, Outcome::Skip);
+}
+
+#[tokio::test]
+async fn test_models_report() {
+    use axum::routing::get;
+
+    let upstream = axum::Router::new().route(
+        "/models",
+        get(|| async {
+            axum::Json(serde_json::json!({
+                "object": "list",
+                "data": [
+                    {"id": "accounts/fireworks/models/kimi-k2p5", "object": "model"},
+                    {"id": "accounts/fireworks/models/llama-v3p1-8b", "object": "model"},
+                ],
+            }))
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.providers.insert(
+        "offline".to_string(),
+        ProviderConfig {
+            name: "offline".to_string(),
+            base_url: Some("http://127.0.0.1:1".to_string()),
+            ..config.provider.clone()
+        },
+    );
+    config.models.insert(
+        "claude-3-5-haiku-20241022".to_string(),
+        "accounts/fireworks/models/retired".into(),
+    );
+    config.models.insert(
+        "claude-opus-4-20250514".to_string(),
+        serde_json::from_value(serde_json::json!({"model": "big", "provider": "offline"})).unwrap(),
+    );
+
+    let report = claude_proxy::models::report(&config, &reqwest::Client::new())
+        .await
+        .unwrap();
+    assert_eq!(report.providers.len(), 2);
+    assert_eq!(report.providers[0].models.len(), 2);
+    assert!(report.providers[1].error.is_some());
+
+    let offered: HashMap<&str, Option<bool>> = report
+        .mappings
+        .iter()
+        .map(|row| (row.claude_model.as_str(), row.offered))
+        .collect();
+    assert_eq!(offered["claude-sonnet-4-20250514"], Some(true));
+    assert_eq!(offered["claude-3-5-haiku-20241022"], Some(false));
+    assert_eq!(offered["claude-opus-4-20250514"], None);
+    assert!(report
+        .unmapped
+        .contains(&"claude-3-7-sonnet-20250219".to_string()));
+    assert!(!report
+        .unmapped
+        .contains(&"claude-sonnet-4-20250514".to_string()));
+
+    let text = claude_proxy::models::render(&report);
+    assert!(text.contains("Models on fireworks (2):"), "{text}");
+    assert!(text.contains("Couldn't list offline's models"), "{text}");
+    assert!(
+        text.contains("retired  (not listed by the provider)"),
+        "{text}"
+    );
+    assert!(
+        text.contains("No mapping for these Claude Code models"),
+        "{text}"
+    );
+}
+
+#[tokio::test]
+async fn test_prompted_tools() {
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // The tools go into the system prompt; the call written in the reply
+    // comes back as a tool_use block, streamed or not
+    let reply = "Let me read it.\n<tool_call>\n{\"name\": \"Read\", \"arguments\": {\"path\": \"a.txt\"}}\n</tool_call>";
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert!(body.get("tools").is_none());
+            assert!(body["messages"][0]["content"]
+                .as_str()
+                .unwrap()
+                .contains("## Read\nRead a file"));
+            assert_eq!(body["stop"], serde_json::json!(["<tool_result"]));
+            if body["stream"] == true {
+                let (a, b) = reply.split_at(30);
+                let sse: String = [(a, "null"), (b, "\"stop\"")]
+                    .iter()
+                    .map(|(text, finish)| {
+                        format!(
+                            "data: {{\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",\
+                             \"choices\":[{{\"index\":0,\"delta\":{{\"content\":{}}},\"finish_reason\":{finish}}}]}}\n\n",
+                            serde_json::Value::from(*text)
+                        )
+                    })
+                    .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+                    .collect();
+                return ([("content-type", "text/event-stream")], sse).into_response();
+            }
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": reply}, "finish_reason": "stop"}],
+            }))
+            .into_response()
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.models.insert(
+        "claude-sonnet-4-20250514".to_string(),
+        toml::from_str::<HashMap<String, claude_proxy::config::ModelMapping>>(
+            r#"m = { model = "base-model", prompted_tools = "json" }"#,
+        )
+        .unwrap()
+        .remove("m")
+        .unwrap(),
+    );
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-prompted-tools.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+
+    let mut req = simple_request("claude-sonnet-4-20250514", "Read a.txt");
+    req.tools = Some(
+        serde_json::from_value(serde_json::json!([{
+            "name": "Read",
+            "description": "Read a file",
+            "input_schema": {"type": "object", "properties": {"path": {"type": "string"}}},
+        }]))
+        .unwrap(),
+    );
+    let resp = proxy::proxy_non_streaming(&req, &state).await.unwrap();
+    let resp = serde_json::to_value(&resp).unwrap();
+    assert_eq!(resp["stop_reason"], "tool_use");
+    assert_eq!(resp["content"][0]["text"], "Let me read it.");
+    assert_eq!(resp["content"][1]["type"], "tool_use");
+    assert_eq!(resp["content"][1]["name"], "Read");
+    assert_eq!(
+        resp["content"][1]["input"],
+        serde_json::json!({"path": "a.txt"})
+    );
+
+    req.stream = Some(true);
+    let mut stream = proxy::proxy_streaming(&req, &state).await.unwrap();
+    let (mut text, mut tool, mut input, mut stop_reason) =
+        (String::new(), None, String::new(), None);
+    while let Some(event) = stream.next().await {
+        let data: serde_json::Value = serde_json::from_str(&event.unwrap().data).unwrap();
+        if let Some(t) = data["delta"]["text"].as_str() {
+            text.push_str(t);
+        }
+        if data["content_block"]["type"] == "tool_use" {
+            tool = data["content_block"]["name"].as_str().map(str::to_string);
+        }
+        if let Some(json) = data["delta"]["partial_json"].as_str() {
+            input.push_str(json);
+        }
+        if let Some(reason) = data["delta"]["stop_reason"].as_str() {
+            stop_reason = Some(reason.to_string());
+        }
+    }
+    assert_eq!(text.trim_end(), "Let me read it.");
+    assert_eq!(tool.as_deref(), Some("Read"));
+    assert_eq!(input, r#"{"path":"a.txt"}"#);
+    assert_eq!(stop_reason.as_deref(), Some("tool_use"));
+}
+
+#[tokio::test]
+async fn test_large_response_parsed_as_it_arrives() {
+    use axum::routing::post;
+    use std::sync::Arc;
+
+    // A 4 MB answer sent in 4 KB pieces; "broken" gets a body cut short
+    let answer = "All work and no play. ".repeat(200_000);
+    let expected = answer.clone();
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let answer = answer.clone();
+            async move {
+                let mut json = serde_json::to_vec(&serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": answer}, "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 10, "completion_tokens": 1_000_000, "total_tokens": 1_000_010},
+                }))
+                .unwrap();
+                if body["messages"].to_string().contains("broken") {
+                    json.truncate(100);
+                }
+                let chunks: Vec<Result<bytes::Bytes, std::io::Error>> = json
+                    .chunks(4096)
+                    .map(|chunk| Ok(bytes::Bytes::copy_from_slice(chunk)))
+                    .collect();
+                axum::body::Body::from_stream(futures::stream::iter(chunks))
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-large-response.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+
+    let req = simple_request("claude-sonnet-4-20250514", "Write a lot");
+    let resp = proxy::proxy_non_streaming(&req, &state).await.unwrap();
+    assert!(matches!(&resp.content[0], ResponseContentBlock::Text { text } if *text == expected));
+    assert_eq!(resp.usage.output_tokens, 1_000_000);
+
+    let req = simple_request("claude-sonnet-4-20250514", "broken");
+    let Err(err) = proxy::proxy_non_streaming(&req, &state).await else {
+        panic!("a body cut short parsed");
+    };
+    let err = err.to_string();
+    assert!(err.contains("Failed to parse provider response"), "{err}");
+    assert!(err.contains("Body: {\"choices\""), "{err}");
+}
+
+#[tokio::test]
+async fn test_image_urls() {
+    use axum::routing::{get, post};
+    use std::sync::{Arc, Mutex};
+
+    // The proxy fetches /cat.png itself only when the provider asks for inline images
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let upstream = axum::Router::new()
+        .route(
+            "/cat.png",
+            get(|| async { ([("content-type", "image/png")], b"\x89PNG".to_vec()) }),
+        )
+        .route(
+            "/notes.txt",
+            get(|| async { ([("content-type", "text/plain")], "not a cat") }),
+        )
+        .route(
+            "/chat/completions",
+            post({
+                let seen = seen.clone();
+                move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                    let url = body["messages"][0]["content"][0]["image_url"]["url"].clone();
+                    seen.lock().unwrap().push(url);
+                    axum::Json(serde_json::json!({
+                        "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                        "choices": [{"index": 0, "message": {"role": "assistant", "content": "A cat."}, "finish_reason": "stop"}],
+                    }))
+                }
+            }),
+        );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let request = |path: &str| -> MessagesRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4-20250514",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": [
+                {"type": "image", "source": {"type": "url", "url": format!("http://{upstream_addr}{path}")}},
+                {"type": "text", "text": "What is this?"}
+            ]}]
+        }))
+        .unwrap()
+    };
+    let state = |inline| {
+        let mut config = fireworks_config();
+        config.provider.base_url = Some(format!("http://{upstream_addr}"));
+        config.provider.api_key = Some("test-key".to_string());
+        config.provider.inline_image_urls = inline;
+        let logger = SharedLogger::new("/tmp/claude-proxy-test-image-urls.log").unwrap();
+        Arc::new(AppState::new(config, reqwest::Client::new(), logger))
+    };
+
+    proxy::proxy_non_streaming(&request("/cat.png"), &state(false))
+        .await
+        .unwrap();
+    proxy::proxy_non_streaming(&request("/cat.png"), &state(true))
+        .await
+        .unwrap();
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            serde_json::json!(format!("http://{upstream_addr}/cat.png")),
+            serde_json::json!("data:image/png;base64,iVBORw=="),
+        ]
+    );
+
+    // Something that isn't an image is refused before anything is sent
+    let err = proxy::proxy_non_streaming(&request("/notes.txt"), &state(true))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("not an image (content type 'text/plain')"),
+        "{err}"
+    );
+    assert_eq!(seen.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_idempotency_keys() {
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use std::sync::{Arc, Mutex};
+
+    // Every request is refused once with a 503 asking for an immediate retry
+    let keys = Arc::new(Mutex::new(Vec::new()));
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        post({
+            let keys = keys.clone();
+            move |headers: HeaderMap| async move {
+                let key = headers
+                    .get("idempotency-key")
+                    .map(|v| v.to_str().unwrap().to_string());
+                let mut keys = keys.lock().unwrap();
+                keys.push(key);
+                if keys.len() % 2 == 1 {
+                    return (StatusCode::SERVICE_UNAVAILABLE, [("retry-after", "0")], "busy")
+                        .into_response();
+                }
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                }))
+                .into_response()
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.idempotency_header = Some("Idempotency-Key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-idempotency.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let req = simple_request("test-model", "Hi");
+    proxy::proxy_non_streaming(&req, &state).await.unwrap();
+    proxy::proxy_non_streaming(&req, &state).await.unwrap();
+
+    // A retry repeats its request's key; the next request gets a new one
+    let keys: Vec<String> = keys.lock().unwrap().iter().flatten().cloned().collect();
+    assert_eq!(keys.len(), 4);
+    assert_eq!(keys[0], keys[1]);
+    assert_eq!(keys[2], keys[3]);
+    assert_ne!(keys[0], keys[2]);
+    assert!(keys[0].starts_with("claude-proxy-"), "{}", keys[0]);
+}
+
+#[tokio::test]
+async fn test_documents() {
+    use base64::Engine as _;
+    use claude_proxy::translate::documents::DocumentStrategy;
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post({
+            let seen = seen.clone();
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                seen.lock()
+                    .unwrap()
+                    .push(body["messages"][0]["content"].clone());
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "A report."}, "finish_reason": "stop"}],
+                }))
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let pdf = b"%PDF-1.4\n1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj\n\
+        2 0 obj << /Type /Pages /Kids [3 0 R] >> endobj\n\
+        3 0 obj << /Type /Page /Contents 4 0 R >> endobj\n\
+        4 0 obj << /Length 28 >>\nstream\nBT (Quarterly report) Tj ET\nendstream endobj\n\
+        trailer << /Root 1 0 R >>\n%%EOF\n";
+    let request: MessagesRequest = serde_json::from_value(serde_json::json!({
+        "model": "claude-sonnet-4-20250514",
+        "max_tokens": 100,
+        "messages": [{"role": "user", "content": [
+            {"type": "document", "title": "report.pdf", "source": {
+                "type": "base64", "media_type": "application/pdf",
+                "data": base64::engine::general_purpose::STANDARD.encode(pdf)
+            }},
+            {"type": "text", "text": "What is this?"}
+        ]}]
+    }))
+    .unwrap();
+
+    for strategy in [
+        DocumentStrategy::Text,
+        DocumentStrategy::Drop,
+        DocumentStrategy::File,
+    ] {
+        let mut config = fireworks_config();
+        config.provider.base_url = Some(format!("http://{upstream_addr}"));
+        config.provider.api_key = Some("test-key".to_string());
+        config.translation.documents = strategy;
+        let logger = SharedLogger::new("/tmp/claude-proxy-test-documents.log").unwrap();
+        let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+        proxy::proxy_non_streaming(&request, &state).await.unwrap();
+    }
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(
+        seen[0][0]["text"],
+        "<document name=\"report.pdf\">\nQuarterly report\n</document>"
+    );
+    assert_eq!(
+        seen[1][0]["text"],
+        "[Document \"report.pdf\" omitted: this model can't read documents]"
+    );
+    assert_eq!(seen[2][0]["type"], "file");
+    assert_eq!(seen[2][0]["file"]["filename"], "report.pdf");
+    assert_eq!(seen[2][1]["text"], "What is this?");
+}
+
+#[tokio::test]
+async fn test_large_screenshot() {
+    use base64::Engine as _;
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(None));
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post({
+            let seen = seen.clone();
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                *seen.lock().unwrap() = Some(body["messages"][0]["content"][0].clone());
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "A screen."}, "finish_reason": "stop"}],
+                }))
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    // Noise doesn't compress, so the PNG is about as large as its pixels
+    let mut rng = 7u32;
+    let noise = image::RgbImage::from_fn(1000, 1000, |_, _| {
+        rng = rng.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        let [r, g, b, _] = rng.to_be_bytes();
+        image::Rgb([r, g, b])
+    });
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(noise)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    let body = serde_json::json!({
+        "model": "claude-sonnet-4-20250514",
+        "max_tokens": 100,
+        "messages": [{"role": "user", "content": [
+            {"type": "image", "source": {
+                "type": "base64", "media_type": "image/png",
+                "data": base64::engine::general_purpose::STANDARD.encode(&png)
+            }},
+            {"type": "text", "text": "What is this?"}
+        ]}]
+    });
+    assert!(body.to_string().len() > 2 * 1024 * 1024);
+
+    let send = |max_request_mb: usize| {
+        let mut config = fireworks_config();
+        config.provider.base_url = Some(format!("http://{upstream_addr}"));
+        config.provider.api_key = Some("test-key".to_string());
+        config.translation.images.max_dimension = 200;
+        config.max_request_mb = max_request_mb;
+        let logger = SharedLogger::new("/tmp/claude-proxy-test-large-screenshot.log").unwrap();
+        let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+        let body = body.clone();
+        async move {
+            let addr = spawn_server(claude_proxy::build_router(state)).await;
+            reqwest::Client::new()
+                .post(format!("http://{addr}/v1/messages"))
+                .json(&body)
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    // Past the default 2 MB an axum server allows, the image still reaches
+    // the provider, scaled down
+    assert_eq!(send(32).await, 200);
+    let part = seen.lock().unwrap().take().unwrap();
+    let url = part["image_url"]["url"].as_str().unwrap();
+    let data = url.strip_prefix("data:image/png;base64,").unwrap();
+    let shrunk = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .unwrap();
+    let shrunk = image::load_from_memory(&shrunk).unwrap();
+    assert_eq!((shrunk.width(), shrunk.height()), (200, 200));
+
+    // Larger than max_request_mb is refused before it's read
+    assert_eq!(send(1).await, 413);
+    assert!(seen.lock().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_large_document() {
+    use base64::Engine as _;
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(None));
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post({
+            let seen = seen.clone();
+            move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                *seen.lock().unwrap() = Some(body["messages"][0]["content"][0].clone());
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "A report."}, "finish_reason": "stop"}],
+                }))
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    // A one-page PDF made large by an embedded 3 MB stream, such as a scan
+    let mut rng = 11u32;
+    let blob: Vec<u8> = (0..3 * 1024 * 1024)
+        .map(|_| {
+            rng = rng.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            rng.to_be_bytes()[0]
+        })
+        .collect();
+    let mut pdf = b"%PDF-1.4\n1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj\n\
+        2 0 obj << /Type /Pages /Kids [3 0 R] >> endobj\n\
+        3 0 obj << /Type /Page /Contents 4 0 R >> endobj\n\
+        4 0 obj << /Length 28 >>\nstream\nBT (Quarterly report) Tj ET\nendstream endobj\n"
+        .to_vec();
+    pdf.extend_from_slice(format!("5 0 obj << /Length {} >>\nstream\n", blob.len()).as_bytes());
+    pdf.extend_from_slice(&blob);
+    pdf.extend_from_slice(b"\nendstream endobj\ntrailer << /Root 1 0 R >>\n%%EOF\n");
+    let body = serde_json::json!({
+        "model": "claude-sonnet-4-20250514",
+        "max_tokens": 100,
+        "messages": [{"role": "user", "content": [
+            {"type": "document", "title": "report.pdf", "source": {
+                "type": "base64", "media_type": "application/pdf",
+                "data": base64::engine::general_purpose::STANDARD.encode(&pdf)
+            }},
+            {"type": "text", "text": "What is this?"}
+        ]}]
+    });
+    assert!(body.to_string().len() > 4 * 1024 * 1024);
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-large-document.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let status = reqwest::Client::new()
+        .post(format!("http://{addr}/v1/messages"))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .status();
+
+    // Read through the router, and sent on as its text
+    assert_eq!(status, 200);
+    assert_eq!(
+        seen.lock().unwrap().take().unwrap()["text"],
+        "<document name=\"report.pdf\">\nQuarterly report\n</document>"
+    );
+}
+
+#[tokio::test]
+async fn test_response_headers() {
+    use axum::response::IntoResponse;
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                let headers = [
+                    ("x-request-id", "upstream-1"),
+                    ("x-ratelimit-remaining-requests", "99"),
+                    ("openrouter-provider", "Together"),
+                    ("x-internal", "secret"),
+                ];
+                if body["stream"] == true {
+                    let sse = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n";
+                    return (headers, [("content-type", "text/event-stream")], sse).into_response();
+                }
+                (
+                    headers,
+                    axum::Json(serde_json::json!({
+                        "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                        "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                    })),
+                )
+                    .into_response()
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.response_headers = vec![
+        "X-Ratelimit-*".to_string(),
+        "openrouter-provider".to_string(),
+        "x-request-id".to_string(),
+    ];
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-response-headers.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+
+    for stream in [false, true] {
+        let resp = reqwest::Client::new()
+            .post(format!("http://{addr}/v1/messages"))
+            .header("x-request-id", "client-1")
+            .json(&serde_json::json!({
+                "model": "test-model", "max_tokens": 10, "stream": stream,
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let headers = resp.headers();
+        assert_eq!(headers["x-ratelimit-remaining-requests"], "99");
+        assert_eq!(headers["openrouter-provider"], "Together");
+        assert!(headers.get("x-internal").is_none());
+        // The proxy's request id keeps its name
+        assert_eq!(headers["x-request-id"], "client-1");
+        assert_eq!(headers["x-upstream-request-id"], "upstream-1");
+        assert_eq!(headers["request-id"], "req_client1");
+        assert!(resp.text().await.unwrap().contains("Hi"));
+    }
+}
+
+#[tokio::test]
+async fn test_provenance() {
+    use std::sync::Arc;
+
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                use axum::response::IntoResponse;
+                if body["stream"] == true {
+                    let sse = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"mock\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n";
+                    return ([("content-type", "text/event-stream")], sse).into_response();
+                }
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                }))
+                .into_response()
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provenance.header = true;
+    config.provenance.footer = true;
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-provenance.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+
+    for stream in [false, true] {
+        let resp = reqwest::Client::new()
+            .post(format!("http://{addr}/v1/messages"))
+            .header("x-request-id", "client-1")
+            .json(&serde_json::json!({
+                "model": "test-model", "max_tokens": 10, "stream": stream,
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let provenance = resp.headers()["x-claude-proxy-provenance"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(
+            provenance.starts_with(
+                "provider=fireworks; model=accounts/fireworks/models/kimi-k2p5; proxy="
+            ),
+            "{provenance}"
+        );
+        assert!(
+            provenance.ends_with("; request_id=client-1"),
+            "{provenance}"
+        );
+
+        let body = resp.text().await.unwrap();
+        if stream {
+            // The footer is a block of its own, before the turn ends
+            let footer = body.find("claude-proxy-provenance").unwrap();
+            assert!(body.find("\"index\":1").unwrap() < footer, "{body}");
+            assert!(
+                footer < body.find("event: message_delta").unwrap(),
+                "{body}"
+            );
+        } else {
+            let resp: MessagesResponse = serde_json::from_str(&body).unwrap();
+            assert_eq!(resp.content.len(), 2);
+            let ResponseContentBlock::Text { text } = &resp.content[1] else {
+                panic!("footer is text: {body}");
+            };
+            let fields = text
+                .trim()
+                .strip_prefix(claude_proxy::provenance::FOOTER_MARKER)
+                .and_then(|t| t.strip_suffix(" -->"))
+                .unwrap();
+            let fields: serde_json::Value = serde_json::from_str(fields).unwrap();
+            assert_eq!(fields["provider"], "fireworks");
+            assert_eq!(fields["request_id"], "client-1");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_hooks() {
+    use axum::response::IntoResponse;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // The mock echoes the x-team header and the last message it was sent
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(
+            |headers: axum::http::HeaderMap, axum::Json(body): axum::Json<serde_json::Value>| async move {
+                let team = headers["x-team"].to_str().unwrap().to_string();
+                let prompt = body["messages"].as_array().unwrap().last().unwrap()["content"]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
+                let text = format!("{team}: {prompt}");
+                if body["stream"] == true {
+                    let chunk = serde_json::json!({
+                        "id": "c1", "object": "chat.completion.chunk", "created": 0, "model": "mock",
+                        "choices": [{"index": 0, "delta": {"content": text}, "finish_reason": "stop"}],
+                    });
+                    let sse = format!("data: {chunk}\n\ndata: [DONE]\n\n");
+                    return ([("content-type", "text/event-stream")], sse).into_response();
+                }
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": text}, "finish_reason": "stop"}],
+                }))
+                .into_response()
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-hooks.log").unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let state = AppState::new(config, reqwest::Client::new(), logger)
+        .on_request(move |req| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            req.messages.last_mut().unwrap().content =
+                MessageContent::Text("rewritten prompt".to_string());
+        })
+        .on_translated_request(|upstream| {
+            assert_eq!(upstream.provider, "fireworks");
+            upstream
+                .headers
+                .insert("x-team".to_string(), "billing".to_string());
+        })
+        .on_response(|resp| {
+            if let Some(ResponseContentBlock::Text { text }) = resp.content.first_mut() {
+                *text = text.to_uppercase();
+            }
+        })
+        .on_stream_event(|event| {
+            if let StreamEvent::ContentBlockDelta {
+                delta: Delta::TextDelta { text },
+                ..
+            } = event
+            {
+                *text = text.replace("billing", "[team]");
+            }
+        });
+    let state = Arc::new(state);
+
+    let resp = proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let ResponseContentBlock::Text { text } = &resp.content[0] else {
+        panic!("expected text");
+    };
+    assert_eq!(text, "BILLING: REWRITTEN PROMPT");
+
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "[team]: rewritten prompt");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_tool_policy() {
+    use std::sync::{Arc, Mutex};
+
+    // The mock calls Bash, which it isn't offered, until it's told it can't
+    let offered = Arc::new(Mutex::new(Vec::new()));
+    let seen = offered.clone();
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let seen = seen.clone();
+            async move {
+                let tools: Vec<String> = body["tools"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|t| t["function"]["name"].as_str().unwrap().to_string())
+                    .collect();
+                seen.lock().unwrap().push(tools);
+                let last = body["messages"].as_array().unwrap().last().unwrap().clone();
+                let message = if last["role"] == "tool" {
+                    assert!(last["content"].as_str().unwrap().contains("not available"));
+                    serde_json::json!({"role": "assistant", "content": "Listed with Read"})
+                } else {
+                    serde_json::json!({"role": "assistant", "content": null, "tool_calls": [{
+                        "id": "call_1", "type": "function",
+                        "function": {"name": "Bash", "arguments": "{\"command\":\"ls\"}"}
+                    }]})
+                };
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": message, "finish_reason": "stop"}],
+                }))
+            }
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.provider.deny_tools = vec!["Bash".to_string()];
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-tool-policy.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    let mut req = streaming_request("test-model", "List the files");
+    req.tools = Some(
+        serde_json::from_value(serde_json::json!([
+            {"name": "Bash", "input_schema": {"type": "object"}},
+            {"name": "Read", "input_schema": {"type": "object"}},
+        ]))
+        .unwrap(),
+    );
+    let mut stream = proxy::proxy_streaming(&req, &state).await.unwrap();
+    let mut events = Vec::new();
+    while let Some(event) = stream.next().await {
+        events.push(event.unwrap().data);
+    }
+    let events = events.join("\n");
+    assert!(events.contains("Listed with Read"), "{events}");
+    assert!(!events.contains("tool_use"), "{events}");
+    assert_eq!(*offered.lock().unwrap(), [["Read"], ["Read"]]);
+}
+
+#[tokio::test]
+async fn test_tool_use_hook() {
+    use axum::response::IntoResponse;
+
+    // The mock calls Bash, streaming the arguments in fragments
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            if body["stream"] == true {
+                let chunks = [
+                    serde_json::json!({"content": "Running it"}),
+                    serde_json::json!({"tool_calls": [{"index": 0, "id": "call_1", "type": "function",
+                        "function": {"name": "Bash", "arguments": "{\"comm"}}]}),
+                    serde_json::json!({"tool_calls": [{"index": 0,
+                        "function": {"arguments": "and\":\"make deploy\"}"}}]}),
+                ];
+                let mut sse = String::new();
+                for (i, delta) in chunks.iter().enumerate() {
+                    let finish = (i == chunks.len() - 1).then_some("tool_calls");
+                    let chunk = serde_json::json!({
+                        "id": "c1", "object": "chat.completion.chunk", "created": 0, "model": "mock",
+                        "choices": [{"index": 0, "delta": delta, "finish_reason": finish}],
+                    });
+                    sse.push_str("data: ");
+                    sse.push_str(&chunk.to_string());
+                    sse.push_str("\n\n");
+                }
+                sse.push_str("data: [DONE]\n\n");
+                return ([("content-type", "text/event-stream")], sse).into_response();
+            }
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": {"name": "Bash", "arguments": "{\"command\":\"make deploy\"}"}
+                }]}, "finish_reason": "tool_calls"}],
+            }))
+            .into_response()
+        }),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-tool-use-hook.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger).on_tool_use(|call| {
+        assert_eq!(call.id, "call_1");
+        if let Some(serde_json::Value::String(command)) = call.input.get_mut("command") {
+            command.push_str(" --dry-run");
+        }
+    });
+    let tools: Vec<Tool> = serde_json::from_value(serde_json::json!([
+        {"name": "Bash", "input_schema": {"type": "object"}},
+    ]))
+    .unwrap();
+
+    let mut req = simple_request("test-model", "Deploy");
+    req.tools = Some(tools.clone());
+    let resp = proxy::proxy_non_streaming(&req, &state).await.unwrap();
+    let Some(ResponseContentBlock::ToolUse { input, .. }) = resp.content.last() else {
+        panic!("expected a tool call");
+    };
+    assert_eq!(input["command"], "make deploy --dry-run");
+
+    // Streamed, the call arrives whole after the text
+    let mut req = streaming_request("test-model", "Deploy");
+    req.tools = Some(tools);
+    let mut stream = proxy::proxy_streaming(&req, &state).await.unwrap();
+    let mut fragments = Vec::new();
+    let mut text = String::new();
+    while let Some(event) = stream.next().await {
+        match serde_json::from_str(&event.unwrap().data).unwrap() {
+            StreamEvent::ContentBlockDelta {
+                delta: Delta::InputJsonDelta { partial_json },
+                ..
+            } => fragments.push(partial_json),
+            StreamEvent::ContentBlockDelta {
+                delta: Delta::TextDelta { text: delta },
+                ..
+            } => text.push_str(&delta),
+            _ => {}
+        }
+    }
+    assert_eq!(text, "Running it");
+    assert_eq!(fragments.len(), 1);
+    let input: serde_json::Value = serde_json::from_str(&fragments[0]).unwrap();
+    assert_eq!(input["command"], "make deploy --dry-run");
+}
+
+#[tokio::test]
+async fn test_api_key_rotation() {
+    use axum::response::IntoResponse;
+    use std::sync::{Arc, Mutex};
+
+    // The mock rate-limits the first key and answers the second
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let keys = seen.clone();
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(
+            move |headers: axum::http::HeaderMap, axum::Json(body): axum::Json<serde_json::Value>| {
+                let keys = keys.clone();
+                async move {
+                    let key = headers["authorization"].to_str().unwrap().to_string();
+                    keys.lock().unwrap().push(key.clone());
+                    if key == "Bearer key-a" {
+                        return (
+                            axum::http::StatusCode::TOO_MANY_REQUESTS,
+                            [("retry-after", "120")],
+                            "rate limited",
+                        )
+                            .into_response();
+                    }
+                    if body["stream"] == true {
+                        let chunk = serde_json::json!({
+                            "id": "c1", "object": "chat.completion.chunk", "created": 0, "model": "mock",
+                            "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": "stop"}],
+                        });
+                        let sse = format!("data: {chunk}\n\ndata: [DONE]\n\n");
+                        return ([("content-type", "text/event-stream")], sse).into_response();
+                    }
+                    axum::Json(serde_json::json!({
+                        "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                        "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                    }))
+                    .into_response()
+                }
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    std::env::set_var("CLAUDE_PROXY_TEST_ROTATE_A", "key-a");
+    std::env::set_var("CLAUDE_PROXY_TEST_ROTATE_B", "key-b");
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key_envs = vec![
+        "CLAUDE_PROXY_TEST_ROTATE_A".to_string(),
+        "CLAUDE_PROXY_TEST_ROTATE_B".to_string(),
+    ];
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-key-rotation.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    // The refused request is sent again with the next key, without a retry wait
+    let started = std::time::Instant::now();
+    proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    // The resting key is skipped
+    proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hi");
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "Bearer key-a",
+            "Bearer key-b",
+            "Bearer key-b",
+            "Bearer key-b"
+        ]
+    );
+
+    let counts = &state.keys.stats()["fireworks"];
+    let a = &counts["CLAUDE_PROXY_TEST_ROTATE_A"];
+    assert_eq!((a.requests, a.rate_limited), (1, 1));
+    assert!(a.resting_secs.unwrap() > 60);
+    assert_eq!(counts["CLAUDE_PROXY_TEST_ROTATE_B"].successes, 3);
+}
+
+#[tokio::test]
+async fn test_oauth_token_refresh() {
+    use axum::response::IntoResponse;
+    use claude_proxy::config::OAuthConfig;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // The mock issues token-0, token-1, ... and has already revoked token-0
+    let issued = Arc::new(AtomicU32::new(0));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (counter, tokens) = (issued.clone(), seen.clone());
+    let upstream = axum::Router::new()
+        .route(
+            "/token",
+            axum::routing::post(move |axum::Form(form): axum::Form<HashMap<String, String>>| {
+                let counter = counter.clone();
+                async move {
+                    assert_eq!(form["client_id"], "proxy");
+                    assert_eq!(form["client_secret"], "s3cret");
+                    let n = counter.fetch_add(1, Ordering::SeqCst);
+                    axum::Json(serde_json::json!({
+                        "access_token": format!("token-{n}"),
+                        "token_type": "Bearer",
+                        "expires_in": 3600,
+                    }))
+                }
+            }),
+        )
+        .route(
+            "/chat/completions",
+            axum::routing::post(
+                move |headers: axum::http::HeaderMap, axum::Json(body): axum::Json<serde_json::Value>| {
+                    let tokens = tokens.clone();
+                    async move {
+                        let token = headers["authorization"].to_str().unwrap().to_string();
+                        tokens.lock().unwrap().push(token.clone());
+                        if token == "Bearer token-0" {
+                            return (axum::http::StatusCode::UNAUTHORIZED, "token revoked")
+                                .into_response();
+                        }
+                        if body["stream"] == true {
+                            let chunk = serde_json::json!({
+                                "id": "c1", "object": "chat.completion.chunk", "created": 0, "model": "mock",
+                                "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": "stop"}],
+                            });
+                            let sse = format!("data: {chunk}\n\ndata: [DONE]\n\n");
+                            return ([("content-type", "text/event-stream")], sse).into_response();
+                        }
+                        axum::Json(serde_json::json!({
+                            "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                        }))
+                        .into_response()
+                    }
+                },
+            ),
+        );
+    let upstream_addr = spawn_server(upstream).await;
+
+    std::env::set_var("CLAUDE_PROXY_TEST_OAUTH_CLIENT_SECRET", "s3cret");
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.oauth = Some(OAuthConfig {
+        token_url: format!("http://{upstream_addr}/token"),
+        client_id: "proxy".to_string(),
+        client_secret_env: "CLAUDE_PROXY_TEST_OAUTH_CLIENT_SECRET".to_string(),
+        scope: None,
+        audience: None,
+    });
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-oauth.log").unwrap();
+    let state = AppState::new(config, reqwest::Client::new(), logger);
+
+    // The refused token is replaced and the request sent again
+    proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    // The new token is reused while it's good
+    proxy::proxy_non_streaming(&simple_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    let stream = proxy::proxy_streaming(&streaming_request("test-model", "Hi"), &state)
+        .await
+        .unwrap();
+    assert_eq!(streamed_text(stream).await, "Hi");
+    assert_eq!(issued.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            "Bearer token-0",
+            "Bearer token-1",
+            "Bearer token-1",
+            "Bearer token-1"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_eval() {
+    use claude_proxy::capture::Capture;
+    use claude_proxy::config::ModelPrice;
+
+    // One provider calls a tool, the other answers with the model it was asked for
+    let tool_caller = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(|| async {
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": {"name": "Bash", "arguments": "{\"command\":\"ls\"}"}
+                }]}, "finish_reason": "tool_calls"}],
+                "usage": {"prompt_tokens": 100, "completion_tokens": 10, "total_tokens": 110},
+            }))
+        }),
+    );
+    let talker = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+            axum::Json(serde_json::json!({
+                "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": format!("I am {}", body["model"].as_str().unwrap())}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": 90, "completion_tokens": 5, "total_tokens": 95},
+            }))
+        }),
+    );
+    let tool_caller_addr = spawn_server(tool_caller).await;
+    let talker_addr = spawn_server(talker).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{tool_caller_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.providers.insert(
+        "talker".to_string(),
+        ProviderConfig {
+            name: "talker".to_string(),
+            base_url: Some(format!("http://{talker_addr}")),
+            models: HashMap::from([("test-model".to_string(), "talker-large".to_string())]),
+            ..config.provider.clone()
+        },
+    );
+    // Eval pins each side to its provider, so the fallback is never used
+    config.fallback = vec!["talker".to_string()];
+    config.costs.prices.insert(
+        "accounts/fireworks/models/kimi-k2p5".to_string(),
+        ModelPrice {
+            input: 1.0,
+            output: 2.0,
+        },
+    );
+
+    let capture = Capture {
+        request_id: "req_1".to_string(),
+        captured_at: chrono::Utc::now(),
+        provider: "fireworks".to_string(),
+        streaming: true,
+        anthropic_request: serde_json::to_value(streaming_request("test-model", "List files"))
+            .unwrap(),
+        openai_request: serde_json::Value::Null,
+        status: 200,
+        openai_response: serde_json::Value::Null,
+        anthropic_response: serde_json::Value::Null,
+    };
+    let report = claude_proxy::eval::run(&config, &[capture], ["fireworks", "talker"])
+        .await
+        .unwrap();
+
+    let [tools, text] = &report.turns[0].answers;
+    assert_eq!(tools.model, "accounts/fireworks/models/kimi-k2p5");
+    assert_eq!(tools.tool_calls[0].name, "Bash");
+    assert_eq!(tools.tool_calls[0].input["command"], "ls");
+    assert!((tools.cost.unwrap() - 120.0 / 1_000_000.0).abs() < 1e-12);
+    assert_eq!(text.text, "I am talker-large");
+    assert!(text.tool_calls.is_empty() && text.cost.is_none());
+    assert!(!report.turns[0].same_tools);
+    assert_eq!(report.totals[0].tool_calls, 1);
+    assert_eq!(report.totals[1].input_tokens, 90);
+    let rendered = claude_proxy::eval::render(&report);
+    assert!(
+        rendered.contains("Same tools chosen on 0 of 1 turns."),
+        "{rendered}"
+    );
+}
+
+#[tokio::test]
+async fn test_message_batches() {
+    use axum::response::IntoResponse;
+    use std::sync::Arc;
+
+    // The mock answers with the prompt, or fails a prompt of "fail"
+    let upstream = axum::Router::new().route(
+        "/chat/completions",
+        axum::routing::post(
+            |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                assert_ne!(body["stream"], true);
+                let prompt = body["messages"].as_array().unwrap().last().unwrap()["content"]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
+                if prompt == "fail" {
+                    return (
+                        axum::http::StatusCode::BAD_REQUEST,
+                        axum::Json(serde_json::json!({"error": {"message": "bad prompt", "type": "invalid_request_error"}})),
+                    )
+                        .into_response();
+                }
+                axum::Json(serde_json::json!({
+                    "id": "c1", "object": "chat.completion", "created": 0, "model": "mock",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": prompt}, "finish_reason": "stop"}],
+                }))
+                .into_response()
+            },
+        ),
+    );
+    let upstream_addr = spawn_server(upstream).await;
+
+    let mut config = fireworks_config();
+    config.provider.base_url = Some(format!("http://{upstream_addr}"));
+    config.provider.api_key = Some("test-key".to_string());
+    config.max_request_mb = 1;
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-batches.log").unwrap();
+    let state = Arc::new(AppState::new(config, reqwest::Client::new(), logger));
+    let addr = spawn_server(claude_proxy::build_router(state)).await;
+    let client = reqwest::Client::new();
+    let params = |prompt: &str| {
+        serde_json::json!({
+            "model": "test-model", "max_tokens": 10, "stream": true,
+            "messages": [{"role": "user", "content": prompt}]
+        })
+    };
+
+    // Duplicate custom_ids are rejected
+    let resp = client
+        .post(format!("http://{addr}/v1/messages/batches"))
+        .json(&serde_json::json!({"requests": [
+            {"custom_id": "a", "params": params("Hi")},
+            {"custom_id": "a", "params": params("Hi")},
+        ]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+
+    // A batch isn't held to max_request_mb: this one is read, and refused
+    // only for its duplicate ids
+    let requests = vec![serde_json::json!({"custom_id": "a", "params": params("Hi")}); 20_000];
+    let body = serde_json::json!({ "requests": requests });
+    assert!(body.to_string().len() > 2 * 1024 * 1024);
+    let resp = client
+        .post(format!("http://{addr}/v1/messages/batches"))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+
+    let batch: serde_json::Value = client
+        .post(format!("http://{addr}/v1/messages/batches"))
+        .json(&serde_json::json!({"requests": [
+            {"custom_id": "first", "params": params("Hello")},
+            {"custom_id": "second", "params": params("fail")},
+        ]}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(batch["type"], "message_batch");
+    let id = batch["id"].as_str().unwrap().to_string();
+    assert!(id.starts_with("msgbatch_"));
+
+    // Poll until it has ended
+    let mut batch = batch;
+    for _ in 0..100 {
+        if batch["processing_status"] == "ended" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        batch = client
+            .get(format!("http://{addr}/v1/messages/batches/{id}"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    }
+    assert_eq!(batch["processing_status"], "ended");
+    assert_eq!(
+        batch["request_counts"],
+        serde_json::json!({"processing": 0, "succeeded": 1, "errored": 1, "canceled": 0, "expired": 0})
+    );
+
+    // The results are JSON lines, in the order the requests were submitted
+    let results_url = batch["results_url"].as_str().unwrap();
+    let results = client
+        .get(format!("http://{addr}{results_url}"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let lines: Vec<serde_json::Value> = results
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["custom_id"], "first");
+    assert_eq!(lines[0]["result"]["type"], "succeeded");
+    assert_eq!(lines[0]["result"]["message"]["content"][0]["text"], "Hello");
+    assert_eq!(lines[1]["custom_id"], "second");
+    assert_eq!(lines[1]["result"]["type"], "errored");
+    assert_eq!(
+        lines[1]["result"]["error"]["error"]["type"],
+        "invalid_request_error"
+    );
+
+    let list: serde_json::Value = client
+        .get(format!("http://{addr}/v1/messages/batches?limit=5"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(list["data"][0]["id"], id.as_str());
+    assert_eq!(list["has_more"], false);
+
+    let deleted: serde_json::Value = client
+        .delete(format!("http://{addr}/v1/messages/batches/{id}"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deleted["type"], "message_batch_deleted");
+    let resp = client
+        .get(format!("http://{addr}/v1/messages/batches/{id}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn test_port_conflict_with_running_proxy() {
+    use claude_proxy::listen::{self, Bound};
+    use std::sync::Arc;
+
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-port-conflict.log").unwrap();
+    let state = Arc::new(AppState::new(
+        fireworks_config(),
+        reqwest::Client::new(),
+        logger,
+    ));
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let app = claude_proxy::build_router(state);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    match listen::bind(port, PortConflict::Reuse).await.unwrap() {
+        Bound::Running {
+            port: running,
+            version,
+        } => {
+            assert_eq!(running, port);
+            assert_eq!(version, env!("CARGO_PKG_VERSION"));
+        }
+        Bound::Listening(..) => panic!("bound a port in use"),
+    }
+    let error = listen::bind(port, PortConflict::Fail).await.unwrap_err();
+    assert!(
+        error.to_string().contains("in use by claude-proxy"),
+        "{error}"
+    );
+}
+
+// ────────────────────────────────────────────────────────────────
+// Integration tests (need FIREWORKS_API_KEY)
+// ────────────────────────────────────────────────────────────────
+
+#[tokio::test]
+#[ignore = "requires FIREWORKS_API_KEY"]
+async fn test_non_streaming_fireworks() {
+    let logger = SharedLogger::new("/tmp/claude-proxy-test.log").unwrap();
+    let state = AppState::new(fireworks_config(), reqwest::Client::new(), logger);
+    let req = simple_request("test-model", "Say 'hello' and nothing else.");
+
+    let result = proxy::proxy_non_streaming(&req, &state).await;
+
+    match result {
+        Ok(resp) => {
+            assert_eq!(resp.response_type, "message");
+            assert_eq!(resp.role, "assistant");
+            assert!(!resp.content.is_empty());
+            println!("Response: {:?}", resp.content);
+            println!(
+                "Usage: in={} out={}",
+                resp.usage.input_tokens, resp.usage.output_tokens
+            );
+        }
+        Err(e) => {
+            panic!("Proxy error: {e}");
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires FIREWORKS_API_KEY"]
+async fn test_streaming_fireworks() {
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-stream.log").unwrap();
+    let state = AppState::new(fireworks_config(), reqwest::Client::new(), logger);
+    let req = streaming_request("test-model", "Count from 1 to 5.");
+
+    let stream = proxy::proxy_streaming(&req, &state)
+        .await
+        .expect("Failed to start stream");
+
+    let events: Vec<_> = stream
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .filter_map(std::result::Result::ok)
+        .collect();
+
+    assert!(!events.is_empty(), "Stream produced no events");
+
+    let event_names: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
+    println!("Stream events: {event_names:?}");
+
+    assert!(
+        event_names.contains(&"message_start"),
+        "Missing message_start"
+    );
+    assert!(
+        event_names.contains(&"message_stop"),
+        "Missing message_stop"
+    );
+    assert!(
+        event_names.contains(&"content_block_delta"),
+        "Missing content deltas"
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires FIREWORKS_API_KEY"]
+async fn test_tool_use_fireworks() {
+    let logger = SharedLogger::new("/tmp/claude-proxy-test-tools.log").unwrap();
+    let state = AppState::new(fireworks_config(), reqwest::Client::new(), logger);
+    let req = tool_request();
+
+    let result = proxy::proxy_non_streaming(&req, &state).await;
+
+    match result {
+        Ok(resp) => {
+            println!("Tool response: {:?}", resp.content);
+
+            let has_tool_use = resp
+                .content
+                .iter()
+                .any(|b| matches!(b, ResponseContentBlock::ToolUse { .. }));
+
+            // The model may or may not call the tool - just verify we got a valid response
+            assert_eq!(resp.response_type, "message");
+            if has_tool_use {
+                println!("Model correctly used the tool");
+                assert_eq!(resp.stop_reason, Some("tool_use".to_string()));
+            } else {
+                println!("Model responded with text (didn't use tool)");
+            }
+        }
+        Err(e) => {
+            panic!("Proxy error: {e}");
         }
     }
 }
@@ -321,11 +4689,11 @@ async fn test_full_server_roundtrip() {
     let logger = SharedLogger::new("/tmp/claude-proxy-test-server.log").unwrap();
     let client = reqwest::Client::new();
 
-    let state = std::sync::Arc::new(claude_proxy::AppState {
-        config: ProxyConfig { port: 0, ..config },
-        client: client.clone(),
+    let state = std::sync::Arc::new(AppState::new(
+        ProxyConfig { port: 0, ..config },
+        client.clone(),
         logger,
-    });
+    ));
 
     let app = claude_proxy::build_router(state);
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -0,0 +1,426 @@
+//! Property tests of the translators on generated requests and chunk streams.
+//!
+//! Requests are generated as Anthropic JSON (text, tool use, tool results,
+//! images, thinking), and streams as arbitrary sequences of `OpenAI` chunk
+//! deltas: text, reasoning, and tool calls with any index, id and argument
+//! fragment. Translation must never panic, lose text, or break the Anthropic
+//! streaming contract. Run more cases with `PROPTEST_CASES=10000`.
+
+use claude_proxy::translate::anthropic_types::{
+    Delta, MessagesRequest, ResponseContentBlock, StreamEvent,
+};
+use claude_proxy::translate::openai_types::{
+    ChatCompletionChunk, ChatCompletionResponse, ChatUsage, ChunkChoice, ChunkDelta, ChunkToolCall,
+    ChunkToolCallFunction,
+};
+use claude_proxy::translate::prompted_tools::{PromptedTools, ToolPrompt};
+use claude_proxy::translate::request::anthropic_to_openai_for_model;
+use claude_proxy::translate::response::openai_to_anthropic;
+use claude_proxy::translate::stream_check;
+use claude_proxy::translate::streaming::StreamTranslator;
+use proptest::prelude::*;
+use serde_json::{json, Value};
+
+/// Text with the characters that trip up parsers: quotes, escapes, line
+/// breaks, tags and multi-byte characters.
+fn text() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        prop_oneof![
+            "[a-zA-Z0-9 .,]{1,12}",
+            Just("\"".to_string()),
+            Just("\\".to_string()),
+            Just("\n".to_string()),
+            Just("\r\n".to_string()),
+            Just("<think>".to_string()),
+            Just("</think>".to_string()),
+            Just("{\"a\": [1, ".to_string()),
+            Just("é€😀".to_string()),
+            Just("data: [DONE]".to_string()),
+        ],
+        0..6,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+fn tool_input() -> impl Strategy<Value = Value> {
+    prop::collection::btree_map("[a-z]{1,6}", text().prop_map(Value::from), 0..3)
+        .prop_map(|fields| Value::Object(fields.into_iter().collect()))
+}
+
+fn text_block() -> impl Strategy<Value = Value> {
+    text().prop_map(|text| json!({"type": "text", "text": text}))
+}
+
+/// A block of a user message: text, a tool result or an image.
+fn user_block() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        3 => text_block(),
+        2 => ("[a-z0-9]{1,8}", text(), any::<bool>()).prop_map(|(id, content, is_error)| {
+            json!({"type": "tool_result", "tool_use_id": format!("toolu_{id}"),
+                   "content": content, "is_error": is_error})
+        }),
+        1 => ("[a-z0-9]{1,8}", text()).prop_map(|(id, text)| {
+            json!({"type": "tool_result", "tool_use_id": format!("toolu_{id}"),
+                   "content": [{"type": "text", "text": text}]})
+        }),
+        1 => Just(json!({"type": "image", "source": {
+            "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}})),
+    ]
+}
+
+/// A block of an assistant message: text, a tool call or thinking.
+fn assistant_block() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        3 => text_block(),
+        2 => ("[a-z0-9]{1,8}", "[A-Za-z]{1,8}", tool_input()).prop_map(|(id, name, input)| {
+            json!({"type": "tool_use", "id": format!("toolu_{id}"), "name": name, "input": input})
+        }),
+        1 => text().prop_map(|thinking| json!({
+            "type": "thinking", "thinking": thinking, "signature": "sig"})),
+    ]
+}
+
+fn message() -> impl Strategy<Value = Value> {
+    let content = |block: BoxedStrategy<Value>| {
+        prop_oneof![
+            text().prop_map(Value::from),
+            prop::collection::vec(block, 0..5).prop_map(Value::from),
+        ]
+    };
+    prop_oneof![
+        content(user_block().boxed())
+            .prop_map(|content| json!({"role": "user", "content": content})),
+        content(assistant_block().boxed())
+            .prop_map(|content| json!({"role": "assistant", "content": content})),
+    ]
+}
+
+/// An Anthropic request, as JSON.
+fn request() -> impl Strategy<Value = Value> {
+    (
+        prop::collection::vec(message(), 1..8),
+        prop::option::of(text()),
+        prop::collection::vec(("[A-Za-z]{1,8}", text()), 0..3),
+        any::<bool>(),
+    )
+        .prop_map(|(messages, system, tools, stream)| {
+            let mut req = json!({
+                "model": "claude-sonnet-4-20250514",
+                "max_tokens": 1024,
+                "messages": messages,
+                "stream": stream,
+            });
+            if let Some(system) = system {
+                req["system"] = system.into();
+            }
+            if !tools.is_empty() {
+                req["tools"] = tools
+                    .into_iter()
+                    .map(|(name, description)| {
+                        json!({"name": name, "description": description,
+                               "input_schema": {"type": "object"}})
+                    })
+                    .collect();
+            }
+            req
+        })
+}
+
+/// Every text block, string content and tool result text in a request.
+fn request_texts(req: &Value) -> Vec<String> {
+    let mut texts = Vec::new();
+    for message in req["messages"].as_array().into_iter().flatten() {
+        match &message["content"] {
+            Value::String(s) => texts.push(s.clone()),
+            Value::Array(blocks) => {
+                for block in blocks {
+                    match block["type"].as_str() {
+                        Some("text") => texts.push(block["text"].as_str().unwrap().to_string()),
+                        Some("tool_result") => match &block["content"] {
+                            Value::String(s) => texts.push(s.clone()),
+                            content => {
+                                for part in content.as_array().into_iter().flatten() {
+                                    texts.push(part["text"].as_str().unwrap().to_string());
+                                }
+                            }
+                        },
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    texts
+}
+
+/// Every string in a JSON value, joined.
+fn all_strings(value: &Value, out: &mut String) {
+    match value {
+        Value::String(s) => {
+            out.push_str(s);
+            out.push('\u{0}');
+        }
+        Value::Array(items) => items.iter().for_each(|v| all_strings(v, out)),
+        Value::Object(fields) => fields.values().for_each(|v| all_strings(v, out)),
+        _ => {}
+    }
+}
+
+/// One chunk's delta.
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Reasoning(String),
+    TextAndReasoning(String, String),
+    Tool {
+        index: u64,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: Option<String>,
+    },
+    Usage(u64),
+    Empty,
+}
+
+fn piece() -> impl Strategy<Value = Piece> {
+    prop_oneof![
+        4 => text().prop_map(Piece::Text),
+        1 => text().prop_map(Piece::Reasoning),
+        1 => (text(), text()).prop_map(|(t, r)| Piece::TextAndReasoning(t, r)),
+        4 => (
+            0u64..3,
+            prop::option::of("call_[0-9]"),
+            prop::option::of("[a-z]{1,5}"),
+            prop::option::of(prop_oneof![
+                Just("{".to_string()),
+                Just("}".to_string()),
+                Just("\"q\":".to_string()),
+                Just("\"va".to_string()),
+                Just("lue\"".to_string()),
+                Just(",".to_string()),
+                Just("[1, 2".to_string()),
+                Just("{\"q\": \"x\"}".to_string()),
+                text(),
+            ]),
+        )
+            .prop_map(|(index, id, name, arguments)| Piece::Tool { index, id, name, arguments }),
+        1 => (0u64..10_000).prop_map(Piece::Usage),
+        1 => Just(Piece::Empty),
+    ]
+}
+
+fn chunk(piece: Piece, finish_reason: Option<String>) -> ChatCompletionChunk {
+    let mut delta = ChunkDelta::default();
+    let mut usage = None;
+    match piece {
+        Piece::Text(text) => delta.content = Some(text),
+        Piece::Reasoning(text) => delta.reasoning_content = Some(text),
+        Piece::TextAndReasoning(text, reasoning) => {
+            delta.content = Some(text);
+            delta.reasoning_content = Some(reasoning);
+        }
+        Piece::Tool {
+            index,
+            id,
+            name,
+            arguments,
+        } => {
+            delta.tool_calls = Some(vec![ChunkToolCall {
+                index,
+                id,
+                call_type: None,
+                function: Some(ChunkToolCallFunction { name, arguments }),
+            }]);
+        }
+        Piece::Usage(tokens) => {
+            usage = Some(ChatUsage {
+                prompt_tokens: 10,
+                completion_tokens: tokens,
+                total_tokens: tokens + 10,
+                ..ChatUsage::default()
+            });
+        }
+        Piece::Empty => {}
+    }
+    ChatCompletionChunk {
+        id: "chatcmpl-1".to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created: 0,
+        model: "m".to_string(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+            logprobs: None,
+        }],
+        usage,
+        system_fingerprint: None,
+        citations: None,
+    }
+}
+
+fn finish_reason() -> impl Strategy<Value = Option<String>> {
+    prop::option::of(prop_oneof![
+        Just("stop".to_string()),
+        Just("length".to_string()),
+        Just("tool_calls".to_string()),
+        Just("content_filter".to_string()),
+        "[a-z_]{0,10}",
+    ])
+}
+
+/// Text deltas joined, and each tool block's input joined, from a stream.
+fn streamed(events: &[StreamEvent]) -> (String, Vec<String>) {
+    let mut text = String::new();
+    let mut inputs: Vec<(usize, String)> = Vec::new();
+    for event in events {
+        match event {
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: ResponseContentBlock::ToolUse { .. },
+            } => inputs.push((*index, String::new())),
+            StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                Delta::TextDelta { text: t } => text.push_str(t),
+                Delta::InputJsonDelta { partial_json } => {
+                    if let Some(input) = inputs.iter_mut().find(|(i, _)| i == index) {
+                        input.1.push_str(partial_json);
+                    }
+                }
+                Delta::ThinkingDelta { .. } => {}
+            },
+            _ => {}
+        }
+    }
+    (text, inputs.into_iter().map(|(_, input)| input).collect())
+}
+
+proptest! {
+    #[test]
+    fn translated_requests_keep_their_text(req in request()) {
+        let parsed: MessagesRequest = serde_json::from_value(req.clone()).unwrap();
+        let translated = anthropic_to_openai_for_model(&parsed, "m");
+        let out = serde_json::to_value(&translated).unwrap();
+        let mut strings = String::new();
+        all_strings(&out, &mut strings);
+        for text in request_texts(&req) {
+            prop_assert!(strings.contains(&text), "lost {text:?}");
+        }
+    }
+
+    #[test]
+    fn streams_keep_the_contract(
+        pieces in prop::collection::vec(piece(), 0..24),
+        finish in finish_reason(),
+        thinking_blocks in any::<bool>(),
+    ) {
+        let mut translator = StreamTranslator::new("claude").with_thinking_blocks(thinking_blocks);
+        let mut events = Vec::new();
+        let mut expected = String::new();
+        let last = pieces.len().saturating_sub(1);
+        for (i, piece) in pieces.into_iter().enumerate() {
+            match &piece {
+                Piece::Text(t) => expected.push_str(t),
+                Piece::Reasoning(r) if !thinking_blocks => expected.push_str(r),
+                Piece::TextAndReasoning(t, r) => {
+                    if !thinking_blocks {
+                        expected.push_str(r);
+                    }
+                    expected.push_str(t);
+                }
+                _ => {}
+            }
+            let reason = if i == last { finish.clone() } else { None };
+            events.extend(translator.process_chunk(&chunk(piece, reason)));
+        }
+        events.extend(translator.finish());
+
+        let problems = stream_check::check(&events);
+        prop_assert!(problems.is_empty(), "{problems:?}");
+        let (text, inputs) = streamed(&events);
+        prop_assert_eq!(text, expected);
+        for input in inputs {
+            let parsed: Result<serde_json::Map<String, Value>, _> = serde_json::from_str(&input);
+            prop_assert!(parsed.is_ok(), "tool input {input:?} isn't a JSON object");
+        }
+    }
+
+    #[test]
+    fn responses_translate(
+        content in prop::option::of(text()),
+        reasoning in prop::option::of(text()),
+        arguments in prop::collection::vec(text(), 0..3),
+        finish in finish_reason(),
+    ) {
+        let tool_calls: Vec<Value> = arguments
+            .iter()
+            .enumerate()
+            .map(|(i, args)| json!({"id": format!("call_{i}"), "type": "function",
+                                    "function": {"name": "f", "arguments": args}}))
+            .collect();
+        let resp: ChatCompletionResponse = serde_json::from_value(json!({
+            "id": "r", "object": "chat.completion", "model": "m",
+            "choices": [{"index": 0, "finish_reason": finish, "message": {
+                "role": "assistant", "content": content, "reasoning_content": reasoning,
+                "tool_calls": if tool_calls.is_empty() { Value::Null } else { tool_calls.into() },
+            }}],
+        }))
+        .unwrap();
+        let translated = openai_to_anthropic(&resp, "claude").unwrap();
+        let tool_uses = translated
+            .content
+            .iter()
+            .filter(|b| matches!(b, ResponseContentBlock::ToolUse { .. }))
+            .count();
+        prop_assert_eq!(tool_uses, arguments.len());
+    }
+
+    #[test]
+    fn prompted_calls_survive_any_split(
+        before in text(),
+        path in text(),
+        splits in prop::collection::vec(any::<prop::sample::Index>(), 0..4),
+    ) {
+        let tools: Vec<claude_proxy::translate::anthropic_types::Tool> =
+            serde_json::from_value(json!([{"name": "Read", "input_schema": {
+                "type": "object", "properties": {"path": {"type": "string"}}}}]))
+            .unwrap();
+        let tools = PromptedTools::new(ToolPrompt::Json, &tools);
+        let call = json!({"name": "Read", "arguments": {"path": path}});
+        let reply = format!("{before}<tool_call>{call}</tool_call>");
+
+        // Cut the reply at char boundaries and stream the pieces
+        let mut cuts: Vec<usize> = splits
+            .iter()
+            .map(|i| i.index(reply.len() + 1))
+            .filter(|&i| reply.is_char_boundary(i))
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        let mut stream = tools.stream();
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for end in cuts.into_iter().chain([reply.len()]) {
+            chunks.extend(stream.process(chunk(Piece::Text(reply[start..end].to_string()), None)));
+            start = end;
+        }
+        chunks.extend(stream.process(chunk(Piece::Empty, Some("stop".to_string()))));
+
+        let text: String = chunks
+            .iter()
+            .filter_map(|c| c.choices[0].delta.content.as_deref())
+            .collect();
+        prop_assert_eq!(text, before);
+        let calls: Vec<&ChunkToolCall> = chunks
+            .iter()
+            .filter_map(|c| c.choices[0].delta.tool_calls.as_ref())
+            .flatten()
+            .collect();
+        prop_assert_eq!(calls.len(), 1);
+        let arguments: Value = serde_json::from_str(
+            calls[0].function.as_ref().unwrap().arguments.as_deref().unwrap(),
+        )
+        .unwrap();
+        prop_assert_eq!(&arguments["path"], &Value::from(path));
+    }
+}